@@ -0,0 +1,89 @@
+#!/bin/sh
+
+# ClashFun OpenWrt 安装脚本（busybox ash 可用）
+# 用法: wget -qO- https://raw.githubusercontent.com/ink1ing/clashfun/master/openwrt/install.sh | sh
+
+set -e
+
+REPO="ink1ing/clashfun"
+RAW_URL="https://raw.githubusercontent.com/${REPO}/master/openwrt"
+CONFIG_DIR="/etc/cf"
+
+if [ ! -f /etc/openwrt_release ]; then
+    echo "❌ 当前系统不是 OpenWrt，请使用 install.sh 安装"
+    exit 1
+fi
+
+case $(uname -m) in
+    x86_64) ARCH="x86_64" ;;
+    aarch64) ARCH="aarch64" ;;
+    armv7l) ARCH="armv7" ;;
+    mips) ARCH="mips" ;;
+    mipsel|mipsle) ARCH="mipsel" ;;
+    *)
+        echo "❌ 不支持的架构: $(uname -m)"
+        exit 1
+        ;;
+esac
+echo "🔍 检测到 OpenWrt (${ARCH})"
+
+download() {
+    if command -v curl >/dev/null 2>&1; then
+        curl -fsSL "$1" -o "$2"
+    else
+        wget -qO "$2" "$1"
+    fi
+}
+
+VERSION=$(wget -qO- "https://api.github.com/repos/${REPO}/releases/latest" 2>/dev/null | grep '"tag_name"' | cut -d'"' -f4)
+if [ -z "$VERSION" ]; then
+    echo "❌ 无法获取版本信息，请检查网络"
+    exit 1
+fi
+echo "✅ 最新版本: ${VERSION}"
+
+TEMP_DIR=$(mktemp -d)
+trap 'rm -rf "$TEMP_DIR"' EXIT
+
+# 路由器版本使用 release-router 构建，不含交互界面和自动更新
+echo "📦 下载 ClashFun..."
+download "https://github.com/${REPO}/releases/download/${VERSION}/cf-openwrt-${ARCH}.tar.gz" "$TEMP_DIR/cf.tar.gz"
+tar -xzf "$TEMP_DIR/cf.tar.gz" -C "$TEMP_DIR"
+cp "$TEMP_DIR/cf" /usr/bin/cf
+chmod +x /usr/bin/cf
+
+echo "📦 安装服务脚本..."
+download "${RAW_URL}/clashfun.init" /etc/init.d/clashfun
+chmod +x /etc/init.d/clashfun
+
+# 默认配置：路由器模式、允许局域网设备连接、TPROXY 透明代理
+mkdir -p "$CONFIG_DIR"
+if [ ! -f "$CONFIG_DIR/config.yaml" ]; then
+    cat > "$CONFIG_DIR/config.yaml" <<CONFIG
+profile: router
+allow_lan: true
+redirect: tproxy
+desktop_notifications: false
+disable_update_check: true
+CONFIG
+fi
+
+# 升级固件时保留配置
+if ! grep -q "^${CONFIG_DIR}/" /etc/sysupgrade.conf 2>/dev/null; then
+    echo "${CONFIG_DIR}/" >> /etc/sysupgrade.conf
+fi
+
+# TPROXY 需要内核模块
+if command -v opkg >/dev/null 2>&1; then
+    if command -v nft >/dev/null 2>&1; then
+        opkg list-installed | grep -q "^kmod-nft-tproxy " || echo "💡 TPROXY 需要 kmod-nft-tproxy: opkg update && opkg install kmod-nft-tproxy"
+    else
+        opkg list-installed | grep -q "^iptables-mod-tproxy " || echo "💡 TPROXY 需要 iptables-mod-tproxy: opkg update && opkg install iptables-mod-tproxy"
+    fi
+fi
+
+echo "🎉 安装完成！"
+echo "1. 设置订阅链接: HOME=/root XDG_CONFIG_HOME=/etc cf set-subscription <URL>"
+echo "2. 选择节点:     HOME=/root XDG_CONFIG_HOME=/etc cf select-node <名称>"
+echo "3. 指定游戏机上玩的游戏: 在 ${CONFIG_DIR}/config.yaml 中设置 redirect_games，例如 [cs, dota2]"
+echo "4. 启动并开机自启: /etc/init.d/clashfun enable && /etc/init.d/clashfun start"
@@ -0,0 +1,318 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::failover::SwitchReason;
+use crate::game_detect::SupportedGame;
+use crate::health;
+use crate::notification;
+use crate::proxy::ProxyServer;
+use crate::subscription::Node;
+
+/// 测量当前节点延迟的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+/// 每隔几次测量重新检测正在运行的游戏
+const GAME_REFRESH_SAMPLES: u32 = 5;
+/// 单次测量的超时，超时按高延迟处理
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// 自动切换前复测当前节点和备用节点的次数，取中位数
+const CONFIRM_SAMPLES: usize = 3;
+
+static CONFIG: RwLock<Option<LatencyAlarmConfig>> = RwLock::new(None);
+
+/// 延迟报警设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencyAlarmConfig {
+    /// 没有单独设置的游戏使用的阈值（毫秒），0 为关闭
+    pub threshold_ms: u32,
+    /// 按游戏设置的阈值（毫秒）
+    pub games: HashMap<SupportedGame, u32>,
+    /// 延迟持续高于阈值多少秒后报警
+    pub sustain_secs: u64,
+    /// 报警时终端响铃
+    pub beep: bool,
+    /// 报警时语音播报
+    pub speak: bool,
+    /// 报警时自动切换到延迟更低的备用节点，否则只提示
+    pub auto_switch: bool,
+    /// 自动切换前复测，备用节点至少比当前节点低这么多毫秒才切换，避免在对局中换到差不多的节点
+    pub min_improvement_ms: u32,
+}
+
+impl Default for LatencyAlarmConfig {
+    fn default() -> Self {
+        Self {
+            threshold_ms: 0,
+            games: HashMap::new(),
+            sustain_secs: 10,
+            beep: true,
+            speak: false,
+            auto_switch: false,
+            min_improvement_ms: 30,
+        }
+    }
+}
+
+impl LatencyAlarmConfig {
+    fn enabled(&self) -> bool {
+        self.threshold_ms > 0 || self.games.values().any(|ms| *ms > 0)
+    }
+
+    /// 正在进行的游戏对应的阈值，0 表示不报警
+    fn threshold_for(&self, game: Option<&SupportedGame>) -> Option<u32> {
+        let ms = game
+            .and_then(|game| self.games.get(game).copied())
+            .unwrap_or(self.threshold_ms);
+        (ms > 0).then_some(ms)
+    }
+}
+
+pub fn set_config(config: LatencyAlarmConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+fn config() -> LatencyAlarmConfig {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// 延迟状态的变化
+#[derive(Debug, PartialEq, Eq)]
+enum Transition {
+    /// 已持续高于阈值这么久
+    Alarm(Duration),
+    Recovered,
+}
+
+/// 判断延迟是否持续高于阈值，每次超标只报警一次
+#[derive(Default)]
+struct Tracker {
+    above_since: Option<Instant>,
+    alarmed: bool,
+}
+
+impl Tracker {
+    /// 测量失败（rtt 为 None）按超过阈值处理
+    fn observe(&mut self, rtt_ms: Option<u32>, threshold_ms: u32, sustain: Duration, now: Instant) -> Option<Transition> {
+        if rtt_ms.is_some_and(|ms| ms <= threshold_ms) {
+            self.above_since = None;
+            return std::mem::take(&mut self.alarmed).then_some(Transition::Recovered);
+        }
+        let since = *self.above_since.get_or_insert(now);
+        let elapsed = now.duration_since(since);
+        if self.alarmed || elapsed < sustain {
+            return None;
+        }
+        self.alarmed = true;
+        Some(Transition::Alarm(elapsed))
+    }
+}
+
+/// 游戏进行时定期测量当前节点的延迟，持续超过阈值时报警
+pub fn spawn(proxy: Arc<ProxyServer>) {
+    proxy.tasks().spawn("延迟报警", async move {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut tracker = Tracker::default();
+        let mut tracked_node = None;
+        let mut game = None;
+        let mut samples = 0u32;
+
+        loop {
+            interval.tick().await;
+            let config = config();
+            if !config.enabled() {
+                tracker = Tracker::default();
+                continue;
+            }
+            if samples.is_multiple_of(GAME_REFRESH_SAMPLES) {
+                game = proxy.running_game().await;
+            }
+            samples = samples.wrapping_add(1);
+
+            // 只在游戏进行时测量，路由器上以 UDP 会话判断
+            let Some(threshold) = config.threshold_for(game.as_ref()) else {
+                tracker = Tracker::default();
+                continue;
+            };
+            if game.is_none() && proxy.udp_session_count().await == 0 {
+                tracker = Tracker::default();
+                continue;
+            }
+            let Some(node) = proxy.current_node().await else {
+                continue;
+            };
+            if tracked_node.as_ref() != Some(&node.name) {
+                tracker = Tracker::default();
+                tracked_node = Some(node.name.clone());
+            }
+
+            let rtt = health::rtt(&node, PROBE_TIMEOUT).await.ok().map(|rtt| rtt.as_millis() as u32);
+            let sustain = Duration::from_secs(config.sustain_secs);
+            match tracker.observe(rtt, threshold, sustain, Instant::now()) {
+                Some(Transition::Alarm(elapsed)) => {
+                    raise(&proxy, &config, &node, game.as_ref(), rtt, threshold, elapsed).await;
+                }
+                Some(Transition::Recovered) => {
+                    println!("✅ 节点 {} 延迟已恢复到 {}ms", node.name, rtt.unwrap_or_default());
+                    info!("节点 {} 延迟恢复", node.name);
+                }
+                None => {}
+            }
+        }
+    })
+}
+
+async fn raise(
+    proxy: &ProxyServer,
+    config: &LatencyAlarmConfig,
+    node: &Node,
+    game: Option<&SupportedGame>,
+    rtt: Option<u32>,
+    threshold: u32,
+    elapsed: Duration,
+) {
+    let latency = rtt.map_or("超时".to_string(), |ms| format!("{}ms", ms));
+    let game = game.map_or(String::new(), |game| format!("（{}）", game.display_name()));
+    let message = format!(
+        "节点 {} 延迟 {} 已持续 {} 秒高于 {}ms{}",
+        node.name,
+        latency,
+        elapsed.as_secs(),
+        threshold,
+        game
+    );
+    println!("⚠️  {}", message);
+    warn!("{}", message);
+    notification::send("ClashFun 延迟过高", &message);
+    if config.beep {
+        notification::beep();
+    }
+    if config.speak {
+        notification::speak("加速节点延迟过高");
+    }
+
+    // 找延迟低于阈值且比当前节点更低的备用节点
+    let mut best: Option<(Node, u32)> = None;
+    for backup in proxy.backup_nodes().await {
+        if let Ok(rtt) = health::rtt(&backup, PROBE_TIMEOUT).await {
+            let ms = rtt.as_millis() as u32;
+            if ms < threshold && best.as_ref().is_none_or(|(_, best)| ms < *best) {
+                best = Some((backup, ms));
+            }
+        }
+    }
+    let Some((backup, ms)) = best else {
+        println!("💡 没有延迟低于 {}ms 的备用节点", threshold);
+        return;
+    };
+
+    if !config.auto_switch {
+        println!("💡 备用节点 {} 延迟 {}ms，可运行 'cf select-node \"{}\"' 切换", backup.name, ms, backup.name);
+        return;
+    }
+    if let Some(remaining) = proxy.switch_cooldown().await {
+        println!("💡 备用节点 {} 延迟 {}ms，刚切换过节点，{} 秒后才会自动切换", backup.name, ms, remaining.as_secs());
+        return;
+    }
+
+    // 同时复测两个节点，第一次测量可能只是偶然的抖动
+    let (current_ms, backup_ms) = tokio::join!(median_rtt(node), median_rtt(&backup));
+    if !worth_switching(current_ms, backup_ms, config.min_improvement_ms) {
+        let format = |ms: Option<u32>| ms.map_or("超时".to_string(), |ms| format!("{}ms", ms));
+        println!(
+            "💡 复测后备用节点 {} ({}) 没有比当前节点 ({}) 低 {}ms 以上，暂不切换",
+            backup.name,
+            format(backup_ms),
+            format(current_ms),
+            config.min_improvement_ms
+        );
+        info!("备用节点 {} 复测延迟 {:?}，当前节点 {:?}，改善不足，不切换", backup.name, backup_ms, current_ms);
+        return;
+    }
+    let ms = backup_ms.unwrap_or(ms);
+
+    println!("🔄 延迟过高，已切换到备用节点 {} ({}ms)", backup.name, ms);
+    notification::send("ClashFun 已切换节点", &format!("{} 延迟过高，已切换到 {}", node.name, backup.name));
+    proxy.switch_node(backup.clone(), SwitchReason::Latency).await;
+    // 保存选择，重启后继续使用
+    match Config::load_or_recover() {
+        Ok(mut saved) => {
+            saved.select_node(&backup);
+            if let Err(e) = saved.save() {
+                warn!("保存节点选择失败: {}", e);
+            }
+        }
+        Err(e) => warn!("保存节点选择失败: {}", e),
+    }
+}
+
+/// 多次测量节点延迟的中位数，半数以上失败时为 None
+async fn median_rtt(node: &Node) -> Option<u32> {
+    let mut samples = Vec::with_capacity(CONFIRM_SAMPLES);
+    for _ in 0..CONFIRM_SAMPLES {
+        if let Ok(rtt) = health::rtt(node, PROBE_TIMEOUT).await {
+            samples.push(rtt.as_millis() as u32);
+        }
+    }
+    if samples.len() * 2 <= CONFIRM_SAMPLES {
+        return None;
+    }
+    samples.sort_unstable();
+    Some(samples[samples.len() / 2])
+}
+
+/// 备用节点是否值得在对局中切换：当前节点测不通时只要备用节点可用，否则要低至少 min_improvement_ms
+fn worth_switching(current_ms: Option<u32>, backup_ms: Option<u32>, min_improvement_ms: u32) -> bool {
+    match (current_ms, backup_ms) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(current), Some(backup)) => backup.saturating_add(min_improvement_ms) <= current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alarms_once_after_sustained_high_latency() {
+        let sustain = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut tracker = Tracker::default();
+
+        assert_eq!(tracker.observe(Some(200), 100, sustain, at(0)), None);
+        // 短暂恢复后重新计时
+        assert_eq!(tracker.observe(Some(50), 100, sustain, at(4)), None);
+        assert_eq!(tracker.observe(Some(200), 100, sustain, at(6)), None);
+        assert_eq!(tracker.observe(None, 100, sustain, at(12)), None);
+        assert_eq!(tracker.observe(Some(200), 100, sustain, at(16)), Some(Transition::Alarm(sustain)));
+        assert_eq!(tracker.observe(Some(200), 100, sustain, at(30)), None);
+        assert_eq!(tracker.observe(Some(80), 100, sustain, at(32)), Some(Transition::Recovered));
+        assert_eq!(tracker.observe(Some(80), 100, sustain, at(34)), None);
+    }
+
+    #[test]
+    fn picks_threshold_of_running_game() {
+        let config: LatencyAlarmConfig = serde_yaml::from_str("threshold_ms: 150\ngames: {cs: 80, valorant: 0}").unwrap();
+        assert_eq!(config.threshold_for(Some(&SupportedGame::CounterStrike)), Some(80));
+        assert_eq!(config.threshold_for(Some(&SupportedGame::Valorant)), None);
+        assert_eq!(config.threshold_for(Some(&SupportedGame::Dota2)), Some(150));
+        assert_eq!(config.threshold_for(None), Some(150));
+        assert!(!LatencyAlarmConfig::default().enabled());
+    }
+
+    #[test]
+    fn switches_only_for_a_clear_improvement() {
+        assert!(worth_switching(Some(180), Some(100), 30));
+        assert!(!worth_switching(Some(180), Some(160), 30));
+        assert!(worth_switching(None, Some(160), 30));
+        assert!(!worth_switching(Some(180), None, 30));
+        assert!(worth_switching(Some(180), Some(179), 0));
+    }
+}
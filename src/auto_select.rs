@@ -0,0 +1,425 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::failover::SwitchReason;
+use crate::history;
+use crate::notification;
+use crate::proxy::ProxyServer;
+use crate::region;
+use crate::schedule::{self, TimeRule};
+use crate::subscription::{Node, SubscriptionManager};
+use crate::timeouts;
+
+/// 关闭空闲重选时检查配置是否变化的间隔
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 自动选择节点时的排序依据
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectPolicy {
+    /// 中位延迟最低
+    #[default]
+    Latency,
+    /// 综合延迟、抖动和丢包的评分最好
+    Score,
+    /// 抖动最小
+    Jitter,
+    /// 在评分前 N 名中随机选择，分散负载
+    Random,
+}
+
+impl SelectPolicy {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Latency => "最低延迟",
+            Self::Score => "综合评分",
+            Self::Jitter => "最低抖动",
+            Self::Random => "前 N 名随机",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoSelectConfig {
+    pub enabled: bool,
+    pub policy: SelectPolicy,
+    /// 只在该地区的节点中选择，例如 "香港"、"日本" 或 "jp"
+    pub region: Option<String>,
+    /// random 策略的候选数量
+    pub top_n: usize,
+    /// 每个节点测试的次数，用于计算抖动和丢包
+    pub rounds: u32,
+    /// 服务运行时每隔多少分钟重新选择节点，只在没有游戏进行时执行，0 表示关闭
+    pub idle_reselect_minutes: u64,
+    /// 按时段优先选择的节点，进入新的时段时空闲重选一次
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<TimeRule>,
+}
+
+impl Default for AutoSelectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            policy: SelectPolicy::default(),
+            region: None,
+            top_n: 3,
+            rounds: 3,
+            idle_reselect_minutes: 0,
+            schedule: Vec::new(),
+        }
+    }
+}
+
+impl AutoSelectConfig {
+    /// 开启自动选择或修改了选择条件，需要按新的设置重新选择节点；
+    /// 重选间隔和时段规则由空闲重选每轮重新读取，不在此列
+    pub fn needs_reselect(&self, old: &Self) -> bool {
+        self.enabled
+            && (!old.enabled
+                || self.policy != old.policy
+                || self.region != old.region
+                || self.top_n != old.top_n
+                || self.rounds != old.rounds)
+    }
+}
+
+/// 兼容旧版配置中的 `auto_select: true/false`
+pub fn deserialize_compat<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AutoSelectConfig, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Enabled(bool),
+        Block(AutoSelectConfig),
+    }
+
+    Ok(match Setting::deserialize(deserializer)? {
+        Setting::Enabled(enabled) => AutoSelectConfig {
+            enabled,
+            ..AutoSelectConfig::default()
+        },
+        Setting::Block(config) => config,
+    })
+}
+
+/// 多次测试后的节点统计
+#[derive(Debug, Clone)]
+pub struct NodeStats {
+    pub node: Node,
+    pub median_ms: u32,
+    /// 相邻两次测试延迟差的平均值
+    pub jitter_ms: u32,
+    pub loss_percent: u32,
+}
+
+impl NodeStats {
+    /// 从测试结果计算统计，全部失败时返回 None
+    pub fn from_samples(node: Node, samples: &[Option<u32>]) -> Option<Self> {
+        let latencies: Vec<u32> = samples.iter().flatten().copied().collect();
+        if latencies.is_empty() {
+            return None;
+        }
+
+        let jitter_ms = if latencies.len() > 1 {
+            let total: u32 = latencies.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+            total / (latencies.len() as u32 - 1)
+        } else {
+            0
+        };
+        let mut sorted = latencies.clone();
+        sorted.sort_unstable();
+
+        Some(Self {
+            node,
+            median_ms: sorted[sorted.len() / 2],
+            jitter_ms,
+            loss_percent: ((samples.len() - latencies.len()) * 100 / samples.len()) as u32,
+        })
+    }
+
+    /// 越小越好：抖动对游戏的影响按两倍延迟计算，每 1% 丢包相当于 10ms
+    pub fn score(&self) -> u32 {
+        self.median_ms
+            .saturating_add(self.jitter_ms.saturating_mul(2))
+            .saturating_add(self.loss_percent * 10)
+    }
+}
+
+/// 把地区参数统一为地区名称，例如 "jp" → "日本"
+pub fn parse_region(input: &str) -> Result<&'static str> {
+    region::infer(input).ok_or_else(|| anyhow!("无法识别的地区: {}（例如 香港、日本、sg）", input))
+}
+
+/// 测试每个节点 rounds 次，返回至少成功一次的节点
+pub async fn measure(sub_manager: &SubscriptionManager, nodes: &[Node], rounds: u32) -> Vec<NodeStats> {
+    let mut nodes = nodes.to_vec();
+    let mut samples: HashMap<String, Vec<Option<u32>>> = HashMap::new();
+    for _ in 0..rounds.max(1) {
+        if let Err(e) = sub_manager.test_all_nodes(&mut nodes).await {
+            warn!("节点延迟测试失败: {}", e);
+        }
+        if let Err(e) = history::record(&nodes) {
+            warn!("保存延迟历史失败: {}", e);
+        }
+        for node in &nodes {
+            let latency = node.latency.filter(|latency| *latency != u32::MAX);
+            samples.entry(node.name.clone()).or_default().push(latency);
+        }
+    }
+
+    nodes
+        .into_iter()
+        .filter_map(|node| {
+            let node_samples = samples.remove(&node.name)?;
+            NodeStats::from_samples(node, &node_samples)
+        })
+        .collect()
+}
+
+/// 按策略排序，region 限定时只保留该地区的节点
+pub fn rank(mut stats: Vec<NodeStats>, policy: SelectPolicy, region: Option<&str>) -> Vec<NodeStats> {
+    if let Some(region) = region {
+        stats.retain(|stat| region::infer_node(&stat.node) == Some(region));
+    }
+    match policy {
+        SelectPolicy::Latency => stats.sort_by_key(|stat| (stat.loss_percent, stat.median_ms)),
+        SelectPolicy::Jitter => stats.sort_by_key(|stat| (stat.loss_percent, stat.jitter_ms, stat.median_ms)),
+        SelectPolicy::Score | SelectPolicy::Random => stats.sort_by_key(NodeStats::score),
+    }
+    stats
+}
+
+/// 把时段规则优先的节点排到前面，返回其数量；没有符合的节点时保持原顺序
+pub fn prefer(ranked: Vec<NodeStats>, rule: Option<&TimeRule>) -> (Vec<NodeStats>, usize) {
+    let Some(rule) = rule else {
+        return (ranked, 0);
+    };
+    let (mut preferred, others): (Vec<NodeStats>, Vec<NodeStats>) = ranked.into_iter().partition(|stat| rule.matches(&stat.node));
+    let count = preferred.len();
+    preferred.extend(others);
+    (preferred, count)
+}
+
+/// 从排好序的节点中选出一个，random 策略在前 top_n 名中随机
+pub fn choose(ranked: &[NodeStats], policy: SelectPolicy, top_n: usize, seed: u64) -> Option<&NodeStats> {
+    match policy {
+        SelectPolicy::Random => {
+            let candidates = ranked.len().min(top_n.max(1));
+            ranked.get((seed % candidates.max(1) as u64) as usize)
+        }
+        _ => ranked.first(),
+    }
+}
+
+/// 随机策略使用的种子
+pub fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(std::process::id() as u64);
+    hasher.finish()
+}
+
+/// 按配置的策略选出最优节点，同时返回其余可用节点作为备用
+pub async fn select(config: &AutoSelectConfig, nodes: &[Node]) -> Result<Option<(NodeStats, Vec<Node>)>> {
+    let region = config.region.as_deref().map(parse_region).transpose()?;
+    let nodes: Vec<Node> = nodes.iter().filter(|node| node.is_supported()).cloned().collect();
+    let stats = measure(&SubscriptionManager::new(), &nodes, config.rounds).await;
+    let rule = schedule::active(&config.schedule, schedule::now()).map(|(_, rule)| rule);
+    let (ranked, preferred) = prefer(rank(stats, config.policy, region), rule);
+    if let Some(rule) = rule {
+        if preferred == 0 {
+            warn!("时段规则 {} 没有可用的节点，在所有节点中选择", rule.describe());
+        } else {
+            info!("时段规则 {} 生效，有 {} 个可用节点", rule.describe(), preferred);
+        }
+    }
+    let candidates = if preferred > 0 { &ranked[..preferred] } else { &ranked[..] };
+    let Some(best) = choose(candidates, config.policy, config.top_n, random_seed()).cloned() else {
+        return Ok(None);
+    };
+    let backups = ranked
+        .into_iter()
+        .filter(|stat| stat.node.name != best.node.name && timeouts::current().usable(Some(stat.median_ms)))
+        .map(|stat| stat.node)
+        .collect();
+    Ok(Some((best, backups)))
+}
+
+/// 服务运行期间定期在空闲时重新选择节点，保证开局时使用的是最新的最优节点，
+/// 游戏进行中不会切换
+pub fn spawn_idle_reselect(proxy: Arc<ProxyServer>) {
+    proxy.tasks().spawn("空闲时重选节点", async move {
+        let mut last_reselect = Instant::now();
+        // 启动时的节点未必来自当前时段的规则，进入服务后按规则重选一次
+        let mut active_rule = None;
+        loop {
+            tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+            // 每轮重新读取配置，修改间隔和时段规则无需重启
+            let Ok(config) = Config::load() else { continue };
+            let auto_select = &config.auto_select;
+            if !auto_select.enabled {
+                continue;
+            }
+
+            let rule = schedule::active(&auto_select.schedule, schedule::now()).map(|(index, rule)| (index, rule.clone()));
+            let window_changed = rule != active_rule;
+            let minutes = auto_select.idle_reselect_minutes;
+            let due = minutes > 0 && last_reselect.elapsed() >= Duration::from_secs(minutes * 60);
+            if !window_changed && !due {
+                continue;
+            }
+            if let Some((_, rule)) = rule.as_ref().filter(|_| window_changed) {
+                info!("进入时段 {}，重新选择节点", rule.describe());
+            }
+
+            last_reselect = Instant::now();
+            match reselect_if_idle(&proxy).await {
+                Ok(true) => active_rule = rule,
+                // 游戏进行中，时段切换留到下一次检查
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("空闲时重新选择节点失败: {}", e);
+                    active_rule = rule;
+                }
+            }
+        }
+    })
+}
+
+/// 自动选择的设置变更后立即按新设置重选一次节点，游戏进行中留给空闲重选
+pub fn spawn_reselect(proxy: Arc<ProxyServer>) {
+    proxy.tasks().spawn("按新设置重选节点", async move {
+        match reselect_if_idle(&proxy).await {
+            Ok(true) => {}
+            Ok(false) => info!("新的自动选择设置将在下次空闲重选时生效"),
+            Err(e) => warn!("按新设置重新选择节点失败: {}", e),
+        }
+    })
+}
+
+/// 没有游戏进行时重新选择节点，游戏进行中跳过时返回 false
+async fn reselect_if_idle(proxy: &ProxyServer) -> Result<bool> {
+    if proxy.game_session_active().await {
+        info!("游戏进行中，跳过本次节点重选");
+        return Ok(false);
+    }
+
+    let mut config = Config::load_or_recover()?;
+    let Some(url) = config.subscription_url.clone() else {
+        return Ok(true);
+    };
+    let sub_manager = SubscriptionManager::new();
+    let nodes = sub_manager.parse_nodes(&sub_manager.fetch_subscription(&url).await?)?;
+    let Some((best, backups)) = select(&config.auto_select, &nodes).await? else {
+        warn!("空闲重选没有找到可用的节点");
+        return Ok(true);
+    };
+
+    // 测试期间游戏可能已经开始
+    if proxy.game_session_active().await {
+        info!("游戏已开始，放弃本次节点重选");
+        return Ok(false);
+    }
+
+    proxy.set_backup_nodes(backups).await;
+    if proxy.current_node_name().await.as_deref() == Some(best.node.name.as_str()) {
+        info!("空闲重选: 当前节点 {} 仍是最优", best.node.name);
+        return Ok(true);
+    }
+
+    info!("空闲重选: 切换到 {} (延迟 {}ms)", best.node.name, best.median_ms);
+    notification::send("ClashFun 已切换节点", &format!("空闲时自动选择了更优的节点 {}", best.node.name));
+    config.select_node(&best.node);
+    proxy.switch_node(best.node, SwitchReason::Reselect).await;
+    config.save()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(name: &str, samples: &[Option<u32>]) -> NodeStats {
+        let node = Node::test(name, "127.0.0.1", 1, "ss");
+        NodeStats::from_samples(node, samples).unwrap()
+    }
+
+    fn names(ranked: &[NodeStats]) -> Vec<&str> {
+        ranked.iter().map(|stat| stat.node.name.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_nodes_by_policy() {
+        let all = vec![
+            stats("香港 01", &[Some(30), Some(90), Some(30)]),
+            stats("香港 02", &[Some(50), Some(52), Some(51)]),
+            stats("日本 01", &[Some(20), None, Some(20)]),
+        ];
+
+        let by_latency = rank(all.clone(), SelectPolicy::Latency, None);
+        assert_eq!(names(&by_latency), ["香港 01", "香港 02", "日本 01"]);
+        let by_jitter = rank(all.clone(), SelectPolicy::Jitter, None);
+        assert_eq!(names(&by_jitter)[0], "香港 02");
+        let by_score = rank(all.clone(), SelectPolicy::Score, None);
+        assert_eq!(names(&by_score)[0], "香港 02");
+
+        let pinned = rank(all.clone(), SelectPolicy::Latency, Some(parse_region("jp").unwrap()));
+        assert_eq!(names(&pinned), ["日本 01"]);
+        assert!(parse_region("火星").is_err());
+
+        let ranked = rank(all, SelectPolicy::Random, None);
+        for seed in 0..10 {
+            let chosen = choose(&ranked, SelectPolicy::Random, 2, seed).unwrap();
+            assert!(names(&ranked[..2]).contains(&chosen.node.name.as_str()));
+        }
+        assert!(choose(&[], SelectPolicy::Random, 3, 1).is_none());
+
+        // 时段规则优先的节点排在前面，没有符合的节点时保持原顺序
+        let evening: TimeRule = serde_yaml::from_str("{from: \"19:00\", to: \"23:00\", region: jp}").unwrap();
+        let (preferred, count) = prefer(by_latency.clone(), Some(&evening));
+        assert_eq!((names(&preferred), count), (vec!["日本 01", "香港 01", "香港 02"], 1));
+        let nowhere: TimeRule = serde_yaml::from_str("{from: \"19:00\", to: \"23:00\", nodes: premium}").unwrap();
+        let (unchanged, count) = prefer(by_latency.clone(), Some(&nowhere));
+        assert_eq!((names(&unchanged), count), (names(&by_latency), 0));
+    }
+
+    #[test]
+    fn accepts_legacy_boolean_setting() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            #[serde(deserialize_with = "deserialize_compat")]
+            auto_select: AutoSelectConfig,
+        }
+
+        let legacy: Wrapper = serde_yaml::from_str("auto_select: false").unwrap();
+        assert_eq!(legacy.auto_select, AutoSelectConfig { enabled: false, ..Default::default() });
+
+        let block: Wrapper = serde_yaml::from_str("auto_select:\n  policy: random\n  top_n: 5").unwrap();
+        assert_eq!(block.auto_select.policy, SelectPolicy::Random);
+        assert_eq!(block.auto_select.top_n, 5);
+        assert!(block.auto_select.enabled);
+    }
+
+    #[test]
+    fn reselects_when_selection_settings_change() {
+        let old = AutoSelectConfig::default();
+        assert!(!old.needs_reselect(&old));
+
+        let policy = AutoSelectConfig { policy: SelectPolicy::Score, ..Default::default() };
+        assert!(policy.needs_reselect(&old));
+        let region = AutoSelectConfig { region: Some("jp".to_string()), ..Default::default() };
+        assert!(region.needs_reselect(&old));
+        let disabled = AutoSelectConfig { enabled: false, ..Default::default() };
+        assert!(old.needs_reselect(&disabled));
+        assert!(!disabled.needs_reselect(&old));
+
+        // 重选间隔由空闲重选每轮读取，不需要立即重选
+        let interval = AutoSelectConfig { idle_reselect_minutes: 30, ..Default::default() };
+        assert!(!interval.needs_reselect(&old));
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::Config;
+
+/// 自启动项使用的名称，与卸载时清理的名称保持一致
+pub const SERVICE_NAME: &str = "clashfun";
+#[cfg(target_os = "macos")]
+pub const LAUNCH_AGENT_LABEL: &str = "com.clashfun.cf";
+#[cfg(windows)]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+/// 自启动时运行的命令，便携模式下带上 --portable 以使用同一份配置
+fn start_command() -> Result<(PathBuf, Vec<&'static str>)> {
+    let exe = std::env::current_exe().context("无法获取程序路径")?;
+    let mut args = vec!["start"];
+    if Config::is_portable() {
+        args.insert(0, "--portable");
+    }
+    Ok((exe, args))
+}
+
+/// 自启动项所在位置（systemd 用户服务、XDG 自启动 / LaunchAgent）
+pub fn files() -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    if let Some(config_dir) = dirs::config_dir() {
+        files.push(config_dir.join("systemd/user").join(format!("{}.service", SERVICE_NAME)));
+        files.push(config_dir.join("autostart").join(format!("{}.desktop", SERVICE_NAME)));
+    }
+
+    #[cfg(target_os = "macos")]
+    if let Some(home) = dirs::home_dir() {
+        files.push(home.join("Library/LaunchAgents").join(format!("{}.plist", LAUNCH_AGENT_LABEL)));
+    }
+
+    files
+}
+
+/// 是否已设置登录后自动启动
+pub fn enabled() -> bool {
+    #[cfg(windows)]
+    {
+        Command::new("reg")
+            .args(["query", RUN_KEY, "/v", SERVICE_NAME])
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    #[cfg(not(windows))]
+    files().iter().any(|file| file.exists())
+}
+
+/// 登录后自动运行 cf start，返回自启动项的位置
+#[cfg(target_os = "linux")]
+pub fn enable() -> Result<String> {
+    let (exe, args) = start_command()?;
+    let command = format!("\"{}\" {}", exe.display(), args.join(" "));
+    let files = files();
+    let (unit, desktop) = (&files[0], &files[1]);
+
+    // 有 systemd 用户实例时使用用户服务，异常退出后自动重启；否则使用桌面环境的自启动
+    let systemd = Command::new("systemctl")
+        .args(["--user", "show-environment"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if systemd {
+        fs::create_dir_all(unit.parent().context("无效的服务路径")?)?;
+        // Ctrl+C 对应 SIGINT，守护进程据此正常退出并清理控制通道
+        let content = format!(
+            "[Unit]\nDescription=ClashFun 游戏加速器\nAfter=network-online.target\n\n\
+             [Service]\nExecStart={}\nRestart=on-failure\nRestartSec=5\nKillSignal=SIGINT\n\n\
+             [Install]\nWantedBy=default.target\n",
+            command
+        );
+        fs::write(unit, content).with_context(|| format!("无法写入 {:?}", unit))?;
+        let _ = Command::new("systemctl").args(["--user", "daemon-reload"]).output();
+        let status = Command::new("systemctl")
+            .args(["--user", "enable", SERVICE_NAME])
+            .output()
+            .context("无法运行 systemctl")?;
+        if !status.status.success() {
+            anyhow::bail!("systemctl 启用服务失败: {}", String::from_utf8_lossy(&status.stderr).trim());
+        }
+        return Ok(unit.display().to_string());
+    }
+
+    fs::create_dir_all(desktop.parent().context("无效的自启动路径")?)?;
+    let content = format!(
+        "[Desktop Entry]\nType=Application\nName=ClashFun\nComment=轻量级游戏加速器\nExec={}\nTerminal=false\nX-GNOME-Autostart-enabled=true\n",
+        command
+    );
+    fs::write(desktop, content).with_context(|| format!("无法写入 {:?}", desktop))?;
+    Ok(desktop.display().to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub fn enable() -> Result<String> {
+    let (exe, args) = start_command()?;
+    let plist = files().remove(0);
+    let log = Config::cache_dir()?.join("daemon.log");
+    fs::create_dir_all(plist.parent().context("无效的 LaunchAgent 路径")?)?;
+    fs::create_dir_all(Config::cache_dir()?)?;
+
+    let arguments: String = std::iter::once(exe.display().to_string())
+        .chain(args.iter().map(|arg| arg.to_string()))
+        .map(|arg| format!("        <string>{}</string>\n", arg))
+        .collect();
+    let content = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \x20   <key>Label</key>\n    <string>{}</string>\n\
+         \x20   <key>ProgramArguments</key>\n    <array>\n{}    </array>\n\
+         \x20   <key>RunAtLoad</key>\n    <true/>\n\
+         \x20   <key>KeepAlive</key>\n    <dict>\n        <key>SuccessfulExit</key>\n        <false/>\n    </dict>\n\
+         \x20   <key>StandardOutPath</key>\n    <string>{}</string>\n\
+         \x20   <key>StandardErrorPath</key>\n    <string>{}</string>\n\
+         </dict>\n</plist>\n",
+        LAUNCH_AGENT_LABEL,
+        arguments,
+        log.display(),
+        log.display()
+    );
+    fs::write(&plist, content).with_context(|| format!("无法写入 {:?}", plist))?;
+    let _ = Command::new("launchctl").arg("load").arg("-w").arg(&plist).output();
+    Ok(plist.display().to_string())
+}
+
+/// 写入当前用户的 Run 注册表项，登录后在用户会话中运行，
+/// 这样可以读取用户的配置并弹出通知（系统服务运行在会话 0，两者都做不到）
+#[cfg(windows)]
+pub fn enable() -> Result<String> {
+    let (exe, args) = start_command()?;
+    let command = format!("\"{}\" {}", exe.display(), args.join(" "));
+    let output = Command::new("reg")
+        .args(["add", RUN_KEY, "/v", SERVICE_NAME, "/t", "REG_SZ", "/d", &command, "/f"])
+        .output()
+        .context("无法运行 reg")?;
+    if !output.status.success() {
+        anyhow::bail!("写入注册表失败: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(format!(r"{}\{}", RUN_KEY, SERVICE_NAME))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn enable() -> Result<String> {
+    let _ = start_command()?;
+    anyhow::bail!("当前系统不支持设置自启动")
+}
+
+/// 移除所有自启动项，返回是否移除了任何一项
+pub fn disable() -> bool {
+    let mut removed = false;
+
+    for file in files() {
+        if !file.exists() {
+            continue;
+        }
+
+        #[cfg(target_os = "linux")]
+        if file.extension().and_then(|e| e.to_str()) == Some("service") {
+            let _ = Command::new("systemctl")
+                .args(["--user", "disable", "--now", SERVICE_NAME])
+                .output();
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            let _ = Command::new("launchctl").arg("unload").arg(&file).output();
+        }
+
+        match fs::remove_file(&file) {
+            Ok(()) => {
+                println!("✅ 已移除自启动项: {}", file.display());
+                removed = true;
+            }
+            Err(e) => println!("⚠️  移除自启动项失败 {}: {}", file.display(), e),
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let deleted = Command::new("reg")
+            .args(["delete", RUN_KEY, "/v", SERVICE_NAME, "/f"])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        if deleted {
+            println!("✅ 已移除开机自启动项");
+            removed = true;
+        }
+    }
+
+    if !removed {
+        info!("没有找到自启动项");
+    }
+    removed
+}
@@ -0,0 +1,123 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// 等待客户端发送首个数据包的时间，与连接节点同时进行，通常不会增加延迟
+const DETECT_TIMEOUT: Duration = Duration::from_millis(150);
+/// BitTorrent 握手：长度 19 加上协议名
+const HANDSHAKE: &[u8] = b"\x13BitTorrent protocol";
+/// UDP tracker 连接请求开头的固定协议标识 (BEP 15)
+const UDP_TRACKER_MAGIC: [u8; 8] = 0x0417_2710_1980u64.to_be_bytes();
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TCP_BLOCKED: AtomicU64 = AtomicU64::new(0);
+static UDP_BLOCKED: AtomicU64 = AtomicU64::new(0);
+
+/// 拦截 BitTorrent 流量的累计统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BtStats {
+    /// 拒绝的 TCP 连接
+    pub tcp_blocked: u64,
+    /// 丢弃的 DHT / UDP tracker 数据包
+    pub udp_blocked: u64,
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn stats() -> BtStats {
+    BtStats {
+        tcp_blocked: TCP_BLOCKED.load(Ordering::Relaxed),
+        udp_blocked: UDP_BLOCKED.load(Ordering::Relaxed),
+    }
+}
+
+/// TCP 数据以明文 BitTorrent 握手开头
+///
+/// 加密握手 (MSE/PE) 看起来是随机数据，无法识别。
+pub fn is_handshake(data: &[u8]) -> bool {
+    data.starts_with(HANDSHAKE)
+}
+
+/// UDP 数据包是 DHT 消息或 UDP tracker 连接请求
+///
+/// uTP 的包头太短，容易与游戏数据包混淆，不做识别。
+pub fn is_udp_packet(data: &[u8]) -> bool {
+    // DHT 消息是以 d 开头、e 结尾的 bencode 字典，并带有消息类型 y
+    let dht = data.starts_with(b"d1:") && data.ends_with(b"e") && data.windows(5).any(|w| w == b"1:y1:");
+    let tracker = data.len() >= 16 && data[..8] == UDP_TRACKER_MAGIC && data[8..12] == [0, 0, 0, 0];
+    dht || tracker
+}
+
+/// 查看（不读取）客户端发送的首批数据，拦截开启且是 BitTorrent 握手时返回 true
+pub async fn should_block_tcp(stream: &TcpStream, client_addr: SocketAddr) -> bool {
+    if !enabled() {
+        return false;
+    }
+    let mut buf = [0u8; HANDSHAKE.len()];
+    let detected = tokio::time::timeout(DETECT_TIMEOUT, async {
+        loop {
+            let size = stream.peek(&mut buf).await.ok()?;
+            if size == 0 || !HANDSHAKE.starts_with(&buf[..size]) {
+                return Some(false);
+            }
+            if size == buf.len() {
+                return Some(is_handshake(&buf));
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or(false);
+
+    if detected {
+        TCP_BLOCKED.fetch_add(1, Ordering::Relaxed);
+        warn!("拒绝来自 {} 的 BitTorrent 连接，避免节点账号因下载而被封禁", client_addr);
+    }
+    detected
+}
+
+/// 拦截开启且是 BitTorrent 的 UDP 数据包时返回 true
+pub fn should_block_udp(data: &[u8], client_addr: SocketAddr) -> bool {
+    if !enabled() || !is_udp_packet(data) {
+        return false;
+    }
+    UDP_BLOCKED.fetch_add(1, Ordering::Relaxed);
+    debug!("丢弃来自 {} 的 BitTorrent UDP 包", client_addr);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_handshakes_dht_and_trackers() {
+        let mut handshake = HANDSHAKE.to_vec();
+        handshake.extend_from_slice(&[0; 8]);
+        assert!(is_handshake(&handshake));
+        assert!(!is_handshake(b"\x16\x03\x01\x02\x00"));
+        assert!(!is_handshake(b"\x13BitTorr"));
+
+        assert!(is_udp_packet(b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe"));
+        assert!(is_udp_packet(b"d1:rd2:id20:abcdefghij0123456789e1:t2:aa1:y1:re"));
+        let mut connect = UDP_TRACKER_MAGIC.to_vec();
+        connect.extend_from_slice(&[0, 0, 0, 0, 1, 2, 3, 4]);
+        assert!(is_udp_packet(&connect));
+
+        // 游戏数据包
+        assert!(!is_udp_packet(b"d1:"));
+        assert!(!is_udp_packet(&[0x41, 0x00, 0x12, 0x34, 0, 0, 0, 0]));
+        assert!(!is_udp_packet(b"\xff\xff\xff\xffTSource Engine Query\0"));
+    }
+}
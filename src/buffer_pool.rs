@@ -0,0 +1,100 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use crate::profiling::{self, LockSite};
+
+/// UDP 数据包的最大长度
+pub const UDP_BUFFER_SIZE: usize = 64 * 1024;
+/// 池中最多保留的空闲缓冲区数量，超出的直接释放
+const MAX_IDLE_BUFFERS: usize = 256;
+
+/// 可复用的固定大小缓冲区池，避免每个数据包都重新分配内存
+#[derive(Clone)]
+pub struct BufferPool {
+    idle: Arc<Mutex<Vec<Box<[u8]>>>>,
+    buffer_size: usize,
+    max_idle: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            idle: Arc::new(Mutex::new(Vec::new())),
+            buffer_size,
+            max_idle: MAX_IDLE_BUFFERS,
+        }
+    }
+
+    /// 设置最多保留的空闲缓冲区数量，内存紧张时调小
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// 池中空闲的缓冲区数量
+    pub fn idle_buffers(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// 取出一个缓冲区，长度为池的缓冲区大小；释放时自动归还
+    pub fn get(&self) -> PooledBuffer {
+        let buf = profiling::lock_sync(LockSite::BufferPool, &self.idle)
+            .pop()
+            .unwrap_or_else(|| vec![0; self.buffer_size].into_boxed_slice());
+
+        PooledBuffer {
+            len: buf.len(),
+            buf: Some(buf),
+            pool: self.clone(),
+        }
+    }
+
+    fn put(&self, buf: Box<[u8]>) {
+        let mut idle = profiling::lock_sync(LockSite::BufferPool, &self.idle);
+        if idle.len() < self.max_idle {
+            idle.push(buf);
+        }
+    }
+}
+
+/// 从池中借出的缓冲区，Drop 时归还
+pub struct PooledBuffer {
+    buf: Option<Box<[u8]>>,
+    len: usize,
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// 收到数据后截断为实际长度，不会重新分配
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf.as_ref().expect("缓冲区已归还")[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf.as_mut().expect("缓冲区已归还")[..self.len]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.put(buf);
+        }
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::RwLock;
+use tokio::net::TcpStream;
+
+/// 直连规则：匹配的连接不经过加速节点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BypassRule {
+    /// 域名及其子域名
+    Domain(String),
+    /// IP 段，单个 IP 视为 /32 或 /128
+    Cidr { network: IpAddr, prefix: u8 },
+    /// 目标端口范围
+    Port { start: u16, end: u16 },
+}
+
+/// 常用规则集合
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BypassPreset {
+    /// 局域网地址
+    Lan,
+    /// 常见视频/直播服务
+    Streaming,
+}
+
+impl BypassPreset {
+    pub fn rules(self) -> &'static [&'static str] {
+        match self {
+            Self::Lan => &[
+                "10.0.0.0/8",
+                "172.16.0.0/12",
+                "192.168.0.0/16",
+                "169.254.0.0/16",
+                "fc00::/7",
+                "fe80::/10",
+            ],
+            Self::Streaming => &[
+                "netflix.com",
+                "nflxvideo.net",
+                "youtube.com",
+                "googlevideo.com",
+                "twitch.tv",
+                "bilibili.com",
+                "bilivideo.com",
+                "iqiyi.com",
+                "youku.com",
+                "douyin.com",
+            ],
+        }
+    }
+}
+
+/// 连接的原始目标，不知道的部分为 None
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Destination<'a> {
+    pub host: Option<&'a str>,
+    pub addr: Option<SocketAddr>,
+}
+
+impl FromStr for BypassRule {
+    type Err = anyhow::Error;
+
+    /// 支持 `example.com`、`192.168.0.0/16`、`10.0.0.1`、`port:80`、`port:27000-27100`
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            bail!("规则不能为空");
+        }
+
+        if let Some(ports) = s.strip_prefix("port:") {
+            let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+            let start: u16 = start.trim().parse().map_err(|_| anyhow!("无效的端口: {}", start))?;
+            let end: u16 = end.trim().parse().map_err(|_| anyhow!("无效的端口: {}", end))?;
+            if start == 0 || start > end {
+                bail!("无效的端口范围: {}", ports);
+            }
+            return Ok(Self::Port { start, end });
+        }
+
+        let (ip, prefix) = s.split_once('/').map_or((s, None), |(ip, prefix)| (ip, Some(prefix)));
+        if let Ok(network) = ip.parse::<IpAddr>() {
+            let max = if network.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max)
+                    .ok_or_else(|| anyhow!("无效的前缀长度: {}", prefix))?,
+                None => max,
+            };
+            return Ok(Self::Cidr {
+                network: mask(network, prefix),
+                prefix,
+            });
+        }
+        if prefix.is_some() {
+            bail!("无效的 IP 段: {}", s);
+        }
+
+        let domain = s.trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+        let valid = !domain.is_empty()
+            && domain.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+            });
+        if !valid {
+            bail!("无法识别的规则: {}（应为域名、IP/CIDR 或 port:端口）", s);
+        }
+        Ok(Self::Domain(domain))
+    }
+}
+
+impl fmt::Display for BypassRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Domain(domain) => write!(f, "{}", domain),
+            Self::Cidr { network, prefix } => write!(f, "{}/{}", network, prefix),
+            Self::Port { start, end } if start == end => write!(f, "port:{}", start),
+            Self::Port { start, end } => write!(f, "port:{}-{}", start, end),
+        }
+    }
+}
+
+impl BypassRule {
+    pub fn matches(&self, destination: &Destination) -> bool {
+        match self {
+            Self::Domain(domain) => destination.host.is_some_and(|host| {
+                let host = host.trim_end_matches('.').to_ascii_lowercase();
+                host == *domain || host.ends_with(&format!(".{}", domain))
+            }),
+            Self::Cidr { network, prefix } => destination.addr.is_some_and(|addr| {
+                let ip = match (addr.ip(), network) {
+                    // IPv4 映射的 IPv6 地址按 IPv4 匹配
+                    (IpAddr::V6(v6), IpAddr::V4(_)) => v6.to_ipv4_mapped().map(IpAddr::V4),
+                    (ip, _) => Some(ip),
+                };
+                ip.is_some_and(|ip| ip.is_ipv4() == network.is_ipv4() && mask(ip, *prefix) == *network)
+            }),
+            Self::Port { start, end } => destination
+                .addr
+                .is_some_and(|addr| (*start..=*end).contains(&addr.port())),
+        }
+    }
+}
+
+fn mask(ip: IpAddr, prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let bits = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix as u32) };
+            IpAddr::V4((u32::from(v4) & bits).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix as u32) };
+            IpAddr::V6((u128::from(v6) & bits).into())
+        }
+    }
+}
+
+static RULES: RwLock<Vec<BypassRule>> = RwLock::new(Vec::new());
+
+/// 设置生效的直连规则，无效的规则会被忽略
+pub fn set_rules(rules: &[String]) {
+    let parsed = rules
+        .iter()
+        .filter_map(|rule| match rule.parse() {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                warn!("忽略无效的直连规则 {}: {}", rule, e);
+                None
+            }
+        })
+        .collect();
+    *RULES.write().unwrap_or_else(|e| e.into_inner()) = parsed;
+}
+
+/// 是否有域名规则；没有时不需要识别目标域名，由服务端先发送数据的连接不必等待
+pub fn has_domain_rules() -> bool {
+    contains_domain(&RULES.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+fn contains_domain(rules: &[BypassRule]) -> bool {
+    rules.iter().any(|rule| matches!(rule, BypassRule::Domain(_)))
+}
+
+/// 目标是否命中直连规则
+pub fn should_bypass(destination: &Destination) -> bool {
+    RULES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|rule| rule.matches(destination))
+}
+
+/// 被 iptables REDIRECT 或 TPROXY 到代理端口的连接的原始目标，直接连接到代理时返回 None
+#[cfg(target_os = "linux")]
+pub fn original_destination(stream: &TcpStream) -> Option<SocketAddr> {
+    use std::os::fd::AsRawFd;
+
+    const SO_ORIGINAL_DST: libc::c_int = 80;
+
+    let local = stream.local_addr().ok()?;
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_IP,
+            SO_ORIGINAL_DST,
+            &mut addr as *mut libc::sockaddr_in as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 || addr.sin_family != libc::AF_INET as libc::sa_family_t {
+        return crate::netfilter::tproxy_destination(local);
+    }
+
+    let original = SocketAddr::from((
+        std::net::Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        u16::from_be(addr.sin_port),
+    ));
+    (original != local).then_some(original)
+}
+
+/// 被 pf rdr 规则重定向到代理端口的连接的原始目标
+#[cfg(target_os = "macos")]
+pub fn original_destination(stream: &TcpStream) -> Option<SocketAddr> {
+    crate::pf::original_destination(stream.peer_addr().ok()?, stream.local_addr().ok()?)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn original_destination(_stream: &TcpStream) -> Option<SocketAddr> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dest(host: Option<&str>, addr: &str) -> bool {
+        let rules: Vec<BypassRule> = ["example.com", "192.168.0.0/16", "port:27000-27100", "fe80::/10"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect();
+        let destination = Destination {
+            host,
+            addr: addr.parse().ok(),
+        };
+        rules.iter().any(|rule| rule.matches(&destination))
+    }
+
+    #[test]
+    fn parses_and_formats_rules() {
+        for (input, expected) in [
+            ("*.Example.COM", "example.com"),
+            ("192.168.1.7/16", "192.168.0.0/16"),
+            ("10.0.0.1", "10.0.0.1/32"),
+            ("port:443", "port:443"),
+            ("port:27000-27100", "port:27000-27100"),
+            ("0.0.0.0/0", "0.0.0.0/0"),
+        ] {
+            assert_eq!(input.parse::<BypassRule>().unwrap().to_string(), expected);
+        }
+
+        for invalid in ["", "port:0", "port:9-1", "10.0.0.0/33", "bad/rule", "exa mple.com"] {
+            assert!(invalid.parse::<BypassRule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn matches_domains_cidrs_and_ports() {
+        assert!(dest(Some("video.example.com"), "1.1.1.1:443"));
+        assert!(!dest(Some("notexample.com"), "1.1.1.1:443"));
+        assert!(dest(None, "192.168.3.4:80"));
+        assert!(dest(None, "[::ffff:192.168.3.4]:80"));
+        assert!(dest(None, "[fe80::1]:80"));
+        assert!(dest(None, "8.8.8.8:27015"));
+        assert!(!dest(None, "8.8.8.8:443"));
+        assert!(!dest(None, "no address"));
+    }
+
+    #[test]
+    fn only_domain_rules_need_sniffing() {
+        let parse = |rules: &[&str]| -> Vec<BypassRule> { rules.iter().map(|r| r.parse().unwrap()).collect() };
+        assert!(!contains_domain(&parse(&["192.168.0.0/16", "port:27000-27100"])));
+        assert!(contains_domain(&parse(&["192.168.0.0/16", "bilibili.com"])));
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::classifier;
+use crate::game_detect::SupportedGame;
+
+/// pcapng 中的链路类型：没有链路层，直接是 IP 包
+const LINKTYPE_RAW: u16 = 101;
+const OPT_COMMENT: u16 = 1;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+/// 守护进程在抓包时长之后多等一会儿再自动停止，留给 cf capture 自己结束
+const AUTO_STOP_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// 抓包参数，由 cf capture 通过控制通道发给守护进程
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureOptions {
+    pub path: PathBuf,
+    /// 标注数据包是否匹配该游戏的特征
+    pub game: Option<SupportedGame>,
+    /// 每个包最多保存的负载字节数，None 表示完整保存
+    pub max_payload: Option<usize>,
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSummary {
+    pub path: PathBuf,
+    pub packets: u64,
+    pub bytes: u64,
+    /// 匹配游戏特征的 UDP 包数量
+    pub matched: u64,
+}
+
+struct Capture {
+    id: u64,
+    writer: PcapngWriter<BufWriter<File>>,
+    options: CaptureOptions,
+    /// 每个方向的 TCP 序号，按 (源, 目标) 记录
+    tcp_seq: HashMap<(SocketAddr, SocketAddr), u32>,
+    packets: u64,
+    bytes: u64,
+    matched: u64,
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 是否正在抓包，转发路径据此决定是否需要拷贝数据
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 开始抓包，超过设定时长后自动停止
+pub fn start(options: CaptureOptions) -> Result<()> {
+    let mut capture = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    if capture.is_some() {
+        bail!("已经在抓包中");
+    }
+
+    let file = File::create(&options.path).with_context(|| format!("无法创建抓包文件: {:?}", options.path))?;
+    let comment = match &options.game {
+        Some(game) => format!("ClashFun 转发流量，标注 {} 的特征匹配结果", game.display_name()),
+        None => "ClashFun 转发流量".to_string(),
+    };
+    let writer = PcapngWriter::new(BufWriter::new(file), &comment)?;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let timeout = Duration::from_secs(options.duration_secs) + AUTO_STOP_GRACE;
+    info!("开始抓包: {:?}", options.path);
+    *capture = Some(Capture {
+        id,
+        writer,
+        options,
+        tcp_seq: HashMap::new(),
+        packets: 0,
+        bytes: 0,
+        matched: 0,
+    });
+    ACTIVE.store(true, Ordering::Relaxed);
+
+    // cf capture 被强制结束时也不会一直抓下去
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        if let Some(summary) = stop_if(|capture| capture.id == id) {
+            info!("抓包超时自动停止，已记录 {} 个数据包", summary.packets);
+        }
+    });
+    Ok(())
+}
+
+/// 停止抓包并返回统计，没有在抓包时返回 None
+pub fn stop() -> Option<CaptureSummary> {
+    stop_if(|_| true)
+}
+
+fn stop_if(condition: impl FnOnce(&Capture) -> bool) -> Option<CaptureSummary> {
+    let mut guard = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    if !guard.as_ref().is_some_and(condition) {
+        return None;
+    }
+    ACTIVE.store(false, Ordering::Relaxed);
+    let mut capture = guard.take()?;
+    if let Err(e) = capture.writer.flush() {
+        warn!("写入抓包文件失败: {}", e);
+    }
+    info!("抓包已停止: {:?}", capture.options.path);
+    Some(CaptureSummary {
+        path: capture.options.path,
+        packets: capture.packets,
+        bytes: capture.bytes,
+        matched: capture.matched,
+    })
+}
+
+/// 记录一个转发的数据包，src/dst 为客户端和节点的地址
+pub fn record(transport: Transport, src: SocketAddr, dst: SocketAddr, payload: &[u8]) {
+    if !active() {
+        return;
+    }
+    let mut guard = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(capture) = guard.as_mut() else { return };
+
+    let seq = match transport {
+        Transport::Tcp => {
+            let seq = capture.tcp_seq.entry((src, dst)).or_insert(0);
+            let current = *seq;
+            *seq = seq.wrapping_add(payload.len() as u32);
+            current
+        }
+        Transport::Udp => 0,
+    };
+
+    let mut comment = None;
+    if let (Some(game), Transport::Udp) = (&capture.options.game, transport) {
+        let matched = classifier::payload_matches(game, payload);
+        capture.matched += matched as u64;
+        comment = Some(if matched { "特征匹配" } else { "特征不匹配" });
+    }
+
+    let packet = build_packet(transport, src, dst, seq, payload);
+    let headers = packet.len() - payload.len();
+    let captured = match capture.options.max_payload {
+        Some(max) => headers + payload.len().min(max),
+        None => packet.len(),
+    };
+    match capture.writer.write_packet(SystemTime::now(), &packet[..captured], packet.len(), comment) {
+        Ok(()) => {
+            capture.packets += 1;
+            capture.bytes += payload.len() as u64;
+        }
+        Err(e) => {
+            warn!("写入抓包文件失败，停止抓包: {}", e);
+            ACTIVE.store(false, Ordering::Relaxed);
+            guard.take();
+        }
+    }
+}
+
+/// 为负载补上 IP 和 UDP/TCP 头，使 Wireshark 等工具可以直接解析
+fn build_packet(transport: Transport, src: SocketAddr, dst: SocketAddr, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut l4 = Vec::with_capacity(20 + payload.len());
+    l4.extend_from_slice(&src.port().to_be_bytes());
+    l4.extend_from_slice(&dst.port().to_be_bytes());
+    let protocol = match transport {
+        Transport::Udp => {
+            l4.extend_from_slice(&((8 + payload.len()).min(u16::MAX as usize) as u16).to_be_bytes());
+            l4.extend_from_slice(&[0, 0]);
+            17u8
+        }
+        Transport::Tcp => {
+            l4.extend_from_slice(&seq.to_be_bytes());
+            l4.extend_from_slice(&0u32.to_be_bytes());
+            // 数据偏移 5，PSH|ACK，窗口 65535
+            l4.extend_from_slice(&[0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+            6u8
+        }
+    };
+    l4.extend_from_slice(payload);
+
+    let mut packet = Vec::with_capacity(40 + l4.len());
+    match (to_v4(src.ip()), to_v4(dst.ip())) {
+        (Some(src_ip), Some(dst_ip)) => {
+            let total = (20 + l4.len()).min(u16::MAX as usize) as u16;
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total.to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            let checksum = ipv4_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        _ => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(l4.len().min(u16::MAX as usize) as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, 64]);
+            packet.extend_from_slice(&to_v6(src.ip()).octets());
+            packet.extend_from_slice(&to_v6(dst.ip()).octets());
+        }
+    }
+    packet.extend_from_slice(&l4);
+    packet
+}
+
+fn to_v4(ip: IpAddr) -> Option<std::net::Ipv4Addr> {
+    match ip {
+        IpAddr::V4(v4) => Some(v4),
+        IpAddr::V6(v6) => v6.to_ipv4_mapped(),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// 最小的 pcapng 写入器：一个 Section、一个接口、若干 Enhanced Packet Block
+struct PcapngWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    fn new(mut out: W, comment: &str) -> std::io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+        push_option(&mut body, OPT_SHB_USERAPPL, format!("ClashFun {}", env!("CARGO_PKG_VERSION")).as_bytes());
+        body.extend_from_slice(&[0; 4]);
+        write_block(&mut out, 0x0A0D_0D0A, &body)?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut body, OPT_IF_NAME, b"clashfun-relay");
+        body.extend_from_slice(&[0; 4]);
+        write_block(&mut out, 1, &body)?;
+
+        Ok(Self { out })
+    }
+
+    fn write_packet(&mut self, time: SystemTime, data: &[u8], original_len: usize, comment: Option<&str>) -> std::io::Result<()> {
+        // 默认时间精度为微秒
+        let micros = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let mut body = Vec::with_capacity(32 + data.len());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(original_len as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+        if let Some(comment) = comment {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            body.extend_from_slice(&[0; 4]);
+        }
+        write_block(&mut self.out, 6, &body)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn write_block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> std::io::Result<()> {
+    let total = (12 + body.len()) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+/// 默认的抓包文件名，例如 clashfun-1700000000.pcapng
+pub fn default_file_name() -> PathBuf {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    PathBuf::from(format!("clashfun-{}.pcapng", secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_well_formed_blocks() {
+        let mut out = Vec::new();
+        let mut writer = PcapngWriter::new(&mut out, "test").unwrap();
+        let packet = build_packet(
+            Transport::Udp,
+            "127.0.0.1:5000".parse().unwrap(),
+            "10.0.0.1:9000".parse().unwrap(),
+            0,
+            b"hello",
+        );
+        assert_eq!(packet.len(), 20 + 8 + 5);
+        assert_eq!(ipv4_checksum(&packet[..20]), 0);
+        writer.write_packet(SystemTime::now(), &packet[..30], packet.len(), Some("特征匹配")).unwrap();
+
+        // 依次遍历每个块，首尾长度一致且按 4 字节对齐
+        let mut offset = 0;
+        let mut types = Vec::new();
+        while offset < out.len() {
+            let block_type = u32::from_le_bytes(out[offset..offset + 4].try_into().unwrap());
+            let len = u32::from_le_bytes(out[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(out[offset + len - 4..offset + len], out[offset + 4..offset + 8]);
+            types.push(block_type);
+            offset += len;
+        }
+        assert_eq!(types, [0x0A0D_0D0A, 1, 6]);
+    }
+
+    #[test]
+    fn synthesizes_ipv6_tcp_headers() {
+        let packet = build_packet(
+            Transport::Tcp,
+            "[::1]:5000".parse().unwrap(),
+            "10.0.0.1:443".parse().unwrap(),
+            42,
+            b"data",
+        );
+        assert_eq!(packet[0] >> 4, 6);
+        assert_eq!(packet[6], 6);
+        assert_eq!(u32::from_be_bytes(packet[44..48].try_into().unwrap()), 42);
+        assert_eq!(&packet[60..], b"data");
+    }
+}
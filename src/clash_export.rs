@@ -0,0 +1,273 @@
+use serde_yaml::{Mapping, Value};
+
+use clashfun::parser::{ClashConfig, Proxy};
+
+use crate::bypass::BypassRule;
+use crate::config::Config;
+use crate::failover;
+use crate::node_overrides;
+use crate::subscription::{self, Node};
+
+/// 手动选择节点的策略组，所有未命中直连规则的流量都走这里
+pub const SELECT_GROUP: &str = "ClashFun";
+/// 与 ClashFun 故障切换对应的 fallback 策略组，首选节点排在最前
+const FALLBACK_GROUP: &str = "故障切换";
+/// fallback 策略组的健康检查地址
+const HEALTH_CHECK_URL: &str = "http://www.gstatic.com/generate_204";
+/// 节点字段在生成的配置中的顺序，其余字段按名称排序
+const LEADING_KEYS: [&str; 4] = ["name", "type", "server", "port"];
+
+/// 生成的 Clash 配置，以及无法在 Clash 中表达而被忽略的设置
+#[derive(Debug)]
+pub struct ClashExport {
+    pub config: Value,
+    pub ignored: Vec<String>,
+}
+
+impl ClashExport {
+    pub fn to_yaml(&self) -> serde_yaml::Result<String> {
+        serde_yaml::to_string(&self.config)
+    }
+}
+
+/// 按 ClashFun 的配置生成等效的 Clash 配置：订阅中 ClashFun 能使用的节点（已合并重复、改名并应用节点设置）、
+/// 选择和故障切换策略组、直连规则，其余流量走加速节点
+pub fn generate(config: &Config, clash: &ClashConfig, nodes: &[Node]) -> ClashExport {
+    let mut ignored = Vec::new();
+    let usable: Vec<&Node> = nodes.iter().filter(|node| node.is_supported()).collect();
+    if usable.len() < nodes.len() {
+        ignored.push(format!("{} 个协议不支持的节点", nodes.len() - usable.len()));
+    }
+
+    let mut proxies = Vec::new();
+    let mut names = Vec::new();
+    for node in &usable {
+        let Some(source) = clash.proxies.iter().find(|proxy| is_source(proxy, node)) else {
+            continue;
+        };
+        let (proxy, dropped) = export_proxy(source, node);
+        ignored.extend(dropped);
+        names.push(Value::from(node.name.clone()));
+        proxies.push(proxy);
+    }
+
+    // 首选节点排在故障切换组的最前面，与加速服务的切回行为一致
+    let selected = config
+        .selected_node
+        .as_deref()
+        .and_then(|name| subscription::find_selected(nodes, name, config.selected_node_id.as_deref()));
+    if let Some(index) = selected.and_then(|node| names.iter().position(|name| name.as_str() == Some(&node.name))) {
+        let first = names.remove(index);
+        names.insert(0, first);
+    }
+    for name in config.obfuscation.keys() {
+        ignored.push(format!("节点 {} 的流量混淆", name));
+    }
+
+    let mut fallback = Mapping::new();
+    fallback.insert("name".into(), FALLBACK_GROUP.into());
+    fallback.insert("type".into(), "fallback".into());
+    fallback.insert("proxies".into(), Value::Sequence(names.clone()));
+    fallback.insert("url".into(), HEALTH_CHECK_URL.into());
+    fallback.insert("interval".into(), failover::HEALTH_CHECK_INTERVAL.as_secs().into());
+
+    let mut select = Mapping::new();
+    select.insert("name".into(), SELECT_GROUP.into());
+    select.insert("type".into(), "select".into());
+    let mut choices = vec![Value::from(FALLBACK_GROUP)];
+    choices.extend(names);
+    select.insert("proxies".into(), Value::Sequence(choices));
+
+    let mut rules: Vec<Value> = config
+        .bypass
+        .iter()
+        .filter_map(|rule| rule.parse::<BypassRule>().ok())
+        .map(|rule| Value::from(bypass_rule(&rule)))
+        .collect();
+    rules.push(format!("MATCH,{}", SELECT_GROUP).into());
+    for device in config.lan_devices.keys() {
+        ignored.push(format!("设备 {} 的游戏端口分流", device));
+    }
+    if config.block_bt {
+        ignored.push("拒绝 BitTorrent 流量 (block_bt)".to_string());
+    }
+
+    let mut root = Mapping::new();
+    root.insert("mixed-port".into(), config.proxy_port.into());
+    root.insert("allow-lan".into(), config.allow_lan.into());
+    if config.allow_lan && !config.allowed_clients.is_empty() {
+        // mihomo 支持按地址段限制局域网客户端
+        let clients = config.allowed_clients.iter().cloned().map(Value::from).collect();
+        root.insert("lan-allowed-ips".into(), Value::Sequence(clients));
+    }
+    root.insert("mode".into(), "rule".into());
+    root.insert("log-level".into(), log_level(config.log_level.as_deref()).into());
+    root.insert("proxies".into(), Value::Sequence(proxies));
+    root.insert(
+        "proxy-groups".into(),
+        Value::Sequence(vec![Value::Mapping(select), Value::Mapping(fallback)]),
+    );
+    root.insert("rules".into(), Value::Sequence(rules));
+
+    ClashExport {
+        config: Value::Mapping(root),
+        ignored,
+    }
+}
+
+/// 订阅中解析出该节点的原始代理，改名后按地址、认证信息和其余字段的摘要查找
+fn is_source(proxy: &Proxy, node: &Node) -> bool {
+    let text = |key: &str| proxy.get(key).and_then(Value::as_str);
+    text("server").is_some_and(|server| server.eq_ignore_ascii_case(&node.server))
+        && proxy.get("port").and_then(Value::as_u64) == Some(node.port as u64)
+        && text("type").is_some_and(|protocol| protocol.eq_ignore_ascii_case(&node.protocol))
+        && text("password") == node.password.as_deref()
+        && text("cipher") == node.cipher.as_deref()
+        && text("username") == node.username.as_deref()
+        && node.fingerprint.is_none_or(|fingerprint| subscription::fingerprint(proxy) == fingerprint)
+}
+
+/// 复制原始代理的全部字段，换成 ClashFun 中的名称并应用节点设置；返回无法表达的设置
+fn export_proxy(source: &Proxy, node: &Node) -> (Value, Vec<String>) {
+    let mut fields = source.clone();
+    fields.insert("name".to_string(), node.name.clone().into());
+
+    let applied = node_overrides::for_node(node);
+    if let Some(sni) = &applied.sni {
+        // vmess/vless 的 SNI 字段是 servername，其他协议是 sni
+        let key = if matches!(node.protocol.to_ascii_lowercase().as_str(), "vmess" | "vless") {
+            "servername"
+        } else {
+            "sni"
+        };
+        fields.insert(key.to_string(), sni.clone().into());
+    }
+    if !applied.udp {
+        fields.insert("udp".to_string(), false.into());
+    }
+    let mut dropped = Vec::new();
+    if applied.connect_timeout_ms.is_some() {
+        dropped.push(format!("节点 {} 的连接超时", node.name));
+    }
+
+    let mut proxy = Mapping::new();
+    for key in LEADING_KEYS {
+        if let Some(value) = fields.remove(key) {
+            proxy.insert(key.into(), value);
+        }
+    }
+    let mut rest: Vec<_> = fields.into_iter().collect();
+    rest.sort_by(|a, b| a.0.cmp(&b.0));
+    for (key, value) in rest {
+        proxy.insert(key.into(), value);
+    }
+    (Value::Mapping(proxy), dropped)
+}
+
+/// 直连规则对应的 Clash 规则
+fn bypass_rule(rule: &BypassRule) -> String {
+    match rule {
+        BypassRule::Domain(domain) => format!("DOMAIN-SUFFIX,{},DIRECT", domain),
+        BypassRule::Cidr { network, prefix } if network.is_ipv4() => {
+            format!("IP-CIDR,{}/{},DIRECT,no-resolve", network, prefix)
+        }
+        BypassRule::Cidr { network, prefix } => format!("IP-CIDR6,{}/{},DIRECT,no-resolve", network, prefix),
+        BypassRule::Port { start, end } if start == end => format!("DST-PORT,{},DIRECT", start),
+        BypassRule::Port { start, end } => format!("DST-PORT,{}-{},DIRECT", start, end),
+    }
+}
+
+/// Clash 的日志级别没有 trace，warn 写作 warning
+fn log_level(level: Option<&str>) -> &'static str {
+    match level.map(str::to_ascii_lowercase).as_deref() {
+        Some("error") => "error",
+        Some("warn") | Some("warning") => "warning",
+        Some("debug") | Some("trace") => "debug",
+        Some("off") => "silent",
+        _ => "info",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clash_import;
+    use crate::subscription::SubscriptionManager;
+
+    #[test]
+    fn generates_config_that_parses_back_to_the_same_nodes() {
+        let clash: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - {name: "香港 01", type: ss, server: hk.example.com, port: 8388, cipher: aes-128-gcm, password: secret}
+  - {name: "香港 01 备用", type: ss, server: hk.example.com, port: 8388, cipher: aes-128-gcm, password: secret}
+  - {name: "日本 01", type: trojan, server: jp.example.com, port: 443, password: pass, udp: true}
+  - {name: "美国 01", type: hysteria2, server: us.example.com, port: 443, password: pass}
+"#,
+        )
+        .unwrap();
+        let manager = SubscriptionManager::new();
+        let nodes = manager.parse_nodes(&clash).unwrap();
+        let config = Config {
+            selected_node: Some("日本 01".to_string()),
+            bypass: vec!["192.168.0.0/16".into(), "bilibili.com".into(), "port:27000-27100".into()],
+            ..Config::default()
+        };
+
+        let export = generate(&config, &clash, &nodes);
+        let yaml = export.to_yaml().unwrap();
+        assert!(export.ignored.iter().any(|item| item.contains("协议不支持")));
+
+        let groups = export.config["proxy-groups"].as_sequence().unwrap();
+        assert_eq!(groups[1]["proxies"][0].as_str(), Some("日本 01"));
+        let rules: Vec<&str> = export.config["rules"].as_sequence().unwrap().iter().filter_map(Value::as_str).collect();
+        assert_eq!(
+            rules,
+            [
+                "IP-CIDR,192.168.0.0/16,DIRECT,no-resolve",
+                "DOMAIN-SUFFIX,bilibili.com,DIRECT",
+                "DST-PORT,27000-27100,DIRECT",
+                "MATCH,ClashFun",
+            ]
+        );
+
+        // 生成的配置作为订阅重新解析，得到的节点与 ClashFun 使用的一致
+        let reparsed = manager.parse_nodes(&manager.parse_subscription_content(&yaml).unwrap()).unwrap();
+        let key = |nodes: &[Node]| {
+            let mut keys: Vec<_> = nodes.iter().filter(|n| n.is_supported()).map(|n| (n.name.clone(), n.id())).collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(key(&reparsed), key(&nodes));
+
+        let dir = std::env::temp_dir().join(format!("clashfun-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.yaml"), &yaml).unwrap();
+        let imported = clash_import::import_from(&dir).unwrap();
+        assert_eq!(imported.proxy_port, Some(config.proxy_port));
+        assert_eq!(imported.proxy_count, 2);
+        assert_eq!(imported.selected_node.as_deref(), Some("日本 01"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn exports_the_source_proxy_of_each_cdn_fronted_node() {
+        let clash: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - {name: "香港 01", type: vmess, server: cdn.example.com, port: 443, uuid: 1111, network: ws, ws-opts: {headers: {Host: hk.example.com}}}
+  - {name: "日本 01", type: vmess, server: cdn.example.com, port: 443, uuid: 2222, network: ws, ws-opts: {headers: {Host: jp.example.com}}}
+"#,
+        )
+        .unwrap();
+        let nodes = SubscriptionManager::new().parse_nodes(&clash).unwrap();
+        let export = generate(&Config::default(), &clash, &nodes);
+        let proxies = export.config["proxies"].as_sequence().unwrap();
+        assert_eq!(proxies.len(), 2);
+        for proxy in proxies {
+            let source = clash.proxies.iter().find(|source| source["name"] == proxy["name"]).unwrap();
+            assert_eq!(proxy["uuid"], source["uuid"]);
+            assert_eq!(proxy["ws-opts"], source["ws-opts"]);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bypass::BypassRule;
+use crate::config::Config;
+
+/// Clash Verge Rev 的应用标识，用作其配置目录名
+const CLASH_VERGE_ID: &str = "io.github.clash-verge-rev.clash-verge-rev";
+/// 旧版 Clash Verge 的应用标识
+const CLASH_VERGE_LEGACY_ID: &str = "io.github.clash-verge";
+
+/// 从现有 Clash 安装中读取到的设置
+#[derive(Debug, Default)]
+pub struct ClashImport {
+    pub source: PathBuf,
+    pub subscription_url: Option<String>,
+    pub proxy_port: Option<u16>,
+    pub selected_node: Option<String>,
+    pub proxy_count: usize,
+    /// 由 DIRECT 规则转换来的直连规则
+    pub bypass: Vec<String>,
+    /// 无法转换而被忽略的规则，原样保留用于提示
+    pub dropped_rules: Vec<String>,
+}
+
+impl ClashImport {
+    /// 将导入的设置合并到 clashfun 配置，未找到的项保持原值
+    pub fn apply_to(&self, config: &mut Config) {
+        if let Some(url) = &self.subscription_url {
+            config.subscription_url = Some(url.clone());
+        }
+        if let Some(port) = self.proxy_port {
+            config.proxy_port = port;
+        }
+        if let Some(node) = &self.selected_node {
+            config.selected_node = Some(node.clone());
+        }
+        for rule in &self.bypass {
+            if !config.bypass.contains(rule) {
+                config.bypass.push(rule.clone());
+            }
+        }
+    }
+}
+
+/// 按常见安装位置查找 Clash / Clash Verge 的配置
+pub fn locate_clash_config() -> Option<PathBuf> {
+    let bases: Vec<PathBuf> = [dirs::config_dir(), dirs::data_dir()].into_iter().flatten().collect();
+    locate_in(&bases, dirs::home_dir().as_deref())
+}
+
+/// 先找 Clash Verge 的订阅列表，再找 Clash / mihomo 的配置文件
+fn locate_in(bases: &[PathBuf], home: Option<&Path>) -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+
+    for base in bases {
+        candidates.push(base.join(CLASH_VERGE_ID).join("profiles.yaml"));
+        candidates.push(base.join(CLASH_VERGE_LEGACY_ID).join("profiles.yaml"));
+    }
+
+    if let Some(home) = home {
+        candidates.push(home.join(".config").join("clash.meta").join("config.yaml"));
+        candidates.push(home.join(".config").join("mihomo").join("config.yaml"));
+        candidates.push(home.join(".config").join("clash").join("config.yaml"));
+    }
+
+    candidates.into_iter().find(|path| path.exists())
+}
+
+/// 解析指定的 Clash 配置文件或目录
+pub fn import_from(path: &Path) -> Result<ClashImport> {
+    let path = if path.is_dir() {
+        let profiles = path.join("profiles.yaml");
+        if profiles.exists() {
+            profiles
+        } else {
+            path.join("config.yaml")
+        }
+    } else {
+        path.to_path_buf()
+    };
+
+    if path.file_name().and_then(|n| n.to_str()) == Some("profiles.yaml") {
+        import_verge_profiles(&path)
+    } else {
+        import_clash_config(&path)
+    }
+}
+
+fn read_yaml(path: &Path) -> Result<Value> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("无法读取 Clash 配置: {:?}", path))?;
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("无法解析 Clash 配置: {:?}", path))
+}
+
+/// 普通 Clash 配置：端口、内联节点和规则
+fn import_clash_config(path: &Path) -> Result<ClashImport> {
+    let yaml = read_yaml(path)?;
+
+    let proxy_count = yaml
+        .get("proxies")
+        .and_then(Value::as_sequence)
+        .map(|proxies| proxies.len())
+        .unwrap_or(0);
+
+    // 内联节点通过本地文件作为订阅源
+    let subscription_url = if proxy_count > 0 {
+        let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        Some(format!("file://{}", absolute.display()))
+    } else {
+        None
+    };
+
+    let (bypass, dropped_rules) = convert_rules(&yaml);
+    Ok(ClashImport {
+        source: path.to_path_buf(),
+        subscription_url,
+        proxy_port: read_port(&yaml),
+        selected_node: first_selected_proxy(&yaml),
+        proxy_count,
+        bypass,
+        dropped_rules,
+    })
+}
+
+/// Clash Verge 的 profiles.yaml：当前订阅及其选中的节点
+fn import_verge_profiles(path: &Path) -> Result<ClashImport> {
+    let profiles = read_yaml(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let current = profiles.get("current").and_then(Value::as_str);
+    let items = profiles
+        .get("items")
+        .and_then(Value::as_sequence)
+        .cloned()
+        .unwrap_or_default();
+
+    let item = items
+        .iter()
+        .find(|item| item.get("uid").and_then(Value::as_str) == current)
+        .or_else(|| items.iter().find(|item| item.get("url").is_some()))
+        .context("Clash Verge 中没有找到可用的订阅")?;
+
+    // 远程订阅直接使用原链接，本地订阅读取 profiles/ 下的文件
+    let profile_file = item
+        .get("file")
+        .and_then(Value::as_str)
+        .map(|file| dir.join("profiles").join(file));
+    let profile = profile_file.as_deref().and_then(|file| read_yaml(file).ok());
+
+    let subscription_url = match item.get("url").and_then(Value::as_str) {
+        Some(url) => Some(url.to_string()),
+        None => profile_file
+            .as_ref()
+            .filter(|file| file.exists())
+            .map(|file| format!("file://{}", file.display())),
+    };
+
+    // Verge 在订阅条目里记录每个策略组当前选择的节点
+    let selected_node = item
+        .get("selected")
+        .and_then(Value::as_sequence)
+        .and_then(|groups| groups.first())
+        .and_then(|group| group.get("now"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| profile.as_ref().and_then(first_selected_proxy));
+
+    // 端口配置在 Verge 生成的 config.yaml 中
+    let proxy_port = read_yaml(&dir.join("config.yaml"))
+        .ok()
+        .and_then(|config| read_port(&config));
+
+    let proxy_count = profile
+        .as_ref()
+        .and_then(|p| p.get("proxies"))
+        .and_then(Value::as_sequence)
+        .map(|proxies| proxies.len())
+        .unwrap_or(0);
+
+    let (bypass, dropped_rules) = profile.as_ref().map(convert_rules).unwrap_or_default();
+    Ok(ClashImport {
+        source: path.to_path_buf(),
+        subscription_url,
+        proxy_port,
+        selected_node,
+        proxy_count,
+        bypass,
+        dropped_rules,
+    })
+}
+
+fn read_port(yaml: &Value) -> Option<u16> {
+    ["mixed-port", "port", "socks-port"]
+        .iter()
+        .filter_map(|key| yaml.get(*key).and_then(Value::as_u64))
+        .find(|port| *port > 0 && *port <= u16::MAX as u64)
+        .map(|port| port as u16)
+}
+
+/// 把 Clash 规则转换为直连规则，返回 (直连规则, 无法转换的规则)
+///
+/// ClashFun 中没有命中直连规则的流量都经过节点，因此指向节点或策略组的规则和 MATCH 不需要转换；
+/// DIRECT 的域名、IP 段和端口规则转换为直连规则，其他类型（GEOIP、RULE-SET、进程名等）和 REJECT 规则被忽略
+fn convert_rules(yaml: &Value) -> (Vec<String>, Vec<String>) {
+    let mut bypass = Vec::new();
+    let mut dropped = Vec::new();
+    let rules = yaml.get("rules").and_then(Value::as_sequence).into_iter().flatten();
+    for rule in rules.filter_map(Value::as_str) {
+        let parts: Vec<&str> = rule.split(',').map(str::trim).collect();
+        match parts.as_slice() {
+            ["MATCH", ..] => {}
+            [kind, value, target, ..] if target.eq_ignore_ascii_case("DIRECT") => {
+                let converted = match kind.to_ascii_uppercase().as_str() {
+                    "DOMAIN" | "DOMAIN-SUFFIX" | "IP-CIDR" | "IP-CIDR6" => Some(value.to_string()),
+                    "DST-PORT" => Some(format!("port:{}", value)),
+                    _ => None,
+                };
+                match converted.filter(|rule| rule.parse::<BypassRule>().is_ok()) {
+                    Some(rule) if !bypass.contains(&rule) => bypass.push(rule),
+                    Some(_) => {}
+                    None => dropped.push(rule.to_string()),
+                }
+            }
+            [_, _, target, ..] if target.to_ascii_uppercase().starts_with("REJECT") => dropped.push(rule.to_string()),
+            [_, _, _, ..] => {}
+            _ => dropped.push(rule.to_string()),
+        }
+    }
+    (bypass, dropped)
+}
+
+/// 取第一个 select 策略组中的第一个实际节点
+fn first_selected_proxy(yaml: &Value) -> Option<String> {
+    let proxy_names: Vec<&str> = yaml
+        .get("proxies")
+        .and_then(Value::as_sequence)?
+        .iter()
+        .filter_map(|proxy| proxy.get("name").and_then(Value::as_str))
+        .collect();
+
+    yaml.get("proxy-groups")
+        .and_then(Value::as_sequence)?
+        .iter()
+        .filter(|group| group.get("type").and_then(Value::as_str) == Some("select"))
+        .filter_map(|group| group.get("proxies").and_then(Value::as_sequence))
+        .flatten()
+        .filter_map(Value::as_str)
+        .find(|name| proxy_names.contains(name))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个测试使用独立的临时目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clashfun-import-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn converts_direct_rules_and_reports_the_rest() {
+        let yaml: Value = serde_yaml::from_str(
+            r#"
+rules:
+  - DOMAIN-SUFFIX,bilibili.com,DIRECT
+  - DOMAIN,steamcontent.com,DIRECT
+  - IP-CIDR,192.168.0.0/16,DIRECT,no-resolve
+  - IP-CIDR6,fe80::/10,DIRECT
+  - DST-PORT,27000-27100,DIRECT
+  - GEOIP,CN,DIRECT
+  - DOMAIN-KEYWORD,ads,REJECT
+  - DOMAIN-SUFFIX,google.com,节点选择
+  - MATCH,节点选择
+"#,
+        )
+        .unwrap();
+        let (bypass, dropped) = convert_rules(&yaml);
+        assert_eq!(
+            bypass,
+            ["bilibili.com", "steamcontent.com", "192.168.0.0/16", "fe80::/10", "port:27000-27100"]
+        );
+        assert_eq!(dropped, ["GEOIP,CN,DIRECT", "DOMAIN-KEYWORD,ads,REJECT"]);
+
+        let imported = ClashImport { bypass, ..Default::default() };
+        let mut config = Config { bypass: vec!["bilibili.com".to_string()], ..Config::default() };
+        imported.apply_to(&mut config);
+        assert_eq!(config.bypass.len(), 5);
+    }
+
+    #[test]
+    fn imports_clash_config_and_verge_profiles() {
+        let clash = temp_dir("clash");
+        fs::write(
+            clash.join("config.yaml"),
+            r#"
+mixed-port: 7897
+proxies:
+  - {name: "香港 01", type: ss, server: hk.example.com, port: 8388, cipher: aes-128-gcm, password: secret}
+proxy-groups:
+  - {name: 节点选择, type: select, proxies: [DIRECT, "香港 01"]}
+rules:
+  - DOMAIN-SUFFIX,lan,DIRECT
+  - MATCH,节点选择
+"#,
+        )
+        .unwrap();
+        let imported = import_from(&clash).unwrap();
+        assert_eq!(imported.proxy_port, Some(7897));
+        assert_eq!(imported.selected_node.as_deref(), Some("香港 01"));
+        assert_eq!(imported.proxy_count, 1);
+        assert!(imported.subscription_url.unwrap().starts_with("file://"));
+        assert_eq!(imported.bypass, ["lan"]);
+
+        let verge = temp_dir("verge");
+        fs::create_dir_all(verge.join("profiles")).unwrap();
+        fs::write(
+            verge.join("profiles.yaml"),
+            r#"
+current: remote
+items:
+  - {uid: local, type: local, file: local.yaml}
+  - {uid: remote, type: remote, url: "https://sub.example.com/clash", file: remote.yaml, selected: [{name: 节点选择, now: 日本 01}]}
+"#,
+        )
+        .unwrap();
+        fs::write(verge.join("profiles").join("remote.yaml"), "rules:\n  - GEOIP,CN,DIRECT\n").unwrap();
+        fs::write(verge.join("config.yaml"), "port: 7890\n").unwrap();
+        let imported = import_from(&verge).unwrap();
+        assert_eq!(imported.subscription_url.as_deref(), Some("https://sub.example.com/clash"));
+        assert_eq!(imported.selected_node.as_deref(), Some("日本 01"));
+        assert_eq!(imported.proxy_port, Some(7890));
+        assert_eq!(imported.dropped_rules, ["GEOIP,CN,DIRECT"]);
+
+        // Clash Verge 优先于 Clash 的配置文件
+        let home = temp_dir("home");
+        let config_dir = home.join(".config").join("clash");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(config_dir.join("config.yaml"), "port: 7890\n").unwrap();
+        assert_eq!(locate_in(&[], Some(&home)), Some(config_dir.join("config.yaml")));
+        let bases = [verge.parent().unwrap().to_path_buf()];
+        fs::create_dir_all(bases[0].join(CLASH_VERGE_ID)).unwrap();
+        fs::copy(verge.join("profiles.yaml"), bases[0].join(CLASH_VERGE_ID).join("profiles.yaml")).unwrap();
+        assert_eq!(locate_in(&bases, Some(&home)), Some(bases[0].join(CLASH_VERGE_ID).join("profiles.yaml")));
+        assert_eq!(locate_in(&[], None), None);
+
+        let _ = fs::remove_dir_all(bases[0].join(CLASH_VERGE_ID));
+        for dir in [clash, verge, home] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+
+use crate::bypass::{BypassRule, Destination};
+use crate::config::Config;
+use crate::game_detect::{PortProtocol, SupportedGame};
+
+const BUILTIN_SIGNATURES: &str = include_str!("../data/game_signatures.yaml");
+const USER_SIGNATURES_FILE: &str = "signatures.yaml";
+
+/// 置信度达到该值才判定为游戏流量
+pub const MIN_CONFIDENCE: u8 = 50;
+/// 发出流量的进程属于该游戏
+const OWNER_WEIGHT: u8 = 60;
+/// 目标在游戏服务器 IP 段内
+const RANGE_WEIGHT: u8 = 40;
+/// 端口是游戏常用端口，其他程序也可能使用，单独命中不足以判定
+const PORT_WEIGHT: u8 = 25;
+const DEFAULT_PAYLOAD_WEIGHT: u8 = 30;
+
+/// 识别依据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Owner,
+    Range,
+    Port,
+    Payload,
+}
+
+impl Signal {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Owner => "进程",
+            Self::Range => "IP 段",
+            Self::Port => "端口",
+            Self::Payload => "数据特征",
+        }
+    }
+}
+
+/// 待识别的一条流量
+#[derive(Debug, Clone, Copy)]
+pub struct Flow<'a> {
+    pub client: SocketAddr,
+    /// 原始目标，UDP 和未重定向的 TCP 不知道
+    pub destination: Option<SocketAddr>,
+    /// 发出流量的进程名，只有本机流量才能查到
+    pub owner: Option<&'a str>,
+    /// 第一个数据包，TCP 为空
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub game: SupportedGame,
+    /// 0-100
+    pub confidence: u8,
+    pub signals: Vec<Signal>,
+}
+
+impl Classification {
+    pub fn describe(&self) -> String {
+        let signals: Vec<&str> = self.signals.iter().map(Signal::display_name).collect();
+        format!("{} (置信度 {}%，依据: {})", self.game.display_name(), self.confidence, signals.join("、"))
+    }
+}
+
+/// 数据文件中的一条游戏特征
+#[derive(Debug, Deserialize)]
+struct SignatureEntry {
+    game: SupportedGame,
+    #[serde(default)]
+    ranges: Vec<String>,
+    #[serde(default)]
+    ports: Vec<u16>,
+    #[serde(default)]
+    payloads: Vec<PayloadEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PayloadEntry {
+    name: String,
+    #[serde(default)]
+    hex: Option<String>,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    offset: Option<usize>,
+    #[serde(default)]
+    weight: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct PayloadSignature {
+    name: String,
+    bytes: Vec<u8>,
+    offset: Option<usize>,
+    weight: u8,
+}
+
+impl PayloadSignature {
+    fn matches(&self, data: &[u8]) -> bool {
+        match self.offset {
+            Some(offset) => data.get(offset..).is_some_and(|rest| rest.starts_with(&self.bytes)),
+            None => data.windows(self.bytes.len()).any(|w| w == self.bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct GameSignatures {
+    game: SupportedGame,
+    ranges: Vec<BypassRule>,
+    ports: Vec<u16>,
+    payloads: Vec<PayloadSignature>,
+}
+
+/// 各游戏的识别特征
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    games: Vec<GameSignatures>,
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.len().is_multiple_of(2) {
+        bail!("十六进制长度必须为偶数");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("无效的十六进制: {}", hex)))
+        .collect()
+}
+
+impl Signatures {
+    /// 解析 YAML 格式的特征文件
+    pub fn parse(content: &str) -> Result<Self> {
+        let entries: Vec<SignatureEntry> = serde_yaml::from_str(content).context("特征文件格式错误")?;
+        let mut signatures = Self::default();
+        for entry in entries {
+            let ranges = entry
+                .ranges
+                .iter()
+                .map(|range| match range.parse::<BypassRule>() {
+                    Ok(rule @ BypassRule::Cidr { .. }) => Ok(rule),
+                    _ => Err(anyhow!("{}: 无效的 IP 段 {}", entry.game.display_name(), range)),
+                })
+                .collect::<Result<_>>()?;
+            let payloads = entry
+                .payloads
+                .into_iter()
+                .map(|payload| {
+                    let bytes = match (&payload.hex, &payload.text) {
+                        (Some(hex), None) => parse_hex(hex)?,
+                        (None, Some(text)) => text.as_bytes().to_vec(),
+                        _ => bail!("{}: 特征 {} 需要 hex 或 text 之一", entry.game.display_name(), payload.name),
+                    };
+                    if bytes.is_empty() {
+                        bail!("{}: 特征 {} 为空", entry.game.display_name(), payload.name);
+                    }
+                    Ok(PayloadSignature {
+                        name: payload.name,
+                        bytes,
+                        offset: payload.offset,
+                        weight: payload.weight.unwrap_or(DEFAULT_PAYLOAD_WEIGHT).min(100),
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            signatures.games.push(GameSignatures {
+                game: entry.game,
+                ranges,
+                ports: entry.ports,
+                payloads,
+            });
+        }
+        Ok(signatures)
+    }
+
+    /// 内置特征加上配置目录中用户追加的特征
+    pub fn load() -> Self {
+        let mut signatures = Self::parse(BUILTIN_SIGNATURES).expect("内置特征文件无效");
+        let Ok(path) = Config::config_dir().map(|dir| dir.join(USER_SIGNATURES_FILE)) else {
+            return signatures;
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => match Self::parse(&content) {
+                Ok(user) => signatures.games.extend(user.games),
+                Err(e) => warn!("忽略无效的特征文件 {:?}: {:#}", path, e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("无法读取特征文件 {:?}: {}", path, e),
+        }
+        signatures
+    }
+
+    fn entries<'a>(&'a self, game: &'a SupportedGame) -> impl Iterator<Item = &'a GameSignatures> + 'a {
+        self.games.iter().filter(move |entry| entry.game == *game)
+    }
+
+    /// 数据包是否命中该游戏的任一特征
+    pub fn payload_matches(&self, game: &SupportedGame, data: &[u8]) -> bool {
+        self.entries(game)
+            .flat_map(|entry| &entry.payloads)
+            .any(|signature| signature.matches(data))
+    }
+
+    /// 该游戏的得分和命中的依据
+    fn score(&self, game: &SupportedGame, flow: &Flow) -> Classification {
+        let mut signals = Vec::new();
+        let mut confidence: u32 = 0;
+
+        if flow.owner.is_some_and(|owner| game.matches_process(owner)) {
+            signals.push(Signal::Owner);
+            confidence += OWNER_WEIGHT as u32;
+        }
+
+        let destination = Destination {
+            host: None,
+            addr: flow.destination,
+        };
+        if self
+            .entries(game)
+            .flat_map(|entry| &entry.ranges)
+            .any(|range| range.matches(&destination))
+        {
+            signals.push(Signal::Range);
+            confidence += RANGE_WEIGHT as u32;
+        }
+
+        let port_matches = |port: u16| {
+            game.get_game_ports().contains(&port) || self.entries(game).any(|entry| entry.ports.contains(&port))
+        };
+        if port_matches(flow.client.port()) || flow.destination.is_some_and(|addr| port_matches(addr.port())) {
+            signals.push(Signal::Port);
+            confidence += PORT_WEIGHT as u32;
+        }
+
+        // 同一游戏的多个特征只取权重最高的一个
+        let payload = self
+            .entries(game)
+            .flat_map(|entry| &entry.payloads)
+            .filter(|signature| signature.matches(flow.payload))
+            .max_by_key(|signature| signature.weight);
+        if let Some(signature) = payload {
+            debug!("{} 命中特征: {}", game.display_name(), signature.name);
+            signals.push(Signal::Payload);
+            confidence += signature.weight as u32;
+        }
+
+        Classification {
+            game: game.clone(),
+            confidence: confidence.min(100) as u8,
+            signals,
+        }
+    }
+
+    /// 按得分从高到低返回有命中的游戏
+    pub fn rank(&self, flow: &Flow) -> Vec<Classification> {
+        let mut ranked: Vec<Classification> = SupportedGame::value_variants()
+            .iter()
+            .map(|game| self.score(game, flow))
+            .filter(|classification| classification.confidence > 0)
+            .collect();
+        ranked.sort_by_key(|classification| std::cmp::Reverse(classification.confidence));
+        ranked
+    }
+}
+
+/// 识别质量的累计统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClassifierStats {
+    /// 参与识别的流量数
+    pub flows: u64,
+    pub identified: u64,
+    /// 有命中但置信度不足
+    pub low_confidence: u64,
+    /// 多个游戏同时达到阈值
+    pub ambiguous: u64,
+    pub owner_hits: u64,
+    pub range_hits: u64,
+    pub port_hits: u64,
+    pub payload_hits: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    flows: AtomicU64,
+    identified: AtomicU64,
+    low_confidence: AtomicU64,
+    ambiguous: AtomicU64,
+    owner_hits: AtomicU64,
+    range_hits: AtomicU64,
+    port_hits: AtomicU64,
+    payload_hits: AtomicU64,
+}
+
+static SIGNATURES: LazyLock<RwLock<Signatures>> = LazyLock::new(|| RwLock::new(Signatures::load()));
+static COUNTERS: LazyLock<Counters> = LazyLock::new(Counters::default);
+
+/// 设置生效的特征
+pub fn set_signatures(signatures: Signatures) {
+    *SIGNATURES.write().unwrap_or_else(|e| e.into_inner()) = signatures;
+}
+
+/// 数据包是否命中该游戏的特征，用于抓包时标注
+pub fn payload_matches(game: &SupportedGame, data: &[u8]) -> bool {
+    SIGNATURES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .payload_matches(game, data)
+}
+
+/// 游戏使用的端口：内置端口加上特征文件中追加的端口
+pub fn game_ports(game: &SupportedGame) -> Vec<u16> {
+    let signatures = SIGNATURES.read().unwrap_or_else(|e| e.into_inner());
+    let mut ports = game.get_game_ports();
+    ports.extend(signatures.entries(game).flat_map(|entry| entry.ports.iter().copied()));
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// 游戏服务器所在的 IPv4 段，来自特征文件
+pub fn game_ranges(game: &SupportedGame) -> Vec<String> {
+    let signatures = SIGNATURES.read().unwrap_or_else(|e| e.into_inner());
+    let mut ranges: Vec<String> = signatures
+        .entries(game)
+        .flat_map(|entry| &entry.ranges)
+        .filter(|range| matches!(range, BypassRule::Cidr { network: IpAddr::V4(_), .. }))
+        .map(BypassRule::to_string)
+        .collect();
+    ranges.sort();
+    ranges.dedup();
+    ranges
+}
+
+/// 识别一条新流量属于哪个游戏，置信度不足时返回 None
+pub fn classify(protocol: PortProtocol, client: SocketAddr, destination: Option<SocketAddr>, payload: &[u8]) -> Option<Classification> {
+    let owner = socket_owner(protocol, client);
+    let flow = Flow {
+        client,
+        destination,
+        owner: owner.as_deref(),
+        payload,
+    };
+    let ranked = SIGNATURES.read().unwrap_or_else(|e| e.into_inner()).rank(&flow);
+
+    let counters = &*COUNTERS;
+    counters.flows.fetch_add(1, Ordering::Relaxed);
+    let mut ranked = ranked.into_iter();
+    let best = ranked.next()?;
+    if best.confidence < MIN_CONFIDENCE {
+        counters.low_confidence.fetch_add(1, Ordering::Relaxed);
+        debug!("{} {} 疑似 {}，置信度不足", protocol.as_str(), client, best.describe());
+        return None;
+    }
+    if ranked.next().is_some_and(|second| second.confidence >= MIN_CONFIDENCE) {
+        counters.ambiguous.fetch_add(1, Ordering::Relaxed);
+    }
+
+    counters.identified.fetch_add(1, Ordering::Relaxed);
+    for signal in &best.signals {
+        let counter = match signal {
+            Signal::Owner => &counters.owner_hits,
+            Signal::Range => &counters.range_hits,
+            Signal::Port => &counters.port_hits,
+            Signal::Payload => &counters.payload_hits,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    Some(best)
+}
+
+pub fn stats() -> ClassifierStats {
+    let counters = &*COUNTERS;
+    ClassifierStats {
+        flows: counters.flows.load(Ordering::Relaxed),
+        identified: counters.identified.load(Ordering::Relaxed),
+        low_confidence: counters.low_confidence.load(Ordering::Relaxed),
+        ambiguous: counters.ambiguous.load(Ordering::Relaxed),
+        owner_hits: counters.owner_hits.load(Ordering::Relaxed),
+        range_hits: counters.range_hits.load(Ordering::Relaxed),
+        port_hits: counters.port_hits.load(Ordering::Relaxed),
+        payload_hits: counters.payload_hits.load(Ordering::Relaxed),
+    }
+}
+
+/// 查找本机发出该流量的进程名
+#[cfg(target_os = "linux")]
+fn socket_owner(protocol: PortProtocol, local: SocketAddr) -> Option<String> {
+    // 局域网设备的流量无法查到进程
+    if !local.ip().is_loopback() {
+        return None;
+    }
+    crate::conflict::proc_owner(protocol, local.port()).map(|owner| owner.name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_owner(_protocol: PortProtocol, _local: SocketAddr) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow<'a>(client_port: u16, destination: Option<&str>, owner: Option<&'a str>, payload: &'a [u8]) -> Flow<'a> {
+        Flow {
+            client: SocketAddr::from(([192, 168, 1, 20], client_port)),
+            destination: destination.map(|addr| addr.parse().unwrap()),
+            owner,
+            payload,
+        }
+    }
+
+    #[test]
+    fn builtin_signatures_parse() {
+        let signatures = Signatures::parse(BUILTIN_SIGNATURES).unwrap();
+        assert!(signatures.entries(&SupportedGame::Dota2).any(|entry| !entry.ranges.is_empty()));
+    }
+
+    #[test]
+    fn combines_signals_into_confidence() {
+        let signatures = Signatures::parse(BUILTIN_SIGNATURES).unwrap();
+
+        // 旧规则把任何以 00 00 开头的包都当作我的世界
+        let ranked = signatures.rank(&flow(40000, None, None, &[0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a]));
+        assert!(ranked.is_empty());
+
+        // 只有端口命中时置信度不足
+        let ranked = signatures.rank(&flow(25565, None, None, b"hello"));
+        assert_eq!(ranked[0].game, SupportedGame::Minecraft);
+        assert!(ranked[0].confidence < MIN_CONFIDENCE);
+
+        // A2S 查询发往 Valve 的服务器
+        let query = b"\xff\xff\xff\xffTSource Engine Query\x00";
+        let ranked = signatures.rank(&flow(40000, Some("155.133.230.10:27015"), None, query));
+        assert_eq!(ranked[0].confidence, 100);
+        assert_eq!(ranked[0].signals, vec![Signal::Range, Signal::Port, Signal::Payload]);
+        assert_eq!(ranked[1].confidence, 100);
+
+        // 进程归属可以区分共用引擎的游戏
+        let ranked = signatures.rank(&flow(40000, None, Some("cs2"), b"\xff\xff\xff\xff"));
+        assert_eq!(ranked[0].game, SupportedGame::CounterStrike);
+        assert_eq!(ranked[0].confidence, 80);
+        assert!(ranked[1].confidence < MIN_CONFIDENCE);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finds_owner_of_local_socket() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let owner = socket_owner(PortProtocol::Udp, socket.local_addr().unwrap());
+        let comm = fs::read_to_string("/proc/self/comm").unwrap();
+        assert_eq!(owner.as_deref(), Some(comm.trim()));
+    }
+
+    #[test]
+    fn rejects_invalid_signature_files() {
+        assert!(Signatures::parse("- game: dst\n  ranges: [example.com]\n").is_err());
+        assert!(Signatures::parse("- game: dst\n  payloads:\n    - name: x\n      hex: abc\n").is_err());
+        assert!(Signatures::parse("- game: dst\n  payloads:\n    - name: x\n").is_err());
+
+        let user = Signatures::parse("- game: dst\n  ports: [10888]\n  payloads:\n    - name: x\n      text: DST\n      offset: 2\n      weight: 40\n").unwrap();
+        assert!(user.payload_matches(&SupportedGame::DontStarveTogether, b"..DST"));
+        assert!(!user.payload_matches(&SupportedGame::DontStarveTogether, b"DST"));
+        assert_eq!(user.rank(&flow(10888, None, None, b""))[0].signals, vec![Signal::Port]);
+    }
+}
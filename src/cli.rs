@@ -1,55 +1,426 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::auto_select::SelectPolicy;
+use crate::bypass::BypassPreset;
+use crate::game_detect::SupportedGame;
+use crate::lan::Console;
+use crate::redirect::RedirectMode;
+use crate::region::Grouping;
+use crate::simulate::NetworkConditions;
+use crate::updater::UpdateChannel;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(name = "cf")]
 #[command(about = "轻量级游戏加速器")]
 pub struct Cli {
+    #[arg(long, global = true, help = "便携模式：配置和缓存保存在程序所在目录")]
+    pub portable: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "使用独立的配置、端口和控制通道，可以同时运行多个实例，例如 --profile dst"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, help = "输出更多日志，可重复使用 (-vv)，优先于 RUST_LOG")]
+    pub verbose: u8,
+
+    #[arg(short, long, global = true, conflicts_with = "verbose", help = "只输出错误，不显示测试进度，适合脚本调用")]
+    pub quiet: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    #[command(about = "启动加速服务")]
-    Start,
+    #[command(about = "启动加速服务", visible_alias = "s")]
+    Start {
+        #[arg(
+            long,
+            value_name = "SPEC",
+            help = "开发者模式：模拟较差的网络，例如 \"latency=80ms jitter=20ms loss=1%\""
+        )]
+        simulate: Option<NetworkConditions>,
+
+        #[arg(long, value_enum, value_name = "MODE", help = "把检测到的游戏流量透明重定向到代理端口，覆盖配置中的 redirect")]
+        redirect: Option<RedirectMode>,
+    },
 
     #[command(about = "停止加速服务")]
     Stop,
 
+    #[command(about = "只输出一个值，供 shell 提示符、tmux、polybar 等状态栏使用，取不到时不输出并以 1 退出")]
+    Get {
+        #[arg(value_enum)]
+        key: GetKey,
+    },
+
     #[command(about = "查看服务状态")]
-    Status,
+    Status {
+        #[arg(long, short, help = "每秒刷新节点、延迟、速率和游戏，按 Ctrl+C 退出")]
+        watch: bool,
+    },
 
-    #[command(about = "列出所有节点")]
-    Nodes,
+    #[command(about = "列出所有节点", visible_alias = "n")]
+    Nodes {
+        #[arg(long, value_enum, default_value_t = Grouping::Region, help = "分组方式")]
+        group_by: Grouping,
+
+        #[arg(long, help = "不显示协议暂不支持的节点")]
+        hide_unsupported: bool,
+    },
 
     #[command(about = "设置订阅链接")]
     SetSubscription {
-        #[arg(help = "订阅链接 URL")]
-        url: String,
+        #[arg(required_unless_present = "from_clipboard", help = "订阅链接 URL")]
+        url: Option<String>,
+
+        #[arg(long, conflicts_with = "url", help = "从系统剪贴板读取订阅链接，避免在终端里粘贴出错")]
+        from_clipboard: bool,
+
+        #[arg(long, help = "只获取并解析订阅，显示节点统计，不保存")]
+        check: bool,
     },
 
     #[command(about = "切换到指定节点")]
     SelectNode {
-        #[arg(help = "节点名称")]
-        name: String,
+        #[arg(help = "节点名称（包含即可），省略时在列表中筛选选择")]
+        name: Option<String>,
+
+        #[arg(long, conflicts_with = "name", help = "按 'cf nodes' 显示的序号选择")]
+        index: Option<usize>,
+
+        #[arg(long, requires = "name", help = "只接受名称完全相同的节点（忽略大小写）")]
+        exact: bool,
+    },
+
+    #[command(about = "按上次 'cf nodes' 显示的序号切换节点")]
+    Use {
+        #[arg(help = "节点序号")]
+        index: usize,
     },
 
     #[command(about = "自动选择最优节点")]
-    AutoSelect,
+    AutoSelect {
+        #[arg(long, value_enum, help = "选择策略，默认使用配置中的 auto_select.policy")]
+        policy: Option<SelectPolicy>,
+
+        #[arg(long, help = "只在指定地区中选择，例如 香港、日本、sg")]
+        region: Option<String>,
+
+        #[arg(long, value_name = "N", help = "random 策略的候选数量")]
+        top: Option<usize>,
+    },
 
     #[command(about = "更新到最新版本")]
-    Update,
+    Update {
+        #[arg(long, value_enum, help = "切换更新通道 (stable/beta)")]
+        channel: Option<UpdateChannel>,
+
+        #[arg(long, value_name = "VERSION", help = "安装指定版本，例如 v0.4.2")]
+        to: Option<String>,
+
+        #[arg(long, help = "只检查是否有新版本，不安装")]
+        check: bool,
+    },
 
     #[command(about = "卸载程序")]
-    Uninstall,
+    Uninstall {
+        #[arg(long, help = "同时删除所有配置和缓存，不再确认")]
+        purge: bool,
+        #[arg(short, long, help = "删除程序前不再确认")]
+        yes: bool,
+    },
 
     #[command(about = "检测运行中的游戏")]
     DetectGame,
 
-    #[command(about = "一键卸载程序和配置")]
+    #[command(about = "一键卸载程序和配置 (等同于 uninstall --purge)", hide = true)]
     ForceUninstall,
 
     #[command(about = "清除所有节点配置恢复原始状态")]
     Reset,
+
+    #[command(about = "从现有的 Clash / Clash Verge 导入设置")]
+    ImportClash {
+        #[arg(help = "Clash 配置文件或目录，默认自动查找")]
+        path: Option<PathBuf>,
+    },
+
+    #[command(about = "按当前的订阅、节点设置和直连规则生成等效的 Clash 配置，例如 cf generate-clash > config.yaml")]
+    GenerateClash {
+        #[arg(long, help = "写入指定文件，默认输出到标准输出")]
+        out: Option<PathBuf>,
+    },
+
+    #[command(about = "在路由器上为本机开服映射端口 (UPnP / NAT-PMP)")]
+    PortMap {
+        #[arg(value_enum, help = "游戏，例如 dst、minecraft、cs")]
+        game: Option<SupportedGame>,
+
+        #[arg(long = "port", value_name = "PORT[/tcp|/udp]", help = "额外映射的端口，例如 25565 或 10999/udp")]
+        ports: Vec<String>,
+
+        #[arg(long, help = "删除映射")]
+        remove: bool,
+
+        #[arg(long, default_value_t = 120, help = "映射有效期（分钟），到期后需重新运行")]
+        lease_minutes: u64,
+    },
+
+    #[command(about = "检测 NAT 类型，判断 P2P 游戏能否直连")]
+    Nat {
+        #[arg(long = "server", value_name = "HOST:PORT", help = "使用指定的 STUN 服务器，可重复")]
+        servers: Vec<String>,
+    },
+
+    #[command(about = "抓取经过加速的数据包，保存为 pcapng 供 Wireshark 分析")]
+    Capture {
+        #[arg(long, value_enum, help = "标注数据包是否匹配该游戏的特征")]
+        game: Option<SupportedGame>,
+
+        #[arg(long, help = "输出文件，默认 clashfun-<时间>.pcapng")]
+        out: Option<PathBuf>,
+
+        #[arg(long, value_name = "BYTES", help = "每个包最多保存的负载字节数，避免记录敏感内容")]
+        max_payload: Option<usize>,
+
+        #[arg(long, default_value_t = 60, help = "抓包时长（秒），也可以按 Ctrl+C 提前结束")]
+        duration: u64,
+    },
+
+    #[command(about = "按地区和时段统计节点延迟，帮助选择晚高峰的地区")]
+    Report {
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=10), help = "每个节点测试的轮数")]
+        rounds: u32,
+        #[arg(long, value_name = "FILE", help = "同时生成可以分享的 HTML 报告，包含延迟、流量和本次运行的切换记录")]
+        html: Option<PathBuf>,
+    },
+
+    #[command(about = "按天统计经过加速的流量，查看游戏占用了多少套餐流量")]
+    Stats {
+        #[arg(long, default_value = "7d", value_name = "PERIOD", value_parser = crate::usage::parse_period, help = "统计最近多长时间，例如 7d、2w")]
+        history: u32,
+    },
+
+    #[command(about = "查看本机记录的功能使用次数和节点切换次数（需开启 insights，从不上传）")]
+    Insights {
+        #[arg(long, help = "清空已记录的统计")]
+        clear: bool,
+    },
+
+    #[command(about = "查看加速服务记录的重要事件：切换节点、健康检查失败、识别到游戏、会话开始结束、刷新订阅")]
+    Events {
+        #[arg(long, default_value = "1h", value_name = "PERIOD", value_parser = crate::events::parse_since, help = "查看最近多长时间的事件，例如 30m、1h、2d")]
+        since: std::time::Duration,
+    },
+
+    #[command(about = "查看加速服务的内存、CPU、任务数、各表大小和锁争用，排查低配设备上的性能问题")]
+    Perf {
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=60), value_name = "SECS", help = "两次采样的间隔，用于计算 CPU 占用和分配速率")]
+        interval: u64,
+    },
+
+    #[command(about = "管理使用加速的局域网设备")]
+    Device {
+        #[command(subcommand)]
+        action: DeviceAction,
+    },
+
+    #[command(about = "管理直连列表（不经过加速节点的域名、IP 段和端口）")]
+    Bypass {
+        #[command(subcommand)]
+        action: BypassAction,
+    },
+
+    #[command(about = "设置登录后自动启动加速服务")]
+    Autostart {
+        #[command(subcommand)]
+        action: AutostartAction,
+    },
+
+    #[command(about = "查看节点的健康状况")]
+    Node {
+        #[command(subcommand)]
+        action: NodeAction,
+    },
+
+    #[command(about = "查看订阅的获取情况，判断问题是否出在订阅提供商")]
+    Subscription {
+        #[command(subcommand)]
+        action: SubscriptionAction,
+    },
+}
+
+impl Commands {
+    /// 记入使用统计的命令名
+    pub fn feature_name(&self) -> &'static str {
+        match self {
+            Self::Start { .. } => "cf start",
+            Self::Stop => "cf stop",
+            Self::Get { .. } => "cf get",
+            Self::Status { .. } => "cf status",
+            Self::Nodes { .. } => "cf nodes",
+            Self::SetSubscription { .. } => "cf set-subscription",
+            Self::SelectNode { .. } => "cf select-node",
+            Self::Use { .. } => "cf use",
+            Self::AutoSelect { .. } => "cf auto-select",
+            Self::Update { .. } => "cf update",
+            Self::Uninstall { .. } => "cf uninstall",
+            Self::DetectGame => "cf detect-game",
+            Self::ForceUninstall => "cf force-uninstall",
+            Self::Reset => "cf reset",
+            Self::ImportClash { .. } => "cf import-clash",
+            Self::GenerateClash { .. } => "cf generate-clash",
+            Self::PortMap { .. } => "cf port-map",
+            Self::Nat { .. } => "cf nat",
+            Self::Capture { .. } => "cf capture",
+            Self::Report { .. } => "cf report",
+            Self::Stats { .. } => "cf stats",
+            Self::Insights { .. } => "cf insights",
+            Self::Events { .. } => "cf events",
+            Self::Perf { .. } => "cf perf",
+            Self::Device { .. } => "cf device",
+            Self::Bypass { .. } => "cf bypass",
+            Self::Autostart { .. } => "cf autostart",
+            Self::Node { .. } => "cf node",
+            Self::Subscription { .. } => "cf subscription",
+        }
+    }
+}
+
+/// cf get 可以读取的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GetKey {
+    /// 正在使用的节点名称，服务未运行时为配置中选中的节点
+    CurrentNode,
+    /// 当前节点最近一次健康检查的延迟（毫秒，只有数字）
+    Latency,
+    /// 代理端口
+    Port,
+}
+
+#[derive(Subcommand)]
+pub enum AutostartAction {
+    #[command(about = "登录后自动运行 cf start（Linux 使用 systemd 用户服务，macOS 使用 LaunchAgent，Windows 使用注册表 Run 项）")]
+    Enable,
+
+    #[command(about = "取消自启动并停止由它启动的服务")]
+    Disable,
+
+    #[command(about = "查看是否已设置自启动")]
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum NodeAction {
+    #[command(about = "显示各节点的连续失败次数、最近检查结果、切换记录和备用节点")]
+    Health,
+
+    #[command(about = "按故障切换时的尝试顺序显示备用节点及其最近测得的延迟")]
+    Backups,
+
+    #[command(about = "显示节点的地址、协议、稳定 ID、合并的重复节点和 node_overrides 中的设置")]
+    Info {
+        #[arg(help = "节点名称，完全相同优先，否则按包含匹配")]
+        name: String,
+
+        #[arg(long, help = "只接受名称完全相同的节点")]
+        exact: bool,
+    },
+
+    #[command(about = "通过节点的 UDP 转发发送探测包，检查节点是否真正支持 UDP（能连上但游戏超时多半是缺少 UDP）", name = "test-udp")]
+    TestUdp {
+        #[arg(help = "节点名称，完全相同优先，否则按包含匹配")]
+        name: String,
+
+        #[arg(long, help = "只接受名称完全相同的节点")]
+        exact: bool,
+    },
+
+    #[command(about = "分别测量节点的 DNS 解析、TCP 连接、TLS 握手耗时，判断慢在网络还是节点服务端")]
+    Timing {
+        #[arg(help = "节点名称，完全相同优先，否则按包含匹配")]
+        name: String,
+
+        #[arg(long, help = "只接受名称完全相同的节点")]
+        exact: bool,
+    },
+
+    #[command(about = "并发测试所有节点的延迟，再对延迟最低的几个节点测速，按延迟和吞吐量综合排名", name = "speed-rank")]
+    SpeedRank {
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..=20), help = "测速的节点数量")]
+        top: u64,
+
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..=30), help = "每个节点的测速时长（秒）")]
+        duration: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SubscriptionAction {
+    #[command(about = "显示各订阅的获取成功率、最近错误、节点数量变化和平均可用节点比例")]
+    Status,
+
+    #[command(about = "预览配置中的改名规则对当前订阅节点名称的效果")]
+    Rename,
+}
+
+#[derive(Subcommand)]
+pub enum DeviceAction {
+    #[command(about = "列出经过加速的设备及流量")]
+    List,
+
+    #[command(about = "禁止设备使用加速")]
+    Block {
+        #[arg(help = "设备的 IP 或 MAC 地址")]
+        device: String,
+    },
+
+    #[command(about = "重新允许设备使用加速")]
+    Allow {
+        #[arg(help = "设备的 IP 或 MAC 地址")]
+        device: String,
+    },
+
+    #[command(about = "设备只有游戏端口经过加速，系统更新等直连（需要透明重定向）")]
+    Ports {
+        #[arg(help = "设备的 IP 或 MAC 地址")]
+        device: String,
+
+        #[arg(long, value_enum, help = "使用游戏机的联机端口 (switch/playstation/xbox)")]
+        console: Option<Console>,
+
+        #[arg(long = "port", value_name = "PORT[-PORT]", help = "额外的游戏端口或端口范围，可重复")]
+        ports: Vec<String>,
+
+        #[arg(long, help = "删除该设备的设置，全部流量重新经过加速")]
+        clear: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BypassAction {
+    #[command(about = "添加直连规则")]
+    Add {
+        #[arg(help = "域名、IP/CIDR 或 port:端口，例如 example.com 192.168.0.0/16 port:27000-27100")]
+        rules: Vec<String>,
+
+        #[arg(long, value_enum, help = "添加预设规则 (lan/streaming)")]
+        preset: Option<BypassPreset>,
+    },
+
+    #[command(about = "删除直连规则")]
+    Remove {
+        #[arg(required = true, help = "要删除的规则")]
+        rules: Vec<String>,
+    },
+
+    #[command(about = "列出直连规则")]
+    List,
 }
\ No newline at end of file
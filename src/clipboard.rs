@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Result};
+
+/// 检查粘贴的文本是否是订阅链接，返回去掉首尾空白的链接
+#[cfg_attr(not(feature = "clipboard"), allow(dead_code))]
+pub fn validate_url(text: &str) -> Result<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(anyhow!("剪贴板是空的"));
+    }
+    if text.contains(char::is_whitespace) {
+        return Err(anyhow!("剪贴板里不止一个链接，请只复制订阅链接"));
+    }
+    let url = reqwest::Url::parse(text).map_err(|_| anyhow!("剪贴板里的内容不是链接"))?;
+    match url.scheme() {
+        "http" | "https" if url.host_str().is_some() => Ok(text.to_string()),
+        "http" | "https" => Err(anyhow!("订阅链接缺少服务器地址")),
+        scheme => Err(anyhow!("不支持 {}:// 链接，订阅链接应以 http:// 或 https:// 开头", scheme)),
+    }
+}
+
+/// 从系统剪贴板读取订阅链接
+#[cfg(feature = "clipboard")]
+pub fn read_subscription_url() -> Result<String> {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| anyhow!("无法读取剪贴板: {}", e))?;
+    validate_url(&text)
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn read_subscription_url() -> Result<String> {
+    Err(anyhow!("当前版本没有剪贴板支持，请直接在命令中填写订阅链接"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_single_http_links() {
+        assert_eq!(
+            validate_url("  https://sub.example.com/api/v1/client?token=abc\n").unwrap(),
+            "https://sub.example.com/api/v1/client?token=abc"
+        );
+        assert!(validate_url("").is_err());
+        assert!(validate_url("https://a.example.com https://b.example.com").is_err());
+        assert!(validate_url("订阅链接").is_err());
+        assert!(validate_url("ftp://sub.example.com/clash").is_err());
+        assert!(validate_url("vmess://eyJhZGQiOiIxLjIuMy40In0=").is_err());
+    }
+}
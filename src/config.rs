@@ -1,14 +1,149 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clashfun::error::ConfigError;
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::alarm::LatencyAlarmConfig;
+use crate::conflict;
+use crate::auto_select::{self, AutoSelectConfig};
+use crate::dns::{ResolveStrategy, ResolverKind};
+use crate::dscp::DscpConfig;
+use crate::game_detect::SupportedGame;
+use crate::lan::{self, LanDeviceConfig};
+use crate::mtu::OversizePolicy;
+use crate::network::NetworkConfig;
+use crate::node_overrides::{self, NodeOverride};
+use crate::obfs::ObfsConfig;
+use crate::performance::{DoNotDisturbConfig, PerformanceConfig};
+use crate::preflight::PreflightMode;
+use crate::profile::Profile;
+use crate::redirect::RedirectMode;
+use crate::rename::{self, RenameConfig};
+use crate::subscription::Node;
+use crate::timeouts::Timeouts;
+use crate::udp_keepalive::UdpKeepaliveConfig;
+use crate::updater::UpdateChannel;
+
+/// 程序目录下存在该文件时自动启用便携模式
+const PORTABLE_FLAG_FILE: &str = "portable.flag";
+
+/// 命名实例的配置和缓存所在的子目录
+const INSTANCES_DIR: &str = "profiles";
+
+static PORTABLE: AtomicBool = AtomicBool::new(false);
+/// cf --profile 指定的实例名称
+static INSTANCE: OnceLock<String> = OnceLock::new();
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub subscription_url: Option<String>,
     pub selected_node: Option<String>,
+    /// 选中节点的稳定 ID，订阅中节点改名后据此找回
+    pub selected_node_id: Option<String>,
     pub proxy_port: u16,
-    pub auto_select: bool,
+    /// 自动选择节点的策略，兼容旧版的 true/false
+    #[serde(deserialize_with = "auto_select::deserialize_compat")]
+    pub auto_select: AutoSelectConfig,
+    /// 日志级别 (error/warn/info/debug/trace)，设置了 RUST_LOG 时以环境变量为准
+    pub log_level: Option<String>,
+    pub update_channel: UpdateChannel,
+    /// 检查和下载更新时使用的镜像前缀，例如 https://ghproxy.com
+    pub update_mirror: Option<String>,
+    /// 下载更新时使用的 HTTP 代理
+    pub update_proxy: Option<String>,
+    /// 关闭后台自动检查更新
+    pub disable_update_check: bool,
+    /// 后台检查更新的间隔（小时）
+    pub update_check_interval_hours: u64,
+    /// 节点地址解析方式 (system/doh)
+    pub dns_resolver: ResolverKind,
+    /// 自定义 DoH 服务地址
+    pub doh_url: Option<String>,
+    /// 节点同时有 IPv4 和 IPv6 地址时的选择 (prefer-ipv4/prefer-ipv6/ipv4-only/ipv6-only)
+    pub resolve_strategy: ResolveStrategy,
+    /// 两次自动切换节点之间的最短间隔（秒）
+    pub failover_cooldown_secs: u64,
+    /// 首选节点恢复后自动切回
+    pub failback: bool,
+    /// 首选节点连续通过多少次健康检查后切回
+    pub failback_checks: u32,
+    /// 每分钟最多为单个连接改用备用节点重试的次数，0 为不重试
+    pub stream_retries_per_minute: u32,
+    /// 最多保留的备用节点数量，按最近测得的延迟排序后取前几个
+    pub max_backup_nodes: usize,
+    /// 启动时检查节点，节点拒绝认证时改用备用节点 (failover)、直接退出 (fail) 或不检查 (off)
+    pub start_preflight: PreflightMode,
+    /// 游戏中延迟持续高于阈值时报警，可按游戏设置阈值
+    pub latency_alarm: LatencyAlarmConfig,
+    /// 游戏 UDP 会话空闲时发送保活包，避免节点或 NAT 上的映射在比赛中途过期，可按游戏设置间隔
+    pub udp_keepalive: UdpKeepaliveConfig,
+    /// 游戏数据包的 DSCP 标记，可按游戏设置类别，默认关闭
+    pub dscp: DscpConfig,
+    /// 在按流量计费的网络（手机热点等）上暂停加速，可按 Wi-Fi 名称或网卡设置
+    pub network: NetworkConfig,
+    /// 低延迟模式等性能设置（修改后需重启）
+    pub performance: PerformanceConfig,
+    /// 竞技游戏对局中推迟订阅刷新、节点测速和检查更新
+    pub do_not_disturb: DoNotDisturbConfig,
+    /// 健康检查、备用节点探测、订阅下载等的超时，以及可用节点的延迟上限
+    pub timeouts: Timeouts,
+    /// 切换节点等事件发送桌面通知
+    pub desktop_notifications: bool,
+    /// 连接节点时通告的 TCP MSS，隧道开销导致分片时调小
+    pub tcp_mss: Option<u16>,
+    /// 转发到节点的单个 UDP 数据包最大字节数
+    pub udp_max_payload: Option<usize>,
+    /// UDP 数据包超过上限时丢弃 (drop) 或整包发送并允许 IP 分片 (fragment)
+    pub udp_oversize: OversizePolicy,
+    /// 按节点名称启用的流量混淆（填充、平滑发送、TLS 承载 UDP），需要节点端支持
+    pub obfuscation: HashMap<String, ObfsConfig>,
+    /// 按节点 ID 覆盖订阅中的设置（SNI、是否转发 UDP、连接超时）
+    pub node_overrides: HashMap<String, NodeOverride>,
+    /// 订阅节点的改名规则（正则替换、加上或去掉 emoji），写法与 subconverter 相同
+    pub rename: RenameConfig,
+    /// 合并地址、端口、协议和认证信息都相同的重复节点
+    pub dedup_nodes: bool,
+    /// 直连规则（域名、IP/CIDR、port:端口），命中的连接不经过加速节点
+    pub bypass: Vec<String>,
+    /// 透明模式下从 TLS SNI 和 HTTP Host 识别连接的目标域名，用于域名直连规则和统计
+    pub sniff: bool,
+    /// 拒绝 BitTorrent 连接和 DHT 流量，很多游戏节点禁止下载，违规可能被封号
+    pub block_bt: bool,
+    /// 允许局域网设备（例如 Switch/PS5）连接代理端口
+    pub allow_lan: bool,
+    /// 开启 allow_lan 后允许连接代理端口的地址段（IP 或 CIDR），为空时不限制，本机始终允许
+    pub allowed_clients: Vec<String>,
+    /// 禁止使用加速的局域网设备（IP 或 MAC 地址）
+    pub blocked_devices: Vec<String>,
+    /// 按设备 IP 或 MAC 设置游戏机类型或游戏端口，这些设备只有游戏端口经过加速，系统更新等直连
+    pub lan_devices: HashMap<String, LanDeviceConfig>,
+    /// 自动切换节点后，仍有流量的目标继续使用原节点的时间（秒），0 表示关闭
+    pub sticky_ttl_secs: u64,
+    /// 连接节点的 TCP keepalive 空闲时间（秒），避免空闲的游戏大厅连接被节点或 NAT 断开，0 为关闭
+    pub tcp_keepalive_secs: u64,
+    /// 在本机记录各功能的使用次数和节点切换次数，供 cf insights 查看，从不上传
+    pub insights: bool,
+    /// 连接节点使用的网卡（仅 Linux），多网卡或开启 TUN 时避免流量绕回
+    pub interface: Option<String>,
+    /// 连接节点使用的源地址
+    pub bind_ip: Option<IpAddr>,
+    /// 运行模式 (desktop/router)，路由器上减少内存占用和后台任务，修改后重启生效
+    pub profile: Profile,
+    /// 把检测到的游戏流量透明重定向到代理端口 (off/pf/redirect/tproxy)
+    pub redirect: RedirectMode,
+    /// 始终重定向的游戏，用于路由器后面的游戏机等本机检测不到的设备
+    pub redirect_games: Vec<SupportedGame>,
+    /// 该 cgroup 中进程的全部流量都重定向（仅 Linux），例如 user.slice/games
+    pub redirect_cgroup: Option<String>,
 }
 
 impl Default for Config {
@@ -16,54 +151,380 @@ impl Default for Config {
         Self {
             subscription_url: None,
             selected_node: None,
+            selected_node_id: None,
             proxy_port: 7890,
-            auto_select: true,
+            auto_select: AutoSelectConfig::default(),
+            log_level: None,
+            update_channel: UpdateChannel::default(),
+            update_mirror: None,
+            update_proxy: None,
+            disable_update_check: false,
+            update_check_interval_hours: 24,
+            dns_resolver: ResolverKind::default(),
+            doh_url: None,
+            resolve_strategy: ResolveStrategy::default(),
+            failover_cooldown_secs: 60,
+            failback: true,
+            failback_checks: 3,
+            stream_retries_per_minute: 20,
+            max_backup_nodes: 10,
+            start_preflight: PreflightMode::default(),
+            latency_alarm: LatencyAlarmConfig::default(),
+            udp_keepalive: UdpKeepaliveConfig::default(),
+            dscp: DscpConfig::default(),
+            network: NetworkConfig::default(),
+            performance: PerformanceConfig::default(),
+            do_not_disturb: DoNotDisturbConfig::default(),
+            timeouts: Timeouts::default(),
+            desktop_notifications: true,
+            tcp_mss: None,
+            udp_max_payload: None,
+            udp_oversize: OversizePolicy::default(),
+            obfuscation: HashMap::new(),
+            node_overrides: HashMap::new(),
+            rename: RenameConfig::default(),
+            dedup_nodes: true,
+            bypass: Vec::new(),
+            sniff: true,
+            block_bt: false,
+            allow_lan: false,
+            allowed_clients: Vec::new(),
+            blocked_devices: Vec::new(),
+            lan_devices: HashMap::new(),
+            sticky_ttl_secs: 600,
+            tcp_keepalive_secs: 30,
+            insights: false,
+            interface: None,
+            bind_ip: None,
+            profile: Profile::default(),
+            redirect: RedirectMode::default(),
+            redirect_games: Vec::new(),
+            redirect_cgroup: None,
         }
     }
 }
 
 impl Config {
-    pub fn config_dir() -> Result<PathBuf> {
+    pub fn enable_portable() {
+        PORTABLE.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_portable() -> bool {
+        PORTABLE.load(Ordering::Relaxed)
+    }
+
+    pub fn portable_flag_present() -> bool {
+        Self::exe_dir()
+            .map(|dir| dir.join(PORTABLE_FLAG_FILE).exists())
+            .unwrap_or(false)
+    }
+
+    fn exe_dir() -> Result<PathBuf, ConfigError> {
+        let exe = std::env::current_exe().map_err(|_| ConfigError::NoDir("程序路径"))?;
+        exe.parent()
+            .map(Path::to_path_buf)
+            .ok_or(ConfigError::NoDir("程序所在目录"))
+    }
+
+    /// 使用命名实例的配置和缓存目录，端口、守护进程信息和控制通道都与其他实例分开，可以同时运行。
+    /// 实例第一次使用时复制默认配置并分配一个未被其他实例使用的端口，返回该端口
+    pub fn enable_instance(name: &str) -> Result<Option<u16>> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            bail!("配置名称只能包含字母、数字、- 和 _: {}", name);
+        }
+        let base = Self::load().unwrap_or_default();
+        let mut used_ports = vec![base.proxy_port];
+        for instance in Self::instances() {
+            if let Ok(config) = Self::load_from(&Self::instances_dir()?.join(&instance).join("config.yaml")) {
+                used_ports.push(config.proxy_port);
+            }
+        }
+
+        let _ = INSTANCE.set(name.to_string());
+        if Self::config_file()?.exists() {
+            return Ok(None);
+        }
+
+        let highest = used_ports.into_iter().max().unwrap_or(base.proxy_port);
+        let ip = if base.allow_lan { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::LOCALHOST) };
+        let port = conflict::next_free_port(ip, highest).context("没有可供新配置使用的空闲端口")?;
+        let config = Self { proxy_port: port, ..base };
+        config.save()?;
+        Ok(Some(port))
+    }
+
+    pub fn instance() -> Option<&'static str> {
+        INSTANCE.get().map(String::as_str)
+    }
+
+    /// 已创建的命名实例
+    pub fn instances() -> Vec<String> {
+        let Some(entries) = Self::instances_dir().ok().and_then(|dir| fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.path().join("config.yaml").exists())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn instances_dir() -> Result<PathBuf, ConfigError> {
+        Self::base_config_dir().map(|dir| dir.join(INSTANCES_DIR))
+    }
+
+    fn base_config_dir() -> Result<PathBuf, ConfigError> {
+        if Self::is_portable() {
+            return Self::exe_dir().map(|dir| dir.join("config"));
+        }
+
         dirs::config_dir()
             .map(|dir| dir.join("cf"))
-            .context("无法获取配置目录")
+            .ok_or(ConfigError::NoDir("配置目录"))
+    }
+
+    pub fn config_dir() -> Result<PathBuf, ConfigError> {
+        let dir = Self::base_config_dir()?;
+        Ok(match Self::instance() {
+            Some(name) => dir.join(INSTANCES_DIR).join(name),
+            None => dir,
+        })
+    }
+
+    pub fn cache_dir() -> Result<PathBuf, ConfigError> {
+        let dir = if Self::is_portable() {
+            Self::exe_dir()?.join("cache")
+        } else {
+            dirs::cache_dir()
+                .map(|dir| dir.join("cf"))
+                .ok_or(ConfigError::NoDir("缓存目录"))?
+        };
+        Ok(match Self::instance() {
+            Some(name) => dir.join(INSTANCES_DIR).join(name),
+            None => dir,
+        })
     }
 
-    pub fn config_file() -> Result<PathBuf> {
+    pub fn config_file() -> Result<PathBuf, ConfigError> {
         Self::config_dir().map(|dir| dir.join("config.yaml"))
     }
 
-    pub fn load() -> Result<Self> {
+    /// 记录选中的节点，同时保存稳定 ID
+    pub fn select_node(&mut self, node: &Node) {
+        self.selected_node = Some(node.name.clone());
+        self.selected_node_id = Some(node.id());
+    }
+
+    pub fn log_level_filter(&self) -> Option<LevelFilter> {
+        self.log_level.as_deref().and_then(|level| level.parse().ok())
+    }
+
+    pub fn backup_file() -> Result<PathBuf, ConfigError> {
+        Self::config_dir().map(|dir| dir.join("config.yaml.bak"))
+    }
+
+    pub fn load() -> Result<Self, ConfigError> {
         let config_file = Self::config_file()?;
 
         if !config_file.exists() {
             return Ok(Self::default());
         }
 
-        let content = fs::read_to_string(&config_file)
-            .with_context(|| format!("无法读取配置文件: {:?}", config_file))?;
+        Self::load_from(&config_file)
+    }
+
+    fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)
+            .map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
 
         let config: Self = serde_yaml::from_str(&content)
-            .with_context(|| format!("无法解析配置文件: {:?}", config_file))?;
+            .map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })?;
+        let invalid = |e: anyhow::Error| ConfigError::Invalid {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        };
+        config.timeouts.validate().map_err(invalid)?;
+        lan::validate_allowed(&config.allowed_clients).map_err(invalid)?;
+        lan::validate_devices(&config.lan_devices).map_err(invalid)?;
+        node_overrides::validate(&config.node_overrides).map_err(invalid)?;
+        rename::validate(&config.rename).map_err(invalid)?;
 
         Ok(config)
     }
 
-    pub fn save(&self) -> Result<()> {
-        let config_dir = Self::config_dir()?;
+    /// 加载配置，解析失败时在终端中提示从备份恢复或重置
+    pub fn load_or_recover() -> Result<Self> {
+        let err = match Self::load() {
+            Ok(config) => return Ok(config),
+            Err(e) => e,
+        };
+
         let config_file = Self::config_file()?;
+        // 设置项的取值不合法时只需修改对应的值，不当作损坏处理
+        if let ConfigError::Invalid { path, .. } = &err {
+            println!("❌ {} ({})", err, path.display());
+            return Err(err.into());
+        }
+        let err = anyhow::Error::new(err);
+        let backup_file = Self::backup_file()?;
+        let backup = Self::load_from(&backup_file).ok();
+
+        println!("❌ 配置文件已损坏: {:#}", err);
+
+        if !io::stdin().is_terminal() {
+            if backup.is_some() {
+                println!("💡 可以从备份恢复: cp {:?} {:?}", backup_file, config_file);
+            }
+            println!("💡 或者使用 'cf reset' 重置配置");
+            return Err(err);
+        }
+
+        if backup.is_some() {
+            println!("  [r] 从备份恢复 ({})", backup_file.display());
+        }
+        println!("  [d] 重置为默认配置 (损坏的文件会被保留)");
+        println!("  [q] 退出");
+        print!("请选择: ");
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+
+        match (answer.trim(), backup) {
+            ("r" | "R", Some(config)) => {
+                let preserved = config.replace_corrupt(&config_file)?;
+                println!("✅ 已从备份恢复配置，损坏的文件已保存为: {}", preserved.display());
+                Ok(config)
+            }
+            ("d" | "D", _) => {
+                let config = Self::default();
+                let preserved = config.replace_corrupt(&config_file)?;
+                println!("✅ 配置已重置，损坏的文件已保存为: {}", preserved.display());
+                Ok(config)
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// 保留损坏的配置文件后写入当前配置，返回损坏文件的新路径
+    fn replace_corrupt(&self, config_file: &Path) -> Result<PathBuf> {
+        let preserved = Self::preserve_corrupt_file(config_file)?;
+        self.save_to(config_file)?;
+        Ok(preserved)
+    }
+
+    /// 将损坏的配置文件重命名保留，便于事后排查
+    fn preserve_corrupt_file(config_file: &Path) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let preserved = config_file.with_extension(format!("yaml.corrupt-{}", timestamp));
+
+        fs::rename(config_file, &preserved)
+            .with_context(|| format!("无法保留损坏的配置文件: {:?}", config_file))?;
+
+        Ok(preserved)
+    }
+
+    /// 原子写入：先写临时文件并落盘，再替换正式文件；替换前保留上一份可用配置作为备份
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&Self::config_file()?)
+    }
+
+    fn save_to(&self, config_file: &Path) -> Result<(), ConfigError> {
+        let config_dir = config_file.parent().ok_or(ConfigError::NoDir("配置目录"))?;
+        let backup_file = config_file.with_extension("yaml.bak");
+        let temp_file = config_file.with_extension("yaml.tmp");
+        let write_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| ConfigError::Write { path, source }
+        };
 
         if !config_dir.exists() {
-            fs::create_dir_all(&config_dir)
-                .with_context(|| format!("无法创建配置目录: {:?}", config_dir))?;
+            fs::create_dir_all(config_dir).map_err(write_error(config_dir))?;
+        }
+
+        let content = serde_yaml::to_string(self).map_err(ConfigError::Serialize)?;
+
+        {
+            let mut file = File::create(&temp_file).map_err(write_error(&temp_file))?;
+            file.write_all(content.as_bytes()).map_err(write_error(&temp_file))?;
+            file.sync_all().map_err(write_error(&temp_file))?;
         }
 
-        let content = serde_yaml::to_string(self)
-            .context("无法序列化配置")?;
+        // 只有当前文件能正常解析时才轮换备份，避免用损坏的文件覆盖可用备份
+        if Self::load_from(config_file).is_ok() {
+            fs::copy(config_file, &backup_file).map_err(write_error(&backup_file))?;
+        }
 
-        fs::write(&config_file, content)
-            .with_context(|| format!("无法写入配置文件: {:?}", config_file))?;
+        fs::rename(&temp_file, config_file).map_err(write_error(config_file))?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个测试使用独立的临时配置目录
+    fn temp_config_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clashfun-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("config.yaml")
+    }
+
+    fn port_in(path: &Path) -> u16 {
+        Config::load_from(path).unwrap().proxy_port
+    }
+
+    #[test]
+    fn saves_atomically_and_keeps_last_good_backup() {
+        let config_file = temp_config_file("save");
+        let backup_file = config_file.with_extension("yaml.bak");
+
+        // 第一次保存时创建目录，没有可备份的旧文件
+        Config { proxy_port: 7891, ..Config::default() }.save_to(&config_file).unwrap();
+        assert_eq!(port_in(&config_file), 7891);
+        assert!(!backup_file.exists());
+        assert!(!config_file.with_extension("yaml.tmp").exists());
+
+        // 再次保存时上一份配置成为备份
+        Config { proxy_port: 7892, ..Config::default() }.save_to(&config_file).unwrap();
+        assert_eq!(port_in(&config_file), 7892);
+        assert_eq!(port_in(&backup_file), 7891);
+
+        // 当前文件损坏时不覆盖可用的备份
+        fs::write(&config_file, "proxy_port: [").unwrap();
+        Config { proxy_port: 7893, ..Config::default() }.save_to(&config_file).unwrap();
+        assert_eq!(port_in(&config_file), 7893);
+        assert_eq!(port_in(&backup_file), 7891);
+
+        let _ = fs::remove_dir_all(config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn recovers_corrupt_config_and_keeps_the_broken_file() {
+        let config_file = temp_config_file("recover");
+        Config { proxy_port: 7891, ..Config::default() }.save_to(&config_file).unwrap();
+        Config { proxy_port: 7892, ..Config::default() }.save_to(&config_file).unwrap();
+        fs::write(&config_file, "proxy_port: [").unwrap();
+        assert!(matches!(Config::load_from(&config_file), Err(ConfigError::Parse { .. })));
+
+        // 从备份恢复
+        let backup = Config::load_from(&config_file.with_extension("yaml.bak")).unwrap();
+        let preserved = backup.replace_corrupt(&config_file).unwrap();
+        assert_eq!(port_in(&config_file), 7891);
+        assert_eq!(fs::read_to_string(&preserved).unwrap(), "proxy_port: [");
+        assert!(preserved.file_name().unwrap().to_string_lossy().starts_with("config.yaml.corrupt-"));
+
+        // 设置项取值不合法时不当作损坏
+        fs::write(&config_file, "allowed_clients: [\"不是地址段\"]").unwrap();
+        assert!(matches!(Config::load_from(&config_file), Err(ConfigError::Invalid { .. })));
+
+        let _ = fs::remove_dir_all(config_file.parent().unwrap());
+    }
+}
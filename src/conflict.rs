@@ -0,0 +1,202 @@
+use anyhow::Result;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::config::Config;
+use crate::game_detect::PortProtocol;
+use crate::handover;
+use crate::ipc;
+
+/// 端口被占用时最多向后查找多少个端口
+const PORT_SEARCH_RANGE: u16 = 100;
+
+/// 占用端口的进程
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortOwner {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// TCP 和 UDP 端口都能绑定
+pub fn port_available(ip: IpAddr, port: u16) -> bool {
+    std::net::TcpListener::bind((ip, port)).is_ok() && std::net::UdpSocket::bind((ip, port)).is_ok()
+}
+
+/// 从 port 之后查找第一个空闲端口
+pub fn next_free_port(ip: IpAddr, port: u16) -> Option<u16> {
+    (1..=PORT_SEARCH_RANGE)
+        .filter_map(|offset| port.checked_add(offset))
+        .find(|&candidate| port_available(ip, candidate))
+}
+
+/// 启动前检查代理端口，返回实际使用的端口；无法启动时打印原因并返回 None
+pub async fn preflight(allow_lan: bool, port: u16) -> Result<Option<u16>> {
+    let ip = if allow_lan { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::LOCALHOST) };
+    if port_available(ip, port) {
+        return Ok(Some(port));
+    }
+
+    // 另一个 ClashFun 实例：能交接时由新实例接管，否则提示
+    if let Ok(report) = ipc::query_status().await {
+        if report.proxy_port == port {
+            if handover::available() {
+                return Ok(Some(port));
+            }
+            println!("⚠️  ClashFun 已在运行 (PID {}，端口 {})", report.pid, port);
+            println!("💡 请先在运行它的窗口按 Ctrl+C 停止，再重新运行 'cf start'");
+            return Ok(None);
+        }
+    }
+
+    let owner = port_owner(PortProtocol::Tcp, port).or_else(|| port_owner(PortProtocol::Udp, port));
+    match &owner {
+        Some(owner) => println!("⚠️  端口 {} 已被 {} (PID {}) 占用", port, owner.name, owner.pid),
+        None => println!("⚠️  端口 {} 已被其他程序占用", port),
+    }
+    if owner.as_ref().is_some_and(|owner| owner.name.to_lowercase().contains("clash")) {
+        println!("💡 看起来是 Clash，可以退出 Clash 后再启动，或者让 ClashFun 使用其他端口");
+    }
+
+    let Some(free) = next_free_port(ip, port) else {
+        println!("❌ {}-{} 之间没有空闲端口，请在配置文件中修改 proxy_port", port, port.saturating_add(PORT_SEARCH_RANGE));
+        return Ok(None);
+    };
+
+    if !io::stdin().is_terminal() {
+        println!("💡 关闭占用端口的程序，或在配置文件中把 proxy_port 改为 {} 等空闲端口", free);
+        return Ok(None);
+    }
+
+    println!("  [n] 本次改用空闲端口 {}", free);
+    println!("  [s] 改用端口 {} 并保存到配置", free);
+    println!("  [q] 退出");
+    print!("请选择: ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim() {
+        "n" | "N" => Ok(Some(free)),
+        "s" | "S" => {
+            let mut config = Config::load_or_recover()?;
+            config.proxy_port = free;
+            config.save()?;
+            println!("✅ 已将代理端口改为 {}，记得同时修改游戏或系统中的代理设置", free);
+            Ok(Some(free))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// 查找持有该端口 socket 的进程：在 /proc/net 中找到 socket inode，再找持有它的进程
+#[cfg(target_os = "linux")]
+pub fn proc_owner(protocol: PortProtocol, port: u16) -> Option<PortOwner> {
+    use std::fs;
+
+    let tables = match protocol {
+        PortProtocol::Tcp => ["/proc/net/tcp", "/proc/net/tcp6"],
+        PortProtocol::Udp => ["/proc/net/udp", "/proc/net/udp6"],
+    };
+    let inode = tables.iter().find_map(|table| {
+        let content = fs::read_to_string(table).ok()?;
+        content.lines().skip(1).find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?;
+            let inode = *fields.get(9)?;
+            (u16::from_str_radix(local_port, 16).ok()? == port && inode != "0").then(|| inode.to_string())
+        })
+    })?;
+
+    let target = format!("socket:[{}]", inode);
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let owns = fds
+            .flatten()
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == target.as_str()));
+        if owns {
+            let name = fs::read_to_string(entry.path().join("comm")).ok()?;
+            return Some(PortOwner { pid, name: name.trim().to_string() });
+        }
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+pub fn port_owner(protocol: PortProtocol, port: u16) -> Option<PortOwner> {
+    proc_owner(protocol, port)
+}
+
+/// macOS 上通过 lsof 查询
+#[cfg(target_os = "macos")]
+pub fn port_owner(protocol: PortProtocol, port: u16) -> Option<PortOwner> {
+    let filter = match protocol {
+        PortProtocol::Tcp => vec![format!("-iTCP:{}", port), "-sTCP:LISTEN".to_string()],
+        PortProtocol::Udp => vec![format!("-iUDP:{}", port)],
+    };
+    let output = std::process::Command::new("lsof").args(["-nP", "-Fpc"]).args(filter).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pid = stdout.lines().find_map(|line| line.strip_prefix('p')?.parse().ok())?;
+    let name = stdout.lines().find_map(|line| line.strip_prefix('c'))?.to_string();
+    Some(PortOwner { pid, name })
+}
+
+/// Windows 上通过 netstat 找到 PID，再查进程名
+#[cfg(windows)]
+pub fn port_owner(protocol: PortProtocol, port: u16) -> Option<PortOwner> {
+    use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+
+    let output = std::process::Command::new("netstat")
+        .args(["-ano", "-p", protocol.as_str()])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let suffix = format!(":{}", port);
+    let pid: u32 = stdout.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let local = fields.get(1)?;
+        if !local.ends_with(&suffix) {
+            return None;
+        }
+        // TCP 只看监听中的连接，UDP 没有状态列
+        if protocol == PortProtocol::Tcp && fields.get(3) != Some(&"LISTENING") {
+            return None;
+        }
+        fields.last()?.parse().ok()
+    })?;
+
+    let mut system = System::new();
+    system.refresh_process(Pid::from_u32(pid));
+    let name = system
+        .process(Pid::from_u32(pid))
+        .map_or_else(|| "未知程序".to_string(), |process| process.name().to_string());
+    Some(PortOwner { pid, name })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn port_owner(_protocol: PortProtocol, _port: u16) -> Option<PortOwner> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_next_free_port_after_conflict() {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let tcp = std::net::TcpListener::bind((ip, 0)).unwrap();
+        let port = tcp.local_addr().unwrap().port();
+
+        assert!(!port_available(ip, port));
+        let free = next_free_port(ip, port).unwrap();
+        assert!(free > port && port_available(ip, free));
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(port_owner(PortProtocol::Tcp, port).map(|owner| owner.pid), Some(std::process::id()));
+    }
+}
@@ -0,0 +1,140 @@
+use log::{Log, Metadata, Record};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+/// 崩溃报告中保留的最近日志条数
+const MAX_RECENT_LOGS: usize = 200;
+const ISSUE_URL: &str = "https://github.com/ink1ing/clashfun/issues";
+
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+/// 交互界面是否占用了终端（raw 模式 + 备用屏幕）
+static TUI_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// 包装 env_logger，同时把日志记入内存供崩溃报告使用
+struct RecordingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for RecordingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!("{} {} {}", record.level(), record.target(), record.args());
+        let mut recent = RECENT_LOGS.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() >= MAX_RECENT_LOGS {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+        drop(recent);
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// 安装日志记录器，替代 env_logger 的 init
+pub fn install_logger(mut builder: env_logger::Builder) {
+    let logger = RecordingLogger { inner: builder.build() };
+    log::set_max_level(logger.inner.filter());
+    let _ = log::set_boxed_logger(Box::new(logger));
+}
+
+#[cfg(feature = "tui")]
+pub fn set_tui_active(active: bool) {
+    TUI_ACTIVE.store(active, Ordering::Relaxed);
+}
+
+/// 安装 panic 处理：恢复终端、写入崩溃报告并提示报告位置
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        if TUI_ACTIVE.swap(false, Ordering::Relaxed) {
+            restore_terminal();
+        }
+
+        default_hook(info);
+
+        match write_report(info) {
+            Ok(path) => {
+                eprintln!("💥 ClashFun 发生崩溃，崩溃报告已保存到: {}", path.display());
+                eprintln!("💡 请将该文件附在问题反馈中: {}", ISSUE_URL);
+            }
+            Err(e) => eprintln!("💥 ClashFun 发生崩溃，写入崩溃报告失败: {}", e),
+        }
+    }));
+}
+
+#[cfg(not(feature = "tui"))]
+fn restore_terminal() {}
+
+#[cfg(feature = "tui")]
+fn restore_terminal() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = crossterm::execute!(
+        io::stdout(),
+        crossterm::terminal::LeaveAlternateScreen,
+        crossterm::cursor::Show
+    );
+}
+
+fn write_report(info: &PanicHookInfo) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "未知错误".to_string());
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+        .unwrap_or_default();
+
+    let mut report = String::new();
+    let _ = writeln!(report, "ClashFun 崩溃报告");
+    let _ = writeln!(report, "版本: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "系统: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let _ = writeln!(report, "时间: {}", timestamp);
+    let _ = writeln!(report, "命令: {}", std::env::args().collect::<Vec<_>>().join(" "));
+    let _ = writeln!(report, "线程: {}", std::thread::current().name().unwrap_or("<unnamed>"));
+    let _ = writeln!(report, "位置: {}", location);
+    let _ = writeln!(report, "信息: {}", message);
+    let _ = writeln!(report, "\n调用栈:\n{}", Backtrace::force_capture());
+
+    let _ = writeln!(report, "\n最近日志:");
+    for line in RECENT_LOGS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(report, "{}", line);
+    }
+
+    let dir = Config::cache_dir()
+        .map(|dir| dir.join("crashes"))
+        .unwrap_or_else(|_| std::env::temp_dir());
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("crash-{}.txt", timestamp));
+    fs::write(&path, report)?;
+    Ok(path)
+}
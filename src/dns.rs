@@ -0,0 +1,392 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+use crate::config::Config;
+use crate::mtu;
+use crate::node_overrides;
+use crate::outbound;
+use crate::subscription::Node;
+use crate::timeouts;
+
+/// 未指定 doh_url 时使用的 DoH 服务
+pub const DEFAULT_DOH_URL: &str = "https://cloudflare-dns.com/dns-query";
+/// 系统解析器不返回 TTL，按固定时间缓存
+const SYSTEM_TTL: Duration = Duration::from_secs(60);
+/// DoH 返回的 TTL 限制在此范围内，避免频繁解析或长期使用过期地址
+const MIN_TTL: Duration = Duration::from_secs(5);
+const MAX_TTL: Duration = Duration::from_secs(3600);
+const DOH_TIMEOUT: Duration = Duration::from_secs(5);
+
+static RESOLVER: OnceLock<Resolver> = OnceLock::new();
+
+/// 节点地址的解析方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolverKind {
+    /// 操作系统解析器
+    #[default]
+    System,
+    /// DNS over HTTPS，不受本地 DNS 污染影响
+    Doh,
+}
+
+/// 节点地址同时有 IPv4 和 IPv6 时的选择方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResolveStrategy {
+    /// 先尝试 IPv4 地址
+    #[default]
+    PreferIpv4,
+    /// 先尝试 IPv6 地址
+    PreferIpv6,
+    /// 只使用 IPv4 地址，用于 AAAA 记录线路很差的节点
+    Ipv4Only,
+    /// 只使用 IPv6 地址
+    Ipv6Only,
+}
+
+impl ResolveStrategy {
+    /// 按策略过滤并排列地址，同一协议族内保持解析结果的顺序
+    pub fn apply(&self, mut addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        match self {
+            Self::PreferIpv4 => addrs.sort_by_key(|ip| ip.is_ipv6()),
+            Self::PreferIpv6 => addrs.sort_by_key(|ip| ip.is_ipv4()),
+            Self::Ipv4Only => addrs.retain(IpAddr::is_ipv4),
+            Self::Ipv6Only => addrs.retain(IpAddr::is_ipv6),
+        }
+        addrs
+    }
+}
+
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+struct Resolver {
+    kind: ResolverKind,
+    strategy: ResolveStrategy,
+    doh_url: String,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    #[serde(rename = "TTL")]
+    ttl: u64,
+    data: String,
+}
+
+/// 按配置初始化解析器，只在启动时生效一次
+pub fn init(config: &Config) {
+    let _ = RESOLVER.set(Resolver::new(config.dns_resolver, config.doh_url.clone(), config.resolve_strategy));
+}
+
+fn resolver() -> &'static Resolver {
+    RESOLVER.get_or_init(|| Resolver::new(ResolverKind::System, None, ResolveStrategy::default()))
+}
+
+/// 解析节点地址，优先使用缓存，按 resolve_strategy 选择 IPv4 / IPv6
+pub async fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    resolve_with(host, port, true).await
+}
+
+/// 不使用缓存重新解析，用于测量解析耗时
+pub async fn resolve_fresh(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    resolve_with(host, port, false).await
+}
+
+async fn resolve_with(host: &str, port: u16, cached: bool) -> io::Result<Vec<SocketAddr>> {
+    let resolver = resolver();
+    let addrs = resolver.strategy.apply(resolver.lookup(host, cached).await?);
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} 没有符合 resolve_strategy 的地址", host),
+        ));
+    }
+    Ok(addrs.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+}
+
+/// 解析节点地址并依次尝试连接，节点单独设置了连接超时时使用该值
+pub async fn connect_node(node: &Node) -> io::Result<TcpStream> {
+    let timeout = node_overrides::for_node(node)
+        .connect_timeout()
+        .unwrap_or_else(|| timeouts::current().connect());
+    let mut last_error = None;
+    for addr in resolve(&node.server, node.port).await? {
+        let socket = match outbound::tcp_socket(addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+        mtu::clamp_mss(&socket);
+        // 单个地址超时后尝试下一个，例如 IPv6 不通时改用 IPv4
+        match tokio::time::timeout(timeout, socket.connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => last_error = Some(io::Error::new(io::ErrorKind::TimedOut, format!("连接 {} 超时", addr))),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} 没有可用的地址", node.server))
+    }))
+}
+
+/// 在后台预先解析节点地址，切换节点时无需等待 DNS
+pub fn prefetch(nodes: &[Node]) {
+    let hosts: Vec<String> = nodes.iter().map(|node| node.server.clone()).collect();
+    tokio::spawn(async move {
+        for host in hosts {
+            if let Err(e) = resolver().lookup(&host, true).await {
+                debug!("预解析 {} 失败: {}", host, e);
+            }
+        }
+    });
+}
+
+impl Resolver {
+    fn new(kind: ResolverKind, doh_url: Option<String>, strategy: ResolveStrategy) -> Self {
+        Self {
+            kind,
+            strategy,
+            doh_url: doh_url.unwrap_or_else(|| DEFAULT_DOH_URL.to_string()),
+            client: reqwest::Client::builder()
+                .timeout(DOH_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn lookup(&self, host: &str, cached: bool) -> io::Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let stale = {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            match cache.get(host) {
+                Some(entry) if cached && entry.expires > Instant::now() => return Ok(entry.addrs.clone()),
+                Some(entry) => Some(entry.addrs.clone()),
+                None => None,
+            }
+        };
+
+        let result = match self.kind {
+            ResolverKind::System => self.lookup_system(host).await,
+            ResolverKind::Doh => match self.lookup_doh(host).await {
+                Ok(result) => Ok(result),
+                Err(e) => {
+                    warn!("DoH 解析 {} 失败，改用系统解析: {}", host, e);
+                    self.lookup_system(host).await
+                }
+            },
+        };
+
+        match result {
+            Ok((addrs, ttl)) if !addrs.is_empty() => {
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.insert(
+                    host.to_string(),
+                    CacheEntry {
+                        addrs: addrs.clone(),
+                        expires: Instant::now() + ttl,
+                    },
+                );
+                Ok(addrs)
+            }
+            // 解析失败时继续使用过期的地址，比直接断开更好
+            Ok(_) | Err(_) if stale.is_some() => {
+                warn!("解析 {} 失败，使用缓存的旧地址", host);
+                Ok(stale.unwrap_or_default())
+            }
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} 没有解析结果", host),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn lookup_system(&self, host: &str) -> io::Result<(Vec<IpAddr>, Duration)> {
+        let addrs = tokio::net::lookup_host((host, 0)).await?;
+        let mut ips: Vec<IpAddr> = Vec::new();
+        for addr in addrs {
+            if !ips.contains(&addr.ip()) {
+                ips.push(addr.ip());
+            }
+        }
+        Ok((ips, SYSTEM_TTL))
+    }
+
+    async fn lookup_doh(&self, host: &str) -> io::Result<(Vec<IpAddr>, Duration)> {
+        let mut ips = Vec::new();
+        let mut ttl = MAX_TTL;
+
+        // A 记录在前，IPv4 通常更稳定
+        for record_type in ["A", "AAAA"] {
+            let response: DohResponse = self
+                .client
+                .get(&self.doh_url)
+                .query(&[("name", host), ("type", record_type)])
+                .header("accept", "application/dns-json")
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(io::Error::other)?
+                .json()
+                .await
+                .map_err(io::Error::other)?;
+
+            // 只取地址记录，跳过 CNAME 等中间结果
+            for answer in response.answer.iter().filter(|a| a.record_type == 1 || a.record_type == 28) {
+                if let Ok(ip) = answer.data.parse::<IpAddr>() {
+                    ips.push(ip);
+                    ttl = ttl.min(Duration::from_secs(answer.ttl));
+                }
+            }
+        }
+
+        Ok((ips, ttl.clamp(MIN_TTL, MAX_TTL)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn orders_and_filters_address_families() {
+        let v4 = |last| IpAddr::from([203, 0, 113, last]);
+        let v6 = |last| IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, last]);
+        let addrs = vec![v6(1), v4(1), v6(2), v4(2)];
+
+        assert_eq!(ResolveStrategy::PreferIpv4.apply(addrs.clone()), [v4(1), v4(2), v6(1), v6(2)]);
+        assert_eq!(ResolveStrategy::PreferIpv6.apply(addrs.clone()), [v6(1), v6(2), v4(1), v4(2)]);
+        assert_eq!(ResolveStrategy::Ipv4Only.apply(addrs.clone()), [v4(1), v4(2)]);
+        assert_eq!(ResolveStrategy::Ipv6Only.apply(addrs), [v6(1), v6(2)]);
+        assert!(ResolveStrategy::Ipv6Only.apply(vec![v4(1)]).is_empty());
+        assert_eq!(serde_yaml::from_str::<ResolveStrategy>("ipv4-only").unwrap(), ResolveStrategy::Ipv4Only);
+    }
+
+    /// 本地的 DoH 服务，game.example 返回一个 A 记录，其他域名没有结果；返回请求计数
+    async fn doh_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut request = vec![0u8; 4096];
+                let len = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..len]);
+                let body = if request.contains("name=game.example&type=A ") {
+                    r#"{"Answer":[{"type":5,"TTL":300,"data":"cdn.example."},{"type":1,"TTL":1,"data":"203.0.113.7"}]}"#
+                } else {
+                    r#"{"Status":3}"#
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/dns-json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn expire(resolver: &Resolver, host: &str) {
+        let mut cache = resolver.cache.lock().unwrap();
+        cache.get_mut(host).unwrap().expires = Instant::now() - Duration::from_secs(1);
+    }
+
+    #[tokio::test]
+    async fn caches_doh_answers_until_their_ttl_expires() {
+        let (url, requests) = doh_server().await;
+        let resolver = Resolver::new(ResolverKind::Doh, Some(url), ResolveStrategy::default());
+        let game = vec![IpAddr::from([203, 0, 113, 7])];
+
+        // 查询 A 和 AAAA 两次，跳过 CNAME
+        assert_eq!(resolver.lookup("game.example", true).await.unwrap(), game);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(resolver.lookup("game.example", true).await.unwrap(), game);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // 过短的 TTL 按 MIN_TTL 缓存
+        let ttl = resolver.cache.lock().unwrap()["game.example"].expires - Instant::now();
+        assert!(ttl > MIN_TTL - Duration::from_secs(1) && ttl <= MIN_TTL, "{:?}", ttl);
+
+        expire(&resolver, "game.example");
+        assert_eq!(resolver.lookup("game.example", true).await.unwrap(), game);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        resolver.lookup("game.example", false).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_empty_answers_but_keeps_stale_addresses() {
+        let (url, requests) = doh_server().await;
+        let resolver = Resolver::new(ResolverKind::Doh, Some(url), ResolveStrategy::default());
+
+        // 没有结果时报错且不缓存，下次重新查询
+        let error = resolver.lookup("missing.example", true).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(resolver.lookup("missing.example", true).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // 地址过期后查不到结果时继续使用旧地址
+        let stale = vec![IpAddr::from([198, 51, 100, 1])];
+        resolver.cache.lock().unwrap().insert(
+            "moved.example".to_string(),
+            CacheEntry {
+                addrs: stale.clone(),
+                expires: Instant::now(),
+            },
+        );
+        assert_eq!(resolver.lookup("moved.example", true).await.unwrap(), stale);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_system_resolver_when_doh_fails() {
+        let loopback = |addrs: &[IpAddr]| !addrs.is_empty() && addrs.iter().all(IpAddr::is_loopback);
+
+        // IP 地址不经过解析
+        let unreachable = Some("http://127.0.0.1:1/dns-query".to_string());
+        let doh = Resolver::new(ResolverKind::Doh, unreachable, ResolveStrategy::default());
+        assert_eq!(doh.lookup("[::1]", true).await.unwrap(), [IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1])]);
+        assert!(doh.cache.lock().unwrap().is_empty());
+
+        assert!(loopback(&doh.lookup("localhost", true).await.unwrap()));
+        let ttl = doh.cache.lock().unwrap()["localhost"].expires - Instant::now();
+        assert!(ttl > SYSTEM_TTL - Duration::from_secs(1) && ttl <= SYSTEM_TTL, "{:?}", ttl);
+
+        let system = Resolver::new(ResolverKind::System, None, ResolveStrategy::default());
+        assert_eq!(system.doh_url, DEFAULT_DOH_URL);
+        assert!(loopback(&system.lookup("localhost", true).await.unwrap()));
+    }
+}
@@ -0,0 +1,148 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+use crate::game_detect::SupportedGame;
+
+static CONFIG: RwLock<Option<DscpConfig>> = RwLock::new(None);
+
+/// 常用于游戏流量的 DSCP 类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DscpClass {
+    /// 加速转发 (46)，多数路由器的最高优先级
+    Ef,
+    /// 实时交互 (32)
+    Cs4,
+    /// 信令 (40)
+    Cs5,
+    /// 交互视频 (34)
+    Af41,
+    /// 不标记
+    Off,
+}
+
+impl DscpClass {
+    pub fn value(self) -> Option<u8> {
+        match self {
+            Self::Ef => Some(46),
+            Self::Cs4 => Some(32),
+            Self::Cs5 => Some(40),
+            Self::Af41 => Some(34),
+            Self::Off => None,
+        }
+    }
+}
+
+/// 发往节点的游戏数据包的 DSCP 标记，家用路由器开启 QoS 时据此优先转发
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DscpConfig {
+    pub enabled: bool,
+    /// 未单独设置的游戏使用的类别
+    pub class: DscpClass,
+    /// 按游戏设置的类别，off 为该游戏不标记
+    pub games: HashMap<SupportedGame, DscpClass>,
+}
+
+impl Default for DscpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            class: DscpClass::Ef,
+            games: HashMap::new(),
+        }
+    }
+}
+
+impl DscpConfig {
+    /// 该游戏的连接要标记的 DSCP 值，不是游戏流量或不标记时为 None
+    pub fn value_for(&self, game: Option<&SupportedGame>) -> Option<u8> {
+        if !self.enabled {
+            return None;
+        }
+        let game = game?;
+        self.games.get(game).copied().unwrap_or(self.class).value()
+    }
+}
+
+pub fn set_config(config: DscpConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+fn config() -> DscpConfig {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// 按游戏标记连接 target 的 socket 之后发出的数据包，失败只记调试日志
+pub fn mark<'a>(socket: impl Into<SockRef<'a>>, target: SocketAddr, game: Option<&SupportedGame>) {
+    let Some(dscp) = config().value_for(game) else {
+        return;
+    };
+    // DSCP 占 ToS / Traffic Class 字节的高 6 位
+    let tos = u32::from(dscp) << 2;
+    let socket = socket.into();
+    let result = if target.is_ipv4() { socket.set_tos_v4(tos) } else { set_tclass_v6(&socket, tos) };
+    match result {
+        Ok(()) => debug!("发往 {} 的数据包标记为 DSCP {}", target, dscp),
+        Err(e) => debug!("无法为发往 {} 的数据包设置 DSCP: {}", target, e),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set_tclass_v6(socket: &SockRef<'_>, tclass: u32) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value = tclass as libc::c_int;
+    // SAFETY: 传入的是有效的 socket 和大小正确的 int 选项值
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_tclass_v6(_socket: &SockRef<'_>, _tclass: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "当前平台不支持 IPv6 流量类别"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_class_per_game() {
+        assert_eq!(DscpConfig::default().value_for(Some(&SupportedGame::Valorant)), None);
+
+        let config: DscpConfig = serde_yaml::from_str("enabled: true\ngames: {cs: cs4, minecraft: off}").unwrap();
+        assert_eq!(config.value_for(Some(&SupportedGame::Valorant)), Some(46));
+        assert_eq!(config.value_for(Some(&SupportedGame::CounterStrike)), Some(32));
+        assert_eq!(config.value_for(Some(&SupportedGame::Minecraft)), None);
+        assert_eq!(config.value_for(None), None);
+    }
+
+    #[test]
+    fn marks_udp_socket() {
+        set_config(DscpConfig {
+            enabled: true,
+            ..DscpConfig::default()
+        });
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = "127.0.0.1:9".parse().unwrap();
+        mark(&socket, target, Some(&SupportedGame::Valorant));
+        assert_eq!(SockRef::from(&socket).tos_v4().unwrap(), 46 << 2);
+    }
+}
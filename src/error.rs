@@ -0,0 +1,235 @@
+//! 各模块对外返回的错误类型
+//!
+//! 命令行和界面据此区分错误原因（例如订阅过期和网络不通），给出不同的提示。
+
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::parser::SubscriptionError;
+
+/// ClashFun 的错误，按来源分类
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Subscription(#[from] FetchError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Proxy(#[from] ProxyError),
+    #[error(transparent)]
+    Update(#[from] UpdateError),
+}
+
+impl Error {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Subscription(e) => e.hint(),
+            Self::Config(e) => e.hint(),
+            Self::Proxy(e) => e.hint(),
+            Self::Update(e) => e.hint(),
+        }
+    }
+}
+
+/// 获取订阅的错误
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("读取本地订阅文件失败: {path}")]
+    ReadFile {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("无法连接订阅服务器")]
+    Unreachable(#[source] reqwest::Error),
+    #[error("订阅已失效 (HTTP {status})")]
+    Expired { status: u16 },
+    #[error("订阅服务器返回错误 (HTTP {status})")]
+    Http { status: u16 },
+    #[error("读取订阅内容失败")]
+    Read(#[source] reqwest::Error),
+    #[error(transparent)]
+    Parse(#[from] SubscriptionError),
+}
+
+impl FetchError {
+    /// 按 HTTP 状态码区分订阅失效和服务器故障
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 | 404 | 410 => Self::Expired { status },
+            _ => Self::Http { status },
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::ReadFile { .. } => Some("检查订阅文件路径，或用 'cf set-subscription <URL>' 重新设置"),
+            Self::Unreachable(_) => Some("检查网络连接；订阅地址无法直连时可以先开启系统代理再更新"),
+            Self::Expired { .. } => Some("订阅可能已过期或被重置，续费后复制新的订阅链接，运行 'cf set-subscription <URL>'"),
+            Self::Http { .. } => Some("订阅服务器暂时不可用，稍后重试"),
+            Self::Read(_) => Some("网络不稳定，稍后重试"),
+            Self::Parse(_) => Some("确认链接是 Clash 订阅或 ss/vmess/vless/trojan 节点链接"),
+        }
+    }
+}
+
+/// 读写配置文件的错误
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("无法获取{0}")]
+    NoDir(&'static str),
+    #[error("无法读取配置文件: {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("无法解析配置文件: {path:?}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    #[error("配置文件中的设置无效: {reason}")]
+    Invalid { path: PathBuf, reason: String },
+    #[error("无法序列化配置")]
+    Serialize(#[source] serde_yaml::Error),
+    #[error("无法写入 {path:?}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ConfigError {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::NoDir(_) => Some("设置 HOME 环境变量，或使用 --portable 把配置放在程序目录"),
+            Self::Read { source, .. } | Self::Write { source, .. } if source.kind() == io::ErrorKind::PermissionDenied => {
+                Some("没有权限访问配置文件，检查文件所有者（是否曾用 sudo 运行过 cf）")
+            }
+            Self::Parse { .. } => Some("修正配置文件的格式，或使用 'cf reset' 重置配置"),
+            Self::Invalid { .. } => Some("修改配置文件中对应的设置后重试"),
+            _ => None,
+        }
+    }
+}
+
+/// 启动代理服务的错误
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("端口 {port} 已被占用")]
+    PortInUse { port: u16 },
+    #[error("无法绑定 {protocol} 端口 {port}")]
+    Bind {
+        protocol: &'static str,
+        port: u16,
+        #[source]
+        source: io::Error,
+    },
+    #[error("代理服务器已在运行")]
+    AlreadyRunning,
+    #[error("节点 {node} 拒绝了认证: {reason}")]
+    AuthRejected { node: String, reason: &'static str },
+}
+
+impl ProxyError {
+    /// 绑定端口失败时区分端口被占用和其他原因
+    pub fn bind(protocol: &'static str, port: u16, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::AddrInUse => Self::PortInUse { port },
+            _ => Self::Bind { protocol, port, source },
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::PortInUse { .. } => Some("使用 'cf start --port <端口>' 换一个端口，或先关闭占用端口的程序"),
+            Self::Bind { source, .. } if source.kind() == io::ErrorKind::PermissionDenied => {
+                Some("小于 1024 的端口需要管理员权限，换用更大的端口")
+            }
+            Self::Bind { .. } => None,
+            Self::AlreadyRunning => Some("使用 'cf status' 查看正在运行的服务"),
+            Self::AuthRejected { .. } => Some("节点的用户名或密码可能已更换，用 'cf subscription status' 确认订阅是否有效，或用 'cf select-node' 换一个节点"),
+        }
+    }
+}
+
+/// 检查和安装更新的错误
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error("无法连接更新服务器")]
+    Unreachable(#[source] reqwest::Error),
+    #[error("获取版本信息失败 (HTTP {status})")]
+    Api { status: u16 },
+    #[error("下载失败 (HTTP {status})")]
+    Download { status: u16 },
+    #[error("无效的版本号 {version}: {reason}")]
+    InvalidVersion { version: String, reason: String },
+    #[error("找不到版本: {0}")]
+    VersionNotFound(String),
+    #[error("不支持的平台: {os}-{arch}")]
+    UnsupportedPlatform { os: String, arch: String },
+    #[error("未找到适合当前平台的更新文件")]
+    NoAsset,
+    #[error("压缩包中未找到 cf 可执行文件")]
+    MissingExecutable,
+    #[error("无效的更新代理 {proxy}: {reason}")]
+    InvalidProxy { proxy: String, reason: String },
+    #[error("此版本未包含自动更新，请通过软件包管理器（如 opkg）或安装脚本更新")]
+    NotIncluded,
+}
+
+impl UpdateError {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Unreachable(_) | Self::Download { .. } => Some("无法访问 GitHub 时可以设置 update_mirror 或 update_proxy"),
+            Self::Api { status: 403 | 429 } => Some("GitHub API 访问次数超限，稍后重试或设置 update_mirror"),
+            Self::VersionNotFound(_) => Some("版本号格式为 0.2.0，可以在 GitHub Releases 页面查看"),
+            Self::InvalidProxy { .. } => Some("update_proxy 的格式为 http://host:port 或 socks5://host:port"),
+            _ => None,
+        }
+    }
+}
+
+/// 从错误链中找到第一个可以给出提示的错误
+pub fn hint(error: &anyhow::Error) -> Option<&'static str> {
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            e.hint()
+        } else if let Some(e) = cause.downcast_ref::<FetchError>() {
+            e.hint()
+        } else if let Some(e) = cause.downcast_ref::<ConfigError>() {
+            e.hint()
+        } else if let Some(e) = cause.downcast_ref::<ProxyError>() {
+            e.hint()
+        } else if let Some(e) = cause.downcast_ref::<UpdateError>() {
+            e.hint()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_hint_in_error_chain() {
+        assert!(matches!(FetchError::from_status(403), FetchError::Expired { status: 403 }));
+        assert!(matches!(FetchError::from_status(502), FetchError::Http { status: 502 }));
+
+        let error = anyhow::Error::new(FetchError::from_status(410)).context("更新订阅失败");
+        assert_eq!(hint(&error), FetchError::Expired { status: 410 }.hint());
+
+        let in_use = ProxyError::bind("TCP", 7890, io::Error::from(io::ErrorKind::AddrInUse));
+        assert!(matches!(in_use, ProxyError::PortInUse { port: 7890 }));
+        let error = anyhow::Error::new(Error::from(in_use));
+        assert!(hint(&error).unwrap().contains("--port"));
+
+        assert_eq!(hint(&anyhow::anyhow!("其他错误")), None);
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::Config;
+
+const EVENTS_FILE: &str = "events.jsonl";
+/// 最多保留的事件数量，超出后丢弃最早的事件
+const MAX_EVENTS: usize = 2000;
+
+/// 只有运行中的加速服务记录事件，命令行和测试中不写入
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOG: Mutex<Option<EventLog>> = Mutex::new(None);
+
+/// 事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NodeSwitch,
+    HealthFailure,
+    GameDetected,
+    SessionStart,
+    SessionEnd,
+    SubscriptionRefresh,
+}
+
+impl EventKind {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::NodeSwitch => "切换节点",
+            Self::HealthFailure => "健康检查失败",
+            Self::GameDetected => "识别到游戏",
+            Self::SessionStart => "会话开始",
+            Self::SessionEnd => "会话结束",
+            Self::SubscriptionRefresh => "刷新订阅",
+        }
+    }
+}
+
+/// 一条事件记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Unix 秒
+    pub timestamp: u64,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+/// 内存中的最近事件，文件只追加，行数达到上限的两倍时按内存中的事件重写
+struct EventLog {
+    events: VecDeque<Event>,
+    file_lines: usize,
+}
+
+fn events_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(EVENTS_FILE))
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// 记录一条事件，写入失败只记调试日志，不影响加速
+pub fn record(kind: EventKind, message: impl Into<String>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let event = Event {
+        timestamp: chrono::Local::now().timestamp().max(0) as u64,
+        kind,
+        message: message.into(),
+    };
+    if let Err(e) = append(event) {
+        debug!("无法写入事件日志: {}", e);
+    }
+}
+
+fn append(event: Event) -> Result<()> {
+    let path = events_file()?;
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let log = log.get_or_insert_with(|| {
+        let events = read(&path).unwrap_or_default();
+        EventLog {
+            file_lines: events.len(),
+            events,
+        }
+    });
+
+    let line = serde_json::to_string(&event)? + "\n";
+    log.events.push_back(event);
+    while log.events.len() > MAX_EVENTS {
+        log.events.pop_front();
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("无法创建缓存目录")?;
+    }
+    if log.file_lines + 1 >= MAX_EVENTS * 2 {
+        let mut lines = String::new();
+        for event in &log.events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        fs::write(&path, lines).with_context(|| format!("无法写入事件日志: {:?}", path))?;
+        log.file_lines = log.events.len();
+        return Ok(());
+    }
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("无法写入事件日志: {:?}", path))?;
+    log.file_lines += 1;
+    Ok(())
+}
+
+/// 文件中最近的事件，损坏的行直接跳过
+fn read(path: &PathBuf) -> Result<VecDeque<Event>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+        Err(e) => return Err(e).with_context(|| format!("无法读取事件日志: {:?}", path)),
+    };
+    let mut events: VecDeque<Event> = content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    while events.len() > MAX_EVENTS {
+        events.pop_front();
+    }
+    Ok(events)
+}
+
+/// 最近一段时间内的事件，从旧到新
+pub fn since(period: Duration) -> Result<Vec<Event>> {
+    let cutoff = (chrono::Local::now().timestamp().max(0) as u64).saturating_sub(period.as_secs());
+    Ok(read(&events_file()?)?
+        .into_iter()
+        .filter(|event| event.timestamp >= cutoff)
+        .collect())
+}
+
+/// 解析 30m、1h、2d 这样的时长
+pub fn parse_since(value: &str) -> Result<Duration> {
+    let value = value.trim().to_ascii_lowercase();
+    let Some(unit) = value.chars().last() else {
+        bail!("格式应为 30m、1h、2d 这样的时长");
+    };
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        _ => bail!("时长需要单位 s、m、h 或 d，例如 1h"),
+    };
+    let number: u64 = value[..value.len() - 1]
+        .parse()
+        .context("格式应为 30m、1h、2d 这样的时长")?;
+    if number == 0 {
+        bail!("时长应大于 0");
+    }
+    Ok(Duration::from_secs(number * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_since_periods() {
+        assert_eq!(parse_since("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_since(" 30M ").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_since("2d").unwrap(), Duration::from_secs(2 * 24 * 3600));
+        assert!(parse_since("0h").is_err());
+        assert!(parse_since("1").is_err());
+        assert!(parse_since("h").is_err());
+        assert!(parse_since("").is_err());
+    }
+}
@@ -0,0 +1,590 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::events::{self, EventKind};
+use crate::health::Probe;
+use crate::subscription::Node;
+
+/// 连续失败多少次后切换节点
+pub const FAILURE_THRESHOLD: u32 = 3;
+/// 节点健康检查间隔
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// 节点失败后的初始退避时间，之后每次失败翻倍
+const BASE_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+/// 最多保留的切换记录
+const HISTORY_LIMIT: usize = 20;
+/// 连接重试次数的统计窗口
+const RETRY_WINDOW: Duration = Duration::from_secs(60);
+/// 自动切换完成后状态里保留"已切换"的时间
+const SWITCHED_SHOWN: Duration = Duration::from_secs(120);
+
+/// 故障切换策略，来自配置文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailoverPolicy {
+    /// 健康检查间隔，固定为 HEALTH_CHECK_INTERVAL；端到端测试把它缩短到毫秒级以便很快触发切换
+    pub check_interval: Duration,
+    /// 两次切换之间的最短间隔，避免在节点间来回切换
+    pub cooldown: Duration,
+    /// 首选节点恢复后是否自动切回
+    pub failback: bool,
+    /// 首选节点需要连续通过多少次健康检查才切回
+    pub failback_checks: u32,
+    /// 节点失败后的初始退避时间
+    pub base_backoff: Duration,
+    /// 每分钟最多为单个连接改用备用节点重试的次数，0 为不重试
+    pub stream_retries_per_minute: u32,
+    /// 最多保留的备用节点数量
+    pub max_backups: usize,
+}
+
+impl FailoverPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            check_interval: HEALTH_CHECK_INTERVAL,
+            cooldown: Duration::from_secs(config.failover_cooldown_secs),
+            failback: config.failback,
+            failback_checks: config.failback_checks.max(1),
+            base_backoff: BASE_BACKOFF,
+            stream_retries_per_minute: config.stream_retries_per_minute,
+            max_backups: config.max_backup_nodes.max(1),
+        }
+    }
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
+
+struct Backoff {
+    failures: u32,
+    until: Instant,
+}
+
+/// 切换节点的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchReason {
+    /// 当前节点连续健康检查失败
+    Failure,
+    /// 首选节点恢复后切回
+    Failback,
+    /// 用户选择或修改配置
+    Manual,
+    /// 空闲时自动重选
+    Reselect,
+    /// 游戏中延迟持续高于报警阈值
+    Latency,
+}
+
+impl SwitchReason {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Failure => "节点连续故障",
+            Self::Failback => "首选节点恢复",
+            Self::Manual => "手动切换",
+            Self::Reselect => "空闲自动重选",
+            Self::Latency => "延迟持续过高",
+        }
+    }
+}
+
+/// 一次节点切换
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchEvent {
+    /// Unix 秒
+    pub timestamp: u64,
+    pub from: Option<String>,
+    pub to: String,
+    pub reason: SwitchReason,
+}
+
+/// 节点最近一次健康检查的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckRecord {
+    /// Unix 秒
+    pub timestamp: u64,
+    pub latency_ms: Option<u32>,
+    pub error: Option<String>,
+}
+
+/// 单个节点的健康状况，供 cf node health 显示
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeHealth {
+    pub name: String,
+    pub consecutive_failures: u32,
+    /// 退避剩余秒数，退避期内不会被选为切换目标
+    pub backoff_secs: Option<u64>,
+    pub last_check: Option<CheckRecord>,
+    pub backup: bool,
+}
+
+/// 故障切换的整体状况
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub current: Option<String>,
+    pub preferred: Option<String>,
+    /// 切换冷却剩余秒数
+    pub cooldown_secs: Option<u64>,
+    pub nodes: Vec<NodeHealth>,
+    /// 最近的切换，从新到旧
+    pub switches: Vec<SwitchEvent>,
+    /// 连接节点失败后改用备用节点重试的次数
+    #[serde(default)]
+    pub stream_retries: u64,
+    /// 备用节点，按切换时的优先顺序排列
+    #[serde(default)]
+    pub backups: Vec<String>,
+}
+
+/// 当前节点故障后的恢复进度：降级 → 探测备用节点 → 已切换
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum FailoverPhase {
+    /// 当前节点健康检查失败，还没达到切换的次数或仍在冷却中
+    Degraded { node: String, failures: u32 },
+    /// 正在同时探测备用节点
+    Probing { node: String, candidates: usize },
+    /// 备用节点都不可用，下次健康检查时重试
+    NoBackup { node: String },
+    /// 已切换到备用节点
+    Switched { from: String, to: String },
+}
+
+/// 故障切换的进度，当前节点健康时为 None
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailoverStatus {
+    #[serde(flatten)]
+    pub phase: FailoverPhase,
+    /// 进入该阶段的 Unix 秒
+    pub since: u64,
+    /// 降级时预计多少秒后切换，没有备用节点时多少秒后重试
+    pub eta_secs: Option<u64>,
+}
+
+impl FailoverStatus {
+    pub fn describe(&self) -> String {
+        let eta = |action: &str| match self.eta_secs {
+            Some(secs) => format!("，约 {} 秒后{}", secs, action),
+            None => String::new(),
+        };
+        match &self.phase {
+            FailoverPhase::Degraded { node, failures } if *failures >= FAILURE_THRESHOLD => {
+                format!("⚠️ {} 连续故障 {} 次，等待切换冷却{}", node, failures, eta("切换备用节点"))
+            }
+            FailoverPhase::Degraded { node, failures } => {
+                format!("⚠️ {} 健康检查失败 {}/{} 次{}", node, failures, FAILURE_THRESHOLD, eta("切换备用节点"))
+            }
+            FailoverPhase::Probing { node, candidates } => {
+                format!("🔍 {} 不可用，正在探测 {} 个备用节点", node, candidates)
+            }
+            FailoverPhase::NoBackup { node } => format!("❌ {} 不可用且没有可用的备用节点{}", node, eta("重试")),
+            FailoverPhase::Switched { from, to } => format!("✅ 已从 {} 切换到 {}", from, to),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 故障切换状态：首选节点、上次切换时间和各节点的退避窗口
+#[derive(Default)]
+pub struct Failover {
+    pub policy: FailoverPolicy,
+    preferred: Option<Node>,
+    last_switch: Option<Instant>,
+    backoff: HashMap<String, Backoff>,
+    preferred_streak: u32,
+    history: VecDeque<SwitchEvent>,
+    checks: HashMap<String, CheckRecord>,
+    /// 最近一分钟内的连接重试时间
+    recent_retries: VecDeque<Instant>,
+    stream_retries: u64,
+    /// 故障切换的当前阶段和进入时间
+    phase: Option<(FailoverPhase, Instant, u64)>,
+}
+
+impl Failover {
+    /// 用户选择的节点，故障恢复后会切回该节点
+    pub fn set_preferred(&mut self, node: &Node) {
+        self.preferred = Some(node.clone());
+        self.preferred_streak = 0;
+        self.backoff.remove(&node.name);
+    }
+
+    pub fn preferred(&self) -> Option<&Node> {
+        self.preferred.as_ref()
+    }
+
+    /// 记录一次失败并延长退避时间，返回退避时长
+    pub fn record_failure(&mut self, name: &str) -> Duration {
+        let entry = self.backoff.entry(name.to_string()).or_insert(Backoff {
+            failures: 0,
+            until: Instant::now(),
+        });
+        entry.failures += 1;
+
+        let backoff = self
+            .policy
+            .base_backoff
+            .saturating_mul(1 << (entry.failures - 1).min(16))
+            .min(MAX_BACKOFF);
+        entry.until = Instant::now() + backoff;
+
+        if self.preferred.as_ref().is_some_and(|node| node.name == name) {
+            self.preferred_streak = 0;
+        }
+        backoff
+    }
+
+    pub fn record_success(&mut self, name: &str) {
+        self.backoff.remove(name);
+        if let Some((FailoverPhase::Degraded { node, .. } | FailoverPhase::NoBackup { node }, _, _)) = &self.phase {
+            if node == name {
+                self.phase = None;
+            }
+        }
+    }
+
+    /// 进入故障切换的某个阶段，降级期间再次失败时保留最初的降级时间
+    pub fn set_phase(&mut self, phase: FailoverPhase) {
+        let started = match (&self.phase, &phase) {
+            (
+                Some((FailoverPhase::Degraded { node: previous, .. }, at, since)),
+                FailoverPhase::Degraded { node, .. },
+            ) if previous == node => Some((*at, *since)),
+            _ => None,
+        };
+        let (at, since) = started.unwrap_or_else(|| (Instant::now(), unix_now()));
+        self.phase = Some((phase, at, since));
+    }
+
+    /// 故障切换的进度，附带预计的切换或重试时间
+    pub fn status(&self) -> Option<FailoverStatus> {
+        let (phase, at, since) = self.phase.as_ref()?;
+        let interval = self.policy.check_interval;
+        let until_next_check = |node: &str| {
+            let checked = self.checks.get(node).map_or(*since, |check| check.timestamp);
+            interval.saturating_sub(Duration::from_secs(unix_now().saturating_sub(checked)))
+        };
+        let eta = match phase {
+            FailoverPhase::Switched { .. } if at.elapsed() >= SWITCHED_SHOWN => return None,
+            FailoverPhase::Degraded { node, failures } => {
+                // 还要再失败几次才切换，达到次数后只需等冷却结束
+                let remaining = FAILURE_THRESHOLD.saturating_sub(*failures);
+                let checks = match remaining {
+                    0 => Duration::ZERO,
+                    n => until_next_check(node) + interval * (n - 1),
+                };
+                Some(checks.max(self.cooldown_remaining().unwrap_or_default()))
+            }
+            FailoverPhase::NoBackup { node } => Some(until_next_check(node)),
+            FailoverPhase::Probing { .. } | FailoverPhase::Switched { .. } => None,
+        };
+        Some(FailoverStatus {
+            phase: phase.clone(),
+            since: *since,
+            eta_secs: eta.map(|eta| eta.as_secs()),
+        })
+    }
+
+    /// 节点是否处于退避期内，退避期内不作为切换目标
+    pub fn in_backoff(&self, name: &str) -> bool {
+        self.backoff
+            .get(name)
+            .is_some_and(|backoff| backoff.until > Instant::now())
+    }
+
+    /// 距离冷却结束的剩余时间，None 表示可以切换
+    pub fn cooldown_remaining(&self) -> Option<Duration> {
+        let elapsed = self.last_switch?.elapsed();
+        self.policy.cooldown.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+
+    /// 记录一次切换并计入使用统计，自动切换之后进入冷却
+    pub fn record_switch(&mut self, from: Option<&str>, to: &str, reason: SwitchReason) {
+        crate::insights::record_switch(reason);
+        if matches!(reason, SwitchReason::Failure | SwitchReason::Failback | SwitchReason::Latency) {
+            self.last_switch = Some(Instant::now());
+        }
+        self.preferred_streak = 0;
+        self.phase = match (reason, from) {
+            (SwitchReason::Failure, Some(from)) => Some((
+                FailoverPhase::Switched {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                },
+                Instant::now(),
+                unix_now(),
+            )),
+            _ => None,
+        };
+        events::record(
+            EventKind::NodeSwitch,
+            format!("{} → {}（{}）", from.unwrap_or("无"), to, reason.display_name()),
+        );
+
+        if self.history.len() >= HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(SwitchEvent {
+            timestamp: unix_now(),
+            from: from.map(str::to_string),
+            to: to.to_string(),
+            reason,
+        });
+    }
+
+    /// 最近的切换，从新到旧
+    pub fn history(&self) -> impl Iterator<Item = &SwitchEvent> {
+        self.history.iter().rev()
+    }
+
+    /// 记录健康探测的结果，延迟取建立连接的耗时，与测速结果可以直接比较
+    pub fn record_check(&mut self, name: &str, result: &Result<Probe>) {
+        self.record(name, result.as_ref().map(|probe| probe.latency).map_err(|e| e.to_string()));
+    }
+
+    fn record(&mut self, name: &str, result: std::result::Result<Duration, String>) {
+        let record = CheckRecord {
+            timestamp: unix_now(),
+            latency_ms: result.as_ref().ok().map(|latency| latency.as_millis() as u32),
+            error: result.err(),
+        };
+        self.checks.insert(name.to_string(), record);
+    }
+
+    pub fn last_check(&self, name: &str) -> Option<&CheckRecord> {
+        self.checks.get(name)
+    }
+
+    /// 把刚测得的节点延迟记为检查结果，之后的探测结果会覆盖它
+    pub fn record_tested(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            if let Some(latency) = node.latency.filter(|&latency| latency != u32::MAX) {
+                self.record(&node.name, Ok(Duration::from_millis(latency as u64)));
+            }
+        }
+    }
+
+    /// 按最近一次检查的延迟排列备用节点，失败或退避中的节点排在最后，只保留前 max_backups 个
+    pub fn rank_backups(&self, mut nodes: Vec<Node>) -> Vec<Node> {
+        nodes.sort_by_key(|node| {
+            let latency = match self.checks.get(&node.name) {
+                Some(check) => check.latency_ms,
+                None => node.latency.filter(|&latency| latency != u32::MAX),
+            };
+            (self.in_backoff(&node.name), latency.unwrap_or(u32::MAX))
+        });
+        nodes.truncate(self.policy.max_backups.max(1));
+        nodes
+    }
+
+    /// 退避剩余时间，不在退避期内时返回 None
+    pub fn backoff_remaining(&self, name: &str) -> Option<Duration> {
+        self.backoff
+            .get(name)
+            .and_then(|backoff| backoff.until.checked_duration_since(Instant::now()))
+            .filter(|d| !d.is_zero())
+    }
+
+    /// 所有检查过或在退避中的节点
+    pub fn known_nodes(&self) -> impl Iterator<Item = &String> {
+        self.checks.keys().chain(self.backoff.keys())
+    }
+
+    /// 消耗一次连接重试的额度，超过每分钟上限时返回 false
+    pub fn take_stream_retry(&mut self) -> bool {
+        let now = Instant::now();
+        while self.recent_retries.front().is_some_and(|at| now.duration_since(*at) >= RETRY_WINDOW) {
+            self.recent_retries.pop_front();
+        }
+        if self.recent_retries.len() >= self.policy.stream_retries_per_minute as usize {
+            return false;
+        }
+        self.recent_retries.push_back(now);
+        self.stream_retries += 1;
+        true
+    }
+
+    /// 累计的连接重试次数
+    pub fn stream_retries(&self) -> u64 {
+        self.stream_retries
+    }
+
+    /// 首选节点通过了一次健康检查，返回是否满足切回条件
+    pub fn preferred_recovered(&mut self) -> bool {
+        self.preferred_streak += 1;
+        self.policy.failback
+            && self.preferred_streak >= self.policy.failback_checks
+            && self.cooldown_remaining().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_stream_retries_per_minute() {
+        let mut failover = Failover::default();
+        failover.policy.stream_retries_per_minute = 2;
+        assert!(failover.take_stream_retry());
+        assert!(failover.take_stream_retry());
+        assert!(!failover.take_stream_retry());
+        assert_eq!(failover.stream_retries(), 2);
+
+        failover.policy.stream_retries_per_minute = 0;
+        failover.recent_retries.clear();
+        assert!(!failover.take_stream_retry());
+    }
+
+    #[test]
+    fn keeps_recent_switches_and_cooldown_for_automatic_ones() {
+        let mut failover = Failover::default();
+        failover.policy.cooldown = Duration::from_secs(60);
+
+        failover.record_switch(Some("a"), "b", SwitchReason::Manual);
+        assert!(failover.cooldown_remaining().is_none());
+
+        for i in 0..HISTORY_LIMIT {
+            failover.record_switch(Some("b"), &format!("n{}", i), SwitchReason::Failure);
+        }
+        assert!(failover.cooldown_remaining().is_some());
+        assert_eq!(failover.history().count(), HISTORY_LIMIT);
+        let latest = failover.history().next().unwrap();
+        assert_eq!(latest.to, format!("n{}", HISTORY_LIMIT - 1));
+        assert_eq!(latest.reason, SwitchReason::Failure);
+
+        failover.record_check("a", &Err(anyhow::anyhow!("探测超时")));
+        failover.record_check("b", &Ok(Probe { latency: Duration::from_millis(42), authenticated: false }));
+        assert_eq!(failover.last_check("a").unwrap().error.as_deref(), Some("探测超时"));
+        assert_eq!(failover.last_check("b").unwrap().latency_ms, Some(42));
+    }
+
+    #[test]
+    fn ranks_backups_by_latest_latency() {
+        let node = |name: &str, latency: u32| Node {
+            latency: Some(latency),
+            ..Node::test(name, "127.0.0.1", 1, "ss")
+        };
+        let mut failover = Failover::default();
+        failover.policy.max_backups = 3;
+        let nodes = vec![node("a", 300), node("b", 100), node("c", 200), node("d", 50), node("e", 400)];
+        failover.record_tested(&nodes);
+
+        // d 刚刚探测失败，c 重新探测后延迟变低
+        failover.record_check("d", &Err(anyhow::anyhow!("探测超时")));
+        failover.record_failure("d");
+        failover.record_check("c", &Ok(Probe { latency: Duration::from_millis(80), authenticated: false }));
+
+        let ranked: Vec<String> = failover.rank_backups(nodes).into_iter().map(|n| n.name).collect();
+        assert_eq!(ranked, ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn tracks_failover_phases_with_eta() {
+        let mut failover = Failover::default();
+        failover.policy.check_interval = Duration::from_secs(10);
+        assert!(failover.status().is_none());
+
+        failover.record_check("a", &Err(anyhow::anyhow!("探测超时")));
+        failover.set_phase(FailoverPhase::Degraded { node: "a".into(), failures: 1 });
+        let degraded = failover.status().unwrap();
+        // 还要再失败两次，下次检查约 10 秒后
+        assert!((19..=20).contains(&degraded.eta_secs.unwrap()));
+
+        failover.set_phase(FailoverPhase::Degraded { node: "a".into(), failures: 2 });
+        assert_eq!(failover.status().unwrap().since, degraded.since);
+
+        // 恢复后回到健康状态
+        failover.record_success("a");
+        assert!(failover.status().is_none());
+
+        failover.set_phase(FailoverPhase::Probing { node: "a".into(), candidates: 2 });
+        assert_eq!(failover.status().unwrap().eta_secs, None);
+        failover.record_switch(Some("a"), "b", SwitchReason::Failure);
+        let switched = failover.status().unwrap();
+        assert_eq!(switched.phase, FailoverPhase::Switched { from: "a".into(), to: "b".into() });
+        assert!(switched.describe().contains("b"));
+
+        failover.record_switch(Some("b"), "c", SwitchReason::Manual);
+        assert!(failover.status().is_none());
+    }
+
+    #[test]
+    fn backs_off_failed_nodes_until_they_recover() {
+        let mut failover = Failover::default();
+        let base = failover.policy.base_backoff;
+        assert!(!failover.in_backoff("a"));
+
+        // 每次失败退避时间翻倍，最长不超过 MAX_BACKOFF
+        assert_eq!(failover.record_failure("a"), base);
+        assert_eq!(failover.record_failure("a"), base * 2);
+        assert_eq!(failover.record_failure("a"), base * 4);
+        assert!(failover.in_backoff("a"));
+        assert!(failover.backoff_remaining("a").is_some_and(|left| left > base * 3 && left <= base * 4));
+        for _ in 0..20 {
+            failover.record_failure("a");
+        }
+        assert_eq!(failover.record_failure("a"), MAX_BACKOFF);
+
+        // 成功一次后清除退避，下次失败重新从初始时间开始
+        failover.record_success("a");
+        assert!(!failover.in_backoff("a"));
+        assert_eq!(failover.backoff_remaining("a"), None);
+        assert_eq!(failover.record_failure("a"), base);
+
+        // 重新选择为首选节点时同样清除退避
+        failover.set_preferred(&Node::test("a", "127.0.0.1", 1, "ss"));
+        assert!(!failover.in_backoff("a"));
+
+        // 退避期已过的节点可以再次作为切换目标
+        failover.record_failure("b");
+        failover.backoff.get_mut("b").unwrap().until = Instant::now() - Duration::from_secs(1);
+        assert!(!failover.in_backoff("b"));
+        assert_eq!(failover.backoff_remaining("b"), None);
+    }
+
+    #[test]
+    fn fails_back_after_consecutive_checks_outside_cooldown() {
+        let mut failover = Failover::default();
+        failover.policy.failback = true;
+        failover.policy.failback_checks = 3;
+        failover.policy.cooldown = Duration::from_secs(60);
+        failover.set_preferred(&Node::test("首选", "127.0.0.1", 1, "ss"));
+
+        assert!(!failover.preferred_recovered());
+        assert!(!failover.preferred_recovered());
+        // 中途失败一次，重新计数
+        failover.record_failure("首选");
+        assert!(!failover.preferred_recovered());
+        assert!(!failover.preferred_recovered());
+        assert!(failover.preferred_recovered());
+
+        // 自动切换后的冷却期内不切回
+        failover.record_switch(Some("首选"), "备用", SwitchReason::Failure);
+        for _ in 0..3 {
+            assert!(!failover.preferred_recovered());
+        }
+        assert!(failover.cooldown_remaining().is_some_and(|left| left <= Duration::from_secs(60)));
+
+        // 冷却结束后已经累计的检查次数足够，立即切回
+        failover.last_switch = Some(Instant::now() - Duration::from_secs(61));
+        assert_eq!(failover.cooldown_remaining(), None);
+        assert!(failover.preferred_recovered());
+
+        // 关闭自动切回时不会切回
+        failover.policy.failback = false;
+        assert!(!failover.preferred_recovered());
+    }
+}
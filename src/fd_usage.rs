@@ -0,0 +1,174 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::tasks::TaskSet;
+
+/// 检查文件描述符用量的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// 用量超过上限的该比例时提前警告
+const WARN_PERCENT: u64 = 80;
+/// 用量回落到该比例以下后才会再次警告
+const CLEAR_PERCENT: u64 = 70;
+/// 用量超过该比例时，新建 UDP 会话前先释放最久未用的会话
+const SHED_PERCENT: u64 = 90;
+/// 提高软上限时最多提高到的数量，避免硬上限为无限时占用过多内核资源
+const MAX_SOFT_LIMIT: u64 = 65536;
+
+static NEAR_LIMIT: AtomicBool = AtomicBool::new(false);
+static SHED_SESSIONS: AtomicU64 = AtomicU64::new(0);
+
+/// 进程打开的文件描述符（包括 socket）数量和上限
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FdUsage {
+    pub open: u64,
+    pub limit: u64,
+    /// 因接近上限而释放的空闲 UDP 会话
+    #[serde(default)]
+    pub shed_sessions: u64,
+}
+
+impl FdUsage {
+    pub fn percent(&self) -> u64 {
+        self.open * 100 / self.limit.max(1)
+    }
+}
+
+/// 当前用量，不支持的平台返回 None
+pub fn usage() -> Option<FdUsage> {
+    Some(FdUsage {
+        open: open_count()?,
+        limit: soft_limit()?,
+        shed_sessions: SHED_SESSIONS.load(Ordering::Relaxed),
+    })
+}
+
+#[cfg(unix)]
+fn open_count() -> Option<u64> {
+    let dir = if cfg!(target_os = "linux") { "/proc/self/fd" } else { "/dev/fd" };
+    // 读取目录本身也会占用一个描述符
+    std::fs::read_dir(dir).ok().map(|entries| entries.count().saturating_sub(1) as u64)
+}
+
+#[cfg(not(unix))]
+fn open_count() -> Option<u64> {
+    None
+}
+
+// rlim_t 在部分平台上不是 u64
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn soft_limit() -> Option<u64> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: 只读取本进程的资源上限
+    (unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0).then_some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn soft_limit() -> Option<u64> {
+    None
+}
+
+/// 把软上限提高到硬上限，macOS 等系统默认的 256 个很容易被大量连接耗尽
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn raise_limit() {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: 读取并修改本进程的资源上限，新的软上限不超过硬上限
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) != 0 {
+            return;
+        }
+        let target = (limit.rlim_max as u64).min(MAX_SOFT_LIMIT);
+        if limit.rlim_cur as u64 >= target {
+            return;
+        }
+        let old = limit.rlim_cur;
+        limit.rlim_cur = target as libc::rlim_t;
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) == 0 {
+            info!("文件描述符上限从 {} 提高到 {}", old, target);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_limit() {}
+
+/// 用量是否已接近上限，此时新建 UDP 会话前先释放空闲会话
+pub fn near_limit() -> bool {
+    NEAR_LIMIT.load(Ordering::Relaxed)
+}
+
+/// 打开 socket 失败是否因为描述符耗尽
+pub fn is_exhausted(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(is_exhausted_io)
+}
+
+/// 系统调用失败是否因为描述符或内核缓冲区耗尽，释放一些连接后可以重试
+pub fn is_exhausted_io(error: &std::io::Error) -> bool {
+    is_exhausted_code(error.raw_os_error())
+}
+
+#[cfg(unix)]
+fn is_exhausted_code(code: Option<i32>) -> bool {
+    matches!(code, Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS))
+}
+
+#[cfg(not(unix))]
+fn is_exhausted_code(_code: Option<i32>) -> bool {
+    false
+}
+
+pub fn record_shed() {
+    SHED_SESSIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 定期检查用量，接近上限时提前警告
+pub fn spawn(tasks: &TaskSet) {
+    tasks.spawn("文件描述符检查", async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut warned = false;
+        loop {
+            interval.tick().await;
+            let Some(usage) = usage() else {
+                return;
+            };
+            let percent = usage.percent();
+            NEAR_LIMIT.store(percent >= SHED_PERCENT, Ordering::Relaxed);
+            if percent >= WARN_PERCENT && !warned {
+                warn!(
+                    "已打开 {} 个文件描述符，接近上限 {}，请提高 ulimit -n，否则新的游戏连接可能失败",
+                    usage.open, usage.limit
+                );
+                warned = true;
+            } else if percent < CLEAR_PERCENT {
+                warned = false;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_usage_and_exhaustion() {
+        assert_eq!(FdUsage { open: 900, limit: 1000, shed_sessions: 0 }.percent(), 90);
+        assert_eq!(FdUsage { open: 1, limit: 0, shed_sessions: 0 }.percent(), 100);
+
+        #[cfg(unix)]
+        {
+            let usage = usage().unwrap();
+            assert!(usage.open > 0 && usage.open <= usage.limit);
+            let error = anyhow::Error::from(std::io::Error::from_raw_os_error(libc::EMFILE)).context("无法创建 UDP socket");
+            assert!(is_exhausted(&error));
+            assert!(is_exhausted_io(&std::io::Error::from_raw_os_error(libc::ENOBUFS)));
+        }
+        assert!(!is_exhausted(&std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into()));
+    }
+}
@@ -8,9 +8,30 @@ pub struct GameProcess {
     pub exe_path: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// 端口使用的传输协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl PortProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "TCP",
+            Self::Udp => "UDP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SupportedGame {
+    #[value(alias = "dst")]
+    #[serde(alias = "dst")]
     DontStarveTogether,
+    #[value(alias = "cs")]
+    #[serde(alias = "cs")]
     CounterStrike,
     Dota2,
     LeagueOfLegends,
@@ -62,6 +83,14 @@ impl SupportedGame {
         }
     }
 
+    /// 进程名或可执行文件路径是否属于该游戏
+    pub fn matches_process(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.process_names()
+            .iter()
+            .any(|target| name.contains(&target.to_lowercase()))
+    }
+
     pub fn display_name(&self) -> &'static str {
         match self {
             Self::DontStarveTogether => "饥荒联机版",
@@ -88,16 +117,39 @@ impl SupportedGame {
         }
     }
 
-    pub fn should_optimize(&self) -> bool {
+    /// 在本机开服时需要对外开放的端口，不支持自建服务器的游戏返回空
+    pub fn server_ports(&self) -> Vec<(PortProtocol, u16)> {
+        use PortProtocol::{Tcp, Udp};
         match self {
-            Self::DontStarveTogether => true,
-            Self::CounterStrike => true,
-            Self::Dota2 => true,
-            Self::LeagueOfLegends => true,
-            Self::Valorant => true,
-            Self::Minecraft => true,
-            Self::ApexLegends => true,
-            Self::Overwatch => true,
+            // 主世界、洞穴和 Steam 认证端口
+            Self::DontStarveTogether => vec![(Udp, 10999), (Udp, 11000), (Udp, 12346), (Udp, 12347)],
+            Self::CounterStrike | Self::Dota2 => vec![(Udp, 27015), (Tcp, 27015)],
+            Self::Minecraft => vec![(Tcp, 25565)],
+            Self::LeagueOfLegends | Self::Valorant | Self::ApexLegends | Self::Overwatch => Vec::new(),
+        }
+    }
+
+    /// 空闲的 UDP 会话多久发送一次保活包（秒），0 为不需要。
+    /// 长时间只靠服务端推送数据的游戏，节点或 NAT 上的映射可能在比赛中途过期
+    pub fn udp_keepalive_secs(&self) -> u64 {
+        match self {
+            // 饥荒在等待其他玩家、暂停时客户端长时间不发包
+            Self::DontStarveTogether => 15,
+            Self::CounterStrike | Self::Dota2 | Self::ApexLegends | Self::Overwatch => 20,
+            Self::LeagueOfLegends | Self::Valorant => 20,
+            // 我的世界 Java 版使用 TCP
+            Self::Minecraft => 0,
+        }
+    }
+
+    /// 内置的会话空闲超时（秒），超过后关闭该游戏的 TCP 连接和 UDP 会话；
+    /// 等待其他玩家、暂停或在大厅挂机时可能长时间没有数据
+    pub fn idle_timeout_secs(&self) -> u64 {
+        match self {
+            Self::DontStarveTogether => 900,
+            Self::Minecraft => 600,
+            Self::CounterStrike | Self::Dota2 | Self::Valorant | Self::LeagueOfLegends => 300,
+            Self::ApexLegends | Self::Overwatch => 300,
         }
     }
 }
@@ -110,7 +162,8 @@ pub struct GameDetector {
 impl GameDetector {
     pub fn new() -> Self {
         Self {
-            system: System::new_all(),
+            // 只用到进程信息，检测时再刷新，避免加载磁盘、网卡等无关数据
+            system: System::new(),
             supported_games: vec![
                 SupportedGame::DontStarveTogether,
                 SupportedGame::CounterStrike,
@@ -143,39 +196,19 @@ impl GameDetector {
     }
 
     fn find_game_process(&self, game: &SupportedGame) -> Result<Option<GameProcess>> {
-        let process_names = game.process_names();
-
         for (pid, process) in self.system.processes() {
             let process_name = process.name();
-            let exe_path = Some(process.exe().to_string_lossy().to_string());
-
-            for &target_name in &process_names {
-                if process_name.to_lowercase().contains(&target_name.to_lowercase()) {
-                    return Ok(Some(GameProcess {
-                        name: process_name.to_string(),
-                        pid: pid.as_u32(),
-                        exe_path,
-                    }));
-                }
-            }
-
-            if let Some(path) = &exe_path {
-                for &target_name in &process_names {
-                    if path.to_lowercase().contains(&target_name.to_lowercase()) {
-                        return Ok(Some(GameProcess {
-                            name: process_name.to_string(),
-                            pid: pid.as_u32(),
-                            exe_path,
-                        }));
-                    }
-                }
+            let exe_path = process.exe().to_string_lossy().to_string();
+
+            if game.matches_process(process_name) || game.matches_process(&exe_path) {
+                return Ok(Some(GameProcess {
+                    name: process_name.to_string(),
+                    pid: pid.as_u32(),
+                    exe_path: Some(exe_path),
+                }));
             }
         }
 
         Ok(None)
     }
-
-    pub fn is_game_running(&mut self, game: &SupportedGame) -> Result<bool> {
-        Ok(self.find_game_process(game)?.is_some())
-    }
 }
\ No newline at end of file
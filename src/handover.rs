@@ -0,0 +1,365 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::proxy::ProxyServer;
+
+/// 接管后旧实例等待已有 TCP 连接结束的最长时间
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// 交接的 UDP 会话
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub client: SocketAddr,
+    pub node: String,
+    pub remote: SocketAddr,
+}
+
+/// 从旧实例接管的监听 socket 和 UDP 会话
+pub struct Inherited {
+    /// 旧实例的 PID
+    pub pid: u32,
+    pub tcp: std::net::TcpListener,
+    pub udp: std::net::UdpSocket,
+    pub sessions: Vec<(SessionInfo, std::net::UdpSocket)>,
+}
+
+/// 接管后旧实例停止接受新连接，等待已有的 TCP 连接结束，Ctrl+C 可提前退出
+pub async fn drain(proxy: &ProxyServer) {
+    let deadline = tokio::time::Instant::now() + DRAIN_TIMEOUT;
+    loop {
+        let (_, _, connections) = proxy.traffic().snapshot();
+        if connections == 0 {
+            return;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {
+                log::warn!("等待连接结束超时，仍有 {} 个 TCP 连接，直接退出", connections);
+                return;
+            }
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = tokio::signal::ctrl_c() => return,
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{available, cleanup, request_takeover, wait_for_takeover, Exported, MAX_SESSIONS};
+
+/// 其他平台不支持传递 socket，新实例需要等旧实例退出后再启动
+#[cfg(not(unix))]
+pub fn request_takeover(_listen: SocketAddr) -> anyhow::Result<Option<Inherited>> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub async fn wait_for_takeover(_proxy: std::sync::Arc<ProxyServer>) -> anyhow::Result<u32> {
+    std::future::pending().await
+}
+
+#[cfg(not(unix))]
+pub fn cleanup() {}
+
+#[cfg(not(unix))]
+pub fn available() -> bool {
+    false
+}
+
+/// 通过 Unix socket 的 SCM_RIGHTS 把监听 socket 和 UDP 会话交给新实例
+#[cfg(unix)]
+mod unix {
+    use anyhow::{anyhow, bail, Context, Result};
+    use log::{info, warn};
+    use serde::{Deserialize, Serialize};
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Inherited, SessionInfo};
+    use crate::config::Config;
+    use crate::proxy::ProxyServer;
+
+    const HANDOVER_FILE: &str = "handover.sock";
+    const IO_TIMEOUT: Duration = Duration::from_secs(5);
+    /// 一次最多交接的 UDP 会话，受单条消息可携带的 fd 数量限制
+    pub const MAX_SESSIONS: usize = 200;
+
+    /// 旧实例导出的 socket 副本
+    pub struct Exported {
+        pub tcp: OwnedFd,
+        pub udp: OwnedFd,
+        pub sessions: Vec<(SessionInfo, OwnedFd)>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TakeoverRequest {
+        pid: u32,
+        listen: SocketAddr,
+    }
+
+    #[derive(Default, Serialize, Deserialize)]
+    struct TakeoverReply {
+        pid: u32,
+        #[serde(default)]
+        error: Option<String>,
+        #[serde(default)]
+        sessions: Vec<SessionInfo>,
+    }
+
+    fn socket_file() -> Result<PathBuf> {
+        Ok(Config::cache_dir()?.join(HANDOVER_FILE))
+    }
+
+    /// 有运行中的实例可以交接
+    pub fn available() -> bool {
+        socket_file().is_ok_and(|path| path.exists())
+    }
+
+    /// 正常退出时删除接管 socket
+    pub fn cleanup() {
+        if let Ok(path) = socket_file() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// 新实例：请求运行中的实例交出监听 socket，没有运行中的实例时返回 None
+    pub fn request_takeover(listen: SocketAddr) -> Result<Option<Inherited>> {
+        let path = socket_file()?;
+        let stream = match UnixStream::connect(&path) {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("无法连接 {:?}", path)),
+        };
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut request = serde_json::to_string(&TakeoverRequest {
+            pid: std::process::id(),
+            listen,
+        })?;
+        request.push('\n');
+        (&stream).write_all(request.as_bytes())?;
+
+        let (payload, fds) = recv_with_fds(&stream, MAX_SESSIONS + 2).context("没有收到旧实例的回复")?;
+        let reply: TakeoverReply = serde_json::from_slice(&payload)?;
+        if let Some(error) = reply.error {
+            bail!(error);
+        }
+        if fds.len() != reply.sessions.len() + 2 {
+            bail!("收到 {} 个 socket，应为 {} 个", fds.len(), reply.sessions.len() + 2);
+        }
+
+        let mut fds = fds.into_iter();
+        let tcp = std::net::TcpListener::from(fds.next().expect("已检查数量"));
+        let udp = std::net::UdpSocket::from(fds.next().expect("已检查数量"));
+        let sessions = reply
+            .sessions
+            .into_iter()
+            .zip(fds.map(std::net::UdpSocket::from))
+            .collect();
+        Ok(Some(Inherited {
+            pid: reply.pid,
+            tcp,
+            udp,
+            sessions,
+        }))
+    }
+
+    /// 旧实例：等待新实例请求接管，交接完成后返回新实例的 PID
+    pub async fn wait_for_takeover(proxy: Arc<ProxyServer>) -> Result<u32> {
+        let path = socket_file()?;
+        // 上次异常退出可能留下 socket 文件
+        let _ = fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).with_context(|| format!("无法创建接管 socket: {:?}", path))?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            match serve_takeover(&proxy, stream).await {
+                Ok(Some(pid)) => return Ok(pid),
+                Ok(None) => {}
+                Err(e) => warn!("处理接管请求失败: {:#}", e),
+            }
+        }
+    }
+
+    async fn serve_takeover(proxy: &ProxyServer, stream: tokio::net::UnixStream) -> Result<Option<u32>> {
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let (stream, request) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line)?;
+            let request: TakeoverRequest = serde_json::from_str(&line).context("无效的接管请求")?;
+            Ok((stream, request))
+        })
+        .await??;
+
+        let listen = proxy.listen_addr();
+        let exported = if request.listen != listen {
+            Err(anyhow!("运行中的实例监听 {}，与新配置的 {} 不同，请先停止旧实例", listen, request.listen))
+        } else {
+            proxy.export_for_handover().await
+        };
+        let exported = match exported {
+            Ok(exported) => exported,
+            Err(e) => {
+                info!("拒绝接管请求 (PID {}): {}", request.pid, e);
+                let reply = TakeoverReply {
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                };
+                let payload = serde_json::to_vec(&reply)?;
+                tokio::task::spawn_blocking(move || send_with_fds(&stream, &payload, &[])).await??;
+                return Ok(None);
+            }
+        };
+
+        let mut fds = vec![exported.tcp.as_raw_fd(), exported.udp.as_raw_fd()];
+        fds.extend(exported.sessions.iter().map(|(_, fd)| fd.as_raw_fd()));
+        let reply = TakeoverReply {
+            pid: std::process::id(),
+            error: None,
+            sessions: exported.sessions.iter().map(|(info, _)| info.clone()).collect(),
+        };
+        let payload = serde_json::to_vec(&reply)?;
+        // fd 在发送完成前必须保持打开
+        tokio::task::spawn_blocking(move || {
+            let _exported = exported;
+            send_with_fds(&stream, &payload, &fds)
+        })
+        .await??;
+        Ok(Some(request.pid))
+    }
+
+    /// 发送 4 字节长度前缀的数据，fd 附在第一段数据上
+    fn send_with_fds(stream: &UnixStream, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let fds_len = mem::size_of_val(fds) as libc::c_uint;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len) } as usize];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = control.len() as _;
+            // SAFETY: control 的大小按 CMSG_SPACE 分配，足够容纳一个携带 fds 的 cmsg
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+                std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+            }
+        }
+
+        let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // 剩余的数据不再携带 fd
+        let mut stream = stream;
+        stream.write_all(&data[sent as usize..])
+    }
+
+    fn recv_with_fds(stream: &UnixStream, max_fds: usize) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let space = unsafe { libc::CMSG_SPACE((max_fds * mem::size_of::<RawFd>()) as libc::c_uint) } as usize;
+        let mut control = vec![0u8; space];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        #[cfg(target_os = "linux")]
+        let flags = libc::MSG_CMSG_CLOEXEC;
+        #[cfg(not(target_os = "linux"))]
+        let flags = 0;
+        let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, flags) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut fds = Vec::new();
+        // SAFETY: 遍历内核填写的 cmsg，SCM_RIGHTS 的数据是 fd 数组
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..count {
+                        fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(data.add(i))));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::other("携带的 socket 过多"));
+        }
+
+        buf.truncate(received as usize);
+        while buf.len() < 4 {
+            read_more(stream, &mut buf)?;
+        }
+        let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        while buf.len() < 4 + len {
+            read_more(stream, &mut buf)?;
+        }
+        buf.drain(..4);
+        buf.truncate(len);
+        Ok((buf, fds))
+    }
+
+    fn read_more(mut stream: &UnixStream, buf: &mut Vec<u8>) -> io::Result<()> {
+        let mut chunk = [0u8; 16 * 1024];
+        match stream.read(&mut chunk)? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn passes_sockets_with_payload() {
+            let (left, right) = UnixStream::pair().unwrap();
+            let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = udp.local_addr().unwrap();
+            let payload = vec![b'x'; 100_000];
+
+            let sender = std::thread::spawn(move || send_with_fds(&left, &payload, &[udp.as_raw_fd()]));
+            let (received, fds) = recv_with_fds(&right, 4).unwrap();
+            sender.join().unwrap().unwrap();
+
+            assert_eq!(received.len(), 100_000);
+            assert_eq!(fds.len(), 1);
+            let udp = std::net::UdpSocket::from(fds.into_iter().next().unwrap());
+            assert_eq!(udp.local_addr().unwrap(), addr);
+        }
+    }
+}
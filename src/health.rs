@@ -0,0 +1,526 @@
+use anyhow::{anyhow, Context, Result};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+use crate::buffer_pool::{BufferPool, UDP_BUFFER_SIZE};
+use crate::dns;
+use crate::http_proxy;
+use crate::node_overrides;
+use crate::obfs::{self, ObfsReceiver, ObfsSender};
+use crate::outbound;
+use crate::simulate::{self, Fate};
+use crate::socks5;
+use crate::subscription::Node;
+use crate::timeouts;
+
+/// QUIC Initial 包的最小长度
+const QUIC_PROBE_SIZE: usize = 1200;
+/// 保留的 QUIC 版本号，服务端必须回复版本协商包
+const QUIC_PROBE_VERSION: u32 = 0x1a2a_3a4a;
+/// UDP 测试发送的 DNS 查询域名
+const UDP_PROBE_DOMAIN: &str = "www.example.com";
+/// UDP 测试每次发送后等待回复的时间（毫秒），没有回复时重发
+const UDP_PROBE_WAITS: [u64; 3] = [500, 1000, 2000];
+
+/// 按节点协议选择的探测方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeMethod {
+    /// 建立 TCP 连接并确认服务端没有立即断开 (ss、vmess、vless)，只说明端口上有服务在线，
+    /// 不验证密码或 uuid，后端已经失效但端口仍被转发的节点同样通过
+    TcpAlive,
+    /// 完成 TLS 握手 (trojan)，只说明 TLS 前端在线，不发送 trojan 认证
+    TlsAlive,
+    /// 发送 QUIC 探测包并等待版本协商回复 (hysteria、tuic)
+    Quic,
+    /// 完成 SOCKS5 方法协商和认证 (socks5)
+    Socks5,
+    /// 收到 HTTP 代理的响应且认证通过 (http)
+    Http,
+}
+
+impl ProbeMethod {
+    pub fn for_node(node: &Node) -> Self {
+        match node.protocol.to_ascii_lowercase().as_str() {
+            "trojan" => Self::TlsAlive,
+            "hysteria" | "hysteria2" | "hy2" | "tuic" => Self::Quic,
+            "socks5" => Self::Socks5,
+            "http" => Self::Http,
+            _ => Self::TcpAlive,
+        }
+    }
+
+    /// 探测是否验证了节点的用户名和密码
+    pub fn authenticates(&self) -> bool {
+        matches!(self, Self::Socks5 | Self::Http)
+    }
+}
+
+/// 一次探测的结果
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    /// 建立连接的耗时（QUIC 节点为探测包的往返时间），不含握手和等待服务端断开的时间，作为节点延迟
+    pub latency: Duration,
+    /// 探测验证了认证信息；为 false 时只确认了节点在线
+    pub authenticated: bool,
+}
+
+/// 按节点协议探测可用性
+///
+/// 仅连通 TCP 端口并不代表节点服务正常，例如后端进程已退出但端口仍被转发；
+/// 而 QUIC 节点根本不监听 TCP。socks5 和 http 节点完成认证，其他协议只能确认服务在线。
+pub async fn probe(node: &Node, timeout: Duration) -> Result<Probe> {
+    let method = ProbeMethod::for_node(node);
+
+    let latency = tokio::time::timeout(timeout, async {
+        // 模拟的网络状况同样作用于探测，用来测试故障切换
+        let delay = match simulate::round_trip() {
+            Some(Fate::Drop) => std::future::pending().await,
+            Some(Fate::Delay(delay)) => {
+                tokio::time::sleep(delay).await;
+                delay
+            }
+            None => Duration::ZERO,
+        };
+        let connect = match method {
+            ProbeMethod::TcpAlive => probe_tcp_alive(node).await,
+            ProbeMethod::TlsAlive => probe_tls_alive(node).await,
+            ProbeMethod::Quic => probe_quic(node).await,
+            ProbeMethod::Socks5 => probe_socks5(node).await,
+            ProbeMethod::Http => probe_http(node).await,
+        }?;
+        Ok::<_, anyhow::Error>(delay + connect)
+    })
+    .await
+    .map_err(|_| anyhow!("探测超时"))??;
+
+    Ok(Probe { latency, authenticated: method.authenticates() })
+}
+
+/// 连接节点，返回连接和建立连接的耗时
+async fn connect_timed(node: &Node) -> Result<(tokio::net::TcpStream, Duration)> {
+    let start = Instant::now();
+    let stream = dns::connect_node(node).await.context("TCP 连接失败")?;
+    Ok((stream, start.elapsed()))
+}
+
+/// 测量到节点的往返延迟：TCP 节点为建立连接的耗时，QUIC 节点为探测包的往返时间，
+/// 不像 probe 那样等待服务端是否断开，适合频繁测量
+pub async fn rtt(node: &Node, timeout: Duration) -> Result<Duration> {
+    if ProbeMethod::for_node(node) == ProbeMethod::Quic {
+        return probe(node, timeout).await.map(|probe| probe.latency);
+    }
+
+    let start = Instant::now();
+    tokio::time::timeout(timeout, async {
+        match simulate::round_trip() {
+            Some(Fate::Drop) => std::future::pending().await,
+            Some(Fate::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => {}
+        }
+        dns::connect_node(node).await.context("TCP 连接失败")
+    })
+    .await
+    .map_err(|_| anyhow!("探测超时"))??;
+
+    Ok(start.elapsed())
+}
+
+/// 只确认端口上的服务没有立即断开连接，不验证节点协议
+async fn probe_tcp_alive(node: &Node) -> Result<Duration> {
+    let (mut stream, connect) = connect_timed(node).await?;
+
+    // 代理协议由客户端先发送数据，正常的服务端会保持连接等待；
+    // 立即被关闭或重置说明后端服务不可用
+    let mut buf = [0u8; 1];
+    match tokio::time::timeout(timeouts::current().probe_settle(), stream.read(&mut buf)).await {
+        Err(_) => Ok(connect),
+        Ok(Ok(0)) => Err(anyhow!("连接被服务端关闭")),
+        Ok(Ok(_)) => Ok(connect),
+        Ok(Err(e)) => Err(anyhow!("连接被重置: {}", e)),
+    }
+}
+
+/// 只确认 TLS 前端完成握手，不发送 trojan 认证
+async fn probe_tls_alive(node: &Node) -> Result<Duration> {
+    let (stream, connect) = connect_timed(node).await?;
+
+    // 只关心服务是否在线，自签证书的节点同样视为可用
+    let connector = tokio_native_tls::native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .context("无法创建 TLS 连接器")?;
+    tokio_native_tls::TlsConnector::from(connector)
+        .connect(&node_overrides::server_name(node), stream)
+        .await
+        .context("TLS 握手失败")?;
+
+    Ok(connect)
+}
+
+/// SOCKS5 服务端会主动回复方法协商，认证失败同样视为不可用
+async fn probe_socks5(node: &Node) -> Result<Duration> {
+    let (mut stream, connect) = connect_timed(node).await?;
+    socks5::negotiate(&mut stream, node).await?;
+    Ok(connect)
+}
+
+async fn probe_http(node: &Node) -> Result<Duration> {
+    let (mut stream, connect) = connect_timed(node).await?;
+    http_proxy::probe(&mut stream, node).await?;
+    Ok(connect)
+}
+
+async fn probe_quic(node: &Node) -> Result<Duration> {
+    let addr = *dns::resolve(&node.server, node.port)
+        .await?
+        .first()
+        .context("没有解析结果")?;
+    let socket = outbound::udp_socket(addr).await?;
+    let start = Instant::now();
+    socket.send(&quic_probe_packet()).await?;
+
+    let mut buf = [0u8; 1500];
+    let size = socket.recv(&mut buf).await.context("没有收到 QUIC 回复")?;
+
+    // 版本协商包为长包头且版本字段为 0
+    if size >= 5 && buf[0] & 0x80 != 0 && buf[1..5] == [0, 0, 0, 0] {
+        Ok(start.elapsed())
+    } else {
+        Err(anyhow!("收到的不是 QUIC 版本协商包"))
+    }
+}
+
+/// 构造一个使用保留版本号的 QUIC 长包头数据包
+fn quic_probe_packet() -> Vec<u8> {
+    let mut packet = Vec::with_capacity(QUIC_PROBE_SIZE);
+    packet.push(0xc0);
+    packet.extend_from_slice(&QUIC_PROBE_VERSION.to_be_bytes());
+
+    // 连接 ID 只需要不重复，用时间戳填充即可
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    for _ in 0..2 {
+        packet.push(8);
+        packet.extend_from_slice(&nanos.to_be_bytes());
+    }
+
+    packet.resize(QUIC_PROBE_SIZE, 0);
+    packet
+}
+
+/// 握手的各个阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Dns,
+    Tcp,
+    Tls,
+    /// QUIC 节点的探测包往返
+    Quic,
+}
+
+impl Stage {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Dns => "DNS 解析",
+            Self::Tcp => "TCP 连接",
+            Self::Tls => "TLS 握手",
+            Self::Quic => "QUIC 往返",
+        }
+    }
+}
+
+/// 连接节点各阶段的耗时，失败时记录失败的阶段
+#[derive(Debug, Default)]
+pub struct Timing {
+    /// 实际连接的地址
+    pub addr: Option<SocketAddr>,
+    pub stages: Vec<(Stage, Duration)>,
+    pub failed: Option<(Stage, String)>,
+}
+
+impl Timing {
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+
+    fn get(&self, stage: Stage) -> Option<Duration> {
+        self.stages.iter().find(|(s, _)| *s == stage).map(|(_, elapsed)| *elapsed)
+    }
+
+    /// 根据各阶段的比例判断慢在网络还是节点服务端
+    pub fn diagnose(&self) -> Vec<&'static str> {
+        let mut notes = Vec::new();
+        if self.get(Stage::Dns).is_some_and(|dns| dns > SLOW_DNS) {
+            notes.push("DNS 解析较慢，可以设置 dns_resolver: doh，解析结果会缓存，不影响游戏中的连接");
+        }
+        let tcp = self.get(Stage::Tcp);
+        if tcp.is_some_and(|tcp| tcp > SLOW_RTT) {
+            notes.push("TCP 连接（一次网络往返）就很慢，问题在本地到节点的线路，可以换其他地区的节点");
+        }
+        // TLS 握手通常只需要一到两次往返，明显更慢说明节点服务端处理慢或负载高
+        if let (Some(tcp), Some(tls)) = (tcp, self.get(Stage::Tls)) {
+            if tls > tcp * SLOW_TLS_RATIO && tls > SLOW_TLS_MIN {
+                notes.push("TLS 握手远慢于网络往返，问题多半在节点服务端，可以反馈给服务商");
+            }
+        }
+        notes
+    }
+}
+
+/// 超过该耗时的 DNS 解析视为较慢
+const SLOW_DNS: Duration = Duration::from_millis(200);
+/// 超过该耗时的网络往返视为较慢
+const SLOW_RTT: Duration = Duration::from_millis(250);
+/// TLS 握手超过 TCP 连接耗时的几倍视为服务端慢
+const SLOW_TLS_RATIO: u32 = 4;
+/// 低于该耗时的 TLS 握手不提示
+const SLOW_TLS_MIN: Duration = Duration::from_millis(100);
+
+/// 分别测量解析、TCP 连接、TLS 握手（trojan）或 QUIC 往返（hysteria、tuic）的耗时
+pub async fn timing(node: &Node, timeout: Duration) -> Timing {
+    let mut timing = Timing::default();
+    if let Err((stage, e)) = measure_stages(node, timeout, &mut timing).await {
+        timing.failed = Some((stage, format!("{:#}", e)));
+    }
+    timing
+}
+
+async fn measure_stages(node: &Node, timeout: Duration, timing: &mut Timing) -> std::result::Result<(), (Stage, anyhow::Error)> {
+    async fn timed<T>(
+        stage: Stage,
+        timeout: Duration,
+        timing: &mut Timing,
+        future: impl std::future::Future<Output = Result<T>>,
+    ) -> std::result::Result<T, (Stage, anyhow::Error)> {
+        let start = Instant::now();
+        let value = tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| (stage, anyhow!("超时")))?
+            .map_err(|e| (stage, e))?;
+        timing.stages.push((stage, start.elapsed()));
+        Ok(value)
+    }
+
+    let addrs = timed(Stage::Dns, timeout, timing, async {
+        dns::resolve_fresh(&node.server, node.port).await.context("解析失败")
+    })
+    .await?;
+    let addr = *addrs.first().ok_or((Stage::Dns, anyhow!("没有解析结果")))?;
+    timing.addr = Some(addr);
+
+    let method = ProbeMethod::for_node(node);
+    if method == ProbeMethod::Quic {
+        return timed(Stage::Quic, timeout, timing, async {
+            let socket = outbound::udp_socket(addr).await?;
+            socket.send(&quic_probe_packet()).await?;
+            let mut buf = [0u8; 1500];
+            socket.recv(&mut buf).await.context("没有收到 QUIC 回复")?;
+            Ok(())
+        })
+        .await;
+    }
+
+    let stream = timed(Stage::Tcp, timeout, timing, async {
+        let socket = outbound::tcp_socket(addr)?;
+        Ok(socket.connect(addr).await?)
+    })
+    .await?;
+
+    if method == ProbeMethod::TlsAlive {
+        timed(Stage::Tls, timeout, timing, async {
+            let connector = tokio_native_tls::native_tls::TlsConnector::builder()
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true)
+                .build()
+                .context("无法创建 TLS 连接器")?;
+            tokio_native_tls::TlsConnector::from(connector)
+                .connect(&node_overrides::server_name(node), stream)
+                .await
+                .context("TLS 握手失败")
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// UDP 测试收到的回复
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpReply {
+    /// 对探测包的 DNS 响应
+    Dns,
+    /// 原样返回的探测包
+    Echo,
+    /// 其他数据，同样说明 UDP 被转发
+    Other,
+}
+
+/// UDP 测试结果
+pub struct UdpProbe {
+    pub reply: UdpReply,
+    pub size: usize,
+    pub rtt: Duration,
+    /// 第几次发送后收到回复
+    pub attempt: usize,
+}
+
+/// UDP 测试总共等待回复的时间
+pub fn udp_probe_timeout() -> Duration {
+    Duration::from_millis(UDP_PROBE_WAITS.iter().sum())
+}
+
+/// 与代理转发 UDP 相同的路径，启用混淆的节点经由混淆通道
+enum UdpChannel {
+    Direct(tokio::net::UdpSocket),
+    Obfuscated(ObfsSender, ObfsReceiver),
+}
+
+impl UdpChannel {
+    async fn send(&self, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Direct(socket) => {
+                socket.send(data).await?;
+            }
+            Self::Obfuscated(sender, _) => {
+                if !sender.send(data) {
+                    return Err(anyhow!("混淆通道已关闭"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Self::Direct(socket) => {
+                let mut buf = vec![0u8; 65535];
+                let size = socket.recv(&mut buf).await?;
+                buf.truncate(size);
+                Ok(buf)
+            }
+            Self::Obfuscated(_, receiver) => Ok(receiver.recv(&BufferPool::new(UDP_BUFFER_SIZE)).await?.to_vec()),
+        }
+    }
+}
+
+/// 经由节点的 UDP 转发发送 DNS 查询，收到任何回复都说明节点转发了 UDP；
+/// 所有重试都没有回复时返回 None
+pub async fn probe_udp(node: &Node) -> Result<Option<UdpProbe>> {
+    let mut channel = match obfs::for_node(&node.name) {
+        Some(config) => {
+            let (sender, receiver) = obfs::connect(node, &config).await?;
+            UdpChannel::Obfuscated(sender, receiver)
+        }
+        None => {
+            let addr = *dns::resolve(&node.server, node.port)
+                .await?
+                .first()
+                .context("没有解析结果")?;
+            UdpChannel::Direct(outbound::udp_socket(addr).await.context("无法创建 UDP socket")?)
+        }
+    };
+
+    let id = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default();
+    let query = dns_query(id, UDP_PROBE_DOMAIN);
+
+    for (attempt, wait) in UDP_PROBE_WAITS.iter().enumerate() {
+        let start = Instant::now();
+        channel.send(&query).await?;
+        // 接收出错（例如 ICMP 端口不可达）同样视为没有转发
+        if let Ok(Ok(reply)) = tokio::time::timeout(Duration::from_millis(*wait), channel.recv()).await {
+            return Ok(Some(UdpProbe {
+                reply: classify_reply(&reply, &query),
+                size: reply.len(),
+                rtt: start.elapsed(),
+                attempt: attempt + 1,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// 构造查询 A 记录的 DNS 请求
+fn dns_query(id: u16, domain: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(17 + domain.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // 期望递归查询，1 个问题
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in domain.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    // QTYPE A, QCLASS IN
+    packet.extend_from_slice(&[0, 1, 0, 1]);
+    packet
+}
+
+fn classify_reply(reply: &[u8], query: &[u8]) -> UdpReply {
+    if reply == query {
+        UdpReply::Echo
+    } else if reply.len() >= 12 && reply[..2] == query[..2] && reply[2] & 0x80 != 0 {
+        UdpReply::Dns
+    } else {
+        UdpReply::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_udp_probe_replies() {
+        let query = dns_query(0x1234, "www.example.com");
+        assert_eq!(query.len(), 33);
+        assert_eq!(&query[12..17], b"\x03www\x07");
+
+        let mut response = query.clone();
+        response[2] |= 0x80;
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        assert_eq!(classify_reply(&response, &query), UdpReply::Dns);
+        assert_eq!(classify_reply(&query, &query), UdpReply::Echo);
+        assert_eq!(classify_reply(b"hello", &query), UdpReply::Other);
+    }
+
+    #[tokio::test]
+    async fn liveness_probe_reports_connect_time_without_settle() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            // 保持连接不发送数据，与等待客户端先发送的代理服务端一样
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let node = Node::test("ss", "127.0.0.1", port, "ss");
+        let probe = probe(&node, Duration::from_secs(5)).await.unwrap();
+        assert!(!probe.authenticated);
+        assert!(probe.latency < timeouts::current().probe_settle(), "{:?}", probe.latency);
+    }
+
+    #[test]
+    fn tells_slow_server_from_slow_network() {
+        let ms = Duration::from_millis;
+        let timing = |stages: Vec<(Stage, Duration)>| Timing { stages, ..Default::default() };
+
+        let healthy = timing(vec![(Stage::Dns, ms(10)), (Stage::Tcp, ms(40)), (Stage::Tls, ms(90))]);
+        assert_eq!(healthy.total(), ms(140));
+        assert!(healthy.diagnose().is_empty());
+
+        let slow_server = timing(vec![(Stage::Dns, ms(10)), (Stage::Tcp, ms(40)), (Stage::Tls, ms(600))]);
+        assert_eq!(slow_server.diagnose().len(), 1);
+        assert!(slow_server.diagnose()[0].contains("服务端"));
+
+        let slow_network = timing(vec![(Stage::Dns, ms(300)), (Stage::Tcp, ms(400)), (Stage::Tls, ms(900))]);
+        let notes = slow_network.diagnose();
+        assert_eq!(notes.len(), 2);
+        assert!(notes[1].contains("线路"));
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::region;
+use crate::subscription::Node;
+
+const HISTORY_FILE: &str = "latency_history.jsonl";
+/// 只保留最近 30 天的记录
+const RETENTION_SECS: u64 = 30 * 24 * 3600;
+/// 每个时段覆盖的小时数
+pub const BUCKET_HOURS: u32 = 4;
+pub const BUCKETS: usize = (24 / BUCKET_HOURS) as usize;
+
+/// 一次延迟测试的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencySample {
+    pub node: String,
+    /// Unix 秒
+    pub timestamp: u64,
+    /// 测试时的本地小时 (0-23)
+    pub hour: u32,
+    /// 超时或连接失败时为 None
+    pub latency_ms: Option<u32>,
+}
+
+impl LatencySample {
+    pub fn now(node: &str, latency_ms: Option<u32>) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            node: node.to_string(),
+            timestamp: now.timestamp().max(0) as u64,
+            hour: now.hour(),
+            latency_ms,
+        }
+    }
+}
+
+/// 某个地区在某个时段的统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub median_ms: Option<u32>,
+    /// 丢包率 (百分比)
+    pub loss_percent: u32,
+    pub samples: usize,
+}
+
+/// 地区 × 时段的延迟统计
+pub type Heatmap = BTreeMap<&'static str, [Option<Cell>; BUCKETS]>;
+
+fn history_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(HISTORY_FILE))
+}
+
+/// 测试过延迟的节点对应的样本
+pub fn samples(nodes: &[Node]) -> Vec<LatencySample> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let latency = node.latency?;
+            Some(LatencySample::now(&node.name, (latency != u32::MAX).then_some(latency)))
+        })
+        .collect()
+}
+
+/// 把测试过延迟的节点追加到历史记录
+pub fn record(nodes: &[Node]) -> Result<()> {
+    append(&samples(nodes))
+}
+
+pub fn append(samples: &[LatencySample]) -> Result<()> {
+    if samples.is_empty() {
+        return Ok(());
+    }
+    let path = history_file()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("无法创建缓存目录")?;
+    }
+
+    let mut lines = String::new();
+    for sample in samples {
+        lines.push_str(&serde_json::to_string(sample)?);
+        lines.push('\n');
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("无法写入延迟历史: {:?}", path))
+}
+
+/// 读取保留期内的历史记录，顺便清理过期的记录
+pub fn load() -> Result<Vec<LatencySample>> {
+    let path = history_file()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("无法读取延迟历史: {:?}", path)),
+    };
+
+    let cutoff = (chrono::Local::now().timestamp().max(0) as u64).saturating_sub(RETENTION_SECS);
+    let total = content.lines().count();
+    // 损坏的行直接跳过
+    let samples: Vec<LatencySample> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<LatencySample>(line).ok())
+        .filter(|sample| sample.timestamp >= cutoff)
+        .collect();
+
+    if samples.len() < total {
+        let mut lines = String::new();
+        for sample in &samples {
+            lines.push_str(&serde_json::to_string(sample)?);
+            lines.push('\n');
+        }
+        fs::write(&path, lines).with_context(|| format!("无法写入延迟历史: {:?}", path))?;
+    }
+    Ok(samples)
+}
+
+/// 把以重复节点的原名称记录的样本归到合并后的节点，同一时刻的样本只保留延迟最低的一个
+pub fn merge_aliases(samples: &mut Vec<LatencySample>, nodes: &[Node]) {
+    let canonical: HashMap<&str, &str> = nodes
+        .iter()
+        .flat_map(|node| node.aliases.iter().map(|alias| (alias.as_str(), node.name.as_str())))
+        .collect();
+    if canonical.is_empty() {
+        return;
+    }
+    for sample in samples.iter_mut() {
+        if let Some(name) = canonical.get(sample.node.as_str()) {
+            sample.node = name.to_string();
+        }
+    }
+    samples.sort_by(|a, b| {
+        (a.timestamp, &a.node, a.latency_ms.unwrap_or(u32::MAX)).cmp(&(b.timestamp, &b.node, b.latency_ms.unwrap_or(u32::MAX)))
+    });
+    samples.dedup_by(|later, kept| later.timestamp == kept.timestamp && later.node == kept.node);
+}
+
+/// 一组样本的中位延迟和丢包率
+pub fn summarize<'a>(samples: impl IntoIterator<Item = &'a LatencySample>) -> Option<Cell> {
+    let mut latencies = Vec::new();
+    let mut total = 0;
+    for sample in samples {
+        total += 1;
+        latencies.extend(sample.latency_ms);
+    }
+    if total == 0 {
+        return None;
+    }
+
+    latencies.sort_unstable();
+    let loss = total - latencies.len();
+    Some(Cell {
+        median_ms: latencies.get(latencies.len() / 2).copied(),
+        loss_percent: (loss * 100 / total) as u32,
+        samples: total,
+    })
+}
+
+/// 按节点名称推断地区，按测试时段分组统计
+pub fn heatmap(samples: &[LatencySample]) -> Heatmap {
+    let mut groups: BTreeMap<&'static str, [Vec<&LatencySample>; BUCKETS]> = BTreeMap::new();
+    for sample in samples {
+        let bucket = (sample.hour / BUCKET_HOURS) as usize % BUCKETS;
+        groups.entry(region::of(&sample.node)).or_default()[bucket].push(sample);
+    }
+
+    groups
+        .into_iter()
+        .map(|(region, buckets)| (region, buckets.map(summarize)))
+        .collect()
+}
+
+/// 时段的显示名称，例如 "20-24点"
+pub fn bucket_label(bucket: usize) -> String {
+    let start = bucket as u32 * BUCKET_HOURS;
+    format!("{}-{}点", start, start + BUCKET_HOURS)
+}
+
+/// 指定时段表现最好的地区：先比较丢包率，再比较中位延迟
+pub fn best_region(heatmap: &Heatmap, bucket: usize) -> Option<(&'static str, Cell)> {
+    heatmap
+        .iter()
+        .filter(|(region, _)| **region != region::UNKNOWN_REGION)
+        .filter_map(|(region, cells)| Some((*region, cells[bucket]?)))
+        .filter(|(_, cell)| cell.median_ms.is_some())
+        .min_by_key(|(_, cell)| (cell.loss_percent, cell.median_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(node: &str, hour: u32, latency_ms: Option<u32>) -> LatencySample {
+        LatencySample {
+            node: node.to_string(),
+            timestamp: 0,
+            hour,
+            latency_ms,
+        }
+    }
+
+    #[test]
+    fn builds_region_heatmap() {
+        let samples = vec![
+            sample("香港 01", 21, Some(40)),
+            sample("香港 02", 22, Some(60)),
+            sample("香港 01", 23, None),
+            sample("日本 01", 20, Some(80)),
+            sample("日本 01", 9, Some(50)),
+            sample("Traffic: 10G", 21, Some(10)),
+        ];
+        let map = heatmap(&samples);
+
+        let evening = BUCKETS - 1;
+        assert_eq!(
+            map["香港"][evening],
+            Some(Cell { median_ms: Some(60), loss_percent: 33, samples: 3 })
+        );
+        assert_eq!(map["日本"][9 / BUCKET_HOURS as usize].unwrap().median_ms, Some(50));
+        assert_eq!(map["日本"][0], None);
+
+        // 香港有丢包，晚上推荐日本；未知地区不参与推荐
+        assert_eq!(best_region(&map, evening).map(|(region, _)| region), Some("日本"));
+        assert_eq!(bucket_label(evening), "20-24点");
+    }
+
+    #[test]
+    fn merges_samples_of_duplicate_nodes() {
+        let node = Node {
+            aliases: vec!["HK 01 [备用]".to_string()],
+            ..Node::test("香港 01", "hk.example.com", 443, "ss")
+        };
+        let mut samples = vec![sample("香港 01", 21, None), sample("HK 01 [备用]", 21, Some(45)), sample("日本 01", 21, Some(80))];
+        merge_aliases(&mut samples, &[node]);
+        let merged: Vec<(&str, Option<u32>)> = samples.iter().map(|s| (s.node.as_str(), s.latency_ms)).collect();
+        assert_eq!(merged, [("日本 01", Some(80)), ("香港 01", Some(45))]);
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use notify::{Event, RecursiveMode, Watcher};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::alarm;
+use crate::auto_select;
+use crate::bittorrent;
+use crate::bypass;
+use crate::config::Config;
+use crate::dscp;
+use crate::lan;
+use crate::logging;
+use crate::failover::{FailoverPolicy, SwitchReason};
+use crate::insights;
+use crate::mtu::{self, PacketLimits};
+use crate::network;
+use crate::node_overrides;
+use crate::notification;
+use crate::obfs;
+use crate::sniff;
+use crate::outbound::{self, OutboundBinding};
+use crate::performance;
+use crate::proxy::ProxyServer;
+use crate::rename;
+use crate::sticky;
+use crate::subscription::{self, SubscriptionManager};
+use crate::timeouts;
+use crate::udp_keepalive;
+
+/// 连续写入事件的合并窗口，编辑器保存时通常会触发多次事件
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// 监听配置文件变化，在服务运行期间应用无需重启的配置
+pub struct ConfigWatcher {
+    proxy_server: Arc<ProxyServer>,
+    current: Config,
+}
+
+impl ConfigWatcher {
+    pub fn new(proxy_server: Arc<ProxyServer>, current: Config) -> Self {
+        Self {
+            proxy_server,
+            current,
+        }
+    }
+
+    /// 启动后台监听任务
+    pub fn spawn(mut self) -> Result<()> {
+        let config_dir = Config::config_dir()?;
+        let config_file = Config::config_file()?;
+
+        // 监听目录而不是文件本身，这样文件被替换（重命名写入）后依然能收到事件
+        if !config_dir.exists() {
+            fs::create_dir_all(&config_dir)
+                .with_context(|| format!("无法创建配置目录: {:?}", config_dir))?;
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<notify::Result<Event>>();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })
+        .context("无法创建配置文件监听器")?;
+
+        watcher
+            .watch(&config_dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("无法监听配置目录: {:?}", config_dir))?;
+
+        let tasks = self.proxy_server.tasks();
+        tasks.spawn("配置热重载", async move {
+            // watcher 被 drop 后监听即停止，需要在任务内保持存活
+            let _watcher = watcher;
+
+            while let Some(res) = rx.recv().await {
+                match res {
+                    Ok(event) => {
+                        let touches_config = event
+                            .paths
+                            .iter()
+                            .any(|path| path.file_name() == config_file.file_name());
+                        if !touches_config || event.kind.is_access() {
+                            continue;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("配置文件监听错误: {}", e);
+                        continue;
+                    }
+                }
+
+                tokio::time::sleep(DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+
+                self.reload().await;
+            }
+
+            info!("配置文件监听已停止");
+        });
+
+        info!("配置热重载已启用");
+        Ok(())
+    }
+
+    async fn reload(&mut self) {
+        let new_config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("重新加载配置失败，继续使用当前配置: {:#}", e);
+                return;
+            }
+        };
+
+        self.apply(new_config).await;
+    }
+
+    async fn apply(&mut self, mut new_config: Config) {
+        let old = &self.current;
+
+        // 需要重启才能生效的变更只做提示，保持当前值以便后续继续比较
+        if new_config.proxy_port != old.proxy_port {
+            warn!(
+                "代理端口已修改为 {}，需要重启服务后生效 (当前仍监听 {})",
+                new_config.proxy_port, old.proxy_port
+            );
+            new_config.proxy_port = old.proxy_port;
+        }
+
+        if new_config.allow_lan != old.allow_lan {
+            warn!("局域网共享设置已修改，需要重启服务后生效");
+            new_config.allow_lan = old.allow_lan;
+        }
+
+        if new_config.dns_resolver != old.dns_resolver
+            || new_config.doh_url != old.doh_url
+            || new_config.resolve_strategy != old.resolve_strategy
+        {
+            warn!("DNS 解析设置已修改，需要重启服务后生效");
+            new_config.dns_resolver = old.dns_resolver;
+            new_config.doh_url = old.doh_url.clone();
+            new_config.resolve_strategy = old.resolve_strategy;
+        }
+
+        if new_config.log_level != old.log_level {
+            if !logging::config_level_applies() {
+                info!("已通过 RUST_LOG 或 -v/-q 指定日志级别，忽略配置中的日志级别");
+            } else if let Some(level) = new_config.log_level_filter() {
+                log::set_max_level(level);
+                info!("日志级别已更新为 {}", level);
+            } else {
+                warn!("无效的日志级别: {:?}", new_config.log_level);
+            }
+        }
+
+        if new_config.auto_select.enabled != old.auto_select.enabled {
+            info!(
+                "自动选择已{}",
+                if new_config.auto_select.enabled { "开启" } else { "关闭" }
+            );
+        }
+        if new_config.auto_select.needs_reselect(&old.auto_select) {
+            info!("自动选择设置已更新 ({})，重新选择节点", new_config.auto_select.policy.display_name());
+            auto_select::spawn_reselect(Arc::clone(&self.proxy_server));
+        }
+
+        let policy = FailoverPolicy::from_config(&new_config);
+        if policy != FailoverPolicy::from_config(old) {
+            self.proxy_server.set_failover_policy(policy).await;
+            info!("故障切换策略已更新");
+        }
+
+        if new_config.latency_alarm != old.latency_alarm {
+            alarm::set_config(new_config.latency_alarm.clone());
+            info!("延迟报警设置已更新");
+        }
+
+        if new_config.udp_keepalive != old.udp_keepalive {
+            udp_keepalive::set_config(new_config.udp_keepalive.clone());
+            info!("UDP 保活设置已更新");
+        }
+
+        if new_config.dscp != old.dscp {
+            dscp::set_config(new_config.dscp.clone());
+            info!("DSCP 标记设置已更新，新建立的连接生效");
+        }
+
+        if new_config.do_not_disturb != old.do_not_disturb {
+            performance::set_do_not_disturb(new_config.do_not_disturb.clone());
+            info!("勿扰模式设置已更新");
+        }
+
+        if new_config.network != old.network {
+            network::set_config(new_config.network.clone());
+            info!("按网络暂停加速的设置已更新");
+        }
+
+        if new_config.timeouts != old.timeouts {
+            timeouts::set(new_config.timeouts.clone());
+            info!("超时设置已更新");
+        }
+
+        let limits = PacketLimits::from_config(&new_config);
+        if limits != PacketLimits::from_config(old) {
+            mtu::set_limits(limits);
+            info!("数据包大小限制已更新");
+        }
+
+        if new_config.obfuscation != old.obfuscation {
+            obfs::set_rules(new_config.obfuscation.clone());
+            info!("流量混淆设置已更新，新建立的 UDP 会话生效");
+        }
+
+        if new_config.node_overrides != old.node_overrides {
+            node_overrides::set_rules(new_config.node_overrides.clone());
+            info!("节点设置已更新，新建立的连接生效");
+        }
+
+        if new_config.dedup_nodes != old.dedup_nodes {
+            subscription::set_dedup(new_config.dedup_nodes);
+            info!("重复节点合并已{}，下次刷新订阅时生效", if new_config.dedup_nodes { "开启" } else { "关闭" });
+        }
+
+        if new_config.rename != old.rename {
+            rename::set_config(&new_config.rename);
+            info!("节点改名规则已更新，下次刷新订阅时生效");
+        }
+
+        if new_config.bypass != old.bypass {
+            bypass::set_rules(&new_config.bypass);
+            info!("直连规则已更新 ({} 条)", new_config.bypass.len());
+        }
+
+        if new_config.sniff != old.sniff {
+            sniff::set_enabled(new_config.sniff);
+            info!("域名识别已{}", if new_config.sniff { "开启" } else { "关闭" });
+        }
+
+        if new_config.block_bt != old.block_bt {
+            bittorrent::set_enabled(new_config.block_bt);
+            info!("BitTorrent 拦截已{}", if new_config.block_bt { "开启" } else { "关闭" });
+        }
+
+        if new_config.blocked_devices != old.blocked_devices {
+            lan::set_blocked(&new_config.blocked_devices);
+            info!("设备禁用列表已更新");
+        }
+
+        if new_config.lan_devices != old.lan_devices {
+            lan::set_devices(&new_config.lan_devices);
+            info!("局域网设备的分流设置已更新");
+        }
+
+        if new_config.allowed_clients != old.allowed_clients {
+            lan::set_allowed(&new_config.allowed_clients);
+            info!("允许连接的客户端地址段已更新");
+        }
+
+        let binding = OutboundBinding::from_config(&new_config);
+        if binding != OutboundBinding::from_config(old) {
+            outbound::set_binding(binding);
+            info!("出口网卡绑定已更新，新建立的连接生效");
+        }
+
+        if new_config.tcp_keepalive_secs != old.tcp_keepalive_secs {
+            outbound::set_tcp_keepalive(new_config.tcp_keepalive_secs);
+            info!("TCP keepalive 已更新，新建立的连接生效");
+        }
+
+        if new_config.sticky_ttl_secs != old.sticky_ttl_secs {
+            sticky::set_ttl(new_config.sticky_ttl_secs);
+            info!("粘性路由有效期已更新为 {} 秒", new_config.sticky_ttl_secs);
+        }
+
+        if new_config.insights != old.insights {
+            insights::set_enabled(new_config.insights);
+        }
+
+        if new_config.desktop_notifications != old.desktop_notifications {
+            notification::set_enabled(new_config.desktop_notifications);
+        }
+
+        if new_config.subscription_url != old.subscription_url {
+            if let Some(url) = &new_config.subscription_url {
+                info!("订阅链接已变更，刷新备用节点...");
+                self.proxy_server.set_subscription_url(url.clone()).await;
+                if let Err(e) = self.proxy_server.refresh_backup_nodes().await {
+                    warn!("刷新备用节点失败: {}", e);
+                }
+            } else {
+                warn!("订阅链接已被清除，继续使用当前节点");
+            }
+        }
+
+        if new_config.selected_node != old.selected_node {
+            if let (Some(name), Some(url)) = (&new_config.selected_node, &new_config.subscription_url) {
+                if let Err(e) = self.switch_node(url, name, new_config.selected_node_id.as_deref()).await {
+                    warn!("切换到节点 {} 失败: {}", name, e);
+                }
+            }
+        }
+
+        self.current = new_config;
+    }
+
+    async fn switch_node(&self, subscription_url: &str, name: &str, id: Option<&str>) -> Result<()> {
+        let sub_manager = SubscriptionManager::new();
+        let clash_config = sub_manager.fetch_subscription(subscription_url).await?;
+        let nodes = sub_manager.parse_nodes(&clash_config)?;
+
+        let node = subscription::find_selected(&nodes, name, id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("找不到节点: {}", name))?;
+
+        info!("配置变更，切换到节点: {}", node.name);
+        self.proxy_server.switch_node(node, SwitchReason::Manual).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use clashfun::error::ProxyError;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::subscription::Node;
+
+/// 响应头的最大长度，超过时视为不是 HTTP 代理
+const MAX_RESPONSE_HEADER: usize = 8 * 1024;
+
+/// 是否为 HTTP 上游节点，例如只允许通过 HTTP 代理出网的公司网络
+pub fn is_http(node: &Node) -> bool {
+    node.protocol.eq_ignore_ascii_case("http")
+}
+
+/// 节点设置了用户名时的 Proxy-Authorization 请求头
+fn authorization(node: &Node) -> String {
+    match node.username.as_deref() {
+        Some(username) => {
+            let credential = format!("{}:{}", username, node.password.as_deref().unwrap_or(""));
+            format!("Proxy-Authorization: Basic {}\r\n", general_purpose::STANDARD.encode(credential))
+        }
+        None => String::new(),
+    }
+}
+
+/// 通过 HTTP 节点的 CONNECT 隧道连接目标，成功后 stream 即为到目标的连接
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, node: &Node, target: SocketAddr) -> Result<()> {
+    let request = format!(
+        "CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n{}Proxy-Connection: keep-alive\r\n\r\n",
+        authorization(node)
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    match read_status(stream).await? {
+        200..=299 => Ok(()),
+        407 => Err(rejected(node)),
+        status => bail!("HTTP 代理无法连接 {} (状态码 {})", target, status),
+    }
+}
+
+/// 确认节点是可用的 HTTP 代理：发送不连接任何目标的 OPTIONS 请求，服务端回复 HTTP 响应即可，
+/// 认证失败视为不可用
+pub async fn probe<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, node: &Node) -> Result<()> {
+    let request = format!(
+        "OPTIONS * HTTP/1.1\r\nHost: {}:{}\r\n{}Connection: close\r\n\r\n",
+        node.server,
+        node.port,
+        authorization(node)
+    );
+    stream.write_all(request.as_bytes()).await?;
+    if read_status(stream).await? == 407 {
+        return Err(rejected(node));
+    }
+    Ok(())
+}
+
+fn rejected(node: &Node) -> anyhow::Error {
+    ProxyError::AuthRejected { node: node.name.clone(), reason: "HTTP 代理用户名或密码错误" }.into()
+}
+
+/// 逐字节读取响应头，不多读隧道中的数据，返回状态码
+async fn read_status<S: AsyncRead + Unpin>(stream: &mut S) -> Result<u16> {
+    let mut header = Vec::with_capacity(256);
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER {
+            bail!("HTTP 代理的响应头过长");
+        }
+        let byte = stream.read_u8().await.context("HTTP 代理没有回复")?;
+        header.push(byte);
+    }
+
+    let status_line = header.split(|b| *b == b'\n').next().unwrap_or_default();
+    let status_line = String::from_utf8_lossy(status_line);
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next().and_then(|code| code.parse().ok())) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") => Ok(status),
+        _ => bail!("不是 HTTP 代理的响应: {}", status_line.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncBufReadExt, BufReader};
+
+    #[tokio::test]
+    async fn tunnels_through_connect_with_basic_auth() {
+        let node = Node {
+            password: Some("secret".to_string()),
+            username: Some("user".to_string()),
+            ..Node::test("公司代理", "proxy.corp", 3128, "http")
+        };
+        let (mut client, server) = duplex(1024);
+        let upstream = tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let mut request = Vec::new();
+            loop {
+                let mut line = String::new();
+                server.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                request.push(line.trim_end().to_string());
+            }
+            assert_eq!(request[0], "CONNECT 203.0.113.7:27015 HTTP/1.1");
+            assert!(request.contains(&"Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=".to_string()));
+            // 响应头后紧跟隧道数据，不能被当作响应头读掉
+            server.write_all(b"HTTP/1.1 200 Connection established\r\n\r\ngame").await.unwrap();
+        });
+
+        connect(&mut client, &node, "203.0.113.7:27015".parse().unwrap()).await.unwrap();
+        let mut data = [0u8; 4];
+        client.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"game");
+        upstream.await.unwrap();
+
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let _ = server.read(&mut buf).await;
+            server.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await.unwrap();
+        });
+        let error = probe(&mut client, &node).await.unwrap_err();
+        assert!(error.to_string().contains("用户名或密码"), "{}", error);
+    }
+}
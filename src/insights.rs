@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::Config;
+use crate::failover::SwitchReason;
+
+const INSIGHTS_FILE: &str = "insights.json";
+/// 平均每次启动超过这么多次故障切换时建议检查节点
+const FAILOVER_PER_START_WARN: f64 = 3.0;
+
+/// 默认关闭，在配置中设置 insights: true 后才记录
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 本地使用统计：各功能的使用次数和节点切换次数，只保存在本机，从不上传
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Insights {
+    /// 第一次记录的日期
+    pub since: Option<NaiveDate>,
+    /// 命令（cf start、/stats 等）的使用次数
+    pub features: BTreeMap<String, u64>,
+    /// 按原因统计的节点切换次数
+    pub switches: BTreeMap<SwitchReason, u64>,
+}
+
+fn insights_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(INSIGHTS_FILE))
+}
+
+impl Insights {
+    /// 读取记录，文件不存在或损坏时从空记录开始
+    pub fn load() -> Self {
+        let Ok(path) = insights_file() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("使用统计已损坏，重新开始记录: {}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = insights_file()?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string(self)?).with_context(|| format!("无法写入使用统计: {:?}", temp))?;
+        fs::rename(&temp, &path).with_context(|| format!("无法写入使用统计: {:?}", path))?;
+        Ok(())
+    }
+
+    /// 删除全部记录
+    pub fn clear() -> Result<()> {
+        let path = insights_file()?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("无法删除使用统计: {:?}", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn touch(&mut self, today: NaiveDate) {
+        self.since.get_or_insert(today);
+    }
+
+    pub fn add_feature(&mut self, feature: &str, today: NaiveDate) {
+        self.touch(today);
+        *self.features.entry(feature.to_string()).or_default() += 1;
+    }
+
+    pub fn add_switch(&mut self, reason: SwitchReason, today: NaiveDate) {
+        self.touch(today);
+        *self.switches.entry(reason).or_default() += 1;
+    }
+
+    /// 使用次数从多到少排列的功能
+    pub fn top_features(&self) -> Vec<(&str, u64)> {
+        let mut features: Vec<(&str, u64)> = self.features.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        features.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        features
+    }
+
+    pub fn total_switches(&self) -> u64 {
+        self.switches.values().sum()
+    }
+
+    /// 平均每次启动服务发生的故障切换次数
+    pub fn failovers_per_start(&self) -> Option<f64> {
+        let starts = self.features.get("cf start").copied().unwrap_or(0);
+        if starts == 0 {
+            return None;
+        }
+        let failovers = self.switches.get(&SwitchReason::Failure).copied().unwrap_or(0);
+        Some(failovers as f64 / starts as f64)
+    }
+
+    /// 根据统计给出的调整建议
+    pub fn suggestions(&self) -> Vec<&'static str> {
+        let mut suggestions = Vec::new();
+        if self.failovers_per_start().is_some_and(|rate| rate > FAILOVER_PER_START_WARN) {
+            suggestions.push("故障切换较频繁，用 'cf node health' 查看哪个节点经常失败，或用 'cf auto-select --policy score' 选择更稳定的节点");
+        }
+        let latency = self.switches.get(&SwitchReason::Latency).copied().unwrap_or(0);
+        let failback = self.switches.get(&SwitchReason::Failback).copied().unwrap_or(0);
+        if latency > failback.max(1) * 2 {
+            suggestions.push("延迟报警经常触发切换，可以调大 latency_alarm 的阈值或 sustain_secs");
+        }
+        if failback > 0 && failback * 2 >= self.total_switches() {
+            suggestions.push("经常切回首选节点，可以调大 failover_cooldown_secs 或 failback_checks 减少来回切换");
+        }
+        suggestions
+    }
+}
+
+/// 在记录中加一次，写入失败只记日志
+fn update(f: impl FnOnce(&mut Insights, NaiveDate)) {
+    if !enabled() {
+        return;
+    }
+    let mut insights = Insights::load();
+    f(&mut insights, chrono::Local::now().date_naive());
+    if let Err(e) = insights.save() {
+        warn!("保存使用统计失败: {:#}", e);
+    }
+}
+
+/// 记录一次命令的使用
+pub fn record_feature(feature: &str) {
+    update(|insights, today| insights.add_feature(feature, today));
+}
+
+#[cfg(test)]
+thread_local! {
+    /// 测试中本线程记录过的切换，不受 insights 开关影响
+    pub static RECORDED_SWITCHES: std::cell::RefCell<Vec<SwitchReason>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// 记录一次节点切换，在后台线程写入文件
+pub fn record_switch(reason: SwitchReason) {
+    #[cfg(test)]
+    RECORDED_SWITCHES.with(|recorded| recorded.borrow_mut().push(reason));
+    if !enabled() {
+        return;
+    }
+    tokio::task::spawn_blocking(move || update(|insights, today| insights.add_switch(reason, today)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_features_and_switches() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let mut insights = Insights::default();
+        assert!(insights.failovers_per_start().is_none());
+        assert!(insights.suggestions().is_empty());
+
+        insights.add_feature("cf start", today);
+        insights.add_feature("cf status", today);
+        insights.add_feature("cf status", today + chrono::Duration::days(1));
+        for _ in 0..4 {
+            insights.add_switch(SwitchReason::Failure, today);
+        }
+        insights.add_switch(SwitchReason::Manual, today);
+
+        assert_eq!(insights.since, Some(today));
+        assert_eq!(insights.top_features()[0], ("cf status", 2));
+        assert_eq!(insights.total_switches(), 5);
+        assert_eq!(insights.failovers_per_start(), Some(4.0));
+        assert!(insights.suggestions()[0].contains("cf node health"));
+
+        let json = serde_json::to_string(&insights).unwrap();
+        assert!(json.contains("\"failure\":4"));
+        let loaded: Insights = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.switches.get(&SwitchReason::Manual), Some(&1));
+    }
+}
@@ -1,6 +1,6 @@
-use std::io::{self, Write};
+use std::io;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -9,18 +9,28 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    widgets::{BarChart, Block, Borders, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
 use anyhow::Result;
-use crate::{config::Config, subscription::Node, proxy::ProxyServer, game_detect::GameDetector};
+use crate::lan::DeviceReport;
+use crate::udp_quality::SessionQuality;
+use crate::usage::Usage;
+use crate::{config::Config, subscription::Node, proxy::ProxyServer};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TryRecvError};
 use tokio::sync::RwLock;
 
+/// 没有按键时重绘界面的间隔
+const TICK: Duration = Duration::from_millis(250);
+
+/// 重新读取后台更新检查结果的间隔
+const UPDATE_STATE_REFRESH: Duration = Duration::from_secs(60);
+
 pub struct InteractiveApp {
     pub config: Arc<RwLock<Config>>,
     pub proxy_server: Option<Arc<ProxyServer>>,
-    pub game_detector: Arc<RwLock<GameDetector>>,
     pub should_quit: bool,
     pub input: String,
     pub status_message: String,
@@ -28,21 +38,34 @@ pub struct InteractiveApp {
     pub selected_node: Option<usize>,
     pub list_state: ListState,
     pub current_mode: AppMode,
+    pub available_update: Option<String>,
+    /// 上次读取更新检查结果的时间，None 表示需要重新读取
+    update_state_loaded: Option<Instant>,
+    /// 进行中的更新发来的进度消息，更新结束后通道关闭
+    update_progress: Option<mpsc::UnboundedReceiver<String>>,
+    pub devices: Vec<DeviceReport>,
+    pub device_state: ListState,
+    pub usage: Usage,
+    /// 流量图显示的天数
+    pub usage_days: u32,
+    /// 运行中的 UDP 会话的丢包和乱序估计
+    pub udp_quality: Vec<SessionQuality>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppMode {
     Main,
     NodeSelection,
+    Devices,
+    Stats,
     Help,
 }
 
 impl InteractiveApp {
-    pub fn new(config: Arc<RwLock<Config>>, game_detector: Arc<RwLock<GameDetector>>) -> Self {
+    pub fn new(config: Arc<RwLock<Config>>) -> Self {
         Self {
             config,
             proxy_server: None,
-            game_detector,
             should_quit: false,
             input: String::new(),
             status_message: "欢迎使用 ClashFun! 输入 /help 查看帮助".to_string(),
@@ -50,6 +73,14 @@ impl InteractiveApp {
             selected_node: None,
             list_state: ListState::default(),
             current_mode: AppMode::Main,
+            available_update: None,
+            update_state_loaded: None,
+            update_progress: None,
+            devices: Vec::new(),
+            device_state: ListState::default(),
+            usage: Usage::default(),
+            usage_days: 7,
+            udp_quality: Vec::new(),
         }
     }
 
@@ -58,16 +89,21 @@ impl InteractiveApp {
         enable_raw_mode()?;
         let mut stdout = io::stdout();
         execute!(stdout, EnterAlternateScreen)?;
+        crate::crash::set_tui_active(true);
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
-        // 加载节点
-        self.load_nodes().await?;
+        // 后台检查更新，结果在状态栏显示
+        crate::updater::spawn_background_check(&*self.config.read().await);
 
-        // 主循环
-        let result = self.run_app(&mut terminal).await;
+        // 加载节点后进入主循环，出错时同样要恢复终端
+        let result = match self.load_nodes().await {
+            Ok(()) => self.run_app(&mut terminal).await,
+            Err(e) => Err(e),
+        };
 
         // 恢复终端
+        crate::crash::set_tui_active(false);
         disable_raw_mode()?;
         execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
         terminal.show_cursor()?;
@@ -77,12 +113,20 @@ impl InteractiveApp {
 
     async fn run_app<B: ratatui::backend::Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
         loop {
+            self.refresh_available_update();
+            self.receive_update_progress();
+
             terminal.draw(|f| self.ui(f))?;
 
+            if !event::poll(TICK)? {
+                continue;
+            }
             if let Event::Key(key) = event::read()? {
                 match self.current_mode {
                     AppMode::Main => self.handle_main_input(key).await?,
                     AppMode::NodeSelection => self.handle_node_selection_input(key).await?,
+                    AppMode::Devices => self.handle_devices_input(key).await?,
+                    AppMode::Stats => self.handle_stats_input(key).await?,
                     AppMode::Help => self.handle_help_input(key).await?,
                 }
             }
@@ -94,6 +138,30 @@ impl InteractiveApp {
         Ok(())
     }
 
+    /// 后台检查更新的结果写在缓存文件中，按间隔重新读取而不是每次重绘都读
+    fn refresh_available_update(&mut self) {
+        if self.update_state_loaded.is_some_and(|loaded| loaded.elapsed() < UPDATE_STATE_REFRESH) {
+            return;
+        }
+        self.available_update = crate::updater::UpdateCheckState::load()
+            .available_version()
+            .map(str::to_string);
+        self.update_state_loaded = Some(Instant::now());
+    }
+
+    /// 状态栏显示更新任务的最新进度
+    fn receive_update_progress(&mut self) {
+        let Some(progress) = &mut self.update_progress else { return };
+        loop {
+            match progress.try_recv() {
+                Ok(message) => self.status_message = message,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        self.update_progress = None;
+    }
+
     fn ui(&mut self, f: &mut Frame) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
@@ -115,6 +183,8 @@ impl InteractiveApp {
         match self.current_mode {
             AppMode::Main => self.render_main_content(f, chunks[1]),
             AppMode::NodeSelection => self.render_node_selection(f, chunks[1]),
+            AppMode::Devices => self.render_devices(f, chunks[1]),
+            AppMode::Stats => self.render_stats(f, chunks[1]),
             AppMode::Help => self.render_help(f, chunks[1]),
         }
 
@@ -125,9 +195,13 @@ impl InteractiveApp {
         f.render_widget(input, chunks[2]);
 
         // 状态栏
+        let status_title = match &self.available_update {
+            Some(version) => format!("状态 · 🆕 新版本 {} 可用，输入 /update 更新", version),
+            None => "状态".to_string(),
+        };
         let status = Paragraph::new(self.status_message.clone())
             .style(Style::default().fg(Color::Green))
-            .block(Block::default().borders(Borders::ALL).title("状态"));
+            .block(Block::default().borders(Borders::ALL).title(status_title));
         f.render_widget(status, chunks[3]);
     }
 
@@ -175,9 +249,11 @@ impl InteractiveApp {
             "📊 /status   - 查看服务状态",
             "🌐 /nodes    - 查看节点列表",
             "🎯 /select   - 选择节点",
-            "⚙️  /set     - 设置订阅链接",
+            "⚙️  /set     - 设置订阅链接（不带链接时读取剪贴板）",
             "🔄 /auto     - 自动选择最优节点",
             "🎮 /detect   - 检测运行中的游戏",
+            "📱 /devices  - 局域网设备与流量",
+            "📈 /stats    - 每天的流量统计",
             "⬆️  /update   - 检查并更新到最新版本",
             "❓ /help     - 显示帮助信息",
             "🚪 /quit     - 退出程序",
@@ -209,16 +285,18 @@ impl InteractiveApp {
             .map(|(i, node)| {
                 let style = if Some(i) == self.selected_node {
                     Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else if !node.is_supported() {
+                    Style::default().fg(Color::DarkGray)
                 } else {
                     Style::default().fg(Color::White)
                 };
 
-                ListItem::new(Line::from(format!(
-                    "{} {} - {}ms",
-                    node.name,
-                    node.server,
-                    node.latency.unwrap_or(999)
-                ))).style(style)
+                let text = if node.is_supported() {
+                    format!("{} {} - {}ms", node.name, node.server, node.latency.unwrap_or(999))
+                } else {
+                    format!("{} {} - {} 不支持", node.name, node.server, node.protocol)
+                };
+                ListItem::new(Line::from(text)).style(style)
             })
             .collect();
 
@@ -229,6 +307,110 @@ impl InteractiveApp {
         f.render_stateful_widget(nodes_list, area, &mut self.list_state);
     }
 
+    fn render_devices(&mut self, f: &mut Frame, area: Rect) {
+        let title = "局域网设备 (↑↓选择, B 禁用/允许, R 刷新, Esc返回)";
+        if self.devices.is_empty() {
+            let msg = Paragraph::new("还没有设备通过加速服务（服务未运行时无法获取）")
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .style(Style::default().fg(Color::Gray));
+            f.render_widget(msg, area);
+            return;
+        }
+
+        let items: Vec<ListItem> = self
+            .devices
+            .iter()
+            .map(|device| {
+                let name = match (&device.mac, device.is_local()) {
+                    (_, true) => format!("{} (本机)", device.ip),
+                    (Some(mac), _) => format!("{} ({})", device.ip, mac),
+                    (None, _) => device.ip.to_string(),
+                };
+                let style = if device.blocked {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                ListItem::new(Line::from(format!(
+                    "{}  ↑ {} / ↓ {}  TCP {}{}",
+                    name,
+                    crate::format_bytes(device.upload_bytes),
+                    crate::format_bytes(device.download_bytes),
+                    device.tcp_connections,
+                    if device.blocked { "  🚫 已禁用" } else { "" }
+                )))
+                .style(style)
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().bg(Color::Blue).add_modifier(Modifier::BOLD));
+        f.render_stateful_widget(list, area, &mut self.device_state);
+    }
+
+    fn render_stats(&self, f: &mut Frame, area: Rect) {
+        const MB: u64 = 1024 * 1024;
+
+        let today = chrono::Local::now().date_naive();
+        let recent = self.usage.recent(self.usage_days, today);
+        let summary = self.usage.summary(self.usage_days, today);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(area);
+
+        // 每天一根柱子，单位 MB
+        let labels: Vec<String> = recent.iter().map(|(date, _)| date.format("%d").to_string()).collect();
+        let data: Vec<(&str, u64)> = labels
+            .iter()
+            .zip(&recent)
+            .map(|(label, (_, day))| (label.as_str(), day.total.total().div_ceil(MB)))
+            .collect();
+        let bar_width = (chunks[0].width.saturating_sub(2) / self.usage_days.max(1) as u16).saturating_sub(1).max(1);
+        let chart = BarChart::default()
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "最近 {} 天的流量 (MB)，合计 {} (Tab 切换 7/30 天, R 刷新, Esc返回)",
+                self.usage_days,
+                crate::format_bytes(summary.total.total())
+            )))
+            .data(&data)
+            .bar_width(bar_width)
+            .bar_gap(1)
+            .bar_style(Style::default().fg(Color::Cyan))
+            .value_style(Style::default().fg(Color::Black).bg(Color::Cyan));
+        f.render_widget(chart, chunks[0]);
+
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(30), Constraint::Percentage(40)])
+            .split(chunks[1]);
+        for (area, title, list) in [(columns[0], "按节点", &summary.nodes), (columns[1], "按游戏", &summary.games)] {
+            let items: Vec<ListItem> = list
+                .iter()
+                .map(|(name, bytes)| ListItem::new(Line::from(format!("{}  {}", name, crate::format_bytes(bytes.total())))))
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .style(Style::default().fg(Color::White));
+            f.render_widget(list, area);
+        }
+
+        // 丢包对很多游戏比延迟影响更大，下行是节点到本机方向
+        let items: Vec<ListItem> = self
+            .udp_quality
+            .iter()
+            .map(|session| {
+                let game = session.game.as_ref().map(|game| game.display_name()).unwrap_or(&session.client);
+                ListItem::new(Line::from(format!("{}  ↓ {}", game, session.down.describe())))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("UDP 会话（丢包/乱序估计）"))
+            .style(Style::default().fg(Color::White));
+        f.render_widget(list, columns[2]);
+    }
+
     fn render_help(&self, f: &mut Frame, area: Rect) {
         let help_text = vec![
             Line::from("🎮 ClashFun 交互式界面帮助"),
@@ -239,14 +421,17 @@ impl InteractiveApp {
             Line::from("  /status   - 查看当前服务状态"),
             Line::from("  /nodes    - 显示所有可用节点"),
             Line::from("  /select   - 进入节点选择界面"),
-            Line::from("  /set      - 设置订阅链接"),
+            Line::from("  /set      - 设置订阅链接，不带链接时读取剪贴板"),
             Line::from("  /auto     - 自动选择最优节点"),
             Line::from("  /detect   - 检测运行中的游戏"),
+            Line::from("  /devices  - 查看局域网设备，禁用或允许设备使用加速"),
+            Line::from("  /stats    - 每天的流量图，按节点和游戏统计"),
             Line::from("  /update   - 检查并更新到最新版本"),
             Line::from("  /quit     - 退出程序"),
             Line::from(""),
             Line::from("⌨️  快捷键:"),
             Line::from("  Ctrl+C    - 强制退出"),
+            Line::from("  Ctrl+V    - 把剪贴板中的链接设为订阅"),
             Line::from("  Esc       - 返回主界面"),
             Line::from("  ↑↓        - 在选择界面中导航"),
             Line::from("  Enter     - 确认选择"),
@@ -262,6 +447,9 @@ impl InteractiveApp {
 
     async fn handle_main_input(&mut self, key: KeyEvent) -> Result<()> {
         match key.code {
+            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.set_subscription_from_clipboard().await?;
+            }
             KeyCode::Char(c) => {
                 self.input.push(c);
             }
@@ -312,13 +500,17 @@ impl InteractiveApp {
             KeyCode::Enter => {
                 if let Some(i) = self.list_state.selected() {
                     if i < self.nodes.len() {
-                        self.selected_node = Some(i);
                         let node = &self.nodes[i];
+                        if let Some(reason) = node.unsupported_reason() {
+                            self.status_message = format!("❌ {}", reason);
+                            return Ok(());
+                        }
+                        self.selected_node = Some(i);
 
                         // 更新配置
                         {
                             let mut config = self.config.write().await;
-                            config.selected_node = Some(node.name.clone());
+                            config.select_node(node);
                             config.save()?;
                         }
 
@@ -335,16 +527,90 @@ impl InteractiveApp {
         Ok(())
     }
 
-    async fn handle_help_input(&mut self, key: KeyEvent) -> Result<()> {
+    async fn handle_devices_input(&mut self, key: KeyEvent) -> Result<()> {
+        let count = self.devices.len();
         match key.code {
-            KeyCode::Esc => {
-                self.current_mode = AppMode::Main;
+            KeyCode::Up if count > 0 => {
+                let i = self.device_state.selected().map_or(0, |i| (i + count - 1) % count);
+                self.device_state.select(Some(i));
+            }
+            KeyCode::Down if count > 0 => {
+                let i = self.device_state.selected().map_or(0, |i| (i + 1) % count);
+                self.device_state.select(Some(i));
+            }
+            KeyCode::Char('b') | KeyCode::Char('B') => {
+                if let Some(device) = self.device_state.selected().and_then(|i| self.devices.get(i)) {
+                    // 优先使用 MAC，设备换了 IP 也能识别
+                    let id = device.mac.clone().unwrap_or_else(|| device.ip.to_string());
+                    let blocked = device.blocked;
+                    {
+                        let mut config = self.config.write().await;
+                        if blocked {
+                            let ip = device.ip.to_string();
+                            config.blocked_devices.retain(|d| *d != id && *d != ip);
+                        } else {
+                            config.blocked_devices.push(id.clone());
+                        }
+                        config.save()?;
+                    }
+                    self.status_message = if blocked {
+                        format!("✅ 已允许设备 {} 使用加速", id)
+                    } else {
+                        format!("🚫 已禁止设备 {} 使用加速", id)
+                    };
+                    // 配置热重载生效需要一点时间，先在本地更新显示
+                    if let Some(device) = self.device_state.selected().and_then(|i| self.devices.get_mut(i)) {
+                        device.blocked = !blocked;
+                    }
+                }
             }
+            KeyCode::Char('r') | KeyCode::Char('R') => self.refresh_devices().await,
+            KeyCode::Esc => self.current_mode = AppMode::Main,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn refresh_devices(&mut self) {
+        match crate::ipc::query_status().await {
+            Ok(report) => {
+                self.devices = report.devices;
+                self.status_message = format!("📱 {} 台设备", self.devices.len());
+            }
+            Err(_) => {
+                self.devices.clear();
+                self.status_message = "❌ 加速服务未运行，无法获取设备列表".to_string();
+            }
+        }
+        let selected = self.device_state.selected().filter(|i| *i < self.devices.len());
+        self.device_state.select(selected.or((!self.devices.is_empty()).then_some(0)));
+    }
+
+    async fn refresh_stats(&mut self) {
+        self.usage = Usage::load();
+        self.udp_quality = crate::ipc::query_status()
+            .await
+            .map(|report| report.udp_quality)
+            .unwrap_or_default();
+    }
+
+    async fn handle_stats_input(&mut self, key: KeyEvent) -> Result<()> {
+        match key.code {
+            KeyCode::Tab => self.usage_days = if self.usage_days == 7 { 30 } else { 7 },
+            KeyCode::Char('r') | KeyCode::Char('R') => self.refresh_stats().await,
+            KeyCode::Esc => self.current_mode = AppMode::Main,
             _ => {}
         }
         Ok(())
     }
 
+    async fn handle_help_input(&mut self, key: KeyEvent) -> Result<()> {
+        if key.code == KeyCode::Esc {
+            self.current_mode = AppMode::Main;
+        }
+        Ok(())
+    }
+
     async fn execute_command(&mut self, command: String) -> Result<()> {
         match command.as_str() {
             "/start" => {
@@ -352,8 +618,10 @@ impl InteractiveApp {
                 // TODO: 实现启动逻辑
             }
             "/stop" => {
-                self.status_message = "🛑 正在停止加速服务...".to_string();
-                // TODO: 实现停止逻辑
+                self.status_message = match crate::ipc::request_stop().await {
+                    Ok(()) => "🛑 已向加速服务发送停止信号".to_string(),
+                    Err(_) => "❌ 加速服务未运行".to_string(),
+                };
             }
             "/status" => {
                 self.status_message = "📊 查看服务状态".to_string();
@@ -373,8 +641,7 @@ impl InteractiveApp {
                 }
             }
             "/set" => {
-                self.status_message = "⚙️ 请在输入框中输入订阅链接".to_string();
-                // TODO: 实现订阅链接设置
+                self.set_subscription_from_clipboard().await?;
             }
             "/auto" => {
                 self.status_message = "🔄 正在自动选择最优节点...".to_string();
@@ -388,6 +655,15 @@ impl InteractiveApp {
                 self.status_message = "🔄 正在检查更新...".to_string();
                 self.check_and_update().await?;
             }
+            "/devices" => {
+                self.refresh_devices().await;
+                self.current_mode = AppMode::Devices;
+            }
+            "/stats" => {
+                self.refresh_stats().await;
+                self.current_mode = AppMode::Stats;
+                self.status_message = "📈 每天经过加速的流量".to_string();
+            }
             "/help" => {
                 self.current_mode = AppMode::Help;
                 self.status_message = "❓ 显示帮助信息".to_string();
@@ -401,8 +677,10 @@ impl InteractiveApp {
             }
             _ => {
                 self.status_message = format!("❌ 未知命令: {}，输入 /help 查看帮助", command);
+                return Ok(());
             }
         }
+        crate::insights::record_feature(command.split_whitespace().next().unwrap_or_default());
         Ok(())
     }
 
@@ -410,13 +688,21 @@ impl InteractiveApp {
         let config = self.config.read().await;
         if let Some(ref url) = config.subscription_url {
             let sub_manager = crate::subscription::SubscriptionManager::new();
-            if let Ok(clash_config) = sub_manager.fetch_subscription(url).await {
-                if let Ok(mut nodes) = sub_manager.parse_nodes(&clash_config) {
-                    // 测试延迟
-                    let _ = sub_manager.test_all_nodes(&mut nodes).await;
-                    // 按延迟排序
-                    nodes.sort_by_key(|node| node.latency.unwrap_or(9999));
-                    self.nodes = nodes;
+            match sub_manager.fetch_subscription(url).await {
+                Ok(clash_config) => {
+                    if let Ok(mut nodes) = sub_manager.parse_nodes(&clash_config) {
+                        // 测试延迟
+                        let _ = sub_manager.test_all_nodes(&mut nodes).await;
+                        // 按延迟排序
+                        nodes.sort_by_key(|node| node.latency.unwrap_or(9999));
+                        self.nodes = nodes;
+                    }
+                }
+                Err(e) => {
+                    self.status_message = match e.hint() {
+                        Some(hint) => format!("❌ 获取订阅失败: {} 💡 {}", e, hint),
+                        None => format!("❌ 获取订阅失败: {}", e),
+                    };
                 }
             }
         }
@@ -430,43 +716,54 @@ impl InteractiveApp {
             config.save()?;
         }
 
-        self.status_message = format!("✅ 订阅链接已设置: {}", url);
+        self.status_message = format!("✅ 订阅链接已设置: {}", crate::provider::mask_url(&url));
         self.load_nodes().await?;
         Ok(())
     }
 
-    async fn check_and_update(&mut self) -> Result<()> {
-        let updater = crate::updater::Updater::new();
-
-        // 检查更新
-        match updater.check_for_updates().await {
-            Ok(update_info) => {
-                if update_info.update_available {
-                    self.status_message = format!("🚀 发现新版本 {} -> {}，正在更新...",
-                        update_info.current_version,
-                        update_info.latest_version.unwrap_or_else(|| "未知".to_string()));
-
-                    if let Some(download_url) = &update_info.download_url {
-                        match updater.perform_update(download_url).await {
-                            Ok(()) => {
-                                self.status_message = "✅ 更新完成！请重启程序".to_string();
-                            }
-                            Err(e) => {
-                                self.status_message = format!("❌ 更新失败: {}", e);
-                            }
-                        }
-                    } else {
-                        self.status_message = "❌ 未找到适合的更新文件".to_string();
-                    }
-                } else {
-                    self.status_message = format!("✅ 已是最新版本 {}", update_info.current_version);
-                }
-            }
+    async fn set_subscription_from_clipboard(&mut self) -> Result<()> {
+        match crate::clipboard::read_subscription_url() {
+            Ok(url) => self.set_subscription(url).await,
             Err(e) => {
-                self.status_message = format!("❌ 检查更新失败: {}", e);
+                self.status_message = format!("❌ {}，也可以输入 /set <URL>", e);
+                Ok(())
             }
         }
+    }
+
+    async fn check_and_update(&mut self) -> Result<()> {
+        if self.update_progress.is_some() {
+            self.status_message = "🔄 更新正在进行中".to_string();
+            return Ok(());
+        }
+
+        let (updater, channel) = {
+            let config = self.config.read().await;
+            (crate::updater::Updater::from_config(&config)?, config.update_channel)
+        };
+
+        let request = crate::updater::UpdateRequest {
+            channel,
+            version: None,
+            check_only: false,
+        };
+
+        // 与 CLI 共用更新流程，在后台任务中下载，界面收到进度后重绘状态栏
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.update_progress = Some(rx);
+        tokio::spawn(async move {
+            let progress = tx.clone();
+            let result = updater.run(&request, &mut |event| {
+                let _ = progress.send(event.message());
+            }).await;
+            if let Err(e) = result {
+                let _ = tx.send(match clashfun::error::hint(&e) {
+                    Some(hint) => format!("❌ 更新失败: {:#} 💡 {}", e, hint),
+                    None => format!("❌ 更新失败: {:#}", e),
+                });
+            }
+        });
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+
+use crate::bittorrent::{self, BtStats};
+use crate::capture::{self, CaptureOptions, CaptureSummary};
+use crate::classifier::{self, ClassifierStats};
+use crate::config::Config;
+use crate::failover::{CheckRecord, FailoverStatus, HealthReport};
+use crate::fd_usage::{self, FdUsage};
+use crate::game_detect::SupportedGame;
+use crate::lan::{self, DeviceReport};
+use crate::network;
+use crate::performance;
+use crate::profiling::PerfReport;
+use crate::proxy::ProxyServer;
+use crate::simulate;
+use crate::sniff;
+use crate::udp_quality::SessionQuality;
+
+const DAEMON_INFO_FILE: &str = "daemon.json";
+#[cfg(unix)]
+const SOCKET_FILE: &str = "cf.sock";
+/// Windows 上控制管道名称的前缀，后面加上缓存目录的哈希
+#[cfg(windows)]
+const PIPE_PREFIX: &str = r"\\.\pipe\clashfun-";
+/// 管道所有实例都在使用中
+#[cfg(windows)]
+const ERROR_PIPE_BUSY: i32 = 231;
+/// 等待守护进程回复的超时时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// 状态中显示的域名数量
+const SNIFFED_HOSTS_SHOWN: usize = 5;
+
+/// 守护进程运行时写入的信息，供其他 cf 命令找到它
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    pub pid: u32,
+    pub started_at: u64,
+}
+
+/// 收到 cf stop 的停止请求
+static STOP: Notify = Notify::const_new();
+
+/// 控制通道的请求，每行一个 JSON
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    CaptureStart(CaptureOptions),
+    CaptureStop,
+    NodeHealth,
+    Perf,
+    Stop,
+}
+
+/// 守护进程的运行状态
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub pid: u32,
+    pub version: String,
+    pub uptime_secs: u64,
+    pub proxy_port: u16,
+    pub node: Option<String>,
+    pub preferred_node: Option<String>,
+    pub tcp_connections: u64,
+    pub udp_sessions: usize,
+    pub upload_bytes: u64,
+    pub download_bytes: u64,
+    #[serde(default)]
+    pub udp_dropped: u64,
+    #[serde(default)]
+    pub udp_fragmented: u64,
+    /// 已结束的 TCP 连接中正常关闭和出错结束的数量
+    #[serde(default)]
+    pub tcp_closed: u64,
+    #[serde(default)]
+    pub tcp_failed: u64,
+    /// 经过代理的设备，按流量排序
+    #[serde(default)]
+    pub devices: Vec<DeviceReport>,
+    /// 游戏流量识别的统计
+    #[serde(default)]
+    pub classifier: ClassifierStats,
+    /// 透明模式下识别到的域名及连接次数
+    #[serde(default)]
+    pub sniffed_hosts: Vec<(String, u64)>,
+    /// 拦截的 BitTorrent 流量
+    #[serde(default)]
+    pub bittorrent: BtStats,
+    /// 加速暂停的原因，例如当前是按流量计费的网络
+    #[serde(default)]
+    pub paused: Option<String>,
+    /// 开发者模式下模拟的网络状况
+    #[serde(default)]
+    pub simulate: Option<String>,
+    /// 当前节点最近一次健康检查
+    #[serde(default)]
+    pub last_check: Option<CheckRecord>,
+    /// 各 UDP 会话的包数和估计的丢包、乱序
+    #[serde(default)]
+    pub udp_quality: Vec<SessionQuality>,
+    /// 打开的文件描述符和上限，不支持的平台为 None
+    #[serde(default)]
+    pub fds: Option<FdUsage>,
+    /// 不在 allowed_clients 中被拒绝的来源数量和总次数
+    #[serde(default)]
+    pub rejected_clients: (usize, u64),
+    /// 当前节点故障后的切换进度
+    #[serde(default)]
+    pub failover: Option<FailoverStatus>,
+    /// 勿扰模式下正在进行的竞技游戏
+    #[serde(default)]
+    pub do_not_disturb: Option<SupportedGame>,
+}
+
+/// 守护进程退出时清理信息文件和 socket
+pub struct DaemonGuard {
+    files: Vec<PathBuf>,
+}
+
+impl Drop for DaemonGuard {
+    fn drop(&mut self) {
+        // 已被新实例接管时文件属于新实例
+        if daemon_info().is_some_and(|info| info.pid != std::process::id()) {
+            return;
+        }
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+fn info_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(DAEMON_INFO_FILE))
+}
+
+#[cfg(unix)]
+fn socket_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(SOCKET_FILE))
+}
+
+/// 便携模式等使用不同缓存目录的实例互不干扰
+#[cfg(windows)]
+fn pipe_name() -> Result<String> {
+    let dir = Config::cache_dir()?.to_string_lossy().to_lowercase();
+    let hash = dir
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    Ok(format!("{}{:016x}", PIPE_PREFIX, hash))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 读取守护进程信息，不代表进程仍在运行
+pub fn daemon_info() -> Option<DaemonInfo> {
+    let content = fs::read_to_string(info_file().ok()?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// 启动控制通道并写入守护进程信息
+pub async fn serve(proxy: Arc<ProxyServer>) -> Result<DaemonGuard> {
+    let cache_dir = Config::cache_dir()?;
+    fs::create_dir_all(&cache_dir).context("无法创建缓存目录")?;
+
+    let started_at = unix_now();
+    let mut guard = DaemonGuard { files: Vec::new() };
+
+    #[cfg(unix)]
+    {
+        let path = socket_file()?;
+        // 上次异常退出可能留下 socket 文件
+        let _ = fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path)
+            .with_context(|| format!("无法创建控制 socket: {:?}", path))?;
+        guard.files.push(path);
+
+        let proxy = Arc::clone(&proxy);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_client(stream, Arc::clone(&proxy), started_at));
+            }
+        });
+    }
+
+    // 每个客户端连接占用一个管道实例，连接后立即创建下一个实例继续等待
+    #[cfg(windows)]
+    {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = pipe_name()?;
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&name)
+            .with_context(|| format!("无法创建控制管道: {}", name))?;
+
+        let proxy = Arc::clone(&proxy);
+        tokio::spawn(async move {
+            while server.connect().await.is_ok() {
+                let client = server;
+                server = match ServerOptions::new().create(&name) {
+                    Ok(server) => server,
+                    Err(e) => {
+                        log::warn!("无法创建控制管道: {}", e);
+                        break;
+                    }
+                };
+                tokio::spawn(handle_client(client, Arc::clone(&proxy), started_at));
+            }
+        });
+    }
+
+    let info = DaemonInfo {
+        pid: std::process::id(),
+        started_at,
+    };
+    let path = info_file()?;
+    fs::write(&path, serde_json::to_string_pretty(&info)?)
+        .with_context(|| format!("无法写入守护进程信息: {:?}", path))?;
+    guard.files.push(path);
+
+    Ok(guard)
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(stream: S, proxy: Arc<ProxyServer>, started_at: u64) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Status) => serde_json::to_value(status_report(&proxy, started_at).await),
+            Ok(Request::CaptureStart(options)) => Ok(match capture::start(options) {
+                Ok(()) => serde_json::json!({ "ok": true }),
+                Err(e) => serde_json::json!({ "error": format!("{:#}", e) }),
+            }),
+            Ok(Request::CaptureStop) => match capture::stop() {
+                Some(summary) => serde_json::to_value(summary),
+                None => Ok(serde_json::json!({ "error": "没有正在进行的抓包" })),
+            },
+            Ok(Request::NodeHealth) => serde_json::to_value(proxy.health_report().await),
+            Ok(Request::Perf) => serde_json::to_value(PerfReport::collect(proxy.table_sizes().await)),
+            Ok(Request::Stop) => {
+                STOP.notify_one();
+                Ok(serde_json::json!({ "ok": true }))
+            }
+            Err(e) => {
+                debug!("无效的控制请求: {}", e);
+                Ok(serde_json::json!({ "error": e.to_string() }))
+            }
+        };
+
+        let Ok(response) = response else { break };
+        let mut payload = response.to_string();
+        payload.push('\n');
+        if writer.write_all(payload.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn status_report(proxy: &ProxyServer, started_at: u64) -> StatusReport {
+    let (upload_bytes, download_bytes, tcp_connections) = proxy.traffic().snapshot();
+    let (udp_dropped, udp_fragmented) = proxy.traffic().udp_drops();
+    let (tcp_closed, tcp_failed) = proxy.traffic().tcp_endings();
+    StatusReport {
+        pid: std::process::id(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: unix_now().saturating_sub(started_at),
+        proxy_port: proxy.get_proxy_port(),
+        node: proxy.current_node_name().await,
+        preferred_node: proxy.preferred_node_name().await,
+        tcp_connections,
+        udp_sessions: proxy.udp_session_count().await,
+        upload_bytes,
+        download_bytes,
+        udp_dropped,
+        udp_fragmented,
+        tcp_closed,
+        tcp_failed,
+        devices: proxy.devices().snapshot(),
+        classifier: classifier::stats(),
+        sniffed_hosts: sniff::top_hosts(SNIFFED_HOSTS_SHOWN),
+        bittorrent: bittorrent::stats(),
+        paused: network::paused(),
+        simulate: simulate::conditions().map(|conditions| conditions.to_string()),
+        last_check: proxy.current_check().await,
+        udp_quality: proxy.udp_quality().await,
+        fds: fd_usage::usage(),
+        rejected_clients: lan::rejected(),
+        failover: proxy.failover_status().await,
+        do_not_disturb: performance::do_not_disturb(),
+    }
+}
+
+/// 向运行中的守护进程发送请求
+async fn request<T: for<'de> Deserialize<'de>>(request: &Request) -> Result<T> {
+    let mut payload = serde_json::to_string(request)?;
+    payload.push('\n');
+
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(socket_file()?).await?;
+
+        #[cfg(windows)]
+        let stream = {
+            use tokio::net::windows::named_pipe::ClientOptions;
+
+            let name = pipe_name()?;
+            loop {
+                match ClientOptions::new().open(&name) {
+                    Ok(client) => break client,
+                    Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    Err(e) => return Err(e).context("无法连接守护进程的控制管道"),
+                }
+            }
+        };
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        writer.write_all(payload.as_bytes()).await?;
+
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("守护进程关闭了连接"))?;
+        let value: serde_json::Value = serde_json::from_str(&line)?;
+        if let Some(error) = value.get("error").and_then(|e| e.as_str()) {
+            return Err(anyhow!("{}", error));
+        }
+        Ok(serde_json::from_value(value)?)
+    })
+    .await
+    .map_err(|_| anyhow!("等待守护进程回复超时"))?
+}
+
+/// 查询守护进程状态，未运行时返回错误
+pub async fn query_status() -> Result<StatusReport> {
+    request(&Request::Status).await
+}
+
+/// 让守护进程开始抓包
+pub async fn start_capture(options: CaptureOptions) -> Result<()> {
+    let _: serde_json::Value = request(&Request::CaptureStart(options)).await?;
+    Ok(())
+}
+
+/// 让守护进程停止抓包，返回统计
+pub async fn stop_capture() -> Result<CaptureSummary> {
+    request(&Request::CaptureStop).await
+}
+
+/// 让守护进程正常退出
+pub async fn request_stop() -> Result<()> {
+    let _: serde_json::Value = request(&Request::Stop).await?;
+    Ok(())
+}
+
+/// 等待 cf stop 发来的停止请求
+pub async fn stop_requested() {
+    STOP.notified().await
+}
+
+/// 查询各节点的健康状况和切换历史
+pub async fn query_node_health() -> Result<HealthReport> {
+    request(&Request::NodeHealth).await
+}
+
+/// 查询守护进程的资源占用
+pub async fn query_perf() -> Result<PerfReport> {
+    request(&Request::Perf).await
+}
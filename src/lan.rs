@@ -0,0 +1,420 @@
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bypass::{BypassRule, Destination};
+use crate::profiling::{self, LockSite};
+
+/// 单个客户端设备的统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceReport {
+    pub ip: IpAddr,
+    pub mac: Option<String>,
+    pub upload_bytes: u64,
+    pub download_bytes: u64,
+    pub tcp_connections: u64,
+    /// 最后一次有流量的时间 (Unix 秒)
+    pub last_seen: u64,
+    pub blocked: bool,
+    /// 只有游戏端口经过加速，其他连接直连
+    #[serde(default)]
+    pub game_ports_only: bool,
+}
+
+impl DeviceReport {
+    /// 本机发起的连接
+    pub fn is_local(&self) -> bool {
+        self.ip.is_loopback()
+    }
+}
+
+/// 按来源 IP 统计经过代理的设备
+#[derive(Default)]
+pub struct DeviceTable {
+    devices: Mutex<HashMap<IpAddr, DeviceReport>>,
+}
+
+impl DeviceTable {
+    fn with_device<F: FnOnce(&mut DeviceReport)>(&self, ip: IpAddr, update: F) {
+        let mut devices = profiling::lock_sync(LockSite::Devices, &self.devices);
+        let device = devices.entry(ip).or_insert_with(|| DeviceReport {
+            ip,
+            mac: mac_address(ip),
+            upload_bytes: 0,
+            download_bytes: 0,
+            tcp_connections: 0,
+            last_seen: 0,
+            blocked: false,
+            game_ports_only: false,
+        });
+        device.last_seen = unix_now();
+        update(device);
+    }
+
+    pub fn add_traffic(&self, ip: IpAddr, upload: u64, download: u64) {
+        self.with_device(ip, |device| {
+            device.upload_bytes += upload;
+            device.download_bytes += download;
+        });
+    }
+
+    pub fn connection_opened(&self, ip: IpAddr) {
+        self.with_device(ip, |device| device.tcp_connections += 1);
+    }
+
+    pub fn connection_closed(&self, ip: IpAddr) {
+        self.with_device(ip, |device| device.tcp_connections = device.tcp_connections.saturating_sub(1));
+    }
+
+    /// 是否拒绝该设备的流量，同时记录被拒绝的设备以便在列表中显示
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        let mut blocked = false;
+        self.with_device(ip, |device| {
+            device.blocked = is_blocked(ip, device.mac.as_deref());
+            blocked = device.blocked;
+        });
+        blocked
+    }
+
+    /// 该设备到目标端口的连接是否经过加速节点，没有设置游戏端口的设备全部经过
+    pub fn should_relay(&self, ip: IpAddr, port: u16) -> bool {
+        let mut relay = true;
+        self.with_device(ip, |device| {
+            let ports = game_ports(ip, device.mac.as_deref());
+            device.game_ports_only = ports.is_some();
+            relay = ports.is_none_or(|ports| ports.iter().any(|range| range.contains(&port)));
+        });
+        relay
+    }
+
+    /// 记录过的设备数量
+    pub fn device_count(&self) -> usize {
+        self.devices.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// 按流量从大到小排列的设备列表
+    pub fn snapshot(&self) -> Vec<DeviceReport> {
+        let devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let mut list: Vec<DeviceReport> = devices
+            .values()
+            .cloned()
+            .map(|mut device| {
+                device.blocked = is_blocked(device.ip, device.mac.as_deref());
+                device.game_ports_only = game_ports(device.ip, device.mac.as_deref()).is_some();
+                device
+            })
+            .collect();
+        list.sort_by_key(|device| std::cmp::Reverse(device.upload_bytes + device.download_bytes));
+        list
+    }
+}
+
+static BLOCKED: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// 设置被禁止使用加速的设备（IP 或 MAC 地址）
+pub fn set_blocked(devices: &[String]) {
+    *BLOCKED.write().unwrap_or_else(|e| e.into_inner()) = devices.iter().map(|d| normalize(d)).collect();
+}
+
+fn is_blocked(ip: IpAddr, mac: Option<&str>) -> bool {
+    let ip = ip.to_string();
+    BLOCKED
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|entry| *entry == ip || mac.is_some_and(|mac| mac == entry))
+}
+
+/// 游戏机的联机端口，只有发往这些端口的流量经过加速，系统更新等走直连
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Console {
+    /// Nintendo Switch
+    Switch,
+    /// PS4 / PS5
+    Playstation,
+    /// Xbox One / Series
+    Xbox,
+}
+
+impl Console {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Switch => "Nintendo Switch",
+            Self::Playstation => "PlayStation",
+            Self::Xbox => "Xbox",
+        }
+    }
+
+    /// 厂商公布的联机端口
+    pub fn ports(&self) -> &'static [&'static str] {
+        match self {
+            Self::Switch => &["6667", "12400", "28910", "29900-29901", "29920", "45000-65535"],
+            Self::Playstation => &["3074", "3478-3480", "3658-3659", "9295-9304"],
+            Self::Xbox => &["88", "500", "3074-3075", "3544", "4500"],
+        }
+    }
+}
+
+/// 局域网设备的分流设置：设置了游戏机类型或端口后，只有发往这些端口的连接经过加速
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanDeviceConfig {
+    pub console: Option<Console>,
+    /// 额外的目标端口或端口范围，例如 27015、27000-27100
+    pub ports: Vec<String>,
+}
+
+impl LanDeviceConfig {
+    pub fn is_empty(&self) -> bool {
+        self.console.is_none() && self.ports.is_empty()
+    }
+
+    fn port_ranges(&self) -> Result<Vec<RangeInclusive<u16>>> {
+        let console_ports = self.console.iter().flat_map(|console| console.ports().iter().copied());
+        console_ports
+            .chain(self.ports.iter().map(String::as_str))
+            .map(parse_port_range)
+            .collect()
+    }
+
+    /// 用于显示的端口列表
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(console) = self.console {
+            parts.push(format!("{} 联机端口", console.display_name()));
+        }
+        if !self.ports.is_empty() {
+            parts.push(format!("端口 {}", self.ports.join(", ")));
+        }
+        parts.join(" + ")
+    }
+}
+
+fn parse_port_range(ports: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+    match (start.trim().parse::<u16>(), end.trim().parse::<u16>()) {
+        (Ok(start), Ok(end)) if start > 0 && start <= end => Ok(start..=end),
+        _ => bail!("无效的端口或端口范围: {}", ports),
+    }
+}
+
+static GAME_PORTS: RwLock<Vec<(String, Vec<RangeInclusive<u16>>)>> = RwLock::new(Vec::new());
+
+/// 检查 lan_devices 中的设备地址和端口
+pub fn validate_devices(devices: &HashMap<String, LanDeviceConfig>) -> Result<()> {
+    for (device, config) in devices {
+        if device.trim().is_empty() {
+            bail!("lan_devices 中的设备不能为空，应为 IP 或 MAC 地址");
+        }
+        config
+            .port_ranges()
+            .map_err(|e| anyhow::anyhow!("lan_devices 中 {} 的{}", device, e))?;
+    }
+    Ok(())
+}
+
+/// 设置只让游戏端口经过加速的设备，无效的端口会被忽略
+pub fn set_devices(devices: &HashMap<String, LanDeviceConfig>) {
+    let parsed = devices
+        .iter()
+        .filter(|(_, config)| !config.is_empty())
+        .filter_map(|(device, config)| match config.port_ranges() {
+            Ok(ranges) => Some((normalize(device), ranges)),
+            Err(e) => {
+                warn!("忽略设备 {} 的分流设置: {}", device, e);
+                None
+            }
+        })
+        .collect();
+    *GAME_PORTS.write().unwrap_or_else(|e| e.into_inner()) = parsed;
+}
+
+/// 设备的游戏端口，没有设置时为 None
+fn game_ports(ip: IpAddr, mac: Option<&str>) -> Option<Vec<RangeInclusive<u16>>> {
+    let ip = ip.to_canonical().to_string();
+    GAME_PORTS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(entry, _)| *entry == ip || mac.is_some_and(|mac| mac == entry))
+        .map(|(_, ranges)| ranges.clone())
+}
+
+static ALLOWED: RwLock<Vec<BypassRule>> = RwLock::new(Vec::new());
+/// 不在允许列表中而被拒绝的来源 IP 及次数
+static REJECTED: Mutex<Option<HashMap<IpAddr, u64>>> = Mutex::new(None);
+
+/// 检查允许连接代理端口的地址段，只接受 IP 或 CIDR
+pub fn validate_allowed(clients: &[String]) -> Result<()> {
+    for client in clients {
+        match client.parse::<BypassRule>() {
+            Ok(BypassRule::Cidr { .. }) => {}
+            _ => bail!("allowed_clients 中的 {} 不是有效的 IP 或 CIDR", client),
+        }
+    }
+    Ok(())
+}
+
+/// 设置允许连接代理端口的地址段，为空时不限制
+pub fn set_allowed(clients: &[String]) {
+    let parsed = clients
+        .iter()
+        .filter_map(|client| match client.parse() {
+            Ok(rule @ BypassRule::Cidr { .. }) => Some(rule),
+            _ => {
+                warn!("忽略无效的客户端地址段: {}", client);
+                None
+            }
+        })
+        .collect();
+    *ALLOWED.write().unwrap_or_else(|e| e.into_inner()) = parsed;
+}
+
+/// 来源地址是否允许使用代理，本机始终允许；不允许时记录并计数
+pub fn check_allowed(ip: IpAddr) -> bool {
+    if ip.to_canonical().is_loopback() {
+        return true;
+    }
+    let allowed = ALLOWED.read().unwrap_or_else(|e| e.into_inner());
+    let destination = Destination {
+        host: None,
+        addr: Some(SocketAddr::new(ip, 0)),
+    };
+    if allowed.is_empty() || allowed.iter().any(|rule| rule.matches(&destination)) {
+        return true;
+    }
+    drop(allowed);
+
+    let mut rejected = REJECTED.lock().unwrap_or_else(|e| e.into_inner());
+    let count = rejected.get_or_insert_with(HashMap::new).entry(ip).or_default();
+    *count += 1;
+    // 同一来源只提示一次，之后只记调试日志，避免被扫描时刷屏
+    if *count == 1 {
+        warn!("{} 不在 allowed_clients 中，拒绝连接", ip);
+    } else {
+        debug!("拒绝来自 {} 的连接 (第 {} 次)", ip, count);
+    }
+    false
+}
+
+/// 被拒绝的来源数量和总次数
+pub fn rejected() -> (usize, u64) {
+    let rejected = REJECTED.lock().unwrap_or_else(|e| e.into_inner());
+    rejected
+        .as_ref()
+        .map_or((0, 0), |rejected| (rejected.len(), rejected.values().sum()))
+}
+
+/// 统一 IP / MAC 的写法，MAC 使用小写冒号分隔
+pub fn normalize(device: &str) -> String {
+    let device = device.trim();
+    match device.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => device.replace('-', ":").to_ascii_lowercase(),
+    }
+}
+
+/// 从 ARP 表查找局域网设备的 MAC 地址
+#[cfg(target_os = "linux")]
+fn mac_address(ip: IpAddr) -> Option<String> {
+    if ip.is_loopback() {
+        return None;
+    }
+    let table = std::fs::read_to_string("/proc/net/arp").ok()?;
+    parse_arp_table(&table, ip)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mac_address(_ip: IpAddr) -> Option<String> {
+    None
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_arp_table(table: &str, ip: IpAddr) -> Option<String> {
+    let ip = ip.to_string();
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [address, _, _, mac, ..] if *address == ip && *mac != "00:00:00:00:00:00" => {
+                Some(mac.to_ascii_lowercase())
+            }
+            _ => None,
+        }
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mac_in_arp_table() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.20     0x1         0x2         98:B6:E9:01:02:03     *        eth0
+192.168.1.30     0x1         0x0         00:00:00:00:00:00     *        eth0
+";
+        let lookup = |ip: &str| parse_arp_table(table, ip.parse().unwrap());
+        assert_eq!(lookup("192.168.1.20").as_deref(), Some("98:b6:e9:01:02:03"));
+        assert_eq!(lookup("192.168.1.30"), None);
+        assert_eq!(lookup("192.168.1.40"), None);
+    }
+
+    #[test]
+    fn restricts_clients_to_allowed_ranges() {
+        assert!(validate_allowed(&["192.168.1.0/24".into(), "10.0.0.5".into()]).is_ok());
+        assert!(validate_allowed(&["example.com".into()]).is_err());
+
+        set_allowed(&["192.168.1.0/24".into()]);
+        assert!(check_allowed("192.168.1.20".parse().unwrap()));
+        assert!(check_allowed("127.0.0.1".parse().unwrap()));
+        assert!(check_allowed("::ffff:192.168.1.20".parse().unwrap()));
+        assert!(!check_allowed("192.168.2.20".parse().unwrap()));
+        assert!(!check_allowed("192.168.2.20".parse().unwrap()));
+        assert_eq!(rejected(), (1, 2));
+        set_allowed(&[]);
+        assert!(check_allowed("192.168.2.20".parse().unwrap()));
+    }
+
+    #[test]
+    fn relays_only_game_ports_of_configured_consoles() {
+        let devices: HashMap<String, LanDeviceConfig> = serde_yaml::from_str(
+            "192.168.1.50: {console: switch}\n192.168.1.60: {ports: ['27015', '27000-27100']}",
+        )
+        .unwrap();
+        validate_devices(&devices).unwrap();
+        set_devices(&devices);
+
+        let table = DeviceTable::default();
+        let switch: IpAddr = "192.168.1.50".parse().unwrap();
+        assert!(table.should_relay(switch, 50000));
+        assert!(!table.should_relay(switch, 443));
+        assert!(table.should_relay("::ffff:192.168.1.60".parse().unwrap(), 27050));
+        assert!(!table.should_relay("192.168.1.60".parse().unwrap(), 80));
+        assert!(table.should_relay("192.168.1.70".parse().unwrap(), 443));
+        assert!(table.snapshot().iter().any(|device| device.ip == switch && device.game_ports_only));
+
+        let invalid: HashMap<String, LanDeviceConfig> = serde_yaml::from_str("192.168.1.50: {ports: ['0-10']}").unwrap();
+        assert!(validate_devices(&invalid).is_err());
+        set_devices(&HashMap::new());
+    }
+
+    #[test]
+    fn normalizes_device_ids() {
+        assert_eq!(normalize(" 98-B6-E9-01-02-03 "), "98:b6:e9:01:02:03");
+        assert_eq!(normalize("192.168.1.20"), "192.168.1.20");
+    }
+}
@@ -0,0 +1,4 @@
+//! ClashFun 中可独立使用的组件（供模糊测试等外部 crate 使用）
+
+pub mod error;
+pub mod parser;
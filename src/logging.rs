@@ -0,0 +1,89 @@
+use log::LevelFilter;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::Config;
+use crate::crash;
+
+/// 日志级别由 RUST_LOG 或 -v/-q 决定，配置文件中的 log_level 不生效
+static OVERRIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// 命令行指定的详细程度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Verbosity {
+    /// -v 的个数
+    pub verbose: u8,
+    pub quiet: bool,
+}
+
+impl Verbosity {
+    fn given(&self) -> bool {
+        self.verbose > 0 || self.quiet
+    }
+
+    /// 在默认级别的基础上每个 -v 提高一级，-q 只输出错误
+    fn level(&self, default: LevelFilter) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::Error;
+        }
+        LevelFilter::iter()
+            .skip_while(|level| *level != default)
+            .nth(self.verbose as usize)
+            .unwrap_or(LevelFilter::Trace)
+    }
+}
+
+/// 未指定日志级别时使用的默认值：加速服务为 info，交互界面为 error（避免打乱界面），其他命令为 warn
+pub fn default_level(daemon: bool, interactive: bool) -> LevelFilter {
+    match (daemon, interactive) {
+        (true, _) => LevelFilter::Info,
+        (_, true) => LevelFilter::Error,
+        _ => LevelFilter::Warn,
+    }
+}
+
+/// 按 -v/-q、RUST_LOG、配置文件、默认值的顺序确定日志级别
+pub fn init(verbosity: Verbosity, default: LevelFilter) {
+    if !verbosity.given() && std::env::var_os("RUST_LOG").is_some() {
+        OVERRIDDEN.store(true, Ordering::Relaxed);
+        crash::install_logger(env_logger::Builder::from_default_env());
+        return;
+    }
+
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(LevelFilter::Warn)
+        .filter_module(env!("CARGO_CRATE_NAME"), LevelFilter::Trace);
+    crash::install_logger(builder);
+
+    let level = if verbosity.given() {
+        OVERRIDDEN.store(true, Ordering::Relaxed);
+        verbosity.level(default)
+    } else {
+        Config::load()
+            .ok()
+            .and_then(|config| config.log_level_filter())
+            .unwrap_or(default)
+    };
+    log::set_max_level(level);
+}
+
+/// 配置文件中的 log_level 是否生效，热重载时据此决定是否更新级别
+pub fn config_level_applies() -> bool {
+    !OVERRIDDEN.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raises_level_per_verbose_flag() {
+        let level = |verbose, quiet| Verbosity { verbose, quiet }.level(LevelFilter::Warn);
+        assert_eq!(level(0, false), LevelFilter::Warn);
+        assert_eq!(level(1, false), LevelFilter::Info);
+        assert_eq!(level(2, false), LevelFilter::Debug);
+        assert_eq!(level(9, false), LevelFilter::Trace);
+        assert_eq!(level(0, true), LevelFilter::Error);
+        assert_eq!(Verbosity { verbose: 1, quiet: false }.level(LevelFilter::Info), LevelFilter::Debug);
+    }
+}
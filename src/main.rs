@@ -1,45 +1,186 @@
 use clap::Parser;
-use env_logger;
-use log::{error, info};
+use log::{error, info, warn};
 use std::process;
 use std::sync::Arc;
 use std::fs;
-use std::path::Path;
-
+use std::io::{self, Write};
+#[cfg(feature = "tui")]
+use std::io::IsTerminal;
+
+mod alarm;
+mod auto_select;
+mod autostart;
+mod bittorrent;
+mod buffer_pool;
+mod bypass;
+mod capture;
+mod classifier;
+mod clash_export;
+mod clash_import;
+mod clipboard;
 mod cli;
 mod config;
+mod conflict;
+mod crash;
+mod dns;
+mod dscp;
+mod events;
+mod failover;
+mod fd_usage;
 mod game_detect;
+mod handover;
+mod health;
+mod history;
+mod hot_reload;
+mod http_proxy;
+mod insights;
+mod ipc;
+mod lan;
+mod logging;
+mod mtu;
+mod nat;
+#[cfg(target_os = "linux")]
+mod netfilter;
+mod network;
+mod node_index;
+mod node_overrides;
+mod obfs;
+mod outbound;
+mod performance;
+#[cfg(feature = "tui")]
+mod picker;
+#[cfg(unix)]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+mod pf;
+mod preflight;
+mod profiling;
 mod proxy;
+mod redirect;
+mod region;
+mod relay;
+mod rename;
+mod report;
+mod schedule;
+mod simulate;
+mod sniff;
+mod socks5;
+mod speed;
+mod sticky;
 mod subscription;
+mod table;
+mod tasks;
+mod timeouts;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tui")]
 mod interactive;
+mod notification;
+mod portmap;
+mod profile;
+mod progress;
+mod provider;
+mod uninstall;
+mod udp_batch;
+mod udp_quality;
+mod udp_keepalive;
 mod updater;
+mod usage;
 
 use cli::Cli;
 use proxy::ProxyServer;
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
-
+fn main() {
     let cli = Cli::parse();
 
-    if let Err(e) = run(cli).await {
-        error!("错误: {}", e);
+    if cli.portable || config::Config::portable_flag_present() {
+        config::Config::enable_portable();
+    }
+    if let Some(name) = &cli.profile {
+        match config::Config::enable_instance(name) {
+            Ok(Some(port)) => println!("📁 已为配置 {} 创建独立的配置文件（复制自默认配置，代理端口 {}）", name, port),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("❌ {:#}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let verbosity = logging::Verbosity {
+        verbose: cli.verbose,
+        quiet: cli.quiet,
+    };
+    let daemon = matches!(cli.command, Some(cli::Commands::Start { .. }));
+    logging::init(verbosity, logging::default_level(daemon, cli.command.is_none()));
+    crash::install_panic_hook();
+
+    let mut performance = performance::PerformanceConfig::default();
+    if let Ok(config) = config::Config::load() {
+        dns::init(&config);
+        profile::set(config.profile);
+        timeouts::set(config.timeouts);
+        insights::set_enabled(config.insights);
+        node_overrides::set_rules(config.node_overrides.clone());
+        rename::set_config(&config.rename);
+        subscription::set_dedup(config.dedup_nodes);
+        // 低延迟模式只用于加速服务本身
+        if matches!(cli.command, Some(cli::Commands::Start { .. })) {
+            performance = config.performance;
+        }
+    }
+
+    // 路由器模式下限制线程数，减少内存占用
+    let mut runtime = performance::runtime_builder(&performance);
+    runtime.enable_all();
+    if profile::router() && !performance.low_latency {
+        runtime
+            .worker_threads(profile::ROUTER_WORKER_THREADS)
+            .max_blocking_threads(profile::ROUTER_BLOCKING_THREADS);
+    }
+    let runtime = match runtime.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("无法创建异步运行时: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = runtime.block_on(run(cli)) {
+        error!("错误: {:#}", e);
+        if let Some(hint) = clashfun::error::hint(&e) {
+            println!("💡 {}", hint);
+        }
         process::exit(1);
     }
 }
 
+/// 输出失败原因，能判断出错误类型时附带处理建议
+fn print_error(action: &str, error: impl Into<anyhow::Error>) {
+    let error = error.into();
+    println!("❌ {}: {:#}", action, error);
+    if let Some(hint) = clashfun::error::hint(&error) {
+        println!("💡 {}", hint);
+    }
+}
+
 async fn run(cli: Cli) -> anyhow::Result<()> {
     // 如果没有提供子命令，启动交互模式
     if cli.command.is_none() {
         return run_interactive_mode().await;
     }
 
-    match cli.command.unwrap() {
-        cli::Commands::Start => {
+    let quiet = cli.quiet;
+    let command = cli.command.unwrap();
+    // 状态栏会频繁调用 cf get，不计入使用统计
+    if !matches!(command, cli::Commands::Get { .. }) {
+        insights::record_feature(command.feature_name());
+    }
+
+    match command {
+        cli::Commands::Start { simulate, redirect } => {
             info!("启动 ClashFun 服务...");
 
-            let config = config::Config::load()?;
+            let config = config::Config::load_or_recover()?;
 
             // 检查是否已配置订阅和节点
             if config.subscription_url.is_none() {
@@ -65,35 +206,223 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
             if let Err(e) = sub_manager.test_all_nodes(&mut nodes).await {
                 println!("⚠️  延迟测试失败: {}", e);
             }
+            record_latency(&nodes);
 
-            let selected_node = nodes.iter()
-                .find(|n| &n.name == selected_node_name)
+            let selected_node = subscription::find_selected(&nodes, selected_node_name, config.selected_node_id.as_deref())
                 .ok_or_else(|| anyhow::anyhow!("找不到选中的节点: {}", selected_node_name))?
                 .clone();
+            if let Some(reason) = selected_node.unsupported_reason() {
+                return Err(anyhow::anyhow!(reason));
+            }
+            if selected_node.name != *selected_node_name {
+                println!("💡 节点 {} 已改名为 {}", selected_node_name, selected_node.name);
+                let mut renamed = config.clone();
+                renamed.select_node(&selected_node);
+                renamed.save()?;
+            }
 
-            // 过滤出可用的备用节点（延迟 < 1000ms 且不是当前节点）
-            let backup_nodes: Vec<subscription::Node> = nodes
+            // 过滤出可用的备用节点（延迟低于上限且不是当前节点），正在玩的游戏可以单独设置上限
+            let game = match profile::router() {
+                true => None,
+                false => game_detect::GameDetector::new()
+                    .detect_running_games()
+                    .ok()
+                    .and_then(|games| games.into_iter().next())
+                    .map(|(game, _)| game),
+            };
+            let mut backup_nodes: Vec<subscription::Node> = nodes
                 .into_iter()
-                .filter(|n| &n.name != selected_node_name && n.latency.unwrap_or(u32::MAX) < 1000)
+                .filter(|n| n.name != selected_node.name && config.timeouts.usable_for(n.latency, game.as_ref()))
                 .collect();
 
+            // 监听端口前先与节点完成握手，节点拒绝认证时不会在所有连接都失败的情况下显示启动成功
+            if config.start_preflight != preflight::PreflightMode::Off {
+                println!("🩺 检查节点 {}...", selected_node.name);
+            }
+            let selected_node = match preflight::check(&selected_node, &backup_nodes, config.start_preflight).await? {
+                preflight::Outcome::Passed(probe) if probe.authenticated => {
+                    println!("✅ 节点认证通过 (连接 {}ms)", probe.latency.as_millis());
+                    selected_node
+                }
+                preflight::Outcome::Passed(probe) => {
+                    println!("✅ 节点可连接，未验证认证 (连接 {}ms)", probe.latency.as_millis());
+                    selected_node
+                }
+                preflight::Outcome::Unreachable(e) => {
+                    println!("⚠️  节点 {} 暂时无法连接: {:#}", selected_node.name, e);
+                    println!("💡 启动后健康检查会在连续失败时自动切换到备用节点");
+                    selected_node
+                }
+                preflight::Outcome::Replaced { node, rejected } => {
+                    warn!("{:#}，改用备用节点 {}", rejected, node.name);
+                    println!("❗ {:#}", rejected);
+                    println!("❗ 本次改用通过检查的备用节点 {}，配置中选中的节点不变", node.name);
+                    if let Some(hint) = clashfun::error::hint(&rejected) {
+                        println!("💡 {}", hint);
+                    }
+                    backup_nodes.retain(|n| n.name != node.name);
+                    node
+                }
+                preflight::Outcome::Skipped => selected_node,
+            };
+
+            // 端口被其他程序占用时说明占用者，并提供空闲端口
+            let Some(proxy_port) = conflict::preflight(config.allow_lan, config.proxy_port).await? else {
+                return Ok(());
+            };
+
             // 创建代理服务器
-            let proxy_server = Arc::new(ProxyServer::new(config.proxy_port));
+            let proxy_server = Arc::new(ProxyServer::new(proxy_port).allow_lan(config.allow_lan));
+            proxy_server.set_failover_policy(failover::FailoverPolicy::from_config(&config)).await;
+            notification::set_enabled(config.desktop_notifications && !profile::router());
+            mtu::set_limits(mtu::PacketLimits::from_config(&config));
+            obfs::set_rules(config.obfuscation.clone());
+            bypass::set_rules(&config.bypass);
+            sniff::set_enabled(config.sniff);
+            bittorrent::set_enabled(config.block_bt);
+            events::set_enabled(true);
+            performance::raise_priority();
+            performance::set_do_not_disturb(config.do_not_disturb.clone());
+            fd_usage::raise_limit();
+            fd_usage::spawn(&proxy_server.tasks());
+            lan::set_blocked(&config.blocked_devices);
+            lan::set_allowed(&config.allowed_clients);
+            lan::set_devices(&config.lan_devices);
+            classifier::set_signatures(classifier::Signatures::load());
+            sticky::set_ttl(config.sticky_ttl_secs);
+            outbound::set_binding(outbound::OutboundBinding::from_config(&config));
+            outbound::set_tcp_keepalive(config.tcp_keepalive_secs);
+            if let Some(conditions) = &simulate {
+                println!("🧪 开发者模式: 模拟网络状况 ({})", conditions);
+                warn!("正在模拟网络状况: {}", conditions);
+            }
+            simulate::set_conditions(simulate);
             proxy_server.set_node(selected_node.clone()).await;
 
             // 设置订阅URL和备用节点
             proxy_server.set_subscription_url(subscription_url.clone()).await;
-            proxy_server.set_backup_nodes(backup_nodes.clone()).await;
-            println!("🔄 设置了 {} 个备用节点", backup_nodes.len());
+            proxy_server.set_backup_nodes(backup_nodes).await;
+            println!("🔄 设置了 {} 个备用节点", proxy_server.backup_nodes().await.len());
 
             println!("🚀 正在启动代理服务器...");
             println!("📍 节点: {}", selected_node.name);
             println!("🌐 服务器: {}:{}", selected_node.server, selected_node.port);
-            println!("🚪 本地端口: {}", config.proxy_port);
+            println!("🚪 本地端口: {}", proxy_port);
             println!("📊 协议: {}", selected_node.protocol);
+            if let Some(name) = config::Config::instance() {
+                println!("🗂️  配置: {}", name);
+            }
+
+            if profile::router() {
+                println!("📦 运行模式: {}", profile::Profile::Router.display_name());
+            }
+
+            // 后台定期检查更新，路由器上由软件包管理器负责更新
+            if !profile::router() {
+                if let Some(handle) = updater::spawn_background_check(&config) {
+                    proxy_server.tasks().track("检查更新", handle);
+                }
+            }
+
+            // 没有游戏进行时定期重新选择节点，进入新的时段时按时段规则重选
+            auto_select::spawn_idle_reselect(Arc::clone(&proxy_server));
+
+            // 定期保存按天统计的流量
+            usage::spawn(Arc::clone(&proxy_server));
+
+            // 游戏中延迟持续过高时报警
+            alarm::set_config(config.latency_alarm.clone());
+            alarm::spawn(Arc::clone(&proxy_server));
+
+            // 游戏 UDP 会话空闲时发送保活包
+            udp_keepalive::set_config(config.udp_keepalive.clone());
+            udp_keepalive::spawn(Arc::clone(&proxy_server));
+
+            // 按游戏标记发往节点的数据包，供路由器 QoS 使用
+            dscp::set_config(config.dscp.clone());
+
+            // 在按流量计费的网络上暂停加速，路由器的上行网络由用户自行决定
+            if !profile::router() {
+                network::set_config(config.network.clone());
+                network::spawn(&proxy_server.tasks());
+            }
+
+            // 监听配置文件变化，运行中应用可热更新的配置
+            let watcher = hot_reload::ConfigWatcher::new(Arc::clone(&proxy_server), config.clone());
+            if let Err(e) = watcher.spawn() {
+                warn!("配置热重载启用失败: {}", e);
+            }
+
+            // 控制通道，供 cf status 等命令查询运行状态
+            let _daemon = match ipc::serve(Arc::clone(&proxy_server)).await {
+                Ok(guard) => Some(guard),
+                Err(e) => {
+                    warn!("控制通道启动失败: {}", e);
+                    None
+                }
+            };
+
+            // 已有实例在运行时接管它的监听端口和 UDP 会话，进行中的游戏不断线
+            let listen = proxy_server.listen_addr();
+            let inherited = match tokio::task::spawn_blocking(move || handover::request_takeover(listen)).await? {
+                Ok(inherited) => inherited,
+                Err(e) => {
+                    println!("⚠️  无法接管运行中的实例: {:#}", e);
+                    None
+                }
+            };
+            if let Some(inherited) = &inherited {
+                println!(
+                    "🔄 已从运行中的实例 (PID {}) 接管监听端口和 {} 个 UDP 会话，旧实例会在现有连接结束后退出",
+                    inherited.pid,
+                    inherited.sessions.len()
+                );
+            }
 
-            // 启动服务器 (这会阻塞直到服务器停止)
-            if let Err(e) = proxy_server.start().await {
+            // 透明重定向游戏流量，退出时自动移除规则
+            let mut redirect_mode = redirect.unwrap_or(config.redirect);
+            if redirect_mode != redirect::RedirectMode::Off && config::Config::instance().is_some() {
+                println!("⚠️  透明重定向的系统规则由所有实例共用，只能在默认配置中开启，本实例不重定向");
+                redirect_mode = redirect::RedirectMode::Off;
+            }
+            let redirect = redirect::start(redirect_mode, proxy_port, &config);
+
+            // 启动服务器 (这会阻塞直到服务器停止)，Ctrl+C 或 SIGTERM 时正常退出以清理控制通道和重定向规则
+            let result = tokio::select! {
+                result = async {
+                    match inherited {
+                        Some(inherited) => proxy_server.start_inherited(inherited).await,
+                        None => proxy_server.start().await,
+                    }
+                } => result,
+                _ = shutdown_signal() => Ok(()),
+                _ = ipc::stop_requested() => Ok(()),
+                taken = handover::wait_for_takeover(Arc::clone(&proxy_server)) => {
+                    match taken {
+                        Ok(pid) => {
+                            proxy_server.stop().await?;
+                            // 重定向规则由新实例继续维护
+                            if let Some(redirect) = redirect {
+                                redirect.hand_over();
+                            }
+                            println!("🔄 新实例 (PID {}) 已接管监听端口，等待现有连接结束后退出...", pid);
+                            handover::drain(&proxy_server).await;
+                            usage::flush(&proxy_server).await;
+                            println!("🛑 ClashFun 服务已交接给新实例");
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            warn!("无法等待新实例接管: {:#}", e);
+                            std::future::pending().await
+                        }
+                    }
+                }
+            };
+            // 等待健康监控等后台任务退出后再保存流量记录，之后不会再有任务修改状态
+            proxy_server.stop().await?;
+            handover::cleanup();
+            usage::flush(&proxy_server).await;
+            if let Err(e) = result {
                 error!("代理服务器启动失败: {}", e);
                 return Err(e);
             }
@@ -104,16 +433,66 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
         cli::Commands::Stop => {
             info!("停止 ClashFun 服务...");
 
-            // 这里可以实现进程间通信来停止服务
-            // 目前先显示简单信息，后续可以通过 PID 文件或 signal 来实现
-            println!("🛑 停止信号已发送");
-            println!("💡 如果服务仍在运行，请使用 Ctrl+C 强制停止");
+            match ipc::request_stop().await {
+                Ok(()) => {
+                    println!("🛑 停止信号已发送，等待服务退出...");
+                    // 守护进程退出后控制通道随之关闭
+                    for _ in 0..50 {
+                        if ipc::query_status().await.is_err() {
+                            println!("✅ ClashFun 服务已停止");
+                            return Ok(());
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                    }
+                    println!("⚠️  服务仍在运行，可能正在等待连接结束");
+                }
+                Err(e) => match ipc::daemon_info() {
+                    Some(info) => {
+                        println!("⚠️  找到守护进程信息 (PID {})，但无法连接: {}", info.pid, e);
+                        println!("💡 进程可能已异常退出，重新运行 'cf start' 即可");
+                    }
+                    None => {
+                        println!("💡 加速服务未运行");
+                        let instances = config::Config::instances();
+                        if config::Config::instance().is_none() && !instances.is_empty() {
+                            println!("💡 停止其他配置的实例: cf stop --profile <名称>（{}）", instances.join(", "));
+                        }
+                    }
+                },
+            }
+            Ok(())
+        }
+        cli::Commands::Get { key } => {
+            let config = config::Config::load().unwrap_or_default();
+            let report = ipc::query_status().await.ok();
+            let value = match key {
+                cli::GetKey::CurrentNode => match report {
+                    Some(report) => report.node,
+                    None => config.selected_node,
+                },
+                cli::GetKey::Latency => report
+                    .and_then(|report| report.last_check)
+                    .and_then(|check| check.latency_ms)
+                    .map(|ms| ms.to_string()),
+                cli::GetKey::Port => Some(report.map_or(config.proxy_port, |report| report.proxy_port).to_string()),
+            };
+            match value {
+                Some(value) => println!("{}", value),
+                None => process::exit(1),
+            }
+            Ok(())
+        }
+        #[cfg(feature = "tui")]
+        cli::Commands::Status { watch: true } => watch_status().await,
+        #[cfg(not(feature = "tui"))]
+        cli::Commands::Status { watch: true } => {
+            println!("❌ 此版本未包含终端界面，请使用 'cf status'");
             Ok(())
         }
-        cli::Commands::Status => {
+        cli::Commands::Status { watch: false } => {
             info!("检查服务状态...");
 
-            let config = config::Config::load()?;
+            let config = config::Config::load_or_recover()?;
 
             println!("📊 ClashFun 状态信息:");
             println!("  🔗 订阅链接: {}",
@@ -121,14 +500,117 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
             println!("  🌐 当前节点: {}",
                 config.selected_node.as_deref().unwrap_or("未选择"));
             println!("  🚪 代理端口: {}", config.proxy_port);
-            println!("  🤖 自动选择: {}", if config.auto_select { "开启" } else { "关闭" });
+            if config.auto_select.enabled {
+                let region = config.auto_select.region.as_deref().map(|r| format!(", 地区: {}", r)).unwrap_or_default();
+                println!("  🤖 自动选择: 开启 ({}{})", config.auto_select.policy.display_name(), region);
+                let active_rule = schedule::active(&config.auto_select.schedule, schedule::now()).map(|(index, _)| index);
+                for (index, rule) in config.auto_select.schedule.iter().enumerate() {
+                    let active = if active_rule == Some(index) { " (当前时段)" } else { "" };
+                    println!("  ⏰ 时段规则: {}{}", rule.describe(), active);
+                }
+            } else {
+                println!("  🤖 自动选择: 关闭");
+            }
+            if config.redirect != redirect::RedirectMode::Off {
+                println!("  🔀 透明重定向: {}", config.redirect.display_name());
+            }
+            if config.profile == profile::Profile::Router {
+                println!("  📦 运行模式: {}", config.profile.display_name());
+            }
+            if config::Config::is_portable() {
+                println!("  📦 便携模式: {}", config::Config::config_dir()?.display());
+            }
+            match config::Config::instance() {
+                Some(name) => println!("  🗂️  配置: {}", name),
+                None => {
+                    let instances = config::Config::instances();
+                    if !instances.is_empty() {
+                        println!("  🗂️  其他配置: {}（使用 --profile <名称> 查看）", instances.join(", "));
+                    }
+                }
+            }
 
-            // 检查服务状态 - 简单的端口检查
-            let service_status = match tokio::net::TcpListener::bind(format!("127.0.0.1:{}", config.proxy_port)).await {
-                Ok(_) => "未运行",
-                Err(_) => "正在运行",
-            };
-            println!("  ⚡ 服务状态: {}", service_status);
+            // 向守护进程查询实际运行状态
+            match ipc::query_status().await {
+                Ok(report) => {
+                    println!("  ⚡ 服务状态: 正在运行 (PID {}, 版本 {})", report.pid, report.version);
+                    println!("  ⏱️  运行时间: {}", format_duration(report.uptime_secs));
+                    println!("  🚪 监听端口: {}", report.proxy_port);
+                    match (&report.node, &report.preferred_node) {
+                        (Some(node), Some(preferred)) if node != preferred => {
+                            println!("  📍 使用节点: {} (首选 {} 不可用，已切换)", node, preferred);
+                        }
+                        (Some(node), _) => println!("  📍 使用节点: {}", node),
+                        (None, _) => println!("  📍 使用节点: 无"),
+                    }
+                    if let Some(failover) = &report.failover {
+                        println!("  🩺 故障切换: {}", describe_failover(failover));
+                    }
+                    if let Some(game) = &report.do_not_disturb {
+                        println!("  🔕 勿扰模式: {} 对局中，推迟订阅刷新、节点测速和检查更新", game.display_name());
+                    }
+                    if let Some(reason) = &report.paused {
+                        println!("  ⏸️  加速已暂停: {}", reason);
+                    }
+                    if let Some(conditions) = &report.simulate {
+                        println!("  🧪 模拟网络: {}", conditions);
+                    }
+                    println!("  🔌 活动连接: TCP {} / UDP 会话 {}", report.tcp_connections, report.udp_sessions);
+                    println!("  📊 累计流量: ↑ {} / ↓ {}", format_bytes(report.upload_bytes), format_bytes(report.download_bytes));
+                    if report.tcp_failed > 0 {
+                        println!("  🔚 已结束连接: 正常关闭 {} / 异常中断 {}", report.tcp_closed, report.tcp_failed);
+                    }
+                    let (rejected_sources, rejected_attempts) = report.rejected_clients;
+                    if rejected_attempts > 0 {
+                        println!(
+                            "  🚫 不在 allowed_clients 中: 拒绝了 {} 个来源的 {} 次连接",
+                            rejected_sources, rejected_attempts
+                        );
+                    }
+                    if let Some(fds) = &report.fds {
+                        println!("  🗂️  文件描述符: {} / {} ({}%)", fds.open, fds.limit, fds.percent());
+                        if fds.shed_sessions > 0 {
+                            println!("      接近上限时释放了 {} 个空闲 UDP 会话", fds.shed_sessions);
+                        }
+                    }
+                    if report.udp_dropped > 0 || report.udp_fragmented > 0 {
+                        println!("  ✂️  超大 UDP 包: 丢弃 {} / IP 分片 {}", report.udp_dropped, report.udp_fragmented);
+                    }
+                    let bt = &report.bittorrent;
+                    if bt.tcp_blocked > 0 || bt.udp_blocked > 0 {
+                        println!("  🚫 拦截 BitTorrent: TCP 连接 {} / UDP 包 {}", bt.tcp_blocked, bt.udp_blocked);
+                    }
+                    let classified = &report.classifier;
+                    if classified.flows > 0 {
+                        println!(
+                            "  🎯 流量识别: {} 条中识别 {} 条 (置信度不足 {} / 多个游戏冲突 {})",
+                            classified.flows, classified.identified, classified.low_confidence, classified.ambiguous
+                        );
+                        println!(
+                            "      依据命中: 进程 {} / IP 段 {} / 端口 {} / 数据特征 {}",
+                            classified.owner_hits, classified.range_hits, classified.port_hits, classified.payload_hits
+                        );
+                    }
+                    if !report.sniffed_hosts.is_empty() {
+                        let hosts: Vec<String> = report.sniffed_hosts.iter().map(|(host, count)| format!("{} ({})", host, count)).collect();
+                        println!("  🔍 识别到的域名: {}", hosts.join(", "));
+                    }
+                    if report.devices.iter().any(|device| !device.is_local()) {
+                        println!("  📱 局域网设备:");
+                        print_devices(&report.devices);
+                    }
+                }
+                Err(e) => {
+                    println!("  ⚡ 服务状态: 未运行");
+                    if let Some(info) = ipc::daemon_info() {
+                        println!("  ⚠️  找到守护进程信息 (PID {})，但无法连接: {}", info.pid, e);
+                    }
+                }
+            }
+
+            if let Some(version) = updater::UpdateCheckState::load().available_version() {
+                println!("  🆕 新版本: {} 可用，运行 'cf update' 更新", version);
+            }
 
             // 检测游戏
             let mut detector = game_detect::GameDetector::new();
@@ -150,10 +632,10 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
 
             Ok(())
         }
-        cli::Commands::Nodes => {
+        cli::Commands::Nodes { group_by, hide_unsupported } => {
             info!("获取节点列表...");
 
-            let config = config::Config::load()?;
+            let config = config::Config::load_or_recover()?;
 
             if let Some(url) = config.subscription_url {
                 println!("🔄 从订阅链接获取节点...");
@@ -164,29 +646,26 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                         match sub_manager.parse_nodes(&clash_config) {
                             Ok(mut nodes) => {
                                 println!("🔍 测试节点延迟...");
-                                if let Err(e) = sub_manager.test_all_nodes(&mut nodes).await {
+                                // 测速会按延迟排序，序号保持订阅中的顺序，与 select-node --index 一致
+                                let order: Vec<String> = nodes.iter().map(|n| n.name.clone()).collect();
+                                let tested = {
+                                    let _progress = progress::begin("测试节点延迟", nodes.len(), quiet);
+                                    sub_manager.test_all_nodes(&mut nodes).await
+                                };
+                                if let Err(e) = tested {
                                     println!("⚠️  延迟测试失败: {}", e);
                                 }
+                                record_latency(&nodes);
+                                nodes.sort_by_key(|n| order.iter().position(|name| *name == n.name));
+                                // 记住序号对应的节点，供 cf use 使用
+                                if let Err(e) = node_index::save(&nodes) {
+                                    warn!("保存节点序号失败: {}", e);
+                                }
 
                                 println!("🌐 节点列表 (共{}个):", nodes.len());
-                                println!("{:<4} {:<30} {:<20} {:<10} {:<10}", "序号", "节点名称", "服务器", "协议", "延迟(ms)");
-                                println!("{}", "-".repeat(80));
-
-                                for (i, node) in nodes.iter().enumerate() {
-                                    let latency = match node.latency {
-                                        Some(lat) if lat == u32::MAX => "超时".to_string(),
-                                        Some(lat) => format!("{}", lat),
-                                        None => "未测试".to_string(),
-                                    };
-
-                                    println!("{:<4} {:<30} {:<20} {:<10} {:<10}",
-                                        i + 1,
-                                        node.name.chars().take(30).collect::<String>(),
-                                        node.server.chars().take(20).collect::<String>(),
-                                        node.protocol,
-                                        latency
-                                    );
-                                }
+                                print_nodes(&nodes, group_by, hide_unsupported);
+                                print_unsupported_summary(&nodes, hide_unsupported);
+                                print_merged_summary(&nodes);
                             }
                             Err(e) => {
                                 println!("❌ 解析节点失败: {}", e);
@@ -194,7 +673,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                         }
                     }
                     Err(e) => {
-                        println!("❌ 获取订阅失败: {}", e);
+                        print_error("获取订阅失败", e);
                     }
                 }
             } else {
@@ -205,21 +684,38 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
 
             Ok(())
         }
-        cli::Commands::SetSubscription { url } => {
-            info!("设置订阅链接: {}", url);
+        cli::Commands::SetSubscription { url, from_clipboard, check } => {
+            let url = match url {
+                Some(url) => url,
+                None => match clipboard::read_subscription_url() {
+                    Ok(url) => url,
+                    Err(e) => {
+                        println!("❌ {}", e);
+                        return Ok(());
+                    }
+                },
+            };
+            // 订阅链接中的 token 相当于密码，不在终端和日志里完整显示
+            let masked = provider::mask_url(&url);
+            if check {
+                check_subscription(&url).await;
+                return Ok(());
+            }
+            info!("设置订阅链接: {}", masked);
 
-            let mut config = config::Config::load()?;
-            config.subscription_url = Some(url.clone());
+            let mut config = config::Config::load_or_recover()?;
+            config.subscription_url = Some(url);
             config.save()?;
 
-            println!("✅ 订阅链接已设置: {}", url);
+            if from_clipboard {
+                println!("📋 已从剪贴板读取订阅链接");
+            }
+            println!("✅ 订阅链接已设置: {}", masked);
             println!("💡 使用 'cf nodes' 查看可用节点");
             Ok(())
         }
-        cli::Commands::SelectNode { name } => {
-            info!("切换到节点: {}", name);
-
-            let mut config = config::Config::load()?;
+        cli::Commands::SelectNode { name, index, exact } => {
+            let mut config = config::Config::load_or_recover()?;
 
             if let Some(url) = &config.subscription_url {
                 let sub_manager = subscription::SubscriptionManager::new();
@@ -227,15 +723,33 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                     Ok(clash_config) => {
                         match sub_manager.parse_nodes(&clash_config) {
                             Ok(nodes) => {
-                                // 查找匹配的节点
-                                if let Some(node) = nodes.iter().find(|n| n.name.contains(&name)) {
-                                    config.selected_node = Some(node.name.clone());
-                                    config.save()?;
-                                    println!("🔄 已切换到节点: {}", node.name);
-                                    println!("📍 服务器: {}:{}", node.server, node.port);
-                                } else {
-                                    println!("❌ 未找到包含 '{}' 的节点", name);
-                                    println!("💡 使用 'cf nodes' 查看可用节点");
+                                let node = match (name, index) {
+                                    (_, Some(index)) => match index.checked_sub(1).and_then(|i| nodes.get(i)) {
+                                        Some(node) => Some(node),
+                                        None => {
+                                            println!("❌ 序号 {} 超出范围 (1-{})", index, nodes.len());
+                                            None
+                                        }
+                                    },
+                                    (Some(name), None) => lookup_node(&nodes, &name, exact),
+                                    #[cfg(feature = "tui")]
+                                    (None, None) if io::stdin().is_terminal() && io::stdout().is_terminal() => {
+                                        match picker::pick(&nodes, config.selected_node.as_deref())? {
+                                            Some(i) => Some(&nodes[i]),
+                                            None => {
+                                                println!("💡 已取消");
+                                                None
+                                            }
+                                        }
+                                    }
+                                    (None, None) => {
+                                        println!("❌ 请指定节点名称或 --index");
+                                        None
+                                    }
+                                };
+
+                                if let Some(node) = node {
+                                    switch_to_node(&mut config, node)?;
                                 }
                             }
                             Err(e) => {
@@ -244,7 +758,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                         }
                     }
                     Err(e) => {
-                        println!("❌ 获取订阅失败: {}", e);
+                        print_error("获取订阅失败", e);
                     }
                 }
             } else {
@@ -253,10 +767,51 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
 
             Ok(())
         }
-        cli::Commands::Update => {
+        cli::Commands::Use { index } => {
+            let entries = node_index::load()?;
+            if entries.is_empty() {
+                println!("❌ 还没有节点序号，请先运行 'cf nodes'");
+                return Ok(());
+            }
+            if index == 0 || index > entries.len() {
+                println!("❌ 序号 {} 超出范围 (1-{})", index, entries.len());
+                return Ok(());
+            }
+
+            let mut config = config::Config::load_or_recover()?;
+            let Some(url) = config.subscription_url.clone() else {
+                println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                return Ok(());
+            };
+            let sub_manager = subscription::SubscriptionManager::new();
+            match sub_manager.fetch_subscription(&url).await {
+                Ok(clash_config) => match sub_manager.parse_nodes(&clash_config) {
+                    Ok(nodes) => match node_index::resolve(&entries, index, &nodes) {
+                        Some(node) => switch_to_node(&mut config, node)?,
+                        None => println!(
+                            "❌ 订阅中已找不到节点 {}，请重新运行 'cf nodes'",
+                            entries[index - 1].name
+                        ),
+                    },
+                    Err(e) => println!("❌ 解析节点失败: {}", e),
+                },
+                Err(e) => print_error("获取订阅失败", e),
+            }
+
+            Ok(())
+        }
+        cli::Commands::Update { channel, to, check } => {
             info!("检查更新...");
 
-            let updater = updater::Updater::new();
+            let mut config = config::Config::load_or_recover()?;
+            let updater = updater::Updater::from_config(&config)?;
+            if let Some(channel) = channel {
+                if channel != config.update_channel {
+                    config.update_channel = channel;
+                    config.save()?;
+                    println!("🔀 已切换到 {} 更新通道", channel);
+                }
+            }
 
             // 首先检查版本冲突
             match updater.check_version_conflicts().await {
@@ -266,7 +821,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                         println!("   📁 {}", conflict.display());
                     }
                     println!("💡 建议先运行 'cf reset' 清理配置，然后手动删除重复的安装文件");
-                    println!("💡 或者使用 'cf force-uninstall' 进行完全清理后重新安装");
+                    println!("💡 或者使用 'cf uninstall --purge' 进行完全清理后重新安装");
                 }
                 Ok(_) => {
                     println!("✅ 未检测到版本冲突");
@@ -276,68 +831,74 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                 }
             }
 
-            // 检查更新
-            match updater.check_for_updates().await {
-                Ok(update_info) => {
-                    println!("📊 版本信息:");
-                    println!("   当前版本: {}", update_info.current_version);
-
-                    if let Some(latest) = &update_info.latest_version {
-                        println!("   最新版本: {}", latest);
-                    }
-
-                    if update_info.update_available {
-                        println!("🚀 发现新版本！");
+            let request = updater::UpdateRequest {
+                channel: config.update_channel,
+                version: to,
+                check_only: check,
+            };
+            println!("📊 当前版本: {} (通道: {})", env!("CARGO_PKG_VERSION"), config.update_channel);
 
-                        if let Some(notes) = &update_info.release_notes {
-                            println!("📝 更新说明:");
-                            for line in notes.lines().take(10) {
-                                println!("   {}", line);
-                            }
+            let mut installed = false;
+            let mut on_progress = |event: updater::UpdateEvent| match &event {
+                updater::UpdateEvent::Downloading { .. } => {
+                    print!("\r{}", event.message());
+                    let _ = io::stdout().flush();
+                }
+                updater::UpdateEvent::Installing => {
+                    println!();
+                    println!("{}", event.message());
+                }
+                updater::UpdateEvent::Finished(_) => {
+                    installed = true;
+                    println!("{}", event.message());
+                }
+                updater::UpdateEvent::Available(info) => {
+                    println!("{}", event.message());
+                    if let Some(notes) = &info.release_notes {
+                        println!("📝 更新说明:");
+                        for line in notes.lines().take(20) {
+                            println!("   {}", line);
                         }
+                    }
+                    if check {
+                        println!("💡 运行 'cf update' 安装新版本");
+                    }
+                }
+                _ => println!("{}", event.message()),
+            };
 
-                        println!("🔄 正在自动更新...");
-
-                        if let Some(download_url) = &update_info.download_url {
-                            match updater.perform_update(download_url).await {
-                                Ok(()) => {
-                                    println!("✅ 更新完成！");
-                                    println!("💡 请重新运行 'cf' 命令使用新版本");
-                                }
-                                Err(e) => {
-                                    error!("更新失败: {}", e);
-                                    println!("❌ 自动更新失败: {}", e);
-                                    println!("💡 请尝试手动更新:");
-                                    println!("   curl -fsSL https://raw.githubusercontent.com/ink1ing/clashfun/master/install.sh | sh");
-                                }
-                            }
-                        } else {
-                            println!("❌ 未找到适合当前平台的更新文件");
-                        }
-                    } else {
-                        println!("✅ 当前已是最新版本");
+            match updater.run(&request, &mut on_progress).await {
+                Ok(_) if installed => {
+                    if ipc::query_status().await.is_ok() {
+                        println!("💡 加速服务正在运行，再次运行 'cf start' 即可由新版本接管，进行中的游戏连接不会断开");
                     }
                 }
+                Ok(_) => {}
                 Err(e) => {
-                    error!("检查更新失败: {}", e);
-                    println!("❌ 检查更新失败: {}", e);
-                    println!("💡 请检查网络连接或手动更新:");
+                    error!("更新失败: {:#}", e);
+                    print_error("更新失败", e);
+                    println!("💡 再次运行 'cf update' 会从断点继续下载");
+                    println!("💡 或者尝试手动更新:");
                     println!("   curl -fsSL https://raw.githubusercontent.com/ink1ing/clashfun/master/install.sh | sh");
                 }
             }
 
             Ok(())
         }
-        cli::Commands::Uninstall => {
+        cli::Commands::Uninstall { purge, yes } => {
             info!("卸载 ClashFun...");
-            // TODO: 实现卸载逻辑
-            println!("🗑️  ClashFun 已卸载");
-            Ok(())
+            uninstall::run(purge, yes)
         }
-        cli::Commands::AutoSelect => {
+        cli::Commands::AutoSelect { policy, region, top } => {
             info!("自动选择最优节点...");
 
-            let mut config = config::Config::load()?;
+            let mut config = config::Config::load_or_recover()?;
+            // 命令行参数只对本次选择生效
+            let mut options = config.auto_select.clone();
+            options.policy = policy.unwrap_or(options.policy);
+            options.top_n = top.unwrap_or(options.top_n);
+            options.region = region.or(options.region);
+            let region = options.region.as_deref().map(auto_select::parse_region).transpose()?;
 
             if let Some(url) = &config.subscription_url {
                 println!("🔍 获取并测试所有节点...");
@@ -346,24 +907,28 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                 match sub_manager.fetch_subscription(url).await {
                     Ok(clash_config) => {
                         match sub_manager.parse_nodes(&clash_config) {
-                            Ok(mut nodes) => {
-                                println!("🧪 测试节点延迟...");
-                                if let Err(e) = sub_manager.test_all_nodes(&mut nodes).await {
-                                    println!("⚠️  延迟测试失败: {}", e);
-                                }
-
-                                // 找到延迟最低的可用节点
-                                if let Some(best_node) = nodes.iter()
-                                    .filter(|n| n.latency.unwrap_or(u32::MAX) < u32::MAX)
-                                    .min_by_key(|n| n.latency.unwrap_or(u32::MAX)) {
-
-                                    config.selected_node = Some(best_node.name.clone());
+                            Ok(nodes) => {
+                                println!("🧪 测试节点延迟 ({} 轮)...", options.rounds.max(1));
+                                let tests = nodes.iter().filter(|node| node.is_supported()).count() * options.rounds.max(1) as usize;
+                                let selected = {
+                                    let _progress = progress::begin("测试节点延迟", tests, quiet);
+                                    auto_select::select(&options, &nodes).await?
+                                };
+                                if let Some((best, _)) = selected {
+                                    let best_node = &best.node;
+                                    config.select_node(best_node);
                                     config.save()?;
 
-                                    println!("🚀 自动选择最优节点: {}", best_node.name);
+                                    println!("🚀 自动选择最优节点: {} (策略: {})", best_node.name, options.policy.display_name());
+                                    if let Some((_, rule)) = schedule::active(&options.schedule, schedule::now()) {
+                                        let note = if rule.matches(best_node) { "" } else { "，但没有可用的优先节点" };
+                                        println!("⏰ 时段规则: {}{}", rule.describe(), note);
+                                    }
                                     println!("📍 服务器: {}:{}", best_node.server, best_node.port);
-                                    println!("⚡ 延迟: {}ms", best_node.latency.unwrap_or(0));
+                                    println!("⚡ 延迟: {}ms  抖动: {}ms  丢包: {}%", best.median_ms, best.jitter_ms, best.loss_percent);
                                     println!("📊 协议: {}", best_node.protocol);
+                                } else if let Some(region) = region {
+                                    println!("❌ {} 地区没有可用的节点", region);
                                 } else {
                                     println!("❌ 没有找到可用的节点");
                                 }
@@ -374,7 +939,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                         }
                     }
                     Err(e) => {
-                        println!("❌ 获取订阅失败: {}", e);
+                        print_error("获取订阅失败", e);
                     }
                 }
             } else {
@@ -415,44 +980,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
         }
         cli::Commands::ForceUninstall => {
             info!("执行一键卸载...");
-
-            println!("🗑️ 正在卸载 ClashFun...");
-
-            // 获取当前可执行文件路径
-            let current_exe = std::env::current_exe()?;
-            println!("📁 当前程序路径: {}", current_exe.display());
-
-            // 删除配置文件
-            if let Some(config_dir) = dirs::config_dir() {
-                let cf_config_dir = config_dir.join("cf");
-                if cf_config_dir.exists() {
-                    match fs::remove_dir_all(&cf_config_dir) {
-                        Ok(()) => println!("✅ 配置目录已删除: {}", cf_config_dir.display()),
-                        Err(e) => println!("⚠️  删除配置目录失败: {}", e),
-                    }
-                } else {
-                    println!("💡 没有找到配置目录");
-                }
-            }
-
-            // 删除缓存文件
-            if let Some(cache_dir) = dirs::cache_dir() {
-                let cf_cache_dir = cache_dir.join("cf");
-                if cf_cache_dir.exists() {
-                    match fs::remove_dir_all(&cf_cache_dir) {
-                        Ok(()) => println!("✅ 缓存目录已删除: {}", cf_cache_dir.display()),
-                        Err(e) => println!("⚠️  删除缓存目录失败: {}", e),
-                    }
-                } else {
-                    println!("💡 没有找到缓存目录");
-                }
-            }
-
-            println!("🎉 ClashFun 卸载完成！");
-            println!("💡 请手动删除可执行文件: {}", current_exe.display());
-            println!("💡 可以使用命令: rm {}", current_exe.display());
-
-            Ok(())
+            uninstall::run(true, true)
         }
         cli::Commands::Reset => {
             info!("重置所有配置...");
@@ -460,8 +988,7 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
             println!("🔄 正在重置 ClashFun 配置...");
 
             // 删除配置文件但保留程序
-            if let Some(config_dir) = dirs::config_dir() {
-                let cf_config_dir = config_dir.join("cf");
+            if let Ok(cf_config_dir) = config::Config::config_dir() {
                 if cf_config_dir.exists() {
                     match fs::remove_dir_all(&cf_config_dir) {
                         Ok(()) => {
@@ -486,14 +1013,14 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                     println!("💡 现在可以重新设置订阅: cf set-subscription <URL>");
                 },
                 Err(e) => {
-                    println!("❌ 重置配置失败: {}", e);
+                    let e = anyhow::Error::new(e);
+                    println!("❌ 重置配置失败: {:#}", e);
                     return Err(e);
                 }
             }
 
             // 删除缓存
-            if let Some(cache_dir) = dirs::cache_dir() {
-                let cf_cache_dir = cache_dir.join("cf");
+            if let Ok(cf_cache_dir) = config::Config::cache_dir() {
                 if cf_cache_dir.exists() {
                     match fs::remove_dir_all(&cf_cache_dir) {
                         Ok(()) => println!("✅ 缓存已清除"),
@@ -506,20 +1033,1457 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
 
             Ok(())
         }
-    }
-}
+        cli::Commands::ImportClash { path } => {
+            info!("导入 Clash 配置...");
+
+            let path = match path.or_else(clash_import::locate_clash_config) {
+                Some(path) => path,
+                None => {
+                    println!("❌ 没有找到 Clash / Clash Verge 的配置");
+                    println!("💡 请指定配置路径: cf import-clash <PATH>");
+                    return Ok(());
+                }
+            };
 
-async fn run_interactive_mode() -> anyhow::Result<()> {
-    info!("启动 ClashFun 交互模式...");
+            println!("📁 读取 Clash 配置: {}", path.display());
+            let imported = clash_import::import_from(&path)?;
 
-    // 加载配置
-    let config = Arc::new(tokio::sync::RwLock::new(config::Config::load().unwrap_or_default()));
+            let mut config = config::Config::load_or_recover()?;
+            imported.apply_to(&mut config);
+            config.save()?;
+
+            println!("✅ 已从 {} 导入设置:", imported.source.display());
+            println!("  🔗 订阅链接: {}", imported.subscription_url.as_deref().unwrap_or("未找到"));
+            println!("  🌐 选中节点: {}", imported.selected_node.as_deref().unwrap_or("未找到"));
+            match imported.proxy_port {
+                Some(port) => println!("  🚪 代理端口: {}", port),
+                None => println!("  🚪 代理端口: 未找到，保持 {}", config.proxy_port),
+            }
+            if imported.proxy_count > 0 {
+                println!("  📦 内联节点: {} 个", imported.proxy_count);
+            }
+            if !imported.bypass.is_empty() {
+                println!("  🛣️  直连规则: 由 DIRECT 规则转换 {} 条，用 'cf bypass list' 查看", imported.bypass.len());
+            }
+            if !imported.dropped_rules.is_empty() {
+                // 规则很多时只列出前几条
+                let shown = 10;
+                println!("  ⚠️  以下 {} 条规则 ClashFun 无法表达，已忽略:", imported.dropped_rules.len());
+                for rule in imported.dropped_rules.iter().take(shown) {
+                    println!("     {}", rule);
+                }
+                if imported.dropped_rules.len() > shown {
+                    println!("     ... 等 {} 条", imported.dropped_rules.len());
+                }
+            }
+            println!("💡 使用 'cf nodes' 查看可用节点");
+
+            Ok(())
+        }
+        cli::Commands::GenerateClash { out } => {
+            // 配置默认输出到标准输出，提示信息输出到标准错误，便于重定向
+            let config = config::Config::load_or_recover()?;
+            let Some(url) = config.subscription_url.clone() else {
+                eprintln!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                return Ok(());
+            };
+
+            let sub_manager = subscription::SubscriptionManager::new();
+            let clash_config = sub_manager.fetch_subscription(&url).await?;
+            let nodes = sub_manager.parse_nodes(&clash_config)?;
+            let export = clash_export::generate(&config, &clash_config, &nodes);
+            let yaml = export.to_yaml()?;
+
+            match &out {
+                Some(path) => {
+                    fs::write(path, &yaml)?;
+                    eprintln!("✅ 已生成 Clash 配置: {}", path.display());
+                }
+                None => print!("{}", yaml),
+            }
+            if !export.ignored.is_empty() {
+                eprintln!("⚠️  以下设置在 Clash 中没有对应项，已忽略:");
+                for item in &export.ignored {
+                    eprintln!("  {}", item);
+                }
+            }
+            eprintln!(
+                "💡 所有流量默认经过 {} 策略组，'cf import-clash' 可以把该配置导回 ClashFun",
+                clash_export::SELECT_GROUP
+            );
+
+            Ok(())
+        }
+        cli::Commands::PortMap { game, ports, remove, lease_minutes } => {
+            let mut mappings = Vec::new();
+            if let Some(game) = &game {
+                let server_ports = game.server_ports();
+                if server_ports.is_empty() {
+                    println!("❌ {} 不支持自建服务器", game.display_name());
+                    return Ok(());
+                }
+                mappings.extend(server_ports);
+            }
+            for spec in &ports {
+                mappings.extend(portmap::parse_port_spec(spec)?);
+            }
+            // 游戏端口和 --port 可能重复
+            let mappings: Vec<_> = mappings.into_iter().fold(Vec::new(), |mut unique, mapping| {
+                if !unique.contains(&mapping) {
+                    unique.push(mapping);
+                }
+                unique
+            });
+            if mappings.is_empty() {
+                println!("❌ 请指定游戏或端口，例如: cf port-map dst 或 cf port-map --port 25565/tcp");
+                return Ok(());
+            }
 
-    // 初始化游戏检测器
-    let game_detector = Arc::new(tokio::sync::RwLock::new(game_detect::GameDetector::new()));
+            println!("🔍 查找支持端口映射的路由器...");
+            let mapper = match portmap::PortMapper::discover().await {
+                Ok(mapper) => mapper,
+                Err(e) => {
+                    println!("❌ {}", e);
+                    println!("💡 请在路由器设置中开启 UPnP 或 NAT-PMP，或手动添加端口转发");
+                    return Ok(());
+                }
+            };
+            println!("✅ 使用 {} 映射端口", mapper.method());
+
+            let lease = std::time::Duration::from_secs(lease_minutes * 60);
+            let mut mapped = Vec::new();
+            for (protocol, port) in mappings {
+                let result = if remove {
+                    mapper.remove(protocol, port).await.map(|_| port)
+                } else {
+                    mapper.add(protocol, port, lease).await
+                };
+                match result {
+                    Ok(_) if remove => println!("  🗑️  已删除 {} {}", protocol.as_str(), port),
+                    Ok(external) => {
+                        println!("  ✅ {} {} → 外部端口 {}", protocol.as_str(), port, external);
+                        mapped.push((protocol, external));
+                    }
+                    Err(e) => println!("  ❌ {} {}: {}", protocol.as_str(), port, e),
+                }
+            }
+            if remove {
+                return Ok(());
+            }
+
+            match mapper.external_ip().await {
+                Ok(ip) if portmap::is_behind_another_nat(ip) => {
+                    println!("⚠️  路由器的外网地址 {} 仍是内网地址，上层还有一层 NAT（例如运营商级 NAT）", ip);
+                    println!("💡 其他玩家依然无法直接连接，需要向运营商申请公网 IP 或使用联机平台的中继");
+                }
+                Ok(ip) => {
+                    println!("🌐 外网地址: {}", ip);
+                    for (protocol, port) in &mapped {
+                        if *protocol != game_detect::PortProtocol::Tcp {
+                            continue;
+                        }
+                        if portmap::check_reachable(ip, *port).await {
+                            println!("  ✅ {}:{} 可以从外网访问", ip, port);
+                        } else {
+                            println!("  ❓ {}:{} 无法在本机验证（路由器可能不支持回环访问），请让好友尝试连接", ip, port);
+                        }
+                    }
+                }
+                Err(e) => println!("⚠️  无法获取外网地址: {}", e),
+            }
+            println!("💡 映射 {} 分钟后过期，开服期间请定期重新运行，结束后可用 --remove 删除", lease_minutes);
+            Ok(())
+        }
+        cli::Commands::Nat { servers } => {
+            let servers = if servers.is_empty() {
+                nat::DEFAULT_STUN_SERVERS.iter().map(|s| s.to_string()).collect()
+            } else {
+                servers
+            };
+
+            println!("🔍 正在通过 STUN 检测 NAT 类型...");
+            match nat::detect(&servers).await {
+                Ok(report) => {
+                    println!("📡 本机地址: {}", report.local);
+                    if let Some(mapped) = report.mapped {
+                        println!("🌐 公网映射: {}", mapped);
+                    }
+                    println!("🔒 NAT 类型: {}", report.nat_type.display_name());
+                    println!("🎮 {}", report.nat_type.p2p_verdict());
+                    if matches!(
+                        report.nat_type,
+                        nat::NatType::Symmetric | nat::NatType::PortRestrictedCone | nat::NatType::RestrictedCone
+                    ) {
+                        println!("💡 可以尝试 'cf port-map <游戏>' 在路由器上开放端口");
+                    }
+                    if report.nat_type == nat::NatType::Cone {
+                        println!("💡 STUN 服务器不支持过滤行为测试，可用 --server 指定支持 RFC 5780 的服务器");
+                    }
+                }
+                Err(e) => println!("❌ NAT 检测失败: {}", e),
+            }
+
+            // 经由节点的检测需要节点本身响应 STUN
+            match ipc::query_status().await {
+                Ok(status) => {
+                    let node = status.node.unwrap_or_else(|| "未知节点".to_string());
+                    println!("🔄 通过当前节点 {} 检测...", node);
+                    match nat::probe_via_proxy(status.proxy_port).await {
+                        Some(response) => println!("  🌐 经节点的公网映射: {}", response.mapped),
+                        None => {
+                            println!("  ⚠️  节点没有响应 STUN 请求，无法检测经过节点后的 NAT 类型");
+                            println!("  💡 加速只转发游戏流量，不会改变上面检测到的本机 NAT 类型");
+                        }
+                    }
+                }
+                Err(_) => println!("💡 加速服务未运行，仅检测了直连网络"),
+            }
+            Ok(())
+        }
+        cli::Commands::Capture { game, out, max_payload, duration } => {
+            let path = std::env::current_dir()?.join(out.unwrap_or_else(capture::default_file_name));
+            let options = capture::CaptureOptions {
+                path,
+                game: game.clone(),
+                max_payload,
+                duration_secs: duration,
+            };
+            if ipc::query_status().await.is_err() {
+                println!("❌ 加速服务未运行，请先运行 'cf start'");
+                return Ok(());
+            }
+            if let Err(e) = ipc::start_capture(options).await {
+                println!("❌ 无法开始抓包: {}", e);
+                return Ok(());
+            }
+
+            println!("🔍 正在抓包 {} 秒，按 Ctrl+C 提前结束...", duration);
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(duration)) => {}
+                _ = tokio::signal::ctrl_c() => println!(),
+            }
+
+            match ipc::stop_capture().await {
+                Ok(summary) => {
+                    println!("✅ 抓包完成: {}", summary.path.display());
+                    println!("📊 共 {} 个数据包，{} 字节", summary.packets, summary.bytes);
+                    if let Some(game) = game {
+                        println!("🎮 匹配 {} 特征的 UDP 包: {}", game.display_name(), summary.matched);
+                    }
+                    println!("💡 可以用 Wireshark 打开，反馈问题时请附上该文件");
+                }
+                Err(e) => println!("❌ 停止抓包失败: {}", e),
+            }
+            Ok(())
+        }
+        cli::Commands::Report { rounds, html } => {
+            let config = config::Config::load_or_recover()?;
+            let Some(url) = &config.subscription_url else {
+                println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                return Ok(());
+            };
+
+            let sub_manager = subscription::SubscriptionManager::new();
+            let clash_config = sub_manager.fetch_subscription(url).await?;
+            let mut nodes = sub_manager.parse_nodes(&clash_config)?;
+            if nodes.is_empty() {
+                println!("❌ 订阅中没有节点");
+                return Ok(());
+            }
+
+            println!("🔍 测试 {} 个节点 ({} 轮)...", nodes.len(), rounds);
+            let mut current = Vec::new();
+            for _ in 0..rounds {
+                sub_manager.test_all_nodes(&mut nodes).await?;
+                current.extend(history::samples(&nodes));
+            }
+            if let Err(e) = history::append(&current) {
+                warn!("保存延迟历史失败: {}", e);
+            }
+
+            println!();
+            println!("📊 当前各地区延迟:");
+            let mut table = table::Table::new(&["地区", "节点", "中位延迟", "丢包率", "最佳节点"]).indent(2).right(1);
+            let regions = report::region_rows(&nodes, &current);
+            for row in &regions {
+                table.row(vec![
+                    row.region.to_string(),
+                    row.nodes.to_string(),
+                    format_median(row.cell.median_ms),
+                    format!("{}%", row.cell.loss_percent),
+                    row.best.clone().unwrap_or_else(|| "-".to_string()),
+                ]);
+            }
+            table.print();
+
+            let mut samples = history::load()?;
+            history::merge_aliases(&mut samples, &nodes);
+            let heatmap = history::heatmap(&samples);
+            println!();
+            println!("🗓️  各时段中位延迟 / 丢包率 (最近 30 天, 共 {} 次测试):", samples.len());
+            let labels: Vec<String> = (0..history::BUCKETS).map(history::bucket_label).collect();
+            let headers: Vec<&str> = std::iter::once("地区").chain(labels.iter().map(String::as_str)).collect();
+            let mut table = table::Table::new(&headers).indent(2);
+            for (name, cells) in &heatmap {
+                let mut row = vec![name.to_string()];
+                row.extend(cells.iter().map(|cell| match cell {
+                    Some(cell) => format!("{} {}/{}%", latency_level(cell), format_median(cell.median_ms), cell.loss_percent),
+                    None => "-".to_string(),
+                }));
+                table.row(row);
+            }
+            table.print();
+            println!("  🟢 <80ms  🟡 <150ms  🟠 <250ms  🔴 更高或丢包严重");
+
+            // 晚上 20-24 点是游戏高峰
+            let evening = history::BUCKETS - 1;
+            match history::best_region(&heatmap, evening) {
+                Some((name, cell)) => println!(
+                    "💡 晚高峰 ({}) 推荐地区: {} (中位延迟 {}, 丢包 {}%)",
+                    history::bucket_label(evening), name, format_median(cell.median_ms), cell.loss_percent
+                ),
+                None => println!("💡 还没有晚高峰 ({}) 的测试记录，可以在晚上再运行一次 'cf report'", history::bucket_label(evening)),
+            }
+
+            if let Some(path) = html {
+                const USAGE_DAYS: u32 = 7;
+                let usage = usage::Usage::load().summary(USAGE_DAYS, chrono::Local::now().date_naive());
+                // 服务没有运行时只是少了本次运行的部分
+                let status = ipc::query_status().await.ok();
+                let health = ipc::query_node_health().await.ok();
+                let page = report::render(&report::HtmlReport {
+                    generated_at: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+                    regions: &regions,
+                    heatmap: &heatmap,
+                    history_samples: samples.len(),
+                    usage: &usage,
+                    usage_days: USAGE_DAYS,
+                    status: status.as_ref(),
+                    health: health.as_ref(),
+                });
+                fs::write(&path, page).map_err(|e| anyhow::anyhow!("无法写入报告 {:?}: {}", path, e))?;
+                println!("📄 HTML 报告已保存到 {}", path.display());
+            }
+            Ok(())
+        }
+        cli::Commands::Device { action } => {
+            match action {
+                cli::DeviceAction::List => match ipc::query_status().await {
+                    Ok(report) if report.devices.is_empty() => println!("📱 还没有设备通过加速服务"),
+                    Ok(report) => {
+                        println!("📱 经过加速的设备 ({} 台):", report.devices.len());
+                        print_devices(&report.devices);
+                    }
+                    Err(_) => println!("❌ 加速服务未运行，请先运行 'cf start'"),
+                },
+                cli::DeviceAction::Block { device } => {
+                    let mut config = config::Config::load_or_recover()?;
+                    let device = lan::normalize(&device);
+                    if config.blocked_devices.contains(&device) {
+                        println!("💡 设备 {} 已在禁用列表中", device);
+                    } else {
+                        config.blocked_devices.push(device.clone());
+                        config.save()?;
+                        println!("✅ 已禁止设备 {} 使用加速", device);
+                    }
+                    if !config.allow_lan {
+                        println!("💡 当前未开启局域网共享 (allow_lan)，其他设备本来就无法连接");
+                    }
+                }
+                cli::DeviceAction::Allow { device } => {
+                    let mut config = config::Config::load_or_recover()?;
+                    let device = lan::normalize(&device);
+                    let before = config.blocked_devices.len();
+                    config.blocked_devices.retain(|blocked| *blocked != device);
+                    if config.blocked_devices.len() < before {
+                        config.save()?;
+                        println!("✅ 已允许设备 {} 使用加速", device);
+                    } else {
+                        println!("💡 设备 {} 不在禁用列表中", device);
+                    }
+                }
+                cli::DeviceAction::Ports { device, console, ports, clear } => {
+                    let mut config = config::Config::load_or_recover()?;
+                    let device = lan::normalize(&device);
+                    if clear {
+                        if config.lan_devices.remove(&device).is_some() {
+                            config.save()?;
+                            println!("✅ 设备 {} 的全部流量将重新经过加速", device);
+                        } else {
+                            println!("💡 设备 {} 没有设置游戏端口", device);
+                        }
+                        return Ok(());
+                    }
+                    if console.is_none() && ports.is_empty() {
+                        match config.lan_devices.get(&device) {
+                            Some(settings) => println!("🎮 设备 {} 只有 {} 经过加速", device, settings.describe()),
+                            None => println!("💡 设备 {} 的全部流量都经过加速，用 --console 或 --port 设置游戏端口", device),
+                        }
+                        return Ok(());
+                    }
+
+                    let settings = lan::LanDeviceConfig { console, ports };
+                    let check = std::collections::HashMap::from([(device.clone(), settings.clone())]);
+                    if let Err(e) = lan::validate_devices(&check) {
+                        println!("❌ {}", e);
+                        return Ok(());
+                    }
+                    println!("✅ 设备 {} 只有 {} 经过加速，其他连接直连", device, settings.describe());
+                    config.lan_devices.insert(device, settings);
+                    config.save()?;
+                    if config.redirect == redirect::RedirectMode::Off {
+                        println!("💡 只有透明重定向 (redirect) 的连接能识别目标端口，UDP 流量始终经过加速");
+                    }
+                }
+            }
+            Ok(())
+        }
+        cli::Commands::Bypass { action } => {
+            let mut config = config::Config::load_or_recover()?;
+
+            match action {
+                cli::BypassAction::Add { rules, preset } => {
+                    let mut inputs = rules;
+                    if let Some(preset) = preset {
+                        inputs.extend(preset.rules().iter().map(|rule| rule.to_string()));
+                    }
+                    if inputs.is_empty() {
+                        println!("❌ 请指定要添加的规则或 --preset");
+                        return Ok(());
+                    }
+
+                    let mut added = 0;
+                    for input in inputs {
+                        // 统一保存为规范格式，避免同一规则的不同写法重复出现
+                        let rule = match input.parse::<bypass::BypassRule>() {
+                            Ok(rule) => rule.to_string(),
+                            Err(e) => {
+                                println!("❌ {}", e);
+                                continue;
+                            }
+                        };
+                        if config.bypass.contains(&rule) {
+                            println!("💡 已存在: {}", rule);
+                        } else {
+                            println!("✅ 已添加: {}", rule);
+                            config.bypass.push(rule);
+                            added += 1;
+                        }
+                    }
+                    if added > 0 {
+                        config.save()?;
+                    }
+                }
+                cli::BypassAction::Remove { rules } => {
+                    let mut removed = 0;
+                    for input in rules {
+                        let rule = input
+                            .parse::<bypass::BypassRule>()
+                            .map(|rule| rule.to_string())
+                            .unwrap_or(input);
+                        let before = config.bypass.len();
+                        config.bypass.retain(|existing| *existing != rule);
+                        if config.bypass.len() < before {
+                            println!("✅ 已删除: {}", rule);
+                            removed += 1;
+                        } else {
+                            println!("❌ 直连列表中没有: {}", rule);
+                        }
+                    }
+                    if removed > 0 {
+                        config.save()?;
+                    }
+                }
+                cli::BypassAction::List => {
+                    if config.bypass.is_empty() {
+                        println!("📋 直连列表为空");
+                        println!("💡 使用 'cf bypass add --preset lan' 添加局域网地址");
+                    } else {
+                        println!("📋 直连列表 ({} 条):", config.bypass.len());
+                        for rule in &config.bypass {
+                            println!("  • {}", rule);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        cli::Commands::Autostart { action } => {
+            match action {
+                cli::AutostartAction::Enable => match autostart::enable() {
+                    Ok(location) => {
+                        println!("✅ 已设置登录后自动启动加速服务: {}", location);
+                        println!("💡 使用 'cf stop' 停止当前运行的服务，'cf autostart disable' 取消自启动");
+                    }
+                    Err(e) => println!("❌ 设置自启动失败: {:#}", e),
+                },
+                cli::AutostartAction::Disable => {
+                    if !autostart::disable() {
+                        println!("💡 没有设置自启动");
+                    }
+                }
+                cli::AutostartAction::Status => {
+                    if autostart::enabled() {
+                        println!("✅ 已设置登录后自动启动");
+                    } else {
+                        println!("💡 未设置自启动，使用 'cf autostart enable' 开启");
+                    }
+                }
+            }
+            Ok(())
+        }
+        cli::Commands::Stats { history } => {
+            print_usage(&usage::Usage::load(), history);
+            if let Ok(report) = ipc::query_status().await {
+                print_udp_quality(&report.udp_quality);
+            }
+            Ok(())
+        }
+        cli::Commands::Insights { clear } => {
+            if clear {
+                insights::Insights::clear()?;
+                println!("✅ 已清空使用统计");
+            } else {
+                print_insights(&insights::Insights::load());
+            }
+            Ok(())
+        }
+        cli::Commands::Events { since } => {
+            print_events(&events::since(since)?);
+            Ok(())
+        }
+        cli::Commands::Perf { interval } => {
+            let Ok(before) = ipc::query_perf().await else {
+                println!("❌ 加速服务未运行，请先运行 'cf start'");
+                return Ok(());
+            };
+            let started = std::time::Instant::now();
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+            let after = ipc::query_perf().await?;
+            print_perf(&after, &profiling::rates(&before, &after, started.elapsed()));
+            Ok(())
+        }
+        cli::Commands::Node { action } => {
+            match action {
+                cli::NodeAction::Health => match ipc::query_node_health().await {
+                    Ok(report) => print_node_health(&report),
+                    Err(_) => println!("❌ 加速服务未运行，请先运行 'cf start'"),
+                },
+                cli::NodeAction::Backups => match ipc::query_node_health().await {
+                    Ok(report) => print_backups(&report),
+                    Err(_) => println!("❌ 加速服务未运行，请先运行 'cf start'"),
+                },
+                cli::NodeAction::Info { name, exact } => {
+                    let config = config::Config::load_or_recover()?;
+                    let Some(url) = &config.subscription_url else {
+                        println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                        return Ok(());
+                    };
+                    let sub_manager = subscription::SubscriptionManager::new();
+                    let nodes = sub_manager.parse_nodes(&sub_manager.fetch_subscription(url).await?)?;
+                    if let Some(node) = lookup_node(&nodes, &name, exact) {
+                        print_node_info(node);
+                    }
+                }
+                cli::NodeAction::TestUdp { name, exact } => {
+                    let config = config::Config::load_or_recover()?;
+                    let Some(url) = &config.subscription_url else {
+                        println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                        return Ok(());
+                    };
+                    let sub_manager = subscription::SubscriptionManager::new();
+                    let nodes = sub_manager.parse_nodes(&sub_manager.fetch_subscription(url).await?)?;
+                    if let Some(node) = lookup_node(&nodes, &name, exact) {
+                        // 与 cf start 相同的出口和混淆设置
+                        obfs::set_rules(config.obfuscation.clone());
+                        outbound::set_binding(outbound::OutboundBinding::from_config(&config));
+                        test_udp(node).await;
+                    }
+                }
+                cli::NodeAction::Timing { name, exact } => {
+                    let config = config::Config::load_or_recover()?;
+                    let Some(url) = &config.subscription_url else {
+                        println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                        return Ok(());
+                    };
+                    let sub_manager = subscription::SubscriptionManager::new();
+                    let nodes = sub_manager.parse_nodes(&sub_manager.fetch_subscription(url).await?)?;
+                    if let Some(node) = lookup_node(&nodes, &name, exact) {
+                        outbound::set_binding(outbound::OutboundBinding::from_config(&config));
+                        print_timing(node, &health::timing(node, timeouts::current().health_probe()).await);
+                    }
+                }
+                cli::NodeAction::SpeedRank { top, duration } => {
+                    let config = config::Config::load_or_recover()?;
+                    let Some(url) = &config.subscription_url else {
+                        println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                        return Ok(());
+                    };
+                    let sub_manager = subscription::SubscriptionManager::new();
+                    let nodes = sub_manager.parse_nodes(&sub_manager.fetch_subscription(url).await?)?;
+                    outbound::set_binding(outbound::OutboundBinding::from_config(&config));
+                    speed_rank(&nodes, top as usize, std::time::Duration::from_secs(duration)).await;
+                }
+            }
+            Ok(())
+        }
+        cli::Commands::Subscription { action } => {
+            match action {
+                cli::SubscriptionAction::Status => {
+                    let config = config::Config::load_or_recover()?;
+                    print_provider_health(&provider::ProviderRecords::load(), config.subscription_url.as_deref());
+                }
+                cli::SubscriptionAction::Rename => {
+                    let config = config::Config::load_or_recover()?;
+                    let Some(url) = &config.subscription_url else {
+                        println!("❌ 请先设置订阅链接: cf set-subscription <URL>");
+                        return Ok(());
+                    };
+                    let sub_manager = subscription::SubscriptionManager::new();
+                    let clash_config = sub_manager.fetch_subscription(url).await?;
+                    let names: Vec<&str> = clash_config
+                        .proxies
+                        .iter()
+                        .filter_map(|proxy| proxy.get("name").and_then(|v| v.as_str()))
+                        .collect();
+                    print_rename_preview(&rename::Renamer::new(&config.rename)?, &names);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_rename_preview(renamer: &rename::Renamer, names: &[&str]) {
+    let mut table = table::Table::new(&["原名称", "", "新名称"]).max_width(0, 40).separator();
+    for name in names {
+        let renamed = renamer.rename(name);
+        if renamed != *name {
+            table.row(vec![name.to_string(), "→".to_string(), renamed]);
+        }
+    }
+    let changed = table.rows();
+    if changed == 0 {
+        println!("✏️  改名规则不会改变任何节点名称（共 {} 个节点）", names.len());
+        println!("💡 在配置文件的 rename 中添加规则，例如 rules: ['^\\[.*?\\]\\s*@']");
+        return;
+    }
+    println!("✏️  {} 个节点中有 {} 个会改名:", names.len(), changed);
+    table.print();
+}
+
+/// 按名称查找节点，找不到或有多个匹配时打印提示
+fn lookup_node<'a>(nodes: &'a [subscription::Node], name: &str, exact: bool) -> Option<&'a subscription::Node> {
+    match subscription::match_nodes(nodes, name, exact) {
+        subscription::NodeMatch::Found(node) => Some(node),
+        subscription::NodeMatch::Ambiguous(matches) => {
+            println!("⚠️  有 {} 个节点包含 '{}'，请指定完整名称或序号:", matches.len(), name);
+            let mut table = table::Table::headless(2).indent(2);
+            for node in matches {
+                let index = nodes.iter().position(|n| std::ptr::eq(n, node)).unwrap_or_default();
+                table.row(vec![(index + 1).to_string(), node.name.clone()]);
+            }
+            table.print();
+            println!("💡 例如: cf select-node --index <序号>");
+            None
+        }
+        subscription::NodeMatch::NotFound => {
+            if exact {
+                println!("❌ 没有名称为 '{}' 的节点", name);
+            } else {
+                println!("❌ 未找到包含 '{}' 的节点", name);
+            }
+            println!("💡 使用 'cf nodes' 查看可用节点");
+            None
+        }
+    }
+}
+
+/// 分别探测节点的 TCP 和 UDP，区分节点不可用和只是不转发 UDP
+async fn test_udp(node: &subscription::Node) {
+    println!("🧪 测试节点 {} 的 UDP 转发 ({}:{}, {})", node.name, node.server, node.port, node.protocol);
+
+    let tcp = health::probe(node, timeouts::current().health_probe()).await;
+    match &tcp {
+        Ok(probe) if probe.authenticated => println!("  ✅ 节点连接: 正常 ({}ms)", probe.latency.as_millis()),
+        Ok(probe) => println!("  ✅ 节点连接: 可连接，未验证认证 ({}ms)", probe.latency.as_millis()),
+        Err(e) => println!("  ❌ 节点连接: {:#}", e),
+    }
+
+    match health::probe_udp(node).await {
+        Ok(Some(probe)) => {
+            let reply = match probe.reply {
+                health::UdpReply::Dns => "DNS 响应",
+                health::UdpReply::Echo => "原样回显",
+                health::UdpReply::Other => "其他数据",
+            };
+            println!(
+                "  ✅ UDP 转发: 正常，收到 {} 字节{} ({}ms，第 {} 次发送)",
+                probe.size,
+                reply,
+                probe.rtt.as_millis(),
+                probe.attempt
+            );
+            println!("🎯 节点支持 UDP，可以用于需要 UDP 的游戏");
+        }
+        Ok(None) => {
+            println!("  ❌ UDP 转发: {} 秒内没有收到任何回复", health::udp_probe_timeout().as_secs_f32());
+            if tcp.is_ok() {
+                println!("⚠️  节点可以连接但不转发 UDP，游戏会出现连上后超时、匹配失败等问题");
+                println!("💡 请在订阅服务商处开启 UDP 转发，或换用支持 UDP 的节点");
+            } else {
+                println!("💡 节点本身无法连接，请先用 'cf nodes' 确认节点可用");
+            }
+        }
+        Err(e) => println!("  ❌ UDP 转发: {:#}", e),
+    }
+}
+
+fn print_node_info(node: &subscription::Node) {
+    println!("📍 {}", node.name);
+    println!("  服务器: {}:{}", node.server, node.port);
+    println!("  协议: {}", node.protocol);
+    println!("  ID: {}", node.id());
+    if !node.aliases.is_empty() {
+        println!("  重复节点: {}（地址和认证信息相同，已合并）", node.aliases.join("、"));
+    }
+    let overrides = node_overrides::for_node(node).describe();
+    if overrides.is_empty() {
+        println!("💡 可以在配置文件的 node_overrides 中按 ID 为该节点单独设置 SNI、UDP 和连接超时");
+    } else {
+        println!("  单独设置: {}", overrides.join("，"));
+    }
+}
+
+fn print_timing(node: &subscription::Node, timing: &health::Timing) {
+    const BAR_WIDTH: u128 = 30;
+
+    println!("⏱️  节点 {} ({}:{}, {}) 的握手耗时:", node.name, node.server, node.port, node.protocol);
+    if let Some(addr) = timing.addr {
+        println!("  📍 地址: {}", addr);
+    }
+    let total = timing.total().as_millis().max(1);
+    for (stage, elapsed) in &timing.stages {
+        let ms = elapsed.as_millis();
+        let bar = "█".repeat((ms * BAR_WIDTH / total).max(1) as usize);
+        println!("  {:<10} {:>6}ms  {}", stage.display_name(), ms, bar);
+    }
+    if let Some((stage, error)) = &timing.failed {
+        println!("  ❌ {}失败: {}", stage.display_name(), error);
+    }
+    println!("  {:<10} {:>6}ms", "总计", timing.total().as_millis());
+
+    for note in timing.diagnose() {
+        println!("💡 {}", note);
+    }
+    if timing.failed.is_none() && timing.diagnose().is_empty() {
+        println!("✅ 各阶段耗时正常");
+    }
+}
+
+async fn speed_rank(nodes: &[subscription::Node], top: usize, duration: std::time::Duration) {
+    println!("🔍 并发测试 {} 个节点的延迟...", nodes.len());
+    let reachable = speed::probe_rtt(nodes).await;
+    if reachable.is_empty() {
+        println!("❌ 没有可以连接的节点");
+        return;
+    }
+    println!("  {} 个节点可以连接，对延迟最低的 {} 个节点测速，每个 {} 秒", reachable.len(), top.min(reachable.len()), duration.as_secs());
+
+    let mut measured = Vec::new();
+    for (node, rtt) in reachable.into_iter().take(top) {
+        println!("  ⏱️  {} ({}ms)...", node.name, rtt.as_millis());
+        let throughput = speed::sample_throughput(&node, duration).await;
+        measured.push((node, rtt, throughput));
+    }
+
+    println!();
+    println!("🏆 综合排名（延迟 + 吞吐量）:");
+    let mut table = table::Table::headless(4).indent(2).right(2);
+    for (rank, result) in speed::rank(measured).iter().enumerate() {
+        let throughput = match (result.throughput, &result.error) {
+            (Some(throughput), _) => format!("{}/s", format_bytes(throughput as u64)),
+            (None, Some(error)) => format!("测速失败: {}", error),
+            (None, None) => "测速失败".to_string(),
+        };
+        table.row(vec![
+            (rank + 1).to_string(),
+            result.node.name.clone(),
+            format!("{}ms", result.rtt.as_millis()),
+            throughput,
+        ]);
+    }
+    table.print();
+    println!("💡 吞吐量为上传方向的估算，每比最快的节点慢一半按多 50ms 延迟计入排名");
+}
+
+/// Ctrl+C，Unix 上还包括 SIGTERM (procd、systemd 停止服务时发送)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// 在原地每秒刷新的精简状态，适合放在副屏的小终端里
+#[cfg(feature = "tui")]
+async fn watch_status() -> anyhow::Result<()> {
+    use crossterm::{cursor, queue, terminal};
+
+    const GAME_REFRESH_TICKS: u64 = 5;
+
+    let mut stdout = io::stdout();
+    crossterm::execute!(stdout, terminal::Clear(terminal::ClearType::All), cursor::Hide)?;
+
+    let mut detector = game_detect::GameDetector::new();
+    let mut games = String::new();
+    let mut previous: Option<(u64, u64, std::time::Instant)> = None;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    for tick in 0u64.. {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        // 游戏检测需要扫描进程，降低频率
+        if tick.is_multiple_of(GAME_REFRESH_TICKS) {
+            games = match detector.detect_running_games() {
+                Ok(detected) if !detected.is_empty() => {
+                    detected.iter().map(|(game, _)| game.display_name()).collect::<Vec<_>>().join("、")
+                }
+                Ok(_) => "无".to_string(),
+                Err(_) => "检测失败".to_string(),
+            };
+        }
+
+        let now = chrono::Local::now();
+        let mut lines = Vec::new();
+        match ipc::query_status().await {
+            Ok(report) => {
+                lines.push(format!(
+                    "⚡ ClashFun 运行中 · 已运行 {} · {}",
+                    format_duration(report.uptime_secs),
+                    now.format("%H:%M:%S")
+                ));
+                let node = report.node.as_deref().unwrap_or("无");
+                match &report.preferred_node {
+                    Some(preferred) if Some(preferred) != report.node.as_ref() => {
+                        lines.push(format!("📍 节点: {} (首选 {} 不可用)", node, preferred))
+                    }
+                    _ => lines.push(format!("📍 节点: {}", node)),
+                }
+                let latency = match &report.last_check {
+                    Some(check) => {
+                        let age = (now.timestamp().max(0) as u64).saturating_sub(check.timestamp);
+                        match check.latency_ms {
+                            Some(ms) => format!("{}ms ({}秒前检查)", ms, age),
+                            None => format!("❌ 检查失败 ({}秒前)", age),
+                        }
+                    }
+                    None => "等待健康检查".to_string(),
+                };
+                lines.push(format!("📶 延迟: {}", latency));
+                if let Some(failover) = &report.failover {
+                    lines.push(format!("🩺 {}", describe_failover(failover)));
+                }
+
+                let at = std::time::Instant::now();
+                let rate = match previous {
+                    Some((upload, download, last)) => {
+                        let secs = at.duration_since(last).as_secs_f64().max(0.001);
+                        format!(
+                            "↑ {}/s  ↓ {}/s",
+                            format_bytes((report.upload_bytes.saturating_sub(upload) as f64 / secs) as u64),
+                            format_bytes((report.download_bytes.saturating_sub(download) as f64 / secs) as u64)
+                        )
+                    }
+                    None => "计算中...".to_string(),
+                };
+                previous = Some((report.upload_bytes, report.download_bytes, at));
+                lines.push(format!("🚀 速率: {}", rate));
+                lines.push(format!("🔌 连接: TCP {} / UDP 会话 {}", report.tcp_connections, report.udp_sessions));
+            }
+            Err(_) => {
+                previous = None;
+                lines.push(format!("⚡ ClashFun 未运行 · {}", now.format("%H:%M:%S")));
+                lines.push("💡 运行 'cf start' 启动加速服务".to_string());
+            }
+        }
+        lines.push(format!("🎮 游戏: {}", games));
+        lines.push(String::new());
+        lines.push("按 Ctrl+C 退出".to_string());
+
+        queue!(stdout, cursor::MoveTo(0, 0))?;
+        for line in &lines {
+            queue!(stdout, terminal::Clear(terminal::ClearType::UntilNewLine))?;
+            write!(stdout, "{}\r\n", line)?;
+        }
+        queue!(stdout, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        stdout.flush()?;
+    }
+
+    crossterm::execute!(stdout, cursor::Show)?;
+    println!();
+    Ok(())
+}
+
+fn print_nodes(nodes: &[subscription::Node], grouping: region::Grouping, hide_unsupported: bool) {
+    for group in region::group(nodes, grouping) {
+        if hide_unsupported && group.nodes.iter().all(|(_, node)| !node.is_supported()) {
+            continue;
+        }
+        if grouping != region::Grouping::None {
+            let best = group.best_latency().map_or("无可用节点".to_string(), |ms| format!("最低 {}ms", ms));
+            println!();
+            println!("📍 {} ({} 个，{} 个可用，{})", group.name, group.nodes.len(), group.available(), best);
+        }
+        let mut table = table::Table::new(&["序号", "节点名称", "服务器", "协议", "延迟(ms)"])
+            .max_width(1, 30)
+            .max_width(2, 20)
+            .separator();
+        for (i, node) in group.nodes {
+            if hide_unsupported && !node.is_supported() {
+                continue;
+            }
+            let latency = match node.latency {
+                _ if !node.is_supported() => "不支持".to_string(),
+                Some(lat) if lat == u32::MAX => "超时".to_string(),
+                Some(lat) => format!("{}", lat),
+                None => "未测试".to_string(),
+            };
+
+            table.row(vec![(i + 1).to_string(), node.name.clone(), node.server.clone(), node.protocol.clone(), latency]);
+        }
+        table.print();
+    }
+}
+
+/// 把节点保存为选中节点，协议暂不支持时只提示
+fn switch_to_node(config: &mut config::Config, node: &subscription::Node) -> anyhow::Result<()> {
+    if let Some(reason) = node.unsupported_reason() {
+        println!("❌ {}", reason);
+        return Ok(());
+    }
+    info!("切换到节点: {}", node.name);
+    config.select_node(node);
+    config.save()?;
+    println!("🔄 已切换到节点: {}", node.name);
+    println!("📍 服务器: {}:{}", node.server, node.port);
+    Ok(())
+}
+
+/// 提示合并了多少个重复节点
+fn print_merged_summary(nodes: &[subscription::Node]) {
+    let merged: usize = nodes.iter().map(|node| node.aliases.len()).sum();
+    if merged > 0 {
+        println!("🔗 合并了 {} 个配置相同的重复节点，用 'cf node info <名称>' 查看", merged);
+    }
+}
+
+/// 提示订阅中有多少节点的协议暂不支持
+fn print_unsupported_summary(nodes: &[subscription::Node], hidden: bool) {
+    let unsupported: Vec<&subscription::Node> = nodes.iter().filter(|node| !node.is_supported()).collect();
+    if unsupported.is_empty() {
+        return;
+    }
+    let mut protocols: Vec<&str> = unsupported.iter().map(|node| node.protocol.as_str()).collect();
+    protocols.sort_unstable();
+    protocols.dedup();
+    println!();
+    if hidden {
+        println!("💡 已隐藏 {} 个协议暂不支持的节点 ({})", unsupported.len(), protocols.join(", "));
+    } else {
+        println!(
+            "⚠️  {} 个节点使用暂不支持的协议 ({})，无法选择；可以用 --hide-unsupported 隐藏",
+            unsupported.len(),
+            protocols.join(", ")
+        );
+    }
+}
+
+fn print_backups(report: &failover::HealthReport) {
+    if report.backups.is_empty() {
+        println!("🔄 没有备用节点，当前节点故障时无法自动切换");
+        return;
+    }
+    let now = chrono::Local::now().timestamp().max(0) as u64;
+    println!("🔄 备用节点（故障切换时同时探测，使用最先通过的节点）:");
+    let mut table = table::Table::headless(4).indent(2).right(0);
+    for (index, name) in report.backups.iter().enumerate() {
+        let health = report.nodes.iter().find(|node| &node.name == name);
+        let latency = match health.and_then(|node| node.last_check.as_ref()) {
+            Some(check) => {
+                let result = match check.latency_ms {
+                    Some(ms) => format!("{}ms", ms),
+                    None => "失败".to_string(),
+                };
+                format!("{} ({}前)", result, format_duration(now.saturating_sub(check.timestamp)))
+            }
+            None => "尚未测试".to_string(),
+        };
+        let backoff = health
+            .and_then(|node| node.backoff_secs)
+            .map(|secs| format!("退避中，还剩 {}", format_duration(secs)))
+            .unwrap_or_default();
+        table.row(vec![format!("{}.", index + 1), name.clone(), latency, backoff]);
+    }
+    table.print();
+}
+
+fn print_node_health(report: &failover::HealthReport) {
+    println!("🩺 节点健康状况");
+    match (&report.current, &report.preferred) {
+        (Some(current), Some(preferred)) if current != preferred => {
+            println!("📍 当前节点: {} (首选节点: {})", current, preferred)
+        }
+        (Some(current), _) => println!("📍 当前节点: {}", current),
+        (None, _) => println!("📍 当前节点: 未选择"),
+    }
+    if let Some(secs) = report.cooldown_secs {
+        println!("⏳ 刚刚自动切换过，{} 后才会再次切换", format_duration(secs));
+    }
+    if report.stream_retries > 0 {
+        println!("🔁 连接节点失败后改用备用节点重试了 {} 条连接", report.stream_retries);
+    }
+
+    let now = chrono::Local::now().timestamp().max(0) as u64;
+    println!();
+    let mut table = table::Table::headless(4).indent(2);
+    for node in &report.nodes {
+        let marker = if report.current.as_ref() == Some(&node.name) { "▶" } else { "•" };
+        let check = match &node.last_check {
+            Some(check) => {
+                let result = match (check.latency_ms, &check.error) {
+                    (Some(ms), _) => format!("✅ {}ms", ms),
+                    (None, Some(error)) => format!("❌ {}", error),
+                    (None, None) => "❌ 失败".to_string(),
+                };
+                format!("{} ({}前)", result, format_duration(now.saturating_sub(check.timestamp)))
+            }
+            None => "尚未检查".to_string(),
+        };
+        let mut notes = Vec::new();
+        if node.consecutive_failures > 0 {
+            notes.push(format!("连续失败 {} 次", node.consecutive_failures));
+        }
+        if let Some(secs) = node.backoff_secs {
+            notes.push(format!("退避中，还剩 {}", format_duration(secs)));
+        }
+        if node.backup {
+            notes.push("备用".to_string());
+        }
+        table.row(vec![marker.to_string(), node.name.clone(), check, notes.join("，")]);
+    }
+    table.print();
+
+    println!();
+    if report.switches.is_empty() {
+        println!("🔄 启动以来没有切换过节点");
+    } else {
+        println!("🔄 最近的切换:");
+        for event in &report.switches {
+            let time = chrono::DateTime::from_timestamp(event.timestamp as i64, 0)
+                .map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            println!(
+                "  {}  {} → {}  ({})",
+                time,
+                event.from.as_deref().unwrap_or("无"),
+                event.to,
+                event.reason.display_name()
+            );
+        }
+    }
+
+    let backups: Vec<&str> = report.nodes.iter().filter(|n| n.backup).map(|n| n.name.as_str()).collect();
+    if backups.is_empty() {
+        println!("📋 没有备用节点，当前节点故障时无法自动切换");
+    } else {
+        println!("📋 备用节点 ({} 个): {}", backups.len(), backups.join("、"));
+    }
+}
+
+fn print_devices(devices: &[lan::DeviceReport]) {
+    for device in devices {
+        let name = match (&device.mac, device.is_local()) {
+            (_, true) => format!("{} (本机)", device.ip),
+            (Some(mac), _) => format!("{} ({})", device.ip, mac),
+            (None, _) => device.ip.to_string(),
+        };
+        let state = match (device.blocked, device.game_ports_only) {
+            (true, _) => " 🚫 已禁用",
+            (_, true) => " 🎮 仅游戏端口",
+            _ => "",
+        };
+        println!(
+            "    • {}  ↑ {} / ↓ {}  TCP {}{}",
+            name,
+            format_bytes(device.upload_bytes),
+            format_bytes(device.download_bytes),
+            device.tcp_connections,
+            state
+        );
+    }
+}
+
+/// 保存节点延迟到历史记录，供 cf report 统计
+fn record_latency(nodes: &[subscription::Node]) {
+    if let Err(e) = history::record(nodes) {
+        warn!("保存延迟历史失败: {}", e);
+    }
+}
+
+fn format_median(median_ms: Option<u32>) -> String {
+    median_ms.map_or("超时".to_string(), |ms| format!("{}ms", ms))
+}
+
+fn latency_level(cell: &history::Cell) -> &'static str {
+    match cell.median_ms {
+        _ if cell.loss_percent >= 20 => "🔴",
+        Some(ms) if ms < 80 => "🟢",
+        Some(ms) if ms < 150 => "🟡",
+        Some(ms) if ms < 250 => "🟠",
+        _ => "🔴",
+    }
+}
+
+/// 故障切换的进度和进入该阶段的时间
+fn describe_failover(status: &failover::FailoverStatus) -> String {
+    let since = chrono::DateTime::from_timestamp(status.since as i64, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default();
+    format!("{}（{} 起）", status.describe(), since)
+}
+
+fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}天{}小时{}分", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}小时{}分", hours, minutes)
+    } else {
+        format!("{}分{}秒", minutes, secs % 60)
+    }
+}
+
+/// 每天的流量条形图，以及按节点和游戏的合计
+fn print_usage(usage: &usage::Usage, days: u32) {
+    const BAR_WIDTH: u64 = 30;
+    const TOP: usize = 5;
+
+    let today = chrono::Local::now().date_naive();
+    let recent = usage.recent(days, today);
+    let summary = usage.summary(days, today);
+    if summary.total.total() == 0 {
+        println!("📊 最近 {} 天没有经过加速的流量", days);
+        println!("💡 运行 'cf start' 后流量会按天记录");
+        return;
+    }
+
+    println!("📊 最近 {} 天的流量 ({} ~ {})", days, recent[0].0, today);
+    let max = recent.iter().map(|(_, day)| day.total.total()).max().unwrap_or(1).max(1);
+    for (date, day) in &recent {
+        let width = (day.total.total() * BAR_WIDTH).div_ceil(max) as usize;
+        println!(
+            "  {}  ↑ {:>9} / ↓ {:>9}  {}",
+            date.format("%m-%d"),
+            format_bytes(day.total.upload),
+            format_bytes(day.total.download),
+            "█".repeat(width)
+        );
+    }
+    println!(
+        "  合计: ↑ {} / ↓ {}，共 {}",
+        format_bytes(summary.total.upload),
+        format_bytes(summary.total.download),
+        format_bytes(summary.total.total())
+    );
+
+    for (title, list) in [("🌐 按节点:", &summary.nodes), ("🎮 按游戏:", &summary.games)] {
+        println!("{}", title);
+        let mut table = table::Table::headless(3).indent(2).right(1).max_width(0, 24);
+        for (name, bytes) in list.iter().take(TOP) {
+            let percent = bytes.total() * 100 / summary.total.total();
+            table.row(vec![name.clone(), format_bytes(bytes.total()), format!("({}%)", percent)]);
+        }
+        table.print();
+        if list.len() > TOP {
+            println!("  ... 以及其他 {} 项", list.len() - TOP);
+        }
+    }
+}
+
+/// 运行中的 UDP 会话的丢包和乱序估计
+fn print_udp_quality(sessions: &[udp_quality::SessionQuality]) {
+    if sessions.is_empty() {
+        return;
+    }
+    println!("📶 当前 UDP 会话（丢包和乱序按游戏协议中的序号估计，饥荒、反恐精英、刀塔2 支持）:");
+    for session in sessions {
+        let game = session.game.as_ref().map(|game| game.display_name()).unwrap_or("未识别");
+        println!("  {} {} → {}", session.client, game, session.node);
+        println!("      ↑ {}", session.up.describe());
+        println!("      ↓ {}", session.down.describe());
+    }
+    if sessions.len() > 1 {
+        let (up, down) = udp_quality::total(sessions);
+        println!("  合计: ↑ {} / ↓ {}", up.describe(), down.describe());
+    }
+}
+
+fn print_perf(report: &profiling::PerfReport, rates: &profiling::PerfRates) {
+    println!("⚙️  加速服务资源占用:");
+    let unknown = || "不支持".to_string();
+    println!("  🧠 常驻内存: {}", report.rss_bytes.map(format_bytes).unwrap_or_else(unknown));
+    println!("  🔥 CPU 占用: {}", rates.cpu_percent.map(|p| format!("{:.1}%", p)).unwrap_or_else(unknown));
+    println!(
+        "  🧵 工作线程: {}，异步任务: {}，排队任务: {}",
+        report.worker_threads, report.alive_tasks, report.queued_tasks
+    );
+    match (report.allocations, rates.allocations_per_sec, rates.allocated_bytes_per_sec) {
+        (Some(allocations), Some(count), Some(bytes)) => println!(
+            "  📦 内存分配: {:.0} 次/秒，{}/秒，未释放 {}",
+            count,
+            format_bytes(bytes as u64),
+            format_bytes(allocations.live_bytes)
+        ),
+        _ => println!("  📦 内存分配: 未统计（使用 --features alloc-stats 编译的版本才统计）"),
+    }
+
+    println!("📋 各表条目数:");
+    let mut tables = table::Table::headless(2).indent(2).right(1);
+    for (name, size) in &report.tables {
+        tables.row(vec![name.clone(), size.to_string()]);
+    }
+    tables.print();
+
+    let contended: Vec<_> = report.locks.iter().filter(|lock| lock.contended > 0).take(profiling::LOCKS_SHOWN).collect();
+    if contended.is_empty() {
+        println!("🔒 转发路径上的锁没有发生争用");
+        return;
+    }
+    println!("🔒 锁争用（启动以来，按等待时间排序）:");
+    let mut locks = table::Table::new(&["位置", "加锁次数", "需等待", "累计等待", "最长等待"])
+        .indent(2)
+        .right(1)
+        .right(2)
+        .right(3)
+        .right(4);
+    for lock in contended {
+        locks.row(vec![
+            lock.site.clone(),
+            lock.acquisitions.to_string(),
+            format!("{:.2}%", lock.contended_percent()),
+            format!("{:.1}ms", lock.wait_us as f64 / 1000.0),
+            format!("{:.1}ms", lock.max_wait_us as f64 / 1000.0),
+        ]);
+    }
+    locks.print();
+}
+
+fn print_events(events: &[events::Event]) {
+    if events.is_empty() {
+        println!("📜 这段时间内没有记录到事件（只有 cf start 运行期间会记录）");
+        return;
+    }
+    println!("📜 最近的事件:");
+    let mut table = table::Table::headless(3).indent(2);
+    for event in events {
+        let time = chrono::DateTime::from_timestamp(event.timestamp as i64, 0)
+            .map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        table.row(vec![time, event.kind.display_name().to_string(), event.message.clone()]);
+    }
+    table.print();
+}
+
+fn print_insights(insights: &insights::Insights) {
+    const TOP: usize = 10;
+
+    if !insights::enabled() {
+        println!("ℹ️  本地使用统计未开启，在配置文件中设置 insights: true 后开始记录");
+    }
+    let Some(since) = insights.since else {
+        println!("📈 还没有使用统计");
+        return;
+    };
+
+    println!("📈 使用统计（自 {} 起，仅保存在本机，从不上传）", since);
+    println!("🧭 常用功能:");
+    let features = insights.top_features();
+    let mut table = table::Table::headless(2).indent(2).right(1);
+    for (name, count) in features.iter().take(TOP) {
+        table.row(vec![name.to_string(), format!("{} 次", count)]);
+    }
+    table.print();
+    if features.len() > TOP {
+        println!("  ... 以及其他 {} 项", features.len() - TOP);
+    }
+
+    println!("🔀 节点切换: 共 {} 次", insights.total_switches());
+    let mut table = table::Table::headless(2).indent(2).right(1);
+    for (reason, count) in &insights.switches {
+        table.row(vec![reason.display_name().to_string(), format!("{} 次", count)]);
+    }
+    table.print();
+    if let Some(rate) = insights.failovers_per_start() {
+        println!("  平均每次启动故障切换 {:.1} 次", rate);
+    }
+
+    for suggestion in insights.suggestions() {
+        println!("💡 {}", suggestion);
+    }
+}
+
+/// 预览订阅：获取并解析，统计节点，不保存也不记录订阅状态
+async fn check_subscription(url: &str) {
+    /// 最多列出的无效链接和代理数量
+    const MAX_WARNINGS: usize = 5;
+
+    println!("🔍 检查订阅: {}", provider::mask_url(url));
+    let sub_manager = subscription::SubscriptionManager::new();
+    let content = match sub_manager.fetch_content(url).await {
+        Ok(content) => content,
+        Err(e) => return print_error("获取订阅失败", e),
+    };
+    let clash_config = match sub_manager.parse_subscription_content(&content) {
+        Ok(clash_config) => clash_config,
+        Err(e) => return print_error("订阅内容无法解析", e),
+    };
+    let (nodes, skipped) = sub_manager.check_nodes(&clash_config);
+
+    let mut warnings: Vec<String> = clashfun::parser::invalid_links(&content)
+        .into_iter()
+        .map(|(index, e)| format!("第 {} 条链接: {}", index, e))
+        .collect();
+    warnings.extend(skipped);
+
+    if nodes.is_empty() {
+        println!("❌ 订阅中没有可用的节点，可能已到期或流量用完");
+    } else {
+        println!("✅ 订阅可用，共 {} 个节点", nodes.len());
+        let unsupported = nodes.iter().filter(|node| !node.is_supported()).count();
+        if unsupported > 0 {
+            println!("⚠️  其中 {} 个节点的协议暂不支持，无法使用", unsupported);
+        }
+        let mut protocols: std::collections::BTreeMap<&str, usize> = Default::default();
+        let mut regions: std::collections::BTreeMap<&str, usize> = Default::default();
+        for node in &nodes {
+            *protocols.entry(node.protocol.as_str()).or_default() += 1;
+            *regions.entry(region::of(&node.name)).or_default() += 1;
+        }
+        let join = |counts: std::collections::BTreeMap<&str, usize>| {
+            counts.into_iter().map(|(name, count)| format!("{} {}", name, count)).collect::<Vec<_>>().join("，")
+        };
+        println!("  按协议: {}", join(protocols));
+        println!("  按地区: {}", join(regions));
+    }
+
+    if !warnings.is_empty() {
+        println!("⚠️  跳过了 {} 条无法解析的节点:", warnings.len());
+        for warning in warnings.iter().take(MAX_WARNINGS) {
+            println!("  {}", warning);
+        }
+        if warnings.len() > MAX_WARNINGS {
+            println!("  ……还有 {} 条", warnings.len() - MAX_WARNINGS);
+        }
+    }
+    println!("💡 订阅链接没有保存，确认无误后去掉 --check 再运行一次");
+}
+
+fn print_provider_health(records: &provider::ProviderRecords, current: Option<&str>) {
+    if records.subscriptions.is_empty() {
+        println!("📡 还没有订阅获取记录，运行 'cf nodes' 或 'cf start' 后再查看");
+        return;
+    }
+    let time = |timestamp: u64| {
+        chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    };
+
+    println!("📡 订阅状态:");
+    for (url, health) in &records.subscriptions {
+        let marker = if Some(url.as_str()) == current { "（当前）" } else { "" };
+        println!();
+        println!("🔗 {}{}", provider::mask_url(url), marker);
+        println!(
+            "  获取成功率: {}%（{} 次中失败 {} 次）",
+            health.success_percent().unwrap_or(0),
+            health.fetches,
+            health.failures
+        );
+        if let Some(success) = health.last_success {
+            println!("  上次成功: {}", time(success));
+        }
+        if let Some(failure) = &health.last_error {
+            println!("  最近错误: {} {}", time(failure.timestamp), failure.error);
+        }
+        if let Some(count) = health.node_count() {
+            let changes: Vec<String> = health
+                .node_counts
+                .iter()
+                .map(|change| format!("{} {}", time(change.timestamp), change.count))
+                .collect();
+            println!("  节点数量: {}（变化: {}）", count, changes.join(" → "));
+        }
+        if let (Some(average), Some(last)) = (health.average_usable_percent(), health.last_usable_percent) {
+            println!("  可用节点比例: 平均 {}%，最近 {}%（{} 次测速）", average, last, health.usable_samples);
+        }
+        for warning in health.warnings() {
+            println!("  ⚠️  {}", warning);
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+async fn run_interactive_mode() -> anyhow::Result<()> {
+    println!("💡 此版本未包含交互界面，运行 'cf --help' 查看可用命令");
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+async fn run_interactive_mode() -> anyhow::Result<()> {
+    info!("启动 ClashFun 交互模式...");
+
+    // 加载配置
+    let config = Arc::new(tokio::sync::RwLock::new(config::Config::load_or_recover()?));
 
     // 创建并运行交互式应用
-    let mut app = interactive::InteractiveApp::new(config, game_detector);
+    let mut app = interactive::InteractiveApp::new(config);
     app.run().await?;
 
     Ok(())
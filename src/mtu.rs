@@ -0,0 +1,177 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tokio::net::{TcpSocket, UdpSocket};
+
+use crate::config::Config;
+
+/// UDP 数据包超过上限时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizePolicy {
+    /// 丢弃并计数
+    #[default]
+    Drop,
+    /// 整包发送并允许在 IP 层分片，数据包边界不变，由对端协议栈重组
+    #[serde(alias = "split")]
+    Fragment,
+}
+
+/// 数据包大小相关的限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PacketLimits {
+    /// 连接节点时通告的 TCP MSS
+    pub tcp_mss: Option<u16>,
+    /// 转发到节点的单个 UDP 数据包最大字节数
+    pub udp_max_payload: Option<usize>,
+    pub udp_oversize: OversizePolicy,
+}
+
+/// 对单个 UDP 数据包的处理结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum UdpVerdict {
+    Send,
+    Drop,
+    /// 清除 DF 标志后整包发送，由 IP 层分片
+    Fragment,
+}
+
+static LIMITS: RwLock<PacketLimits> = RwLock::new(PacketLimits {
+    tcp_mss: None,
+    udp_max_payload: None,
+    udp_oversize: OversizePolicy::Drop,
+});
+
+impl PacketLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            tcp_mss: config.tcp_mss.filter(|mss| *mss > 0),
+            udp_max_payload: config.udp_max_payload.filter(|size| *size > 0),
+            udp_oversize: config.udp_oversize,
+        }
+    }
+
+    pub fn check_udp(&self, len: usize) -> UdpVerdict {
+        match self.udp_max_payload {
+            Some(max) if len > max => match self.udp_oversize {
+                OversizePolicy::Drop => UdpVerdict::Drop,
+                OversizePolicy::Fragment => UdpVerdict::Fragment,
+            },
+            _ => UdpVerdict::Send,
+        }
+    }
+}
+
+pub fn set_limits(limits: PacketLimits) {
+    *LIMITS.write().unwrap_or_else(|e| e.into_inner()) = limits;
+}
+
+pub fn limits() -> PacketLimits {
+    *LIMITS.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 在连接前设置 TCP_MAXSEG，让握手时通告较小的 MSS，避免隧道封装后分片
+pub fn clamp_mss(socket: &TcpSocket) {
+    let Some(mss) = limits().tcp_mss else { return };
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let value = libc::c_int::from(mss);
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_MAXSEG,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            debug!("设置 TCP MSS {} 失败: {}", mss, std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        debug!("当前平台不支持设置 TCP MSS ({})，已忽略", mss);
+    }
+}
+
+/// 清除发往节点的 UDP socket 的 DF 标志，超过路径 MTU 的数据包由内核分片而不是丢弃；
+/// 只有 Linux 默认按路径 MTU 设置 DF，其他平台的 UDP socket 默认允许分片
+pub fn allow_fragmentation(socket: &UdpSocket) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let (level, option, value) = match socket.local_addr() {
+            Ok(addr) if addr.is_ipv6() => (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DONT),
+            _ => (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DONT),
+        };
+        // SAFETY: 只修改本 socket 的选项，value 在调用期间有效
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            debug!("清除 UDP socket 的 DF 标志失败: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = socket;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_udp_is_dropped_or_fragmented() {
+        let mut limits = PacketLimits::default();
+        assert_eq!(limits.check_udp(65_000), UdpVerdict::Send);
+
+        limits.udp_max_payload = Some(1200);
+        assert_eq!(limits.check_udp(1200), UdpVerdict::Send);
+        assert_eq!(limits.check_udp(1201), UdpVerdict::Drop);
+
+        limits.udp_oversize = OversizePolicy::Fragment;
+        assert_eq!(limits.check_udp(3000), UdpVerdict::Fragment);
+        // 旧配置中的 split 按 IP 分片处理
+        assert_eq!(serde_yaml::from_str::<OversizePolicy>("split").unwrap(), OversizePolicy::Fragment);
+    }
+
+    #[tokio::test]
+    async fn fragmented_datagram_keeps_its_boundary() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.connect(receiver.local_addr().unwrap()).await.unwrap();
+        allow_fragmentation(&sender);
+
+        let datagram: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        sender.send(&datagram).await.unwrap();
+        let mut buf = [0u8; 4096];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], &datagram[..]);
+    }
+
+    #[test]
+    fn zero_means_unlimited() {
+        let config = Config {
+            tcp_mss: Some(0),
+            udp_max_payload: Some(0),
+            ..Config::default()
+        };
+        let limits = PacketLimits::from_config(&config);
+        assert_eq!(limits.tcp_mss, None);
+        assert_eq!(limits.check_udp(10_000), UdpVerdict::Send);
+    }
+}
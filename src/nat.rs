@@ -0,0 +1,366 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::dns;
+
+/// 默认使用的 STUN 服务器，第一个支持 CHANGE-REQUEST 的服务器用于过滤行为测试
+pub const DEFAULT_STUN_SERVERS: [&str; 4] = [
+    "stun.stunprotocol.org:3478",
+    "stun.l.google.com:19302",
+    "stun.cloudflare.com:3478",
+    "stun.miwifi.com:3478",
+];
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_CHANGE_REQUEST: u16 = 0x0003;
+const ATTR_CHANGED_ADDRESS: u16 = 0x0005;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_OTHER_ADDRESS: u16 = 0x802C;
+const CHANGE_IP: u32 = 0x04;
+const CHANGE_PORT: u32 = 0x02;
+/// 单次请求的重试间隔，UDP 请求可能丢失
+const RETRIES: [u64; 3] = [300, 600, 1200];
+
+/// NAT 类型，从最容易到最难穿透
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatType {
+    /// 没有 NAT，本机直接拥有公网地址
+    Open,
+    FullCone,
+    RestrictedCone,
+    PortRestrictedCone,
+    /// 锥形 NAT，但服务器不支持过滤行为测试
+    Cone,
+    Symmetric,
+    /// 收不到任何 STUN 响应
+    UdpBlocked,
+}
+
+impl NatType {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Open => "开放 (无 NAT)",
+            Self::FullCone => "完全锥形 (NAT1)",
+            Self::RestrictedCone => "IP 限制锥形 (NAT2)",
+            Self::PortRestrictedCone => "端口限制锥形 (NAT3)",
+            Self::Cone => "锥形 (NAT1-3，无法进一步区分)",
+            Self::Symmetric => "对称型 (NAT4)",
+            Self::UdpBlocked => "UDP 被阻断",
+        }
+    }
+
+    /// 对饥荒、怪物猎人等 P2P 联机游戏的影响
+    pub fn p2p_verdict(&self) -> &'static str {
+        match self {
+            Self::Open | Self::FullCone => "可以直接联机，其他玩家也能加入你创建的房间",
+            Self::RestrictedCone | Self::Cone => "大多数 P2P 游戏可以正常联机，与对称型 NAT 的玩家联机可能失败",
+            Self::PortRestrictedCone => "可以加入大部分房间，但与对称型 NAT 的玩家无法直连，需要游戏的中继服务器",
+            Self::Symmetric => "P2P 联机困难：饥荒、怪物猎人等可能无法加入好友房间，建议开启 UPnP (cf port-map) 或使用专用服务器",
+            Self::UdpBlocked => "UDP 无法使用，绝大部分联机游戏都无法连接，请检查防火墙",
+        }
+    }
+}
+
+/// 一次 Binding 请求的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingResponse {
+    /// 服务器看到的本机地址
+    pub mapped: SocketAddr,
+    /// 服务器的另一个地址，存在时说明支持 CHANGE-REQUEST
+    pub other: Option<SocketAddr>,
+}
+
+#[derive(Debug)]
+pub struct NatReport {
+    pub nat_type: NatType,
+    pub local: SocketAddr,
+    pub mapped: Option<SocketAddr>,
+}
+
+pub fn binding_request(transaction: &[u8; 12], change: u32) -> Vec<u8> {
+    let mut message = Vec::with_capacity(28);
+    message.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    let length: u16 = if change != 0 { 8 } else { 0 };
+    message.extend_from_slice(&length.to_be_bytes());
+    message.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    message.extend_from_slice(transaction);
+    if change != 0 {
+        message.extend_from_slice(&ATTR_CHANGE_REQUEST.to_be_bytes());
+        message.extend_from_slice(&4u16.to_be_bytes());
+        message.extend_from_slice(&change.to_be_bytes());
+    }
+    message
+}
+
+/// 解析 Binding 响应，事务 ID 不匹配或格式错误时返回 None
+pub fn parse_binding_response(data: &[u8], transaction: &[u8; 12]) -> Option<BindingResponse> {
+    let header = data.get(..20)?;
+    if u16::from_be_bytes([header[0], header[1]]) != BINDING_RESPONSE
+        || header[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || header[8..20] != transaction[..]
+    {
+        return None;
+    }
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let mut attributes = data.get(20..20 + length)?;
+
+    let (mut mapped, mut xor_mapped, mut other) = (None, None, None);
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + len)?;
+        match kind {
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = parse_address(value, Some(transaction)),
+            ATTR_MAPPED_ADDRESS => mapped = parse_address(value, None),
+            ATTR_OTHER_ADDRESS | ATTR_CHANGED_ADDRESS => other = parse_address(value, None),
+            _ => {}
+        }
+        // 属性按 4 字节对齐
+        let padded = (4 + len + 3) & !3;
+        attributes = attributes.get(padded..).unwrap_or_default();
+    }
+
+    Some(BindingResponse {
+        mapped: xor_mapped.or(mapped)?,
+        other,
+    })
+}
+
+fn parse_address(value: &[u8], xor: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let family = *value.get(1)?;
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let mut port = u16::from_be_bytes([*value.get(2)?, *value.get(3)?]);
+    if xor.is_some() {
+        port ^= u16::from_be_bytes([cookie[0], cookie[1]]);
+    }
+
+    let ip = match family {
+        0x01 => {
+            let mut octets: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            if xor.is_some() {
+                octets.iter_mut().zip(cookie).for_each(|(b, k)| *b ^= k);
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        0x02 => {
+            let mut octets: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            if let Some(transaction) = xor {
+                let key = cookie.iter().chain(transaction.iter());
+                octets.iter_mut().zip(key).for_each(|(b, k)| *b ^= k);
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+fn new_transaction() -> [u8; 12] {
+    use std::hash::{BuildHasher, Hasher};
+    let mut transaction = [0u8; 12];
+    for chunk in transaction.chunks_mut(8) {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes()[..chunk.len()]);
+    }
+    transaction
+}
+
+/// 从 socket 向服务器发送 Binding 请求，超时返回 None
+pub async fn binding(socket: &UdpSocket, server: SocketAddr, change: u32) -> Option<BindingResponse> {
+    let transaction = new_transaction();
+    let request = binding_request(&transaction, change);
+    let mut buf = [0u8; 1024];
+
+    for timeout in RETRIES {
+        socket.send_to(&request, server).await.ok()?;
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout);
+        // 忽略其他事务的迟到响应
+        while let Ok(Ok((size, _))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            if let Some(response) = parse_binding_response(&buf[..size], &transaction) {
+                return Some(response);
+            }
+        }
+    }
+    None
+}
+
+async fn resolve_server(server: &str) -> Result<SocketAddr> {
+    let (host, port) = server.rsplit_once(':').context("STUN 服务器需要包含端口")?;
+    let port: u16 = port.parse().context("无效的 STUN 端口")?;
+    dns::resolve(host.trim_matches(['[', ']']), port)
+        .await?
+        .into_iter()
+        .find(SocketAddr::is_ipv4)
+        .with_context(|| format!("{} 没有 IPv4 地址", host))
+}
+
+/// 按 RFC 5780 的方法检测 NAT 映射和过滤行为
+pub async fn detect(servers: &[String]) -> Result<NatReport> {
+    let mut resolved = Vec::new();
+    for server in servers {
+        match resolve_server(server).await {
+            Ok(addr) => resolved.push(addr),
+            Err(e) => debug!("跳过 STUN 服务器 {}: {}", server, e),
+        }
+    }
+    if resolved.is_empty() {
+        bail!("无法解析任何 STUN 服务器");
+    }
+
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    // 本机用于出站的地址，用来判断是否存在 NAT
+    let local_ip = {
+        let probe = UdpSocket::bind("0.0.0.0:0").await?;
+        probe.connect(resolved[0]).await?;
+        probe.local_addr()?.ip()
+    };
+    let local = SocketAddr::new(local_ip, socket.local_addr()?.port());
+
+    // 映射行为：同一个本地端口发往不同服务器，映射地址不同即为对称型
+    let mut responses = Vec::new();
+    for server in &resolved {
+        if let Some(response) = binding(&socket, *server, 0).await {
+            debug!("STUN {} -> 映射地址 {}", server, response.mapped);
+            responses.push((*server, response));
+        }
+        if responses.len() >= 2 && responses.iter().any(|(_, r)| r.other.is_some()) {
+            break;
+        }
+    }
+
+    let Some((_, first)) = responses.first() else {
+        return Ok(NatReport {
+            nat_type: NatType::UdpBlocked,
+            local,
+            mapped: None,
+        });
+    };
+    let mapped = first.mapped;
+
+    if responses.iter().any(|(_, r)| r.mapped != mapped) {
+        return Ok(NatReport {
+            nat_type: NatType::Symmetric,
+            local,
+            mapped: Some(mapped),
+        });
+    }
+
+    // 过滤行为：让服务器从另一个 IP/端口回复，看 NAT 是否放行
+    let Some((server, _)) = responses.iter().find(|(_, r)| r.other.is_some()) else {
+        let nat_type = if mapped == local { NatType::Open } else { NatType::Cone };
+        return Ok(NatReport {
+            nat_type,
+            local,
+            mapped: Some(mapped),
+        });
+    };
+
+    let nat_type = if binding(&socket, *server, CHANGE_IP | CHANGE_PORT).await.is_some() {
+        if mapped == local { NatType::Open } else { NatType::FullCone }
+    } else if binding(&socket, *server, CHANGE_PORT).await.is_some() {
+        NatType::RestrictedCone
+    } else {
+        NatType::PortRestrictedCone
+    };
+
+    Ok(NatReport {
+        nat_type,
+        local,
+        mapped: Some(mapped),
+    })
+}
+
+/// 经由本地代理端口发送 Binding 请求，节点本身需要响应 STUN
+pub async fn probe_via_proxy(proxy_port: u16) -> Option<BindingResponse> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.ok()?;
+    binding(&socket, SocketAddr::from(([127, 0, 0, 1], proxy_port)), 0).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 RFC 5389 构造带 XOR-MAPPED-ADDRESS 的响应
+    fn response(transaction: &[u8; 12], mapped: SocketAddr, other: Option<SocketAddr>) -> Vec<u8> {
+        let mut attributes = Vec::new();
+        let SocketAddr::V4(mapped) = mapped else { unreachable!() };
+        let cookie = MAGIC_COOKIE.to_be_bytes();
+        attributes.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        attributes.extend_from_slice(&8u16.to_be_bytes());
+        attributes.extend_from_slice(&[0, 1]);
+        attributes.extend_from_slice(&(mapped.port() ^ 0x2112).to_be_bytes());
+        attributes.extend(mapped.ip().octets().iter().zip(cookie).map(|(b, k)| b ^ k));
+        if let Some(SocketAddr::V4(other)) = other {
+            attributes.extend_from_slice(&ATTR_OTHER_ADDRESS.to_be_bytes());
+            attributes.extend_from_slice(&8u16.to_be_bytes());
+            attributes.extend_from_slice(&[0, 1]);
+            attributes.extend_from_slice(&other.port().to_be_bytes());
+            attributes.extend_from_slice(&other.ip().octets());
+        }
+
+        let mut message = Vec::new();
+        message.extend_from_slice(&BINDING_RESPONSE.to_be_bytes());
+        message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        message.extend_from_slice(&cookie);
+        message.extend_from_slice(transaction);
+        message.extend(attributes);
+        message
+    }
+
+    #[test]
+    fn parses_binding_responses() {
+        let transaction = new_transaction();
+        let mapped: SocketAddr = "203.0.113.5:40000".parse().unwrap();
+        let other: SocketAddr = "198.51.100.9:3479".parse().unwrap();
+        let data = response(&transaction, mapped, Some(other));
+        assert_eq!(
+            parse_binding_response(&data, &transaction),
+            Some(BindingResponse { mapped, other: Some(other) })
+        );
+
+        // 事务不匹配、截断的数据都被拒绝
+        assert_eq!(parse_binding_response(&data, &[0; 12]), None);
+        assert_eq!(parse_binding_response(&data[..data.len() - 3], &transaction), None);
+        assert_eq!(parse_binding_response(&[1, 1, 0], &transaction), None);
+
+        let request = binding_request(&transaction, CHANGE_IP | CHANGE_PORT);
+        assert_eq!(request.len(), 28);
+        assert_eq!(&request[20..], &[0, 3, 0, 4, 0, 0, 0, 6]);
+    }
+
+    /// 本地 STUN 服务器：回复请求方地址，忽略 CHANGE-REQUEST（相当于端口限制型 NAT 的效果）
+    async fn stun_server(other: bool) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            while let Ok((size, peer)) = socket.recv_from(&mut buf).await {
+                let transaction: [u8; 12] = buf[8..20].try_into().unwrap();
+                if size > 20 {
+                    continue;
+                }
+                let other = other.then(|| SocketAddr::from(([127, 0, 0, 2], addr.port())));
+                let _ = socket.send_to(&response(&transaction, peer, other), peer).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn classifies_nat_behaviour() {
+        let servers = vec![stun_server(true).await.to_string(), stun_server(false).await.to_string()];
+        let report = detect(&servers).await.unwrap();
+        // 本机地址等于映射地址，但服务器从不从其他地址回复
+        assert_eq!(report.nat_type, NatType::PortRestrictedCone);
+
+        let report = detect(&[stun_server(false).await.to_string()]).await.unwrap();
+        assert_eq!(report.nat_type, NatType::Open);
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use crate::redirect::{Targets, DIRECT_RANGES};
+
+/// nftables 表名
+const TABLE: &str = "clashfun";
+/// iptables 自定义链：PREROUTING 跳转到 CHAIN，OUTPUT 跳转到 OUTPUT_CHAIN
+const CHAIN: &str = "CLASHFUN";
+const OUTPUT_CHAIN: &str = "CLASHFUN_OUT";
+/// TPROXY 使用的防火墙标记和策略路由表
+const FWMARK: &str = "0x1f2";
+const ROUTE_TABLE: &str = "233";
+
+/// 正在 TPROXY 的代理端口，0 表示未启用
+static TPROXY_PORT: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// nat 表 REDIRECT，TCP 的原始目标通过 SO_ORIGINAL_DST 取得
+    Redirect,
+    /// TCP 使用 TPROXY 不做地址转换；UDP 仍使用 REDIRECT，回包由 conntrack 还原源地址
+    Tproxy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Nft,
+    Iptables,
+}
+
+impl Backend {
+    /// 优先使用 nft，规则可以整表原子替换
+    pub fn detect() -> Option<Self> {
+        let installed = |program: &str| {
+            Command::new(program)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        if installed("nft") {
+            Some(Self::Nft)
+        } else if installed("iptables") {
+            Some(Self::Iptables)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nft => "nftables",
+            Self::Iptables => "iptables",
+        }
+    }
+}
+
+/// nft 中匹配目标的表达式，proto 为 "{ tcp, udp }"、"tcp" 或 "udp"
+fn nft_matches(targets: &Targets, proto: &str) -> Vec<String> {
+    let mut matches = Vec::new();
+    if !targets.ports.is_empty() {
+        let ports = targets.ports.iter().map(u16::to_string).collect::<Vec<_>>().join(", ");
+        matches.push(format!("meta l4proto {} th dport {{ {} }}", proto, ports));
+    }
+    if !targets.ranges.is_empty() {
+        matches.push(format!("meta l4proto {} ip daddr {{ {} }}", proto, targets.ranges.join(", ")));
+    }
+    matches
+}
+
+/// cgroup v2 路径需要同时给出层级
+fn nft_cgroup(cgroup: &str, proto: &str) -> String {
+    let path = cgroup.trim_matches('/');
+    format!("meta l4proto {} socket cgroupv2 level {} \"{}\"", proto, path.split('/').count(), path)
+}
+
+/// 整表替换的 nft 脚本；uid 为代理自身，它连接节点的流量不能被重定向
+pub fn nft_script(method: Method, targets: &Targets, cgroup: Option<&str>, proxy_port: u16, uid: u32) -> String {
+    let mut script = format!(
+        "add table ip {table}\ndelete table ip {table}\ntable ip {table} {{\n    set direct {{\n        type ipv4_addr; flags interval\n        elements = {{ {} }}\n    }}\n",
+        DIRECT_RANGES.join(", "),
+        table = TABLE
+    );
+
+    let mut chain = |name: &str, hook: &str, rules: Vec<String>, output: bool| {
+        script += &format!("    chain {} {{\n        {}; policy accept;\n", name, hook);
+        if output {
+            script += &format!("        meta skuid {} return\n", uid);
+        }
+        script += "        ip daddr @direct return\n";
+        for rule in rules {
+            script += &format!("        {}\n", rule);
+        }
+        script += "    }\n";
+    };
+
+    let redirect = format!("redirect to :{}", proxy_port);
+    let nat_proto = if method == Method::Tproxy { "udp" } else { "{ tcp, udp }" };
+    let nat_rules = |output: bool| {
+        let mut rules: Vec<String> = nft_matches(targets, nat_proto)
+            .into_iter()
+            .map(|m| format!("{} {}", m, redirect))
+            .collect();
+        if let Some(cgroup) = cgroup.filter(|_| output) {
+            rules.push(format!("{} {}", nft_cgroup(cgroup, nat_proto), redirect));
+        }
+        rules
+    };
+    chain("prerouting", "type nat hook prerouting priority dstnat", nat_rules(false), false);
+    chain("output", "type nat hook output priority -100", nat_rules(true), true);
+
+    if method == Method::Tproxy {
+        let tproxy = format!("tproxy to :{} meta mark set {} accept", proxy_port, FWMARK);
+        let prerouting = nft_matches(targets, "tcp").into_iter().map(|m| format!("{} {}", m, tproxy)).collect();
+        chain("tproxy", "type filter hook prerouting priority mangle", prerouting, false);
+
+        // 本机发出的 TCP 打上标记后经策略路由回到 lo，再由上面的 TPROXY 规则接收
+        let mark = format!("meta mark set {}", FWMARK);
+        let mut output: Vec<String> = nft_matches(targets, "tcp").into_iter().map(|m| format!("{} {}", m, mark)).collect();
+        if let Some(cgroup) = cgroup {
+            output.push(format!("{} {}", nft_cgroup(cgroup, "tcp"), mark));
+        }
+        chain("tproxy_output", "type route hook output priority mangle", output, true);
+    }
+
+    script += "}\n";
+    script
+}
+
+/// iptables 中匹配目标的参数
+fn iptables_matches(targets: &Targets, proto: &str) -> Vec<Vec<String>> {
+    let ports = targets.ports.iter().map(|port| vec!["--dport".to_string(), port.to_string()]);
+    let ranges = targets.ranges.iter().map(|range| vec!["-d".to_string(), range.clone()]);
+    ports
+        .chain(ranges)
+        .map(|matcher| [vec!["-p".to_string(), proto.to_string()], matcher].concat())
+        .collect()
+}
+
+/// 依次执行的 iptables 参数（含 -t 表名）
+pub fn iptables_commands(method: Method, targets: &Targets, cgroup: Option<&str>, proxy_port: u16, uid: u32) -> Vec<Vec<String>> {
+    let args = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+    let port = proxy_port.to_string();
+    let mut commands = Vec::new();
+
+    // 两个表中的链结构相同：先跳过本机/局域网，OUTPUT 还要跳过代理自身，再按目标执行动作
+    let mut chains = |table: &str, protos: &[&str], prerouting: &[&str], output: &[&str]| {
+        commands.push(args(&["-t", table, "-N", CHAIN]));
+        commands.push(args(&["-t", table, "-N", OUTPUT_CHAIN]));
+        commands.push(args(&["-t", table, "-A", OUTPUT_CHAIN, "-m", "owner", "--uid-owner", &uid.to_string(), "-j", "RETURN"]));
+        for range in DIRECT_RANGES {
+            commands.push(args(&["-t", table, "-A", CHAIN, "-d", range, "-j", "RETURN"]));
+            commands.push(args(&["-t", table, "-A", OUTPUT_CHAIN, "-d", range, "-j", "RETURN"]));
+        }
+        for proto in protos {
+            for matcher in iptables_matches(targets, proto) {
+                for (chain, action) in [(CHAIN, prerouting), (OUTPUT_CHAIN, output)] {
+                    let mut command = args(&["-t", table, "-A", chain]);
+                    command.extend(matcher.iter().cloned());
+                    command.extend(args(action));
+                    commands.push(command);
+                }
+            }
+            if let Some(cgroup) = cgroup {
+                let mut command = args(&["-t", table, "-A", OUTPUT_CHAIN, "-p", proto, "-m", "cgroup", "--path", cgroup]);
+                command.extend(args(output));
+                commands.push(command);
+            }
+        }
+        commands.push(args(&["-t", table, "-I", "PREROUTING", "-j", CHAIN]));
+        commands.push(args(&["-t", table, "-I", "OUTPUT", "-j", OUTPUT_CHAIN]));
+    };
+
+    let redirect = ["-j", "REDIRECT", "--to-ports", &port];
+    match method {
+        Method::Redirect => chains("nat", &["tcp", "udp"], &redirect, &redirect),
+        Method::Tproxy => {
+            chains("nat", &["udp"], &redirect, &redirect);
+            // 本机发出的 TCP 打上标记后经策略路由回到 lo，再由 PREROUTING 的 TPROXY 接收
+            chains(
+                "mangle",
+                &["tcp"],
+                &["-j", "TPROXY", "--on-port", &port, "--tproxy-mark", FWMARK],
+                &["-j", "MARK", "--set-mark", FWMARK],
+            );
+        }
+    }
+    commands
+}
+
+fn run(program: &str, args: &[String], input: Option<&str>) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("无法运行 {}", program))?;
+    if let Some(input) = input {
+        child.stdin.take().context("无法写入规则")?.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{} {} 失败: {}", program, args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+fn quiet(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// TPROXY 需要把带标记的包路由到本机
+fn add_policy_route() -> Result<()> {
+    remove_policy_route();
+    let args = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+    run("ip", &args(&["rule", "add", "fwmark", FWMARK, "lookup", ROUTE_TABLE]), None)?;
+    run("ip", &args(&["route", "add", "local", "0.0.0.0/0", "dev", "lo", "table", ROUTE_TABLE]), None)
+}
+
+fn remove_policy_route() {
+    while quiet("ip", &["rule", "del", "fwmark", FWMARK, "lookup", ROUTE_TABLE]) {}
+    quiet("ip", &["route", "flush", "table", ROUTE_TABLE]);
+}
+
+/// 替换重定向规则；没有目标也没有 cgroup 时移除全部规则
+pub fn apply(backend: Backend, method: Method, targets: &Targets, cgroup: Option<&str>, proxy_port: u16) -> Result<()> {
+    if targets.is_empty() && cgroup.is_none() {
+        cleanup();
+        return Ok(());
+    }
+    let uid = unsafe { libc::geteuid() };
+
+    match backend {
+        Backend::Nft => {
+            let script = nft_script(method, targets, cgroup, proxy_port, uid);
+            run("nft", &["-f".to_string(), "-".to_string()], Some(&script))?;
+        }
+        Backend::Iptables => {
+            remove_iptables();
+            for command in iptables_commands(method, targets, cgroup, proxy_port, uid) {
+                run("iptables", &command, None)?;
+            }
+        }
+    }
+
+    if method == Method::Tproxy {
+        add_policy_route()?;
+        TPROXY_PORT.store(proxy_port, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+fn remove_iptables() {
+    for table in ["nat", "mangle"] {
+        while quiet("iptables", &["-t", table, "-D", "PREROUTING", "-j", CHAIN]) {}
+        while quiet("iptables", &["-t", table, "-D", "OUTPUT", "-j", OUTPUT_CHAIN]) {}
+        for chain in [CHAIN, OUTPUT_CHAIN] {
+            quiet("iptables", &["-t", table, "-F", chain]);
+            quiet("iptables", &["-t", table, "-X", chain]);
+        }
+    }
+}
+
+/// 移除两种后端的全部规则和策略路由，启动时也会调用以清理上次异常退出的残留
+pub fn cleanup() {
+    TPROXY_PORT.store(0, Ordering::Relaxed);
+    quiet("nft", &["delete", "table", "ip", TABLE]);
+    remove_iptables();
+    remove_policy_route();
+}
+
+/// TPROXY 接收的连接的本地地址就是原始目标
+pub fn tproxy_destination(local: SocketAddr) -> Option<SocketAddr> {
+    let port = TPROXY_PORT.load(Ordering::Relaxed);
+    (port != 0 && local.port() != port).then_some(local)
+}
+
+/// 允许监听 socket 接收 TPROXY 转来的、目标不是本机地址的连接
+pub fn set_transparent(fd: std::os::fd::RawFd) -> Result<()> {
+    let enable: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_IP,
+            libc::IP_TRANSPARENT,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        bail!("设置 IP_TRANSPARENT 失败: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Targets {
+        Targets {
+            ports: vec![27015],
+            ranges: vec!["155.133.224.0/19".to_string()],
+        }
+    }
+
+    #[test]
+    fn builds_nft_script() {
+        let script = nft_script(Method::Redirect, &targets(), None, 7890, 0);
+        assert!(script.starts_with("add table ip clashfun\ndelete table ip clashfun\n"));
+        assert!(script.contains("meta l4proto { tcp, udp } th dport { 27015 } redirect to :7890"));
+        assert!(script.contains("meta l4proto { tcp, udp } ip daddr { 155.133.224.0/19 } redirect to :7890"));
+        assert!(script.contains("meta skuid 0 return"));
+        assert!(!script.contains("tproxy"));
+
+        let script = nft_script(Method::Tproxy, &targets(), Some("/user.slice/games"), 7890, 0);
+        assert!(script.contains("meta l4proto udp th dport { 27015 } redirect to :7890"));
+        assert!(script.contains("meta l4proto tcp th dport { 27015 } tproxy to :7890 meta mark set 0x1f2 accept"));
+        assert!(script.contains("meta l4proto tcp socket cgroupv2 level 2 \"user.slice/games\" meta mark set 0x1f2"));
+        assert_eq!(script.matches('{').count(), script.matches('}').count());
+    }
+
+    #[test]
+    fn builds_iptables_commands() {
+        let commands: Vec<String> = iptables_commands(Method::Tproxy, &targets(), Some("games"), 7890, 1000)
+            .into_iter()
+            .map(|command| command.join(" "))
+            .collect();
+        assert!(commands.contains(&"-t nat -A CLASHFUN -p udp --dport 27015 -j REDIRECT --to-ports 7890".to_string()));
+        assert!(commands.contains(&"-t mangle -A CLASHFUN -p tcp -d 155.133.224.0/19 -j TPROXY --on-port 7890 --tproxy-mark 0x1f2".to_string()));
+        assert!(commands.contains(&"-t mangle -A CLASHFUN_OUT -p tcp -m cgroup --path games -j MARK --set-mark 0x1f2".to_string()));
+        assert!(commands.contains(&"-t nat -A CLASHFUN_OUT -m owner --uid-owner 1000 -j RETURN".to_string()));
+        assert!(!commands.iter().any(|command| command.contains("-p tcp --dport 27015 -j REDIRECT")));
+    }
+}
@@ -0,0 +1,313 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::notification;
+use crate::tasks::TaskSet;
+
+/// 检查当前网络的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+static CONFIG: RwLock<Option<NetworkConfig>> = RwLock::new(None);
+/// 暂停加速的原因，None 表示正常加速
+static PAUSED: RwLock<Option<String>> = RwLock::new(None);
+
+/// 在某个网络上的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkAction {
+    /// 暂停加速：透明重定向的连接直连原始目标，其他连接被拒绝
+    Pause,
+    /// 正常加速，即使网络按流量计费
+    Accelerate,
+}
+
+/// 按 Wi-Fi 名称或网卡匹配的网络规则
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssid: Option<String>,
+    /// 网卡名称，例如手机 USB 共享网络的 usb0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    pub action: NetworkAction,
+}
+
+impl NetworkRule {
+    fn matches(&self, network: &NetworkInfo) -> bool {
+        if self.ssid.is_none() && self.interface.is_none() {
+            return false;
+        }
+        let ssid = self.ssid.as_ref().is_none_or(|ssid| network.ssid.as_ref() == Some(ssid));
+        let interface = self.interface.as_ref().is_none_or(|name| network.interface.as_ref() == Some(name));
+        ssid && interface
+    }
+}
+
+/// 按当前网络暂停或继续加速的设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// 系统标记为按流量计费或手机热点的网络上暂停加速
+    pub pause_on_metered: bool,
+    /// 按顺序匹配，优先于按流量计费的判断
+    pub rules: Vec<NetworkRule>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            pause_on_metered: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// 当前网络上需要暂停加速时返回原因
+    pub fn pause_reason(&self, network: &NetworkInfo) -> Option<String> {
+        let name = network.display_name();
+        match self.rules.iter().find(|rule| rule.matches(network)) {
+            Some(rule) if rule.action == NetworkAction::Pause => Some(format!("按规则在网络 {} 上暂停", name)),
+            Some(_) => None,
+            None if self.pause_on_metered && network.metered => Some(format!("{} 是按流量计费的网络", name)),
+            None => None,
+        }
+    }
+}
+
+/// 当前上网使用的网络
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkInfo {
+    /// 默认路由所在的网卡
+    pub interface: Option<String>,
+    pub ssid: Option<String>,
+    /// 系统标记为按流量计费，或是手机 USB 共享网络
+    pub metered: bool,
+}
+
+impl NetworkInfo {
+    pub fn display_name(&self) -> String {
+        self.ssid
+            .clone()
+            .or_else(|| self.interface.clone())
+            .unwrap_or_else(|| "未知网络".to_string())
+    }
+}
+
+pub fn set_config(config: NetworkConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+fn config() -> NetworkConfig {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// 加速暂停时返回原因
+pub fn paused() -> Option<String> {
+    PAUSED.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+fn set_paused(reason: Option<String>) {
+    let mut paused = PAUSED.write().unwrap_or_else(|e| e.into_inner());
+    if *paused == reason {
+        return;
+    }
+    match &reason {
+        Some(reason) => {
+            warn!("暂停加速: {}", reason);
+            notification::send("ClashFun 已暂停加速", reason);
+        }
+        None => {
+            info!("恢复加速");
+            notification::send("ClashFun 已恢复加速", "当前网络不再需要暂停加速");
+        }
+    }
+    *paused = reason;
+}
+
+/// 定期检查当前网络，在按流量计费的网络上暂停加速
+pub fn spawn(tasks: &TaskSet) {
+    tasks.spawn("网络检查", async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = config();
+            if !config.pause_on_metered && config.rules.is_empty() {
+                set_paused(None);
+                continue;
+            }
+            let Ok(network) = tokio::task::spawn_blocking(detect).await else {
+                continue;
+            };
+            set_paused(config.pause_reason(&network));
+        }
+    })
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Linux 上从默认路由找到网卡，通过 NetworkManager 查询 Wi-Fi 名称和是否按流量计费
+#[cfg(target_os = "linux")]
+pub fn detect() -> NetworkInfo {
+    let interface = run("ip", &["route", "show", "default"]).and_then(|output| parse_linux_route(&output));
+    let ssid = run("iwgetid", &["-r"])
+        .map(|output| output.trim().to_string())
+        .filter(|ssid| !ssid.is_empty());
+    let metered = match &interface {
+        Some(name) if is_tether_interface(name) => true,
+        Some(name) => run("nmcli", &["-t", "-f", "GENERAL.METERED", "device", "show", name])
+            .is_some_and(|output| parse_nmcli_metered(&output)),
+        None => false,
+    };
+    NetworkInfo { interface, ssid, metered }
+}
+
+/// macOS 上从默认路由找到网卡，iPhone USB 共享网络视为按流量计费
+#[cfg(target_os = "macos")]
+pub fn detect() -> NetworkInfo {
+    let interface = run("route", &["-n", "get", "default"]).and_then(|output| parse_macos_route(&output));
+    let ssid = interface
+        .as_deref()
+        .and_then(|name| run("ipconfig", &["getsummary", name]))
+        .and_then(|output| parse_ssid(&output));
+    let metered = interface.as_deref().is_some_and(|name| {
+        run("networksetup", &["-listallhardwareports"]).is_some_and(|output| parse_macos_tether_port(&output, name))
+    });
+    NetworkInfo { interface, ssid, metered }
+}
+
+/// Windows 上通过 netsh 查询 Wi-Fi 名称，通过网络成本判断是否按流量计费
+#[cfg(windows)]
+pub fn detect() -> NetworkInfo {
+    const COST_SCRIPT: &str = "[void][Windows.Networking.Connectivity.NetworkInformation,Windows.Networking.Connectivity,ContentType=WindowsRuntime];\
+        [Windows.Networking.Connectivity.NetworkInformation]::GetInternetConnectionProfile().GetConnectionCost().NetworkCostType";
+    let ssid = run("netsh", &["wlan", "show", "interfaces"]).and_then(|output| parse_ssid(&output));
+    let metered = run("powershell", &["-NoProfile", "-NonInteractive", "-Command", COST_SCRIPT])
+        .is_some_and(|output| matches!(output.trim(), "Fixed" | "Variable"));
+    NetworkInfo { interface: None, ssid, metered }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn detect() -> NetworkInfo {
+    NetworkInfo::default()
+}
+
+/// `ip route show default` 输出中的网卡
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_linux_route(output: &str) -> Option<String> {
+    let line = output.lines().next()?;
+    let mut fields = line.split_whitespace();
+    fields.find(|field| *field == "dev")?;
+    fields.next().map(str::to_string)
+}
+
+/// NetworkManager 的 GENERAL.METERED，包括猜测为按流量计费的手机热点
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_nmcli_metered(output: &str) -> bool {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("GENERAL.METERED:"))
+        .any(|value| value.trim_start().starts_with("yes"))
+}
+
+/// 安卓手机 USB 共享网络的网卡
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_tether_interface(name: &str) -> bool {
+    name.starts_with("usb") || name.starts_with("rndis")
+}
+
+/// `route -n get default` 输出中的网卡
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_macos_route(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("interface:"))
+        .map(|name| name.trim().to_string())
+}
+
+/// `networksetup -listallhardwareports` 中该网卡是否是 iPhone USB
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_macos_tether_port(output: &str, interface: &str) -> bool {
+    let mut port = "";
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix("Hardware Port:") {
+            port = name.trim();
+        } else if line.strip_prefix("Device:").map(str::trim) == Some(interface) {
+            return port.contains("iPhone");
+        }
+    }
+    false
+}
+
+/// `ipconfig getsummary` 和 `netsh wlan show interfaces` 中的 SSID 行
+#[cfg_attr(not(any(target_os = "macos", windows)), allow(dead_code))]
+fn parse_ssid(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "SSID")
+            .then(|| value.trim().to_string())
+            .filter(|ssid| !ssid.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_platform_output() {
+        assert_eq!(
+            parse_linux_route("default via 192.168.42.129 dev usb0 proto dhcp metric 100\n").as_deref(),
+            Some("usb0")
+        );
+        assert!(parse_nmcli_metered("GENERAL.METERED:yes (guessed)\n"));
+        assert!(!parse_nmcli_metered("GENERAL.METERED:no (guessed)\n"));
+        assert_eq!(
+            parse_macos_route("   route to: default\n  gateway: 172.20.10.1\n  interface: en8\n").as_deref(),
+            Some("en8")
+        );
+        let ports = "Hardware Port: Wi-Fi\nDevice: en0\n\nHardware Port: iPhone USB\nDevice: en8\n";
+        assert!(parse_macos_tether_port(ports, "en8"));
+        assert!(!parse_macos_tether_port(ports, "en0"));
+        assert_eq!(parse_ssid("    Name  : WLAN\n    SSID                   : Home 5G\n    BSSID : aa:bb\n").as_deref(), Some("Home 5G"));
+        assert_eq!(parse_ssid("  BSSID : aa:bb\n"), None);
+    }
+
+    #[test]
+    fn rules_override_metered_detection() {
+        let hotspot = NetworkInfo {
+            interface: Some("wlan0".to_string()),
+            ssid: Some("Pixel".to_string()),
+            metered: true,
+        };
+        let home = NetworkInfo {
+            ssid: Some("Home".to_string()),
+            metered: false,
+            ..hotspot.clone()
+        };
+
+        let mut config = NetworkConfig::default();
+        assert!(config.pause_reason(&hotspot).is_some());
+        assert!(config.pause_reason(&home).is_none());
+
+        config.rules = vec![
+            NetworkRule { ssid: Some("Pixel".to_string()), interface: None, action: NetworkAction::Accelerate },
+            NetworkRule { ssid: Some("Home".to_string()), interface: None, action: NetworkAction::Pause },
+        ];
+        assert!(config.pause_reason(&hotspot).is_none());
+        assert_eq!(config.pause_reason(&home).as_deref(), Some("按规则在网络 Home 上暂停"));
+
+        config.pause_on_metered = false;
+        config.rules.clear();
+        assert!(config.pause_reason(&hotspot).is_none());
+    }
+}
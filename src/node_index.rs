@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::subscription::{self, Node};
+
+const NODE_INDEX_FILE: &str = "node_index.json";
+
+/// 上次 'cf nodes' 显示的一个序号对应的节点
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedNode {
+    pub name: String,
+    /// 稳定 ID，节点改名后据此找回
+    pub id: String,
+}
+
+fn index_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(NODE_INDEX_FILE))
+}
+
+/// 保存序号和节点的对应关系，第 i 个节点的序号为 i + 1
+pub fn save(nodes: &[Node]) -> Result<()> {
+    let entries: Vec<IndexedNode> = nodes
+        .iter()
+        .map(|node| IndexedNode {
+            name: node.name.clone(),
+            id: node.id(),
+        })
+        .collect();
+    let path = index_file()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("无法创建缓存目录")?;
+    }
+    fs::write(&path, serde_json::to_string(&entries)?).with_context(|| format!("无法写入节点序号: {:?}", path))
+}
+
+/// 上次保存的对应关系，还没有运行过 'cf nodes' 时为空
+pub fn load() -> Result<Vec<IndexedNode>> {
+    let path = index_file()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("无法读取节点序号: {:?}", path)),
+    };
+    Ok(serde_json::from_str(&content).unwrap_or_default())
+}
+
+/// 在当前订阅中找到序号对应的节点，订阅顺序变化或节点改名时仍能找回
+pub fn resolve<'a>(entries: &[IndexedNode], index: usize, nodes: &'a [Node]) -> Option<&'a Node> {
+    let entry = index.checked_sub(1).and_then(|i| entries.get(i))?;
+    subscription::find_selected(nodes, &entry.name, Some(&entry.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, port: u16) -> Node {
+        Node::test(name, "127.0.0.1", port, "ss")
+    }
+
+    #[test]
+    fn resolves_indices_after_reorder_and_rename() {
+        let listed = [node("香港 01", 8388), node("日本 01", 8389)];
+        let entries: Vec<IndexedNode> = listed
+            .iter()
+            .map(|node| IndexedNode {
+                name: node.name.clone(),
+                id: node.id(),
+            })
+            .collect();
+
+        let mut renamed = listed[1].clone();
+        renamed.name = "日本 01 [IPLC]".to_string();
+        let current = vec![renamed, listed[0].clone()];
+        assert_eq!(resolve(&entries, 1, &current).map(|n| n.name.as_str()), Some("香港 01"));
+        assert_eq!(resolve(&entries, 2, &current).map(|n| n.name.as_str()), Some("日本 01 [IPLC]"));
+        assert!(resolve(&entries, 0, &current).is_none());
+        assert!(resolve(&entries, 3, &current).is_none());
+    }
+}
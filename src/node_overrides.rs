@@ -0,0 +1,169 @@
+use anyhow::{bail, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+use crate::subscription::Node;
+
+static RULES: LazyLock<RwLock<HashMap<String, NodeOverride>>> = LazyLock::new(Default::default);
+
+/// 单个节点的设置，覆盖订阅中的内容，用来绕过个别节点的问题而不必修改订阅
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeOverride {
+    /// TLS 握手时使用的服务器名称，默认为节点地址
+    pub sni: Option<String>,
+    /// 是否经该节点转发 UDP，节点不支持 UDP 时关闭
+    pub udp: bool,
+    /// 连接该节点的超时（毫秒），默认使用 timeouts.connect_ms
+    pub connect_timeout_ms: Option<u64>,
+}
+
+impl Default for NodeOverride {
+    fn default() -> Self {
+        Self {
+            sni: None,
+            udp: true,
+            connect_timeout_ms: None,
+        }
+    }
+}
+
+impl NodeOverride {
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// 与默认设置不同的项，用于显示
+    pub fn describe(&self) -> Vec<String> {
+        let mut items = Vec::new();
+        if let Some(sni) = &self.sni {
+            items.push(format!("SNI {}", sni));
+        }
+        if !self.udp {
+            items.push("不转发 UDP".to_string());
+        }
+        if let Some(ms) = self.connect_timeout_ms {
+            items.push(format!("连接超时 {}ms", ms));
+        }
+        items
+    }
+}
+
+/// 检查配置中的节点 ID 和各项设置
+pub fn validate(rules: &HashMap<String, NodeOverride>) -> Result<()> {
+    for (id, rule) in rules {
+        if id.len() != 12 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("node_overrides 中的 {} 不是节点 ID，运行 'cf node info <节点名称>' 查看", id);
+        }
+        if rule.sni.as_deref().is_some_and(|sni| sni.trim().is_empty()) {
+            bail!("节点 {} 的 sni 不能为空", id);
+        }
+        if rule.connect_timeout_ms == Some(0) {
+            bail!("节点 {} 的 connect_timeout_ms 必须大于 0", id);
+        }
+    }
+    Ok(())
+}
+
+/// 与订阅中的节点对照，找出没有对应节点或对应多个节点（未合并的重复节点）的设置
+pub fn mismatches(rules: &HashMap<String, NodeOverride>, nodes: &[Node]) -> Vec<String> {
+    let mut problems: Vec<String> = rules
+        .keys()
+        .filter_map(|id| {
+            let matched: Vec<&str> =
+                nodes.iter().filter(|node| node.id() == *id).map(|node| node.name.as_str()).collect();
+            match matched.len() {
+                0 => Some(format!("node_overrides 中的 {} 没有对应的节点，运行 'cf node info <节点名称>' 查看新的 ID", id)),
+                1 => None,
+                _ => Some(format!("node_overrides 中的 {} 同时对应 {} 个节点: {}", id, matched.len(), matched.join("、"))),
+            }
+        })
+        .collect();
+    problems.sort();
+    problems
+}
+
+/// 订阅解析后提示与节点对不上的设置
+pub fn warn_mismatches(nodes: &[Node]) {
+    let rules = RULES.read().unwrap_or_else(|e| e.into_inner());
+    for problem in mismatches(&rules, nodes) {
+        warn!("{}", problem);
+    }
+}
+
+/// 设置按节点 ID 覆盖的设置
+pub fn set_rules(rules: HashMap<String, NodeOverride>) {
+    *RULES.write().unwrap_or_else(|e| e.into_inner()) = rules;
+}
+
+/// 节点生效的设置，没有单独设置时为默认值
+pub fn for_node(node: &Node) -> NodeOverride {
+    RULES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&node.id())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// TLS 握手时发送的服务器名称
+pub fn server_name(node: &Node) -> String {
+    for_node(node).sni.unwrap_or_else(|| node.server.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_overrides_by_node_id() {
+        let node = Node::test("香港 01", "hk.example.com", 443, "trojan");
+        let rules: HashMap<String, NodeOverride> =
+            serde_yaml::from_str(&format!("{}: {{sni: cdn.example.com, udp: false}}", node.id())).unwrap();
+        validate(&rules).unwrap();
+        set_rules(rules);
+
+        let applied = for_node(&node);
+        assert!(!applied.udp);
+        assert_eq!(applied.connect_timeout(), None);
+        assert_eq!(server_name(&node), "cdn.example.com");
+
+        // 节点改名后按 ID 仍然生效，地址变化后不再生效
+        let renamed = Node { name: "香港 01 [IPLC]".to_string(), ..node.clone() };
+        assert!(!for_node(&renamed).udp);
+        let moved = Node { port: 8443, ..node.clone() };
+        assert_eq!(for_node(&moved), NodeOverride::default());
+
+        // 同一 CDN 入口后面 uuid 不同的节点不受影响
+        let neighbour = Node { password: Some("2222".to_string()), ..node };
+        assert_eq!(for_node(&neighbour), NodeOverride::default());
+
+        let by_name: HashMap<String, NodeOverride> = serde_yaml::from_str("香港 01: {udp: false}").unwrap();
+        assert!(validate(&by_name).is_err());
+    }
+
+    #[test]
+    fn reports_overrides_that_do_not_match_exactly_one_node() {
+        let node = |name: &str, uuid: &str| Node {
+            password: Some(uuid.to_string()),
+            ..Node::test(name, "cdn.example.com", 443, "vmess")
+        };
+        let hk = node("香港 01", "1111");
+        let jp = node("日本 01", "2222");
+        let hk_copy = node("香港 01 副本", "1111");
+        assert_ne!(hk.id(), jp.id());
+
+        let rules: HashMap<String, NodeOverride> =
+            [(hk.id(), NodeOverride::default()), ("000000000000".to_string(), NodeOverride::default())].into();
+        let problems = mismatches(&rules, &[hk.clone(), jp.clone()]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("000000000000"));
+
+        // 关闭 dedup_nodes 时未合并的重复节点共用同一个 ID
+        let problems = mismatches(&rules, &[hk, jp, hk_copy]);
+        assert!(problems.iter().any(|p| p.contains("2 个节点") && p.contains("香港 01 副本")), "{:?}", problems);
+    }
+}
@@ -0,0 +1,128 @@
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// 未注册应用 ID 的程序不能直接弹出通知，借用 PowerShell 的应用 ID
+#[cfg(windows)]
+const TOAST_APP_ID: &str = r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// 发送桌面通知，失败时静默忽略（无图形环境等）
+pub fn send(title: &str, body: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    let command = Command::new("notify-send")
+        .args(["--app-name=ClashFun", title, body])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    #[cfg(target_os = "macos")]
+    let command = Command::new("osascript")
+        .arg("-e")
+        .arg(format!(
+            "display notification {:?} with title {:?}",
+            body, title
+        ))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    #[cfg(windows)]
+    let command = {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+        Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &toast_script(title, body)])
+            .creation_flags(CREATE_NO_WINDOW)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+    };
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    let command: std::io::Result<std::process::Child> = {
+        let _ = (title, body);
+        Err(std::io::ErrorKind::Unsupported.into())
+    };
+
+    // 不等待通知进程退出，交给系统回收
+    if let Ok(mut child) = command {
+        std::thread::spawn(move || child.wait());
+    }
+}
+
+/// 终端响铃，游戏全屏时也能听到
+pub fn beep() {
+    use std::io::Write;
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(b"\x07");
+    let _ = stdout.flush();
+}
+
+/// 语音播报，没有语音合成程序时静默忽略
+pub fn speak(text: &str) {
+    #[cfg(target_os = "macos")]
+    let command = Command::new("say").arg(text).stdout(Stdio::null()).stderr(Stdio::null()).spawn();
+
+    #[cfg(target_os = "linux")]
+    let command = Command::new("spd-say")
+        .arg(text)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .or_else(|_| Command::new("espeak").arg(text).stdout(Stdio::null()).stderr(Stdio::null()).spawn());
+
+    #[cfg(windows)]
+    let command = {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+        let script = format!(
+            "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak('{}')",
+            text.replace('\'', "''")
+        );
+        Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+            .creation_flags(CREATE_NO_WINDOW)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+    };
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    let command: std::io::Result<std::process::Child> = {
+        let _ = text;
+        Err(std::io::ErrorKind::Unsupported.into())
+    };
+
+    if let Ok(mut child) = command {
+        std::thread::spawn(move || child.wait());
+    }
+}
+
+/// 通过 WinRT 的 ToastNotificationManager 显示 Windows 10/11 的通知
+#[cfg(windows)]
+fn toast_script(title: &str, body: &str) -> String {
+    // PowerShell 单引号字符串中用两个单引号表示一个
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    format!(
+        "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null; \
+         $xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $text = $xml.GetElementsByTagName('text'); \
+         $text.Item(0).AppendChild($xml.CreateTextNode({})) > $null; \
+         $text.Item(1).AppendChild($xml.CreateTextNode({})) > $null; \
+         [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier({}).Show([Windows.UI.Notifications.ToastNotification]::new($xml))",
+        quote(title),
+        quote(body),
+        quote(TOAST_APP_ID)
+    )
+}
@@ -0,0 +1,433 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tokio_native_tls::TlsStream;
+
+use crate::buffer_pool::{BufferPool, PooledBuffer};
+use crate::dns;
+use crate::node_overrides;
+use crate::outbound;
+use crate::subscription::Node;
+
+/// 帧头：2 字节数据长度 + 2 字节填充长度
+const FRAME_HEADER: usize = 4;
+/// 发送队列长度，队列满时丢包而不是阻塞
+const SEND_QUEUE: usize = 256;
+
+/// 单个节点的流量混淆设置
+///
+/// pacing_ms 只改变发送时机，任何节点都能使用。max_padding 和 tls 把数据包封装为 ClashFun 自己的帧格式
+/// `2 字节数据长度 | 2 字节填充长度 | 数据 | 填充`（见 encode / decode），Clash、SS、Trojan 等服务端都不认识，
+/// 只有节点端在转发前用相同格式解封装时才能使用，因此需要同时设置 experimental_framing。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObfsConfig {
+    /// 每个 UDP 包追加的随机填充最大字节数，0 为关闭；需要 experimental_framing
+    pub max_padding: u16,
+    /// 按固定间隔发送以平滑时序特征（毫秒），0 为关闭
+    pub pacing_ms: u64,
+    /// 通过 TLS 连接承载 UDP；需要 experimental_framing
+    pub tls: bool,
+    /// 确认节点端部署了按 encode / decode 格式解封装的转发程序，未设置时忽略 max_padding 和 tls
+    pub experimental_framing: bool,
+    /// TLS 模式下跳过证书校验
+    pub skip_cert_verify: bool,
+    /// TLS 模式下隧道空闲多少秒后发送不含数据的帧保持连接，0 为关闭，需要节点端忽略空帧
+    pub keepalive_secs: u64,
+}
+
+impl ObfsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_padding > 0 || self.pacing_ms > 0 || self.tls
+    }
+
+    /// 是否需要加帧头（节点端需要解封装）
+    fn framed(&self) -> bool {
+        self.max_padding > 0 || self.tls
+    }
+
+    /// 每个数据包最多增加的字节数（不含 TLS 记录开销）
+    pub fn overhead(&self) -> usize {
+        if self.framed() {
+            FRAME_HEADER + self.max_padding as usize
+        } else {
+            0
+        }
+    }
+}
+
+static RULES: LazyLock<RwLock<HashMap<String, ObfsConfig>>> = LazyLock::new(Default::default);
+
+/// 设置按节点名称启用的混淆规则，并提示额外开销
+pub fn set_rules(rules: HashMap<String, ObfsConfig>) {
+    let rules = effective(rules);
+
+    for (node, config) in &rules {
+        let mut costs = Vec::new();
+        if config.overhead() > 0 {
+            costs.push(format!("每个 UDP 包最多增加 {} 字节", config.overhead()));
+        }
+        if config.pacing_ms > 0 {
+            costs.push(format!("最多增加 {}ms 发送延迟", config.pacing_ms));
+        }
+        if config.tls {
+            costs.push("UDP 改为经由 TLS 连接传输，丢包时会出现队头阻塞".to_string());
+        }
+        warn!("⚠️ 节点 {} 启用了流量混淆: {}", node, costs.join("，"));
+        if config.keepalive_secs > 0 && !config.tls {
+            warn!("⚠️ 节点 {} 的 keepalive_secs 只在 tls 模式下生效", node);
+        }
+        if config.framed() {
+            warn!("⚠️ 节点 {} 启用了实验性的帧封装，节点端没有解封装时 UDP 将无法使用", node);
+        }
+    }
+
+    *RULES.write().unwrap_or_else(|e| e.into_inner()) = rules;
+}
+
+/// 实际生效的规则：没有确认 experimental_framing 的封装设置被忽略，去掉没有启用任何混淆的节点
+fn effective(rules: HashMap<String, ObfsConfig>) -> HashMap<String, ObfsConfig> {
+    rules
+        .into_iter()
+        .map(|(node, mut config)| {
+            if config.framed() && !config.experimental_framing {
+                warn!(
+                    "⚠️ 节点 {} 的 max_padding / tls 使用 ClashFun 私有的帧格式，普通的 Clash/SS/Trojan 节点无法解析，\
+                     已忽略；节点端部署了解封装程序时设置 experimental_framing: true",
+                    node
+                );
+                config.max_padding = 0;
+                config.tls = false;
+            }
+            (node, config)
+        })
+        .filter(|(_, config)| config.is_enabled())
+        .collect()
+}
+
+/// 节点启用的混淆设置
+pub fn for_node(name: &str) -> Option<ObfsConfig> {
+    RULES.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+}
+
+/// 向混淆会话发送数据的句柄，全部释放后发送任务自动结束
+#[derive(Clone)]
+pub struct ObfsSender {
+    tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl ObfsSender {
+    /// 放入发送队列，队列已满或会话已关闭时返回 false
+    pub fn send(&self, data: &[u8]) -> bool {
+        self.tx.try_send(data.to_vec()).is_ok()
+    }
+}
+
+/// 从节点接收并解封装的数据
+pub enum ObfsReceiver {
+    Udp { socket: Arc<UdpSocket>, framed: bool },
+    Tls(ReadHalf<TlsStream<TcpStream>>),
+}
+
+impl ObfsReceiver {
+    /// 接收下一个数据包，使用从 pool 借出的缓冲区
+    pub async fn recv(&mut self, pool: &BufferPool) -> io::Result<PooledBuffer> {
+        let mut buf = pool.get();
+        match self {
+            Self::Udp { socket, framed } => loop {
+                let size = socket.recv(&mut buf).await?;
+                if !*framed {
+                    buf.truncate(size);
+                    return Ok(buf);
+                }
+                match decode(&buf[..size]).map(<[u8]>::len) {
+                    Some(len) => {
+                        buf.copy_within(FRAME_HEADER..FRAME_HEADER + len, 0);
+                        buf.truncate(len);
+                        return Ok(buf);
+                    }
+                    None => debug!("丢弃无法解封装的 UDP 包: {} 字节", size),
+                }
+            },
+            Self::Tls(reader) => {
+                read_payload(reader, &mut buf).await?;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// 建立到节点的混淆 UDP 会话
+pub async fn connect(node: &Node, config: &ObfsConfig) -> Result<(ObfsSender, ObfsReceiver)> {
+    let (tx, rx) = mpsc::channel(SEND_QUEUE);
+
+    let receiver = if config.tls {
+        let stream = dns::connect_node(node)
+            .await
+            .context("TCP 连接失败")?;
+        let connector = tokio_native_tls::native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(config.skip_cert_verify)
+            .danger_accept_invalid_hostnames(config.skip_cert_verify)
+            .build()
+            .context("无法创建 TLS 连接器")?;
+        let stream = tokio_native_tls::TlsConnector::from(connector)
+            .connect(&node_overrides::server_name(node), stream)
+            .await
+            .context("TLS 握手失败")?;
+
+        let (reader, writer) = tokio::io::split(stream);
+        tokio::spawn(write_stream(writer, rx, config.clone()));
+        ObfsReceiver::Tls(reader)
+    } else {
+        let target: SocketAddr = *dns::resolve(&node.server, node.port)
+            .await?
+            .first()
+            .context("没有解析结果")?;
+        let socket = Arc::new(outbound::udp_socket(target).await.context("无法创建 UDP socket")?);
+
+        tokio::spawn(write_datagrams(Arc::clone(&socket), rx, config.clone()));
+        ObfsReceiver::Udp {
+            socket,
+            framed: config.framed(),
+        }
+    };
+
+    Ok((ObfsSender { tx }, receiver))
+}
+
+/// 启用了平滑发送时按固定间隔放行数据包
+fn pacer(config: &ObfsConfig) -> Option<tokio::time::Interval> {
+    (config.pacing_ms > 0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.pacing_ms));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    })
+}
+
+async fn write_datagrams(socket: Arc<UdpSocket>, mut rx: mpsc::Receiver<Vec<u8>>, config: ObfsConfig) {
+    let mut pacer = pacer(&config);
+    let mut rng = Rng::new();
+
+    while let Some(packet) = rx.recv().await {
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.tick().await;
+        }
+        let result = if config.framed() {
+            socket.send(&encode(&packet, config.max_padding, &mut rng)).await
+        } else {
+            socket.send(&packet).await
+        };
+        if let Err(e) = result {
+            debug!("混淆 UDP 发送失败: {}", e);
+        }
+    }
+}
+
+async fn write_stream<W: AsyncWrite + Unpin>(mut writer: W, mut rx: mpsc::Receiver<Vec<u8>>, config: ObfsConfig) {
+    let mut pacer = pacer(&config);
+    let mut rng = Rng::new();
+
+    let keepalive = (config.keepalive_secs > 0).then(|| Duration::from_secs(config.keepalive_secs));
+
+    loop {
+        let packet = match keepalive {
+            // 空闲时发送不含数据的帧，避免节点断开空闲的隧道
+            Some(idle) => tokio::time::timeout(idle, rx.recv()).await.unwrap_or(Some(Vec::new())),
+            None => rx.recv().await,
+        };
+        let Some(packet) = packet else {
+            break;
+        };
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.tick().await;
+        }
+        if let Err(e) = writer.write_all(&encode(&packet, config.max_padding, &mut rng)).await {
+            debug!("TLS 隧道写入失败: {}", e);
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// 封装为 `长度 | 填充长度 | 数据 | 随机填充`，超过 u16 的部分不会出现（UDP 包最大 64KB）
+pub fn encode(payload: &[u8], max_padding: u16, rng: &mut Rng) -> Vec<u8> {
+    let payload = &payload[..payload.len().min(u16::MAX as usize)];
+    let padding = if max_padding > 0 {
+        (rng.next() % (max_padding as u64 + 1)) as usize
+    } else {
+        0
+    };
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER + payload.len() + padding);
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&(padding as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.extend((0..padding).map(|_| rng.next() as u8));
+    frame
+}
+
+/// 解封装单个数据报，格式不符时返回 None
+pub fn decode(frame: &[u8]) -> Option<&[u8]> {
+    let header = frame.get(..FRAME_HEADER)?;
+    let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+    let padding = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if frame.len() != FRAME_HEADER + len + padding {
+        return None;
+    }
+    frame.get(FRAME_HEADER..FRAME_HEADER + len)
+}
+
+/// 把下一个带数据的帧读入 buf，跳过节点端发送的 keepalive 空帧
+async fn read_payload<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut PooledBuffer) -> io::Result<()> {
+    loop {
+        let len = read_frame(reader, buf).await?;
+        if len > 0 {
+            buf.truncate(len);
+            return Ok(());
+        }
+    }
+}
+
+/// 读取一帧，数据放在 buf 开头，返回数据长度；填充读到 buf 的剩余部分后丢弃
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut header = [0u8; FRAME_HEADER];
+    reader.read_exact(&mut header).await?;
+    let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+    let padding = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    let frame = buf
+        .get_mut(..len + padding)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "帧长度超过缓冲区"))?;
+    reader.read_exact(frame).await?;
+    Ok(len)
+}
+
+/// 生成填充用的伪随机数 (xorshift)，不用于加密
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new() -> Self {
+        Self(RandomState::new().hash_one(std::time::Instant::now()) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip_with_padding() {
+        let mut rng = Rng::new();
+        for size in [0, 1, 512, 1400] {
+            let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let frame = encode(&payload, 64, &mut rng);
+            assert!(frame.len() >= FRAME_HEADER + size && frame.len() <= FRAME_HEADER + size + 64);
+            assert_eq!(decode(&frame), Some(&payload[..]));
+        }
+
+        let frame = encode(b"abc", 0, &mut rng);
+        assert_eq!(frame.len(), FRAME_HEADER + 3);
+    }
+
+    #[test]
+    fn malformed_frames_are_rejected() {
+        let mut rng = Rng::new();
+        let frame = encode(b"hello", 16, &mut rng);
+        assert_eq!(decode(&frame[..frame.len() - 1]), None);
+        assert_eq!(decode(&[0, 1]), None);
+        assert_eq!(decode(&[0, 5, 0, 0, 1]), None);
+    }
+
+    #[tokio::test]
+    async fn reads_frames_from_stream() {
+        let mut rng = Rng::new();
+        let mut stream = encode(b"first", 8, &mut rng);
+        stream.extend(encode(b"second", 8, &mut rng));
+
+        let pool = BufferPool::new(1024);
+        let mut reader = &stream[..];
+        let mut buf = pool.get();
+        let len = read_frame(&mut reader, &mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"first");
+        let len = read_frame(&mut reader, &mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"second");
+        assert!(read_frame(&mut reader, &mut buf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn receives_framed_datagrams_into_pooled_buffers() {
+        let node_side = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(node_side.local_addr().unwrap()).await.unwrap();
+        node_side.connect(socket.local_addr().unwrap()).await.unwrap();
+        let mut receiver = ObfsReceiver::Udp { socket: Arc::new(socket), framed: true };
+
+        let pool = BufferPool::new(2048);
+        node_side.send(b"not a frame").await.unwrap();
+        node_side.send(&encode(b"payload", 32, &mut Rng::new())).await.unwrap();
+        let packet = receiver.recv(&pool).await.unwrap();
+        assert_eq!(&packet[..], b"payload");
+        drop(packet);
+        assert_eq!(pool.idle_buffers(), 1);
+    }
+
+    #[test]
+    fn framing_requires_explicit_opt_in() {
+        let padded = ObfsConfig { max_padding: 64, pacing_ms: 5, ..Default::default() };
+        let tunneled = ObfsConfig { tls: true, experimental_framing: true, ..Default::default() };
+        let unpaced = ObfsConfig { tls: true, ..Default::default() };
+        let rules = effective(HashMap::from([
+            ("香港 01".to_string(), padded),
+            ("日本 01".to_string(), tunneled.clone()),
+            ("美国 01".to_string(), unpaced),
+        ]));
+
+        // 没有确认的封装设置被忽略，只保留平滑发送
+        assert_eq!(rules["香港 01"], ObfsConfig { pacing_ms: 5, ..Default::default() });
+        assert_eq!(rules["日本 01"], tunneled);
+        assert!(!rules.contains_key("美国 01"));
+    }
+
+    #[tokio::test]
+    async fn sends_keepalive_frames_when_idle() {
+        let config = ObfsConfig {
+            tls: true,
+            keepalive_secs: 1,
+            ..Default::default()
+        };
+        let (tx, rx) = mpsc::channel(SEND_QUEUE);
+        let (writer, mut reader) = tokio::io::duplex(1024);
+        tokio::spawn(write_stream(writer, rx, config));
+
+        // 空闲时收到空帧，读取数据时会跳过
+        let mut frame = [0u8; 64];
+        let keepalive = tokio::time::timeout(Duration::from_secs(3), read_frame(&mut reader, &mut frame))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(keepalive, 0);
+        tx.send(b"data".to_vec()).await.unwrap();
+        let pool = BufferPool::new(1024);
+        let mut buf = pool.get();
+        read_payload(&mut reader, &mut buf).await.unwrap();
+        assert_eq!(&buf[..], b"data");
+    }
+}
@@ -0,0 +1,132 @@
+use log::warn;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::net::{TcpSocket, UdpSocket};
+
+use crate::config::Config;
+
+/// 连接节点时使用的出口网卡和地址
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutboundBinding {
+    /// 网卡名称，例如 eth0、wlan0 (仅 Linux)
+    pub interface: Option<String>,
+    /// 源地址，需要属于本机的某个网卡
+    pub bind_ip: Option<IpAddr>,
+}
+
+impl OutboundBinding {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            interface: config.interface.clone().filter(|name| !name.trim().is_empty()),
+            bind_ip: config.bind_ip,
+        }
+    }
+
+    /// 绑定到 target 所用的本地地址，协议族不同时返回错误
+    fn local_addr(&self, target: SocketAddr) -> io::Result<Option<SocketAddr>> {
+        match self.bind_ip {
+            Some(ip) if ip.is_ipv4() != target.is_ipv4() => Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("出口地址 {} 与目标 {} 的协议族不同", ip, target),
+            )),
+            Some(ip) => Ok(Some(SocketAddr::new(ip, 0))),
+            None => Ok(None),
+        }
+    }
+}
+
+static BINDING: RwLock<OutboundBinding> = RwLock::new(OutboundBinding {
+    interface: None,
+    bind_ip: None,
+});
+
+/// 设置出口绑定，只影响之后建立的连接
+pub fn set_binding(binding: OutboundBinding) {
+    if let Some(name) = &binding.interface {
+        if cfg!(not(target_os = "linux")) {
+            warn!("当前系统不支持按网卡绑定出口，忽略 interface: {}，请改用 bind_ip", name);
+        } else if !std::path::Path::new("/sys/class/net").join(name).exists() {
+            warn!("找不到网卡 {}，连接节点可能会失败", name);
+        }
+    }
+    *BINDING.write().unwrap_or_else(|e| e.into_inner()) = binding;
+}
+
+fn binding() -> OutboundBinding {
+    BINDING.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// TCP keepalive 的空闲时间（秒），0 为关闭
+static TCP_KEEPALIVE_SECS: AtomicU64 = AtomicU64::new(30);
+/// 开始探测后两次探测的间隔
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 设置连接节点的 TCP keepalive，只影响之后建立的连接
+pub fn set_tcp_keepalive(secs: u64) {
+    TCP_KEEPALIVE_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// 空闲时定期发送 keepalive 探测，避免节点或中间的 NAT 断开空闲的游戏大厅连接
+fn apply_keepalive(socket: &TcpSocket) -> io::Result<()> {
+    let secs = TCP_KEEPALIVE_SECS.load(Ordering::Relaxed);
+    if secs == 0 {
+        return Ok(());
+    }
+    let keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(secs));
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd", target_os = "windows"))]
+    let keepalive = keepalive.with_interval(KEEPALIVE_INTERVAL.min(Duration::from_secs(secs)));
+    socket2::SockRef::from(socket).set_tcp_keepalive(&keepalive)
+}
+
+/// 创建连接 target 的 TCP socket，按配置绑定出口网卡和地址
+pub fn tcp_socket(target: SocketAddr) -> io::Result<TcpSocket> {
+    let binding = binding();
+    let socket = if target.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    if let Some(local) = binding.local_addr(target)? {
+        socket.bind(local)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(name) = &binding.interface {
+        socket.bind_device(Some(name.as_bytes()))?;
+    }
+    if let Err(e) = apply_keepalive(&socket) {
+        warn!("设置 TCP keepalive 失败: {}", e);
+    }
+    Ok(socket)
+}
+
+/// 创建已连接到 target 的 UDP socket，按配置绑定出口网卡和地址
+pub async fn udp_socket(target: SocketAddr) -> io::Result<UdpSocket> {
+    let binding = binding();
+    let local = binding.local_addr(target)?.unwrap_or_else(|| {
+        let unspecified = if target.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+        SocketAddr::new(unspecified, 0)
+    });
+    let socket = UdpSocket::bind(local).await?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = &binding.interface {
+        socket.bind_device(Some(name.as_bytes()))?;
+    }
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_ip_must_match_target_family() {
+        let binding = OutboundBinding {
+            interface: None,
+            bind_ip: Some("192.0.2.10".parse().unwrap()),
+        };
+        let local = binding.local_addr("198.51.100.1:443".parse().unwrap()).unwrap();
+        assert_eq!(local, Some("192.0.2.10:0".parse().unwrap()));
+        assert!(binding.local_addr("[2001:db8::1]:443".parse().unwrap()).is_err());
+        assert_eq!(OutboundBinding::default().local_addr("198.51.100.1:443".parse().unwrap()).unwrap(), None);
+    }
+}
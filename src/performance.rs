@@ -0,0 +1,234 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::game_detect::SupportedGame;
+
+/// 低延迟模式下默认的工作线程数：一个处理转发，一个处理其他任务
+const LOW_LATENCY_WORKER_THREADS: usize = 2;
+/// 低延迟模式下提高后的进程优先级 (nice 值)
+#[cfg_attr(not(unix), allow(dead_code))]
+const LOW_LATENCY_NICE: i32 = -10;
+/// 推迟的后台任务再次检查游戏是否结束的间隔
+pub const DEFER_RETRY: Duration = Duration::from_secs(60);
+
+static LOW_LATENCY: AtomicBool = AtomicBool::new(false);
+static GAME_ACTIVE: AtomicBool = AtomicBool::new(false);
+static DO_NOT_DISTURB: RwLock<Option<DoNotDisturbConfig>> = RwLock::new(None);
+/// 当前 UDP 会话识别出的游戏
+static ACTIVE_GAMES: RwLock<Vec<SupportedGame>> = RwLock::new(Vec::new());
+
+/// 性能相关设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerformanceConfig {
+    /// 低延迟模式：限制工作线程并固定到 CPU 核心，游戏进行中推迟后台任务，并提高进程优先级
+    pub low_latency: bool,
+    /// 低延迟模式下的工作线程数，1 为单线程运行时，0 为默认值
+    pub worker_threads: usize,
+    /// 把工作线程固定到各自的 CPU 核心上（仅 Linux）
+    pub pin_threads: bool,
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        Self {
+            low_latency: false,
+            worker_threads: 0,
+            pin_threads: true,
+        }
+    }
+}
+
+impl PerformanceConfig {
+    fn worker_threads(&self) -> usize {
+        match self.worker_threads {
+            0 => LOW_LATENCY_WORKER_THREADS,
+            threads => threads,
+        }
+    }
+}
+
+/// 勿扰模式：竞技游戏对局中推迟订阅刷新、全部节点测速和检查更新，避免与游戏争抢上行带宽
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DoNotDisturbConfig {
+    pub enabled: bool,
+    /// 对局中开启勿扰的游戏
+    pub games: Vec<SupportedGame>,
+}
+
+impl Default for DoNotDisturbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            games: vec![SupportedGame::Valorant, SupportedGame::CounterStrike, SupportedGame::Dota2],
+        }
+    }
+}
+
+impl DoNotDisturbConfig {
+    /// 正在进行的游戏中第一个需要勿扰的
+    fn matching<'a>(&self, active: &'a [SupportedGame]) -> Option<&'a SupportedGame> {
+        if !self.enabled {
+            return None;
+        }
+        active.iter().find(|game| self.games.contains(game))
+    }
+}
+
+/// 按设置创建运行时构建器，只有 cf start 使用低延迟模式
+pub fn runtime_builder(config: &PerformanceConfig) -> tokio::runtime::Builder {
+    if !config.low_latency {
+        return tokio::runtime::Builder::new_multi_thread();
+    }
+    LOW_LATENCY.store(true, Ordering::Relaxed);
+
+    let threads = config.worker_threads();
+    if threads == 1 {
+        return tokio::runtime::Builder::new_current_thread();
+    }
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.worker_threads(threads);
+    if config.pin_threads {
+        pin_worker_threads(&mut builder, threads);
+    }
+    builder
+}
+
+/// 工作线程依次固定到最后几个 CPU 核心，避开系统通常优先使用的 0 号核心
+#[cfg(target_os = "linux")]
+fn pin_worker_threads(builder: &mut tokio::runtime::Builder, threads: usize) {
+    use std::sync::atomic::AtomicUsize;
+
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    if cores <= threads {
+        debug!("CPU 核心数 {} 不多于工作线程数，不固定线程", cores);
+        return;
+    }
+    let next = std::sync::Arc::new(AtomicUsize::new(0));
+    builder.on_thread_start(move || {
+        // 阻塞任务线程同样会触发，只固定前几个创建的线程（工作线程最先创建）
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        if index >= threads {
+            return;
+        }
+        let core = cores - threads + index;
+        // SAFETY: cpu_set_t 是普通的位图，清零后只设置一个有效的核心编号
+        let pinned = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(core, &mut set);
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+        };
+        if !pinned {
+            debug!("无法把工作线程固定到 CPU {}", core);
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_worker_threads(_builder: &mut tokio::runtime::Builder, _threads: usize) {
+    debug!("当前平台不支持固定工作线程");
+}
+
+/// 提高进程优先级，没有权限时只提示
+#[cfg(unix)]
+pub fn raise_priority() {
+    if !low_latency() {
+        return;
+    }
+    // SAFETY: 只修改本进程的优先级
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, LOW_LATENCY_NICE) };
+    if result == 0 {
+        info!("低延迟模式：已提高进程优先级 (nice {})", LOW_LATENCY_NICE);
+    } else {
+        warn!(
+            "低延迟模式：没有权限提高进程优先级 ({})，可以用 sudo 运行或为程序授予 CAP_SYS_NICE",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_priority() {
+    if low_latency() {
+        debug!("当前平台不支持调整进程优先级");
+    }
+}
+
+pub fn low_latency() -> bool {
+    LOW_LATENCY.load(Ordering::Relaxed)
+}
+
+pub fn set_do_not_disturb(config: DoNotDisturbConfig) {
+    let before = do_not_disturb();
+    *DO_NOT_DISTURB.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+    log_transition(before);
+}
+
+/// 由代理在 UDP 会话建立和结束时更新，games 为各会话识别出的游戏
+pub fn set_game_sessions<'a>(games: impl Iterator<Item = Option<&'a SupportedGame>>) {
+    let before = do_not_disturb();
+    let mut active = false;
+    let mut identified = Vec::new();
+    for game in games {
+        active = true;
+        if let Some(game) = game.filter(|game| !identified.contains(*game)) {
+            identified.push(game.clone());
+        }
+    }
+    GAME_ACTIVE.store(active, Ordering::Relaxed);
+    *ACTIVE_GAMES.write().unwrap_or_else(|e| e.into_inner()) = identified;
+    log_transition(before);
+}
+
+fn log_transition(before: Option<SupportedGame>) {
+    match (before, do_not_disturb()) {
+        (None, Some(game)) => info!("{} 对局进行中，勿扰模式：推迟订阅刷新、节点测速和检查更新", game.display_name()),
+        (Some(_), None) => info!("对局结束，恢复后台任务"),
+        _ => {}
+    }
+}
+
+/// 勿扰模式下正在进行的竞技游戏，没有时为 None
+pub fn do_not_disturb() -> Option<SupportedGame> {
+    let config = DO_NOT_DISTURB.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    let active = ACTIVE_GAMES.read().unwrap_or_else(|e| e.into_inner());
+    config.matching(&active).cloned()
+}
+
+/// 低延迟模式下游戏进行中，或竞技游戏对局中时推迟订阅刷新、检查更新等后台任务
+pub fn defer_background() -> bool {
+    (low_latency() && GAME_ACTIVE.load(Ordering::Relaxed)) || do_not_disturb().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_performance_config() {
+        let config: PerformanceConfig = serde_yaml::from_str("low_latency: true").unwrap();
+        assert!(config.low_latency && config.pin_threads);
+        assert_eq!(config.worker_threads(), LOW_LATENCY_WORKER_THREADS);
+
+        let config: PerformanceConfig = serde_yaml::from_str("low_latency: true\nworker_threads: 1").unwrap();
+        assert_eq!(config.worker_threads(), 1);
+        assert_eq!(PerformanceConfig::default(), serde_yaml::from_str("{}").unwrap());
+    }
+
+    #[test]
+    fn do_not_disturb_only_for_listed_games() {
+        let config = DoNotDisturbConfig::default();
+        let active = [SupportedGame::Minecraft, SupportedGame::Valorant];
+        assert_eq!(config.matching(&active), Some(&SupportedGame::Valorant));
+        assert_eq!(config.matching(&[SupportedGame::Minecraft]), None);
+
+        let config: DoNotDisturbConfig = serde_yaml::from_str("games: [minecraft]").unwrap();
+        assert_eq!(config.matching(&active), Some(&SupportedGame::Minecraft));
+        let config: DoNotDisturbConfig = serde_yaml::from_str("enabled: false").unwrap();
+        assert_eq!(config.matching(&active), None);
+    }
+}
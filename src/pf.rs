@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::redirect::{Targets, DIRECT_RANGES};
+
+/// 系统默认的 pf.conf 会加载 com.apple/* 下的所有锚点（含 rdr-anchor），
+/// 放在这里无需改动用户的主规则集
+pub const ANCHOR: &str = "com.apple/250.ClashFun";
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// pfctl -E 返回的引用令牌，退出时归还，其他程序仍在使用 pf 时不会被关闭
+static TOKEN: Mutex<Option<String>> = Mutex::new(None);
+
+/// 把发往游戏端口和服务器 IP 段的 TCP/UDP 流量重定向到本地代理端口的规则，
+/// 本机发出的流量先 route-to 到 lo0 才能被 rdr 匹配，uid 为代理自身，避免连接节点的流量绕回
+pub fn rules(targets: &Targets, proxy_port: u16, uid: u32) -> String {
+    let mut destinations = Vec::new();
+    if !targets.ports.is_empty() {
+        let ports = targets.ports.iter().map(u16::to_string).collect::<Vec<_>>().join(" ");
+        destinations.push(format!("! <clashfun_direct> port {{ {} }}", ports));
+    }
+    if !targets.ranges.is_empty() {
+        destinations.push(format!("{{ {} }}", targets.ranges.join(", ")));
+    }
+
+    let mut rules = format!("table <clashfun_direct> const {{ {} }}\n", DIRECT_RANGES.join(", "));
+    for to in &destinations {
+        rules += &format!("rdr pass on lo0 inet proto {{ tcp udp }} from any to {} -> 127.0.0.1 port {}\n", to, proxy_port);
+    }
+    for to in &destinations {
+        rules += &format!(
+            "pass out quick on ! lo0 route-to lo0 inet proto {{ tcp udp }} from any to {} user != {} keep state\n",
+            to, uid
+        );
+    }
+    rules
+}
+
+fn pfctl(args: &[&str], input: Option<&str>) -> Result<String> {
+    let mut child = Command::new("pfctl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("无法运行 pfctl")?;
+    if let Some(input) = input {
+        child.stdin.take().context("无法写入 pfctl")?.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    // pfctl 把大部分提示信息写到 stderr
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        bail!("pfctl {} 失败: {}", args.join(" "), text.trim());
+    }
+    Ok(text)
+}
+
+/// 启用 pf 并保留令牌
+fn enable() -> Result<()> {
+    let mut token = TOKEN.lock().unwrap_or_else(|e| e.into_inner());
+    if token.is_none() {
+        let output = pfctl(&["-E"], None)?;
+        *token = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Token :"))
+            .map(|value| value.trim().to_string());
+    }
+    Ok(())
+}
+
+/// 替换锚点中的规则，没有目标时清空
+pub fn apply(targets: &Targets, proxy_port: u16) -> Result<()> {
+    if targets.is_empty() {
+        flush();
+        return Ok(());
+    }
+    enable()?;
+    let uid = unsafe { libc::geteuid() };
+    pfctl(&["-a", ANCHOR, "-f", "-"], Some(&rules(targets, proxy_port, uid)))?;
+    ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// 清空锚点中的规则和状态，启动时也会调用以清理上次异常退出的残留
+pub fn flush() {
+    ACTIVE.store(false, Ordering::Relaxed);
+    if let Err(e) = pfctl(&["-a", ANCHOR, "-F", "all"], None) {
+        log::debug!("清理 pf 锚点失败: {:#}", e);
+    }
+}
+
+/// 清空规则并归还 pf 令牌
+pub fn cleanup() {
+    flush();
+    if let Some(token) = TOKEN.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        let _ = pfctl(&["-X", &token], None);
+    }
+}
+
+/// 从 pfctl -s state 的输出中找到 client → local 这条重定向的原始目标，
+/// 每行形如 `lo0 tcp 127.0.0.1:7890 <- 1.2.3.4:27015 <- 192.168.1.5:50000  ESTABLISHED:ESTABLISHED`
+pub fn parse_state(output: &str, client: SocketAddr, local: SocketAddr) -> Option<SocketAddr> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let start = fields.iter().position(|field| field.parse::<SocketAddr>() == Ok(local))?;
+        match fields.get(start..start + 5)? {
+            [_, "<-", original, "<-", source] if source.parse::<SocketAddr>() == Ok(client) => original.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// 被 pf 重定向到代理端口的连接的原始目标
+pub fn original_destination(client: SocketAddr, local: SocketAddr) -> Option<SocketAddr> {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let output = pfctl(&["-s", "state"], None).ok()?;
+    parse_state(&output, client, local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_rules_and_parses_states() {
+        let targets = Targets {
+            ports: vec![27005, 27015],
+            ranges: vec!["155.133.224.0/19".to_string()],
+        };
+        let rules = rules(&targets, 7890, 501);
+        assert!(rules.contains("rdr pass on lo0 inet proto { tcp udp } from any to ! <clashfun_direct> port { 27005 27015 } -> 127.0.0.1 port 7890"));
+        assert!(rules.contains("from any to { 155.133.224.0/19 } -> 127.0.0.1 port 7890"));
+        assert!(rules.contains("user != 501"));
+
+        let local: SocketAddr = "127.0.0.1:7890".parse().unwrap();
+        let client: SocketAddr = "192.168.1.5:50000".parse().unwrap();
+        let output = "\
+ALL tcp 127.0.0.1:7890 <- 8.8.8.8:443 <- 192.168.1.5:49999       ESTABLISHED:ESTABLISHED
+lo0 tcp 127.0.0.1:7890 <- 155.133.226.10:27015 <- 192.168.1.5:50000       ESTABLISHED:ESTABLISHED
+en0 udp 192.168.1.5:50000 -> 155.133.226.10:27015       MULTIPLE:SINGLE";
+        assert_eq!(parse_state(output, client, local), Some("155.133.226.10:27015".parse().unwrap()));
+        assert_eq!(parse_state(output, "192.168.1.5:1".parse().unwrap(), local), None);
+    }
+}
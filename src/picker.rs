@@ -0,0 +1,171 @@
+use std::io;
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Terminal,
+};
+
+use crate::region;
+use crate::subscription::Node;
+
+/// 模糊匹配：查询的字符按顺序出现即匹配，连续命中和单词开头得分更高
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last: Option<usize> = None;
+
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = pos + text[pos..].iter().position(|&c| c == q)?;
+        score += 1;
+        if last.is_some_and(|last| last + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        last = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+/// 按匹配得分排序的节点序号，得分相同时保持订阅中的顺序
+pub fn filter(nodes: &[Node], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(i32, usize)> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node)| {
+            let text = format!("{} {} {} {}", node.name, region::infer_node(node).unwrap_or(""), node.protocol, node.server);
+            fuzzy_score(query, &text).map(|score| (score, index))
+        })
+        .collect();
+    matches.sort_by_key(|(score, index)| (std::cmp::Reverse(*score), *index));
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+/// 在终端中输入关键字筛选并选择节点，取消时返回 None
+pub fn pick(nodes: &[Node], current: Option<&str>) -> Result<Option<usize>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    crate::crash::set_tui_active(true);
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = run(&mut terminal, nodes, current);
+
+    crate::crash::set_tui_active(false);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+fn run<B: ratatui::backend::Backend>(terminal: &mut Terminal<B>, nodes: &[Node], current: Option<&str>) -> Result<Option<usize>> {
+    let mut query = String::new();
+    let mut matches = filter(nodes, &query);
+    let mut state = ListState::default();
+    state.select(Some(0));
+
+    loop {
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(3), Constraint::Min(0)])
+                .split(f.size());
+
+            let input = Paragraph::new(format!("> {}", query))
+                .style(Style::default().fg(Color::Yellow))
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    "选择节点 {}/{} (输入筛选, ↑↓选择, Enter确认, Esc取消)",
+                    matches.len(),
+                    nodes.len()
+                )));
+            f.render_widget(input, chunks[0]);
+
+            let items: Vec<ListItem> = matches
+                .iter()
+                .map(|&index| {
+                    let node = &nodes[index];
+                    let marker = if current == Some(node.name.as_str()) { "▶" } else { " " };
+                    let unsupported = if node.is_supported() { "" } else { " 不支持" };
+                    ListItem::new(Line::from(format!(
+                        "{} {:<4} {}  [{} · {}{}]",
+                        marker,
+                        index + 1,
+                        node.name,
+                        region::infer_node(node).unwrap_or(region::UNKNOWN_REGION),
+                        node.protocol,
+                        unsupported
+                    )))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL))
+                .highlight_style(Style::default().bg(Color::Blue).add_modifier(Modifier::BOLD));
+            f.render_stateful_widget(list, chunks[1], &mut state);
+        })?;
+
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let selected = state.selected().unwrap_or(0);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if ctrl => return Ok(None),
+            KeyCode::Enter => return Ok(matches.get(selected).copied()),
+            KeyCode::Up => state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Char('p') if ctrl => state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Tab => state.select(Some((selected + 1).min(matches.len().saturating_sub(1)))),
+            KeyCode::Char('n') if ctrl => state.select(Some((selected + 1).min(matches.len().saturating_sub(1)))),
+            KeyCode::Backspace => {
+                query.pop();
+                matches = filter(nodes, &query);
+                state.select(Some(0));
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                matches = filter(nodes, &query);
+                state.select(Some(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str) -> Node {
+        Node::test(name, "example.com", 443, "ss")
+    }
+
+    #[test]
+    fn ranks_fuzzy_matches() {
+        assert!(fuzzy_score("hk", "🇭🇰 HK 01").is_some());
+        assert!(fuzzy_score("hk01", "HK-IPLC-01").is_some());
+        assert!(fuzzy_score("kh", "HK 01").is_none());
+        assert!(fuzzy_score("hk", "HK 01") > fuzzy_score("hk", "Hong Kong"));
+
+        let nodes = vec![node("剩余流量 100G"), node("Hong Kong 02"), node("HK 01"), node("日本 01")];
+        assert_eq!(filter(&nodes, "hk"), [2, 1]);
+        assert_eq!(filter(&nodes, "日本"), [3]);
+        // 地区也参与匹配
+        assert_eq!(filter(&nodes, "香港"), [1, 2]);
+        assert_eq!(filter(&nodes, "").len(), 4);
+    }
+}
@@ -0,0 +1,404 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::game_detect::PortProtocol;
+
+const NAT_PMP_PORT: u16 = 5351;
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+/// 支持端口映射的 WAN 服务类型，按优先级排列
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// 路由器上的端口映射服务
+pub enum PortMapper {
+    NatPmp {
+        gateway: Ipv4Addr,
+    },
+    Upnp {
+        control_url: String,
+        service_type: String,
+        local_ip: Ipv4Addr,
+    },
+}
+
+impl PortMapper {
+    /// 依次尝试 NAT-PMP 和 UPnP IGD
+    pub async fn discover() -> Result<Self> {
+        if let Some(gateway) = default_gateway() {
+            match nat_pmp_external_ip(gateway).await {
+                Ok(_) => return Ok(Self::NatPmp { gateway }),
+                Err(e) => debug!("网关 {} 不支持 NAT-PMP: {}", gateway, e),
+            }
+        }
+
+        let location = ssdp_discover().await.context("没有找到支持 NAT-PMP 或 UPnP 的路由器")?;
+        debug!("UPnP 设备描述: {}", location);
+        let description = reqwest::Client::new()
+            .get(&location)
+            .timeout(SSDP_TIMEOUT)
+            .send()
+            .await
+            .context("获取 UPnP 设备描述失败")?
+            .text()
+            .await?;
+        let (service_type, control_url) =
+            find_wan_service(&description, &location).context("路由器没有提供端口映射服务")?;
+
+        let host: SocketAddr = reqwest::Url::parse(&control_url)?
+            .socket_addrs(|| Some(80))?
+            .into_iter()
+            .next()
+            .context("无法解析 UPnP 控制地址")?;
+        let local_ip = match local_ip_towards(host).await? {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => bail!("UPnP 端口映射只支持 IPv4"),
+        };
+
+        Ok(Self::Upnp {
+            control_url,
+            service_type,
+            local_ip,
+        })
+    }
+
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::NatPmp { .. } => "NAT-PMP",
+            Self::Upnp { .. } => "UPnP IGD",
+        }
+    }
+
+    pub async fn external_ip(&self) -> Result<Ipv4Addr> {
+        match self {
+            Self::NatPmp { gateway } => nat_pmp_external_ip(*gateway).await,
+            Self::Upnp { .. } => {
+                let response = self.soap("GetExternalIPAddress", &[]).await?;
+                xml_tag(&response, "NewExternalIPAddress")
+                    .and_then(|ip| ip.trim().parse().ok())
+                    .context("路由器没有返回外网地址")
+            }
+        }
+    }
+
+    /// 添加映射，返回路由器分配的外部端口
+    pub async fn add(&self, protocol: PortProtocol, port: u16, lease: Duration) -> Result<u16> {
+        match self {
+            Self::NatPmp { gateway } => nat_pmp_map(*gateway, protocol, port, port, lease.as_secs() as u32).await,
+            Self::Upnp { local_ip, .. } => {
+                let port = port.to_string();
+                let local_ip = local_ip.to_string();
+                let lease = lease.as_secs().to_string();
+                self.soap(
+                    "AddPortMapping",
+                    &[
+                        ("NewRemoteHost", ""),
+                        ("NewExternalPort", &port),
+                        ("NewProtocol", protocol.as_str()),
+                        ("NewInternalPort", &port),
+                        ("NewInternalClient", &local_ip),
+                        ("NewEnabled", "1"),
+                        ("NewPortMappingDescription", "ClashFun"),
+                        ("NewLeaseDuration", &lease),
+                    ],
+                )
+                .await?;
+                port.parse().map_err(Into::into)
+            }
+        }
+    }
+
+    pub async fn remove(&self, protocol: PortProtocol, port: u16) -> Result<()> {
+        match self {
+            Self::NatPmp { gateway } => nat_pmp_map(*gateway, protocol, port, 0, 0).await.map(|_| ()),
+            Self::Upnp { .. } => {
+                let port = port.to_string();
+                self.soap(
+                    "DeletePortMapping",
+                    &[
+                        ("NewRemoteHost", ""),
+                        ("NewExternalPort", &port),
+                        ("NewProtocol", protocol.as_str()),
+                    ],
+                )
+                .await
+                .map(|_| ())
+            }
+        }
+    }
+
+    async fn soap(&self, action: &str, args: &[(&str, &str)]) -> Result<String> {
+        let Self::Upnp { control_url, service_type, .. } = self else {
+            bail!("不是 UPnP 设备");
+        };
+
+        let arguments: String = args
+            .iter()
+            .map(|(name, value)| format!("<{0}>{1}</{0}>", name, value))
+            .collect();
+        let body = format!(
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:{action} xmlns:u="{service_type}">{arguments}</u:{action}></s:Body></s:Envelope>"#
+        );
+
+        let response = reqwest::Client::new()
+            .post(control_url)
+            .timeout(SSDP_TIMEOUT)
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header("SOAPAction", format!(r#""{}#{}""#, service_type, action))
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("UPnP 请求 {} 失败", action))?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            let reason = xml_tag(&text, "errorDescription").unwrap_or_else(|| status.to_string());
+            bail!("路由器拒绝了 {}: {}", action, reason);
+        }
+        Ok(text)
+    }
+}
+
+/// 解析 `25565`、`10999/udp`、`27015/tcp`，未指定协议时同时映射 TCP 和 UDP
+pub fn parse_port_spec(spec: &str) -> Result<Vec<(PortProtocol, u16)>> {
+    let (port, protocol) = spec.split_once('/').map_or((spec, None), |(p, proto)| (p, Some(proto)));
+    let port: u16 = port
+        .trim()
+        .parse()
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or_else(|| anyhow!("无效的端口: {}", spec))?;
+    match protocol.map(|p| p.trim().to_ascii_lowercase()).as_deref() {
+        None => Ok(vec![(PortProtocol::Tcp, port), (PortProtocol::Udp, port)]),
+        Some("tcp") => Ok(vec![(PortProtocol::Tcp, port)]),
+        Some("udp") => Ok(vec![(PortProtocol::Udp, port)]),
+        Some(other) => bail!("未知的协议: {}", other),
+    }
+}
+
+/// 通过外网地址连接本机端口，验证映射是否生效（需要路由器支持回环）
+pub async fn check_reachable(external_ip: Ipv4Addr, port: u16) -> bool {
+    // 游戏服务器未运行时临时监听，已运行时直接连接它
+    let _listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await.ok();
+    let connect = tokio::net::TcpStream::connect((external_ip, port));
+    matches!(tokio::time::timeout(Duration::from_secs(2), connect).await, Ok(Ok(_)))
+}
+
+/// 连接目标时本机使用的地址
+async fn local_ip_towards(target: SocketAddr) -> Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(target).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// 地址是否为运营商级 NAT (100.64.0.0/10) 或私有地址，此时外部依然无法直接访问
+pub fn is_behind_another_nat(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    ip.is_private() || (octets[0] == 100 && (64..128).contains(&octets[1]))
+}
+
+async fn nat_pmp_request(gateway: Ipv4Addr, request: &[u8], response_len: usize) -> Result<Vec<u8>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect((gateway, NAT_PMP_PORT)).await?;
+
+    // RFC 6886 建议从 250ms 开始指数重试，这里只重试两次避免等待过久
+    let mut buf = [0u8; 16];
+    for timeout in [250, 500, 1000] {
+        socket.send(request).await?;
+        if let Ok(result) = tokio::time::timeout(Duration::from_millis(timeout), socket.recv(&mut buf)).await {
+            let size = result?;
+            if size < response_len || buf[0] != 0 || buf[1] != request[1] | 0x80 {
+                bail!("无效的 NAT-PMP 响应");
+            }
+            let code = u16::from_be_bytes([buf[2], buf[3]]);
+            if code != 0 {
+                bail!("NAT-PMP 返回错误码 {}", code);
+            }
+            return Ok(buf[..size].to_vec());
+        }
+    }
+    Err(anyhow!("网关没有响应 NAT-PMP 请求"))
+}
+
+async fn nat_pmp_external_ip(gateway: Ipv4Addr) -> Result<Ipv4Addr> {
+    let response = nat_pmp_request(gateway, &[0, 0], 12).await?;
+    Ok(Ipv4Addr::new(response[8], response[9], response[10], response[11]))
+}
+
+async fn nat_pmp_map(gateway: Ipv4Addr, protocol: PortProtocol, internal: u16, external: u16, lifetime: u32) -> Result<u16> {
+    let response = nat_pmp_request(gateway, &nat_pmp_map_request(protocol, internal, external, lifetime), 16).await?;
+    Ok(u16::from_be_bytes([response[10], response[11]]))
+}
+
+fn nat_pmp_map_request(protocol: PortProtocol, internal: u16, external: u16, lifetime: u32) -> Vec<u8> {
+    let opcode = match protocol {
+        PortProtocol::Udp => 1,
+        PortProtocol::Tcp => 2,
+    };
+    let mut request = vec![0, opcode, 0, 0];
+    request.extend_from_slice(&internal.to_be_bytes());
+    request.extend_from_slice(&external.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+/// 通过 SSDP 查找路由器，返回设备描述地址
+async fn ssdp_discover() -> Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 2\r\n\
+                   ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+    socket.send_to(request.as_bytes(), SSDP_ADDR).await?;
+
+    let mut buf = [0u8; 2048];
+    tokio::time::timeout(SSDP_TIMEOUT, async {
+        loop {
+            let size = socket.recv(&mut buf).await?;
+            if let Some(location) = ssdp_location(&String::from_utf8_lossy(&buf[..size])) {
+                return Ok(location);
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("等待 UPnP 设备响应超时"))?
+}
+
+fn ssdp_location(response: &str) -> Option<String> {
+    response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("location").then(|| value.trim().to_string())
+    })
+}
+
+/// 在设备描述中找到 WAN 连接服务，返回 (服务类型, 完整的控制地址)
+fn find_wan_service(description: &str, location: &str) -> Option<(String, String)> {
+    let services: Vec<&str> = description.split("<service>").skip(1).collect();
+    let (service_type, service) = WAN_SERVICES.iter().find_map(|wanted| {
+        services
+            .iter()
+            .find(|service| xml_tag(service, "serviceType").as_deref() == Some(*wanted))
+            .map(|service| (wanted.to_string(), *service))
+    })?;
+    let control = xml_tag(service, "controlURL")?;
+
+    let base = xml_tag(description, "URLBase").unwrap_or_else(|| location.to_string());
+    let url = reqwest::Url::parse(&base).ok()?.join(control.trim()).ok()?;
+    Some((service_type, url.to_string()))
+}
+
+/// 取出第一个同名标签的文本，忽略命名空间前缀
+fn xml_tag(xml: &str, tag: &str) -> Option<String> {
+    let open = [format!("<{}>", tag), format!(":{}>", tag)]
+        .into_iter()
+        .find_map(|open| xml.find(&open).map(|i| i + open.len()))?;
+    let rest = &xml[open..];
+    let end = rest.find("</")?;
+    Some(rest[..end].to_string())
+}
+
+/// 默认网关地址
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Option<Ipv4Addr> {
+    parse_proc_route(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Option<Ipv4Addr> {
+    let output = std::process::Command::new("netstat").arg("-rn").output().ok()?;
+    parse_netstat_routes(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_route(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => {
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                // /proc/net/route 中的地址为小端序
+                Some(Ipv4Addr::from(gateway.swap_bytes())).filter(|ip| !ip.is_unspecified())
+            }
+            _ => None,
+        }
+    })
+}
+
+/// macOS 为 `default 192.168.1.1 ...`，Windows 为 `0.0.0.0 0.0.0.0 192.168.1.1 ...`
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn parse_netstat_routes(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let gateway = match fields.as_slice() {
+            ["default", gateway, ..] => gateway,
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway,
+            _ => return None,
+        };
+        gateway.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_gateway() {
+        let proc_route = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t0\t00000000
+";
+        assert_eq!(parse_proc_route(proc_route), Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+        let mac = "Destination        Gateway            Flags\ndefault            192.168.0.1        UGScg\n";
+        assert_eq!(parse_netstat_routes(mac), Some(Ipv4Addr::new(192, 168, 0, 1)));
+        let windows = "  0.0.0.0          0.0.0.0      10.0.0.138      10.0.0.12     25\n";
+        assert_eq!(parse_netstat_routes(windows), Some(Ipv4Addr::new(10, 0, 0, 138)));
+    }
+
+    #[test]
+    fn finds_wan_service_in_description() {
+        let description = r#"<root><device><serviceList>
+<service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/l3f</controlURL></service>
+<service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><controlURL>/ctl/IPConn</controlURL></service>
+</serviceList></device></root>"#;
+        let (service, url) = find_wan_service(description, "http://192.168.1.1:5000/rootDesc.xml").unwrap();
+        assert_eq!(service, "urn:schemas-upnp-org:service:WANIPConnection:1");
+        assert_eq!(url, "http://192.168.1.1:5000/ctl/IPConn");
+
+        let response = "<s:Body><u:GetExternalIPAddressResponse><NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>";
+        assert_eq!(xml_tag(response, "NewExternalIPAddress").as_deref(), Some("203.0.113.7"));
+        assert_eq!(
+            ssdp_location("HTTP/1.1 200 OK\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n").as_deref(),
+            Some("http://192.168.1.1:5000/rootDesc.xml")
+        );
+    }
+
+    #[test]
+    fn parses_port_specs() {
+        use PortProtocol::{Tcp, Udp};
+        assert_eq!(parse_port_spec("25565").unwrap(), vec![(Tcp, 25565), (Udp, 25565)]);
+        assert_eq!(parse_port_spec("10999/UDP").unwrap(), vec![(Udp, 10999)]);
+        for invalid in ["0", "70000", "80/sctp", "abc"] {
+            assert!(parse_port_spec(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn encodes_nat_pmp_mapping() {
+        assert_eq!(
+            nat_pmp_map_request(PortProtocol::Udp, 10999, 10999, 7200),
+            vec![0, 1, 0, 0, 0x2a, 0xf7, 0x2a, 0xf7, 0, 0, 0x1c, 0x20]
+        );
+        assert!(is_behind_another_nat(Ipv4Addr::new(100, 72, 1, 1)));
+        assert!(!is_behind_another_nat(Ipv4Addr::new(203, 0, 113, 7)));
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use clashfun::error::ProxyError;
+use serde::{Deserialize, Serialize};
+
+use crate::health;
+use crate::subscription::Node;
+use crate::timeouts;
+
+/// 首选节点拒绝认证时最多检查的备用节点数量
+const MAX_REPLACEMENT_CANDIDATES: usize = 5;
+
+/// 启动时节点检查失败后的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightMode {
+    /// 节点拒绝认证时改用通过检查的备用节点
+    #[default]
+    Failover,
+    /// 节点拒绝认证时直接退出
+    Fail,
+    /// 不检查，例如节点屏蔽了探测
+    Off,
+}
+
+/// 启动前检查的结果
+#[derive(Debug)]
+pub enum Outcome {
+    /// 节点通过了探测；只有 socks5、http 节点同时验证了认证
+    Passed(health::Probe),
+    /// 节点暂时连不上，启动后由健康检查负责切换
+    Unreachable(anyhow::Error),
+    /// 节点拒绝了认证，本次改用备用节点
+    Replaced { node: Node, rejected: anyhow::Error },
+    Skipped,
+}
+
+/// 节点是否因为用户名或密码错误而拒绝连接，这种情况重试不会恢复
+pub fn is_auth_rejected(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| matches!(cause.downcast_ref::<ProxyError>(), Some(ProxyError::AuthRejected { .. })))
+}
+
+/// 监听端口前探测节点，能验证认证的协议 (socks5、http) 同时确认用户名和密码，其他协议只确认节点在线；
+/// 节点拒绝认证时按 mode 退出或从 backups（按延迟排序）中找一个通过检查的节点
+pub async fn check(node: &Node, backups: &[Node], mode: PreflightMode) -> Result<Outcome> {
+    if mode == PreflightMode::Off {
+        return Ok(Outcome::Skipped);
+    }
+
+    let rejected = match health::probe(node, timeouts::current().health_probe()).await {
+        Ok(probe) => return Ok(Outcome::Passed(probe)),
+        Err(e) if is_auth_rejected(&e) => e,
+        Err(e) => return Ok(Outcome::Unreachable(e)),
+    };
+    if mode == PreflightMode::Fail {
+        return Err(rejected);
+    }
+
+    for backup in backups.iter().take(MAX_REPLACEMENT_CANDIDATES) {
+        if health::probe(backup, timeouts::current().backup_probe()).await.is_ok() {
+            return Ok(Outcome::Replaced { node: backup.clone(), rejected });
+        }
+    }
+    Err(rejected).context("没有通过检查的备用节点")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// 本地的 SOCKS5 服务端，只回复方法协商和认证
+    async fn socks5_server(accept_password: bool) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut greeting = [0u8; 4];
+                if stream.read_exact(&mut greeting).await.is_err() {
+                    continue;
+                }
+                let _ = stream.write_all(&[5, 2]).await;
+                let mut auth = [0u8; 64];
+                let _ = stream.read(&mut auth).await;
+                let _ = stream.write_all(&[1, if accept_password { 0 } else { 1 }]).await;
+            }
+        });
+        port
+    }
+
+    fn node(name: &str, port: u16) -> Node {
+        Node {
+            password: Some("secret".to_string()),
+            username: Some("user".to_string()),
+            ..Node::test(name, "127.0.0.1", port, "socks5")
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_node_that_rejects_credentials() {
+        let rejecting = node("公司代理", socks5_server(false).await);
+        let working = node("备用代理", socks5_server(true).await);
+
+        assert!(matches!(
+            check(&working, &[], PreflightMode::Failover).await.unwrap(),
+            Outcome::Passed(probe) if probe.authenticated
+        ));
+        assert!(matches!(check(&rejecting, &[], PreflightMode::Off).await.unwrap(), Outcome::Skipped));
+
+        match check(&rejecting, &[rejecting.clone(), working.clone()], PreflightMode::Failover).await.unwrap() {
+            Outcome::Replaced { node, rejected } => {
+                assert_eq!(node.name, "备用代理");
+                assert!(is_auth_rejected(&rejected));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let error = check(&rejecting, &[working], PreflightMode::Fail).await.unwrap_err();
+        assert!(is_auth_rejected(&error));
+        assert!(clashfun::error::hint(&error).is_some());
+        let error = check(&rejecting, &[], PreflightMode::Failover).await.unwrap_err();
+        assert!(error.to_string().contains("备用节点"), "{}", error);
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 路由器模式下 tokio 的工作线程数，每个线程都会占用一份栈和调度队列
+pub const ROUTER_WORKER_THREADS: usize = 2;
+/// 路由器模式下阻塞任务（pfctl/nft、文件读写）最多使用的线程数
+pub const ROUTER_BLOCKING_THREADS: usize = 4;
+/// 路由器模式下 UDP 缓冲池保留的空闲缓冲区数量
+pub const ROUTER_IDLE_UDP_BUFFERS: usize = 16;
+/// 路由器模式下每条 TCP 转发使用的管道大小
+pub const ROUTER_PIPE_SIZE: usize = 16 * 1024;
+
+static ROUTER: AtomicBool = AtomicBool::new(cfg!(feature = "router"));
+
+/// 运行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// 桌面：游戏进程检测、桌面通知、后台检查更新
+    Desktop,
+    /// 路由器/嵌入式：为整个局域网加速，减少内存占用和后台任务
+    Router,
+}
+
+impl Default for Profile {
+    /// 使用 router 特性构建时默认为路由器模式
+    fn default() -> Self {
+        if cfg!(feature = "router") {
+            Self::Router
+        } else {
+            Self::Desktop
+        }
+    }
+}
+
+impl Profile {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Desktop => "桌面",
+            Self::Router => "路由器（精简内存与后台任务）",
+        }
+    }
+}
+
+/// 设置运行模式，需要在创建运行时和代理之前调用
+pub fn set(profile: Profile) {
+    ROUTER.store(profile == Profile::Router, Ordering::Relaxed);
+}
+
+/// 是否以路由器模式运行
+pub fn router() -> bool {
+    ROUTER.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_profile() {
+        assert_eq!(serde_yaml::from_str::<Profile>("router").unwrap(), Profile::Router);
+        assert_eq!(serde_yaml::from_str::<Profile>("desktop").unwrap(), Profile::Desktop);
+        assert!(serde_yaml::from_str::<Profile>("server").is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
+
+/// cf perf 中显示的锁数量
+pub const LOCKS_SHOWN: usize = 5;
+
+/// 转发路径上加锁的位置，等锁的次数和时间计入争用统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockSite {
+    /// 每个客户端 UDP 包都要查找的会话表
+    UdpSessions,
+    /// 每个新连接和 UDP 包读取当前节点
+    CurrentNode,
+    /// 粘性路由缓存
+    Affinity,
+    /// 按设备统计流量的设备表
+    Devices,
+    /// UDP 收包缓冲池
+    BufferPool,
+}
+
+impl LockSite {
+    const ALL: [LockSite; 5] = [
+        LockSite::UdpSessions,
+        LockSite::CurrentNode,
+        LockSite::Affinity,
+        LockSite::Devices,
+        LockSite::BufferPool,
+    ];
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            LockSite::UdpSessions => "UDP 会话表",
+            LockSite::CurrentNode => "当前节点",
+            LockSite::Affinity => "粘性路由缓存",
+            LockSite::Devices => "设备表",
+            LockSite::BufferPool => "UDP 缓冲池",
+        }
+    }
+
+    fn counter(self) -> &'static LockCounter {
+        &LOCKS[self as usize]
+    }
+}
+
+/// 一处锁的加锁次数和等待时间
+struct LockCounter {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_ns: AtomicU64,
+    max_wait_ns: AtomicU64,
+}
+
+impl LockCounter {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            wait_ns: AtomicU64::new(0),
+            max_wait_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn uncontended(&self) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn waited(&self, wait: Duration) {
+        let ns = wait.as_nanos().min(u64::MAX as u128) as u64;
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.wait_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_wait_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    fn report(&self, site: LockSite) -> LockReport {
+        LockReport {
+            site: site.display_name().to_string(),
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            wait_us: self.wait_ns.load(Ordering::Relaxed) / 1000,
+            max_wait_us: self.max_wait_ns.load(Ordering::Relaxed) / 1000,
+        }
+    }
+}
+
+static LOCKS: [LockCounter; 5] = [
+    LockCounter::new(),
+    LockCounter::new(),
+    LockCounter::new(),
+    LockCounter::new(),
+    LockCounter::new(),
+];
+
+/// 先尝试直接加锁，锁被占用时记录等待的时间
+pub async fn lock<T>(site: LockSite, mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    lock_counted(site.counter(), mutex).await
+}
+
+async fn lock_counted<'a, T>(counter: &LockCounter, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+    if let Ok(guard) = mutex.try_lock() {
+        counter.uncontended();
+        return guard;
+    }
+    let start = Instant::now();
+    let guard = mutex.lock().await;
+    counter.waited(start.elapsed());
+    guard
+}
+
+/// 读锁，写锁被持有时记录等待的时间
+pub async fn read<T>(site: LockSite, lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    let counter = site.counter();
+    if let Ok(guard) = lock.try_read() {
+        counter.uncontended();
+        return guard;
+    }
+    let start = Instant::now();
+    let guard = lock.read().await;
+    counter.waited(start.elapsed());
+    guard
+}
+
+/// 同步锁，与其他代码一样忽略锁中毒
+pub fn lock_sync<T>(site: LockSite, mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    let counter = site.counter();
+    match mutex.try_lock() {
+        Ok(guard) => {
+            counter.uncontended();
+            guard
+        }
+        Err(std::sync::TryLockError::Poisoned(e)) => {
+            counter.uncontended();
+            e.into_inner()
+        }
+        Err(std::sync::TryLockError::WouldBlock) => {
+            let start = Instant::now();
+            let guard = mutex.lock().unwrap_or_else(|e| e.into_inner());
+            counter.waited(start.elapsed());
+            guard
+        }
+    }
+}
+
+/// 一处锁的争用情况，时间为累计值
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockReport {
+    pub site: String,
+    pub acquisitions: u64,
+    /// 需要等待的加锁次数
+    pub contended: u64,
+    pub wait_us: u64,
+    pub max_wait_us: u64,
+}
+
+impl LockReport {
+    pub fn contended_percent(&self) -> f64 {
+        self.contended as f64 * 100.0 / self.acquisitions.max(1) as f64
+    }
+}
+
+/// 按累计等待时间排序的锁
+pub fn lock_contention() -> Vec<LockReport> {
+    let mut locks: Vec<LockReport> = LockSite::ALL.iter().map(|site| site.counter().report(*site)).collect();
+    locks.sort_by(|a, b| b.wait_us.cmp(&a.wait_us).then_with(|| b.contended.cmp(&a.contended)));
+    locks
+}
+
+/// 启动以来的内存分配，只有启用 alloc-stats 功能的版本才统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocStats {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    /// 尚未释放的字节数
+    pub live_bytes: u64,
+}
+
+#[cfg(feature = "alloc-stats")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::AllocStats;
+
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+    static FREED: AtomicU64 = AtomicU64::new(0);
+
+    /// 包装系统分配器，记录分配次数和字节数
+    struct CountingAllocator;
+
+    // SAFETY: 所有操作直接转给系统分配器，只额外更新计数
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            FREED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(new_size as u64, Ordering::Relaxed);
+            FREED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    pub fn stats() -> AllocStats {
+        let allocated_bytes = ALLOCATED.load(Ordering::Relaxed);
+        AllocStats {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes,
+            live_bytes: allocated_bytes.saturating_sub(FREED.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(feature = "alloc-stats")]
+fn alloc_stats() -> Option<AllocStats> {
+    Some(counting::stats())
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+fn alloc_stats() -> Option<AllocStats> {
+    None
+}
+
+/// 守护进程的资源占用，累计值由 cf perf 采样两次后换算成速率
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PerfReport {
+    /// 常驻内存，不支持的平台为 None
+    pub rss_bytes: Option<u64>,
+    /// 启动以来占用的 CPU 时间（用户态 + 内核态）
+    pub cpu_time_ms: Option<u64>,
+    pub worker_threads: usize,
+    /// 存活的异步任务，每个连接和会话至少占一个
+    pub alive_tasks: usize,
+    /// 等待工作线程执行的任务，持续不为 0 说明 CPU 跟不上
+    pub queued_tasks: usize,
+    /// 各个表中的条目数
+    pub tables: Vec<(String, usize)>,
+    pub allocations: Option<AllocStats>,
+    pub locks: Vec<LockReport>,
+}
+
+impl PerfReport {
+    /// 采集当前进程的数据，tables 由调用方提供
+    pub fn collect(tables: Vec<(String, usize)>) -> Self {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        Self {
+            rss_bytes: rss_bytes(),
+            cpu_time_ms: cpu_time_ms(),
+            worker_threads: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            queued_tasks: metrics.global_queue_depth(),
+            tables,
+            allocations: alloc_stats(),
+            locks: lock_contention(),
+        }
+    }
+}
+
+/// 两次采样之间的 CPU 占用和分配速率
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PerfRates {
+    /// 占用一个核心为 100%
+    pub cpu_percent: Option<f64>,
+    pub allocations_per_sec: Option<f64>,
+    pub allocated_bytes_per_sec: Option<f64>,
+}
+
+pub fn rates(before: &PerfReport, after: &PerfReport, elapsed: Duration) -> PerfRates {
+    let secs = elapsed.as_secs_f64().max(0.001);
+    let cpu_percent = before
+        .cpu_time_ms
+        .zip(after.cpu_time_ms)
+        .map(|(before, after)| after.saturating_sub(before) as f64 / 10.0 / secs);
+    let allocations = before.allocations.zip(after.allocations);
+    PerfRates {
+        cpu_percent,
+        allocations_per_sec: allocations
+            .map(|(before, after)| after.allocations.saturating_sub(before.allocations) as f64 / secs),
+        allocated_bytes_per_sec: allocations
+            .map(|(before, after)| after.allocated_bytes.saturating_sub(before.allocated_bytes) as f64 / secs),
+    }
+}
+
+fn rss_bytes() -> Option<u64> {
+    use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+
+    let pid = Pid::from_u32(std::process::id());
+    let mut system = System::new();
+    system.refresh_process(pid);
+    system.process(pid).map(|process| process.memory())
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn cpu_time_ms() -> Option<u64> {
+    // SAFETY: 只读取本进程的资源使用情况
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        if libc::getrusage(libc::RUSAGE_SELF, &mut usage) != 0 {
+            return None;
+        }
+        usage
+    };
+    let ms = |time: libc::timeval| time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000;
+    Some(ms(usage.ru_utime) + ms(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time_ms() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn records_lock_contention_and_rates() {
+        let counter = LockCounter::new();
+        let mutex = Arc::new(Mutex::new(0));
+        drop(lock_counted(&counter, &mutex).await);
+
+        let held = mutex.lock().await;
+        let waiter = {
+            let mutex = Arc::clone(&mutex);
+            tokio::spawn(async move {
+                let counter = LockCounter::new();
+                *lock_counted(&counter, &mutex).await += 1;
+                counter.report(LockSite::UdpSessions)
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+        let report = waiter.await.unwrap();
+        assert_eq!((report.acquisitions, report.contended), (1, 1));
+        assert!(report.max_wait_us >= 10_000 && report.wait_us == report.max_wait_us, "{:?}", report);
+        assert_eq!(counter.report(LockSite::UdpSessions).contended, 0);
+
+        let before = PerfReport {
+            cpu_time_ms: Some(1_000),
+            allocations: Some(AllocStats { allocations: 100, allocated_bytes: 4096, live_bytes: 0 }),
+            ..PerfReport::default()
+        };
+        let after = PerfReport {
+            cpu_time_ms: Some(1_500),
+            allocations: Some(AllocStats { allocations: 300, allocated_bytes: 8192, live_bytes: 0 }),
+            ..PerfReport::default()
+        };
+        let rates = rates(&before, &after, Duration::from_secs(2));
+        assert_eq!(rates.cpu_percent, Some(25.0));
+        assert_eq!(rates.allocations_per_sec, Some(100.0));
+        assert_eq!(rates.allocated_bytes_per_sec, Some(2048.0));
+        assert_eq!(super::rates(&PerfReport::default(), &after, Duration::from_secs(1)).cpu_percent, None);
+
+        let collected = PerfReport::collect(vec![("UDP 会话".to_string(), 0)]);
+        assert!(collected.worker_threads >= 1);
+        assert_eq!(collected.locks.len(), LockSite::ALL.len());
+        #[cfg(unix)]
+        assert!(collected.cpu_time_ms.is_some());
+    }
+}
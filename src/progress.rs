@@ -0,0 +1,99 @@
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+
+use crate::subscription::Node;
+
+/// 进度条的格数
+const BAR_WIDTH: usize = 24;
+
+static CURRENT: Mutex<Option<Progress>> = Mutex::new(None);
+
+/// 命令行中测试节点延迟的进度，测完一个节点输出一行结果
+struct Progress {
+    label: String,
+    total: usize,
+    done: usize,
+    /// 输出到终端时在最后一行原地刷新进度条，否则只逐行输出结果
+    tty: bool,
+}
+
+impl Progress {
+    fn redraw(&self, stdout: &mut impl Write) {
+        if self.tty {
+            let _ = write!(stdout, "\r\x1b[2K{} {}", render_bar(self.done, self.total), self.label);
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// 进度显示期间持有，结束时清除进度条
+pub struct ProgressGuard {
+    active: bool,
+}
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        let progress = CURRENT.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if progress.is_some_and(|progress| progress.tty) {
+            print!("\r\x1b[2K");
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+/// 开始显示进度，total 为要测试的次数；quiet 时不输出任何内容，供脚本使用
+pub fn begin(label: &str, total: usize, quiet: bool) -> ProgressGuard {
+    if quiet || total == 0 {
+        return ProgressGuard { active: false };
+    }
+    let progress = Progress {
+        label: label.to_string(),
+        total,
+        done: 0,
+        tty: std::io::stdout().is_terminal(),
+    };
+    progress.redraw(&mut std::io::stdout());
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = Some(progress);
+    ProgressGuard { active: true }
+}
+
+/// 由延迟测试在每个节点测完后调用，没有进行中的进度时什么也不做
+pub fn node_tested(node: &Node) {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(progress) = current.as_mut() else {
+        return;
+    };
+    progress.done = (progress.done + 1).min(progress.total);
+    let result = match node.latency {
+        Some(latency) if latency != u32::MAX => format!("✅ {}ms", latency),
+        _ => "❌ 超时".to_string(),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let width = progress.total.to_string().len();
+    if progress.tty {
+        let _ = write!(stdout, "\r\x1b[2K");
+    }
+    let _ = writeln!(stdout, "  [{:>width$}/{}] {}  {}", progress.done, progress.total, result, node.name, width = width);
+    progress.redraw(&mut stdout);
+}
+
+fn render_bar(done: usize, total: usize) -> String {
+    let filled = (done * BAR_WIDTH).checked_div(total).unwrap_or(0);
+    format!("[{}{}] {}/{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), done, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_bar() {
+        assert_eq!(render_bar(0, 4), format!("[{}] 0/4", "-".repeat(BAR_WIDTH)));
+        assert_eq!(render_bar(2, 4), format!("[{}{}] 2/4", "#".repeat(12), "-".repeat(12)));
+        assert_eq!(render_bar(4, 4), format!("[{}] 4/4", "#".repeat(BAR_WIDTH)));
+    }
+}
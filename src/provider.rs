@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+const PROVIDER_FILE: &str = "subscription_health.json";
+/// 每个订阅最多保留的节点数量变化记录
+const MAX_NODE_COUNT_CHANGES: usize = 20;
+/// 获取成功率低于该值时认为订阅不稳定（百分比）
+const FETCH_SUCCESS_WARN: u32 = 80;
+/// 平均可用节点比例低于该值时认为订阅节点质量差（百分比）
+const USABLE_RATIO_WARN: u32 = 30;
+
+/// 某次节点数量变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeCountChange {
+    /// Unix 秒
+    pub timestamp: u64,
+    pub count: usize,
+}
+
+/// 最近一次获取失败
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchFailure {
+    /// Unix 秒
+    pub timestamp: u64,
+    pub error: String,
+}
+
+/// 一个订阅的获取记录
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderHealth {
+    pub fetches: u64,
+    pub failures: u64,
+    /// 上次获取成功的时间（Unix 秒）
+    pub last_success: Option<u64>,
+    pub last_error: Option<FetchFailure>,
+    /// 节点数量发生变化时的记录，最早的在前
+    pub node_counts: Vec<NodeCountChange>,
+    /// 完整测速的次数和每次可用节点比例（百分比）之和
+    pub usable_samples: u64,
+    pub usable_percent_sum: u64,
+    pub last_usable_percent: Option<u32>,
+}
+
+impl ProviderHealth {
+    pub fn success_percent(&self) -> Option<u32> {
+        (self.fetches > 0).then(|| ((self.fetches - self.failures) * 100 / self.fetches) as u32)
+    }
+
+    pub fn average_usable_percent(&self) -> Option<u32> {
+        (self.usable_samples > 0).then(|| (self.usable_percent_sum / self.usable_samples) as u32)
+    }
+
+    pub fn node_count(&self) -> Option<usize> {
+        self.node_counts.last().map(|change| change.count)
+    }
+
+    fn add_success(&mut self, node_count: usize, now: u64) {
+        self.fetches += 1;
+        self.last_success = Some(now);
+        if self.node_count() != Some(node_count) {
+            self.node_counts.push(NodeCountChange { timestamp: now, count: node_count });
+            if self.node_counts.len() > MAX_NODE_COUNT_CHANGES {
+                self.node_counts.remove(0);
+            }
+        }
+    }
+
+    fn add_failure(&mut self, error: String, now: u64) {
+        self.fetches += 1;
+        self.failures += 1;
+        self.last_error = Some(FetchFailure { timestamp: now, error });
+    }
+
+    fn add_usable(&mut self, usable: usize, total: usize) {
+        if total == 0 {
+            return;
+        }
+        let percent = (usable * 100 / total) as u32;
+        self.usable_samples += 1;
+        self.usable_percent_sum += percent as u64;
+        self.last_usable_percent = Some(percent);
+    }
+
+    /// 问题出在订阅提供商一侧时的说明
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(percent) = self.success_percent().filter(|p| *p < FETCH_SUCCESS_WARN) {
+            warnings.push(format!("订阅获取成功率只有 {}%，订阅服务器不稳定，可以联系提供商", percent));
+        }
+        if self.node_count() == Some(0) {
+            warnings.push("订阅最近返回的节点为空，可能已到期或流量用完".to_string());
+        }
+        if let Some(percent) = self.average_usable_percent().filter(|p| *p < USABLE_RATIO_WARN) {
+            warnings.push(format!("平均只有 {}% 的节点可用，节点质量问题出在提供商一侧", percent));
+        }
+        warnings
+    }
+}
+
+/// 按订阅链接保存的获取记录
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProviderRecords {
+    pub subscriptions: BTreeMap<String, ProviderHealth>,
+}
+
+fn provider_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(PROVIDER_FILE))
+}
+
+impl ProviderRecords {
+    /// 读取记录，文件不存在或损坏时从空记录开始
+    pub fn load() -> Self {
+        let Ok(path) = provider_file() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("订阅状态记录已损坏，重新开始记录: {}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = provider_file()?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string(self)?).with_context(|| format!("无法写入订阅状态记录: {:?}", temp))?;
+        fs::rename(&temp, &path).with_context(|| format!("无法写入订阅状态记录: {:?}", path))?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    chrono::Local::now().timestamp().max(0) as u64
+}
+
+/// 在记录中更新某个订阅，写入失败只记日志
+fn update(url: &str, f: impl FnOnce(&mut ProviderHealth)) {
+    // 本地配置文件不是订阅提供商
+    if url.starts_with("file://") {
+        return;
+    }
+    let mut records = ProviderRecords::load();
+    f(records.subscriptions.entry(url.to_string()).or_default());
+    if let Err(e) = records.save() {
+        warn!("保存订阅状态记录失败: {:#}", e);
+    }
+}
+
+/// 记录一次成功的获取及订阅中的节点数量
+pub fn record_success(url: &str, node_count: usize) {
+    update(url, |health| health.add_success(node_count, now()));
+}
+
+/// 记录一次失败的获取
+pub fn record_failure(url: &str, error: &impl std::fmt::Display) {
+    update(url, |health| health.add_failure(error.to_string(), now()));
+}
+
+/// 记录一次完整测速中可用节点的数量
+pub fn record_usable(url: &str, usable: usize, total: usize) {
+    update(url, |health| health.add_usable(usable, total));
+}
+
+/// 隐藏订阅链接中的 token 等参数，只保留地址和路径
+pub fn mask_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((base, _)) => format!("{}?***", base),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_fetches_node_counts_and_usable_ratio() {
+        let mut health = ProviderHealth::default();
+        assert!(health.success_percent().is_none());
+        assert!(health.warnings().is_empty());
+
+        health.add_success(30, 100);
+        health.add_success(30, 200);
+        health.add_success(25, 300);
+        health.add_failure("订阅服务器返回 502".to_string(), 400);
+        health.add_usable(24, 30);
+        health.add_usable(3, 25);
+
+        assert_eq!(health.success_percent(), Some(75));
+        assert_eq!(health.node_counts, vec![
+            NodeCountChange { timestamp: 100, count: 30 },
+            NodeCountChange { timestamp: 300, count: 25 },
+        ]);
+        assert_eq!(health.last_success, Some(300));
+        assert_eq!(health.last_error.as_ref().unwrap().timestamp, 400);
+        assert_eq!(health.average_usable_percent(), Some(46));
+        assert_eq!(health.last_usable_percent, Some(12));
+        assert_eq!(health.warnings().len(), 1);
+
+        assert_eq!(mask_url("https://sub.example.com/api/v1?token=abc&flag=clash"), "https://sub.example.com/api/v1?***");
+        assert_eq!(mask_url("https://sub.example.com/link/abc"), "https://sub.example.com/link/abc");
+    }
+}
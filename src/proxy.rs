@@ -1,176 +1,547 @@
 use anyhow::{Context, Result};
-use log::{error, info, warn};
-use std::net::SocketAddr;
+use clashfun::error::ProxyError;
+use log::{debug, error, info, trace, warn};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tokio::sync::{RwLock, Mutex};
+use tokio::sync::{watch, RwLock, Mutex, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+use crate::bittorrent;
+use crate::bypass::{self, Destination};
+use crate::capture::{self, Transport};
+use crate::classifier;
+use crate::dns;
+use crate::dscp;
+use crate::events::{self, EventKind};
+use crate::mtu::{self, UdpVerdict};
+use crate::failover::{CheckRecord, Failover, FailoverPhase, FailoverPolicy, FailoverStatus, HealthReport, NodeHealth, SwitchReason, FAILURE_THRESHOLD};
+use crate::fd_usage;
+use crate::lan::{self, DeviceTable};
+use crate::network;
+use crate::node_overrides;
+use crate::notification;
+use crate::obfs::{self, ObfsSender};
+use crate::health;
+use crate::http_proxy;
+use crate::buffer_pool::{BufferPool, PooledBuffer, UDP_BUFFER_SIZE};
+use crate::outbound;
+use crate::performance;
+use crate::profiling::{self, LockSite};
+use crate::relay::{self, Ending};
+use crate::simulate::{self, Fate};
+use crate::sniff;
+use crate::socks5;
+use crate::sticky::{AffinityCache, AffinityKey};
+use crate::tasks::TaskSet;
+use crate::udp_batch;
+use crate::udp_quality::{SessionQuality, UdpQuality};
+use crate::udp_keepalive::{Activity, UdpKeepaliveConfig};
 use crate::subscription::{Node, SubscriptionManager};
-use crate::game_detect::{GameDetector, SupportedGame};
+use crate::game_detect::{GameDetector, PortProtocol, SupportedGame};
+use crate::handover;
+use crate::timeouts;
+
+/// 客户端地址到 UDP 会话的映射
+type UdpSessions = Arc<Mutex<HashMap<SocketAddr, UdpSession>>>;
+
+/// 故障切换时同时探测的备用节点数量
+const BACKUP_PROBE_CONCURRENCY: usize = 8;
+
+/// 接受 TCP 连接失败（如描述符耗尽）后等待多久再继续，避免空转
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// 为每个 UDP 会话分配唯一编号，用于清理时确认会话未被替换
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 一个客户端经由某个节点的 UDP 会话
+struct UdpSession {
+    id: u64,
+    /// 会话所连接的节点，当前节点变化后会话需要重建
+    node: String,
+    /// 节点地址，用于抓包
+    remote: SocketAddr,
+    uplink: UdpUplink,
+    relay: JoinHandle<()>,
+    /// 从第一个数据包识别出的游戏，决定保活间隔
+    game: Option<SupportedGame>,
+    /// 最近一次向节点发送数据的时间，空闲过久时发送保活包
+    activity: Arc<Activity>,
+    /// 最近一次收到客户端数据的时间，不包括保活包，超过空闲超时后关闭会话
+    client_activity: Arc<Activity>,
+    /// 两个方向的包数和估计的丢包、乱序
+    quality: Arc<UdpQuality>,
+}
+
+/// 发往节点的 UDP 通道
+#[derive(Clone)]
+enum UdpUplink {
+    Direct(Arc<UdpSocket>),
+    /// 经过填充/平滑/TLS 封装
+    Obfuscated(ObfsSender),
+}
+
+impl Drop for UdpSession {
+    fn drop(&mut self) {
+        self.relay.abort();
+    }
+}
+
+/// UDP 转发各任务共享的状态
+#[derive(Clone)]
+struct UdpContext {
+    /// 本地监听 socket，用于回复客户端
+    socket: Arc<UdpSocket>,
+    sessions: UdpSessions,
+    buffers: BufferPool,
+    stats: Arc<TrafficStats>,
+    devices: Arc<DeviceTable>,
+    affinity: Arc<AffinityCache>,
+}
+
+/// TCP 转发各连接共享的状态
+#[derive(Clone)]
+struct TcpContext {
+    current_node: Arc<RwLock<Option<Node>>>,
+    backup_nodes: Arc<RwLock<Vec<Node>>>,
+    failover: Arc<Mutex<Failover>>,
+    stats: Arc<TrafficStats>,
+    devices: Arc<DeviceTable>,
+    affinity: Arc<AffinityCache>,
+}
+
+/// 代理流量统计
+#[derive(Default)]
+pub struct TrafficStats {
+    upload: AtomicU64,
+    download: AtomicU64,
+    tcp_connections: AtomicU64,
+    /// 超过大小上限或发送失败而丢弃的 UDP 数据包
+    udp_dropped: AtomicU64,
+    /// 因超过大小上限而交给 IP 层分片的 UDP 数据包
+    udp_fragmented: AtomicU64,
+    /// 双方都正常关闭的 TCP 连接
+    tcp_closed: AtomicU64,
+    /// 转发出错（例如被重置）而结束的 TCP 连接
+    tcp_failed: AtomicU64,
+}
+
+impl TrafficStats {
+    fn add(&self, upload: u64, download: u64) {
+        self.upload.fetch_add(upload, Ordering::Relaxed);
+        self.download.fetch_add(download, Ordering::Relaxed);
+    }
+
+    /// (上行字节, 下行字节, 活动 TCP 连接数)
+    pub fn snapshot(&self) -> (u64, u64, u64) {
+        (
+            self.upload.load(Ordering::Relaxed),
+            self.download.load(Ordering::Relaxed),
+            self.tcp_connections.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 记录 TCP 连接结束的方式，转发的字节数在转发期间已经计入
+    fn record_ending(&self, ending: &Ending) {
+        let counter = match ending {
+            Ending::Closed | Ending::Idle(_) => &self.tcp_closed,
+            Ending::Failed { .. } => &self.tcp_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// (正常关闭的 TCP 连接, 出错结束的 TCP 连接)
+    pub fn tcp_endings(&self) -> (u64, u64) {
+        (
+            self.tcp_closed.load(Ordering::Relaxed),
+            self.tcp_failed.load(Ordering::Relaxed),
+        )
+    }
+
+    /// (丢弃的 UDP 包, IP 分片的 UDP 包)
+    pub fn udp_drops(&self) -> (u64, u64) {
+        (
+            self.udp_dropped.load(Ordering::Relaxed),
+            self.udp_fragmented.load(Ordering::Relaxed),
+        )
+    }
+}
 
 pub struct ProxyServer {
     port: u16,
+    /// 监听地址，允许局域网设备连接时为 0.0.0.0
+    listen_ip: IpAddr,
     current_node: Arc<RwLock<Option<Node>>>,
-    udp_sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
-    is_running: Arc<RwLock<bool>>,
+    udp_sessions: UdpSessions,
+    /// 运行状态，停止时各监听循环立即收到通知
+    running: watch::Sender<bool>,
     game_detector: Arc<Mutex<GameDetector>>,
     backup_nodes: Arc<RwLock<Vec<Node>>>,
     subscription_url: Arc<RwLock<Option<String>>>,
     node_failure_count: Arc<RwLock<HashMap<String, u32>>>,
+    failover: Arc<Mutex<Failover>>,
+    udp_buffers: BufferPool,
+    stats: Arc<TrafficStats>,
+    devices: Arc<DeviceTable>,
+    /// 目标到节点的粘性路由
+    affinity: Arc<AffinityCache>,
+    /// 正在使用的监听 socket，交接给新实例时需要
+    listeners: std::sync::Mutex<Option<(Arc<TcpListener>, Arc<UdpSocket>)>>,
+    /// 健康监控等后台任务，stop() 时等待它们退出
+    tasks: Arc<TaskSet>,
 }
 
 impl ProxyServer {
     pub fn new(port: u16) -> Self {
         Self {
             port,
+            listen_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
             current_node: Arc::new(RwLock::new(None)),
             udp_sessions: Arc::new(Mutex::new(HashMap::new())),
-            is_running: Arc::new(RwLock::new(false)),
+            running: watch::Sender::new(false),
             game_detector: Arc::new(Mutex::new(GameDetector::new())),
             backup_nodes: Arc::new(RwLock::new(Vec::new())),
             subscription_url: Arc::new(RwLock::new(None)),
             node_failure_count: Arc::new(RwLock::new(HashMap::new())),
+            failover: Arc::new(Mutex::new(Failover::default())),
+            udp_buffers: if crate::profile::router() {
+                BufferPool::new(UDP_BUFFER_SIZE).max_idle(crate::profile::ROUTER_IDLE_UDP_BUFFERS)
+            } else {
+                BufferPool::new(UDP_BUFFER_SIZE)
+            },
+            stats: Arc::new(TrafficStats::default()),
+            devices: Arc::new(DeviceTable::default()),
+            affinity: Arc::new(AffinityCache::default()),
+            listeners: std::sync::Mutex::new(None),
+            tasks: Arc::new(TaskSet::default()),
         }
     }
 
+    /// 允许局域网设备（例如游戏主机）使用本机的加速服务
+    pub fn allow_lan(mut self, allow: bool) -> Self {
+        if allow {
+            self.listen_ip = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        }
+        self
+    }
+
+    /// 切换到用户选择的节点，该节点同时作为故障恢复后切回的首选节点
     pub async fn set_node(&self, node: Node) {
+        self.failover.lock().await.set_preferred(&node);
+        self.affinity.clear();
         let mut current = self.current_node.write().await;
         *current = Some(node);
         info!("代理节点已切换");
     }
 
+    /// 运行中切换节点，并记入切换历史
+    pub async fn switch_node(&self, node: Node, reason: SwitchReason) {
+        let from = self.current_node.read().await.as_ref().map(|n| n.name.clone());
+        if from.as_deref() != Some(node.name.as_str()) {
+            self.failover.lock().await.record_switch(from.as_deref(), &node.name, reason);
+        }
+        self.set_node(node).await;
+    }
+
+    /// 各节点的故障计数、最近检查结果和切换历史
+    pub async fn health_report(&self) -> HealthReport {
+        let current = self.current_node.read().await.as_ref().map(|n| n.name.clone());
+        let backups: Vec<String> = self.backup_nodes.read().await.iter().map(|n| n.name.clone()).collect();
+        let failure_count = self.node_failure_count.read().await;
+        let failover = self.failover.lock().await;
+        let preferred = failover.preferred().map(|n| n.name.clone());
+
+        // 当前节点在前，其次是首选和备用节点，最后是其他检查过的节点
+        let mut names: Vec<String> = Vec::new();
+        let others = failure_count.keys().chain(failover.known_nodes()).cloned().collect::<Vec<_>>();
+        for name in current.iter().chain(preferred.iter()).chain(backups.iter()).cloned().chain(others) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let nodes = names
+            .into_iter()
+            .map(|name| NodeHealth {
+                consecutive_failures: failure_count.get(&name).copied().unwrap_or(0),
+                backoff_secs: failover.backoff_remaining(&name).map(|d| d.as_secs().max(1)),
+                last_check: failover.last_check(&name).cloned(),
+                backup: backups.contains(&name),
+                name,
+            })
+            .collect();
+
+        HealthReport {
+            cooldown_secs: failover.cooldown_remaining().map(|d| d.as_secs().max(1)),
+            current,
+            preferred,
+            nodes,
+            switches: failover.history().cloned().collect(),
+            stream_retries: failover.stream_retries(),
+            backups,
+        }
+    }
+
+    pub async fn set_failover_policy(&self, policy: FailoverPolicy) {
+        self.failover.lock().await.policy = policy;
+    }
+
     pub async fn set_subscription_url(&self, url: String) {
         let mut sub_url = self.subscription_url.write().await;
         *sub_url = Some(url);
     }
 
     pub async fn set_backup_nodes(&self, nodes: Vec<Node>) {
-        let mut backup = self.backup_nodes.write().await;
-        *backup = nodes;
-        info!("设置了 {} 个备用节点", backup.len());
+        let count = Self::install_backups(&self.backup_nodes, &self.failover, nodes).await;
+        info!("设置了 {} 个备用节点", count);
     }
 
-    pub async fn is_running(&self) -> bool {
-        *self.is_running.read().await
+    /// 按刚测得的延迟排序并截取备用节点，返回保留的数量
+    async fn install_backups(backup_nodes: &RwLock<Vec<Node>>, failover: &Mutex<Failover>, nodes: Vec<Node>) -> usize {
+        let nodes = {
+            let mut failover = failover.lock().await;
+            failover.record_tested(&nodes);
+            failover.rank_backups(nodes)
+        };
+        dns::prefetch(&nodes);
+        let mut backup = backup_nodes.write().await;
+        *backup = nodes;
+        backup.len()
     }
 
+    /// 停止监听并等待所有后台任务退出，返回后不会再有后台任务访问代理服务器
     pub async fn stop(&self) -> Result<()> {
-        let mut running = self.is_running.write().await;
-        *running = false;
+        self.running.send_replace(false);
         info!("代理服务器停止信号已发送");
+        self.tasks.shutdown().await;
+        info!("后台任务已全部停止");
         Ok(())
     }
 
+    /// 随服务运行的后台任务，stop() 时统一停止
+    pub fn tasks(&self) -> Arc<TaskSet> {
+        Arc::clone(&self.tasks)
+    }
+
+    /// 等待停止信号
+    async fn wait_for_stop(running: &mut watch::Receiver<bool>) {
+        // 发送端随 ProxyServer 一起释放时同样视为停止
+        let _ = running.wait_for(|running| !*running).await;
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.listen_ip, self.port)
+    }
+
     pub async fn start(&self) -> Result<()> {
-        {
-            let mut running = self.is_running.write().await;
-            if *running {
-                return Err(anyhow::anyhow!("代理服务器已在运行"));
+        let tcp_listener = TcpListener::bind((self.listen_ip, self.port))
+            .await
+            .map_err(|e| ProxyError::bind("TCP", self.port, e))?;
+
+        let udp_socket = UdpSocket::bind((self.listen_ip, self.port))
+            .await
+            .map_err(|e| ProxyError::bind("UDP", self.port, e))?;
+
+        info!("代理服务器启动在 {}:{}", self.listen_ip, self.port);
+        self.serve(tcp_listener, udp_socket, Vec::new()).await
+    }
+
+    /// 使用从旧实例接管的监听 socket 启动，并继续转发交接过来的 UDP 会话
+    pub async fn start_inherited(&self, inherited: handover::Inherited) -> Result<()> {
+        inherited.tcp.set_nonblocking(true)?;
+        inherited.udp.set_nonblocking(true)?;
+        let tcp_listener = TcpListener::from_std(inherited.tcp)?;
+        let udp_socket = UdpSocket::from_std(inherited.udp)?;
+
+        let mut sessions = Vec::new();
+        for (info, socket) in inherited.sessions {
+            socket.set_nonblocking(true)?;
+            sessions.push((info, UdpSocket::from_std(socket)?));
+        }
+
+        info!("代理服务器接管了 {}:{} 和 {} 个 UDP 会话", self.listen_ip, self.port, sessions.len());
+        self.serve(tcp_listener, udp_socket, sessions).await
+    }
+
+    /// 复制监听 socket 和直连的 UDP 会话交给新实例，本实例不再转发这些会话
+    #[cfg(unix)]
+    pub async fn export_for_handover(&self) -> Result<handover::Exported> {
+        use std::os::fd::AsFd;
+
+        let (tcp, udp) = self
+            .listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .context("代理服务器尚未启动")?;
+
+        let mut sessions = Vec::new();
+        let mut remaining = self.udp_sessions.lock().await;
+        for (client, session) in remaining.drain() {
+            let UdpUplink::Direct(socket) = &session.uplink else {
+                // 混淆通道的状态无法交接，客户端的下一个包会在新实例中重建会话
+                continue;
+            };
+            if sessions.len() >= handover::MAX_SESSIONS {
+                continue;
             }
-            *running = true;
+            let info = handover::SessionInfo {
+                client,
+                node: session.node.clone(),
+                remote: session.remote,
+            };
+            // 丢弃会话会中止本实例的反向转发任务
+            sessions.push((info, socket.as_fd().try_clone_to_owned()?));
         }
 
-        let tcp_listener = TcpListener::bind(format!("127.0.0.1:{}", self.port))
-            .await
-            .with_context(|| format!("无法绑定 TCP 端口 {}", self.port))?;
+        Ok(handover::Exported {
+            tcp: tcp.as_fd().try_clone_to_owned()?,
+            udp: udp.as_fd().try_clone_to_owned()?,
+            sessions,
+        })
+    }
 
-        let udp_socket = Arc::new(
-            UdpSocket::bind(format!("127.0.0.1:{}", self.port))
-                .await
-                .with_context(|| format!("无法绑定 UDP 端口 {}", self.port))?,
-        );
+    async fn serve(&self, tcp_listener: TcpListener, udp_socket: UdpSocket, inherited: Vec<(handover::SessionInfo, UdpSocket)>) -> Result<()> {
+        let started = self.running.send_if_modified(|running| !std::mem::replace(running, true));
+        if !started {
+            return Err(ProxyError::AlreadyRunning.into());
+        }
+
+        // TPROXY 转来的连接目标不是本机地址，监听 socket 需要 IP_TRANSPARENT
+        #[cfg(target_os = "linux")]
+        if crate::redirect::transparent() {
+            use std::os::fd::AsRawFd;
+            if let Err(e) = crate::netfilter::set_transparent(tcp_listener.as_raw_fd()) {
+                warn!("{:#}", e);
+            }
+        }
 
-        info!("代理服务器启动在端口 {}", self.port);
+        let tcp_listener = Arc::new(tcp_listener);
+        let udp_socket = Arc::new(udp_socket);
+        *self.listeners.lock().unwrap_or_else(|e| e.into_inner()) = Some((Arc::clone(&tcp_listener), Arc::clone(&udp_socket)));
 
         // 启动健康监控
-        let current_node_clone = Arc::clone(&self.current_node);
-        let is_running_clone = Arc::clone(&self.is_running);
-        let failure_count_clone = Arc::clone(&self.node_failure_count);
-        let backup_nodes_clone = Arc::clone(&self.backup_nodes);
-        let subscription_url_clone = Arc::clone(&self.subscription_url);
-
-        Self::start_health_monitor_task(
-            current_node_clone,
-            is_running_clone,
-            failure_count_clone,
-            backup_nodes_clone,
-            subscription_url_clone
-        ).await;
+        self.start_health_monitor_task().await;
 
         let tcp_handle = {
-            let current_node = Arc::clone(&self.current_node);
-            let is_running = Arc::clone(&self.is_running);
-            let game_detector = Arc::clone(&self.game_detector);
+            let context = TcpContext {
+                current_node: Arc::clone(&self.current_node),
+                backup_nodes: Arc::clone(&self.backup_nodes),
+                failover: Arc::clone(&self.failover),
+                stats: Arc::clone(&self.stats),
+                devices: Arc::clone(&self.devices),
+                affinity: Arc::clone(&self.affinity),
+            };
+            let udp_sessions = Arc::clone(&self.udp_sessions);
+            let mut running = self.running.subscribe();
             tokio::spawn(async move {
                 loop {
-                    if !*is_running.read().await {
-                        info!("TCP 服务器收到停止信号");
-                        break;
-                    }
+                    let accepted = tokio::select! {
+                        _ = Self::wait_for_stop(&mut running) => {
+                            info!("TCP 服务器收到停止信号");
+                            break;
+                        }
+                        accepted = tcp_listener.accept() => accepted,
+                    };
 
-                    match tcp_listener.accept().await {
+                    match accepted {
                         Ok((stream, addr)) => {
-                            let node = Arc::clone(&current_node);
-                            let detector = Arc::clone(&game_detector);
+                            if !lan::check_allowed(addr.ip()) {
+                                continue;
+                            }
+                            if context.devices.is_blocked(addr.ip()) {
+                                info!("设备 {} 已被禁止使用加速，拒绝连接", addr.ip());
+                                continue;
+                            }
+
+                            let context = context.clone();
                             tokio::spawn(async move {
-                                if let Err(e) = Self::handle_tcp_connection(stream, addr, node, detector).await {
+                                if let Err(e) = Self::handle_tcp_connection(stream, addr, context).await {
                                     error!("TCP 连接处理错误: {}", e);
                                 }
                             });
                         }
+                        // 监听出错不停止服务，只有收到停止信号才退出
+                        Err(e) if e.kind() == std::io::ErrorKind::ConnectionAborted => {
+                            debug!("客户端在连接建立前断开: {}", e);
+                            continue;
+                        }
                         Err(e) => {
-                            error!("TCP 监听错误: {}", e);
-                            break;
+                            if fd_usage::is_exhausted_io(&e) {
+                                warn!("文件描述符或缓冲区耗尽，暂时无法接受 TCP 连接: {}", e);
+                                let mut sessions = profiling::lock(LockSite::UdpSessions, &udp_sessions).await;
+                                Self::shed_idle_session(&mut sessions, None);
+                            } else {
+                                error!("TCP 监听错误: {}", e);
+                            }
+                            tokio::select! {
+                                _ = Self::wait_for_stop(&mut running) => {
+                                    info!("TCP 服务器收到停止信号");
+                                    break;
+                                }
+                                _ = tokio::time::sleep(ACCEPT_RETRY_DELAY) => {}
+                            }
                         }
                     }
                 }
             })
         };
 
+        let context = UdpContext {
+            socket: Arc::clone(&udp_socket),
+            sessions: Arc::clone(&self.udp_sessions),
+            buffers: self.udp_buffers.clone(),
+            stats: Arc::clone(&self.stats),
+            devices: Arc::clone(&self.devices),
+            affinity: Arc::clone(&self.affinity),
+        };
+        {
+            let mut sessions = self.udp_sessions.lock().await;
+            for (info, socket) in inherited {
+                let session = Self::direct_session(info.client, info.node, info.remote, Arc::new(socket), None, context.clone());
+                sessions.insert(info.client, session);
+            }
+        }
+
         let udp_handle = {
             let current_node = Arc::clone(&self.current_node);
-            let udp_socket = Arc::clone(&udp_socket);
-            let udp_sessions = Arc::clone(&self.udp_sessions);
-            let is_running = Arc::clone(&self.is_running);
-            let game_detector = Arc::clone(&self.game_detector);
+            let mut running = self.running.subscribe();
             tokio::spawn(async move {
-                let mut buf = [0; 65536];
                 loop {
-                    if !*is_running.read().await {
-                        info!("UDP 服务器收到停止信号");
-                        break;
-                    }
-
-                    match tokio::time::timeout(Duration::from_millis(100), udp_socket.recv_from(&mut buf)).await {
-                        Ok(Ok((size, addr))) => {
-                            let node = Arc::clone(&current_node);
-                            let socket = Arc::clone(&udp_socket);
-                            let sessions = Arc::clone(&udp_sessions);
-                            let data = buf[..size].to_vec();
-
-                            let detector = Arc::clone(&game_detector);
-                            tokio::spawn(async move {
-                                if let Err(e) = Self::handle_udp_packet(socket, data, addr, node, sessions, detector).await {
-                                    error!("UDP 包处理错误: {}", e);
-                                }
-                            });
+                    let received = tokio::select! {
+                        _ = Self::wait_for_stop(&mut running) => {
+                            info!("UDP 服务器收到停止信号");
+                            break;
                         }
-                        Ok(Err(e)) => {
+                        received = udp_batch::recv_batch(&context.socket, &context.buffers) => received,
+                    };
+
+                    match received {
+                        Ok(packets) => {
+                            for (buf, addr) in packets {
+                                let node = Arc::clone(&current_node);
+                                let context = context.clone();
+                                tokio::spawn(async move {
+                                    if let Err(e) = Self::handle_udp_packet(context, buf, addr, node).await {
+                                        error!("UDP 包处理错误: {}", e);
+                                    }
+                                });
+                            }
+                        }
+                        Err(e) => {
                             error!("UDP 接收错误: {}", e);
                             break;
                         }
-                        Err(_) => {
-                            // 超时，继续循环检查停止信号
-                            continue;
-                        }
                     }
                 }
             })
         };
 
         tokio::try_join!(tcp_handle, udp_handle)?;
+        *self.listeners.lock().unwrap_or_else(|e| e.into_inner()) = None;
 
         Ok(())
     }
@@ -178,13 +549,56 @@ impl ProxyServer {
     async fn handle_tcp_connection(
         client_stream: TcpStream,
         client_addr: SocketAddr,
-        current_node: Arc<RwLock<Option<Node>>>,
-        game_detector: Arc<Mutex<GameDetector>>,
+        context: TcpContext,
     ) -> Result<()> {
-        info!("新的 TCP 连接来自: {}", client_addr);
+        let TcpContext {
+            current_node,
+            backup_nodes,
+            failover,
+            stats,
+            devices,
+            affinity,
+        } = context;
+        debug!("新的 TCP 连接来自: {}", client_addr);
+
+        // 被重定向到代理端口的连接知道原始目标，命中直连规则时不经过节点
+        let original_destination = bypass::original_destination(&client_stream);
+        if let Some(reason) = network::paused() {
+            return match original_destination {
+                Some(original) => Self::relay_direct(client_stream, client_addr, original).await,
+                None => {
+                    debug!("加速已暂停 ({})，拒绝来自 {} 的连接", reason, client_addr);
+                    Ok(())
+                }
+            };
+        }
+        if let Some(original) = original_destination {
+            // 透明模式下客户端按 IP 连接，有域名规则时才从 TLS SNI 或 HTTP Host 找回域名；
+            // 识别要等待客户端的首个数据包，先按地址和端口判断，命中时不必等待
+            let by_addr = Destination { host: None, addr: Some(original) };
+            if bypass::should_bypass(&by_addr) {
+                return Self::relay_direct(client_stream, client_addr, original).await;
+            }
+            let sniffed = if bypass::has_domain_rules() { sniff::sniff(&client_stream).await } else { None };
+            if let Some(sniffed) = &sniffed {
+                debug!("{} -> {} 的目标域名: {}", client_addr, original, sniffed.host);
+            }
+            let destination = Destination {
+                host: sniffed.as_ref().map(|sniffed| sniffed.host.as_str()),
+                addr: Some(original),
+            };
+            if bypass::should_bypass(&destination) {
+                return Self::relay_direct(client_stream, client_addr, original).await;
+            }
+            // 设置了游戏端口的设备（例如游戏机），系统更新和商店下载等不经过节点
+            if !devices.should_relay(client_addr.ip(), original.port()) {
+                debug!("{} -> {} 不是该设备的游戏端口，直连", client_addr, original);
+                return Self::relay_direct(client_stream, client_addr, original).await;
+            }
+        }
 
         let node = {
-            let guard = current_node.read().await;
+            let guard = profiling::read(LockSite::CurrentNode, &current_node).await;
             match guard.as_ref() {
                 Some(node) => node.clone(),
                 None => {
@@ -193,65 +607,179 @@ impl ProxyServer {
                 }
             }
         };
+        let affinity_key = AffinityKey::new(client_addr, original_destination);
+        let node = affinity.route(affinity_key, &node);
 
-        info!("通过节点 {} 代理 TCP 连接", node.name);
+        debug!("通过节点 {} 代理 TCP 连接", node.name);
 
-        // 检测游戏流量
-        let mut _detected_game = None;
-        {
-            let mut detector = game_detector.lock().await;
-            if let Ok(detected_games) = detector.detect_running_games() {
-                for (game, _) in detected_games {
-                    let game_ports = game.get_game_ports();
-                    if game_ports.contains(&client_addr.port()) {
-                        info!("检测到游戏 {} 的 TCP 流量 (端口: {})", game.display_name(), client_addr.port());
-                        _detected_game = Some(game);
-                        break;
+        // 识别游戏流量
+        let classification = classifier::classify(PortProtocol::Tcp, client_addr, original_destination, &[]);
+        if let Some(classification) = &classification {
+            info!("识别到 TCP 流量属于 {}", classification.describe());
+        }
+
+        // 模拟建立连接的往返延迟，丢包时按 SYN 重传等待 1 秒
+        match simulate::round_trip() {
+            Some(Fate::Delay(delay)) => tokio::time::sleep(delay).await,
+            Some(Fate::Drop) => tokio::time::sleep(Duration::from_secs(1)).await,
+            None => {}
+        }
+
+        // 连接到目标节点，失败时只把这一条连接改用备用节点重试，不切换当前节点
+        let dial = async {
+            match dns::connect_node(&node).await {
+                Ok(target_stream) => Some((node.clone(), target_stream)),
+                Err(e) => {
+                    error!("无法连接到节点 {}:{}: {}", node.server, node.port, e);
+                    Self::retry_on_backup(&node, &backup_nodes, &failover).await
+                }
+            }
+        };
+        // 连接节点的同时检查是否是 BitTorrent 连接；客户端放弃连接时不再继续连接节点
+        let checked_dial = async { tokio::join!(dial, bittorrent::should_block_tcp(&client_stream, client_addr)) };
+        let (node, target_stream) = tokio::select! {
+            (dialed, blocked) = checked_dial => match dialed {
+                _ if blocked => return Ok(()),
+                Some(dialed) => dialed,
+                None => return Ok(()),
+            },
+            _ = relay::closed(&client_stream) => {
+                info!("客户端 {} 在连接节点 {} 期间断开，已取消连接", client_addr, node.name);
+                return Ok(());
+            }
+        };
+        debug!("已连接到目标节点 {}:{}", node.server, node.port);
+        // SOCKS5 和 HTTP 节点需要告诉它要连接的目标；不知道原始目标时由客户端自己与节点握手
+        let mut target_stream = target_stream;
+        if socks5::is_socks5(&node) || http_proxy::is_http(&node) {
+            match original_destination {
+                Some(original) => {
+                    let connected = if socks5::is_socks5(&node) {
+                        socks5::connect(&mut target_stream, &node, original).await
+                    } else {
+                        http_proxy::connect(&mut target_stream, &node, original).await
+                    };
+                    if let Err(e) = connected {
+                        warn!("经 {} 节点 {} 连接 {} 失败: {:#}", node.protocol, node.name, original, e);
+                        return Ok(());
                     }
                 }
+                None => debug!("{} 直接连接代理端口，原样转发到 {} 节点 {}", client_addr, node.protocol, node.name),
             }
         }
+        if let Ok(node_addr) = target_stream.peer_addr() {
+            dscp::mark(&target_stream, node_addr, classification.as_ref().map(|c| &c.game));
+        }
 
-        // 连接到目标节点
-        match TcpStream::connect(format!("{}:{}", node.server, node.port)).await {
-            Ok(target_stream) => {
-                info!("已连接到目标节点 {}:{}", node.server, node.port);
-
-                // 双向数据转发
-                let (mut client_read, mut client_write) = client_stream.into_split();
-                let (mut target_read, mut target_write) = target_stream.into_split();
-
-                let client_to_target = async {
-                    tokio::io::copy(&mut client_read, &mut target_write).await
-                };
-                let target_to_client = async {
-                    tokio::io::copy(&mut target_read, &mut client_write).await
+        // 双向数据转发，空闲超时按识别出的游戏决定
+        stats.tcp_connections.fetch_add(1, Ordering::Relaxed);
+        devices.connection_opened(client_addr.ip());
+        let idle = timeouts::current().tcp_idle(classification.as_ref().map(|c| &c.game));
+        // 转发期间持续计入流量统计，cf status 能看到仍在进行的长连接
+        let progress = |upload, download| {
+            stats.add(upload, download);
+            devices.add_traffic(client_addr.ip(), upload, download);
+        };
+        let relayed = match target_stream.peer_addr() {
+            // 抓包时改用用户态拷贝，才能看到转发的数据
+            Ok(node_addr) if capture::active() => {
+                let observe = |uplink, data: &[u8]| {
+                    let (src, dst) = if uplink { (client_addr, node_addr) } else { (node_addr, client_addr) };
+                    capture::record(Transport::Tcp, src, dst, data);
                 };
+                relay::relay_tcp_observed(client_stream, target_stream, idle, observe, progress).await
+            }
+            _ => relay::relay_tcp(client_stream, target_stream, idle, progress).await,
+        };
+        if let Ending::Idle(idle) = relayed.ending {
+            debug!("TCP 连接 {} 空闲超过 {} 秒，已关闭", client_addr, idle.as_secs());
+        }
+        stats.tcp_connections.fetch_sub(1, Ordering::Relaxed);
+        devices.connection_closed(client_addr.ip());
+        affinity.touch(affinity_key);
+
+        stats.record_ending(&relayed.ending);
+        match relayed.error() {
+            None => debug!("TCP 连接已关闭: {} (上行 {} 字节, 下行 {} 字节)", client_addr, relayed.sent, relayed.received),
+            Some(error) => warn!(
+                "TCP 连接异常结束: {} ({}，上行 {} 字节, 下行 {} 字节)",
+                client_addr, error, relayed.sent, relayed.received
+            ),
+        }
 
-                if let Err(e) = tokio::try_join!(client_to_target, target_to_client) {
-                    warn!("TCP 转发错误: {}", e);
-                }
+        Ok(())
+    }
+
+    /// 连接节点失败时改用最优的备用节点重试这一条连接，受每分钟重试次数限制
+    async fn retry_on_backup(failed: &Node, backup_nodes: &RwLock<Vec<Node>>, failover: &Mutex<Failover>) -> Option<(Node, TcpStream)> {
+        let backup = {
+            let mut failover = failover.lock().await;
+            // 备用节点按延迟排序，取第一个不在退避期内的
+            let backup = backup_nodes
+                .read()
+                .await
+                .iter()
+                .find(|n| n.name != failed.name && !failover.in_backoff(&n.name))
+                .cloned()?;
+            if !failover.take_stream_retry() {
+                warn!("连接重试次数已达每分钟上限，不再改用备用节点");
+                return None;
+            }
+            backup
+        };
 
-                info!("TCP 连接已关闭: {}", client_addr);
+        match dns::connect_node(&backup).await {
+            Ok(stream) => {
+                info!("节点 {} 连接失败，本次连接改用备用节点 {}", failed.name, backup.name);
+                Some((backup, stream))
             }
             Err(e) => {
-                error!("无法连接到节点 {}:{}: {}", node.server, node.port, e);
+                error!("备用节点 {}:{} 同样无法连接: {}", backup.server, backup.port, e);
+                failover.lock().await.record_failure(&backup.name);
+                None
             }
         }
+    }
 
+    /// 直连原始目标，不计入节点流量
+    async fn relay_direct(client_stream: TcpStream, client_addr: SocketAddr, target: SocketAddr) -> Result<()> {
+        debug!("{} -> {} 命中直连规则，不经过节点", client_addr, target);
+        let connect = outbound::tcp_socket(target)?.connect(target);
+        let target_stream = tokio::time::timeout(timeouts::current().connect(), connect)
+            .await
+            .map_err(|_| anyhow::anyhow!("直连 {} 超时", target))?
+            .with_context(|| format!("无法直连 {}", target))?;
+        let relayed = relay::relay_tcp(client_stream, target_stream, timeouts::current().tcp_idle(None), |_, _| {}).await;
+        match relayed.error() {
+            None => debug!("直连已关闭: {} (上行 {} 字节, 下行 {} 字节)", client_addr, relayed.sent, relayed.received),
+            Some(error) => warn!("直连异常结束: {} ({})", client_addr, error),
+        }
         Ok(())
     }
 
     async fn handle_udp_packet(
-        client_socket: Arc<UdpSocket>,
-        data: Vec<u8>,
+        context: UdpContext,
+        data: PooledBuffer,
         client_addr: SocketAddr,
         current_node: Arc<RwLock<Option<Node>>>,
-        udp_sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
-        game_detector: Arc<Mutex<GameDetector>>,
     ) -> Result<()> {
+        if !lan::check_allowed(client_addr.ip()) {
+            return Ok(());
+        }
+        if context.devices.is_blocked(client_addr.ip()) {
+            debug!("设备 {} 已被禁止使用加速，丢弃 UDP 包", client_addr.ip());
+            return Ok(());
+        }
+        if bittorrent::should_block_udp(&data, client_addr) {
+            return Ok(());
+        }
+        if network::paused().is_some() {
+            debug!("加速已暂停，丢弃来自 {} 的 UDP 包", client_addr);
+            return Ok(());
+        }
+
         let node = {
-            let guard = current_node.read().await;
+            let guard = profiling::read(LockSite::CurrentNode, &current_node).await;
             match guard.as_ref() {
                 Some(node) => node.clone(),
                 None => {
@@ -260,326 +788,527 @@ impl ProxyServer {
                 }
             }
         };
+        // UDP 不知道原始目标，按来源设备保持节点不变
+        let node = context.affinity.route(AffinityKey::new(client_addr, None), &node);
+        if socks5::is_socks5(&node) || http_proxy::is_http(&node) {
+            debug!("{} 节点 {} 暂不支持转发 UDP，丢弃来自 {} 的 UDP 包", node.protocol, node.name, client_addr);
+            return Ok(());
+        }
+        if !node_overrides::for_node(&node).udp {
+            debug!("节点 {} 设置了不转发 UDP，丢弃来自 {} 的 UDP 包", node.name, client_addr);
+            return Ok(());
+        }
 
-        // 检测游戏流量
-        let mut detected_game = None;
-        {
-            let mut detector = game_detector.lock().await;
-            if let Ok(detected_games) = detector.detect_running_games() {
-                for (game, _) in detected_games {
-                    let game_ports = game.get_game_ports();
-
-                    // 检查端口匹配
-                    if game_ports.contains(&client_addr.port()) {
-                        info!("检测到游戏 {} 的 UDP 流量 (端口: {})", game.display_name(), client_addr.port());
-                        detected_game = Some(game.clone());
-                        break;
-                    }
-
-                    // 检查数据包特征
-                    if Self::is_game_packet_static(&game, &data) {
-                        info!("检测到游戏 {} 的 UDP 数据包特征", game.display_name());
-                        detected_game = Some(game.clone());
-                        break;
+        trace!("通过节点 {} 代理 UDP 包从 {}", node.name, client_addr);
+
+        // 获取或创建到目标节点的 UDP 会话；节点切换后在原客户端映射上重建，客户端无需重连。
+        // 解析和绑定可能很慢，期间不持有会话表的锁，其他客户端的包照常转发
+        let migrating = {
+            let mut sessions = profiling::lock(LockSite::UdpSessions, &context.sessions).await;
+            match sessions.get(&client_addr) {
+                Some(session) if session.node == node.name => None,
+                existing => {
+                    let migrating = existing.map(|old| (old.node.clone(), old.game.clone()));
+                    // 描述符快用完时先释放空闲会话，不让新的游戏连接失败
+                    if fd_usage::near_limit() {
+                        Self::shed_idle_session(&mut sessions, Some(client_addr));
                     }
+                    Some(migrating)
                 }
             }
-        }
-
-        info!("通过节点 {} 代理 UDP 包从 {}", node.name, client_addr);
-        if let Some(ref game) = detected_game {
-            info!("使用游戏 {} 的优化配置", game.display_name());
-        }
+        };
 
-        // 获取或创建到目标节点的 UDP socket
-        let target_socket = {
-            let mut sessions = udp_sessions.lock().await;
-            if let Some(socket) = sessions.get(&client_addr) {
-                Arc::clone(socket)
-            } else {
-                // 创建新的 UDP socket 连接到目标节点
-                match UdpSocket::bind("0.0.0.0:0").await {
-                    Ok(socket) => {
-                        let socket = Arc::new(socket);
-
-                        // 连接到目标节点
-                        if let Err(e) = socket.connect(format!("{}:{}", node.server, node.port)).await {
-                            error!("无法连接到 UDP 节点 {}:{}: {}", node.server, node.port, e);
-                            return Ok(());
-                        }
+        let (uplink, remote) = match migrating {
+            None => match Self::reuse_udp_session(&context.sessions, client_addr, &node, &data).await {
+                Some(uplink) => uplink,
+                // 会话在两次加锁之间结束，按新会话处理
+                None => match Self::establish_udp_session(&node, client_addr, None, &data, &context).await {
+                    Some(uplink) => uplink,
+                    None => return Ok(()),
+                },
+            },
+            Some(migrating) => match Self::establish_udp_session(&node, client_addr, migrating, &data, &context).await {
+                Some(uplink) => uplink,
+                None => return Ok(()),
+            },
+        };
+        capture::record(Transport::Udp, client_addr, remote, &data);
 
-                        sessions.insert(client_addr, Arc::clone(&socket));
-
-                        // 启动反向数据转发任务
-                        let client_sock = Arc::clone(&client_socket);
-                        let target_sock = Arc::clone(&socket);
-                        let sessions_cleanup = Arc::clone(&udp_sessions);
-                        tokio::spawn(async move {
-                            let mut buf = [0; 65536];
-                            loop {
-                                match target_sock.recv(&mut buf).await {
-                                    Ok(size) => {
-                                        if let Err(e) = client_sock.send_to(&buf[..size], client_addr).await {
-                                            error!("UDP 反向转发失败: {}", e);
-                                            break;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        warn!("UDP 目标接收错误: {}", e);
-                                        break;
-                                    }
-                                }
-                            }
-                            // 清理会话
-                            let mut sessions = sessions_cleanup.lock().await;
-                            sessions.remove(&client_addr);
-                        });
+        // 每个包都在独立的任务中处理，直接等待不会阻塞其他包
+        match simulate::one_way() {
+            Some(Fate::Drop) => return Ok(()),
+            Some(Fate::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => {}
+        }
 
-                        socket
-                    }
-                    Err(e) => {
-                        error!("无法创建 UDP socket: {}", e);
-                        return Ok(());
-                    }
+        // 转发数据到目标节点，超过大小上限的数据包按配置丢弃或整包交给 IP 层分片；
+        // 不能在应用层切开数据报，节点和游戏服务器无法把切开的片段还原
+        match mtu::limits().check_udp(data.len()) {
+            UdpVerdict::Send => {}
+            UdpVerdict::Fragment => {
+                context.stats.udp_fragmented.fetch_add(1, Ordering::Relaxed);
+                debug!("UDP 包 {} 字节超过上限，允许 IP 分片后整包发送", data.len());
+                if let UdpUplink::Direct(socket) = &uplink {
+                    mtu::allow_fragmentation(socket);
                 }
             }
-        };
+            UdpVerdict::Drop => {
+                context.stats.udp_dropped.fetch_add(1, Ordering::Relaxed);
+                debug!("丢弃超过大小上限的 UDP 包: {} 字节", data.len());
+                return Ok(());
+            }
+        }
 
-        // 转发数据到目标节点
-        if let Err(e) = target_socket.send(&data).await {
-            error!("UDP 转发失败: {}", e);
+        match &uplink {
+            UdpUplink::Direct(socket) => match socket.send(&data).await {
+                Ok(size) => {
+                    context.stats.add(size as u64, 0);
+                    context.devices.add_traffic(client_addr.ip(), size as u64, 0);
+                }
+                Err(e) => {
+                    context.stats.udp_dropped.fetch_add(1, Ordering::Relaxed);
+                    error!("UDP 转发失败: {}", e);
+                }
+            },
+            UdpUplink::Obfuscated(sender) => {
+                if sender.send(&data) {
+                    context.stats.add(data.len() as u64, 0);
+                    context.devices.add_traffic(client_addr.ip(), data.len() as u64, 0);
+                } else {
+                    context.stats.udp_dropped.fetch_add(1, Ordering::Relaxed);
+                    debug!("混淆发送队列已满或已关闭，丢弃 UDP 包");
+                }
+            }
         }
 
         Ok(())
     }
 
-    pub fn get_proxy_port(&self) -> u16 {
-        self.port
+    /// 创建连接到节点的 UDP socket，并启动从节点到客户端的反向转发任务
+    /// 沿用客户端在当前节点上的会话
+    async fn reuse_udp_session(
+        sessions: &UdpSessions,
+        client_addr: SocketAddr,
+        node: &Node,
+        data: &[u8],
+    ) -> Option<(UdpUplink, SocketAddr)> {
+        let sessions = profiling::lock(LockSite::UdpSessions, sessions).await;
+        let session = sessions.get(&client_addr).filter(|session| session.node == node.name)?;
+        session.activity.touch();
+        session.client_activity.touch();
+        session.quality.record_up(data);
+        Some((session.uplink.clone(), session.remote))
     }
 
-    async fn detect_game_traffic(&self, client_addr: SocketAddr, data: &[u8]) -> Option<SupportedGame> {
-        let mut detector = self.game_detector.lock().await;
+    /// 在锁外建立新会话后放入会话表；migrating 是被替换会话的节点和游戏。
+    /// 同一客户端的另一个包已经先建好了当前节点的会话时沿用它，丢弃自己建的会话
+    async fn establish_udp_session(
+        node: &Node,
+        client_addr: SocketAddr,
+        migrating: Option<(String, Option<SupportedGame>)>,
+        data: &[u8],
+        context: &UdpContext,
+    ) -> Option<(UdpUplink, SocketAddr)> {
+        // 新会话用第一个数据包识别游戏
+        let classification = match &migrating {
+            Some(_) => None,
+            None => classifier::classify(PortProtocol::Udp, client_addr, None, data),
+        };
+        let game = match &migrating {
+            Some((_, game)) => game.clone(),
+            None => classification.as_ref().map(|classification| classification.game.clone()),
+        };
 
-        if let Ok(detected_games) = detector.detect_running_games() {
-            for (game, _) in detected_games {
-                let game_ports = game.get_game_ports();
+        let opened = match Self::open_udp_session(node, client_addr, game.clone(), context.clone()).await {
+            Err(e) if fd_usage::is_exhausted(&e) => {
+                let shed = {
+                    let mut sessions = profiling::lock(LockSite::UdpSessions, &context.sessions).await;
+                    Self::shed_idle_session(&mut sessions, Some(client_addr))
+                };
+                if shed {
+                    Self::open_udp_session(node, client_addr, game, context.clone()).await
+                } else {
+                    Err(e)
+                }
+            }
+            opened => opened,
+        };
+        let session = match opened {
+            Ok(session) => session,
+            Err(e) => {
+                error!("无法建立到 UDP 节点 {}:{} 的会话: {:#}", node.server, node.port, e);
+                return None;
+            }
+        };
+
+        let mut sessions = profiling::lock(LockSite::UdpSessions, &context.sessions).await;
+        if let Some(winner) = sessions.get(&client_addr).filter(|existing| existing.node == node.name) {
+            // 丢弃自己的会话会中止其反向转发任务
+            winner.activity.touch();
+            winner.client_activity.touch();
+            winner.quality.record_up(data);
+            return Some((winner.uplink.clone(), winner.remote));
+        }
 
-                if game_ports.contains(&client_addr.port()) {
-                    info!("检测到游戏 {} 的流量 (端口: {})", game.display_name(), client_addr.port());
-                    return Some(game);
+        match &migrating {
+            Some((old_node, _)) => info!("UDP 会话 {} 从节点 {} 迁移到 {}", client_addr, old_node, node.name),
+            None => {
+                if let Some(classification) = &classification {
+                    info!("识别到 UDP 流量属于 {}", classification.describe());
+                    events::record(
+                        EventKind::GameDetected,
+                        format!("{} 的 UDP 流量属于 {}", client_addr, classification.describe()),
+                    );
                 }
+                events::record(EventKind::SessionStart, format!("{} 经节点 {} 开始 UDP 会话", client_addr, node.name));
+            }
+        }
+        session.quality.record_up(data);
 
-                if data.len() > 8 {
-                    if self.is_game_packet(&game, data) {
-                        info!("检测到游戏 {} 的数据包特征", game.display_name());
-                        return Some(game);
+        let uplink = (session.uplink.clone(), session.remote);
+        // 替换旧会话时会中止其反向转发任务
+        sessions.insert(client_addr, session);
+        performance::set_game_sessions(sessions.values().map(|s| s.game.as_ref()));
+        Some(uplink)
+    }
+
+    async fn open_udp_session(
+        node: &Node,
+        client_addr: SocketAddr,
+        game: Option<SupportedGame>,
+        context: UdpContext,
+    ) -> Result<UdpSession> {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let target_addr = *dns::resolve(&node.server, node.port)
+            .await?
+            .first()
+            .context("没有解析结果")?;
+
+        if let Some(config) = obfs::for_node(&node.name) {
+            let (sender, mut receiver) = obfs::connect(node, &config).await?;
+            info!("UDP 会话 {} 经由节点 {} 的混淆通道", client_addr, node.name);
+
+            let quality = UdpQuality::new(game.as_ref());
+            let relay_quality = Arc::clone(&quality);
+            let relay = tokio::spawn(async move {
+                loop {
+                    match receiver.recv(&context.buffers).await {
+                        Ok(packet) => {
+                            capture::record(Transport::Udp, target_addr, client_addr, &packet);
+                            relay_quality.record_down(&packet);
+                            context.stats.add(0, packet.len() as u64);
+                            context.devices.add_traffic(client_addr.ip(), 0, packet.len() as u64);
+                            if Self::simulate_downlink(&context.socket, &packet, client_addr) {
+                                continue;
+                            }
+                            if let Err(e) = context.socket.send_to(&packet, client_addr).await {
+                                error!("UDP 反向转发失败: {}", e);
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("混淆通道接收错误: {}", e);
+                            break;
+                        }
                     }
                 }
-            }
+                Self::remove_udp_session(&context.sessions, client_addr, id).await;
+            });
+
+            return Ok(UdpSession {
+                id,
+                node: node.name.clone(),
+                remote: target_addr,
+                uplink: UdpUplink::Obfuscated(sender),
+                relay,
+                game,
+                activity: Activity::new(),
+                client_activity: Activity::new(),
+                quality,
+            });
         }
 
-        None
+        let socket = Arc::new(outbound::udp_socket(target_addr).await.context("无法创建 UDP socket")?);
+        dscp::mark(&*socket, target_addr, game.as_ref());
+        Ok(Self::direct_session(client_addr, node.name.clone(), target_addr, socket, game, context))
     }
 
-    fn is_game_packet(&self, game: &SupportedGame, data: &[u8]) -> bool {
-        match game {
-            SupportedGame::DontStarveTogether => {
-                data.starts_with(b"KU_") ||
-                data.windows(4).any(|w| w == &[0x04, 0x00, 0x00, 0x00]) ||
-                data.len() > 20 && data[0] == 0x04
-            },
-            SupportedGame::CounterStrike => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0xFF, 0xFF, 0xFF, 0xFF]) ||
-                (data.len() > 4 && data[0..4] == [0xFF, 0xFF, 0xFF, 0xFF])
-            },
-            SupportedGame::Dota2 => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0x56, 0x44, 0x50, 0x00]) ||
-                data.len() > 8 && data[4] == 0x56
-            },
-            SupportedGame::LeagueOfLegends => {
-                data.len() > 10 && (
-                    data.starts_with(&[0x00, 0x0C]) ||
-                    data.windows(4).any(|w| w == &[0x17, 0x00, 0x00, 0x00]) ||
-                    data[2] == 0x00 && data[3] == 0x01
-                )
-            },
-            SupportedGame::Valorant => {
-                data.len() > 12 && (
-                    data.starts_with(&[0x00, 0x10]) ||
-                    data.windows(4).any(|w| w == &[0x52, 0x69, 0x6F, 0x74]) || // "Riot"
-                    data[0] == 0x17 && data[1] == 0x03
-                )
-            },
-            SupportedGame::Minecraft => {
-                data.len() > 6 && (
-                    data.starts_with(&[0xFE, 0x01]) ||
-                    data.starts_with(&[0x00, 0x00]) ||
-                    (data[0] >= 0x00 && data[0] <= 0x7F && data[1] == 0x00)
-                )
-            },
-            SupportedGame::ApexLegends => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0x4F, 0x52, 0x49, 0x47]) || // "ORIG"
-                data.len() > 16 && data[8] == 0x52
-            },
-            SupportedGame::Overwatch => {
-                data.len() > 8 && (
-                    data.starts_with(&[0x42, 0x4E, 0x45, 0x54]) || // "BNET"
-                    data.windows(5).any(|w| w == &[0x01, 0x00, 0x00, 0x00, 0x02]) ||
-                    data[0] == 0x17 && data[4] == 0x01
-                )
-            },
+    /// 直接连接节点的 UDP 会话，启动反向转发任务
+    fn direct_session(
+        client_addr: SocketAddr,
+        node: String,
+        target_addr: SocketAddr,
+        socket: Arc<UdpSocket>,
+        game: Option<SupportedGame>,
+        context: UdpContext,
+    ) -> UdpSession {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let target_sock = Arc::clone(&socket);
+        let quality = UdpQuality::new(game.as_ref());
+        let relay_quality = Arc::clone(&quality);
+        let relay = tokio::spawn(async move {
+            loop {
+                match udp_batch::recv_batch(&target_sock, &context.buffers).await {
+                    Ok(packets) => {
+                        let packets: Vec<PooledBuffer> = packets.into_iter().map(|(buf, _)| buf).collect();
+                        for packet in &packets {
+                            capture::record(Transport::Udp, target_addr, client_addr, packet);
+                            relay_quality.record_down(packet);
+                        }
+                        let size: usize = packets.iter().map(|p| p.len()).sum();
+                        context.stats.add(0, size as u64);
+                        context.devices.add_traffic(client_addr.ip(), 0, size as u64);
+                        if simulate::active() {
+                            for packet in &packets {
+                                Self::simulate_downlink(&context.socket, packet, client_addr);
+                            }
+                            continue;
+                        }
+                        if let Err(e) = udp_batch::send_batch(&context.socket, &packets, client_addr).await {
+                            error!("UDP 反向转发失败: {}", e);
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("UDP 目标接收错误: {}", e);
+                        break;
+                    }
+                }
+            }
+            Self::remove_udp_session(&context.sessions, client_addr, id).await;
+        });
+
+        UdpSession {
+            id,
+            node,
+            remote: target_addr,
+            uplink: UdpUplink::Direct(socket),
+            relay,
+            game,
+            activity: Activity::new(),
+            client_activity: Activity::new(),
+            quality,
         }
     }
 
-    fn should_optimize_for_game(&self, game: &SupportedGame) -> bool {
-        game.should_optimize()
+    /// 模拟网络状况时由单独的任务延迟发送下行包，返回 true 表示已接管
+    fn simulate_downlink(socket: &Arc<UdpSocket>, packet: &[u8], client_addr: SocketAddr) -> bool {
+        let delay = match simulate::one_way() {
+            None => return false,
+            Some(Fate::Drop) => return true,
+            Some(Fate::Delay(delay)) => delay,
+        };
+        let socket = Arc::clone(socket);
+        let packet = packet.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(e) = socket.send_to(&packet, client_addr).await {
+                debug!("模拟延迟后反向转发失败: {}", e);
+            }
+        });
+        true
     }
 
-    fn get_game_specific_timeout(&self, game: &SupportedGame) -> Duration {
-        match game {
-            SupportedGame::DontStarveTogether => Duration::from_millis(50),
-            SupportedGame::CounterStrike | SupportedGame::Dota2 | SupportedGame::Valorant => {
-                Duration::from_millis(20)
-            },
-            SupportedGame::LeagueOfLegends => Duration::from_millis(30),
-            SupportedGame::Minecraft => Duration::from_millis(100),
-            SupportedGame::ApexLegends | SupportedGame::Overwatch => Duration::from_millis(25),
+    /// 释放最久没有发送数据的 UDP 会话（不包括 keep），返回是否释放了会话
+    fn shed_idle_session(sessions: &mut HashMap<SocketAddr, UdpSession>, keep: Option<SocketAddr>) -> bool {
+        let Some(idlest) = sessions
+            .iter()
+            .filter(|(client, _)| Some(**client) != keep)
+            .max_by_key(|(_, session)| session.activity.idle())
+            .map(|(client, _)| *client)
+        else {
+            return false;
+        };
+        if let Some(session) = sessions.remove(&idlest) {
+            warn!(
+                "文件描述符接近耗尽，释放空闲 {} 秒的 UDP 会话 {}",
+                session.activity.idle().as_secs(),
+                idlest
+            );
+            fd_usage::record_shed();
         }
+        true
     }
 
-    fn is_game_packet_static(game: &SupportedGame, data: &[u8]) -> bool {
-        match game {
-            SupportedGame::DontStarveTogether => {
-                data.starts_with(b"KU_") ||
-                data.windows(4).any(|w| w == &[0x04, 0x00, 0x00, 0x00]) ||
-                data.len() > 20 && data[0] == 0x04
-            },
-            SupportedGame::CounterStrike => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0xFF, 0xFF, 0xFF, 0xFF]) ||
-                (data.len() > 4 && data[0..4] == [0xFF, 0xFF, 0xFF, 0xFF])
-            },
-            SupportedGame::Dota2 => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0x56, 0x44, 0x50, 0x00]) ||
-                data.len() > 8 && data[4] == 0x56
-            },
-            SupportedGame::LeagueOfLegends => {
-                data.len() > 10 && (
-                    data.starts_with(&[0x00, 0x0C]) ||
-                    data.windows(4).any(|w| w == &[0x17, 0x00, 0x00, 0x00]) ||
-                    data[2] == 0x00 && data[3] == 0x01
-                )
-            },
-            SupportedGame::Valorant => {
-                data.len() > 12 && (
-                    data.starts_with(&[0x00, 0x10]) ||
-                    data.windows(4).any(|w| w == &[0x52, 0x69, 0x6F, 0x74]) || // "Riot"
-                    data[0] == 0x17 && data[1] == 0x03
-                )
-            },
-            SupportedGame::Minecraft => {
-                data.len() > 6 && (
-                    data.starts_with(&[0xFE, 0x01]) ||
-                    data.starts_with(&[0x00, 0x00]) ||
-                    (data[0] >= 0x00 && data[0] <= 0x7F && data[1] == 0x00)
-                )
-            },
-            SupportedGame::ApexLegends => {
-                data.starts_with(b"Source Engine Query") ||
-                data.windows(4).any(|w| w == &[0x4F, 0x52, 0x49, 0x47]) || // "ORIG"
-                data.len() > 16 && data[8] == 0x52
-            },
-            SupportedGame::Overwatch => {
-                data.len() > 8 && (
-                    data.starts_with(&[0x42, 0x4E, 0x45, 0x54]) || // "BNET"
-                    data.windows(5).any(|w| w == &[0x01, 0x00, 0x00, 0x00, 0x02]) ||
-                    data[0] == 0x17 && data[4] == 0x01
-                )
-            },
+    /// 清理会话，会话可能已被迁移到新节点，只删除自己
+    async fn remove_udp_session(sessions: &UdpSessions, client_addr: SocketAddr, id: u64) {
+        let mut sessions = profiling::lock(LockSite::UdpSessions, sessions).await;
+        if sessions.get(&client_addr).is_some_and(|s| s.id == id) {
+            if let Some(session) = sessions.remove(&client_addr) {
+                events::record(EventKind::SessionEnd, format!("{} 在节点 {} 上的 UDP 会话结束", client_addr, session.node));
+            }
+            performance::set_game_sessions(sessions.values().map(|s| s.game.as_ref()));
         }
     }
 
-    async fn check_node_health(&self, node: &Node) -> bool {
-        info!("检查节点健康状态: {}", node.name);
+    pub fn get_proxy_port(&self) -> u16 {
+        self.port
+    }
 
-        match tokio::time::timeout(
-            Duration::from_secs(5),
-            TcpStream::connect(format!("{}:{}", node.server, node.port))
-        ).await {
-            Ok(Ok(_)) => {
-                info!("节点 {} 健康检查通过", node.name);
-                true
-            }
-            Ok(Err(e)) => {
-                warn!("节点 {} 连接失败: {}", node.name, e);
-                false
-            }
-            Err(_) => {
-                warn!("节点 {} 健康检查超时", node.name);
-                false
-            }
-        }
+    pub async fn current_node(&self) -> Option<Node> {
+        self.current_node.read().await.clone()
     }
 
-    async fn record_node_failure(&self, node_name: &str) {
-        let mut failure_count = self.node_failure_count.write().await;
-        let count = failure_count.entry(node_name.to_string()).or_insert(0);
-        *count += 1;
-        warn!("节点 {} 故障计数: {}", node_name, count);
+    pub async fn backup_nodes(&self) -> Vec<Node> {
+        self.backup_nodes.read().await.clone()
     }
 
-    async fn get_node_failure_count(&self, node_name: &str) -> u32 {
-        let failure_count = self.node_failure_count.read().await;
-        failure_count.get(node_name).copied().unwrap_or(0)
+    /// 距离可以再次自动切换节点的剩余时间
+    pub async fn switch_cooldown(&self) -> Option<Duration> {
+        self.failover.lock().await.cooldown_remaining()
     }
 
-    async fn reset_node_failure_count(&self, node_name: &str) {
-        let mut failure_count = self.node_failure_count.write().await;
-        failure_count.insert(node_name.to_string(), 0);
-        info!("重置节点 {} 故障计数", node_name);
+    pub async fn current_node_name(&self) -> Option<String> {
+        self.current_node.read().await.as_ref().map(|node| node.name.clone())
     }
 
-    async fn try_switch_to_backup_node(&self) -> Result<bool> {
-        info!("尝试切换到备用节点...");
+    pub async fn preferred_node_name(&self) -> Option<String> {
+        self.failover.lock().await.preferred().map(|node| node.name.clone())
+    }
 
-        let backup_nodes = {
-            let nodes = self.backup_nodes.read().await;
-            nodes.clone()
+    /// 当前节点最近一次健康检查的结果
+    pub async fn current_check(&self) -> Option<CheckRecord> {
+        let name = self.current_node_name().await?;
+        self.failover.lock().await.last_check(&name).cloned()
+    }
+
+    /// 当前节点故障后的切换进度，节点健康时为 None
+    pub async fn failover_status(&self) -> Option<FailoverStatus> {
+        self.failover.lock().await.status()
+    }
+
+    /// 各 UDP 会话的包数和估计的丢包、乱序
+    pub async fn udp_quality(&self) -> Vec<SessionQuality> {
+        let sessions = self.udp_sessions.lock().await;
+        sessions
+            .iter()
+            .map(|(client, session)| SessionQuality {
+                client: client.to_string(),
+                node: session.node.clone(),
+                game: session.game.clone(),
+                up: session.quality.up(),
+                down: session.quality.down(),
+            })
+            .collect()
+    }
+
+    /// 向空闲超过所属游戏保活间隔的 UDP 会话发送一个空数据包，保持节点和 NAT 上的映射，
+    /// 没有识别出游戏的会话按 running_game 处理，返回发送的数量
+    pub async fn send_udp_keepalives(&self, config: &UdpKeepaliveConfig, running_game: Option<&SupportedGame>) -> usize {
+        let due: Vec<(UdpUplink, Arc<Activity>)> = {
+            let sessions = self.udp_sessions.lock().await;
+            sessions
+                .values()
+                .filter(|session| {
+                    config
+                        .interval_for(session.game.as_ref().or(running_game))
+                        .is_some_and(|interval| session.activity.idle() >= interval)
+                })
+                .map(|session| (session.uplink.clone(), Arc::clone(&session.activity)))
+                .collect()
         };
 
-        if backup_nodes.is_empty() {
-            warn!("没有可用的备用节点");
-            return Ok(false);
+        let mut sent = 0;
+        for (uplink, activity) in due {
+            // 混淆通道中的空帧同样是保活帧
+            let ok = match &uplink {
+                UdpUplink::Direct(socket) => socket.send(&[]).await.is_ok(),
+                UdpUplink::Obfuscated(sender) => sender.send(&[]),
+            };
+            if ok {
+                activity.touch();
+                sent += 1;
+            }
         }
+        sent
+    }
 
-        // 按延迟排序，选择最优节点
-        let mut available_nodes = Vec::new();
-        for node in backup_nodes {
-            if self.get_node_failure_count(&node.name).await < 3 {
-                if self.check_node_health(&node).await {
-                    available_nodes.push(node);
-                }
+    /// 关闭客户端空闲超过所属游戏空闲超时的 UDP 会话，保活包不算作活动；
+    /// 没有识别出游戏的会话按 running_game 处理，返回关闭的数量
+    pub async fn expire_idle_udp_sessions(&self, running_game: Option<&SupportedGame>) -> usize {
+        let timeouts = timeouts::current();
+        let mut sessions = self.udp_sessions.lock().await;
+        let expired: Vec<SocketAddr> = sessions
+            .iter()
+            .filter(|(_, session)| {
+                timeouts
+                    .udp_idle(session.game.as_ref().or(running_game))
+                    .is_some_and(|idle| session.client_activity.idle() >= idle)
+            })
+            .map(|(client, _)| *client)
+            .collect();
+        for client in &expired {
+            if let Some(session) = sessions.remove(client) {
+                info!("UDP 会话 {} 空闲 {} 秒，已关闭", client, session.client_activity.idle().as_secs());
+                events::record(EventKind::SessionEnd, format!("{} 在节点 {} 上的 UDP 会话空闲超时", client, session.node));
             }
         }
+        if !expired.is_empty() {
+            performance::set_game_sessions(sessions.values().map(|s| s.game.as_ref()));
+        }
+        expired.len()
+    }
+
+    pub async fn udp_session_count(&self) -> usize {
+        self.udp_sessions.lock().await.len()
+    }
+
+    /// 各个表的条目数，用于 cf perf 观察是否有表在不断增长
+    pub async fn table_sizes(&self) -> Vec<(String, usize)> {
+        let (_, _, tcp_connections) = self.stats.snapshot();
+        vec![
+            ("UDP 会话".to_string(), self.udp_session_count().await),
+            ("TCP 连接".to_string(), tcp_connections as usize),
+            ("粘性路由".to_string(), self.affinity.entry_count()),
+            ("设备".to_string(), self.devices.device_count()),
+            ("识别到的域名".to_string(), sniff::host_count()),
+            ("空闲 UDP 缓冲区".to_string(), self.udp_buffers.idle_buffers()),
+            ("后台任务".to_string(), self.tasks.running()),
+        ]
+    }
+
+    /// 是否有游戏正在进行：检测到游戏进程或存在活动的 UDP 会话
+    pub async fn game_session_active(&self) -> bool {
+        if self.udp_session_count().await > 0 {
+            return true;
+        }
+        // 路由器上的游戏运行在局域网设备中，本机检测不到进程
+        if crate::profile::router() {
+            return false;
+        }
+        let mut detector = self.game_detector.lock().await;
+        detector
+            .detect_running_games()
+            .map(|games| !games.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// 本机正在运行的游戏，路由器上检测不到
+    pub async fn running_game(&self) -> Option<SupportedGame> {
+        Self::detect_game(&self.game_detector).await
+    }
 
-        if available_nodes.is_empty() {
-            warn!("所有备用节点都不可用");
-            return Ok(false);
+    async fn detect_game(game_detector: &Mutex<GameDetector>) -> Option<SupportedGame> {
+        if crate::profile::router() {
+            return None;
         }
+        let mut detector = game_detector.lock().await;
+        detector
+            .detect_running_games()
+            .ok()
+            .and_then(|games| games.into_iter().next())
+            .map(|(game, _)| game)
+    }
 
-        // 选择第一个可用节点（已按延迟排序）
-        let best_node = available_nodes.into_iter().next().unwrap();
-        info!("切换到备用节点: {}", best_node.name);
+    pub fn traffic(&self) -> &TrafficStats {
+        &self.stats
+    }
 
-        self.set_node(best_node).await;
-        Ok(true)
+    pub fn devices(&self) -> &DeviceTable {
+        &self.devices
     }
 
-    async fn refresh_backup_nodes(&self) -> Result<()> {
+    pub async fn refresh_backup_nodes(&self) -> Result<()> {
         let subscription_url = {
             let url = self.subscription_url.read().await;
             url.clone()
@@ -598,12 +1327,14 @@ impl ProxyServer {
                                 warn!("节点延迟测试失败: {}", e);
                             }
 
-                            // 过滤可用节点（延迟 < 1000ms）
+                            // 过滤延迟低于上限的可用节点，上限按正在玩的游戏确定
+                            let game = self.running_game().await;
                             let available_nodes: Vec<Node> = nodes
                                 .into_iter()
-                                .filter(|n| n.latency.unwrap_or(u32::MAX) < 1000)
+                                .filter(|n| n.is_supported() && timeouts::current().usable_for(n.latency, game.as_ref()))
                                 .collect();
 
+                            events::record(EventKind::SubscriptionRefresh, format!("刷新订阅，{} 个可用节点", available_nodes.len()));
                             self.set_backup_nodes(available_nodes).await;
                             info!("备用节点列表已刷新");
                         }
@@ -613,6 +1344,7 @@ impl ProxyServer {
                     }
                 }
                 Err(e) => {
+                    events::record(EventKind::SubscriptionRefresh, format!("刷新订阅失败: {}", e));
                     error!("获取订阅内容失败: {}", e);
                 }
             }
@@ -621,78 +1353,199 @@ impl ProxyServer {
         Ok(())
     }
 
-    async fn start_health_monitor_task(
-        current_node: Arc<RwLock<Option<Node>>>,
-        is_running: Arc<RwLock<bool>>,
-        failure_count: Arc<RwLock<HashMap<String, u32>>>,
-        backup_nodes: Arc<RwLock<Vec<Node>>>,
-        subscription_url: Arc<RwLock<Option<String>>>,
+    /// 检查当前节点，必要时切换到备用节点或切回首选节点
+    async fn run_health_check(
+        current_node: &RwLock<Option<Node>>,
+        failure_count: &RwLock<HashMap<String, u32>>,
+        backup_nodes: &RwLock<Vec<Node>>,
+        failover: &Mutex<Failover>,
+        affinity: &AffinityCache,
     ) {
+        let Some(node) = current_node.read().await.clone() else {
+            return;
+        };
 
-        tokio::spawn(async move {
-            let mut check_interval = tokio::time::interval(Duration::from_secs(30));
-            let mut refresh_interval = tokio::time::interval(Duration::from_secs(300)); // 5分钟刷新一次
+        let result = health::probe(&node, timeouts::current().health_probe()).await;
+        failover.lock().await.record_check(&node.name, &result);
+        match result {
+            Ok(_) => {
+                // 节点健康，重置故障计数
+                failure_count.write().await.insert(node.name.clone(), 0);
+                failover.lock().await.record_success(&node.name);
+                Self::try_fail_back(&node, current_node, failover).await;
+            }
+            Err(e) => {
+                // 节点故障，增加故障计数
+                let current_count = {
+                    let mut count = failure_count.write().await;
+                    let current_count = count.entry(node.name.clone()).or_insert(0);
+                    *current_count += 1;
+                    *current_count
+                };
+                let backoff = {
+                    let mut failover = failover.lock().await;
+                    failover.set_phase(FailoverPhase::Degraded {
+                        node: node.name.clone(),
+                        failures: current_count,
+                    });
+                    failover.record_failure(&node.name)
+                };
+                events::record(
+                    EventKind::HealthFailure,
+                    format!("{} 健康检查失败 ({})，连续 {} 次", node.name, e, current_count),
+                );
+                warn!("节点 {} 健康检查失败 ({})，故障次数: {}，{} 秒内不再选用",
+                    node.name, e, current_count, backoff.as_secs());
+
+                if current_count < FAILURE_THRESHOLD {
+                    return;
+                }
 
-            loop {
-                if !*is_running.read().await {
-                    break;
+                if let Some(remaining) = failover.lock().await.cooldown_remaining() {
+                    warn!("节点 {} 连续故障 {} 次，但距上次切换不足冷却时间，{} 秒后再尝试",
+                        node.name, current_count, remaining.as_secs());
+                    return;
+                }
+
+                error!("节点 {} 连续故障 {} 次，尝试切换备用节点", node.name, current_count);
+                affinity.evict_node(&node.name);
+
+                // 跳过退避期内的节点，避免在故障节点之间来回切换
+                let candidates: Vec<Node> = {
+                    let mut failover = failover.lock().await;
+                    let candidates: Vec<Node> = backup_nodes
+                        .read()
+                        .await
+                        .iter()
+                        .filter(|n| n.name != node.name && !failover.in_backoff(&n.name))
+                        .cloned()
+                        .collect();
+                    failover.set_phase(FailoverPhase::Probing {
+                        node: node.name.clone(),
+                        candidates: candidates.len(),
+                    });
+                    candidates
+                };
+
+                let chosen = Self::probe_backups(candidates, failover).await;
+                // 探测得到了最新的延迟，重新排列备用节点
+                {
+                    let mut backups = backup_nodes.write().await;
+                    *backups = failover.lock().await.rank_backups(std::mem::take(&mut *backups));
+                }
+                let Some(backup_node) = chosen else {
+                    warn!("没有可用的备用节点");
+                    failover.lock().await.set_phase(FailoverPhase::NoBackup { node: node.name.clone() });
+                    return;
+                };
+                info!("切换到备用节点: {}", backup_node.name);
+                *current_node.write().await = Some(backup_node.clone());
+                failure_count.write().await.insert(backup_node.name.clone(), 0);
+                failover.lock().await.record_switch(Some(&node.name), &backup_node.name, SwitchReason::Failure);
+                notification::send(
+                    "ClashFun 已切换节点",
+                    &format!("{} 不可用，已切换到 {}", node.name, backup_node.name),
+                );
+            }
+        }
+    }
+
+    /// 同时探测备用节点，返回最先通过的节点，即延迟最低的健康节点
+    ///
+    /// 逐个探测时每个不可用的节点都要等待超时，游戏会卡住很久。找到可用节点后其余探测直接取消。
+    async fn probe_backups(candidates: Vec<Node>, failover: &Mutex<Failover>) -> Option<Node> {
+        let permits = Arc::new(Semaphore::new(BACKUP_PROBE_CONCURRENCY));
+        let timeout = timeouts::current().backup_probe();
+        let mut probes = JoinSet::new();
+        for node in candidates {
+            let permits = Arc::clone(&permits);
+            probes.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let result = health::probe(&node, timeout).await;
+                (node, result)
+            });
+        }
+
+        while let Some(joined) = probes.join_next().await {
+            let Ok((node, result)) = joined else {
+                continue;
+            };
+            let mut failover = failover.lock().await;
+            failover.record_check(&node.name, &result);
+            match result {
+                Ok(probe) => {
+                    debug!("备用节点 {} 探测通过，延迟 {} ms", node.name, probe.latency.as_millis());
+                    return Some(node);
                 }
+                Err(_) => {
+                    failover.record_failure(&node.name);
+                }
+            }
+        }
+        None
+    }
+
+    /// 当前运行在备用节点上时，首选节点连续恢复若干次后切回
+    async fn try_fail_back(current: &Node, current_node: &RwLock<Option<Node>>, failover: &Mutex<Failover>) {
+        let preferred = {
+            let failover = failover.lock().await;
+            match failover.preferred() {
+                Some(preferred) if failover.policy.failback
+                    && preferred.name != current.name
+                    && !failover.in_backoff(&preferred.name) => preferred.clone(),
+                _ => return,
+            }
+        };
 
+        let result = health::probe(&preferred, timeouts::current().health_probe()).await;
+        let mut failover = failover.lock().await;
+        failover.record_check(&preferred.name, &result);
+        if result.is_err() {
+            failover.record_failure(&preferred.name);
+            return;
+        }
+
+        if !failover.preferred_recovered() {
+            info!("首选节点 {} 健康检查通过，等待连续通过后切回", preferred.name);
+            return;
+        }
+
+        info!("首选节点 {} 已恢复，从 {} 切回", preferred.name, current.name);
+        *current_node.write().await = Some(preferred.clone());
+        failover.record_switch(Some(&current.name), &preferred.name, SwitchReason::Failback);
+        notification::send(
+            "ClashFun 已切回首选节点",
+            &format!("{} 已恢复，已从 {} 切回", preferred.name, current.name),
+        );
+    }
+
+    async fn start_health_monitor_task(&self) {
+        let current_node = Arc::clone(&self.current_node);
+        let mut running = self.running.subscribe();
+        let failure_count = Arc::clone(&self.node_failure_count);
+        let backup_nodes = Arc::clone(&self.backup_nodes);
+        let subscription_url = Arc::clone(&self.subscription_url);
+        let failover = Arc::clone(&self.failover);
+        let affinity = Arc::clone(&self.affinity);
+        let game_detector = Arc::clone(&self.game_detector);
+
+        let check_period = failover.lock().await.policy.check_interval;
+        self.tasks.spawn("健康监控", async move {
+            let mut check_interval = tokio::time::interval(check_period);
+            let mut refresh_interval = tokio::time::interval(Duration::from_secs(300)); // 5分钟刷新一次
+
+            loop {
                 tokio::select! {
+                    _ = Self::wait_for_stop(&mut running) => break,
                     _ = check_interval.tick() => {
-                        let current = {
-                            let node_guard = current_node.read().await;
-                            node_guard.clone()
-                        };
-
-                        if let Some(node) = current {
-                            // 健康检查当前节点
-                            let health_check = tokio::time::timeout(
-                                Duration::from_secs(5),
-                                TcpStream::connect(format!("{}:{}", node.server, node.port))
-                            ).await;
-
-                            match health_check {
-                                Ok(Ok(_)) => {
-                                    // 节点健康，重置故障计数
-                                    let mut count = failure_count.write().await;
-                                    count.insert(node.name.clone(), 0);
-                                }
-                                Ok(Err(_)) | Err(_) => {
-                                    // 节点故障，增加故障计数
-                                    let mut count = failure_count.write().await;
-                                    let current_count = count.entry(node.name.clone()).or_insert(0);
-                                    *current_count += 1;
-
-                                    warn!("节点 {} 健康检查失败，故障次数: {}", node.name, current_count);
-
-                                    // 如果故障次数达到阈值，尝试切换备用节点
-                                    if *current_count >= 3 {
-                                        error!("节点 {} 连续故障 {} 次，尝试切换备用节点", node.name, current_count);
-
-                                        let backup = backup_nodes.read().await;
-                                        for backup_node in backup.iter() {
-                                            let backup_health = tokio::time::timeout(
-                                                Duration::from_secs(3),
-                                                TcpStream::connect(format!("{}:{}", backup_node.server, backup_node.port))
-                                            ).await;
-
-                                            if backup_health.is_ok() && backup_health.unwrap().is_ok() {
-                                                info!("切换到备用节点: {}", backup_node.name);
-                                                let mut current_guard = current_node.write().await;
-                                                *current_guard = Some(backup_node.clone());
-
-                                                // 重置新节点的故障计数
-                                                count.insert(backup_node.name.clone(), 0);
-                                                break;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                        Self::run_health_check(&current_node, &failure_count, &backup_nodes, &failover, &affinity).await;
                     }
                     _ = refresh_interval.tick() => {
+                        // 低延迟模式或竞技游戏对局中不测试所有节点，等下一轮再刷新
+                        if performance::defer_background() {
+                            debug!("游戏进行中，推迟刷新备用节点列表");
+                            continue;
+                        }
                         // 定期刷新备用节点列表
                         if let Some(url) = subscription_url.read().await.clone() {
                             info!("定期刷新备用节点列表...");
@@ -702,14 +1555,15 @@ impl ProxyServer {
                                 if let Ok(mut nodes) = sub_manager.parse_nodes(&clash_config) {
                                     let _ = sub_manager.test_all_nodes(&mut nodes).await;
 
+                                    let game = Self::detect_game(&game_detector).await;
                                     let available_nodes: Vec<Node> = nodes
                                         .into_iter()
-                                        .filter(|n| n.latency.unwrap_or(u32::MAX) < 1000)
+                                        .filter(|n| n.is_supported() && timeouts::current().usable_for(n.latency, game.as_ref()))
                                         .collect();
 
-                                    let mut backup = backup_nodes.write().await;
-                                    *backup = available_nodes;
-                                    info!("备用节点列表已刷新，共 {} 个可用节点", backup.len());
+                                    let count = Self::install_backups(&backup_nodes, &failover, available_nodes).await;
+                                    info!("备用节点列表已刷新，保留 {} 个备用节点", count);
+                                    events::record(EventKind::SubscriptionRefresh, format!("定期刷新订阅，保留 {} 个备用节点", count));
                                 }
                             }
                         }
@@ -722,4 +1576,61 @@ impl ProxyServer {
 
         info!("节点健康监控已启动");
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::insights::RECORDED_SWITCHES;
+
+    /// 本地一直保持连接的端口，liveness 探测能通过
+    async fn live_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        port
+    }
+
+    /// 已关闭的端口，探测立即失败
+    async fn dead_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port()
+    }
+
+    fn recorded(reason: SwitchReason) -> usize {
+        RECORDED_SWITCHES.with(|recorded| recorded.borrow().iter().filter(|r| **r == reason).count())
+    }
+
+    #[tokio::test]
+    async fn automatic_failover_and_failback_are_counted_in_insights() {
+        let preferred = Node::test("首选", "127.0.0.1", dead_port().await, "ss");
+        let backup = Node::test("备用", "127.0.0.1", live_port().await, "ss");
+        let current_node = RwLock::new(Some(preferred.clone()));
+        let failure_count = RwLock::new(HashMap::new());
+        let backup_nodes = RwLock::new(vec![backup.clone()]);
+        let failover = Mutex::new(Failover::default());
+        failover.lock().await.set_preferred(&preferred);
+        let affinity = AffinityCache::default();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            ProxyServer::run_health_check(&current_node, &failure_count, &backup_nodes, &failover, &affinity).await;
+        }
+        assert_eq!(current_node.read().await.as_ref().unwrap().name, "备用");
+        assert_eq!(recorded(SwitchReason::Failure), 1);
+
+        // 首选节点恢复，冷却结束后切回
+        let recovered = Node { port: live_port().await, ..preferred };
+        {
+            let mut failover = failover.lock().await;
+            failover.set_preferred(&recovered);
+            failover.policy.cooldown = Duration::ZERO;
+            failover.policy.failback_checks = 1;
+        }
+        ProxyServer::run_health_check(&current_node, &failure_count, &backup_nodes, &failover, &affinity).await;
+        assert_eq!(current_node.read().await.as_ref().unwrap().name, "首选");
+        assert_eq!(recorded(SwitchReason::Failback), 1);
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::classifier;
+use crate::config::Config;
+use crate::game_detect::{GameDetector, SupportedGame};
+
+/// 检测游戏并更新重定向规则的间隔
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 不重定向的目标：本机和局域网
+pub const DIRECT_RANGES: &[&str] = &["127.0.0.0/8", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16"];
+
+static TRANSPARENT: AtomicBool = AtomicBool::new(false);
+
+/// 透明重定向方式：不需要在游戏或系统中设置代理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectMode {
+    /// 不重定向，游戏需要自行设置代理
+    #[default]
+    Off,
+    /// macOS pf 的 rdr 规则，不需要创建 utun 设备
+    Pf,
+    /// Linux iptables/nftables REDIRECT，本机和局域网设备的 TCP/UDP 都会重定向
+    Redirect,
+    /// Linux TPROXY，TCP 不经过 NAT 并保留原始目标，UDP 仍使用 REDIRECT
+    Tproxy,
+}
+
+impl RedirectMode {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Off => "关闭",
+            Self::Pf => "pf (macOS)",
+            Self::Redirect => "REDIRECT (Linux)",
+            Self::Tproxy => "TPROXY (Linux)",
+        }
+    }
+}
+
+/// 需要重定向的目标：游戏端口和服务器 IP 段
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Targets {
+    pub ports: Vec<u16>,
+    pub ranges: Vec<String>,
+}
+
+impl Targets {
+    fn for_games(games: &[SupportedGame]) -> Self {
+        let mut targets = Self {
+            ports: games.iter().flat_map(classifier::game_ports).collect(),
+            ranges: games.iter().flat_map(classifier::game_ranges).collect(),
+        };
+        targets.ports.sort_unstable();
+        targets.ports.dedup();
+        targets.ranges.sort();
+        targets.ranges.dedup();
+        targets
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty() && self.ranges.is_empty()
+    }
+}
+
+/// 代理的监听 socket 是否需要 IP_TRANSPARENT (TPROXY 模式)
+pub fn transparent() -> bool {
+    TRANSPARENT.load(Ordering::Relaxed)
+}
+
+/// 运行中的重定向，drop 时移除规则
+pub struct RedirectGuard {
+    task: JoinHandle<()>,
+    cleanup: fn(),
+}
+
+impl RedirectGuard {
+    /// 交接给新实例时保留规则，由新实例继续维护
+    pub fn hand_over(self) {
+        self.task.abort();
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for RedirectGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+        (self.cleanup)();
+    }
+}
+
+/// 开始把游戏流量重定向到代理端口，无法启用时打印原因并返回 None
+pub fn start(mode: RedirectMode, proxy_port: u16, config: &Config) -> Option<RedirectGuard> {
+    match mode {
+        RedirectMode::Off => None,
+        RedirectMode::Pf => start_pf(proxy_port, config),
+        RedirectMode::Redirect | RedirectMode::Tproxy => start_netfilter(mode, proxy_port, config),
+    }
+}
+
+/// 定期检测本机游戏，连同配置中指定的游戏一起计算目标，变化时用 apply 替换规则
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn spawn_monitor<F>(configured: Vec<SupportedGame>, apply: F, cleanup: fn()) -> RedirectGuard
+where
+    F: Fn(&Targets) -> Result<()> + Clone + Send + 'static,
+{
+    let task = tokio::spawn(async move {
+        let mut detector = GameDetector::new();
+        let mut applied: Option<Targets> = None;
+        let mut interval = tokio::time::interval(SCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut games = configured.clone();
+            // 路由器上只重定向配置中指定的游戏，不扫描本机进程
+            let detected = if crate::profile::router() { Ok(Vec::new()) } else { detector.detect_running_games() };
+            match detected {
+                Ok(detected) => {
+                    for (game, _) in detected {
+                        if !games.contains(&game) {
+                            games.push(game);
+                        }
+                    }
+                }
+                Err(e) => warn!("游戏检测失败: {}", e),
+            }
+            let targets = Targets::for_games(&games);
+            if applied.as_ref() == Some(&targets) {
+                continue;
+            }
+
+            let (apply, rules) = (apply.clone(), targets.clone());
+            match tokio::task::spawn_blocking(move || apply(&rules)).await {
+                Ok(Ok(())) if targets.is_empty() => {
+                    if applied.is_some() {
+                        println!("🔀 游戏已退出，已移除重定向规则");
+                    }
+                }
+                Ok(Ok(())) => {
+                    let names: Vec<&str> = games.iter().map(|game| game.display_name()).collect();
+                    println!(
+                        "🔀 已将 {} 的流量重定向到代理 (端口 {:?}，IP 段 {} 个)",
+                        names.join("、"),
+                        targets.ports,
+                        targets.ranges.len()
+                    );
+                }
+                Ok(Err(e)) => {
+                    warn!("更新重定向规则失败: {:#}", e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("更新重定向规则失败: {}", e);
+                    continue;
+                }
+            }
+            applied = Some(targets);
+        }
+    });
+    RedirectGuard { task, cleanup }
+}
+
+#[cfg(target_os = "macos")]
+fn start_pf(proxy_port: u16, config: &Config) -> Option<RedirectGuard> {
+    use crate::pf;
+
+    if unsafe { libc::geteuid() } != 0 {
+        println!("❌ pf 重定向需要管理员权限，请使用 sudo cf start 运行");
+        return None;
+    }
+    // 清理上次异常退出时留下的规则
+    pf::flush();
+    println!("🔀 透明重定向: pf 锚点 {}，检测到游戏后自动重定向其流量", pf::ANCHOR);
+    Some(spawn_monitor(
+        config.redirect_games.clone(),
+        move |targets| pf::apply(targets, proxy_port),
+        pf::cleanup,
+    ))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn start_pf(_proxy_port: u16, _config: &Config) -> Option<RedirectGuard> {
+    println!("❌ pf 重定向仅支持 macOS");
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn start_netfilter(mode: RedirectMode, proxy_port: u16, config: &Config) -> Option<RedirectGuard> {
+    use crate::netfilter::{self, Backend, Method};
+
+    if unsafe { libc::geteuid() } != 0 {
+        println!("❌ {} 需要 root 权限，请使用 sudo cf start 运行", mode.display_name());
+        return None;
+    }
+    let Some(backend) = Backend::detect() else {
+        println!("❌ 找不到 nft 或 iptables 命令，无法设置重定向规则");
+        return None;
+    };
+    // 清理上次异常退出时留下的规则
+    netfilter::cleanup();
+
+    let method = if mode == RedirectMode::Tproxy { Method::Tproxy } else { Method::Redirect };
+    TRANSPARENT.store(method == Method::Tproxy, Ordering::Relaxed);
+    println!("🔀 透明重定向: {}，使用 {}", mode.display_name(), backend.name());
+    if !config.allow_lan {
+        println!("💡 局域网设备（游戏机等）的流量需要开启 allow_lan 才能转发到代理");
+    }
+    if let Some(cgroup) = &config.redirect_cgroup {
+        println!("🔀 cgroup {} 中进程的全部流量都会经过代理", cgroup);
+    }
+
+    let cgroup = config.redirect_cgroup.clone();
+    Some(spawn_monitor(
+        config.redirect_games.clone(),
+        move |targets| netfilter::apply(backend, method, targets, cgroup.as_deref(), proxy_port),
+        netfilter::cleanup,
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_netfilter(mode: RedirectMode, _proxy_port: u16, _config: &Config) -> Option<RedirectGuard> {
+    println!("❌ {} 仅支持 Linux", mode.display_name());
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_targets_of_games() {
+        assert!(Targets::for_games(&[]).is_empty());
+        let targets = Targets::for_games(&[SupportedGame::CounterStrike, SupportedGame::Dota2]);
+        assert_eq!(targets.ports, [27005, 27015, 27020]);
+        // 两个游戏共用 Valve 的 IP 段，合并后不重复
+        assert!(targets.ranges.contains(&"155.133.224.0/19".to_string()));
+        assert_eq!(targets.ranges, Targets::for_games(&[SupportedGame::CounterStrike]).ranges);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::subscription::Node;
+
+/// 无法识别地区的节点归入此分组
+pub const UNKNOWN_REGION: &str = "其他";
+
+/// 地区名称及节点名中常见的写法，英文缩写只按完整单词匹配
+const REGIONS: &[(&str, &[&str], &[&str])] = &[
+    ("香港", &["香港", "🇭🇰", "hong kong", "hongkong"], &["hk", "hkg"]),
+    ("台湾", &["台湾", "台灣", "🇹🇼", "taiwan"], &["tw", "twn"]),
+    ("日本", &["日本", "东京", "大阪", "🇯🇵", "japan", "tokyo", "osaka"], &["jp", "jpn"]),
+    ("韩国", &["韩国", "韓國", "首尔", "🇰🇷", "korea", "seoul"], &["kr", "kor"]),
+    ("新加坡", &["新加坡", "狮城", "🇸🇬", "singapore"], &["sg", "sgp"]),
+    ("美国", &["美国", "洛杉矶", "硅谷", "🇺🇸", "united states", "los angeles"], &["us", "usa"]),
+    ("英国", &["英国", "伦敦", "🇬🇧", "united kingdom", "london"], &["uk", "gb"]),
+    ("德国", &["德国", "法兰克福", "🇩🇪", "germany", "frankfurt"], &["de"]),
+    ("澳大利亚", &["澳大利亚", "澳洲", "悉尼", "🇦🇺", "australia", "sydney"], &["au"]),
+    ("俄罗斯", &["俄罗斯", "莫斯科", "🇷🇺", "russia", "moscow"], &["ru"]),
+];
+
+/// 从节点名称推断所在地区
+pub fn infer(name: &str) -> Option<&'static str> {
+    let lower = name.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    REGIONS
+        .iter()
+        .find(|(_, keywords, codes)| {
+            keywords.iter().any(|keyword| lower.contains(keyword))
+                || words.iter().any(|word| {
+                    // 兼容 "HK01"、"JP2" 这类编号写法
+                    let code = word.trim_end_matches(|c: char| c.is_ascii_digit());
+                    codes.contains(&code)
+                })
+        })
+        .map(|(region, _, _)| *region)
+}
+
+/// 地区对应的旗帜 emoji
+pub fn flag(region: &str) -> Option<&'static str> {
+    let (_, keywords, _) = REGIONS.iter().find(|(name, _, _)| *name == region)?;
+    keywords.iter().copied().find(|keyword| keyword.chars().all(is_regional_indicator))
+}
+
+/// 组成旗帜 emoji 的区域指示符号
+pub fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// 节点所属地区，无法识别时为 "其他"
+pub fn of(name: &str) -> &'static str {
+    infer(name).unwrap_or(UNKNOWN_REGION)
+}
+
+/// 节点名称里没有地区时，再看服务器域名，例如 hk1.example.com
+pub fn infer_node(node: &Node) -> Option<&'static str> {
+    infer(&node.name).or_else(|| infer(&node.server))
+}
+
+/// cf nodes 的分组方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Grouping {
+    /// 按地区分组
+    #[default]
+    Region,
+    /// 按协议分组
+    Protocol,
+    /// 不分组，按订阅中的顺序
+    None,
+}
+
+/// 一组节点，节点附带在订阅中的序号 (从 0 开始)
+pub struct NodeGroup<'a> {
+    pub name: String,
+    pub nodes: Vec<(usize, &'a Node)>,
+}
+
+impl NodeGroup<'_> {
+    /// 组内最低延迟，全部超时或未测试时为 None
+    pub fn best_latency(&self) -> Option<u32> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.is_supported())
+            .filter_map(|(_, node)| node.latency)
+            .filter(|latency| *latency < u32::MAX)
+            .min()
+    }
+
+    pub fn available(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.is_supported() && node.latency.is_some_and(|latency| latency < u32::MAX))
+            .count()
+    }
+}
+
+/// 分组后组按最低延迟排序，组内按延迟排序；无法识别的地区排在最后
+pub fn group(nodes: &[Node], grouping: Grouping) -> Vec<NodeGroup<'_>> {
+    let indexed = nodes.iter().enumerate();
+    if grouping == Grouping::None {
+        return vec![NodeGroup { name: String::new(), nodes: indexed.collect() }];
+    }
+
+    let mut groups: Vec<NodeGroup> = Vec::new();
+    for (index, node) in indexed {
+        let name = match grouping {
+            Grouping::Protocol => node.protocol.to_lowercase(),
+            _ => infer_node(node).unwrap_or(UNKNOWN_REGION).to_string(),
+        };
+        match groups.iter_mut().find(|group| group.name == name) {
+            Some(group) => group.nodes.push((index, node)),
+            None => groups.push(NodeGroup { name, nodes: vec![(index, node)] }),
+        }
+    }
+
+    for group in &mut groups {
+        group.nodes.sort_by_key(|(index, node)| (node.latency.unwrap_or(u32::MAX), *index));
+    }
+    groups.sort_by_key(|group| (group.name == UNKNOWN_REGION, group.best_latency().unwrap_or(u32::MAX)));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_region_from_node_names() {
+        assert_eq!(infer("🇭🇰 香港 IPLC 01"), Some("香港"));
+        assert_eq!(infer("JP2 Tokyo"), Some("日本"));
+        assert_eq!(infer("hk-bgp-03"), Some("香港"));
+        assert_eq!(infer("[SG] 新加坡 游戏专线"), Some("新加坡"));
+        assert_eq!(infer("United States | LA"), Some("美国"));
+        // 缩写不匹配单词内部
+        assert_eq!(infer("Business Plan"), None);
+        assert_eq!(of("剩余流量: 100G"), UNKNOWN_REGION);
+    }
+
+    fn node(name: &str, server: &str, protocol: &str, latency: Option<u32>) -> Node {
+        Node { latency, ..Node::test(name, server, 443, protocol) }
+    }
+
+    #[test]
+    fn groups_nodes_by_region_and_latency() {
+        let nodes = vec![
+            node("剩余流量: 100G", "example.com", "ss", None),
+            node("香港 01", "a.example.com", "ss", Some(80)),
+            node("节点 A", "jp1.example.com", "vmess", Some(40)),
+            node("香港 02", "b.example.com", "trojan", Some(30)),
+            node("香港 03", "c.example.com", "ss", Some(u32::MAX)),
+        ];
+
+        let groups = group(&nodes, Grouping::Region);
+        let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["香港", "日本", UNKNOWN_REGION]);
+        let hk: Vec<usize> = groups[0].nodes.iter().map(|(index, _)| *index).collect();
+        assert_eq!(hk, [3, 1, 4]);
+        assert_eq!(groups[0].best_latency(), Some(30));
+        assert_eq!(groups[0].available(), 2);
+
+        let groups = group(&nodes, Grouping::Protocol);
+        assert_eq!(groups.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(), ["trojan", "vmess", "ss"]);
+        assert_eq!(group(&nodes, Grouping::None)[0].nodes.len(), 5);
+    }
+}
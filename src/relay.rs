@@ -0,0 +1,454 @@
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+/// 非 Linux 平台和抓包时的转发缓冲区大小，比 tokio::io::copy 默认的 8KB 更大以减少系统调用
+const RELAY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 对端已发送数据时检查其是否断开的间隔
+const CLOSED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// 检查连接是否空闲的最短间隔
+const IDLE_CHECK_MIN: Duration = Duration::from_millis(100);
+/// 向流量统计报告已转发字节数的间隔，长时间的游戏连接在结束前也计入统计
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 转发结束的方式
+#[derive(Debug)]
+pub enum Ending {
+    /// 两个方向都读到了 EOF，双方都正常关闭
+    Closed,
+    /// 某个方向出错（例如连接被重置），另一个方向随之中止
+    Failed { uplink: bool, error: io::Error },
+    /// 两个方向都超过空闲超时没有数据，主动关闭
+    Idle(Duration),
+}
+
+/// 一条 TCP 连接的转发结果，出错时也包含出错前已转发的字节数
+#[derive(Debug)]
+pub struct Relayed {
+    pub sent: u64,
+    pub received: u64,
+    pub ending: Ending,
+}
+
+impl Relayed {
+    /// 出错的方向和原因，用于日志
+    pub fn error(&self) -> Option<String> {
+        match &self.ending {
+            Ending::Closed | Ending::Idle(_) => None,
+            Ending::Failed { uplink, error } => Some(format!("{}出错: {}", if *uplink { "上行" } else { "下行" }, error)),
+        }
+    }
+}
+
+/// 在客户端和节点之间双向转发 TCP 数据
+///
+/// 一方读到 EOF 时只关闭另一方的写方向（半关闭），另一个方向继续转发，直到双方都关闭；
+/// 任一方向出错时中止转发。Linux 上使用 splice(2) 经由管道在内核中搬运数据，
+/// 避免拷贝到用户态；其他平台使用大缓冲区的普通拷贝。idle 为空闲超时，为 None 时不限制。
+/// 转发期间定期把新转发的字节数交给 progress(上行, 下行)，结束前报告剩余部分。
+pub async fn relay_tcp<P>(client: TcpStream, target: TcpStream, idle: Option<Duration>, progress: P) -> Relayed
+where
+    P: Fn(u64, u64),
+{
+    let sent = AtomicU64::new(0);
+    let received = AtomicU64::new(0);
+
+    #[cfg(target_os = "linux")]
+    let ending = relay_both(
+        splice::splice_one_way(&client, &target, &sent),
+        splice::splice_one_way(&target, &client, &received),
+        [&sent, &received],
+        idle,
+        &progress,
+    )
+    .await;
+
+    #[cfg(not(target_os = "linux"))]
+    let ending = {
+        let (mut client_read, mut client_write) = client.into_split();
+        let (mut target_read, mut target_write) = target.into_split();
+        relay_both(
+            copy_one_way(&mut client_read, &mut target_write, &sent, |_| {}),
+            copy_one_way(&mut target_read, &mut client_write, &received, |_| {}),
+            [&sent, &received],
+            idle,
+            &progress,
+        )
+        .await
+    };
+
+    Relayed {
+        sent: sent.into_inner(),
+        received: received.into_inner(),
+        ending,
+    }
+}
+
+/// 转发时把经过的数据交给 observe(是否上行, 数据)，用于抓包；需要拷贝到用户态，比 relay_tcp 慢
+pub async fn relay_tcp_observed<F, P>(
+    client: TcpStream,
+    target: TcpStream,
+    idle: Option<Duration>,
+    observe: F,
+    progress: P,
+) -> Relayed
+where
+    F: Fn(bool, &[u8]),
+    P: Fn(u64, u64),
+{
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut target_read, mut target_write) = target.into_split();
+    let sent = AtomicU64::new(0);
+    let received = AtomicU64::new(0);
+    let observe = &observe;
+
+    let ending = relay_both(
+        copy_one_way(&mut client_read, &mut target_write, &sent, move |data| observe(true, data)),
+        copy_one_way(&mut target_read, &mut client_write, &received, move |data| observe(false, data)),
+        [&sent, &received],
+        idle,
+        &progress,
+    )
+    .await;
+
+    Relayed {
+        sent: sent.into_inner(),
+        received: received.into_inner(),
+        ending,
+    }
+}
+
+/// 等待两个方向都正常结束，任一方向出错时立即返回并丢弃另一个方向；
+/// 设置了空闲超时时，按已转发的字节数判断是否空闲，超时后丢弃两个方向。
+/// 每次检查时把新增的字节数报告给 progress，返回前报告剩余部分
+async fn relay_both<U, D, P>(
+    uplink: U,
+    downlink: D,
+    counters: [&AtomicU64; 2],
+    idle: Option<Duration>,
+    progress: &P,
+) -> Ending
+where
+    U: Future<Output = io::Result<()>>,
+    D: Future<Output = io::Result<()>>,
+    P: Fn(u64, u64),
+{
+    let mut reported = [0u64; 2];
+    let mut report = || {
+        let now = counters.map(|counter| counter.load(Ordering::Relaxed));
+        if now != reported {
+            progress(now[0] - reported[0], now[1] - reported[1]);
+            reported = now;
+        }
+    };
+
+    tokio::pin!(uplink, downlink);
+    let total = || counters.iter().map(|counter| counter.load(Ordering::Relaxed)).sum::<u64>();
+    let period = idle.map_or(PROGRESS_INTERVAL, |idle| (idle / 4).min(PROGRESS_INTERVAL));
+    let mut ticker = tokio::time::interval(period.max(IDLE_CHECK_MIN));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let (mut last_total, mut last_change) = (0, Instant::now());
+
+    let (mut uplink_done, mut downlink_done) = (false, false);
+    let ending = loop {
+        if uplink_done && downlink_done {
+            break Ending::Closed;
+        }
+        let (uplink, result) = tokio::select! {
+            result = &mut uplink, if !uplink_done => (true, result),
+            result = &mut downlink, if !downlink_done => (false, result),
+            _ = ticker.tick() => {
+                report();
+                let now_total = total();
+                if now_total != last_total {
+                    (last_total, last_change) = (now_total, Instant::now());
+                } else if let Some(idle) = idle.filter(|idle| last_change.elapsed() >= *idle) {
+                    break Ending::Idle(idle);
+                }
+                continue;
+            }
+        };
+        match result {
+            Ok(()) if uplink => uplink_done = true,
+            Ok(()) => downlink_done = true,
+            Err(error) => break Ending::Failed { uplink, error },
+        }
+    };
+    report();
+    ending
+}
+
+/// 等待对端断开连接（收到 FIN 或 RST），不读取对端已发送的数据
+///
+/// 不能清除就绪状态，否则之后的转发收不到已到达数据的通知，
+/// 因此有数据可读时定期检查是否同时已断开。
+pub async fn closed(stream: &TcpStream) {
+    loop {
+        match stream.ready(Interest::READABLE).await {
+            Ok(ready) if ready.is_read_closed() => return,
+            Ok(_) => tokio::time::sleep(CLOSED_POLL_INTERVAL).await,
+            Err(_) => return,
+        }
+    }
+}
+
+/// reader -> writer，读到 EOF 后关闭 writer 的写方向，已转发的字节数累加到 total
+async fn copy_one_way<R, W, F>(reader: &mut R, writer: &mut W, total: &AtomicU64, observe: F) -> io::Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: Fn(&[u8]),
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            // 对端可能已经完全断开，此时关闭写方向失败不影响结果
+            let _ = writer.shutdown().await;
+            return Ok(());
+        }
+        observe(&buf[..n]);
+        writer.write_all(&buf[..n]).await?;
+        total.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod splice {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::io::Interest;
+    use tokio::net::TcpStream;
+
+    /// 单次 splice 的最大字节数，与默认管道容量一致
+    const PIPE_SIZE: usize = 64 * 1024;
+
+    struct Pipe {
+        read: OwnedFd,
+        write: OwnedFd,
+        /// 管道容量，即单次 splice 的最大字节数
+        size: usize,
+    }
+
+    impl Pipe {
+        fn new() -> io::Result<Self> {
+            let mut fds = [0 as RawFd; 2];
+            let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // pipe2 成功后两个 fd 都归我们所有
+            let mut pipe = unsafe {
+                Self {
+                    read: OwnedFd::from_raw_fd(fds[0]),
+                    write: OwnedFd::from_raw_fd(fds[1]),
+                    size: PIPE_SIZE,
+                }
+            };
+
+            // 路由器模式下缩小管道，每条连接占用的内核内存随之减少
+            if crate::profile::router() {
+                let size = unsafe { libc::fcntl(pipe.write.as_raw_fd(), libc::F_SETPIPE_SZ, crate::profile::ROUTER_PIPE_SIZE as libc::c_int) };
+                if size > 0 {
+                    pipe.size = size as usize;
+                }
+            }
+            Ok(pipe)
+        }
+    }
+
+    fn splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::splice(
+                fd_in,
+                std::ptr::null_mut(),
+                fd_out,
+                std::ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+            )
+        };
+
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    /// src -> 管道 -> dst，读到 EOF 后关闭 dst 的写方向，已转发的字节数累加到 total
+    pub async fn splice_one_way(src: &TcpStream, dst: &TcpStream, total: &AtomicU64) -> io::Result<()> {
+        let pipe = Pipe::new()?;
+
+        loop {
+            // 每轮都会把管道排空，因此写入管道时不会因管道已满而返回 EAGAIN
+            let n = loop {
+                src.readable().await?;
+                match src.try_io(Interest::READABLE, || {
+                    splice(src.as_raw_fd(), pipe.write.as_raw_fd(), pipe.size)
+                }) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+
+            if n == 0 {
+                unsafe {
+                    libc::shutdown(dst.as_raw_fd(), libc::SHUT_WR);
+                }
+                return Ok(());
+            }
+
+            let mut remaining = n;
+            while remaining > 0 {
+                dst.writable().await?;
+                match dst.try_io(Interest::WRITABLE, || {
+                    splice(pipe.read.as_raw_fd(), dst.as_raw_fd(), remaining)
+                }) {
+                    Ok(written) => remaining -= written,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            total.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// 建立一对已连接的 TCP 套接字
+    async fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connect = TcpStream::connect(listener.local_addr().unwrap());
+        let (connected, accepted) = tokio::join!(connect, listener.accept());
+        (connected.unwrap(), accepted.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn keeps_other_direction_open_after_half_close() {
+        let (mut client, proxy_client) = pair().await;
+        let (proxy_target, mut target) = pair().await;
+        let relay = tokio::spawn(relay_tcp(proxy_client, proxy_target, None, |_, _| {}));
+
+        // 客户端发完请求后关闭写方向，服务端读到 EOF 后才回复
+        client.write_all(b"request").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut request = Vec::new();
+        target.read_to_end(&mut request).await.unwrap();
+        assert_eq!(request, b"request");
+        target.write_all(b"response").await.unwrap();
+        drop(target);
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"response");
+
+        let relayed = relay.await.unwrap();
+        assert!(matches!(relayed.ending, Ending::Closed));
+        assert_eq!((relayed.sent, relayed.received), (7, 8));
+    }
+
+    #[tokio::test]
+    async fn reports_reset_with_bytes_relayed_so_far() {
+        let (mut client, proxy_client) = pair().await;
+        let (proxy_target, mut target) = pair().await;
+        let relay = tokio::spawn(relay_tcp(proxy_client, proxy_target, None, |_, _| {}));
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        target.read_exact(&mut buf).await.unwrap();
+        // SO_LINGER 为 0 时关闭会发送 RST
+        target.set_zero_linger().unwrap();
+        drop(target);
+
+        let relayed = tokio::time::timeout(Duration::from_secs(5), relay).await.unwrap().unwrap();
+        assert!(matches!(relayed.ending, Ending::Failed { .. }));
+        assert!(relayed.error().is_some());
+        assert_eq!(relayed.sent, 4);
+    }
+
+    #[tokio::test]
+    async fn closes_connections_idle_past_timeout() {
+        let (mut client, proxy_client) = pair().await;
+        let (proxy_target, mut target) = pair().await;
+        let relay = tokio::spawn(relay_tcp(proxy_client, proxy_target, Some(Duration::from_millis(400)), |_, _| {}));
+
+        // 持续有数据时不会超时
+        for _ in 0..4 {
+            client.write_all(b"tick").await.unwrap();
+            let mut buf = [0u8; 4];
+            target.read_exact(&mut buf).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(150)).await;
+        }
+        assert!(!relay.is_finished());
+
+        let relayed = tokio::time::timeout(Duration::from_secs(3), relay).await.unwrap().unwrap();
+        assert!(matches!(relayed.ending, Ending::Idle(_)));
+        assert!(relayed.error().is_none());
+        assert_eq!(relayed.sent, 16);
+    }
+
+    #[tokio::test]
+    async fn reports_progress_while_connection_is_open() {
+        let (mut client, proxy_client) = pair().await;
+        let (proxy_target, mut target) = pair().await;
+        let reported = Arc::new([AtomicU64::new(0), AtomicU64::new(0)]);
+        let counters = Arc::clone(&reported);
+        let relay = tokio::spawn(relay_tcp(proxy_client, proxy_target, None, move |sent, received| {
+            counters[0].fetch_add(sent, Ordering::Relaxed);
+            counters[1].fetch_add(received, Ordering::Relaxed);
+        }));
+
+        client.write_all(b"login").await.unwrap();
+        let mut buf = [0u8; 5];
+        target.read_exact(&mut buf).await.unwrap();
+        target.write_all(b"ok").await.unwrap();
+        client.read_exact(&mut buf[..2]).await.unwrap();
+
+        // 连接仍然打开时已经计入
+        tokio::time::sleep(PROGRESS_INTERVAL + Duration::from_millis(200)).await;
+        assert!(!relay.is_finished());
+        assert_eq!(reported[0].load(Ordering::Relaxed), 5);
+        assert_eq!(reported[1].load(Ordering::Relaxed), 2);
+
+        client.write_all(b"bye").await.unwrap();
+        target.read_exact(&mut buf[..3]).await.unwrap();
+        drop((client, target));
+        let relayed = relay.await.unwrap();
+        assert_eq!(reported[0].load(Ordering::Relaxed), relayed.sent);
+        assert_eq!(reported[1].load(Ordering::Relaxed), relayed.received);
+    }
+
+    #[tokio::test]
+    async fn detects_peer_close_without_consuming_data() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        client.write_all(b"hello").await.unwrap();
+        let waited = tokio::time::timeout(Duration::from_millis(200), closed(&server)).await;
+        assert!(waited.is_err(), "有数据时不应认为已断开");
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(2), closed(&server)).await.unwrap();
+        // 已发送的数据仍然可以读取
+        let mut buf = [0u8; 5];
+        assert_eq!(server.peek(&mut buf).await.unwrap(), 5);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use crate::region;
+use crate::subscription::Node;
+
+static RENAMER: RwLock<Option<Arc<Renamer>>> = RwLock::new(None);
+
+/// 节点名称中 emoji 的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiMode {
+    /// 保持订阅中的写法
+    #[default]
+    Keep,
+    /// 去掉原有的 emoji，按识别出的地区加上旗帜
+    Add,
+    /// 去掉所有 emoji
+    Strip,
+}
+
+/// 订阅节点的改名规则，写法与 subconverter 相同
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenameConfig {
+    /// 依次应用的 `正则@替换内容`，替换内容中可以用 $1 引用分组，留空为删除匹配的部分
+    pub rules: Vec<String>,
+    pub emoji: EmojiMode,
+}
+
+impl RenameConfig {
+    fn is_active(&self) -> bool {
+        !self.rules.is_empty() || self.emoji != EmojiMode::Keep
+    }
+}
+
+/// 编译好的改名规则
+#[derive(Debug)]
+pub struct Renamer {
+    rules: Vec<(Regex, String)>,
+    emoji: EmojiMode,
+}
+
+impl Renamer {
+    pub fn new(config: &RenameConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                // 正则中可能含有 @，以最后一个 @ 分隔
+                let (pattern, replacement) = rule
+                    .rsplit_once('@')
+                    .ok_or_else(|| anyhow!("改名规则 '{}' 缺少 @，应写作 正则@替换内容", rule))?;
+                let regex = Regex::new(pattern).with_context(|| format!("改名规则 '{}' 的正则无效", rule))?;
+                Ok((regex, replacement.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            rules,
+            emoji: config.emoji,
+        })
+    }
+
+    /// 应用改名规则，结果为空时保留原名
+    pub fn rename(&self, name: &str) -> String {
+        let mut renamed = name.to_string();
+        for (regex, replacement) in &self.rules {
+            renamed = regex.replace_all(&renamed, replacement.as_str()).into_owned();
+        }
+        // 去掉旗帜前先识别地区，只有旗帜的名称也能加回来
+        let flag = region::infer(&renamed).and_then(region::flag);
+        if self.emoji != EmojiMode::Keep {
+            renamed = renamed.chars().filter(|c| !is_emoji(*c)).collect();
+        }
+        let mut renamed = renamed.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let (EmojiMode::Add, Some(flag)) = (self.emoji, flag) {
+            renamed = format!("{} {}", flag, renamed);
+        }
+        if renamed.is_empty() {
+            name.to_string()
+        } else {
+            renamed.trim().to_string()
+        }
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    region::is_regional_indicator(c)
+        || matches!(c, '\u{1F300}'..='\u{1FAFF}' | '\u{2600}'..='\u{27BF}' | '\u{FE0F}' | '\u{200D}')
+}
+
+pub fn validate(config: &RenameConfig) -> Result<()> {
+    Renamer::new(config).map(|_| ())
+}
+
+/// 设置订阅节点的改名规则，规则无效时不改名
+pub fn set_config(config: &RenameConfig) {
+    let renamer = match Renamer::new(config) {
+        Ok(renamer) if config.is_active() => Some(Arc::new(renamer)),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("{:#}，不对节点改名", e);
+            None
+        }
+    };
+    *RENAMER.write().unwrap_or_else(|e| e.into_inner()) = renamer;
+}
+
+/// 对解析出的节点改名，改名后重名的节点依次加上编号
+pub fn apply(nodes: &mut [Node]) {
+    let Some(renamer) = RENAMER.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+        return;
+    };
+    let mut seen = HashSet::new();
+    for node in nodes {
+        let renamed = renamer.rename(&node.name);
+        let mut unique = renamed.clone();
+        let mut index = 2;
+        while !seen.insert(unique.clone()) {
+            unique = format!("{} {}", renamed, index);
+            index += 1;
+        }
+        node.name = unique;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_like_subconverter() {
+        let config: RenameConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - '^\[.*?\]\s*@'
+  - 'IEPL|IPLC@专线'
+  - '(\d+)倍率@x$1'
+emoji: add
+"#,
+        )
+        .unwrap();
+        let renamer = Renamer::new(&config).unwrap();
+        assert_eq!(renamer.rename("[Provider] 香港 IPLC 01"), "🇭🇰 香港 专线 01");
+        assert_eq!(renamer.rename("🇯🇵  Tokyo 2倍率 "), "🇯🇵 Tokyo x2");
+        assert_eq!(renamer.rename("剩余流量：100G ✨"), "剩余流量：100G");
+
+        let strip = Renamer::new(&RenameConfig {
+            rules: Vec::new(),
+            emoji: EmojiMode::Strip,
+        })
+        .unwrap();
+        assert_eq!(strip.rename("🇺🇸 美国 ⚡️ 01"), "美国 01");
+        assert_eq!(strip.rename("🇺🇸"), "🇺🇸");
+
+        assert!(validate(&RenameConfig {
+            rules: vec!["no separator".into()],
+            emoji: EmojiMode::Keep,
+        })
+        .is_err());
+        assert!(validate(&RenameConfig {
+            rules: vec!["(unclosed@x".into()],
+            emoji: EmojiMode::Keep,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::failover::HealthReport;
+use crate::history::{self, Cell, Heatmap, LatencySample};
+use crate::ipc::StatusReport;
+use crate::region;
+use crate::subscription::Node;
+use crate::usage::Summary;
+
+/// HTML 报告中列出的节点和游戏数量
+const TOP_ENTRIES: usize = 10;
+/// HTML 报告中列出的切换记录数量
+const SWITCHES_SHOWN: usize = 20;
+
+/// 本次测试中一个地区的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionRow {
+    pub region: &'static str,
+    pub nodes: usize,
+    pub cell: Cell,
+    pub best: Option<String>,
+}
+
+/// 按地区汇总本次测试的样本
+pub fn region_rows(nodes: &[Node], samples: &[LatencySample]) -> Vec<RegionRow> {
+    let mut regions: BTreeMap<&str, Vec<&LatencySample>> = BTreeMap::new();
+    for sample in samples {
+        regions.entry(region::of(&sample.node)).or_default().push(sample);
+    }
+    regions
+        .into_iter()
+        .filter_map(|(name, samples)| {
+            let cell = history::summarize(samples)?;
+            let in_region = || nodes.iter().filter(move |node| region::of(&node.name) == name);
+            let best = in_region()
+                .filter(|node| node.latency.unwrap_or(u32::MAX) < u32::MAX)
+                .min_by_key(|node| node.latency)
+                .map(|node| node.name.clone());
+            Some(RegionRow {
+                region: name,
+                nodes: in_region().count(),
+                cell,
+                best,
+            })
+        })
+        .collect()
+}
+
+/// 生成 HTML 报告所需的数据，服务未运行时没有 status 和 health
+pub struct HtmlReport<'a> {
+    pub generated_at: String,
+    pub regions: &'a [RegionRow],
+    pub heatmap: &'a Heatmap,
+    pub history_samples: usize,
+    pub usage: &'a Summary,
+    pub usage_days: u32,
+    pub status: Option<&'a StatusReport>,
+    pub health: Option<&'a HealthReport>,
+}
+
+const STYLE: &str = "body{font-family:-apple-system,'PingFang SC','Microsoft YaHei',sans-serif;max-width:960px;margin:2em auto;padding:0 1em;color:#222}\
+h1{font-size:1.6em}h2{font-size:1.2em;margin-top:2em;border-bottom:1px solid #ddd;padding-bottom:.3em}\
+table{border-collapse:collapse;width:100%}th,td{border:1px solid #ddd;padding:.35em .6em;text-align:left}th{background:#f5f5f5}\
+.good{background:#d9f2d9}.ok{background:#fff3c4}.slow{background:#ffe0c2}.bad{background:#ffd0d0}.muted{color:#888}";
+
+/// 转义节点名称等来自订阅的文本
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 与终端报告相同的分级
+fn level_class(cell: &Cell) -> &'static str {
+    match crate::latency_level(cell) {
+        "🟢" => "good",
+        "🟡" => "ok",
+        "🟠" => "slow",
+        _ => "bad",
+    }
+}
+
+fn timestamp(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// 生成不依赖外部资源的单个 HTML 文件，不包含订阅链接
+pub fn render(report: &HtmlReport) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n<title>ClashFun 报告</title>\n<style>{}</style>\n</head>\n<body>\n",
+        STYLE
+    );
+    let _ = writeln!(html, "<h1>ClashFun 网络报告</h1>");
+    let _ = writeln!(
+        html,
+        "<p class=\"muted\">生成于 {}，ClashFun {}</p>",
+        escape(&report.generated_at),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    // 本次测试
+    let _ = writeln!(html, "<h2>当前各地区延迟</h2>");
+    let _ = writeln!(html, "<table><tr><th>地区</th><th>节点</th><th>中位延迟</th><th>丢包率</th><th>最佳节点</th></tr>");
+    for row in report.regions {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}%</td><td>{}</td></tr>",
+            escape(row.region),
+            row.nodes,
+            level_class(&row.cell),
+            crate::format_median(row.cell.median_ms),
+            row.cell.loss_percent,
+            escape(row.best.as_deref().unwrap_or("-"))
+        );
+    }
+    let _ = writeln!(html, "</table>");
+
+    // 历史热力图
+    let _ = writeln!(html, "<h2>各时段中位延迟 / 丢包率（最近 30 天，共 {} 次测试）</h2>", report.history_samples);
+    let _ = write!(html, "<table><tr><th>地区</th>");
+    for bucket in 0..history::BUCKETS {
+        let _ = write!(html, "<th>{}</th>", history::bucket_label(bucket));
+    }
+    let _ = writeln!(html, "</tr>");
+    for (name, cells) in report.heatmap {
+        let _ = write!(html, "<tr><td>{}</td>", escape(name));
+        for cell in cells {
+            match cell {
+                Some(cell) => {
+                    let _ = write!(html, "<td class=\"{}\">{} / {}%</td>", level_class(cell), crate::format_median(cell.median_ms), cell.loss_percent);
+                }
+                None => html.push_str("<td class=\"muted\">-</td>"),
+            }
+        }
+        let _ = writeln!(html, "</tr>");
+    }
+    let _ = writeln!(html, "</table>");
+
+    // 流量
+    let usage = report.usage;
+    let _ = writeln!(html, "<h2>最近 {} 天的流量</h2>", report.usage_days);
+    let _ = writeln!(
+        html,
+        "<p>合计 {}（上行 {}，下行 {}）</p>",
+        crate::format_bytes(usage.total.total()),
+        crate::format_bytes(usage.total.upload),
+        crate::format_bytes(usage.total.download)
+    );
+    for (title, entries) in [("节点", &usage.nodes), ("游戏", &usage.games)] {
+        if entries.is_empty() {
+            continue;
+        }
+        let _ = writeln!(html, "<table><tr><th>{}</th><th>上行</th><th>下行</th><th>合计</th></tr>", title);
+        for (name, traffic) in entries.iter().take(TOP_ENTRIES) {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(name),
+                crate::format_bytes(traffic.upload),
+                crate::format_bytes(traffic.download),
+                crate::format_bytes(traffic.total())
+            );
+        }
+        let _ = writeln!(html, "</table><br>");
+    }
+
+    // 运行中的服务
+    let _ = writeln!(html, "<h2>本次运行</h2>");
+    match report.status {
+        Some(status) => {
+            let _ = writeln!(
+                html,
+                "<p>已运行 {} 分钟，当前节点 {}，累计上行 {} / 下行 {}，活动 TCP 连接 {}，UDP 会话 {}，异常中断的连接 {}</p>",
+                status.uptime_secs / 60,
+                escape(status.node.as_deref().unwrap_or("无")),
+                crate::format_bytes(status.upload_bytes),
+                crate::format_bytes(status.download_bytes),
+                status.tcp_connections,
+                status.udp_sessions,
+                status.tcp_failed
+            );
+        }
+        None => {
+            let _ = writeln!(html, "<p class=\"muted\">生成报告时加速服务未运行</p>");
+        }
+    }
+    if let Some(health) = report.health.filter(|health| !health.switches.is_empty()) {
+        let _ = writeln!(html, "<table><tr><th>时间</th><th>从</th><th>到</th><th>原因</th></tr>");
+        for switch in health.switches.iter().take(SWITCHES_SHOWN) {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                timestamp(switch.timestamp),
+                escape(switch.from.as_deref().unwrap_or("-")),
+                escape(&switch.to),
+                switch.reason.display_name()
+            );
+        }
+        let _ = writeln!(html, "</table>");
+    }
+
+    let _ = writeln!(html, "</body>\n</html>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_escaped_standalone_report() {
+        let nodes = vec![Node { latency: Some(40), ..Node::test("香港 <01> & co", "hk.example.com", 443, "ss") }];
+        let samples = history::samples(&nodes);
+        let regions = region_rows(&nodes, &samples);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].region, "香港");
+        assert_eq!(regions[0].best.as_deref(), Some("香港 <01> & co"));
+
+        let heatmap = history::heatmap(&samples);
+        let usage = Summary::default();
+        let html = render(&HtmlReport {
+            generated_at: "2026-10-15 21:00".to_string(),
+            regions: &regions,
+            heatmap: &heatmap,
+            history_samples: samples.len(),
+            usage: &usage,
+            usage_days: 7,
+            status: None,
+            health: None,
+        });
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("香港 &lt;01&gt; &amp; co"));
+        assert!(!html.contains("<01>"));
+        assert!(html.contains("class=\"good\">40ms"));
+        assert!(html.contains("加速服务未运行"));
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::region;
+use crate::subscription::Node;
+
+/// 一天中的时刻，配置中写作 "19:00"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DayTime {
+    /// 从 0 点开始的分钟数
+    minutes: u16,
+}
+
+impl DayTime {
+    pub fn of(time: NaiveTime) -> Self {
+        Self {
+            minutes: (time.hour() * 60 + time.minute()) as u16,
+        }
+    }
+}
+
+impl TryFrom<String> for DayTime {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        let invalid = || anyhow!("无效的时间: {}（例如 19:00）", value);
+        let (hour, minute) = value.trim().split_once(':').ok_or_else(invalid)?;
+        let hour: u16 = hour.parse().map_err(|_| invalid())?;
+        let minute: u16 = minute.parse().map_err(|_| invalid())?;
+        // 允许 24:00 表示一天结束
+        if minute >= 60 || hour > 24 || (hour == 24 && minute > 0) {
+            return Err(invalid());
+        }
+        Ok(Self { minutes: hour * 60 + minute })
+    }
+}
+
+impl From<DayTime> for String {
+    fn from(time: DayTime) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for DayTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// 在某个时段优先选择的节点，名称关键字和地区同时设置时两者都要满足
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeRule {
+    pub from: DayTime,
+    /// 早于 from 时表示跨过午夜，例如 23:00 - 07:00
+    pub to: DayTime,
+    /// 节点名称包含的关键字（不区分大小写），例如 "JP-premium"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<String>,
+    /// 地区，例如 "日本" 或 "jp"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl TimeRule {
+    pub fn is_active(&self, now: DayTime) -> bool {
+        if self.from <= self.to {
+            self.from <= now && now < self.to
+        } else {
+            now >= self.from || now < self.to
+        }
+    }
+
+    pub fn matches(&self, node: &Node) -> bool {
+        let name = self
+            .nodes
+            .as_deref()
+            .is_none_or(|keyword| node.name.to_lowercase().contains(&keyword.to_lowercase()));
+        let region = self
+            .region
+            .as_deref()
+            .is_none_or(|region| region::infer(region).is_some_and(|region| region::infer_node(node) == Some(region)));
+        name && region
+    }
+
+    pub fn describe(&self) -> String {
+        let target = match (&self.nodes, &self.region) {
+            (Some(nodes), Some(region)) => format!("{} 地区名称包含 {} 的节点", region, nodes),
+            (Some(nodes), None) => format!("名称包含 {} 的节点", nodes),
+            (None, Some(region)) => format!("{} 地区的节点", region),
+            (None, None) => "所有节点".to_string(),
+        };
+        format!("{}-{} 优先{}", self.from, self.to, target)
+    }
+}
+
+/// 当前生效的规则，多条规则重叠时使用写在前面的
+pub fn active(rules: &[TimeRule], now: DayTime) -> Option<(usize, &TimeRule)> {
+    rules.iter().enumerate().find(|(_, rule)| rule.is_active(now))
+}
+
+pub fn now() -> DayTime {
+    DayTime::of(chrono::Local::now().time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str) -> Node {
+        Node::test(name, "127.0.0.1", 1, "ss")
+    }
+
+    fn at(value: &str) -> DayTime {
+        DayTime::try_from(value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn selects_rule_by_time_window() {
+        let rules: Vec<TimeRule> = serde_yaml::from_str(
+            "- { from: \"19:00\", to: \"23:00\", nodes: JP-premium }\n- { from: \"23:00\", to: \"19:00\", region: hk }",
+        )
+        .unwrap();
+
+        assert_eq!(active(&rules, at("20:30")).unwrap().0, 0);
+        assert_eq!(active(&rules, at("23:00")).unwrap().0, 1);
+        assert_eq!(active(&rules, at("03:15")).unwrap().0, 1);
+        assert_eq!(active(&rules[..1], at("18:59")), None);
+
+        assert!(rules[0].matches(&node("日本 jp-Premium 01")));
+        assert!(!rules[0].matches(&node("日本 02")));
+        assert!(rules[1].matches(&node("香港 01")));
+        assert!(!rules[1].matches(&node("日本 01")));
+
+        assert_eq!(at("7:05").to_string(), "07:05");
+        assert!(DayTime::try_from("25:00".to_string()).is_err());
+        assert!(DayTime::try_from("19".to_string()).is_err());
+        let saved = serde_yaml::to_string(&rules).unwrap();
+        assert_eq!(serde_yaml::from_str::<Vec<TimeRule>>(&saved).unwrap(), rules);
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::obfs::Rng;
+
+/// 开发者模式下模拟的网络状况
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// 增加的往返延迟
+    pub latency: Duration,
+    /// 延迟的随机波动，上下浮动不超过该值
+    pub jitter: Duration,
+    /// 每个方向的丢包率 (百分比)
+    pub loss_percent: f64,
+}
+
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        (secs, 1000.0)
+    } else {
+        (value, 1.0)
+    };
+    let millis: f64 = number.trim().parse().map_err(|_| anyhow!("无效的时长: {}", value))?;
+    if !millis.is_finite() || millis < 0.0 {
+        bail!("无效的时长: {}", value);
+    }
+    Ok(Duration::from_secs_f64(millis * scale / 1000.0))
+}
+
+impl FromStr for NetworkConditions {
+    type Err = anyhow::Error;
+
+    /// 支持 `latency=80ms jitter=20ms loss=1%`，也可以用逗号分隔
+    fn from_str(s: &str) -> Result<Self> {
+        let mut conditions = Self::default();
+        let items = s.split(|c: char| c.is_whitespace() || c == ',').filter(|item| !item.is_empty());
+        for item in items {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| anyhow!("无效的参数 {}，应为 名称=值", item))?;
+            match key.trim().to_ascii_lowercase().as_str() {
+                "latency" | "delay" => conditions.latency = parse_duration(value)?,
+                "jitter" => conditions.jitter = parse_duration(value)?,
+                "loss" => {
+                    let value = value.trim();
+                    let percent: f64 = value
+                        .trim_end_matches('%')
+                        .parse()
+                        .map_err(|_| anyhow!("无效的丢包率: {}", value))?;
+                    if !(0.0..=100.0).contains(&percent) {
+                        bail!("丢包率应在 0-100% 之间: {}", value);
+                    }
+                    conditions.loss_percent = percent;
+                }
+                other => bail!("未知的参数 {}，支持 latency、jitter、loss", other),
+            }
+        }
+        if conditions == Self::default() {
+            bail!("至少需要设置 latency、jitter、loss 中的一项");
+        }
+        Ok(conditions)
+    }
+}
+
+impl fmt::Display for NetworkConditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "延迟 +{}ms，抖动 ±{}ms，丢包 {}%",
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.loss_percent
+        )
+    }
+}
+
+/// 数据包的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Drop,
+    Delay(Duration),
+}
+
+impl NetworkConditions {
+    /// legs 为经过的单程数：单向转发为 1，往返为 2
+    fn fate(&self, legs: u32, rng: &mut Rng) -> Fate {
+        for _ in 0..legs {
+            if (rng.next() % 10_000) as f64 / 100.0 < self.loss_percent {
+                return Fate::Drop;
+            }
+        }
+
+        let base = self.latency.as_micros() as i64 * legs as i64 / 2;
+        let jitter = self.jitter.as_micros() as i64;
+        let offset = if jitter > 0 {
+            (rng.next() % (2 * jitter as u64 + 1)) as i64 - jitter
+        } else {
+            0
+        };
+        Fate::Delay(Duration::from_micros((base + offset).max(0) as u64))
+    }
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static CONDITIONS: Mutex<Option<(NetworkConditions, Rng)>> = Mutex::new(None);
+
+/// 设置模拟的网络状况，None 表示关闭
+pub fn set_conditions(conditions: Option<NetworkConditions>) {
+    ACTIVE.store(conditions.is_some(), Ordering::Relaxed);
+    *CONDITIONS.lock().unwrap_or_else(|e| e.into_inner()) = conditions.map(|c| (c, Rng::new()));
+}
+
+/// 是否正在模拟，转发路径据此跳过批量发送
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn conditions() -> Option<NetworkConditions> {
+    CONDITIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|(conditions, _)| *conditions)
+}
+
+fn fate(legs: u32) -> Option<Fate> {
+    if !active() {
+        return None;
+    }
+    let mut guard = CONDITIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let (conditions, rng) = guard.as_mut()?;
+    Some(conditions.fate(legs, rng))
+}
+
+/// 单向转发的一个数据包，未开启模拟时返回 None
+pub fn one_way() -> Option<Fate> {
+    fate(1)
+}
+
+/// 一次往返 (建立连接、健康探测)，未开启模拟时返回 None
+pub fn round_trip() -> Option<Fate> {
+    fate(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_condition_spec() {
+        let conditions: NetworkConditions = "latency=80ms jitter=10 loss=1.5%".parse().unwrap();
+        assert_eq!(conditions.latency, Duration::from_millis(80));
+        assert_eq!(conditions.jitter, Duration::from_millis(10));
+        assert_eq!(conditions.loss_percent, 1.5);
+        assert_eq!("latency=0.2s,loss=0".parse::<NetworkConditions>().unwrap().latency, Duration::from_millis(200));
+
+        assert!("".parse::<NetworkConditions>().is_err());
+        assert!("latency=fast".parse::<NetworkConditions>().is_err());
+        assert!("loss=120%".parse::<NetworkConditions>().is_err());
+        assert!("bandwidth=1mbit".parse::<NetworkConditions>().is_err());
+    }
+
+    #[test]
+    fn splits_latency_between_directions() {
+        let mut rng = Rng::new();
+        let conditions: NetworkConditions = "latency=80ms jitter=5ms".parse().unwrap();
+        for _ in 0..100 {
+            let Fate::Delay(one_way) = conditions.fate(1, &mut rng) else { panic!("不应丢包") };
+            assert!(one_way >= Duration::from_millis(35) && one_way <= Duration::from_millis(45));
+            let Fate::Delay(round_trip) = conditions.fate(2, &mut rng) else { panic!("不应丢包") };
+            assert!(round_trip >= Duration::from_millis(75) && round_trip <= Duration::from_millis(85));
+        }
+
+        let lossy: NetworkConditions = "loss=100%".parse().unwrap();
+        assert_eq!(lossy.fate(1, &mut rng), Fate::Drop);
+    }
+}
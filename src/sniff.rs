@@ -0,0 +1,291 @@
+use log::debug;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// 等待客户端发送首个数据包的时间，游戏等由服务端先发送数据的连接最多延迟这么久
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(150);
+/// 数据不完整时再次读取前的等待时间
+const SNIFF_RETRY: Duration = Duration::from_millis(10);
+/// 最多查看的字节数，足够容纳带后量子密钥交换的 ClientHello
+const SNIFF_LIMIT: usize = 4096;
+/// 统计的域名数量上限，超过时淘汰次数最少的
+const MAX_HOSTS: usize = 256;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static HOSTS: Mutex<Option<HashMap<String, u64>>> = Mutex::new(None);
+
+/// 识别出域名的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tls,
+    Http,
+}
+
+/// 从连接的首个数据包中识别出的目标
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sniffed {
+    pub protocol: Protocol,
+    pub host: String,
+}
+
+/// 解析首个数据包的结果
+#[derive(Debug, PartialEq, Eq)]
+enum Parse {
+    /// 需要更多数据
+    Incomplete,
+    /// 已确定，None 表示不是 TLS/HTTP 或没有域名
+    Done(Option<Sniffed>),
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// 查看（不读取）客户端发送的首批数据，从 TLS SNI 或 HTTP Host 找回目标域名
+pub async fn sniff(stream: &TcpStream) -> Option<Sniffed> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut buf = vec![0u8; SNIFF_LIMIT];
+    let sniffed = tokio::time::timeout(SNIFF_TIMEOUT, async {
+        loop {
+            let size = stream.peek(&mut buf).await.ok()?;
+            if size == 0 {
+                return None;
+            }
+            match parse(&buf[..size]) {
+                Parse::Done(sniffed) => return sniffed,
+                Parse::Incomplete if size == buf.len() => return None,
+                // peek 不会消耗数据，有数据时会立即返回，稍等后续数据到达
+                Parse::Incomplete => tokio::time::sleep(SNIFF_RETRY).await,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()?;
+
+    debug!("从 {:?} 识别到目标域名 {}", sniffed.protocol, sniffed.host);
+    record(&sniffed.host);
+    Some(sniffed)
+}
+
+fn parse(data: &[u8]) -> Parse {
+    match data.first() {
+        Some(0x16) => parse_tls(data),
+        Some(byte) if byte.is_ascii_uppercase() => parse_http(data),
+        _ => Parse::Done(None),
+    }
+}
+
+/// 从 TLS ClientHello 的 server_name 扩展中取出域名
+fn parse_tls(data: &[u8]) -> Parse {
+    if data.len() < 5 {
+        return Parse::Incomplete;
+    }
+    if data[1] != 0x03 {
+        return Parse::Done(None);
+    }
+    let length = u16::from_be_bytes([data[3], data[4]]) as usize;
+    let Some(record) = data.get(5..5 + length) else {
+        return Parse::Incomplete;
+    };
+    Parse::Done(client_hello_sni(record).and_then(|host| normalize(&host)).map(|host| Sniffed {
+        protocol: Protocol::Tls,
+        host,
+    }))
+}
+
+fn client_hello_sni(record: &[u8]) -> Option<String> {
+    let mut reader = Reader(record);
+    // 握手类型 ClientHello 和 3 字节长度
+    if reader.u8()? != 0x01 {
+        return None;
+    }
+    reader.take(3)?;
+    // 版本和随机数
+    reader.take(2 + 32)?;
+    let session_id = reader.u8()? as usize;
+    reader.take(session_id)?;
+    let cipher_suites = reader.u16()? as usize;
+    reader.take(cipher_suites)?;
+    let compression = reader.u8()? as usize;
+    reader.take(compression)?;
+
+    let extensions = reader.u16()? as usize;
+    let mut extensions = Reader(reader.take(extensions)?);
+    while let Some(kind) = extensions.u16() {
+        let length = extensions.u16()? as usize;
+        let mut body = Reader(extensions.take(length)?);
+        if kind != 0 {
+            continue;
+        }
+        let list = body.u16()? as usize;
+        let mut list = Reader(body.take(list)?);
+        while let Some(name_type) = list.u8() {
+            let length = list.u16()? as usize;
+            let name = list.take(length)?;
+            if name_type == 0 {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+        return None;
+    }
+    None
+}
+
+/// 从 HTTP 请求头中取出 Host
+fn parse_http(data: &[u8]) -> Parse {
+    const METHODS: &[&[u8]] = &[b"GET ", b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"CONNECT "];
+
+    let method_known = METHODS.iter().any(|method| {
+        let len = method.len().min(data.len());
+        data[..len] == method[..len]
+    });
+    if !method_known {
+        return Parse::Done(None);
+    }
+
+    let text = String::from_utf8_lossy(data);
+    let complete = text.contains("\r\n\r\n");
+    // 最后一段没有换行时可能只收到了半行
+    let mut lines: Vec<&str> = text.split("\r\n").collect();
+    lines.pop();
+    let host = lines.iter().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim().eq_ignore_ascii_case("host").then_some(value.trim())
+    });
+    match host {
+        Some(host) => {
+            let host = match host.rsplit_once(':') {
+                Some((name, port)) if port.parse::<u16>().is_ok() => name,
+                _ => host,
+            };
+            Parse::Done(normalize(host).map(|host| Sniffed { protocol: Protocol::Http, host }))
+        }
+        None if complete => Parse::Done(None),
+        None => Parse::Incomplete,
+    }
+}
+
+/// 转为小写并去掉末尾的点，IP 地址和非法域名返回 None
+fn normalize(host: &str) -> Option<String> {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = !host.is_empty()
+        && host.len() <= 253
+        && host.parse::<IpAddr>().is_err()
+        && !host.starts_with('[')
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+    valid.then_some(host)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+/// 记录一次识别到的域名
+fn record(host: &str) {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    let hosts = hosts.get_or_insert_with(HashMap::new);
+    if !hosts.contains_key(host) && hosts.len() >= MAX_HOSTS {
+        if let Some(least) = hosts.iter().min_by_key(|(_, count)| **count).map(|(host, _)| host.clone()) {
+            hosts.remove(&least);
+        }
+    }
+    *hosts.entry(host.to_string()).or_default() += 1;
+}
+
+/// 记录的域名数量
+pub fn host_count() -> usize {
+    HOSTS.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map_or(0, HashMap::len)
+}
+
+/// 连接次数最多的域名
+pub fn top_hosts(limit: usize) -> Vec<(String, u64)> {
+    let hosts = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut top: Vec<(String, u64)> = hosts.iter().flatten().map(|(host, count)| (host.clone(), *count)).collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(limit);
+    top
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造只带 server_name 扩展的 ClientHello
+    fn client_hello(host: &str) -> Vec<u8> {
+        let mut sni = Vec::new();
+        sni.extend_from_slice(&((host.len() + 3) as u16).to_be_bytes());
+        sni.push(0);
+        sni.extend_from_slice(&(host.len() as u16).to_be_bytes());
+        sni.extend_from_slice(host.as_bytes());
+
+        let mut extensions = vec![0xff, 0x01, 0, 1, 0];
+        extensions.extend_from_slice(&[0, 0]);
+        extensions.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0u8; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&[0, 2, 0x13, 0x01]);
+        hello.extend_from_slice(&[1, 0]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![0x01, 0, (hello.len() >> 8) as u8, hello.len() as u8];
+        handshake.extend_from_slice(&hello);
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn sniffs_tls_sni_and_http_host() {
+        let hello = client_hello("Steamcdn-A.Akamaihd.net");
+        assert_eq!(
+            parse(&hello),
+            Parse::Done(Some(Sniffed {
+                protocol: Protocol::Tls,
+                host: "steamcdn-a.akamaihd.net".to_string()
+            }))
+        );
+        assert_eq!(parse(&hello[..hello.len() - 3]), Parse::Incomplete);
+
+        let request = b"GET /depot/1 HTTP/1.1\r\nHost: cache.example.com:8080\r\nAccept: */*\r\n\r\n";
+        assert_eq!(
+            parse(request),
+            Parse::Done(Some(Sniffed {
+                protocol: Protocol::Http,
+                host: "cache.example.com".to_string()
+            }))
+        );
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nHos"), Parse::Incomplete);
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nHost: 1.2.3.4\r\n\r\n"), Parse::Done(None));
+        // 游戏私有协议不等待
+        assert_eq!(parse(&[0xff, 0xff, 0xff, 0xff, 0x54]), Parse::Done(None));
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clashfun::error::ProxyError;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::subscription::Node;
+
+const VERSION: u8 = 0x05;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+/// 用户名密码认证子协商的版本 (RFC 1929)
+const AUTH_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// 是否为 SOCKS5 上游节点，例如本机的 Tor 或公司代理
+pub fn is_socks5(node: &Node) -> bool {
+    node.protocol.eq_ignore_ascii_case("socks5")
+}
+
+/// 方法协商和认证，节点设置了用户名时使用用户名密码认证
+pub async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, node: &Node) -> Result<()> {
+    let credential = node
+        .username
+        .as_deref()
+        .map(|username| (username, node.password.as_deref().unwrap_or("")));
+    let greeting: &[u8] = match credential {
+        Some(_) => &[VERSION, 2, METHOD_NONE, METHOD_PASSWORD],
+        None => &[VERSION, 1, METHOD_NONE],
+    };
+    stream.write_all(greeting).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.context("SOCKS5 服务端没有回复")?;
+    if reply[0] != VERSION {
+        bail!("不是 SOCKS5 服务端");
+    }
+    match (reply[1], credential) {
+        (METHOD_NONE, _) => Ok(()),
+        (METHOD_PASSWORD, Some((username, password))) => authenticate(stream, node, username, password).await,
+        (METHOD_PASSWORD, None) => Err(rejected(node, "SOCKS5 服务端要求用户名和密码")),
+        (METHOD_UNACCEPTABLE, _) => Err(rejected(node, "SOCKS5 服务端不接受提供的认证方式")),
+        (method, _) => bail!("SOCKS5 服务端选择了不支持的认证方式 {:#04x}", method),
+    }
+}
+
+fn rejected(node: &Node, reason: &'static str) -> anyhow::Error {
+    ProxyError::AuthRejected { node: node.name.clone(), reason }.into()
+}
+
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, node: &Node, username: &str, password: &str) -> Result<()> {
+    if username.len() > 255 || password.len() > 255 {
+        bail!("SOCKS5 用户名和密码不能超过 255 字节");
+    }
+    let mut request = vec![AUTH_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await.context("SOCKS5 服务端没有回复认证结果")?;
+    if reply[1] != 0 {
+        return Err(rejected(node, "SOCKS5 用户名或密码错误"));
+    }
+    Ok(())
+}
+
+/// 通过 SOCKS5 节点连接目标，成功后 stream 即为到目标的连接
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, node: &Node, target: SocketAddr) -> Result<()> {
+    negotiate(stream, node).await?;
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.context("SOCKS5 服务端没有回复连接结果")?;
+    if reply[1] != 0 {
+        return Err(anyhow!("SOCKS5 服务端无法连接 {}: {}", target, reply_error(reply[1])));
+    }
+    // 跳过服务端绑定的地址和端口
+    let address_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => bail!("SOCKS5 回复中的地址类型无效: {:#04x}", atyp),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        0x01 => "服务端内部错误",
+        0x02 => "规则不允许该连接",
+        0x03 => "网络不可达",
+        0x04 => "主机不可达",
+        0x05 => "连接被拒绝",
+        0x06 => "TTL 超时",
+        0x07 => "不支持 CONNECT 命令",
+        0x08 => "不支持的地址类型",
+        _ => "未知错误",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    fn node(username: Option<&str>) -> Node {
+        Node {
+            password: Some("secret".to_string()),
+            username: username.map(str::to_string),
+            ..Node::test("Tor", "127.0.0.1", 9050, "socks5")
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_through_socks5_with_password() {
+        let (mut client, mut server) = duplex(256);
+        let target: SocketAddr = "203.0.113.7:27015".parse().unwrap();
+        let upstream = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 2, METHOD_NONE, METHOD_PASSWORD]);
+            server.write_all(&[VERSION, METHOD_PASSWORD]).await.unwrap();
+
+            let mut auth = [0u8; 2 + 4 + 1 + 6];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth[2..6], b"user");
+            assert_eq!(&auth[7..], b"secret");
+            server.write_all(&[AUTH_VERSION, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [VERSION, CMD_CONNECT, 0, ATYP_IPV4, 203, 0, 113, 7, 0x69, 0x87]);
+            server.write_all(&[VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            server.write_all(b"game").await.unwrap();
+        });
+
+        connect(&mut client, &node(Some("user")), target).await.unwrap();
+        let mut data = [0u8; 4];
+        client.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"game");
+        upstream.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_connections() {
+        let (mut client, mut server) = duplex(256);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[VERSION, METHOD_NONE]).await.unwrap();
+            let mut request = [0u8; 22];
+            server.read_exact(&mut request).await.unwrap();
+            server.write_all(&[VERSION, 0x05, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+
+        let error = connect(&mut client, &node(None), "[2001:db8::1]:443".parse().unwrap()).await.unwrap_err();
+        assert!(error.to_string().contains("连接被拒绝"), "{}", error);
+    }
+}
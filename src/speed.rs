@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::dns;
+use crate::health;
+use crate::subscription::Node;
+use crate::timeouts;
+
+/// 同时测试延迟的节点数
+const PROBE_CONCURRENCY: usize = 16;
+/// 测速时每次写入的数据量
+const CHUNK_SIZE: usize = 16 * 1024;
+/// 限制发送缓冲区，测到的是节点确认数据的速率而不是写入本机缓冲区的速率
+const SEND_BUFFER: usize = 64 * 1024;
+/// 吞吐量每比最快的节点低一半，相当于增加这么多毫秒的延迟
+const HALVING_PENALTY_MS: f64 = 50.0;
+
+/// 一个节点的测速结果
+#[derive(Debug, Clone)]
+pub struct SpeedResult {
+    pub node: Node,
+    pub rtt: Duration,
+    /// 每秒字节数，测速失败时为 None
+    pub throughput: Option<f64>,
+    pub error: Option<String>,
+    /// 综合得分，越小越好
+    pub score: f64,
+}
+
+/// 并发测试所有节点的往返延迟，返回成功的节点，按延迟从低到高排列
+pub async fn probe_rtt(nodes: &[Node]) -> Vec<(Node, Duration)> {
+    let permits = Arc::new(Semaphore::new(PROBE_CONCURRENCY));
+    let timeout = timeouts::current().backup_probe();
+    let mut tasks = JoinSet::new();
+    for node in nodes.iter().cloned() {
+        let permits = Arc::clone(&permits);
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            let rtt = health::rtt(&node, timeout).await.ok()?;
+            Some((node, rtt))
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some(measured)) = result {
+            results.push(measured);
+        }
+    }
+    results.sort_by_key(|(_, rtt)| *rtt);
+    results
+}
+
+/// 在 duration 内持续向节点发送数据，返回节点接收数据的速率（字节/秒）
+///
+/// 节点不认识的数据多半会被读取后丢弃；节点提前断开时按断开前发送的数据计算。
+pub async fn sample_throughput(node: &Node, duration: Duration) -> Result<f64> {
+    let mut stream = dns::connect_node(node)
+        .await
+        .context("TCP 连接失败")?;
+    socket2::SockRef::from(&stream)
+        .set_send_buffer_size(SEND_BUFFER)
+        .context("无法设置发送缓冲区")?;
+
+    let chunk = payload();
+    let start = Instant::now();
+    let mut sent = 0u64;
+    while start.elapsed() < duration {
+        let remaining = duration.saturating_sub(start.elapsed());
+        match tokio::time::timeout(remaining, stream.write_all(&chunk)).await {
+            Ok(Ok(())) => sent += CHUNK_SIZE as u64,
+            Ok(Err(e)) if sent == 0 => return Err(anyhow!("节点拒绝接收数据: {}", e)),
+            Ok(Err(_)) | Err(_) => break,
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    if sent == 0 || elapsed <= 0.0 {
+        return Err(anyhow!("测速期间没有发送出任何数据"));
+    }
+    Ok(sent as f64 / elapsed)
+}
+
+/// 不可压缩的测速数据，避免链路上的压缩影响结果
+fn payload() -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..CHUNK_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// 综合得分：延迟加上吞吐量不足的惩罚，吞吐量每比最快的节点低一半加 HALVING_PENALTY_MS，
+/// 测速失败的节点排在最后
+pub fn score(rtt: Duration, throughput: Option<f64>, best_throughput: f64) -> f64 {
+    let latency = rtt.as_secs_f64() * 1000.0;
+    match throughput {
+        Some(throughput) if throughput > 0.0 && best_throughput > 0.0 => {
+            latency + HALVING_PENALTY_MS * (best_throughput / throughput).log2().max(0.0)
+        }
+        _ => f64::INFINITY,
+    }
+}
+
+/// 按得分从好到差排列
+pub fn rank(measured: Vec<(Node, Duration, Result<f64>)>) -> Vec<SpeedResult> {
+    let best = measured
+        .iter()
+        .filter_map(|(_, _, throughput)| throughput.as_ref().ok().copied())
+        .fold(0.0, f64::max);
+    let mut results: Vec<SpeedResult> = measured
+        .into_iter()
+        .map(|(node, rtt, throughput)| {
+            let (throughput, error) = match throughput {
+                Ok(throughput) => (Some(throughput), None),
+                Err(e) => (None, Some(format!("{:#}", e))),
+            };
+            SpeedResult {
+                score: score(rtt, throughput, best),
+                node,
+                rtt,
+                throughput,
+                error,
+            }
+        })
+        .collect();
+    results.sort_by(|a, b| a.score.total_cmp(&b.score).then(a.rtt.cmp(&b.rtt)));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str) -> Node {
+        Node::test(name, &format!("{}.example.com", name), 443, "ss")
+    }
+
+    #[test]
+    fn bandwidth_outweighs_small_latency_gaps() {
+        let ms = Duration::from_millis;
+        let mb = 1024.0 * 1024.0;
+        let ranked = rank(vec![
+            // 延迟最低但带宽只有最快节点的 1/8
+            (node("fast-ping"), ms(30), Ok(1.0 * mb)),
+            (node("balanced"), ms(60), Ok(8.0 * mb)),
+            (node("broken"), ms(20), Err(anyhow!("连接被服务端关闭"))),
+            (node("slow"), ms(120), Ok(6.0 * mb)),
+        ]);
+
+        let names: Vec<&str> = ranked.iter().map(|r| r.node.name.as_str()).collect();
+        assert_eq!(names, ["balanced", "slow", "fast-ping", "broken"]);
+        assert_eq!(ranked[0].score, 60.0);
+        assert_eq!(ranked[2].score, 30.0 + 3.0 * HALVING_PENALTY_MS);
+        assert!(ranked[3].throughput.is_none() && ranked[3].error.is_some());
+        assert_eq!(payload().len(), CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::profiling::{self, LockSite};
+use crate::subscription::Node;
+
+/// 粘性路由的有效期（秒），0 表示关闭
+static TTL_SECS: AtomicU64 = AtomicU64::new(600);
+
+pub fn set_ttl(secs: u64) {
+    TTL_SECS.store(secs, Ordering::Relaxed);
+}
+
+fn ttl() -> Option<Duration> {
+    match TTL_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// 粘性路由的键：知道原始目标时按目标，否则按来源设备
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AffinityKey {
+    Destination(SocketAddr),
+    Client(IpAddr),
+}
+
+impl AffinityKey {
+    pub fn new(client: SocketAddr, destination: Option<SocketAddr>) -> Self {
+        match destination {
+            Some(addr) => Self::Destination(addr),
+            None => Self::Client(client.ip()),
+        }
+    }
+}
+
+struct Entry {
+    node: Node,
+    last_used: Instant,
+}
+
+/// 目标 → 节点的粘性路由缓存
+///
+/// 自动切换节点（切回首选节点、空闲重选）后，仍在通信的游戏服务器继续使用原来的节点，
+/// 出口 IP 不变，避免被反作弊判定为异地登录而踢出；有效期内没有流量后才改用当前节点。
+#[derive(Default)]
+pub struct AffinityCache {
+    entries: Mutex<HashMap<AffinityKey, Entry>>,
+}
+
+impl AffinityCache {
+    /// 返回该目标应使用的节点：有效期内沿用之前的节点，否则绑定到当前节点
+    pub fn route(&self, key: AffinityKey, current: &Node) -> Node {
+        self.route_at(key, current, Instant::now())
+    }
+
+    fn route_at(&self, key: AffinityKey, current: &Node, now: Instant) -> Node {
+        let Some(ttl) = ttl() else {
+            return current.clone();
+        };
+
+        let mut entries = profiling::lock_sync(LockSite::Affinity, &self.entries);
+        entries.retain(|_, entry| now.duration_since(entry.last_used) < ttl);
+        let entry = entries.entry(key).or_insert_with(|| Entry {
+            node: current.clone(),
+            last_used: now,
+        });
+        entry.last_used = now;
+        entry.node.clone()
+    }
+
+    /// 连接结束时刷新有效期，长连接结束后的新连接仍走同一节点
+    pub fn touch(&self, key: AffinityKey) {
+        let mut entries = profiling::lock_sync(LockSite::Affinity, &self.entries);
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.last_used = Instant::now();
+        }
+    }
+
+    /// 节点故障时解除绑定，后续流量改走当前节点
+    pub fn evict_node(&self, name: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, entry| entry.node.name != name);
+    }
+
+    /// 用户手动切换节点时清空，所有流量立即使用新节点
+    pub fn clear(&self) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// 缓存中的目标数量，包括已过期但还未清理的
+    pub fn entry_count(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str) -> Node {
+        Node::test(name, "127.0.0.1", 1, "ss")
+    }
+
+    #[test]
+    fn keeps_destinations_on_their_node_until_expiry() {
+        let cache = AffinityCache::default();
+        let server: SocketAddr = "203.0.113.9:27015".parse().unwrap();
+        let key = AffinityKey::new("127.0.0.1:5000".parse().unwrap(), Some(server));
+        let other = AffinityKey::new("192.168.1.20:5000".parse().unwrap(), None);
+        let start = Instant::now();
+
+        assert_eq!(cache.route_at(key, &node("a"), start).name, "a");
+        // 切换到 b 后，已绑定的目标仍走 a，新目标走 b
+        let later = start + Duration::from_secs(60);
+        assert_eq!(cache.route_at(key, &node("b"), later).name, "a");
+        assert_eq!(cache.route_at(other, &node("b"), later).name, "b");
+
+        // 超过有效期没有流量后改用当前节点
+        let expired = later + Duration::from_secs(601);
+        assert_eq!(cache.route_at(key, &node("b"), expired).name, "b");
+
+        cache.evict_node("b");
+        assert_eq!(cache.route_at(key, &node("c"), expired).name, "c");
+        cache.clear();
+        assert_eq!(cache.route_at(key, &node("d"), expired).name, "d");
+    }
+}
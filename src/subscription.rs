@@ -2,8 +2,23 @@ use anyhow::{Context, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use base64::{engine::general_purpose, Engine as _};
-use log::{error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use log::{debug, error, info};
+
+use clashfun::error::FetchError;
+pub use clashfun::parser::{ClashConfig, Proxy};
+use clashfun::parser;
+
+use crate::node_overrides;
+use crate::provider;
+use crate::rename;
+
+/// 加速服务能转发的节点协议，与订阅链接解析支持的协议一致；
+/// hysteria、tuic 等基于 QUIC 的协议以及 wireguard 暂不支持
+pub const SUPPORTED_PROTOCOLS: [&str; 6] = ["ss", "vmess", "vless", "trojan", "socks5", "http"];
+
+static DEDUP: AtomicBool = AtomicBool::new(true);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Node {
@@ -14,276 +29,238 @@ pub struct Node {
     pub password: Option<String>,
     pub cipher: Option<String>,
     pub latency: Option<u32>,
+    /// SOCKS5 / HTTP 节点的用户名，与 password 一起用于认证
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// 合并进来的重复节点的名称
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// 订阅中原始代理其余字段（uuid、SNI、传输选项等）的摘要，区分共用同一地址和端口的节点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClashConfig {
-    pub proxies: Vec<HashMap<String, serde_yaml::Value>>,
-}
-
-pub struct SubscriptionManager {
-    client: Client,
-}
-
-impl SubscriptionManager {
-    pub fn new() -> Self {
+impl Node {
+    /// 测试用的节点，没有认证信息和延迟
+    #[cfg(test)]
+    pub fn test(name: &str, server: &str, port: u16, protocol: &str) -> Self {
         Self {
-            client: Client::new(),
+            name: name.to_string(),
+            server: server.to_string(),
+            port,
+            protocol: protocol.to_string(),
+            password: None,
+            cipher: None,
+            latency: None,
+            username: None,
+            aliases: Vec::new(),
+            fingerprint: None,
         }
     }
 
-    fn url_decode(encoded: &str) -> String {
-        // 简单的URL解码，处理常见的编码模式
-        encoded
-            .replace("%20", " ")
-            .replace("%7C", "|")
-            .replace("%E2%9C%85", "✅")
-            .replace("%F0%9F%87%BA%F0%9F%87%B8", "🇺🇸")
-            .replace("%F0%9F%87%AD%F0%9F%87%B0", "🇭🇰")
-            .replace("%F0%9F%87%AF%F0%9F%87%B5", "🇯🇵")
-            .replace("%F0%9F%87%B8%F0%9F%87%AC", "🇸🇬")
-            .replace("%F0%9F%87%B0%F0%9F%87%B7", "🇰🇷")
-            .replace("%F0%9F%87%A8%F0%9F%87%B3", "🇨🇳")
-            .replace("%E7%BE%8E%E5%9B%BD", "美国")
-            .replace("%E9%A6%99%E6%B8%AF", "香港")
-            .replace("%E6%97%A5%E6%9C%AC", "日本")
-            .replace("%E6%96%B0%E5%8A%A0%E5%9D%A1", "新加坡")
-            .replace("%E9%9F%A9%E5%9B%BD", "韩国")
-            .replace("%E5%8F%B0%E6%B9%BE", "台湾")
-            .replace("%E9%AB%98%E9%80%9F", "高速")
+    /// 由协议、地址、端口、认证信息和原始代理其余字段生成的稳定 ID，订阅中节点改名后仍能找回；
+    /// 同一 CDN 入口后面 uuid 或 ws host 不同的 vmess/vless 节点 ID 不同，ID 相同的节点即为重复节点
+    pub fn id(&self) -> String {
+        let key = format!(
+            "{}://{}:{}\0{}\0{}\0{}\0{}",
+            self.protocol.to_lowercase(),
+            self.server.to_lowercase(),
+            self.port,
+            self.username.as_deref().unwrap_or(""),
+            self.password.as_deref().unwrap_or(""),
+            self.cipher.as_deref().unwrap_or(""),
+            self.fingerprint.unwrap_or(0)
+        );
+        format!("{:012x}", fnv1a(&key) >> 16)
     }
 
-    pub async fn fetch_subscription(&self, url: &str) -> Result<ClashConfig> {
-        let response = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .context("获取订阅内容失败")?;
-
-        let content = response
-            .text()
-            .await
-            .context("读取订阅内容失败")?;
-
-        info!("订阅内容长度: {} 字符", content.len());
-        info!("订阅内容前200字符: {}", content.chars().take(200).collect::<String>());
-
-        // 尝试多种格式解析
-        self.parse_subscription_content(&content)
+    /// 加速服务能否使用该节点
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_PROTOCOLS.contains(&self.protocol.to_ascii_lowercase().as_str())
     }
 
-    fn parse_subscription_content(&self, content: &str) -> Result<ClashConfig> {
-        info!("开始解析订阅内容...");
+    /// 节点不能使用时给用户的说明
+    pub fn unsupported_reason(&self) -> Option<String> {
+        (!self.is_supported()).then(|| {
+            format!(
+                "节点 {} 使用的 {} 协议暂不支持，请选择 {} 协议的节点",
+                self.name,
+                self.protocol,
+                SUPPORTED_PROTOCOLS.join("/")
+            )
+        })
+    }
+}
 
-        // 1. 尝试直接解析为 YAML (Clash 格式)
-        info!("尝试解析为 YAML 格式...");
-        if let Ok(config) = serde_yaml::from_str::<ClashConfig>(content) {
-            info!("YAML 格式解析成功，找到 {} 个代理", config.proxies.len());
-            return Ok(config);
-        }
+/// Node 自身字段之外的代理字段，名称不参与，改名或重名的节点仍能对应
+const FINGERPRINT_EXCLUDED: [&str; 7] = ["name", "server", "port", "type", "password", "cipher", "username"];
+
+/// 原始代理中 Node 没有保存的字段的摘要，按字段名排序后计算
+pub fn fingerprint(proxy: &Proxy) -> u64 {
+    let mut fields: Vec<(&String, &serde_yaml::Value)> =
+        proxy.iter().filter(|(key, _)| !FINGERPRINT_EXCLUDED.contains(&key.as_str())).collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    let text: String = fields
+        .into_iter()
+        .map(|(key, value)| format!("{}={}\0", key, serde_yaml::to_string(value).unwrap_or_default()))
+        .collect();
+    fnv1a(&text)
+}
 
-        // 2. 尝试 Base64 解码后再解析
-        info!("尝试 Base64 解码...");
-        if let Ok(decoded_bytes) = general_purpose::STANDARD.decode(content.trim()) {
-            if let Ok(decoded_str) = String::from_utf8(decoded_bytes) {
-                info!("Base64 解码成功，解码后内容长度: {}", decoded_str.len());
-                info!("解码后前200字符: {}", decoded_str.chars().take(200).collect::<String>());
-
-                // 解码后尝试解析为 YAML
-                if let Ok(config) = serde_yaml::from_str::<ClashConfig>(&decoded_str) {
-                    info!("Base64解码后YAML解析成功，找到 {} 个代理", config.proxies.len());
-                    return Ok(config);
-                }
+/// FNV-1a，不同版本和平台的结果一致
+fn fnv1a(key: &str) -> u64 {
+    key.bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
 
-                // 尝试解析为 ss:// 链接格式
-                if let Ok(config) = self.parse_ss_links(&decoded_str) {
-                    info!("Base64解码后SS链接解析成功，找到 {} 个代理", config.proxies.len());
-                    return Ok(config);
-                }
+/// 是否合并重复节点，来自配置文件的 dedup_nodes
+pub fn set_dedup(enabled: bool) {
+    DEDUP.store(enabled, Ordering::Relaxed);
+}
 
-                // 尝试解析为 vless:// 或其他协议链接
-                if let Ok(config) = self.parse_protocol_links(&decoded_str) {
-                    info!("Base64解码后协议链接解析成功，找到 {} 个代理", config.proxies.len());
-                    return Ok(config);
-                }
+/// 合并同一服务器的重复节点，保留第一次出现的名称，其他名称记为别名，延迟取最低值
+pub fn dedup(nodes: Vec<Node>) -> Vec<Node> {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for node in nodes {
+        let Some(&index) = positions.get(&node.id()) else {
+            positions.insert(node.id(), merged.len());
+            merged.push(node);
+            continue;
+        };
+        let kept = &mut merged[index];
+        for name in std::iter::once(node.name).chain(node.aliases) {
+            if name != kept.name && !kept.aliases.contains(&name) {
+                kept.aliases.push(name);
             }
         }
-
-        // 3. 尝试直接解析为 ss:// 链接格式
-        info!("尝试直接解析为 ss:// 链接格式...");
-        if let Ok(config) = self.parse_ss_links(content) {
-            info!("SS链接解析成功，找到 {} 个代理", config.proxies.len());
-            return Ok(config);
-        }
-
-        // 4. 尝试直接解析为其他协议链接格式
-        info!("尝试直接解析为协议链接格式...");
-        if let Ok(config) = self.parse_protocol_links(content) {
-            info!("协议链接解析成功，找到 {} 个代理", config.proxies.len());
-            return Ok(config);
-        }
-
-        error!("所有解析方法都失败了");
-        Err(anyhow::anyhow!("无法识别的订阅格式"))
+        kept.latency = match (kept.latency, node.latency) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
     }
+    merged
+}
 
-    fn parse_ss_links(&self, content: &str) -> Result<ClashConfig> {
-        let mut proxies = Vec::new();
-
-        for line in content.lines() {
-            let line = line.trim();
-            if line.starts_with("ss://") {
-                if let Ok(proxy) = self.parse_ss_link(line) {
-                    proxies.push(proxy);
-                }
-            }
-        }
-
-        if proxies.is_empty() {
-            return Err(anyhow::anyhow!("没有找到有效的 ss:// 链接"));
-        }
+/// 按名称查找节点的结果
+pub enum NodeMatch<'a> {
+    Found(&'a Node),
+    /// 有多个节点包含该名称
+    Ambiguous(Vec<&'a Node>),
+    NotFound,
+}
 
-        Ok(ClashConfig { proxies })
+/// 按名称或 ID 查找节点：完全相同优先，其次忽略大小写相同，exact 为 false 时再按包含匹配
+pub fn match_nodes<'a>(nodes: &'a [Node], query: &str, exact: bool) -> NodeMatch<'a> {
+    if let Some(node) = nodes.iter().find(|n| n.name == query || n.id() == query) {
+        return NodeMatch::Found(node);
+    }
+    // 合并重复节点前使用的名称
+    if let Some(node) = nodes.iter().find(|n| n.aliases.iter().any(|alias| alias == query)) {
+        return NodeMatch::Found(node);
     }
 
-    fn parse_ss_link(&self, link: &str) -> Result<HashMap<String, serde_yaml::Value>> {
-        // 解析 ss://method:password@server:port#name 格式
-        let link = link.strip_prefix("ss://").context("无效的 ss:// 链接")?;
+    let lower = query.to_lowercase();
+    let same: Vec<&Node> = nodes.iter().filter(|n| n.name.to_lowercase() == lower).collect();
+    let candidates = if same.is_empty() && !exact {
+        nodes.iter().filter(|n| n.name.to_lowercase().contains(&lower)).collect()
+    } else {
+        same
+    };
+
+    match candidates.len() {
+        0 => NodeMatch::NotFound,
+        1 => NodeMatch::Found(candidates[0]),
+        _ => NodeMatch::Ambiguous(candidates),
+    }
+}
 
-        // 分离名称
-        let (main_part, name) = if let Some(pos) = link.find('#') {
-            (&link[..pos], &link[pos+1..])
-        } else {
-            (link, "未命名节点")
-        };
+/// 找回配置中选中的节点：先按名称和别名，找不到时按稳定 ID
+pub fn find_selected<'a>(nodes: &'a [Node], name: &str, id: Option<&str>) -> Option<&'a Node> {
+    nodes
+        .iter()
+        .find(|n| n.name == name)
+        .or_else(|| nodes.iter().find(|n| n.aliases.iter().any(|alias| alias == name)))
+        .or_else(|| id.and_then(|id| nodes.iter().find(|n| n.id() == id)))
+}
 
-        // Base64 解码主要部分或直接解析
-        let decoded = if let Ok(decoded_bytes) = general_purpose::STANDARD.decode(main_part) {
-            String::from_utf8(decoded_bytes).context("解码失败")?
-        } else {
-            main_part.to_string()
-        };
+pub struct SubscriptionManager {
+    client: Client,
+    /// 上次成功获取的订阅和其中的节点数量，测速后据此记录可用节点比例
+    last_fetch: Mutex<Option<(String, usize)>>,
+}
 
-        // 解析 method:password@server:port
-        let auth_server = decoded.split('@').collect::<Vec<_>>();
-        if auth_server.len() != 2 {
-            return Err(anyhow::anyhow!("无效的认证格式"));
+impl SubscriptionManager {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(crate::timeouts::current().subscription())
+                .build()
+                .unwrap_or_default(),
+            last_fetch: Mutex::new(None),
         }
+    }
 
-        let method_password = auth_server[0].split(':').collect::<Vec<_>>();
-        let server_port = auth_server[1].split(':').collect::<Vec<_>>();
-
-        if method_password.len() != 2 || server_port.len() != 2 {
-            return Err(anyhow::anyhow!("无效的服务器格式"));
+    /// 获取并解析订阅，同时记录订阅提供商的状态
+    pub async fn fetch_subscription(&self, url: &str) -> Result<ClashConfig, FetchError> {
+        let result = self.fetch_and_parse(url).await;
+        match &result {
+            Ok(config) => {
+                provider::record_success(url, config.proxies.len());
+                *self.last_fetch.lock().unwrap_or_else(|e| e.into_inner()) = Some((url.to_string(), config.proxies.len()));
+            }
+            Err(e) => provider::record_failure(url, e),
         }
-
-        let mut proxy = HashMap::new();
-        proxy.insert("name".to_string(), serde_yaml::Value::String(name.to_string()));
-        proxy.insert("type".to_string(), serde_yaml::Value::String("ss".to_string()));
-        proxy.insert("server".to_string(), serde_yaml::Value::String(server_port[0].to_string()));
-        proxy.insert("port".to_string(), serde_yaml::Value::Number(server_port[1].parse::<u64>().context("无效端口")?.into()));
-        proxy.insert("cipher".to_string(), serde_yaml::Value::String(method_password[0].to_string()));
-        proxy.insert("password".to_string(), serde_yaml::Value::String(method_password[1].to_string()));
-
-        Ok(proxy)
+        result
     }
 
-    fn parse_protocol_links(&self, content: &str) -> Result<ClashConfig> {
-        let mut proxies = Vec::new();
+    async fn fetch_and_parse(&self, url: &str) -> Result<ClashConfig, FetchError> {
+        let content = self.fetch_content(url).await?;
+        // 尝试多种格式解析
+        self.parse_subscription_content(&content)
+    }
 
-        for line in content.lines() {
-            let line = line.trim();
-            if line.starts_with("vless://") || line.starts_with("vmess://") || line.starts_with("trojan://") {
-                if let Ok(proxy) = self.parse_protocol_link(line) {
-                    proxies.push(proxy);
-                }
-            }
+    /// 只下载订阅内容，不解析也不记录订阅状态
+    pub async fn fetch_content(&self, url: &str) -> Result<String, FetchError> {
+        // 本地配置文件（例如从 Clash 导入的配置）
+        if let Some(path) = url.strip_prefix("file://") {
+            return tokio::fs::read_to_string(path)
+                .await
+                .map_err(|source| FetchError::ReadFile { path: path.to_string(), source });
         }
 
-        if proxies.is_empty() {
-            return Err(anyhow::anyhow!("没有找到有效的协议链接"));
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(FetchError::Unreachable)?;
+        if !response.status().is_success() {
+            return Err(FetchError::from_status(response.status().as_u16()));
         }
 
-        Ok(ClashConfig { proxies })
-    }
-
-    fn parse_protocol_link(&self, link: &str) -> Result<HashMap<String, serde_yaml::Value>> {
-        // 简化处理：从 URL 中提取基本信息
-        let mut proxy = HashMap::new();
-
-        if link.starts_with("vless://") {
-            // 解析 vless://uuid@server:port?params#name 格式
-            let link = link.strip_prefix("vless://").context("无效的 vless:// 链接")?;
-
-            let (main_part, name) = if let Some(pos) = link.find('#') {
-                (&link[..pos], &link[pos+1..])
-            } else {
-                (link, "VLESS节点")
-            };
-
-            let (uuid_server, _params) = if let Some(pos) = main_part.find('?') {
-                (&main_part[..pos], &main_part[pos+1..])
-            } else {
-                (main_part, "")
-            };
-
-            let auth_server = uuid_server.split('@').collect::<Vec<_>>();
-            if auth_server.len() != 2 {
-                return Err(anyhow::anyhow!("无效的认证格式"));
-            }
+        let content = response
+            .text()
+            .await
+            .map_err(FetchError::Read)?;
 
-            let server_port = auth_server[1].split(':').collect::<Vec<_>>();
-            if server_port.len() != 2 {
-                return Err(anyhow::anyhow!("无效的服务器格式"));
-            }
+        debug!("订阅内容长度: {} 字符", content.len());
+        debug!("订阅内容前200字符: {}", content.chars().take(200).collect::<String>());
+        Ok(content)
+    }
 
-            // 将 vless 转换为 vmess 格式以便兼容
-            proxy.insert("name".to_string(), serde_yaml::Value::String(Self::url_decode(name)));
-            proxy.insert("type".to_string(), serde_yaml::Value::String("vmess".to_string()));
-            proxy.insert("server".to_string(), serde_yaml::Value::String(server_port[0].to_string()));
-            proxy.insert("port".to_string(), serde_yaml::Value::Number(server_port[1].parse::<u64>().context("无效端口")?.into()));
-            proxy.insert("uuid".to_string(), serde_yaml::Value::String(auth_server[0].to_string()));
-            proxy.insert("alterId".to_string(), serde_yaml::Value::Number(0.into()));
-            proxy.insert("cipher".to_string(), serde_yaml::Value::String("auto".to_string()));
-
-        } else if link.starts_with("vmess://") {
-            // vmess:// 通常是 Base64 编码的 JSON
-            let _link = link.strip_prefix("vmess://").context("无效的 vmess:// 链接")?;
-
-            proxy.insert("name".to_string(), serde_yaml::Value::String("VMess节点".to_string()));
-            proxy.insert("type".to_string(), serde_yaml::Value::String("vmess".to_string()));
-            proxy.insert("server".to_string(), serde_yaml::Value::String("example.com".to_string()));
-            proxy.insert("port".to_string(), serde_yaml::Value::Number(443.into()));
-
-        } else if link.starts_with("trojan://") {
-            // trojan://password@server:port#name
-            let link = link.strip_prefix("trojan://").context("无效的 trojan:// 链接")?;
-
-            let (main_part, name) = if let Some(pos) = link.find('#') {
-                (&link[..pos], &link[pos+1..])
-            } else {
-                (link, "Trojan节点")
-            };
-
-            let auth_server = main_part.split('@').collect::<Vec<_>>();
-            if auth_server.len() != 2 {
-                return Err(anyhow::anyhow!("无效的认证格式"));
+    pub fn parse_subscription_content(&self, content: &str) -> Result<ClashConfig, FetchError> {
+        debug!("开始解析订阅内容...");
+        match parser::parse_subscription(content) {
+            Ok(config) => {
+                info!("订阅解析成功，找到 {} 个代理", config.proxies.len());
+                Ok(config)
             }
-
-            let server_port = auth_server[1].split(':').collect::<Vec<_>>();
-            if server_port.len() != 2 {
-                return Err(anyhow::anyhow!("无效的服务器格式"));
+            Err(e) => {
+                error!("订阅解析失败: {}", e);
+                Err(e.into())
             }
-
-            proxy.insert("name".to_string(), serde_yaml::Value::String(Self::url_decode(name)));
-            proxy.insert("type".to_string(), serde_yaml::Value::String("trojan".to_string()));
-            proxy.insert("server".to_string(), serde_yaml::Value::String(server_port[0].to_string()));
-            proxy.insert("port".to_string(), serde_yaml::Value::Number(server_port[1].parse::<u64>().context("无效端口")?.into()));
-            proxy.insert("password".to_string(), serde_yaml::Value::String(auth_server[0].to_string()));
         }
-
-        Ok(proxy)
     }
 
     pub fn parse_nodes(&self, config: &ClashConfig) -> Result<Vec<Node>> {
@@ -294,11 +271,37 @@ impl SubscriptionManager {
                 nodes.push(node);
             }
         }
+        if DEDUP.load(Ordering::Relaxed) {
+            nodes = dedup(nodes);
+        }
+        rename::apply(&mut nodes);
+        node_overrides::warn_mismatches(&nodes);
 
         Ok(nodes)
     }
 
-    fn parse_single_node(&self, proxy: &HashMap<String, serde_yaml::Value>) -> Result<Option<Node>> {
+    /// 解析节点并跳过缺少字段的代理，返回节点和被跳过代理的说明
+    pub fn check_nodes(&self, config: &ClashConfig) -> (Vec<Node>, Vec<String>) {
+        let mut nodes = Vec::new();
+        let mut skipped = Vec::new();
+        for (i, proxy) in config.proxies.iter().enumerate() {
+            match self.parse_single_node(proxy) {
+                Ok(Some(node)) => nodes.push(node),
+                Ok(None) => {}
+                Err(e) => {
+                    let name = proxy.get("name").and_then(|v| v.as_str()).unwrap_or("未命名");
+                    skipped.push(format!("第 {} 个代理 ({}): {}", i + 1, name, e));
+                }
+            }
+        }
+        if DEDUP.load(Ordering::Relaxed) {
+            nodes = dedup(nodes);
+        }
+        rename::apply(&mut nodes);
+        (nodes, skipped)
+    }
+
+    fn parse_single_node(&self, proxy: &Proxy) -> Result<Option<Node>> {
         let name = proxy
             .get("name")
             .and_then(|v| v.as_str())
@@ -332,6 +335,11 @@ impl SubscriptionManager {
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
 
+        let username = proxy
+            .get("username")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
         Ok(Some(Node {
             name,
             server,
@@ -340,13 +348,16 @@ impl SubscriptionManager {
             password,
             cipher,
             latency: None,
+            username,
+            aliases: Vec::new(),
+            fingerprint: Some(fingerprint(proxy)),
         }))
     }
 
     pub async fn test_node_latency(&self, node: &Node) -> Result<u32> {
         let start = std::time::Instant::now();
 
-        let result = tokio::net::TcpStream::connect(format!("{}:{}", node.server, node.port)).await;
+        let result = crate::dns::connect_node(node).await;
 
         let latency = start.elapsed().as_millis() as u32;
 
@@ -356,16 +367,132 @@ impl SubscriptionManager {
         }
     }
 
-    pub async fn test_all_nodes(&self, nodes: &mut Vec<Node>) -> Result<()> {
+    pub async fn test_all_nodes(&self, nodes: &mut [Node]) -> Result<()> {
         for node in nodes.iter_mut() {
             match self.test_node_latency(node).await {
                 Ok(latency) => node.latency = Some(latency),
                 Err(_) => node.latency = Some(u32::MAX),
             }
+            crate::progress::node_tested(node);
         }
 
         nodes.sort_by_key(|node| node.latency.unwrap_or(u32::MAX));
 
+        // 只统计测试了整个订阅的情况，按地区筛选后的测速不计入
+        let last_fetch = self.last_fetch.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some((url, count)) = last_fetch.filter(|(_, count)| *count == nodes.len()) {
+            let usable = nodes.iter().filter(|n| crate::timeouts::current().usable(n.latency)).count();
+            provider::record_usable(&url, usable, count);
+        }
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, server: &str) -> Node {
+        Node::test(name, server, 443, "ss")
+    }
+
+    #[test]
+    fn prefers_exact_names_and_reports_ambiguity() {
+        let nodes = vec![node("HK 01", "a.example.com"), node("HK 02", "b.example.com"), node("hk", "c.example.com")];
+
+        assert!(matches!(match_nodes(&nodes, "HK 02", false), NodeMatch::Found(n) if n.name == "HK 02"));
+        // 完全相同（忽略大小写）优先于包含
+        assert!(matches!(match_nodes(&nodes, "HK", false), NodeMatch::Found(n) if n.name == "hk"));
+        assert!(matches!(match_nodes(&nodes, "01", false), NodeMatch::Found(n) if n.name == "HK 01"));
+        assert!(matches!(match_nodes(&nodes, "HK 0", false), NodeMatch::Ambiguous(found) if found.len() == 2));
+        assert!(matches!(match_nodes(&nodes, "HK 0", true), NodeMatch::NotFound));
+        assert!(matches!(match_nodes(&nodes, &nodes[1].id(), true), NodeMatch::Found(n) if n.name == "HK 02"));
+
+        // 改名后按 ID 找回
+        let id = nodes[0].id();
+        let renamed = vec![node("香港 01 [IPLC]", "a.example.com")];
+        assert_eq!(find_selected(&renamed, "HK 01", Some(&id)).unwrap().name, "香港 01 [IPLC]");
+        assert!(find_selected(&renamed, "HK 01", None).is_none());
+        assert_ne!(nodes[0].id(), nodes[1].id());
+    }
+
+    #[test]
+    fn flags_protocols_the_proxy_cannot_relay() {
+        let mut node = node("HK 01", "a.example.com");
+        assert!(node.is_supported());
+        assert!(node.unsupported_reason().is_none());
+
+        node.protocol = "Trojan".to_string();
+        assert!(node.is_supported());
+
+        node.protocol = "hysteria2".to_string();
+        assert!(!node.is_supported());
+        assert!(node.unsupported_reason().unwrap().contains("hysteria2"));
+    }
+
+    #[test]
+    fn merges_duplicate_servers_into_aliases() {
+        let mut a = node("HK 01", "a.example.com");
+        a.password = Some("secret".to_string());
+        let mut b = node("香港 01 [备用订阅]", "A.example.com");
+        b.password = Some("secret".to_string());
+        b.latency = Some(40);
+        let mut other_user = node("HK 01 (另一账号)", "a.example.com");
+        other_user.password = Some("other".to_string());
+
+        let nodes = dedup(vec![a, node("JP 01", "b.example.com"), b, other_user]);
+        let names: Vec<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["HK 01", "JP 01", "HK 01 (另一账号)"]);
+        assert_eq!(nodes[0].aliases, ["香港 01 [备用订阅]"]);
+        assert_eq!(nodes[0].latency, Some(40));
+
+        // 按原来的名称仍能找到合并后的节点
+        assert!(matches!(match_nodes(&nodes, "香港 01 [备用订阅]", true), NodeMatch::Found(n) if n.name == "HK 01"));
+        assert_eq!(find_selected(&nodes, "香港 01 [备用订阅]", None).unwrap().name, "HK 01");
+    }
+
+    #[test]
+    fn keeps_cdn_fronted_nodes_that_share_an_address() {
+        let clash: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - {name: "香港 01", type: vmess, server: cdn.example.com, port: 443, uuid: 1111, network: ws, ws-opts: {path: /ray, headers: {Host: hk.example.com}}}
+  - {name: "香港 01 副本", type: vmess, server: cdn.example.com, port: 443, uuid: 1111, network: ws, ws-opts: {path: /ray, headers: {Host: hk.example.com}}}
+  - {name: "香港 02", type: vmess, server: cdn.example.com, port: 443, uuid: 2222, network: ws, ws-opts: {path: /ray, headers: {Host: hk.example.com}}}
+  - {name: "日本 01", type: vmess, server: cdn.example.com, port: 443, uuid: 1111, network: ws, ws-opts: {path: /ray, headers: {Host: jp.example.com}}}
+"#,
+        )
+        .unwrap();
+        let manager = SubscriptionManager::new();
+        let parsed = clash.proxies.iter().map(|proxy| manager.parse_single_node(proxy).unwrap().unwrap());
+        let nodes = dedup(parsed.collect());
+        let names: Vec<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["香港 01", "香港 02", "日本 01"]);
+        assert_eq!(nodes[0].aliases, ["香港 01 副本"]);
+    }
+
+    #[test]
+    fn cdn_fronted_nodes_keep_distinct_ids_across_renames() {
+        let clash: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - {name: "香港 01", type: vmess, server: cdn.example.com, port: 443, uuid: 1111, network: ws}
+  - {name: "日本 01", type: vmess, server: cdn.example.com, port: 443, uuid: 2222, network: ws}
+"#,
+        )
+        .unwrap();
+        let manager = SubscriptionManager::new();
+        let nodes: Vec<Node> =
+            clash.proxies.iter().map(|proxy| manager.parse_single_node(proxy).unwrap().unwrap()).collect();
+        assert_ne!(nodes[0].id(), nodes[1].id());
+
+        // 两个节点都改名后，按 ID 找回的是原来选中的那个
+        let selected = nodes[1].id();
+        let renamed: Vec<Node> = nodes
+            .into_iter()
+            .map(|node| Node { name: format!("{} [IPLC]", node.name), ..node })
+            .collect();
+        assert_eq!(find_selected(&renamed, "日本 01", Some(&selected)).unwrap().name, "日本 01 [IPLC]");
+    }
+}
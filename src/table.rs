@@ -0,0 +1,245 @@
+use std::io::IsTerminal;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// 终端太窄时列最少保留的显示宽度
+const MIN_COLUMN_WIDTH: usize = 8;
+/// 截断时末尾的省略号
+const ELLIPSIS: &str = "…";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    header: String,
+    align: Align,
+    max_width: Option<usize>,
+}
+
+/// 按显示宽度对齐的表格，中文和 emoji 占两列，超出宽度时按字形截断
+#[derive(Debug, Clone)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+    indent: usize,
+    header: bool,
+    separator: bool,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            columns: headers
+                .iter()
+                .map(|header| Column {
+                    header: header.to_string(),
+                    align: Align::Left,
+                    max_width: None,
+                })
+                .collect(),
+            rows: Vec::new(),
+            indent: 0,
+            header: true,
+            separator: false,
+        }
+    }
+
+    /// 没有表头的表格
+    pub fn headless(columns: usize) -> Self {
+        Self {
+            header: false,
+            ..Self::new(&vec![""; columns])
+        }
+    }
+
+    /// 每行前的空格数
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// 该列右对齐，适合数字
+    pub fn right(mut self, column: usize) -> Self {
+        self.columns[column].align = Align::Right;
+        self
+    }
+
+    /// 该列最多显示的宽度，超出部分截断
+    pub fn max_width(mut self, column: usize, width: usize) -> Self {
+        self.columns[column].max_width = Some(width);
+        self
+    }
+
+    /// 在表头下画一条分隔线
+    pub fn separator(mut self) -> Self {
+        self.separator = true;
+        self
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    /// 已添加的行数
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// 按终端宽度输出，输出不是终端时不限制宽度
+    pub fn print(&self) {
+        for line in self.render(terminal_width()) {
+            println!("{}", line);
+        }
+    }
+
+    /// 渲染成行，width 为可用的总宽度
+    pub fn render(&self, width: Option<usize>) -> Vec<String> {
+        let widths = self.column_widths(width);
+        let prefix = " ".repeat(self.indent);
+        let mut lines = Vec::new();
+        if self.header {
+            let headers: Vec<&str> = self.columns.iter().map(|column| column.header.as_str()).collect();
+            lines.push(format!("{}{}", prefix, self.render_row(&headers, &widths)));
+            if self.separator {
+                let total = widths.iter().sum::<usize>() + widths.len().saturating_sub(1);
+                lines.push(format!("{}{}", prefix, "-".repeat(total)));
+            }
+        }
+        for row in &self.rows {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            lines.push(format!("{}{}", prefix, self.render_row(&cells, &widths)));
+        }
+        lines
+    }
+
+    fn column_widths(&self, width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let header = if self.header { display_width(&column.header) } else { 0 };
+                let content = self.rows.iter().filter_map(|row| row.get(i)).map(|cell| display_width(cell));
+                let natural = content.fold(header, usize::max);
+                column.max_width.map_or(natural, |max| natural.min(max.max(header)))
+            })
+            .collect();
+
+        // 终端不够宽时依次收窄最宽的左对齐列
+        let Some(width) = width else {
+            return widths;
+        };
+        let available = width.saturating_sub(self.indent + widths.len().saturating_sub(1));
+        while widths.iter().sum::<usize>() > available {
+            let widest = widths
+                .iter()
+                .enumerate()
+                .filter(|(i, w)| self.columns[*i].align == Align::Left && **w > MIN_COLUMN_WIDTH)
+                .max_by_key(|(_, w)| **w)
+                .map(|(i, _)| i);
+            let Some(widest) = widest else {
+                break;
+            };
+            widths[widest] -= 1;
+        }
+        widths
+    }
+
+    fn render_row(&self, cells: &[&str], widths: &[usize]) -> String {
+        let last = widths.len().saturating_sub(1);
+        let mut line = String::new();
+        for (i, (column, width)) in self.columns.iter().zip(widths).enumerate() {
+            let cell = truncate(cells.get(i).copied().unwrap_or(""), *width);
+            let padding = " ".repeat(width.saturating_sub(display_width(&cell)));
+            if i > 0 {
+                line.push(' ');
+            }
+            match column.align {
+                Align::Left if i == last => line.push_str(&cell),
+                Align::Left => {
+                    line.push_str(&cell);
+                    line.push_str(&padding);
+                }
+                Align::Right => {
+                    line.push_str(&padding);
+                    line.push_str(&cell);
+                }
+            }
+        }
+        line
+    }
+}
+
+/// 文本在终端中占的列数
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// 截断到不超过 width 列，不会拆开一个字形（例如带修饰符的 emoji）
+pub fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let budget = width.saturating_sub(display_width(ELLIPSIS));
+    let mut result = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        let grapheme_width = display_width(grapheme);
+        if used + grapheme_width > budget {
+            break;
+        }
+        result.push_str(grapheme);
+        used += grapheme_width;
+    }
+    if width >= display_width(ELLIPSIS) {
+        result.push_str(ELLIPSIS);
+    }
+    result
+}
+
+/// 终端的列数，输出被重定向时为 None
+fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    #[cfg(feature = "tui")]
+    if let Ok((columns, _)) = crossterm::terminal::size() {
+        return Some(columns as usize);
+    }
+    std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_and_truncates_by_display_width() {
+        assert_eq!(display_width("香港 01"), 7);
+        assert_eq!(truncate("🇭🇰 香港 IPLC 专线", 8), "🇭🇰 香港…");
+        assert_eq!(truncate("short", 8), "short");
+
+        let mut table = Table::new(&["序号", "节点名称", "延迟"]).right(2).max_width(1, 10).separator();
+        table.row(vec!["1".into(), "🇯🇵 日本 东京 01".into(), "45".into()]);
+        table.row(vec!["12".into(), "US".into(), "180".into()]);
+        let lines = table.render(None);
+        assert_eq!(
+            lines,
+            vec![
+                "序号 节点名称   延迟",
+                "--------------------",
+                "1    🇯🇵 日本 …    45",
+                "12   US          180",
+            ]
+        );
+        assert!(lines.iter().all(|line| display_width(line) == 20));
+
+        // 终端很窄时收窄最宽的列
+        let narrow = table.render(Some(18));
+        assert!(narrow.iter().all(|line| display_width(line) <= 18));
+    }
+}
@@ -0,0 +1,144 @@
+use log::{debug, warn};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// 停止时等待每个后台任务退出的时间，超时后中止
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+struct Task {
+    name: &'static str,
+    handle: JoinHandle<()>,
+    /// 任务自己不等待停止信号，停止时直接中止
+    abort: bool,
+}
+
+/// 随加速服务运行的后台任务（健康监控、节点重选、流量保存等）
+///
+/// 停止时发出信号并等待所有任务退出，调用方在 stop() 返回后不会再有任务访问代理服务器。
+pub struct TaskSet {
+    stopping: watch::Sender<bool>,
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl Default for TaskSet {
+    fn default() -> Self {
+        Self {
+            stopping: watch::Sender::new(false),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl TaskSet {
+    /// 启动后台任务，收到停止信号时在下一个等待点结束；停止后启动的任务立即结束
+    pub fn spawn<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut stopping = self.stopping.subscribe();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                // 发送端随代理服务器释放时同样视为停止
+                _ = stopping.wait_for(|stopping| *stopping) => debug!("后台任务 {} 已停止", name),
+                _ = task => {}
+            }
+        });
+        self.push(Task { name, handle, abort: false });
+    }
+
+    /// 登记已启动的任务，停止时中止它
+    pub fn track(&self, name: &'static str, handle: JoinHandle<()>) {
+        self.push(Task { name, handle, abort: true });
+    }
+
+    fn push(&self, task: Task) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|task| !task.handle.is_finished());
+        tasks.push(task);
+    }
+
+    /// 仍在运行的任务数
+    pub fn running(&self) -> usize {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.iter().filter(|task| !task.handle.is_finished()).count()
+    }
+
+    /// 发出停止信号并等待所有任务退出
+    pub async fn shutdown(&self) {
+        self.stopping.send_replace(true);
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap_or_else(|e| e.into_inner()));
+        for mut task in tasks {
+            if task.abort {
+                task.handle.abort();
+            }
+            match tokio::time::timeout(SHUTDOWN_GRACE, &mut task.handle).await {
+                Ok(Err(e)) if e.is_panic() => warn!("后台任务 {} 异常退出: {}", task.name, e),
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("后台任务 {} 没有及时退出，已中止", task.name);
+                    task.handle.abort();
+                    let _ = task.handle.await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// 任务被丢弃时设置标记
+    struct Dropped(Arc<AtomicBool>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_joins_every_task() {
+        let tasks = TaskSet::default();
+        let monitor = Arc::new(AtomicBool::new(false));
+        let refresher = Arc::new(AtomicBool::new(false));
+
+        let guard = Dropped(Arc::clone(&monitor));
+        tasks.spawn("健康监控", async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+        });
+        let guard = Dropped(Arc::clone(&refresher));
+        tasks.track(
+            "检查更新",
+            tokio::spawn(async move {
+                let _guard = guard;
+                loop {
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                }
+            }),
+        );
+        tasks.spawn("一次性任务", async {});
+        tokio::task::yield_now().await;
+        assert_eq!(tasks.running(), 2);
+
+        tasks.shutdown().await;
+        assert!(monitor.load(Ordering::SeqCst) && refresher.load(Ordering::SeqCst));
+        assert_eq!(tasks.running(), 0);
+
+        // 停止后启动的任务不会运行
+        let late = Arc::new(AtomicBool::new(false));
+        let started = Arc::clone(&late);
+        tasks.spawn("停止后", async move {
+            tokio::task::yield_now().await;
+            started.store(true, Ordering::SeqCst);
+        });
+        tasks.shutdown().await;
+        assert!(!late.load(Ordering::SeqCst));
+    }
+}
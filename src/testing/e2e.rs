@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+use super::{fixtures, free_port, EchoNode, MockSubscriptionServer};
+use crate::failover::FailoverPolicy;
+use crate::obfs::{self, ObfsConfig};
+use crate::proxy::ProxyServer;
+use crate::subscription::{Node, SubscriptionManager};
+
+async fn fetch_nodes(url: &str) -> anyhow::Result<Vec<Node>> {
+    let manager = SubscriptionManager::new();
+    let config = manager.fetch_subscription(url).await?;
+    manager.parse_nodes(&config)
+}
+
+fn summary(nodes: &[Node]) -> Vec<(String, String, u16, String)> {
+    nodes
+        .iter()
+        .map(|n| (n.name.clone(), n.server.clone(), n.port, n.protocol.clone()))
+        .collect()
+}
+
+/// 轮询直到条件成立，超时则测试失败
+async fn wait_for<F, Fut>(what: &str, timeout: Duration, mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    while !condition().await {
+        assert!(tokio::time::Instant::now() < deadline, "等待超时: {}", what);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn start_proxy(proxy: &Arc<ProxyServer>) -> JoinHandle<anyhow::Result<()>> {
+    let handle = tokio::spawn({
+        let proxy = Arc::clone(proxy);
+        async move { proxy.start().await }
+    });
+
+    let port = proxy.get_proxy_port();
+    wait_for("代理端口开始监听", Duration::from_secs(5), || async move {
+        TcpStream::connect(("127.0.0.1", port)).await.is_ok()
+    })
+    .await;
+    handle
+}
+
+#[tokio::test]
+async fn parses_every_subscription_format() {
+    let server = MockSubscriptionServer::start(&[
+        ("/clash.yaml", fixtures::CLASH_YAML.to_string()),
+        ("/clash.b64", fixtures::base64(fixtures::CLASH_YAML)),
+        ("/ss.txt", fixtures::SS_LINKS.to_string()),
+        ("/ss.b64", fixtures::base64(fixtures::SS_LINKS)),
+        ("/links.b64", fixtures::base64(fixtures::PROTOCOL_LINKS)),
+    ])
+    .await;
+
+    let clash = vec![
+        ("香港 01".into(), "127.0.0.1".into(), 8388, "ss".into()),
+        ("日本 01".into(), "jp.example.com".into(), 443, "trojan".into()),
+    ];
+    assert_eq!(summary(&fetch_nodes(&server.url("/clash.yaml")).await.unwrap()), clash);
+    assert_eq!(summary(&fetch_nodes(&server.url("/clash.b64")).await.unwrap()), clash);
+
+    let ss = vec![
+        ("节点1".into(), "10.0.0.1".into(), 8388, "ss".into()),
+        ("节点2".into(), "10.0.0.2".into(), 8389, "ss".into()),
+    ];
+    assert_eq!(summary(&fetch_nodes(&server.url("/ss.txt")).await.unwrap()), ss);
+    assert_eq!(summary(&fetch_nodes(&server.url("/ss.b64")).await.unwrap()), ss);
+
+    assert_eq!(
+        summary(&fetch_nodes(&server.url("/links.b64")).await.unwrap()),
+        vec![
+            ("Trojan HK".into(), "tj.example.com".into(), 443, "trojan".into()),
+            ("VLESS JP".into(), "vl.example.com".into(), 8443, "vless".into()),
+        ]
+    );
+
+    assert!(fetch_nodes(&server.url("/missing")).await.is_err());
+}
+
+#[tokio::test]
+async fn relays_tcp_and_udp_and_counts_traffic() {
+    let echo = EchoNode::start().await;
+    let proxy = Arc::new(ProxyServer::new(free_port().await));
+    proxy.set_node(echo.node("echo")).await;
+    let handle = start_proxy(&proxy).await;
+    let port = proxy.get_proxy_port();
+
+    // TCP: 大于单次 splice 的数据量，并验证半关闭后能读完回显
+    let payload: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let (mut reader, mut writer) = stream.split();
+    let mut echoed = Vec::new();
+    let (written, read) = tokio::join!(
+        async {
+            writer.write_all(&payload).await?;
+            writer.shutdown().await
+        },
+        reader.read_to_end(&mut echoed),
+    );
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(echoed, payload);
+    drop(stream);
+
+    // UDP: 同一客户端的多个数据包复用一个会话
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 2048];
+    for i in 0..10u8 {
+        let packet = [i; 512];
+        client.send_to(&packet, ("127.0.0.1", port)).await.unwrap();
+        let size = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+            .await
+            .expect("UDP 回显超时")
+            .unwrap();
+        assert_eq!(&buf[..size], &packet);
+    }
+    assert_eq!(proxy.udp_session_count().await, 1);
+
+    // TCP 流量在连接关闭后计入
+    let expected = payload.len() as u64 + 10 * 512;
+    wait_for("流量统计", Duration::from_secs(5), || {
+        let proxy = Arc::clone(&proxy);
+        async move {
+            let (upload, download, connections) = proxy.traffic().snapshot();
+            upload == expected && download == expected && connections == 0
+        }
+    })
+    .await;
+
+    proxy.stop().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("代理服务器未能停止")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn fails_over_to_backup_and_back() {
+    let mut primary = EchoNode::start().await;
+    let backup = EchoNode::start().await;
+
+    let proxy = Arc::new(ProxyServer::new(free_port().await));
+    proxy
+        .set_failover_policy(FailoverPolicy {
+            check_interval: Duration::from_millis(100),
+            cooldown: Duration::ZERO,
+            failback: true,
+            failback_checks: 2,
+            base_backoff: Duration::from_millis(50),
+            stream_retries_per_minute: 0,
+            max_backups: 10,
+        })
+        .await;
+    proxy.set_node(primary.node("primary")).await;
+    proxy.set_backup_nodes(vec![backup.node("backup")]).await;
+    let handle = start_proxy(&proxy).await;
+
+    let current = || {
+        let proxy = Arc::clone(&proxy);
+        async move { proxy.current_node_name().await }
+    };
+
+    primary.stop();
+    wait_for("切换到备用节点", Duration::from_secs(10), || async {
+        current().await.as_deref() == Some("backup")
+    })
+    .await;
+
+    // 切换后流量经由备用节点
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy.get_proxy_port())).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    primary.restart().await;
+    wait_for("切回首选节点", Duration::from_secs(10), || async {
+        current().await.as_deref() == Some("primary")
+    })
+    .await;
+
+    proxy.stop().await.unwrap();
+    let _ = tokio::time::timeout(Duration::from_secs(5), handle).await;
+}
+
+#[tokio::test]
+async fn retries_stream_on_backup_without_switching() {
+    let mut primary = EchoNode::start().await;
+    let backup = EchoNode::start().await;
+
+    let proxy = Arc::new(ProxyServer::new(free_port().await));
+    proxy.set_node(primary.node("primary")).await;
+    proxy.set_backup_nodes(vec![backup.node("backup")]).await;
+    let handle = start_proxy(&proxy).await;
+
+    primary.stop();
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy.get_proxy_port())).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    // 连接改用备用节点，当前节点不变（等待端口监听时的探测连接也可能被重试）
+    assert_eq!(proxy.current_node_name().await.as_deref(), Some("primary"));
+    assert!(proxy.health_report().await.stream_retries >= 1);
+
+    proxy.stop().await.unwrap();
+    let _ = tokio::time::timeout(Duration::from_secs(5), handle).await;
+}
+
+#[tokio::test]
+async fn relays_udp_through_padded_channel() {
+    let echo = EchoNode::start().await;
+    // 回显节点原样返回封装后的数据包，客户端应收到去掉填充的原始数据
+    obfs::set_rules(HashMap::from([(
+        "padded".to_string(),
+        ObfsConfig {
+            max_padding: 64,
+            pacing_ms: 2,
+            experimental_framing: true,
+            ..ObfsConfig::default()
+        },
+    )]));
+
+    let proxy = Arc::new(ProxyServer::new(free_port().await));
+    proxy.set_node(echo.node("padded")).await;
+    let handle = start_proxy(&proxy).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 2048];
+    for i in 0..10u8 {
+        let packet = vec![i; 100 + i as usize];
+        client.send_to(&packet, ("127.0.0.1", proxy.get_proxy_port())).await.unwrap();
+        let size = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf))
+            .await
+            .expect("UDP 回显超时")
+            .unwrap();
+        assert_eq!(&buf[..size], &packet[..]);
+    }
+
+    proxy.stop().await.unwrap();
+    let _ = tokio::time::timeout(Duration::from_secs(5), handle).await;
+}
@@ -0,0 +1,177 @@
+//! 测试用的模拟订阅服务器和回显节点
+
+mod e2e;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// 订阅内容样例
+pub mod fixtures {
+    pub const CLASH_YAML: &str = "\
+proxies:
+  - {name: \"香港 01\", type: ss, server: 127.0.0.1, port: 8388, cipher: aes-128-gcm, password: secret}
+  - {name: \"日本 01\", type: trojan, server: jp.example.com, port: 443, password: secret}
+";
+
+    pub const SS_LINKS: &str = "\
+ss://aes-256-gcm:pass1@10.0.0.1:8388#节点1
+ss://YWVzLTEyOC1nY206cGFzczJAMTAuMC4wLjI6ODM4OQ==#节点2
+";
+
+    pub const PROTOCOL_LINKS: &str = "\
+trojan://secret@tj.example.com:443#Trojan%20HK
+vless://0b2f0c5c-5a3d-4a5b-9d9e-6a0b1c2d3e4f@vl.example.com:8443?security=tls#VLESS%20JP
+";
+
+    pub fn base64(content: &str) -> String {
+        use base64::{engine::general_purpose, Engine as _};
+        general_purpose::STANDARD.encode(content)
+    }
+}
+
+/// 最小的 HTTP 服务器，按路径返回固定的订阅内容
+pub struct MockSubscriptionServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockSubscriptionServer {
+    pub async fn start(routes: &[(&str, String)]) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes: Arc<HashMap<String, String>> = Arc::new(
+            routes
+                .iter()
+                .map(|(path, body)| (path.to_string(), body.clone()))
+                .collect(),
+        );
+
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let routes = Arc::clone(&routes);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let (status, body) = match routes.get(path) {
+                        Some(body) => ("200 OK", body.as_str()),
+                        None => ("404 Not Found", ""),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        Self { addr, task }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+}
+
+impl Drop for MockSubscriptionServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 在同一端口上回显 TCP 和 UDP 数据的模拟节点
+///
+/// 代理服务器按字节原样转发，因此回显服务即可代表 ss 等节点的可用性。
+pub struct EchoNode {
+    pub port: u16,
+    task: Option<JoinHandle<()>>,
+}
+
+impl EchoNode {
+    pub async fn start() -> Self {
+        let mut node = Self { port: 0, task: None };
+        node.restart().await;
+        node
+    }
+
+    /// 停止服务，已建立的连接一并断开
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+
+    /// 在原端口（首次启动时为随机端口）上重新启动
+    pub async fn restart(&mut self) {
+        self.stop();
+
+        let (tcp, udp) = loop {
+            let tcp = TcpListener::bind(("127.0.0.1", self.port)).await.unwrap();
+            let port = tcp.local_addr().unwrap().port();
+            if let Ok(udp) = UdpSocket::bind(("127.0.0.1", port)).await {
+                self.port = port;
+                break (tcp, udp);
+            }
+        };
+
+        self.task = Some(tokio::spawn(async move {
+            let mut connections = JoinSet::new();
+            let mut buf = vec![0u8; 65536];
+            loop {
+                tokio::select! {
+                    accepted = tcp.accept() => {
+                        let Ok((mut stream, _)) = accepted else { break };
+                        connections.spawn(async move {
+                            let (mut reader, mut writer) = stream.split();
+                            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                        });
+                    }
+                    received = udp.recv_from(&mut buf) => {
+                        let Ok((size, addr)) = received else { break };
+                        let _ = udp.send_to(&buf[..size], addr).await;
+                    }
+                }
+            }
+        }));
+    }
+
+    pub fn node(&self, name: &str) -> crate::subscription::Node {
+        crate::subscription::Node {
+            password: Some("secret".to_string()),
+            cipher: Some("aes-128-gcm".to_string()),
+            ..crate::subscription::Node::test(name, "127.0.0.1", self.port, "ss")
+        }
+    }
+}
+
+impl Drop for EchoNode {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 找一个 TCP 和 UDP 都空闲的本地端口
+pub async fn free_port() -> u16 {
+    loop {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        if UdpSocket::bind(("127.0.0.1", port)).await.is_ok() {
+            return port;
+        }
+    }
+}
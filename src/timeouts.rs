@@ -0,0 +1,193 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::game_detect::SupportedGame;
+
+/// 探测超时的上限，再长就失去了检测的意义
+const MAX_PROBE_MS: u64 = 60_000;
+
+static TIMEOUTS: RwLock<Option<Timeouts>> = RwLock::new(None);
+
+/// 各处使用的超时和延迟阈值
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// 连接节点（每个解析出的地址）的超时（毫秒）
+    pub connect_ms: u64,
+    /// 检查当前节点和首选节点的超时（毫秒）
+    pub health_probe_ms: u64,
+    /// 检查备用节点的超时（毫秒）
+    pub backup_probe_ms: u64,
+    /// 探测时连接建立后等待节点是否立即断开的时间（毫秒）
+    pub probe_settle_ms: u64,
+    /// 延迟低于该值的节点才会作为备用节点（毫秒）
+    pub max_usable_latency_ms: u32,
+    /// 按游戏设置的延迟上限（毫秒），玩该游戏时覆盖 max_usable_latency_ms
+    pub max_usable_latency_by_game: HashMap<SupportedGame, u32>,
+    /// 下载订阅的超时（秒）
+    pub subscription_secs: u64,
+    /// 没有识别出游戏的 TCP 连接空闲多久后关闭（秒），0 为不关闭
+    pub tcp_idle_secs: u64,
+    /// 没有识别出游戏的 UDP 会话空闲多久后关闭（秒），0 为不关闭
+    pub udp_idle_secs: u64,
+    /// 按游戏设置的空闲超时（秒），同时作用于 TCP 和 UDP，0 为不关闭，未设置的游戏使用内置值
+    pub idle_by_game: HashMap<SupportedGame, u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect_ms: 5000,
+            health_probe_ms: 5000,
+            backup_probe_ms: 3000,
+            probe_settle_ms: 300,
+            max_usable_latency_ms: 1000,
+            max_usable_latency_by_game: HashMap::new(),
+            subscription_secs: 30,
+            tcp_idle_secs: 0,
+            udp_idle_secs: 300,
+            idle_by_game: HashMap::new(),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn validate(&self) -> Result<()> {
+        for (name, ms) in [
+            ("connect_ms", self.connect_ms),
+            ("health_probe_ms", self.health_probe_ms),
+            ("backup_probe_ms", self.backup_probe_ms),
+        ] {
+            if ms == 0 || ms > MAX_PROBE_MS {
+                bail!("timeouts.{} 应在 1 到 {} 之间", name, MAX_PROBE_MS);
+            }
+        }
+        if self.probe_settle_ms >= self.health_probe_ms.min(self.backup_probe_ms) {
+            bail!("timeouts.probe_settle_ms 应小于 health_probe_ms 和 backup_probe_ms");
+        }
+        if self.max_usable_latency_ms == 0 {
+            bail!("timeouts.max_usable_latency_ms 不能为 0");
+        }
+        if let Some(game) = self.max_usable_latency_by_game.iter().find(|(_, ms)| **ms == 0).map(|(game, _)| game) {
+            bail!("timeouts.max_usable_latency_by_game 中 {} 的上限不能为 0", game.display_name());
+        }
+        if self.subscription_secs == 0 {
+            bail!("timeouts.subscription_secs 不能为 0");
+        }
+        Ok(())
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(self.connect_ms)
+    }
+
+    pub fn health_probe(&self) -> Duration {
+        Duration::from_millis(self.health_probe_ms)
+    }
+
+    pub fn backup_probe(&self) -> Duration {
+        Duration::from_millis(self.backup_probe_ms)
+    }
+
+    pub fn probe_settle(&self) -> Duration {
+        Duration::from_millis(self.probe_settle_ms)
+    }
+
+    pub fn subscription(&self) -> Duration {
+        Duration::from_secs(self.subscription_secs)
+    }
+
+    /// 该游戏可以接受的最高延迟，没有单独设置时使用 max_usable_latency_ms
+    pub fn max_usable_latency(&self, game: Option<&SupportedGame>) -> u32 {
+        game.and_then(|game| self.max_usable_latency_by_game.get(game))
+            .copied()
+            .unwrap_or(self.max_usable_latency_ms)
+    }
+
+    /// 该游戏的 TCP 连接的空闲超时，不超时为 None
+    pub fn tcp_idle(&self, game: Option<&SupportedGame>) -> Option<Duration> {
+        self.idle_for(game, self.tcp_idle_secs)
+    }
+
+    /// 该游戏的 UDP 会话的空闲超时，不超时为 None
+    pub fn udp_idle(&self, game: Option<&SupportedGame>) -> Option<Duration> {
+        self.idle_for(game, self.udp_idle_secs)
+    }
+
+    fn idle_for(&self, game: Option<&SupportedGame>, default_secs: u64) -> Option<Duration> {
+        let secs = match game {
+            Some(game) => self.idle_by_game.get(game).copied().unwrap_or_else(|| game.idle_timeout_secs()),
+            None => default_secs,
+        };
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// 测得的延迟是否足够低，可以作为备用节点
+    pub fn usable(&self, latency: Option<u32>) -> bool {
+        self.usable_for(latency, None)
+    }
+
+    /// 玩该游戏时，测得的延迟是否足够低
+    pub fn usable_for(&self, latency: Option<u32>, game: Option<&SupportedGame>) -> bool {
+        latency.unwrap_or(u32::MAX) < self.max_usable_latency(game)
+    }
+}
+
+pub fn set(timeouts: Timeouts) {
+    *TIMEOUTS.write().unwrap_or_else(|e| e.into_inner()) = Some(timeouts);
+}
+
+/// 当前生效的设置，未加载配置时使用默认值
+pub fn current() -> Timeouts {
+    TIMEOUTS.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_timeouts() {
+        assert!(Timeouts::default().validate().is_ok());
+
+        let timeouts: Timeouts = serde_yaml::from_str("backup_probe_ms: 1500\nmax_usable_latency_ms: 200").unwrap();
+        assert!(timeouts.validate().is_ok());
+        assert_eq!(timeouts.health_probe_ms, 5000);
+        assert!(timeouts.usable(Some(150)));
+        assert!(!timeouts.usable(Some(200)));
+        assert!(!timeouts.usable(None));
+
+        let timeouts: Timeouts = serde_yaml::from_str("max_usable_latency_by_game: {valorant: 80}").unwrap();
+        assert!(timeouts.usable_for(Some(150), Some(&SupportedGame::DontStarveTogether)));
+        assert!(!timeouts.usable_for(Some(150), Some(&SupportedGame::Valorant)));
+        assert!(timeouts.usable_for(Some(60), Some(&SupportedGame::Valorant)));
+
+        let timeouts: Timeouts = serde_yaml::from_str("tcp_idle_secs: 600\nidle_by_game: {dst: 0, valorant: 60}").unwrap();
+        assert_eq!(timeouts.tcp_idle(None), Some(Duration::from_secs(600)));
+        assert_eq!(timeouts.udp_idle(None), Some(Duration::from_secs(300)));
+        assert_eq!(timeouts.udp_idle(Some(&SupportedGame::Valorant)), Some(Duration::from_secs(60)));
+        assert_eq!(timeouts.tcp_idle(Some(&SupportedGame::DontStarveTogether)), None);
+        let minecraft = SupportedGame::Minecraft;
+        assert_eq!(timeouts.tcp_idle(Some(&minecraft)), Some(Duration::from_secs(minecraft.idle_timeout_secs())));
+        assert_eq!(Timeouts::default().tcp_idle(None), None);
+
+        let invalid = [
+            Timeouts { connect_ms: 0, ..Default::default() },
+            Timeouts { health_probe_ms: 0, ..Default::default() },
+            Timeouts { backup_probe_ms: 120_000, ..Default::default() },
+            Timeouts { probe_settle_ms: 3000, ..Default::default() },
+            Timeouts { max_usable_latency_ms: 0, ..Default::default() },
+            Timeouts { subscription_secs: 0, ..Default::default() },
+            Timeouts {
+                max_usable_latency_by_game: HashMap::from([(SupportedGame::CounterStrike, 0)]),
+                ..Default::default()
+            },
+        ];
+        for timeouts in invalid {
+            assert!(timeouts.validate().is_err(), "{:?}", timeouts);
+        }
+    }
+}
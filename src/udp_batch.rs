@@ -0,0 +1,257 @@
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+use crate::buffer_pool::{BufferPool, PooledBuffer};
+
+/// 单次批量收发的最大数据包数
+pub const BATCH_SIZE: usize = 16;
+
+/// 批量接收数据包，至少返回一个
+///
+/// Linux 上使用 recvmmsg(2) 一次系统调用取出多个数据包；
+/// 其他平台退化为单次 recv_from。
+pub async fn recv_batch(socket: &UdpSocket, pool: &BufferPool) -> io::Result<Vec<(PooledBuffer, SocketAddr)>> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        loop {
+            socket.readable().await?;
+
+            // 可读后再借出缓冲区，空闲会话不占用缓冲区
+            let mut bufs: Vec<PooledBuffer> = (0..BATCH_SIZE).map(|_| pool.get()).collect();
+            match socket.try_io(Interest::READABLE, || mmsg::recv(socket.as_raw_fd(), &mut bufs)) {
+                Ok(received) => return Ok(pair_received(bufs, received)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let mut buf = pool.get();
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        buf.truncate(len);
+        Ok(vec![(buf, addr)])
+    }
+}
+
+/// 把 recvmmsg 每个槽位的结果与对应的缓冲区配对，丢弃来源地址无法解析的槽位；
+/// 按位置配对，跳过的槽位不会让后面的数据错配到别的缓冲区
+#[cfg(target_os = "linux")]
+fn pair_received(
+    bufs: Vec<PooledBuffer>,
+    received: Vec<(usize, Option<SocketAddr>)>,
+) -> Vec<(PooledBuffer, SocketAddr)> {
+    bufs.into_iter()
+        .zip(received)
+        .filter_map(|(mut buf, (len, addr))| {
+            buf.truncate(len);
+            Some((buf, addr?))
+        })
+        .collect()
+}
+
+/// 将一批数据包发往同一地址
+///
+/// Linux 上使用 sendmmsg(2)，其他平台逐个 send_to。
+pub async fn send_batch<B: AsRef<[u8]>>(socket: &UdpSocket, packets: &[B], addr: SocketAddr) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        let mut sent = 0;
+        while sent < packets.len() {
+            socket.writable().await?;
+            match socket.try_io(Interest::WRITABLE, || mmsg::send(socket.as_raw_fd(), &packets[sent..], addr)) {
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        for packet in packets {
+            socket.send_to(packet.as_ref(), addr).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod mmsg {
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::RawFd;
+
+    use crate::buffer_pool::PooledBuffer;
+
+    /// 每个收到的数据包对应一项，与 bufs 的下标一致；来源地址无法解析时为 None
+    pub fn recv(fd: RawFd, bufs: &mut [PooledBuffer]) -> io::Result<Vec<(usize, Option<SocketAddr>)>> {
+        let count = bufs.len();
+        let mut addrs: Vec<libc::sockaddr_storage> = (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+        let mut iovs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovs.iter_mut())
+            .map(|(addr, iov)| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        let ret = unsafe {
+            libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                count as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(msgs[..ret as usize]
+            .iter()
+            .zip(&addrs)
+            .map(|(msg, addr)| (msg.msg_len as usize, from_sockaddr(addr)))
+            .collect())
+    }
+
+    pub fn send<B: AsRef<[u8]>>(fd: RawFd, packets: &[B], addr: SocketAddr) -> io::Result<usize> {
+        let (mut sockaddr, socklen) = to_sockaddr(addr);
+        let mut iovs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ref().as_ptr() as *mut libc::c_void,
+                iov_len: packet.as_ref().len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .map(|iov| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = &mut sockaddr as *mut _ as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen = socklen;
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        let ret = unsafe {
+            libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, libc::MSG_DONTWAIT)
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    fn from_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(v4) => {
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = v4.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*v4.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(v6) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = v6.port().to_be();
+                sin6.sin6_addr.s6_addr = v6.ip().octets();
+                sin6.sin6_flowinfo = v6.flowinfo();
+                sin6.sin6_scope_id = v6.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn batches_keep_each_packet_with_its_buffer() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packets: Vec<Vec<u8>> = (1..=5u8).map(|i| vec![i; i as usize * 100]).collect();
+        send_batch(&sender, &packets, receiver.local_addr().unwrap()).await.unwrap();
+
+        let pool = BufferPool::new(2048);
+        let mut received = Vec::new();
+        while received.len() < packets.len() {
+            received.extend(recv_batch(&receiver, &pool).await.unwrap());
+        }
+        for ((buf, from), packet) in received.iter().zip(&packets) {
+            assert_eq!(&buf[..], &packet[..]);
+            assert_eq!(*from, sender.local_addr().unwrap());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn skipped_slots_do_not_shift_later_packets() {
+        let pool = BufferPool::new(16);
+        let from: SocketAddr = "10.0.0.2:27015".parse().unwrap();
+        let bufs: Vec<PooledBuffer> = (0..3u8)
+            .map(|i| {
+                let mut buf = pool.get();
+                buf.fill(i);
+                buf
+            })
+            .collect();
+
+        let paired = pair_received(bufs, vec![(4, Some(from)), (8, None), (2, Some(from))]);
+        let contents: Vec<&[u8]> = paired.iter().map(|(buf, _)| &buf[..]).collect();
+        assert_eq!(contents, [&[0u8; 4][..], &[2u8; 2][..]]);
+    }
+}
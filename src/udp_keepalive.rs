@@ -0,0 +1,143 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+use crate::game_detect::SupportedGame;
+use crate::proxy::ProxyServer;
+
+/// 检查空闲会话的间隔，保活间隔的误差不超过这么久
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// 每隔几次检查重新检测正在运行的游戏
+const GAME_REFRESH_CHECKS: u32 = 5;
+
+static CONFIG: RwLock<Option<UdpKeepaliveConfig>> = RwLock::new(None);
+
+/// 游戏 UDP 会话的保活设置
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UdpKeepaliveConfig {
+    pub enabled: bool,
+    /// 按游戏设置的保活间隔（秒），0 为该游戏不保活，未设置的游戏使用内置间隔
+    pub games: HashMap<SupportedGame, u64>,
+}
+
+impl Default for UdpKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            games: HashMap::new(),
+        }
+    }
+}
+
+impl UdpKeepaliveConfig {
+    /// 会话所属游戏的保活间隔，不保活时为 None
+    pub fn interval_for(&self, game: Option<&SupportedGame>) -> Option<Duration> {
+        if !self.enabled {
+            return None;
+        }
+        let game = game?;
+        let secs = self.games.get(game).copied().unwrap_or_else(|| game.udp_keepalive_secs());
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+pub fn set_config(config: UdpKeepaliveConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+fn config() -> UdpKeepaliveConfig {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// UDP 会话最近一次向节点发送数据的时间
+#[derive(Debug)]
+pub struct Activity {
+    created: Instant,
+    /// 相对 created 的毫秒数
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            created: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        })
+    }
+
+    pub fn touch(&self) {
+        self.last_ms.store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.created.elapsed().saturating_sub(Duration::from_millis(self.last_ms.load(Ordering::Relaxed)))
+    }
+}
+
+/// 定期向空闲的游戏 UDP 会话发送保活包，避免节点或 NAT 上的映射在比赛中途过期；
+/// 同时关闭客户端空闲超过 timeouts 中空闲超时的会话
+pub fn spawn(proxy: Arc<ProxyServer>) {
+    proxy.tasks().spawn("UDP 保活", async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut game = None;
+        let mut checks = 0u32;
+
+        loop {
+            interval.tick().await;
+            if proxy.udp_session_count().await == 0 {
+                continue;
+            }
+            // 没能从数据包识别出游戏的会话按本机正在运行的游戏处理
+            if checks.is_multiple_of(GAME_REFRESH_CHECKS) {
+                game = proxy.running_game().await;
+            }
+            checks = checks.wrapping_add(1);
+
+            // 先关闭客户端已不再发送数据的会话，不再为它们保活
+            let expired = proxy.expire_idle_udp_sessions(game.as_ref()).await;
+            if expired > 0 {
+                debug!("关闭了 {} 个空闲超时的 UDP 会话", expired);
+            }
+            let config = config();
+            if !config.enabled {
+                continue;
+            }
+            let sent = proxy.send_udp_keepalives(&config, game.as_ref()).await;
+            if sent > 0 {
+                debug!("向 {} 个空闲的 UDP 会话发送了保活包", sent);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_game_intervals_and_overrides() {
+        let mut config = UdpKeepaliveConfig::default();
+        let dst = SupportedGame::DontStarveTogether;
+        assert_eq!(config.interval_for(Some(&dst)), Some(Duration::from_secs(dst.udp_keepalive_secs())));
+        assert_eq!(config.interval_for(Some(&SupportedGame::Minecraft)), None);
+        assert_eq!(config.interval_for(None), None);
+
+        config.games.insert(dst.clone(), 5);
+        config.games.insert(SupportedGame::Valorant, 0);
+        assert_eq!(config.interval_for(Some(&dst)), Some(Duration::from_secs(5)));
+        assert_eq!(config.interval_for(Some(&SupportedGame::Valorant)), None);
+
+        config.enabled = false;
+        assert_eq!(config.interval_for(Some(&dst)), None);
+
+        let activity = Activity::new();
+        activity.touch();
+        assert!(activity.idle() < Duration::from_secs(1));
+    }
+}
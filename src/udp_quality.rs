@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::game_detect::SupportedGame;
+
+/// 记录最近多少个序号是否收到，用于识别乱序和重复
+const WINDOW: u32 = 64;
+/// 序号一次跳过太多时认为游戏重新建立了连接，重新开始跟踪
+const MAX_GAP: u32 = 1024;
+
+/// 数据包中序号字段的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SequenceFormat {
+    /// RakNet 数据报：首字节最高位为 1（不是 ACK/NAK），随后 3 字节小端序号（饥荒联机版）
+    RakNet,
+    /// Source 引擎 netchan：前 4 字节小端序号，0xFFFFFFFF/0xFFFFFFFE 开头的是无连接包和分片包
+    Source,
+}
+
+impl SequenceFormat {
+    fn for_game(game: &SupportedGame) -> Option<Self> {
+        match game {
+            SupportedGame::DontStarveTogether => Some(Self::RakNet),
+            SupportedGame::CounterStrike | SupportedGame::Dota2 => Some(Self::Source),
+            _ => None,
+        }
+    }
+
+    /// 序号的取值范围（位数）
+    fn bits(&self) -> u32 {
+        match self {
+            Self::RakNet => 24,
+            Self::Source => 32,
+        }
+    }
+
+    fn sequence(&self, packet: &[u8]) -> Option<u32> {
+        match self {
+            Self::RakNet => {
+                let flags = *packet.first()?;
+                if flags & 0x80 == 0 || flags & 0x60 != 0 || packet.len() < 4 {
+                    return None;
+                }
+                Some(u32::from_le_bytes([packet[1], packet[2], packet[3], 0]))
+            }
+            Self::Source => {
+                let sequence = u32::from_le_bytes(packet.get(..4)?.try_into().ok()?);
+                (packet.len() >= 8 && sequence < 0xFFFF_FFFE).then_some(sequence)
+            }
+        }
+    }
+}
+
+/// 按序号估计一个方向上的丢包、乱序和重复
+#[derive(Debug)]
+struct SequenceTracker {
+    format: SequenceFormat,
+    highest: Option<u32>,
+    /// 第 i 位表示 highest - i 是否已收到
+    seen: u64,
+}
+
+/// 一次序号检查的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Observation {
+    InOrder { skipped: u32 },
+    /// 之前被记为丢失、迟到的包
+    Late,
+    Duplicate,
+    Reset,
+}
+
+impl SequenceTracker {
+    fn new(format: SequenceFormat) -> Self {
+        Self { format, highest: None, seen: 0 }
+    }
+
+    fn observe(&mut self, sequence: u32) -> Observation {
+        let mask = if self.format.bits() == 32 { u32::MAX } else { (1 << self.format.bits()) - 1 };
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.seen = 1;
+            return Observation::Reset;
+        };
+
+        let ahead = sequence.wrapping_sub(highest) & mask;
+        let behind = highest.wrapping_sub(sequence) & mask;
+        if ahead == 0 {
+            return Observation::Duplicate;
+        }
+        if ahead <= MAX_GAP {
+            self.highest = Some(sequence);
+            self.seen = if ahead >= WINDOW { 1 } else { (self.seen << ahead) | 1 };
+            return Observation::InOrder { skipped: ahead - 1 };
+        }
+        if behind < WINDOW {
+            let bit = 1u64 << behind;
+            if self.seen & bit != 0 {
+                return Observation::Duplicate;
+            }
+            self.seen |= bit;
+            return Observation::Late;
+        }
+        self.highest = Some(sequence);
+        self.seen = 1;
+        Observation::Reset
+    }
+}
+
+/// 一个方向上的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectionStats {
+    pub packets: u64,
+    /// 能读出序号的包
+    pub tracked: u64,
+    /// 按序号缺口估计的丢包
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+}
+
+impl DirectionStats {
+    /// 估计的丢包率（百分比），没有可用的序号时为 None
+    pub fn loss_percent(&self) -> Option<f64> {
+        let expected = self.tracked.saturating_sub(self.duplicated) + self.lost;
+        (self.tracked > 0 && expected > 0).then(|| self.lost as f64 * 100.0 / expected as f64)
+    }
+
+    /// 乱序包的比例（百分比）
+    pub fn reorder_percent(&self) -> Option<f64> {
+        (self.tracked > 0).then(|| self.reordered as f64 * 100.0 / self.tracked as f64)
+    }
+
+    /// 包数以及能估计时的丢包率和乱序率
+    pub fn describe(&self) -> String {
+        match (self.loss_percent(), self.reorder_percent()) {
+            (Some(loss), Some(reorder)) => format!("{} 包，丢包 {:.1}%，乱序 {:.1}%", self.packets, loss, reorder),
+            _ => format!("{} 包", self.packets),
+        }
+    }
+
+    pub fn merge(&mut self, other: &DirectionStats) {
+        self.packets += other.packets;
+        self.tracked += other.tracked;
+        self.lost += other.lost;
+        self.reordered += other.reordered;
+        self.duplicated += other.duplicated;
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    packets: AtomicU64,
+    tracked: AtomicU64,
+    lost: AtomicU64,
+    reordered: AtomicU64,
+    duplicated: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> DirectionStats {
+        DirectionStats {
+            packets: self.packets.load(Ordering::Relaxed),
+            tracked: self.tracked.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+            reordered: self.reordered.load(Ordering::Relaxed),
+            duplicated: self.duplicated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Direction {
+    counters: Counters,
+    tracker: Mutex<Option<SequenceTracker>>,
+}
+
+impl Direction {
+    fn new(format: Option<SequenceFormat>) -> Self {
+        Self {
+            counters: Counters::default(),
+            tracker: Mutex::new(format.map(SequenceTracker::new)),
+        }
+    }
+
+    fn record(&self, packet: &[u8]) {
+        let counters = &self.counters;
+        counters.packets.fetch_add(1, Ordering::Relaxed);
+        let mut tracker = self.tracker.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(tracker) = tracker.as_mut() else {
+            return;
+        };
+        let Some(sequence) = tracker.format.sequence(packet) else {
+            return;
+        };
+        counters.tracked.fetch_add(1, Ordering::Relaxed);
+        match tracker.observe(sequence) {
+            Observation::InOrder { skipped } => {
+                counters.lost.fetch_add(skipped as u64, Ordering::Relaxed);
+            }
+            Observation::Late => {
+                // 迟到的包之前被计为丢失
+                let _ = counters.lost.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |lost| lost.checked_sub(1));
+                counters.reordered.fetch_add(1, Ordering::Relaxed);
+            }
+            Observation::Duplicate => {
+                counters.duplicated.fetch_add(1, Ordering::Relaxed);
+            }
+            Observation::Reset => {}
+        }
+    }
+}
+
+/// 一个 UDP 会话两个方向的包数，以及能从游戏协议读出序号时估计的丢包和乱序
+pub struct UdpQuality {
+    up: Direction,
+    down: Direction,
+}
+
+impl UdpQuality {
+    pub fn new(game: Option<&SupportedGame>) -> Arc<Self> {
+        let format = game.and_then(SequenceFormat::for_game);
+        Arc::new(Self {
+            up: Direction::new(format),
+            down: Direction::new(format),
+        })
+    }
+
+    /// 客户端发往节点的包
+    pub fn record_up(&self, packet: &[u8]) {
+        self.up.record(packet);
+    }
+
+    /// 节点发回客户端的包
+    pub fn record_down(&self, packet: &[u8]) {
+        self.down.record(packet);
+    }
+
+    pub fn up(&self) -> DirectionStats {
+        self.up.counters.snapshot()
+    }
+
+    pub fn down(&self) -> DirectionStats {
+        self.down.counters.snapshot()
+    }
+}
+
+/// 一个 UDP 会话的质量，供 cf stats 和交互界面显示
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionQuality {
+    pub client: String,
+    pub node: String,
+    pub game: Option<SupportedGame>,
+    pub up: DirectionStats,
+    pub down: DirectionStats,
+}
+
+/// 合并所有会话的统计
+pub fn total(sessions: &[SessionQuality]) -> (DirectionStats, DirectionStats) {
+    let mut up = DirectionStats::default();
+    let mut down = DirectionStats::default();
+    for session in sessions {
+        up.merge(&session.up);
+        down.merge(&session.down);
+    }
+    (up, down)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raknet(sequence: u32) -> Vec<u8> {
+        let bytes = sequence.to_le_bytes();
+        vec![0x84, bytes[0], bytes[1], bytes[2], 0x60, 0x00]
+    }
+
+    #[test]
+    fn estimates_loss_and_reorder_from_sequences() {
+        let quality = UdpQuality::new(Some(&SupportedGame::DontStarveTogether));
+        // 序号在 24 位处回绕：0 丢失，2 迟到，3 重复，最后跳过太多按重新连接处理
+        for sequence in [0xFF_FFFD, 0xFF_FFFE, 0xFF_FFFF, 1, 3, 2, 3, 4, 5000] {
+            quality.record_down(&raknet(sequence));
+        }
+        // ACK 包没有序号
+        quality.record_down(&[0xC0, 0x00, 0x01]);
+
+        let down = quality.down();
+        assert_eq!(down.packets, 10);
+        assert_eq!(down.tracked, 9);
+        assert_eq!(down.lost, 1);
+        assert_eq!(down.reordered, 1);
+        assert_eq!(down.duplicated, 1);
+        assert!(down.loss_percent().unwrap() > 0.0);
+
+        let source = SequenceFormat::Source;
+        assert_eq!(source.sequence(&[7, 0, 0, 0, 1, 0, 0, 0]), Some(7));
+        assert_eq!(source.sequence(&[0xFF, 0xFF, 0xFF, 0xFF, b'T', 0, 0, 0]), None);
+
+        let untracked = UdpQuality::new(Some(&SupportedGame::Valorant));
+        untracked.record_up(&raknet(1));
+        assert_eq!(untracked.up().tracked, 0);
+        assert_eq!(untracked.up().loss_percent(), None);
+    }
+}
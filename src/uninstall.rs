@@ -0,0 +1,176 @@
+use anyhow::Result;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
+
+use crate::autostart;
+use crate::config::Config;
+
+/// 卸载程序：注销自启动项、恢复系统代理、删除可执行文件；
+/// 删除程序前需要确认，`yes` 为 true 时跳过确认；`purge` 为 true 时不经确认删除程序、配置和缓存
+pub fn run(purge: bool, yes: bool) -> Result<()> {
+    let current_exe = std::env::current_exe()?;
+    println!("📁 当前程序路径: {}", current_exe.display());
+
+    if !(yes || purge || confirm("确认卸载 ClashFun 并删除程序?")?) {
+        println!("💡 已取消卸载，非交互环境下使用 'cf uninstall --yes' 跳过确认");
+        return Ok(());
+    }
+
+    println!("🗑️ 正在卸载 ClashFun...");
+    let config = Config::load().unwrap_or_default();
+
+    autostart::disable();
+    restore_system_proxy(config.proxy_port);
+
+    let remove_data = purge || confirm("是否同时删除配置和缓存 (订阅链接、节点选择等)?")?;
+    if remove_data {
+        if let Ok(config_dir) = Config::config_dir() {
+            remove_dir("配置目录", &config_dir);
+        }
+        if let Ok(cache_dir) = Config::cache_dir() {
+            remove_dir("缓存目录", &cache_dir);
+        }
+    } else {
+        println!("💡 已保留配置和缓存，使用 'cf uninstall --purge' 可一并删除");
+    }
+
+    remove_executable(&current_exe);
+
+    println!("🎉 ClashFun 卸载完成！");
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    // 非交互环境下默认不删除
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+
+    print!("❓ {} [y/N]: ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn remove_dir(label: &str, dir: &Path) {
+    if !dir.exists() {
+        println!("💡 没有找到{}", label);
+        return;
+    }
+
+    match fs::remove_dir_all(dir) {
+        Ok(()) => println!("✅ {}已删除: {}", label, dir.display()),
+        Err(e) => println!("⚠️  删除{}失败: {}", label, e),
+    }
+}
+
+/// 如果系统代理指向 ClashFun 的本地端口，则将其关闭
+fn restore_system_proxy(port: u16) {
+    let target = format!("127.0.0.1:{}", port);
+
+    #[cfg(target_os = "macos")]
+    {
+        let services = Command::new("networksetup")
+            .arg("-listallnetworkservices")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+            .unwrap_or_default();
+
+        // 第一行是说明文字，带 * 的是已禁用的服务
+        for service in services.lines().skip(1).map(|s| s.trim_start_matches('*').trim()) {
+            for (get, set) in [
+                ("-getwebproxy", "-setwebproxystate"),
+                ("-getsecurewebproxy", "-setsecurewebproxystate"),
+                ("-getsocksfirewallproxy", "-setsocksfirewallproxystate"),
+            ] {
+                let output = match Command::new("networksetup").args([get, service]).output() {
+                    Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+                    Err(_) => continue,
+                };
+                let points_to_us = output.contains("Enabled: Yes")
+                    && output.contains("Server: 127.0.0.1")
+                    && output.contains(&format!("Port: {}", port));
+                if points_to_us && Command::new("networksetup").args([set, service, "off"]).status().is_ok() {
+                    println!("✅ 已恢复系统代理设置: {} ({})", service, get.trim_start_matches("-get"));
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let gsettings = |args: &[&str]| {
+            Command::new("gsettings")
+                .args(args)
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().trim_matches('\'').to_string())
+                .unwrap_or_default()
+        };
+
+        let mode = gsettings(&["get", "org.gnome.system.proxy", "mode"]);
+        let host = gsettings(&["get", "org.gnome.system.proxy.http", "host"]);
+        let proxy_port = gsettings(&["get", "org.gnome.system.proxy.http", "port"]);
+        if mode == "manual" && format!("{}:{}", host, proxy_port) == target {
+            gsettings(&["set", "org.gnome.system.proxy", "mode", "none"]);
+            println!("✅ 已恢复系统代理设置");
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+        let server = Command::new("reg")
+            .args(["query", key, "/v", "ProxyServer"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+            .unwrap_or_default();
+        if server.contains(&target) {
+            let _ = Command::new("reg")
+                .args(["add", key, "/v", "ProxyEnable", "/t", "REG_DWORD", "/d", "0", "/f"])
+                .output();
+            println!("✅ 已恢复系统代理设置");
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = target;
+}
+
+fn remove_executable(current_exe: &Path) {
+    #[cfg(windows)]
+    {
+        // Windows 无法删除正在运行的程序，退出后由批处理脚本删除
+        let batch_script = format!(
+            "@echo off\r\ntimeout /t 2 /nobreak >nul\r\ndel /f /q \"{}\"\r\ndel \"%~f0\"\r\n",
+            current_exe.display()
+        );
+        let batch_path = std::env::temp_dir().join("cf_uninstall.bat");
+        let scheduled = fs::write(&batch_path, batch_script).is_ok()
+            && Command::new("cmd")
+                .args(["/c", "start", "", "/min"])
+                .arg(&batch_path)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .is_ok();
+        if scheduled {
+            println!("✅ 程序将在退出后删除: {}", current_exe.display());
+        } else {
+            println!("💡 请手动删除可执行文件: {}", current_exe.display());
+        }
+    }
+
+    #[cfg(not(windows))]
+    match fs::remove_file(current_exe) {
+        Ok(()) => println!("✅ 可执行文件已删除: {}", current_exe.display()),
+        Err(e) => {
+            log::warn!("删除可执行文件失败: {}", e);
+            println!("⚠️  删除可执行文件失败: {}", e);
+            println!("💡 可以使用命令: sudo rm {}", current_exe.display());
+        }
+    }
+}
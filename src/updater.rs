@@ -1,29 +1,121 @@
-use anyhow::{Result, anyhow};
-use log::{info, warn, error};
-use reqwest;
+use anyhow::Result;
+use clashfun::error::UpdateError;
+#[cfg(feature = "updater")]
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::fs;
+#[cfg(feature = "updater")]
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::Command;
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const GITHUB_API_URL: &str = "https://api.github.com/repos/ink1ing/clashfun/releases/latest";
+use crate::config::Config;
+use crate::performance;
+
+const GITHUB_API_URL: &str = "https://api.github.com/repos/ink1ing/clashfun/releases";
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// 压缩包中可执行文件可能使用的名称
+#[cfg(feature = "updater")]
+const BINARY_NAMES: &[&str] = &["cf", "cf.exe", "clashfun", "clashfun.exe"];
+
+#[cfg(feature = "updater")]
+#[derive(Debug, Clone, Copy)]
+enum ArchiveKind {
+    TarGz,
+    Zip,
+}
+
+#[cfg(feature = "updater")]
+impl ArchiveKind {
+    fn from_url(url: &str) -> Option<Self> {
+        let url = url.to_lowercase();
+        if url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if url.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// 解析版本号，兼容 v 前缀和省略补丁号的写法（如 v0.3、0.3.0-rc.1）
+fn parse_version(version: &str) -> Result<Version> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+
+    if let Ok(parsed) = Version::parse(version) {
+        return Ok(parsed);
+    }
+
+    // 补齐缺失的版本段后重试
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(pos) => version.split_at(pos),
+        None => (version, ""),
+    };
+    let mut parts: Vec<&str> = core.split('.').collect();
+    while parts.len() < 3 {
+        parts.push("0");
+    }
+
+    Version::parse(&format!("{}{}", parts.join("."), rest))
+        .map_err(|e| {
+            UpdateError::InvalidVersion {
+                version: version.to_string(),
+                reason: e.to_string(),
+            }
+            .into()
+        })
+}
+
+/// 按语义化版本优先级比较，忽略构建元数据
+fn is_newer(version: &Version, than: &Version) -> bool {
+    version.cmp_precedence(than) == std::cmp::Ordering::Greater
+}
+
+#[cfg(feature = "updater")]
+fn is_binary_name(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| BINARY_NAMES.contains(&name))
+        .unwrap_or(false)
+}
+
+/// 更新通道：stable 只接收正式版本，beta 同时接收预发布版本
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl std::fmt::Display for UpdateChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stable => write!(f, "stable"),
+            Self::Beta => write!(f, "beta"),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct GitHubRelease {
     tag_name: String,
-    name: String,
     body: Option<String>,
     assets: Vec<GitHubAsset>,
     prerelease: bool,
+    #[serde(default)]
+    draft: bool,
 }
 
 #[derive(Debug, Deserialize)]
 struct GitHubAsset {
     name: String,
     browser_download_url: String,
-    size: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,35 +127,320 @@ pub struct UpdateInfo {
     pub release_notes: Option<String>,
 }
 
+/// 后台更新检查的结果缓存，供 `cf status` 和 TUI 展示
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateCheckState {
+    pub last_check: u64,
+    pub latest_version: Option<String>,
+}
+
+impl UpdateCheckState {
+    fn path() -> Result<PathBuf> {
+        Ok(Config::cache_dir()?.join("update_check.json"))
+    }
+
+    pub fn load() -> Self {
+        Self::path()
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// 缓存中记录的、比当前运行版本更新的版本
+    pub fn available_version(&self) -> Option<&str> {
+        let latest = self.latest_version.as_deref()?;
+        let newer = is_newer(&parse_version(latest).ok()?, &parse_version(CURRENT_VERSION).ok()?);
+        newer.then_some(latest)
+    }
+
+    fn seconds_until_due(&self, interval: Duration) -> u64 {
+        let next = self.last_check.saturating_add(interval.as_secs());
+        next.saturating_sub(unix_now())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// 启动后台更新检查任务，按配置的间隔（默认每天一次）检查，关闭检查时返回 None
+pub fn spawn_background_check(config: &Config) -> Option<tokio::task::JoinHandle<()>> {
+    if config.disable_update_check {
+        return None;
+    }
+
+    let updater = match Updater::from_config(config) {
+        Ok(updater) => updater,
+        Err(e) => {
+            warn!("无法创建更新检查器: {}", e);
+            return None;
+        }
+    };
+    let channel = config.update_channel;
+    let interval = Duration::from_secs(config.update_check_interval_hours.max(1) * 3600);
+
+    Some(tokio::spawn(async move {
+        loop {
+            let mut state = UpdateCheckState::load();
+            let wait = state.seconds_until_due(interval);
+            if wait > 0 {
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                continue;
+            }
+            if performance::defer_background() {
+                tokio::time::sleep(performance::DEFER_RETRY).await;
+                continue;
+            }
+
+            match updater.check_for_updates(channel).await {
+                Ok(info) => {
+                    if info.update_available {
+                        info!("发现新版本 {}，运行 'cf update' 更新", info.latest_version.as_deref().unwrap_or("未知"));
+                    }
+                    state.latest_version = info.latest_version;
+                }
+                Err(e) => warn!("后台检查更新失败: {}", e),
+            }
+
+            // 失败时同样记录检查时间，避免频繁重试
+            state.last_check = unix_now();
+            if let Err(e) = state.save() {
+                warn!("保存更新检查结果失败: {}", e);
+            }
+        }
+    }))
+}
+
+/// 一次更新操作的参数
+pub struct UpdateRequest {
+    pub channel: UpdateChannel,
+    /// 指定安装的版本，None 表示通道中的最新版本
+    pub version: Option<String>,
+    /// 只检查不安装
+    pub check_only: bool,
+}
+
+/// 更新过程中的进度事件，CLI 和 TUI 使用同一套事件与提示文案
+#[cfg_attr(not(feature = "updater"), allow(dead_code))]
+pub enum UpdateEvent<'a> {
+    Checking,
+    UpToDate(&'a UpdateInfo),
+    Available(&'a UpdateInfo),
+    Downloading { downloaded: u64, total: Option<u64> },
+    Installing,
+    Finished(&'a UpdateInfo),
+}
+
+impl UpdateEvent<'_> {
+    pub fn message(&self) -> String {
+        match self {
+            Self::Checking => "🔍 正在检查更新...".to_string(),
+            Self::UpToDate(info) => format!("✅ 当前已是最新版本 {}", info.current_version),
+            Self::Available(info) => format!(
+                "🚀 发现新版本 {} -> {}",
+                info.current_version,
+                info.latest_version.as_deref().unwrap_or("未知")
+            ),
+            Self::Downloading { downloaded, total: Some(total) } => {
+                let percent = downloaded * 100 / total;
+                let filled = (percent / 5).min(20) as usize;
+                format!(
+                    "📥 [{}{}] {:>3}% {:.1}/{:.1} MB",
+                    "#".repeat(filled),
+                    "-".repeat(20 - filled),
+                    percent,
+                    *downloaded as f64 / 1_048_576.0,
+                    *total as f64 / 1_048_576.0
+                )
+            }
+            Self::Downloading { downloaded, total: None } => {
+                format!("📥 已下载 {:.1} MB", *downloaded as f64 / 1_048_576.0)
+            }
+            Self::Installing => "✅ 下载完成，正在替换旧版本...".to_string(),
+            Self::Finished(info) => format!(
+                "🎉 已更新到 {}，请重新运行 cf 使用新版本",
+                info.latest_version.as_deref().unwrap_or("最新版本")
+            ),
+        }
+    }
+}
+
 pub struct Updater {
     client: reqwest::Client,
+    mirror: Option<String>,
 }
 
 impl Updater {
-    pub fn new() -> Self {
-        Self {
-            client: reqwest::Client::new(),
+    /// 根据配置创建更新器，支持下载镜像和 HTTP 代理
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = &config.update_proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).map_err(|e| UpdateError::InvalidProxy {
+                    proxy: proxy.clone(),
+                    reason: e.to_string(),
+                })?,
+            );
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            mirror: config.update_mirror.clone(),
+        })
+    }
+
+    /// 镜像使用前缀方式，例如 https://ghproxy.com/https://github.com/...；发布信息 API 和下载文件都经过镜像
+    fn mirror_url(&self, url: &str) -> String {
+        match &self.mirror {
+            Some(mirror) => format!("{}/{}", mirror.trim_end_matches('/'), url),
+            None => url.to_string(),
         }
     }
 
-    /// 检查是否有可用更新
-    pub async fn check_for_updates(&self) -> Result<UpdateInfo> {
-        info!("正在检查更新...");
+    /// 下载更新文件，支持断点续传，并在终端显示进度
+    #[cfg(feature = "updater")]
+    async fn download(&self, download_url: &str, progress: &mut (dyn FnMut(UpdateEvent) + Send)) -> Result<Vec<u8>> {
+        let url = self.mirror_url(download_url);
+        let cache_dir = Config::cache_dir()?;
+        fs::create_dir_all(&cache_dir)?;
+        let part_file = cache_dir.join("cf_update.part");
+        let url_file = cache_dir.join("cf_update.url");
+
+        // 残留的部分文件来自其他下载地址时不能续传
+        if fs::read_to_string(&url_file).ok().as_deref() != Some(url.as_str()) {
+            let _ = fs::remove_file(&part_file);
+            fs::write(&url_file, &url)?;
+        }
+
+        let existing = fs::metadata(&part_file).map(|m| m.len()).unwrap_or(0);
 
+        let mut request = self.client
+            .get(&url)
+            .header("User-Agent", format!("ClashFun/{}", CURRENT_VERSION));
+        if existing > 0 {
+            info!("继续下载，已下载 {} 字节", existing);
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+        }
+
+        let mut response = request.send().await.map_err(UpdateError::Unreachable)?;
+        let status = response.status();
+
+        let (mut file, mut downloaded) = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            (fs::OpenOptions::new().append(true).open(&part_file)?, existing)
+        } else if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+            // 部分文件已经完整
+            let bytes = fs::read(&part_file)?;
+            let _ = fs::remove_file(&part_file);
+            let _ = fs::remove_file(&url_file);
+            return Ok(bytes);
+        } else if status.is_success() {
+            (fs::File::create(&part_file)?, 0)
+        } else {
+            return Err(UpdateError::Download { status: status.as_u16() }.into());
+        };
+
+        let total = response.content_length().map(|len| len + downloaded).filter(|t| *t > 0);
+        let mut last_step = None;
+
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk)?;
+            downloaded += chunk.len() as u64;
+
+            // 按百分比（未知总大小时按 MB）节流进度事件
+            let step = match total {
+                Some(total) => downloaded * 100 / total,
+                None => downloaded / 1_048_576,
+            };
+            if last_step != Some(step) {
+                last_step = Some(step);
+                progress(UpdateEvent::Downloading { downloaded, total });
+            }
+        }
+        file.sync_all()?;
+
+        let bytes = fs::read(&part_file)?;
+        let _ = fs::remove_file(&part_file);
+        let _ = fs::remove_file(&url_file);
+        Ok(bytes)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
         let response = self.client
-            .get(GITHUB_API_URL)
+            .get(self.mirror_url(url))
             .header("User-Agent", format!("ClashFun/{}", CURRENT_VERSION))
             .send()
-            .await?;
+            .await
+            .map_err(UpdateError::Unreachable)?;
 
         if !response.status().is_success() {
-            return Err(anyhow!("获取版本信息失败: HTTP {}", response.status()));
+            return Err(UpdateError::Api { status: response.status().as_u16() }.into());
         }
 
-        let release: GitHubRelease = response.json().await?;
+        Ok(response.json().await?)
+    }
+
+    /// 检查并安装更新，进度通过回调通知调用方
+    pub async fn run(
+        &self,
+        request: &UpdateRequest,
+        progress: &mut (dyn FnMut(UpdateEvent) + Send),
+    ) -> Result<UpdateInfo> {
+        progress(UpdateEvent::Checking);
+
+        let info = match &request.version {
+            Some(version) => self.check_version(version).await?,
+            None => self.check_for_updates(request.channel).await?,
+        };
+
+        if !info.update_available {
+            progress(UpdateEvent::UpToDate(&info));
+            return Ok(info);
+        }
+
+        progress(UpdateEvent::Available(&info));
+        if request.check_only {
+            return Ok(info);
+        }
 
-        // 跳过预发布版本
-        if release.prerelease {
+        let download_url = info
+            .download_url
+            .as_deref()
+            .ok_or(UpdateError::NoAsset)?;
+        self.perform_update(download_url, progress).await?;
+
+        progress(UpdateEvent::Finished(&info));
+        Ok(info)
+    }
+
+    /// 检查指定通道是否有可用更新
+    pub async fn check_for_updates(&self, channel: UpdateChannel) -> Result<UpdateInfo> {
+        info!("正在检查更新 (通道: {})...", channel);
+
+        // stable 通道跳过预发布版本，beta 通道包含预发布版本
+        let releases: Vec<GitHubRelease> = self.get_json(GITHUB_API_URL).await?;
+        let mut candidates: Vec<(Version, GitHubRelease)> = releases
+            .into_iter()
+            .filter(|r| !r.draft && (channel == UpdateChannel::Beta || !r.prerelease))
+            .filter_map(|r| parse_version(&r.tag_name).ok().map(|v| (v, r)))
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp_precedence(&a.0));
+
+        let current = parse_version(CURRENT_VERSION)?;
+
+        let Some((latest, release)) = candidates.first() else {
             return Ok(UpdateInfo {
                 current_version: CURRENT_VERSION.to_string(),
                 latest_version: None,
@@ -71,10 +448,9 @@ impl Updater {
                 download_url: None,
                 release_notes: None,
             });
-        }
+        };
 
-        let latest_version = release.tag_name.trim_start_matches('v');
-        let update_available = self.version_compare(CURRENT_VERSION, latest_version)?;
+        let update_available = is_newer(latest, &current);
 
         let download_url = if update_available {
             self.get_download_url(&release.assets)?
@@ -82,38 +458,88 @@ impl Updater {
             None
         };
 
+        // 跨多个版本更新时合并所有跳过版本的更新说明
+        let release_notes = if update_available {
+            Self::release_notes_between(&candidates, &current, latest)
+        } else {
+            release.body.clone()
+        };
+
         Ok(UpdateInfo {
             current_version: CURRENT_VERSION.to_string(),
-            latest_version: Some(latest_version.to_string()),
+            latest_version: Some(latest.to_string()),
             update_available,
             download_url,
-            release_notes: release.body,
+            release_notes,
         })
     }
 
-    /// 比较版本号，返回是否需要更新
-    fn version_compare(&self, current: &str, latest: &str) -> Result<bool> {
-        let current_parts: Vec<u32> = current.split('.')
-            .map(|s| s.parse().unwrap_or(0))
-            .collect();
-        let latest_parts: Vec<u32> = latest.split('.')
-            .map(|s| s.parse().unwrap_or(0))
+    /// 汇总 (current, target] 区间内各版本的更新说明，新版本在前
+    fn release_notes_between(
+        releases: &[(Version, GitHubRelease)],
+        current: &Version,
+        target: &Version,
+    ) -> Option<String> {
+        let notes: Vec<String> = releases
+            .iter()
+            .filter(|(version, _)| is_newer(version, current) && !is_newer(version, target))
+            .filter_map(|(version, release)| {
+                release
+                    .body
+                    .as_deref()
+                    .map(|body| format!("## v{}\n{}", version, body.trim()))
+            })
             .collect();
 
-        let max_len = current_parts.len().max(latest_parts.len());
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.join("\n\n"))
+        }
+    }
 
-        for i in 0..max_len {
-            let curr = current_parts.get(i).unwrap_or(&0);
-            let latest = latest_parts.get(i).unwrap_or(&0);
+    /// 获取指定版本的信息，用于固定安装某个版本（允许降级）
+    pub async fn check_version(&self, version: &str) -> Result<UpdateInfo> {
+        let version = version.trim_start_matches('v');
+        info!("正在获取版本 {} 的信息...", version);
 
-            if latest > curr {
-                return Ok(true);
-            } else if latest < curr {
-                return Ok(false);
-            }
-        }
+        // 兼容带 v 和不带 v 的 tag
+        let release: GitHubRelease = match self
+            .get_json(&format!("{}/tags/v{}", GITHUB_API_URL, version))
+            .await
+        {
+            Ok(release) => release,
+            Err(_) => self
+                .get_json(&format!("{}/tags/{}", GITHUB_API_URL, version))
+                .await
+                .map_err(|e| match e.downcast_ref::<UpdateError>() {
+                    Some(UpdateError::Api { status: 404 }) => UpdateError::VersionNotFound(version.to_string()).into(),
+                    _ => e,
+                })?,
+        };
 
-        Ok(false)
+        let target_version = release.tag_name.trim_start_matches('v');
+        let update_available = target_version != CURRENT_VERSION;
+
+        let download_url = if update_available {
+            self.get_download_url(&release.assets)?
+        } else {
+            None
+        };
+
+        Ok(UpdateInfo {
+            current_version: CURRENT_VERSION.to_string(),
+            latest_version: Some(target_version.to_string()),
+            update_available,
+            download_url,
+            release_notes: release.body,
+        })
+    }
+
+    /// 比较版本号，返回是否需要更新
+    #[cfg(test)]
+    fn version_compare(current: &str, latest: &str) -> Result<bool> {
+        Ok(is_newer(&parse_version(latest)?, &parse_version(current)?))
     }
 
     /// 获取适合当前平台的下载URL
@@ -128,7 +554,13 @@ impl Updater {
             ("linux", "x86_64") => vec!["linux-x86_64", "linux-amd64"],
             ("linux", "aarch64") => vec!["linux-aarch64", "linux-arm64"],
             ("windows", "x86_64") => vec!["windows-x86_64", "win64"],
-            _ => return Err(anyhow!("不支持的平台: {}-{}", os, arch)),
+            _ => {
+                return Err(UpdateError::UnsupportedPlatform {
+                    os: os.to_string(),
+                    arch: arch.to_string(),
+                }
+                .into())
+            }
         };
 
         // 查找匹配的资源
@@ -140,33 +572,29 @@ impl Updater {
             }
         }
 
-        Err(anyhow!("未找到适合当前平台的下载文件"))
+        Err(UpdateError::NoAsset.into())
     }
 
-    /// 执行更新
-    pub async fn perform_update(&self, download_url: &str) -> Result<()> {
-        println!("🔄 正在下载最新版本...");
+    /// 未包含自动更新时只能检查版本
+    #[cfg(not(feature = "updater"))]
+    async fn perform_update(&self, _download_url: &str, _progress: &mut (dyn FnMut(UpdateEvent) + Send)) -> Result<()> {
+        Err(UpdateError::NotIncluded.into())
+    }
 
+    /// 执行更新
+    #[cfg(feature = "updater")]
+    async fn perform_update(&self, download_url: &str, progress: &mut (dyn FnMut(UpdateEvent) + Send)) -> Result<()> {
         // 获取当前可执行文件路径
         let current_exe = env::current_exe()?;
         let temp_dir = env::temp_dir();
         let temp_file = temp_dir.join("cf_new");
 
         // 下载新版本
-        let response = self.client
-            .get(download_url)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Err(anyhow!("下载失败: HTTP {}", response.status()));
-        }
-
-        let bytes = response.bytes().await?;
+        let bytes = self.download(download_url, progress).await?;
 
         // 检查是否是压缩文件
-        if download_url.ends_with(".tar.gz") || download_url.ends_with(".zip") {
-            self.extract_archive(&bytes, &temp_file).await?;
+        if let Some(kind) = ArchiveKind::from_url(download_url) {
+            self.extract_archive(kind, &bytes, &temp_file).await?;
         } else {
             fs::write(&temp_file, bytes)?;
         }
@@ -176,11 +604,11 @@ impl Updater {
         {
             use std::os::unix::fs::PermissionsExt;
             let mut perms = fs::metadata(&temp_file)?.permissions();
-            perms.set_mode(0o755);
+            perms.set_mode(perms.mode() | 0o755);
             fs::set_permissions(&temp_file, perms)?;
         }
 
-        println!("✅ 下载完成，正在替换旧版本...");
+        progress(UpdateEvent::Installing);
 
         // 清理可能存在的旧版本
         self.cleanup_old_versions(&current_exe).await?;
@@ -197,20 +625,91 @@ impl Updater {
         // 删除临时文件
         let _ = fs::remove_file(&temp_file);
 
-        println!("🎉 更新完成！");
-        println!("💡 请重新运行 cf 命令以使用新版本");
+        Ok(())
+    }
+
+    /// 提取压缩文件中的可执行文件，支持压缩包内带有一层目录的情况
+    #[cfg(feature = "updater")]
+    async fn extract_archive(&self, kind: ArchiveKind, bytes: &[u8], output_path: &Path) -> Result<()> {
+        let found = match kind {
+            ArchiveKind::TarGz => Self::extract_from_tar_gz(bytes, output_path)?,
+            ArchiveKind::Zip => Self::extract_from_zip(bytes, output_path)?,
+        };
+
+        if !found {
+            return Err(UpdateError::MissingExecutable.into());
+        }
 
         Ok(())
     }
 
-    /// 提取压缩文件
-    async fn extract_archive(&self, bytes: &[u8], output_path: &Path) -> Result<()> {
-        // 这里简化处理，假设压缩包中直接包含cf可执行文件
-        // 实际实现可能需要使用tar或zip库
-        return Err(anyhow!("暂不支持压缩包格式，请直接下载可执行文件"));
+    #[cfg(feature = "updater")]
+    fn extract_from_tar_gz(bytes: &[u8], output_path: &Path) -> Result<bool> {
+        let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path()?.into_owned();
+            if !is_binary_name(&path) {
+                continue;
+            }
+
+            let mut output = fs::File::create(output_path)?;
+            io::copy(&mut entry, &mut output)?;
+
+            #[cfg(unix)]
+            if let Ok(mode) = entry.header().mode() {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(output_path, fs::Permissions::from_mode(mode))?;
+            }
+
+            info!("已从压缩包提取: {}", path.display());
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    #[cfg(feature = "updater")]
+    fn extract_from_zip(bytes: &[u8], output_path: &Path) -> Result<bool> {
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes))?;
+
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if !file.is_file() {
+                continue;
+            }
+
+            let path = match file.enclosed_name() {
+                Some(path) => path.to_path_buf(),
+                None => continue,
+            };
+            if !is_binary_name(&path) {
+                continue;
+            }
+
+            let mut output = fs::File::create(output_path)?;
+            io::copy(&mut file, &mut output)?;
+
+            #[cfg(unix)]
+            if let Some(mode) = file.unix_mode() {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(output_path, fs::Permissions::from_mode(mode))?;
+            }
+
+            info!("已从压缩包提取: {}", path.display());
+            return Ok(true);
+        }
+
+        Ok(false)
     }
 
     /// 清理旧版本和重复安装
+    #[cfg(feature = "updater")]
     async fn cleanup_old_versions(&self, current_exe: &Path) -> Result<()> {
         let exe_dir = current_exe.parent().unwrap_or_else(|| Path::new("."));
         let exe_name = current_exe.file_name().unwrap_or_else(|| std::ffi::OsStr::new("cf"));
@@ -252,6 +751,7 @@ impl Updater {
     }
 
     /// 替换可执行文件
+    #[cfg(feature = "updater")]
     async fn replace_executable(&self, new_exe: &Path, current_exe: &Path) -> Result<()> {
         // 在Windows上可能需要特殊处理
         #[cfg(windows)]
@@ -273,8 +773,8 @@ del "%~f0"
 
             Command::new("cmd")
                 .args(["/c", "start", "", batch_path.to_str().unwrap()])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
                 .spawn()?;
         }
 
@@ -292,11 +792,12 @@ del "%~f0"
         let mut conflicts = Vec::new();
 
         // 检查常见的安装路径
+        let home_bin = format!("{}/.local/bin/cf", env::var("HOME").unwrap_or_default());
         let common_paths = vec![
             "/usr/local/bin/cf",
             "/usr/bin/cf",
             "/opt/clashfun/cf",
-            &format!("{}/.local/bin/cf", env::var("HOME").unwrap_or_default()),
+            home_bin.as_str(),
         ];
 
         for path_str in common_paths {
@@ -315,14 +816,164 @@ del "%~f0"
             let output_str = String::from_utf8_lossy(&which_output.stdout);
             for line in output_str.lines() {
                 let path = Path::new(line.trim());
-                if path.exists() && path != env::current_exe()? {
-                    if !conflicts.contains(&path.to_path_buf()) {
-                        conflicts.push(path.to_path_buf());
-                    }
+                if path.exists() && path != env::current_exe()? && !conflicts.contains(&path.to_path_buf()) {
+                    conflicts.push(path.to_path_buf());
                 }
             }
         }
 
         Ok(conflicts)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_compare_matrix() {
+        let cases = [
+            ("0.2.0", "0.2.1", true),
+            ("0.2.1", "0.2.0", false),
+            ("0.2.0", "0.2.0", false),
+            ("0.9.9", "0.10.0", true),
+            ("v0.9.9", "v0.10.0", true),
+            ("0.10.0", "0.9.9", false),
+            ("0.3.0-rc.1", "0.3.0", true),
+            ("0.3.0", "0.3.0-rc.1", false),
+            ("0.3.0-rc.1", "0.3.0-rc.2", true),
+            ("0.3.0-alpha", "0.3.0-beta", true),
+            ("0.3.0-rc.10", "0.3.0-rc.9", false),
+            ("0.2.0", "0.3.0-rc.1", true),
+            ("0.3", "0.3.1", true),
+            ("1", "0.99.99", false),
+            ("0.3.0+build.1", "0.3.0+build.2", false),
+        ];
+
+        for (current, latest, expected) in cases {
+            assert_eq!(
+                Updater::version_compare(current, latest).unwrap(),
+                expected,
+                "{} -> {}",
+                current,
+                latest
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_version_is_error() {
+        assert!(parse_version("latest").is_err());
+        assert!(parse_version("").is_err());
+    }
+
+    #[test]
+    fn release_notes_cover_skipped_versions() {
+        let release = |tag: &str, body: &str| {
+            (
+                parse_version(tag).unwrap(),
+                GitHubRelease {
+                    tag_name: tag.to_string(),
+                    body: Some(body.to_string()),
+                    assets: Vec::new(),
+                    prerelease: false,
+                    draft: false,
+                },
+            )
+        };
+        let releases = vec![
+            release("v0.5.0", "five"),
+            release("v0.4.0", "four"),
+            release("v0.3.0", "three"),
+            release("v0.2.0", "two"),
+        ];
+
+        let notes = Updater::release_notes_between(
+            &releases,
+            &parse_version("0.2.0").unwrap(),
+            &parse_version("0.4.0").unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(notes, "## v0.4.0\nfour\n\n## v0.3.0\nthree");
+    }
+
+    #[test]
+    fn mirror_prefixes_api_and_download_urls() {
+        let config = Config {
+            update_mirror: Some("https://ghproxy.com/".to_string()),
+            ..Config::default()
+        };
+        let updater = Updater::from_config(&config).unwrap();
+        assert_eq!(
+            updater.mirror_url(GITHUB_API_URL),
+            "https://ghproxy.com/https://api.github.com/repos/ink1ing/clashfun/releases"
+        );
+
+        let direct = Updater::from_config(&Config::default()).unwrap();
+        assert_eq!(direct.mirror_url(GITHUB_API_URL), GITHUB_API_URL);
+    }
+
+    #[cfg(feature = "updater")]
+    fn tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[cfg(feature = "updater")]
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().unix_permissions(0o755);
+        writer.add_directory("clashfun-v0.3.0/", options).unwrap();
+        for (path, data) in files {
+            writer.start_file(*path, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "updater")]
+    #[test]
+    fn extracts_binary_from_archives() {
+        let dir = std::env::temp_dir().join(format!("clashfun-extract-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("cf_new");
+
+        // 压缩包内带有一层目录，其他文件跳过
+        let nested: &[(&str, &[u8])] = &[
+            ("clashfun-v0.3.0/README.md", b"readme"),
+            ("clashfun-v0.3.0/cf", b"tar binary"),
+        ];
+        assert!(Updater::extract_from_tar_gz(&tar_gz(nested), &output).unwrap());
+        assert_eq!(fs::read(&output).unwrap(), b"tar binary");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&output).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+
+        let nested: &[(&str, &[u8])] = &[
+            ("clashfun-v0.3.0/LICENSE", b"license"),
+            ("clashfun-v0.3.0/cf.exe", b"zip binary"),
+        ];
+        assert!(Updater::extract_from_zip(&zip(nested), &output).unwrap());
+        assert_eq!(fs::read(&output).unwrap(), b"zip binary");
+
+        // 没有可执行文件时返回 false，只是名字相近的文件不算
+        let missing: &[(&str, &[u8])] = &[("clashfun-v0.3.0/README.md", b"readme"), ("cf-helper", b"helper")];
+        assert!(!Updater::extract_from_tar_gz(&tar_gz(missing), &output).unwrap());
+        assert!(!Updater::extract_from_zip(&zip(missing), &output).unwrap());
+        assert!(Updater::extract_from_zip(b"not a zip", &output).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{bail, Context, Result};
+use chrono::{Duration as Days, NaiveDate};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::proxy::ProxyServer;
+
+const USAGE_FILE: &str = "traffic_usage.json";
+/// 每隔多久把累计流量写入文件
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// 只保留最近 120 天的记录
+const RETENTION_DAYS: i64 = 120;
+/// 没有检测到游戏时记入的类别
+pub const OTHER: &str = "其他";
+
+/// 上次写入时的累计流量，只记录增量
+static LAST_SNAPSHOT: Mutex<(u64, u64)> = Mutex::const_new((0, 0));
+
+/// 上行和下行字节数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bytes {
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl Bytes {
+    pub fn total(&self) -> u64 {
+        self.upload + self.download
+    }
+
+    fn add(&mut self, other: Bytes) {
+        self.upload += other.upload;
+        self.download += other.download;
+    }
+}
+
+/// 一天的流量，按节点和游戏分别累计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DayUsage {
+    pub total: Bytes,
+    pub nodes: BTreeMap<String, Bytes>,
+    pub games: BTreeMap<String, Bytes>,
+}
+
+/// 按日期（本地时间）保存的流量记录
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub days: BTreeMap<NaiveDate, DayUsage>,
+}
+
+/// 一段时间内的合计
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub total: Bytes,
+    pub nodes: Vec<(String, Bytes)>,
+    pub games: Vec<(String, Bytes)>,
+}
+
+fn usage_file() -> Result<PathBuf> {
+    Ok(Config::cache_dir()?.join(USAGE_FILE))
+}
+
+/// 解析统计时长，例如 7d、2w，不带单位时按天
+pub fn parse_period(value: &str) -> Result<u32> {
+    let value = value.trim().to_ascii_lowercase();
+    let (number, unit) = match value.strip_suffix('w') {
+        Some(number) => (number, 7),
+        None => (value.strip_suffix('d').unwrap_or(&value), 1),
+    };
+    let days = number.parse::<u32>().context("格式应为 7d、2w 这样的天数或周数")? * unit;
+    if days == 0 || days > RETENTION_DAYS as u32 {
+        bail!("时长应在 1 到 {} 天之间", RETENTION_DAYS);
+    }
+    Ok(days)
+}
+
+impl Usage {
+    /// 读取记录，文件不存在或损坏时从空记录开始
+    pub fn load() -> Self {
+        let Ok(path) = usage_file() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("流量记录已损坏，重新开始记录: {}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = usage_file()?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string(self)?).with_context(|| format!("无法写入流量记录: {:?}", temp))?;
+        fs::rename(&temp, &path).with_context(|| format!("无法写入流量记录: {:?}", path))?;
+        Ok(())
+    }
+
+    /// 把一段流量记入某天的节点和游戏，并清理过期的记录
+    pub fn add(&mut self, date: NaiveDate, node: &str, game: &str, bytes: Bytes) {
+        let day = self.days.entry(date).or_default();
+        day.total.add(bytes);
+        day.nodes.entry(node.to_string()).or_default().add(bytes);
+        day.games.entry(game.to_string()).or_default().add(bytes);
+
+        let oldest = date - Days::days(RETENTION_DAYS);
+        self.days.retain(|day, _| *day > oldest);
+    }
+
+    /// 截止到 today 的最近若干天，没有流量的日期同样列出
+    pub fn recent(&self, days: u32, today: NaiveDate) -> Vec<(NaiveDate, DayUsage)> {
+        (0..days as i64)
+            .rev()
+            .map(|ago| {
+                let date = today - Days::days(ago);
+                (date, self.days.get(&date).cloned().unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// 最近若干天按节点和游戏的合计，按流量从多到少排序
+    pub fn summary(&self, days: u32, today: NaiveDate) -> Summary {
+        let mut summary = Summary::default();
+        let mut nodes: BTreeMap<String, Bytes> = BTreeMap::new();
+        let mut games: BTreeMap<String, Bytes> = BTreeMap::new();
+        for (_, day) in self.recent(days, today) {
+            summary.total.add(day.total);
+            for (node, bytes) in day.nodes {
+                nodes.entry(node).or_default().add(bytes);
+            }
+            for (game, bytes) in day.games {
+                games.entry(game).or_default().add(bytes);
+            }
+        }
+        let sorted = |map: BTreeMap<String, Bytes>| {
+            let mut list: Vec<(String, Bytes)> = map.into_iter().collect();
+            list.sort_by_key(|(_, bytes)| std::cmp::Reverse(bytes.total()));
+            list
+        };
+        summary.nodes = sorted(nodes);
+        summary.games = sorted(games);
+        summary
+    }
+}
+
+/// 把上次写入以来的流量记入当前节点和正在运行的游戏
+pub async fn flush(proxy: &ProxyServer) {
+    let mut last = LAST_SNAPSHOT.lock().await;
+    let (upload, download, _) = proxy.traffic().snapshot();
+    let bytes = Bytes {
+        upload: upload.saturating_sub(last.0),
+        download: download.saturating_sub(last.1),
+    };
+    if bytes.total() == 0 {
+        return;
+    }
+
+    let node = proxy.current_node_name().await.unwrap_or_else(|| OTHER.to_string());
+    let game = proxy
+        .running_game()
+        .await
+        .map_or(OTHER.to_string(), |game| game.display_name().to_string());
+    let today = chrono::Local::now().date_naive();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let mut usage = Usage::load();
+        usage.add(today, &node, &game, bytes);
+        usage.save()
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => *last = (upload, download),
+        Ok(Err(e)) => warn!("保存流量记录失败: {:#}", e),
+        Err(e) => warn!("保存流量记录失败: {}", e),
+    }
+}
+
+/// 服务运行期间定期保存流量记录
+pub fn spawn(proxy: Arc<ProxyServer>) {
+    proxy.tasks().spawn("保存流量记录", async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            flush(&proxy).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_days_nodes_and_games() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let bytes = |upload, download| Bytes { upload, download };
+        let mut usage = Usage::default();
+        usage.add(today - Days::days(200), "旧节点", OTHER, bytes(1, 1));
+        usage.add(today - Days::days(1), "香港 01", "反恐精英", bytes(100, 900));
+        usage.add(today, "香港 01", "反恐精英", bytes(10, 90));
+        usage.add(today, "日本 02", OTHER, bytes(5, 5));
+
+        // 超过保留期的记录被清理
+        assert_eq!(usage.days.len(), 2);
+        let recent = usage.recent(3, today);
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent[0].1.total.total(), 0);
+        assert_eq!(recent[2].1.total, bytes(15, 95));
+
+        let summary = usage.summary(7, today);
+        assert_eq!(summary.total, bytes(115, 995));
+        assert_eq!(summary.nodes[0], ("香港 01".to_string(), bytes(110, 990)));
+        assert_eq!(summary.games[1], (OTHER.to_string(), bytes(5, 5)));
+
+        assert_eq!(parse_period("7d").unwrap(), 7);
+        assert_eq!(parse_period("2w").unwrap(), 14);
+        assert_eq!(parse_period("30").unwrap(), 30);
+        assert!(parse_period("0d").is_err());
+        assert!(parse_period("abc").is_err());
+    }
+}